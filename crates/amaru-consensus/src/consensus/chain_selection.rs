@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{peer::Peer, ConsensusError};
+use crate::{consensus::store::ChainStore, peer::Peer, ConsensusError};
 use amaru_kernel::{cbor, Point};
 use amaru_ouroboros::HASH_SIZE;
 use amaru_ouroboros_traits::is_header::IsHeader;
@@ -25,7 +25,7 @@ use tracing::{instrument, Level};
 /// The list of headers /must/ be a sequence of headers such that
 /// each element has the next one as parent. The anchor is the
 /// parent of the last element of the sequence.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Fragment<H: IsHeader> {
     headers: Vec<H>,
     anchor: Tip<H>,
@@ -162,6 +162,73 @@ impl<H: IsHeader> From<Option<H>> for Tip<H> {
         }
     }
 }
+
+impl<H: IsHeader> Tip<H> {
+    /// The hash of the header, `None` for the genesis.
+    fn header_hash(&self) -> Option<Hash<32>> {
+        match self {
+            Tip::Genesis => None,
+            Tip::Hdr(header) => Some(header.hash()),
+        }
+    }
+}
+
+/// The chain of a peer in a [`ChainSelectorState`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerChain {
+    pub peer: Peer,
+    pub anchor: Option<Hash<32>>,
+    pub tip: Option<Hash<32>>,
+}
+
+/// The state of a [`ChainSelector`], persisted in the chain store so that it can be
+/// restored after a restart, see [`ChainSelectorBuilder::restore`].
+///
+/// Headers are referred to by their hash, `None` standing for the genesis: the
+/// chains of the peers are found again by following the parents of their tip
+/// back to their anchor.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ChainSelectorState {
+    pub tip: Option<Hash<32>>,
+    pub peers: Vec<PeerChain>,
+}
+
+impl<C> cbor::encode::Encode<C> for ChainSelectorState {
+    fn encode<W: cbor::encode::Write>(
+        &self,
+        e: &mut cbor::Encoder<W>,
+        ctx: &mut C,
+    ) -> Result<(), cbor::encode::Error<W::Error>> {
+        e.array(2)?;
+        e.encode_with(self.tip, ctx)?;
+        e.array(self.peers.len() as u64)?;
+        for chain in &self.peers {
+            e.array(3)?;
+            e.str(&chain.peer.name)?;
+            e.encode_with(chain.anchor, ctx)?;
+            e.encode_with(chain.tip, ctx)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'b, C> cbor::decode::Decode<'b, C> for ChainSelectorState {
+    fn decode(d: &mut cbor::Decoder<'b>, ctx: &mut C) -> Result<Self, cbor::decode::Error> {
+        d.array()?;
+        let tip = d.decode_with(ctx)?;
+        let len = d.array()?.unwrap_or_default();
+        let mut peers = Vec::new();
+        for _ in 0..len {
+            d.array()?;
+            peers.push(PeerChain {
+                peer: Peer::new(d.str()?),
+                anchor: d.decode_with(ctx)?,
+                tip: d.decode_with(ctx)?,
+            });
+        }
+        Ok(ChainSelectorState { tip, peers })
+    }
+}
 /// Current state of chain selection process
 ///
 /// Chain selection is parameterised by the header type `H`, in
@@ -170,6 +237,8 @@ impl<H: IsHeader> From<Option<H>> for Tip<H> {
 pub struct ChainSelector<H: IsHeader> {
     tip: Tip<H>,
     peers_chains: HashMap<Peer, Fragment<H>>,
+    max_rollback: Option<u64>,
+    genesis_window: Option<u64>,
 }
 
 /// Definition of a fork.
@@ -210,6 +279,10 @@ pub enum RollbackChainSelection<H: IsHeader> {
 
     /// The current best chain as not changed
     NoChange,
+
+    /// The rollback was rejected as it goes back further than the maximum rollback from
+    /// the tip of the current best chain. The chain of the peer is left unchanged.
+    RollbackTooDeep { depth: u64, max_rollback: u64 },
 }
 
 /// Builder pattern for `ChainSelector`.
@@ -219,6 +292,9 @@ pub enum RollbackChainSelection<H: IsHeader> {
 pub struct ChainSelectorBuilder<H: IsHeader> {
     tip: Option<H>,
     peers: Vec<Peer>,
+    max_rollback: Option<u64>,
+    genesis_window: Option<u64>,
+    restored: HashMap<Peer, Fragment<H>>,
 }
 
 impl<H: IsHeader + Clone> ChainSelectorBuilder<H> {
@@ -226,6 +302,9 @@ impl<H: IsHeader + Clone> ChainSelectorBuilder<H> {
         ChainSelectorBuilder {
             tip: None,
             peers: Vec::new(),
+            max_rollback: None,
+            genesis_window: None,
+            restored: HashMap::new(),
         }
     }
 
@@ -239,6 +318,97 @@ impl<H: IsHeader + Clone> ChainSelectorBuilder<H> {
         self
     }
 
+    /// Set the security parameter `k`: the maximum number of blocks the
+    /// current best chain can be rolled back, whether following a rollback
+    /// or to switch to a fork. Rollbacks are unbounded unless it is set.
+    pub fn set_max_rollback(&mut self, max_rollback: u64) -> &mut Self {
+        self.max_rollback = Some(max_rollback);
+        self
+    }
+
+    /// Enable the Ouroboros Genesis rule with the given window `s`, in slots: a
+    /// chain forking from the current best chain more than `k` blocks back is
+    /// preferred if it has more blocks in the `s` slots following the fork,
+    /// whatever the lengths of the chains. Only applies if the maximum
+    /// rollback `k` is set.
+    pub fn set_genesis_window(&mut self, genesis_window: u64) -> &mut Self {
+        self.genesis_window = Some(genesis_window);
+        self
+    }
+
+    /// Restore the tip and the chains of the peers from a state persisted
+    /// along with their headers in `store`, see [`ChainSelector::state`].
+    ///
+    /// The chains of peers with headers missing from the store start over
+    /// from the tip, as do those of peers added but not found in the state.
+    /// Nothing is restored if the tip itself is missing from the store.
+    pub fn restore(
+        &mut self,
+        state: &ChainSelectorState,
+        store: &(impl ChainStore<H> + ?Sized),
+    ) -> &mut Self {
+        let load = |hash: Option<Hash<32>>| match hash {
+            None => Some(Tip::Genesis),
+            Some(hash) => store.load_header(&hash).map(Tip::Hdr),
+        };
+        let Some(tip) = load(state.tip) else {
+            return self;
+        };
+        self.tip = match tip {
+            Tip::Genesis => None,
+            Tip::Hdr(header) => Some(header),
+        };
+        self.restored = state
+            .peers
+            .iter()
+            .filter_map(|chain| {
+                let anchor = load(chain.anchor)?;
+                let mut headers = vec![];
+                let mut next = chain.tip;
+                while next != chain.anchor {
+                    let header = store.load_header(&next?)?;
+                    next = header.parent();
+                    headers.push(header);
+                }
+                headers.reverse();
+                Some((chain.peer.clone(), Fragment { headers, anchor }))
+            })
+            .collect();
+        self
+    }
+
+    /// Like [`Self::restore`], provided the persisted tip is in `store` and
+    /// is the tip set, e.g. that of the ledger, or one of its descendants.
+    ///
+    /// The tip set is kept either way: peers intersect with it again once
+    /// reconnected, rolling their chains back to it.
+    pub fn restore_onto_tip(
+        &mut self,
+        state: &ChainSelectorState,
+        store: &(impl ChainStore<H> + ?Sized),
+    ) -> &mut Self {
+        let tip: Tip<H> = self.tip.clone().into();
+        let mut next = state.tip;
+        let descends = loop {
+            if next == tip.header_hash() {
+                break true;
+            }
+            let Some(header) = next.and_then(|hash| store.load_header(&hash)) else {
+                break false;
+            };
+            if header.block_height() <= tip.block_height() {
+                break false;
+            }
+            next = header.parent();
+        };
+        if descends {
+            let tip = self.tip.clone();
+            self.restore(state, store);
+            self.tip = tip;
+        }
+        self
+    }
+
     #[allow(clippy::unwrap_used)]
     pub fn build(&self) -> Result<ChainSelector<H>, ConsensusError> {
         Ok(ChainSelector {
@@ -247,12 +417,15 @@ impl<H: IsHeader + Clone> ChainSelectorBuilder<H> {
                 .peers
                 .iter()
                 .map(|peer| {
-                    (
-                        peer.clone(),
-                        Fragment::start_from(&(self.tip.clone().into())),
-                    )
+                    let fragment = match self.restored.get(peer) {
+                        Some(fragment) => fragment.clone(),
+                        None => Fragment::start_from(&(self.tip.clone().into())),
+                    };
+                    (peer.clone(), fragment)
                 })
                 .collect(),
+            max_rollback: self.max_rollback,
+            genesis_window: self.genesis_window,
         })
     }
 }
@@ -267,6 +440,25 @@ impl<H> ChainSelector<H>
 where
     H: IsHeader + Clone + Debug + PartialEq,
 {
+    /// The state to persist in order to restore the chain selector after a
+    /// restart, see [`ChainSelectorBuilder::restore`].
+    pub fn state(&self) -> ChainSelectorState {
+        let mut peers = self
+            .peers_chains
+            .iter()
+            .map(|(peer, fragment)| PeerChain {
+                peer: peer.clone(),
+                anchor: fragment.anchor.header_hash(),
+                tip: fragment.tip().header_hash(),
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by(|a, b| a.peer.name.cmp(&b.peer.name));
+        ChainSelectorState {
+            tip: self.tip.header_hash(),
+            peers,
+        }
+    }
+
     /// Roll forward the chain with a new header from given peer.
     ///
     /// The function returns the result of the chain selection process, which might lead
@@ -280,6 +472,11 @@ where
         // TODO: raise error if header does not match parent
         match fragment.extend_with(&header) {
             FragmentExtension::Extend => {
+                if let Some(fork) = self.find_denser_chain() {
+                    self.tip = fork.tip.clone();
+                    return SwitchToFork(fork);
+                }
+
                 // FIXME: if there's no peer this will return None
                 let (best_peer, best_tip) = self.find_best_chain().unwrap();
 
@@ -287,6 +484,9 @@ where
                     NewTip(header.clone())
                 } else if best_tip.block_height() > self.tip.block_height() {
                     let fragment = self.peers_chains.get(&best_peer).unwrap();
+                    if !self.can_rollback_to(&fragment.anchor) {
+                        return NoChange;
+                    }
                     SwitchToFork(Fork {
                         peer: best_peer,
                         rollback_point: fragment.anchor.point(),
@@ -315,10 +515,23 @@ where
     /// will either return a `SwitchToFork` result with the new tip of
     /// the chain, if the best chain has moved to another peer, or
     /// `NoChange` if the best chain hasn't changed.
+    ///
+    /// Rollbacks going back further than the maximum rollback from the
+    /// current tip are rejected with a `RollbackTooDeep` result.
     #[allow(clippy::unwrap_used)]
     pub fn select_rollback(&mut self, peer: &Peer, point: Hash<32>) -> RollbackChainSelection<H> {
         use RollbackChainSelection::*;
 
+        if let Some(max_rollback) = self.max_rollback {
+            let depth = self.rollback_depth(peer, point);
+            if depth > max_rollback {
+                return RollbackTooDeep {
+                    depth,
+                    max_rollback,
+                };
+            }
+        }
+
         self.rollback_fragment(peer, point);
 
         let (best_peer, best_tip) = self.find_best_chain().unwrap();
@@ -328,9 +541,15 @@ where
         }
 
         let result = if best_peer == *peer {
+            if !self.can_rollback_to(&best_tip) {
+                return NoChange;
+            }
             RollbackTo(point)
         } else {
             let fragment = self.peers_chains.get(&best_peer).unwrap();
+            if !self.can_rollback_to(&fragment.anchor) {
+                return NoChange;
+            }
             // TODO: do not always switch to anchor if there's a better intersection
             // with current chain
             SwitchToFork(Fork {
@@ -346,10 +565,119 @@ where
         result
     }
 
+    /// Whether rolling back the current best chain down to the given
+    /// header stays within the maximum rollback, if any.
+    fn can_rollback_to(&self, header: &Tip<H>) -> bool {
+        self.max_rollback.is_none_or(|max_rollback| {
+            self.tip
+                .block_height()
+                .saturating_sub(header.block_height())
+                <= max_rollback
+        })
+    }
+
+    /// How many blocks the current best chain would lose, would it switch to
+    /// the chain of the peer once rolled back to the given point: the depth of
+    /// the intersection of both chains, which is below the point when the
+    /// peer is on a fork. Unknown points are taken as the anchor of the chain,
+    /// which is where the peer is rolled back to.
+    #[allow(clippy::unwrap_used)]
+    fn rollback_depth(&self, peer: &Peer, point: Hash<32>) -> u64 {
+        let fragment = self.peers_chains.get(peer).unwrap();
+        let theirs = fragment
+            .position_of(point)
+            .map_or(&fragment.headers[..0], |position| {
+                &fragment.headers[..=position]
+            });
+        let height = match common_prefix(self.current_chain(), theirs).checked_sub(1) {
+            Some(last) => theirs[last].block_height(),
+            None => fragment.anchor.block_height(),
+        };
+        self.tip.block_height().saturating_sub(height)
+    }
+
+    /// The headers of the current best chain, after the anchor of the fragments, as found in
+    /// the fragment of a peer following it.
+    fn current_chain(&self) -> &[H] {
+        let tip = self.tip.hash();
+        self.peers_chains
+            .values()
+            .find_map(|fragment| {
+                fragment
+                    .position_of(tip)
+                    .map(|position| &fragment.headers[..=position])
+            })
+            .unwrap_or_default()
+    }
+
+    /// Under the Genesis rule, the densest chain among those forking from the current best
+    /// chain more than `k` blocks back which are denser than it within the genesis window
+    /// following the fork, if any.
+    ///
+    /// Densities are compared in place, only the headers of the chain switched to are copied.
+    #[instrument(level = Level::TRACE, skip_all)]
+    fn find_denser_chain(&self) -> Option<Fork<H>> {
+        let (Some(max_rollback), Some(window)) = (self.max_rollback, self.genesis_window) else {
+            return None;
+        };
+        let current = self.current_chain();
+        let mut best: Option<(usize, &Peer, &Fragment<H>, usize)> = None;
+        for (peer, fragment) in self.peers_chains.iter() {
+            let common = common_prefix(current, &fragment.headers);
+            if (current.len() - common) as u64 <= max_rollback {
+                continue;
+            }
+            let start = match common.checked_sub(1) {
+                Some(last) => current[last].slot(),
+                None => fragment.anchor.slot(),
+            };
+            // headers come in increasing slots, from the one following the intersection
+            let density = |headers: &[H]| {
+                headers
+                    .iter()
+                    .take_while(|header| header.slot() <= start + window)
+                    .count()
+            };
+            let theirs = density(&fragment.headers[common..]);
+            let best_density = best
+                .as_ref()
+                .map_or_else(|| density(&current[common..]), |(d, ..)| *d);
+            if theirs > best_density {
+                best = Some((theirs, peer, fragment, common));
+            }
+        }
+        best.map(|(_, peer, fragment, common)| {
+            let rollback_point = match common.checked_sub(1) {
+                Some(last) => fragment.headers[last].point(),
+                None => fragment.anchor.point(),
+            };
+            Fork {
+                peer: peer.clone(),
+                rollback_point,
+                tip: fragment.tip(),
+                fork: fragment.headers[common..].to_vec(),
+            }
+        })
+    }
+
+    /// The longest chain among those known by the peers. Under the Genesis rule, chains
+    /// forking more than `k` blocks back have already been compared by density and are left
+    /// out.
     #[instrument(level = Level::TRACE, skip_all)]
     fn find_best_chain(&self) -> Option<(Peer, Tip<H>)> {
+        let deep_forks_excluded = match (self.max_rollback, self.genesis_window) {
+            (Some(max_rollback), Some(_)) => Some((max_rollback, self.current_chain())),
+            _ => None,
+        };
         let mut best: Option<(Peer, Tip<H>)> = None;
         for (peer, fragment) in self.peers_chains.iter() {
+            if let Some((max_rollback, current)) = &deep_forks_excluded {
+                if (current.len() - common_prefix(current, &fragment.headers)) as u64
+                    > *max_rollback
+                {
+                    continue;
+                }
+            }
             let best_height = best.as_ref().map_or(0, |(_, tip)| tip.block_height());
             match fragment.tip() {
                 Tip::Hdr(header) if fragment.height() > best_height => {
@@ -370,13 +698,25 @@ where
     }
 }
 
+/// The number of headers `chain` and `other` have in common, from their anchor.
+fn common_prefix<H: IsHeader>(chain: &[H], other: &[H]) -> usize {
+    chain
+        .iter()
+        .zip(other.iter())
+        .take_while(|(ours, theirs)| ours.hash() == theirs.hash())
+        .count()
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
-    use amaru_kernel::{from_cbor, to_cbor};
+    use crate::consensus::store::StoreError;
+    use amaru_kernel::{from_cbor, network::NetworkName, to_cbor, EraHistory, RawBlock};
+    use amaru_ouroboros::Nonces;
     use amaru_ouroboros_traits::is_header::fake::FakeHeader;
     use proptest::prelude::*;
     use rand::{rngs::StdRng, RngCore, SeedableRng};
+    use std::collections::BTreeMap;
 
     /// Very simple function to generate random sequence of bytes of given length.
     pub fn random_bytes(arg: u32) -> Vec<u8> {
@@ -386,6 +726,70 @@ pub(crate) mod tests {
         buffer
     }
 
+    /// A chain store keeping headers and the state of the chain selector in memory.
+    #[derive(Default)]
+    pub struct FakeStore {
+        headers: BTreeMap<Hash<32>, FakeHeader>,
+        chain_selector_state: Option<ChainSelectorState>,
+    }
+
+    impl ChainStore<FakeHeader> for FakeStore {
+        fn load_header(&self, hash: &Hash<32>) -> Option<FakeHeader> {
+            self.headers.get(hash).copied()
+        }
+
+        fn store_header(&mut self, hash: &Hash<32>, header: &FakeHeader) -> Result<(), StoreError> {
+            self.headers.insert(*hash, *header);
+            Ok(())
+        }
+
+        fn remove_header(&mut self, hash: &Hash<32>) -> Result<(), StoreError> {
+            self.headers.remove(hash);
+            Ok(())
+        }
+
+        fn header_hashes_from(&self, height: u64) -> Result<Vec<Hash<32>>, StoreError> {
+            Ok(self
+                .headers
+                .iter()
+                .filter(|(_, header)| header.block_height() >= height)
+                .map(|(hash, _)| *hash)
+                .collect())
+        }
+
+        fn get_nonces(&self, _header: &Hash<32>) -> Option<Nonces> {
+            unimplemented!()
+        }
+
+        fn put_nonces(&mut self, _header: &Hash<32>, _nonces: &Nonces) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        fn era_history(&self) -> &EraHistory {
+            NetworkName::Preprod.into()
+        }
+
+        fn load_block(&self, _hash: &Hash<32>) -> Result<RawBlock, StoreError> {
+            unimplemented!()
+        }
+
+        fn store_block(&mut self, _hash: &Hash<32>, _block: &RawBlock) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        fn load_chain_selector_state(&self) -> Option<ChainSelectorState> {
+            self.chain_selector_state.clone()
+        }
+
+        fn store_chain_selector_state(
+            &mut self,
+            state: &ChainSelectorState,
+        ) -> Result<(), StoreError> {
+            self.chain_selector_state = Some(state.clone());
+            Ok(())
+        }
+    }
+
     /// Generate a chain of headers anchored at a given header.
     ///
     /// The chain is generated by creating headers with random body hash, and linking
@@ -409,6 +813,21 @@ pub(crate) mod tests {
         headers
     }
 
+    /// Generate a chain of headers from genesis, with the given slots.
+    fn chain_with_slots(slots: &[u64]) -> Vec<FakeHeader> {
+        let mut headers: Vec<FakeHeader> = Vec::new();
+        for (i, slot) in slots.iter().enumerate() {
+            let header = FakeHeader {
+                block_number: i as u64 + 1,
+                slot: *slot,
+                parent: headers.last().map(|h| h.hash()),
+                body_hash: random_bytes(32).as_slice().into(),
+            };
+            headers.push(header);
+        }
+        headers
+    }
+
     prop_compose! {
         fn any_test_header()(
             slot in 0..1000000u64,
@@ -540,6 +959,192 @@ pub(crate) mod tests {
         assert_eq!(ForwardChainSelection::NoChange, result.unwrap());
     }
 
+    #[test]
+    fn dont_switch_to_fork_deeper_than_max_rollback() {
+        let alice = Peer::new("alice");
+        let bob = Peer::new("bob");
+        let mut chain_selector = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .add_peer(&bob)
+            .set_max_rollback(4)
+            .build()
+            .unwrap();
+
+        let chain1 = generate_headers_anchored_at(None, 5);
+        let chain2 = generate_headers_anchored_at(None, 6);
+
+        chain1.iter().for_each(|header| {
+            chain_selector.select_roll_forward(&alice, *header);
+        });
+
+        let result = chain2
+            .iter()
+            .map(|header| chain_selector.select_roll_forward(&bob, *header))
+            .next_back();
+
+        assert_eq!(ForwardChainSelection::NoChange, result.unwrap());
+        assert_eq!(Tip::Hdr(chain1[4]), chain_selector.tip);
+    }
+
+    #[test]
+    fn switch_to_denser_fork_deeper_than_max_rollback_given_genesis_window() {
+        let alice = Peer::new("alice");
+        let bob = Peer::new("bob");
+        let mut chain_selector = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .add_peer(&bob)
+            .set_max_rollback(2)
+            .set_genesis_window(20)
+            .build()
+            .unwrap();
+
+        let sparse = chain_with_slots(&[10, 20, 30, 40, 50]);
+        let dense = chain_with_slots(&[1, 2, 3, 4]);
+
+        sparse.iter().for_each(|header| {
+            chain_selector.select_roll_forward(&alice, *header);
+        });
+
+        let results = dense
+            .iter()
+            .map(|header| chain_selector.select_roll_forward(&bob, *header))
+            .collect::<Vec<_>>();
+
+        assert_eq!(ForwardChainSelection::NoChange, results[1]);
+        assert_eq!(
+            ForwardChainSelection::SwitchToFork(Fork {
+                peer: bob,
+                rollback_point: Point::Origin,
+                tip: Tip::Hdr(dense[2]),
+                fork: dense[..3].to_vec(),
+            }),
+            results[2]
+        );
+        assert_eq!(ForwardChainSelection::NewTip(dense[3]), results[3]);
+        assert_eq!(Tip::Hdr(dense[3]), chain_selector.tip);
+    }
+
+    #[test]
+    fn dont_switch_to_sparser_fork_given_genesis_window() {
+        let alice = Peer::new("alice");
+        let bob = Peer::new("bob");
+        let mut chain_selector = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .add_peer(&bob)
+            .set_max_rollback(2)
+            .set_genesis_window(20)
+            .build()
+            .unwrap();
+
+        let dense = chain_with_slots(&[1, 2, 3, 4]);
+        let sparse = chain_with_slots(&[10, 20, 30, 40, 50, 60]);
+
+        dense.iter().for_each(|header| {
+            chain_selector.select_roll_forward(&alice, *header);
+        });
+
+        let result = sparse
+            .iter()
+            .map(|header| chain_selector.select_roll_forward(&bob, *header))
+            .next_back();
+
+        assert_eq!(ForwardChainSelection::NoChange, result.unwrap());
+        assert_eq!(Tip::Hdr(dense[3]), chain_selector.tip);
+    }
+
+    #[test]
+    fn dont_rollback_deeper_than_max_rollback() {
+        let alice = Peer::new("alice");
+        let mut chain_selector = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .set_max_rollback(1)
+            .build()
+            .unwrap();
+
+        let chain1 = generate_headers_anchored_at(None, 5);
+
+        chain1.iter().for_each(|header| {
+            chain_selector.select_roll_forward(&alice, *header);
+        });
+
+        let hash = chain1[3].hash();
+        let result = chain_selector.select_rollback(&alice, hash);
+        assert_eq!(RollbackChainSelection::RollbackTo(hash), result);
+
+        let result = chain_selector.select_rollback(&alice, chain1[1].hash());
+        assert_eq!(
+            RollbackChainSelection::RollbackTooDeep {
+                depth: 2,
+                max_rollback: 1
+            },
+            result
+        );
+        assert_eq!(Tip::Hdr(chain1[3]), chain_selector.tip);
+    }
+
+    #[test]
+    fn deep_rollback_leaves_chain_of_peer_unchanged() {
+        let alice = Peer::new("alice");
+        let mut chain_selector = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .set_max_rollback(2)
+            .build()
+            .unwrap();
+
+        let chain1 = generate_headers_anchored_at(None, 5);
+
+        chain1.iter().for_each(|header| {
+            chain_selector.select_roll_forward(&alice, *header);
+        });
+
+        let result = chain_selector.select_rollback(&alice, chain1[0].hash());
+        assert_eq!(
+            RollbackChainSelection::RollbackTooDeep {
+                depth: 4,
+                max_rollback: 2
+            },
+            result
+        );
+
+        let next = generate_headers_anchored_at(Some(chain1[4]), 1);
+        let result = chain_selector.select_roll_forward(&alice, next[0]);
+        assert_eq!(ForwardChainSelection::NewTip(next[0]), result);
+    }
+
+    #[test]
+    fn rollback_depth_is_measured_from_the_intersection_with_the_chain_of_the_peer() {
+        let alice = Peer::new("alice");
+        let bob = Peer::new("bob");
+        let mut chain_selector = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .add_peer(&bob)
+            .set_max_rollback(2)
+            .build()
+            .unwrap();
+
+        let chain1 = generate_headers_anchored_at(None, 5);
+        let fork = generate_headers_anchored_at(Some(chain1[0]), 3);
+
+        chain1.iter().for_each(|header| {
+            chain_selector.select_roll_forward(&alice, *header);
+        });
+        chain_selector.select_roll_forward(&bob, chain1[0]);
+        fork.iter().for_each(|header| {
+            chain_selector.select_roll_forward(&bob, *header);
+        });
+
+        // the point is a single block below the tip, but the fork of bob leaves the best
+        // chain right after its first block
+        let result = chain_selector.select_rollback(&bob, fork[2].hash());
+        assert_eq!(
+            RollbackChainSelection::RollbackTooDeep {
+                depth: 4,
+                max_rollback: 2
+            },
+            result
+        );
+    }
+
     #[test]
     fn rollback_to_point_given_chain_is_still_longest() {
         let alice = Peer::new("alice");
@@ -653,6 +1258,141 @@ pub(crate) mod tests {
         assert_eq!(RollbackChainSelection::NoChange, result);
     }
 
+    #[test]
+    fn restores_tip_and_chains_of_peers_from_persisted_state() {
+        let alice = Peer::new("alice");
+        let bob = Peer::new("bob");
+        let mut chain_selector = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .add_peer(&bob)
+            .build()
+            .unwrap();
+        let mut store = FakeStore::default();
+
+        let chain1 = generate_headers_anchored_at(None, 5);
+        let chain2 = generate_headers_anchored_at(None, 3);
+        for (peer, chain) in [(&alice, &chain1), (&bob, &chain2)] {
+            for header in chain {
+                store.store_header(&header.hash(), header).unwrap();
+                chain_selector.select_roll_forward(peer, *header);
+            }
+        }
+        store
+            .store_chain_selector_state(&chain_selector.state())
+            .unwrap();
+
+        let state = store.load_chain_selector_state().unwrap();
+        let mut restored = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .add_peer(&bob)
+            .restore(&state, &store)
+            .build()
+            .unwrap();
+
+        assert_eq!(chain_selector.tip, restored.tip);
+        assert_eq!(chain_selector.peers_chains, restored.peers_chains);
+        let next = generate_headers_anchored_at(Some(chain1[4]), 1);
+        assert_eq!(
+            ForwardChainSelection::NewTip(next[0]),
+            restored.select_roll_forward(&alice, next[0])
+        );
+    }
+
+    #[test]
+    fn restores_chains_of_peers_onto_an_ancestor_of_the_persisted_tip() {
+        let alice = Peer::new("alice");
+        let bob = Peer::new("bob");
+        let mut store = FakeStore::default();
+        let chain1 = generate_headers_anchored_at(None, 5);
+        let chain2 = generate_headers_anchored_at(None, 3);
+        chain1.iter().chain(&chain2).for_each(|header| {
+            store.store_header(&header.hash(), header).unwrap();
+        });
+        let state = ChainSelectorState {
+            tip: Some(chain1[4].hash()),
+            peers: vec![PeerChain {
+                peer: alice.clone(),
+                anchor: None,
+                tip: Some(chain1[4].hash()),
+            }],
+        };
+
+        // the ledger is behind chain selection
+        let restored: ChainSelector<FakeHeader> = ChainSelectorBuilder::new()
+            .set_tip(&chain1[2])
+            .add_peer(&alice)
+            .restore_onto_tip(&state, &store)
+            .build()
+            .unwrap();
+        assert_eq!(Tip::Hdr(chain1[2]), restored.tip);
+        assert_eq!(5, restored.peers_chains[&alice].height());
+
+        // the ledger is on another chain
+        let restored: ChainSelector<FakeHeader> = ChainSelectorBuilder::new()
+            .set_tip(&chain2[2])
+            .add_peer(&alice)
+            .restore_onto_tip(&state, &store)
+            .build()
+            .unwrap();
+        assert_eq!(Tip::Hdr(chain2[2]), restored.tip);
+        assert_eq!(
+            Some(&Fragment::start_from(&Tip::Hdr(chain2[2]))),
+            restored.peers_chains.get(&alice)
+        );
+    }
+
+    #[test]
+    fn chains_with_missing_headers_start_over_from_restored_tip() {
+        let alice = Peer::new("alice");
+        let mut store = FakeStore::default();
+        let chain1 = generate_headers_anchored_at(None, 5);
+        chain1[3..].iter().for_each(|header| {
+            store.store_header(&header.hash(), header).unwrap();
+        });
+        let state = ChainSelectorState {
+            tip: Some(chain1[4].hash()),
+            peers: vec![PeerChain {
+                peer: alice.clone(),
+                anchor: None,
+                tip: Some(chain1[4].hash()),
+            }],
+        };
+
+        let restored: ChainSelector<FakeHeader> = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .restore(&state, &store)
+            .build()
+            .unwrap();
+
+        assert_eq!(Tip::Hdr(chain1[4]), restored.tip);
+        assert_eq!(
+            Some(&Fragment::start_from(&Tip::Hdr(chain1[4]))),
+            restored.peers_chains.get(&alice)
+        );
+    }
+
+    #[test]
+    fn chain_selector_state_roundtrips_through_cbor() {
+        let state = ChainSelectorState {
+            tip: Some(Hash::from([1; 32])),
+            peers: vec![
+                PeerChain {
+                    peer: Peer::new("alice"),
+                    anchor: None,
+                    tip: Some(Hash::from([2; 32])),
+                },
+                PeerChain {
+                    peer: Peer::new("bob"),
+                    anchor: Some(Hash::from([3; 32])),
+                    tip: None,
+                },
+            ],
+        };
+
+        let decoded: ChainSelectorState = from_cbor(&to_cbor(&state)).unwrap();
+        assert_eq!(state, decoded);
+    }
+
     #[test]
     fn hash_of_genesis_tip_is_all_zeros() {
         let genesis_tip: Tip<FakeHeader> = Tip::Genesis;
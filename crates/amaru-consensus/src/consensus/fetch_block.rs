@@ -0,0 +1,423 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    reputation::{PeerStatus, Reputation},
+    FetchBlockEvent, ValidateHeaderEvent, EVENT_TARGET,
+};
+use crate::{peer::Peer, ConsensusError};
+use amaru_kernel::{Point, RawBlock};
+use futures_util::{stream, StreamExt};
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{sync::Mutex, time::sleep};
+use tracing::{instrument, warn, Level};
+
+/// The maximum number of blocks fetched at once, see [`FetchBlock::handle_events`].
+pub const MAX_FETCHES_IN_FLIGHT: usize = 8;
+
+/// How many times the peers are asked for a block before giving up on it.
+pub const FETCH_ATTEMPTS: u32 = 3;
+
+/// The time waited before asking the peers for a block anew by default, doubled after each
+/// attempt.
+pub const DEFAULT_FETCH_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A source of block bodies, e.g. the block-fetch clients of the upstream peers.
+pub trait BlockFetcher {
+    fn fetch_block(
+        &self,
+        peer: &Peer,
+        point: &Point,
+    ) -> impl Future<Output = Result<RawBlock, ConsensusError>>;
+}
+
+/// Fetch the bodies of the blocks whose headers were selected, from the peer that sent the
+/// header or, should it fail to serve it, from the other peers.
+///
+/// Demoted peers are asked last and banned peers not at all, see [`Reputation`].
+///
+/// A block which no peer serves, even after a few attempts, can't be validated, nor can the
+/// blocks after it: its chain is dropped until a rollback below it, which the chain selection
+/// sends when it switches to another chain.
+pub struct FetchBlock<F> {
+    fetcher: F,
+    peers: Vec<Peer>,
+    reputation: Arc<Mutex<Reputation>>,
+    backoff: Duration,
+    /// The block which couldn't be fetched, if its chain is being dropped.
+    missing: Option<Point>,
+}
+
+impl<F: BlockFetcher> FetchBlock<F> {
+    /// Fetch blocks with the given fetcher, falling back on the given peers in this order.
+    pub fn new(fetcher: F, peers: &[Peer]) -> Self {
+        Self {
+            fetcher,
+            peers: peers.to_vec(),
+            reputation: Arc::new(Mutex::new(Reputation::default())),
+            backoff: DEFAULT_FETCH_BACKOFF,
+            missing: None,
+        }
+    }
+
+    /// Rank peers by their scores in the given reputation, e.g. that of the stages validating
+    /// headers.
+    pub fn with_reputation(mut self, reputation: Arc<Mutex<Reputation>>) -> Self {
+        self.reputation = reputation;
+        self
+    }
+
+    /// Wait the given time before asking the peers for a block anew, instead of the
+    /// [`DEFAULT_FETCH_BACKOFF`].
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// The peers to ask for a block announced by `peer`: that peer first, then the others in
+    /// order, leaving the demoted ones last and the banned ones out.
+    async fn candidates(&self, peer: &Peer) -> Vec<Peer> {
+        let reputation = self.reputation.lock().await;
+        let alternates = self.peers.iter().filter(|alternate| *alternate != peer);
+        let mut candidates = std::iter::once(peer)
+            .chain(alternates)
+            .filter(|candidate| !reputation.is_banned(candidate))
+            .cloned()
+            .collect::<Vec<_>>();
+        // the sort is stable, keeping the order among good peers and among demoted ones
+        candidates.sort_by_key(|candidate| reputation.status(candidate) == PeerStatus::Demoted);
+        candidates
+    }
+
+    /// Fetch the block at `point`, from `peer` first, then from each of the other peers until
+    /// one of them serves it, returning that peer along with the block, see
+    /// [`FetchBlock::with_reputation`] for how peers are ranked.
+    #[instrument(
+        level = Level::TRACE,
+        skip_all,
+        name = "consensus.fetch_block",
+        fields(peer = %peer.name, point.slot = %point.slot_or_default()),
+    )]
+    pub async fn fetch(
+        &self,
+        peer: &Peer,
+        point: &Point,
+    ) -> Result<(Peer, RawBlock), ConsensusError> {
+        for candidate in self.candidates(peer).await {
+            match self.fetcher.fetch_block(&candidate, point).await {
+                Ok(block) => return Ok((candidate.clone(), block)),
+                Err(e) => warn!(
+                    target: EVENT_TARGET,
+                    peer = %candidate.name,
+                    error = %e,
+                    "fetch_failed"
+                ),
+            }
+        }
+        Err(ConsensusError::FetchBlockFailed(point.clone()))
+    }
+
+    /// Fetch the block at `point` like [`FetchBlock::fetch`], asking the peers anew up to
+    /// [`FETCH_ATTEMPTS`] times, with a growing delay in between.
+    async fn fetch_with_retries(
+        &self,
+        peer: &Peer,
+        point: &Point,
+    ) -> Result<(Peer, RawBlock), ConsensusError> {
+        let mut backoff = self.backoff;
+        for attempt in 1..FETCH_ATTEMPTS {
+            match self.fetch(peer, point).await {
+                Ok(fetched) => return Ok(fetched),
+                Err(e) => warn!(
+                    target: EVENT_TARGET,
+                    point.slot = %point.slot_or_default(),
+                    attempt,
+                    error = %e,
+                    "fetch_retried"
+                ),
+            }
+            sleep(backoff).await;
+            backoff *= 2;
+        }
+        self.fetch(peer, point).await
+    }
+
+    /// Fetch the blocks of the given events, up to [`MAX_FETCHES_IN_FLIGHT`] at once, returning
+    /// the resulting events in the same order.
+    ///
+    /// The events of a dropped chain are left out, see [`FetchBlock`].
+    pub async fn handle_events(
+        &mut self,
+        events: Vec<ValidateHeaderEvent>,
+    ) -> Vec<FetchBlockEvent> {
+        // the blocks of a chain dropped already aren't even asked for
+        let mut missing = self.missing.clone();
+        let fetches = events.iter().map(|event| match event {
+            ValidateHeaderEvent::Validated { peer, point, .. } => {
+                missing.is_none().then_some((peer, point))
+            }
+            ValidateHeaderEvent::Rollback { rollback_point, .. } => {
+                if is_below(rollback_point, missing.as_ref()) {
+                    missing = None;
+                }
+                None
+            }
+        });
+        let this = &*self;
+        let fetched = stream::iter(fetches.collect::<Vec<_>>())
+            .map(|fetch| async move {
+                match fetch {
+                    Some((peer, point)) => Some(this.fetch_with_retries(peer, point).await),
+                    None => None,
+                }
+            })
+            .buffered(MAX_FETCHES_IN_FLIGHT)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut results = vec![];
+        for (event, fetched) in events.into_iter().zip(fetched) {
+            match event {
+                ValidateHeaderEvent::Validated { point, span, .. } => {
+                    if self.missing.is_some() {
+                        continue;
+                    }
+                    match fetched {
+                        Some(Ok((peer, block))) => results.push(FetchBlockEvent::BlockFetched {
+                            peer,
+                            point,
+                            block,
+                            span,
+                        }),
+                        Some(Err(_)) | None => {
+                            warn!(
+                                target: EVENT_TARGET,
+                                point.slot = %point.slot_or_default(),
+                                "fetch_abandoned"
+                            );
+                            self.missing = Some(point);
+                        }
+                    }
+                }
+                ValidateHeaderEvent::Rollback {
+                    peer,
+                    rollback_point,
+                    span,
+                } => {
+                    if is_below(&rollback_point, self.missing.as_ref()) {
+                        self.missing = None;
+                    }
+                    if self.missing.is_none() {
+                        results.push(FetchBlockEvent::Rollback {
+                            peer,
+                            rollback_point,
+                            span,
+                        });
+                    }
+                }
+            }
+        }
+        results
+    }
+}
+
+/// Whether a rollback to `rollback_point` leaves out the `missing` block, if any.
+fn is_below(rollback_point: &Point, missing: Option<&Point>) -> bool {
+    missing.is_some_and(|missing| rollback_point.slot_or_default() < missing.slot_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::reputation::Offence;
+    use amaru_kernel::Hash;
+    use std::collections::BTreeMap;
+    use tracing::Span;
+
+    /// Serves the blocks each peer has, recording which peers were asked.
+    #[derive(Default)]
+    struct FakeFetcher {
+        blocks: BTreeMap<String, RawBlock>,
+        asked: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl FakeFetcher {
+        fn asked(&self) -> Vec<String> {
+            self.asked.lock().unwrap().clone()
+        }
+    }
+
+    impl BlockFetcher for FakeFetcher {
+        async fn fetch_block(
+            &self,
+            peer: &Peer,
+            point: &Point,
+        ) -> Result<RawBlock, ConsensusError> {
+            self.asked.lock().unwrap().push(peer.name.clone());
+            self.blocks
+                .get(&peer.name)
+                .cloned()
+                .ok_or_else(|| ConsensusError::FetchBlockFailed(point.clone()))
+        }
+    }
+
+    fn peers() -> Vec<Peer> {
+        vec![Peer::new("alice"), Peer::new("bob"), Peer::new("carol")]
+    }
+
+    fn validated(peer: &str) -> ValidateHeaderEvent {
+        validated_at(peer, 42)
+    }
+
+    fn validated_at(peer: &str, slot: u64) -> ValidateHeaderEvent {
+        ValidateHeaderEvent::Validated {
+            peer: Peer::new(peer),
+            point: Point::Specific(slot, Hash::from([1; 32]).to_vec()),
+            span: Span::current(),
+        }
+    }
+
+    fn rollback_to(peer: &str, slot: u64) -> ValidateHeaderEvent {
+        ValidateHeaderEvent::Rollback {
+            peer: Peer::new(peer),
+            rollback_point: Point::Specific(slot, Hash::from([2; 32]).to_vec()),
+            span: Span::current(),
+        }
+    }
+
+    fn fetched_slots(events: &[FetchBlockEvent]) -> Vec<u64> {
+        events
+            .iter()
+            .map(|event| match event {
+                FetchBlockEvent::BlockFetched { point, .. } => point.slot_or_default(),
+                FetchBlockEvent::Rollback { rollback_point, .. } => {
+                    rollback_point.slot_or_default()
+                }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fetches_block_from_the_peer_that_sent_the_header() {
+        let fetcher = FakeFetcher {
+            blocks: BTreeMap::from([("bob".to_string(), vec![1]), ("carol".to_string(), vec![1])]),
+            ..FakeFetcher::default()
+        };
+        let mut fetch_block = FetchBlock::new(fetcher, &peers());
+
+        let events = fetch_block.handle_events(vec![validated("carol")]).await;
+
+        assert!(matches!(
+            events.as_slice(),
+            [FetchBlockEvent::BlockFetched { peer, block, .. }]
+                if *peer == Peer::new("carol") && *block == vec![1]
+        ));
+        assert_eq!(fetch_block.fetcher.asked(), vec!["carol"]);
+    }
+
+    #[tokio::test]
+    async fn falls_back_on_other_peers_when_fetch_fails() {
+        let fetcher = FakeFetcher {
+            blocks: BTreeMap::from([("carol".to_string(), vec![1])]),
+            ..FakeFetcher::default()
+        };
+        let mut fetch_block = FetchBlock::new(fetcher, &peers());
+
+        let events = fetch_block.handle_events(vec![validated("bob")]).await;
+
+        assert!(matches!(
+            events.as_slice(),
+            [FetchBlockEvent::BlockFetched { peer, .. }] if *peer == Peer::new("carol")
+        ));
+        assert_eq!(fetch_block.fetcher.asked(), vec!["bob", "alice", "carol"]);
+    }
+
+    #[tokio::test]
+    async fn emits_the_events_in_order() {
+        let fetcher = FakeFetcher {
+            blocks: BTreeMap::from([("alice".to_string(), vec![1]), ("bob".to_string(), vec![2])]),
+            ..FakeFetcher::default()
+        };
+        let mut fetch_block = FetchBlock::new(fetcher, &peers());
+
+        let events = fetch_block
+            .handle_events(vec![
+                validated_at("alice", 40),
+                validated_at("bob", 41),
+                rollback_to("alice", 40),
+                validated_at("bob", 42),
+            ])
+            .await;
+
+        assert_eq!(fetched_slots(&events), vec![40, 41, 40, 42]);
+    }
+
+    #[tokio::test]
+    async fn drops_the_chain_of_a_block_no_peer_serves_until_a_rollback_below_it() {
+        let mut fetch_block =
+            FetchBlock::new(FakeFetcher::default(), &peers()).with_backoff(Duration::ZERO);
+
+        let events = fetch_block
+            .handle_events(vec![validated_at("alice", 42), validated_at("alice", 43)])
+            .await;
+
+        assert!(events.is_empty());
+        // both blocks were asked to every peer on every attempt
+        assert_eq!(
+            fetch_block.fetcher.asked().len(),
+            2 * peers().len() * FETCH_ATTEMPTS as usize
+        );
+
+        fetch_block
+            .fetcher
+            .blocks
+            .insert("bob".to_string(), vec![1]);
+        let asked = fetch_block.fetcher.asked().len();
+        let events = fetch_block
+            .handle_events(vec![
+                validated_at("alice", 44),
+                rollback_to("alice", 42),
+                validated_at("alice", 45),
+                rollback_to("bob", 41),
+                validated_at("bob", 42),
+            ])
+            .await;
+
+        // the blocks of the dropped chain weren't even asked for
+        assert_eq!(fetch_block.fetcher.asked().len(), asked + 1);
+        assert_eq!(fetched_slots(&events), vec![41, 42]);
+    }
+
+    #[tokio::test]
+    async fn asks_demoted_peers_last_and_banned_peers_not_at_all() {
+        let reputation = Arc::new(Mutex::new(Reputation::default()));
+        reputation
+            .lock()
+            .await
+            .record(&Peer::new("alice"), Offence::InvalidHeader);
+        for _ in 0..6 {
+            reputation
+                .lock()
+                .await
+                .record(&Peer::new("bob"), Offence::InvalidHeader);
+        }
+        let fetch_block =
+            FetchBlock::new(FakeFetcher::default(), &peers()).with_reputation(reputation);
+
+        let result = fetch_block.fetch(&Peer::new("alice"), &Point::Origin).await;
+
+        assert!(matches!(result, Err(ConsensusError::FetchBlockFailed(_))));
+        assert_eq!(fetch_block.fetcher.asked(), vec!["carol", "alice"]);
+    }
+}
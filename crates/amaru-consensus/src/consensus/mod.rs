@@ -12,18 +12,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use amaru_kernel::{Header, Point};
+use amaru_kernel::{block::ValidateBlockEvent, Header, Point, RawBlock};
 use tracing::Span;
 
 use crate::peer::Peer;
 
 pub mod chain_selection;
+pub mod fetch_block;
+pub mod opcert;
+pub mod prune;
 pub mod receive_header;
+pub mod reputation;
+pub mod seen_headers;
 pub mod select_chain;
 pub mod store;
 pub mod store_block;
 pub mod store_header;
 pub mod validate_header;
+pub mod watchdog;
 
 pub const EVENT_TARGET: &str = "amaru::consensus";
 
@@ -72,3 +78,37 @@ pub enum ValidateHeaderEvent {
         span: Span,
     },
 }
+
+#[derive(Clone, Debug)]
+pub enum FetchBlockEvent {
+    /// The body of a selected block, along with the peer that served it.
+    BlockFetched {
+        peer: Peer,
+        point: Point,
+        block: RawBlock,
+        span: Span,
+    },
+    Rollback {
+        peer: Peer,
+        rollback_point: Point,
+        span: Span,
+    },
+}
+
+impl From<FetchBlockEvent> for ValidateBlockEvent {
+    fn from(event: FetchBlockEvent) -> Self {
+        match event {
+            FetchBlockEvent::BlockFetched {
+                point, block, span, ..
+            } => ValidateBlockEvent::Validated { point, block, span },
+            FetchBlockEvent::Rollback {
+                rollback_point,
+                span,
+                ..
+            } => ValidateBlockEvent::Rollback {
+                rollback_point,
+                span,
+            },
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::store::ChainStore;
+use amaru_kernel::{Header, PoolId};
+use amaru_ouroboros::{ed25519, issuer_to_pool_id, IsHeader};
+use amaru_ouroboros_traits::{HasStakeDistribution, PoolSummary};
+use slot_arithmetic::Slot;
+use std::collections::HashMap;
+
+/// The latest sequence number of the operational certificate of each pool, as found in the
+/// headers of the chain up to some point.
+///
+/// A header must carry a certificate with the same sequence number as the latest one of its
+/// pool, or the next one: this rejects headers signed with a hot key the pool has since
+/// replaced, e.g. because it was compromised. The ledger only knows the sequence numbers as of
+/// its last snapshot, hence the need to follow them along the chain of each header, so that
+/// those of a fork the node rolled back from don't apply to another.
+#[derive(Debug, Default)]
+pub struct OpCertCounters {
+    counters: HashMap<PoolId, u64>,
+}
+
+impl OpCertCounters {
+    /// The counters as of the parent of `header`, from the headers of its last `window`
+    /// ancestors found in the store, e.g. those within the security parameter of the chain.
+    ///
+    /// Older sequence numbers are left to the ledger.
+    pub fn before(store: &dyn ChainStore<Header>, header: &Header, window: u64) -> Self {
+        let mut counters = HashMap::new();
+        let mut next = header.parent();
+        for _ in 0..window {
+            let Some(ancestor) = next.and_then(|hash| store.load_header(&hash)) else {
+                break;
+            };
+            if let Some(pool) = issuer(&ancestor) {
+                // the nearest ancestors of the header come first, with the latest numbers
+                counters.entry(pool).or_insert(sequence_number(&ancestor));
+            }
+            next = ancestor.parent();
+        }
+        Self { counters }
+    }
+
+    pub fn latest(&self, pool: &PoolId) -> Option<u64> {
+        self.counters.get(pool).copied()
+    }
+
+    /// The given ledger, with the sequence numbers of the certificates found along the chain
+    /// in place of its own.
+    pub fn over<'a>(
+        &'a self,
+        ledger: &'a dyn HasStakeDistribution,
+    ) -> impl HasStakeDistribution + 'a {
+        WithOpCertCounters {
+            ledger,
+            counters: self,
+        }
+    }
+}
+
+/// The pool that issued the header.
+pub(crate) fn issuer(header: &Header) -> Option<PoolId> {
+    let issuer =
+        <[u8; ed25519::PublicKey::SIZE]>::try_from(&header.header_body.issuer_vkey[..]).ok()?;
+    Some(issuer_to_pool_id(&ed25519::PublicKey::from(issuer)))
+}
+
+fn sequence_number(header: &Header) -> u64 {
+    header
+        .header_body
+        .operational_cert
+        .operational_cert_sequence_number
+}
+
+struct WithOpCertCounters<'a> {
+    ledger: &'a dyn HasStakeDistribution,
+    counters: &'a OpCertCounters,
+}
+
+impl HasStakeDistribution for WithOpCertCounters<'_> {
+    fn get_pool(&self, slot: Slot, pool: &PoolId) -> Option<PoolSummary> {
+        self.ledger.get_pool(slot, pool)
+    }
+
+    fn slot_to_kes_period(&self, slot: Slot) -> u64 {
+        self.ledger.slot_to_kes_period(slot)
+    }
+
+    fn max_kes_evolutions(&self) -> u64 {
+        self.ledger.max_kes_evolutions()
+    }
+
+    fn latest_opcert_sequence_number(&self, pool: &PoolId) -> Option<u64> {
+        self.counters
+            .latest(pool)
+            .or_else(|| self.ledger.latest_opcert_sequence_number(pool))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::store::test::{
+        FakeStore, PREPROD_HEADER_70070379, PREPROD_HEADER_70070426,
+    };
+    use amaru_ouroboros_traits::has_stake_distribution::mock::MockLedgerState;
+
+    fn with_sequence_number(header: &Header, sequence_number: u64) -> Header {
+        let mut header = header.clone();
+        header
+            .header_body
+            .operational_cert
+            .operational_cert_sequence_number = sequence_number;
+        header
+    }
+
+    fn with_parent(header: &Header, parent: &Header) -> Header {
+        let mut header = header.clone();
+        header.header_body.prev_hash = Some(parent.hash());
+        header
+    }
+
+    fn store(headers: &[&Header]) -> FakeStore {
+        let mut store = FakeStore::default();
+        for header in headers {
+            store.store_header(&header.hash(), header).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn sequence_numbers_seen_in_headers_supersede_those_of_the_ledger() {
+        let parent = &*PREPROD_HEADER_70070379;
+        let header = &*PREPROD_HEADER_70070426;
+        let pool = issuer(parent).unwrap();
+        let sequence_number = sequence_number(parent);
+        let mut ledger = MockLedgerState::new(
+            "c0d1f9b040d2f6fd7fc8775d24753d6db4b697429f11404a6178a0a4a005867b",
+            1,
+            1,
+        );
+        ledger
+            .op_certs
+            .insert(pool, sequence_number.saturating_sub(1));
+
+        let counters = OpCertCounters::before(&store(&[]), header, 10);
+        assert_eq!(
+            counters.over(&ledger).latest_opcert_sequence_number(&pool),
+            Some(sequence_number.saturating_sub(1))
+        );
+
+        let counters = OpCertCounters::before(&store(&[parent]), header, 10);
+        assert_eq!(
+            counters.over(&ledger).latest_opcert_sequence_number(&pool),
+            Some(sequence_number)
+        );
+    }
+
+    #[test]
+    fn sequence_numbers_follow_the_chain_of_each_header() {
+        let parent = &*PREPROD_HEADER_70070379;
+        let pool = issuer(parent).unwrap();
+        // the same pool issued the parent on two forks, with different certificates
+        let fork = with_sequence_number(parent, sequence_number(parent) + 1);
+        let header = &*PREPROD_HEADER_70070426;
+        let header_on_fork = with_parent(header, &fork);
+        let store = store(&[parent, &fork]);
+
+        assert_eq!(
+            OpCertCounters::before(&store, &header_on_fork, 10).latest(&pool),
+            Some(sequence_number(parent) + 1)
+        );
+        // rolling back to the other fork leaves its sequence number behind
+        assert_eq!(
+            OpCertCounters::before(&store, header, 10).latest(&pool),
+            Some(sequence_number(parent))
+        );
+    }
+
+    #[test]
+    fn only_the_headers_within_the_window_count() {
+        let grandparent = with_sequence_number(&PREPROD_HEADER_70070379, 5);
+        let pool = issuer(&grandparent).unwrap();
+        let mut parent = with_parent(&PREPROD_HEADER_70070426, &grandparent);
+        parent.header_body.issuer_vkey = vec![0; ed25519::PublicKey::SIZE].into();
+        let header = with_parent(&PREPROD_HEADER_70070426, &parent);
+        let store = store(&[&grandparent, &parent]);
+
+        assert_eq!(
+            OpCertCounters::before(&store, &header, 2).latest(&pool),
+            Some(5)
+        );
+        assert_eq!(
+            OpCertCounters::before(&store, &header, 1).latest(&pool),
+            None
+        );
+    }
+}
@@ -0,0 +1,289 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    consensus::{
+        seen_headers::SeenHeaders,
+        store::{ChainStore, StoreError},
+        EVENT_TARGET,
+    },
+    ConsensusError,
+};
+use amaru_kernel::{Hash, Point};
+use amaru_ouroboros_traits::IsHeader;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::Mutex;
+use tracing::{info, instrument, Level};
+
+/// The number of new tips between two prunings by default, see [`PruneForks`].
+pub const DEFAULT_PRUNING_INTERVAL: u64 = 1000;
+
+/// Remove from the store the headers of the forks which branched off the chain ending at `tip`
+/// more than `max_rollback` blocks before it, as the chain can no longer switch to them.
+///
+/// The chain is only walked down to `lowest_height`, e.g. the oldest height the chain could be
+/// rolled back to at the last pruning, which bounds the walk to the window of the last
+/// `max_rollback` blocks and those since then, and the headers looked at to those at or above
+/// the bottom of the walk, see [`ChainStore::header_hashes_from`]. Headers below it are kept:
+/// they are either on
+/// the chain, or on forks pruned before. So are headers whose ancestry cannot be traced back
+/// to the chain, because some ancestor is missing from the store. Returns the hashes of the
+/// removed headers.
+#[instrument(level = Level::TRACE, skip_all, fields(tip.slot = %tip.slot_or_default()))]
+pub fn prune_abandoned_forks<H, S>(
+    store: &mut S,
+    tip: &Point,
+    max_rollback: u64,
+    lowest_height: u64,
+) -> Result<Vec<Hash<32>>, StoreError>
+where
+    H: IsHeader,
+    S: ChainStore<H> + ?Sized,
+{
+    // The height of each header of the chain down to the lowest height, which the forks are
+    // measured against.
+    let mut chain: HashMap<Hash<32>, u64> = HashMap::new();
+    let mut next = match tip {
+        Point::Origin => None,
+        Point::Specific(..) => Some(Hash::from(tip)),
+    };
+    while let Some(header) = next.and_then(|hash| store.load_header(&hash)) {
+        chain.insert(header.hash(), header.block_height());
+        if header.block_height() <= lowest_height {
+            break;
+        }
+        next = header.parent();
+    }
+    let tip_height = chain.values().max().copied().unwrap_or_default();
+    let bottom_height = chain.values().min().copied().unwrap_or_default();
+    let oldest_height = tip_height.saturating_sub(max_rollback);
+
+    // Whether each header off the chain is to be removed, shared by the headers of a same fork.
+    // Headers below the part walked are kept whatever their fork, so they aren't even listed.
+    let mut abandoned: HashMap<Hash<32>, bool> = HashMap::new();
+    for hash in store.header_hashes_from(bottom_height)? {
+        if chain.contains_key(&hash) || abandoned.contains_key(&hash) {
+            continue;
+        }
+        let mut fork = vec![];
+        let mut next = Some(hash);
+        let is_abandoned = loop {
+            let Some(hash) = next else {
+                // the fork branched off at the origin
+                break oldest_height > 0;
+            };
+            if let Some(height) = chain.get(&hash) {
+                break *height < oldest_height;
+            }
+            if let Some(is_abandoned) = abandoned.get(&hash) {
+                break *is_abandoned;
+            }
+            let Some(header) = store.load_header(&hash) else {
+                break false;
+            };
+            if header.block_height() < bottom_height {
+                // either on the chain, below the part walked, or where a fork with headers
+                // above it branched off, deeper than that part
+                break !fork.is_empty() && bottom_height <= oldest_height;
+            }
+            fork.push(hash);
+            next = header.parent();
+        };
+        abandoned.extend(fork.into_iter().map(|hash| (hash, is_abandoned)));
+    }
+
+    let mut pruned = vec![];
+    for (hash, is_abandoned) in abandoned {
+        if is_abandoned {
+            store.remove_header(&hash)?;
+            pruned.push(hash);
+        }
+    }
+    Ok(pruned)
+}
+
+/// Prune the forks abandoned by the node from the chain store as its tip moves forward, every
+/// so many new tips, see [`prune_abandoned_forks`].
+///
+/// Each pruning walks the chain down to the oldest height the previous one could roll back
+/// to, the first one down to the origin.
+pub struct PruneForks<H> {
+    store: Arc<Mutex<dyn ChainStore<H>>>,
+    max_rollback: u64,
+    interval: u64,
+    since_last: u64,
+    lowest_height: u64,
+    stored: Option<Arc<Mutex<SeenHeaders>>>,
+}
+
+impl<H: IsHeader> PruneForks<H> {
+    pub fn new(store: Arc<Mutex<dyn ChainStore<H>>>, max_rollback: u64) -> Self {
+        Self {
+            store,
+            max_rollback,
+            interval: DEFAULT_PRUNING_INTERVAL,
+            since_last: 0,
+            lowest_height: 0,
+            stored: None,
+        }
+    }
+
+    /// Prune every `interval` new tips.
+    pub fn with_interval(mut self, interval: u64) -> Self {
+        self.interval = interval.max(1);
+        self
+    }
+
+    /// Forget the pruned headers among the given ones, e.g. those stored lately by
+    /// [`StoreHeader`](super::store_header::StoreHeader), so that they are stored again should
+    /// a peer announce them anew.
+    pub fn with_stored(mut self, stored: Arc<Mutex<SeenHeaders>>) -> Self {
+        self.stored = Some(stored);
+        self
+    }
+
+    /// Take note of a new tip of the chain, pruning the forks abandoned since the last time if
+    /// enough tips went by. Returns the hashes of the removed headers.
+    pub async fn handle_new_tip(&mut self, tip: &Point) -> Result<Vec<Hash<32>>, ConsensusError> {
+        self.since_last += 1;
+        if self.since_last < self.interval {
+            return Ok(vec![]);
+        }
+        self.since_last = 0;
+
+        let pruned = {
+            let mut store = self.store.lock().await;
+            let pruned =
+                prune_abandoned_forks(&mut *store, tip, self.max_rollback, self.lowest_height)
+                    .map_err(|e| ConsensusError::PruneForksFailed(tip.clone(), e))?;
+            let tip_height = match tip {
+                Point::Origin => None,
+                Point::Specific(..) => store.load_header(&Hash::from(tip)),
+            }
+            .map_or(0, |header| header.block_height());
+            self.lowest_height = tip_height.saturating_sub(self.max_rollback);
+            pruned
+        };
+        // the store is released first, as storing headers takes the seen ones before it
+        if let Some(stored) = &self.stored {
+            let mut stored = stored.lock().await;
+            for hash in &pruned {
+                stored.remove(hash);
+            }
+        }
+        info!(
+            target: EVENT_TARGET,
+            tip.slot = %tip.slot_or_default(),
+            pruned = pruned.len(),
+            "pruned_forks"
+        );
+        Ok(pruned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::chain_selection::tests::{generate_headers_anchored_at, FakeStore};
+    use amaru_ouroboros_traits::is_header::fake::FakeHeader;
+
+    fn store_all(store: &mut FakeStore, headers: &[FakeHeader]) {
+        for header in headers {
+            store.store_header(&header.hash(), header).unwrap();
+        }
+    }
+
+    #[test]
+    fn prunes_forks_branching_off_deeper_than_max_rollback() {
+        let mut store = FakeStore::default();
+        let chain = generate_headers_anchored_at(None, 10);
+        // branches off 7 blocks before the tip
+        let old_fork = generate_headers_anchored_at(Some(chain[2]), 3);
+        // branches off 2 blocks before the tip
+        let recent_fork = generate_headers_anchored_at(Some(chain[7]), 1);
+        store_all(&mut store, &chain);
+        store_all(&mut store, &old_fork);
+        store_all(&mut store, &recent_fork);
+
+        let mut pruned = prune_abandoned_forks(&mut store, &chain[9].point(), 3, 0).unwrap();
+
+        let mut expected = old_fork.iter().map(|h| h.hash()).collect::<Vec<_>>();
+        pruned.sort();
+        expected.sort();
+        assert_eq!(expected, pruned);
+        assert!(chain.iter().all(|h| store.load_header(&h.hash()).is_some()));
+        assert!(store.load_header(&recent_fork[0].hash()).is_some());
+    }
+
+    #[test]
+    fn keeps_headers_of_unknown_ancestry() {
+        let mut store = FakeStore::default();
+        let chain = generate_headers_anchored_at(None, 10);
+        let fork = generate_headers_anchored_at(Some(chain[1]), 3);
+        store_all(&mut store, &chain);
+        // the first header of the fork is missing
+        store_all(&mut store, &fork[1..]);
+
+        let pruned = prune_abandoned_forks(&mut store, &chain[9].point(), 3, 0).unwrap();
+
+        assert!(pruned.is_empty());
+    }
+
+    #[test]
+    fn only_walks_the_chain_down_to_the_lowest_height() {
+        let mut store = FakeStore::default();
+        let chain = generate_headers_anchored_at(None, 10);
+        // branches off 7 blocks before the tip, 2 blocks below the lowest height
+        let old_fork = generate_headers_anchored_at(Some(chain[2]), 3);
+        store_all(&mut store, &chain);
+        store_all(&mut store, &old_fork);
+
+        let mut pruned = prune_abandoned_forks(&mut store, &chain[9].point(), 3, 5).unwrap();
+
+        // the first header of the fork can't be told apart from those of the chain below the
+        // lowest height, unlike those above it
+        let mut expected = old_fork[1..].iter().map(|h| h.hash()).collect::<Vec<_>>();
+        pruned.sort();
+        expected.sort();
+        assert_eq!(expected, pruned);
+        assert!(chain.iter().all(|h| store.load_header(&h.hash()).is_some()));
+    }
+
+    #[tokio::test]
+    async fn prunes_every_interval_tips() {
+        let chain = generate_headers_anchored_at(None, 10);
+        let fork = generate_headers_anchored_at(None, 2);
+        let mut store = FakeStore::default();
+        store_all(&mut store, &chain);
+        store_all(&mut store, &fork);
+        let store = Arc::new(Mutex::new(store));
+        let stored = Arc::new(Mutex::new(SeenHeaders::default()));
+        for header in chain.iter().chain(&fork) {
+            stored.lock().await.insert(header.hash());
+        }
+        let mut prune_forks = PruneForks::new(store.clone(), 3)
+            .with_interval(2)
+            .with_stored(stored.clone());
+
+        let pruned = prune_forks.handle_new_tip(&chain[8].point()).await.unwrap();
+        assert!(pruned.is_empty());
+
+        let pruned = prune_forks.handle_new_tip(&chain[9].point()).await.unwrap();
+        assert_eq!(2, pruned.len());
+        // the pruned headers are stored again should a peer announce them anew
+        let stored = stored.lock().await;
+        assert!(fork.iter().all(|h| !stored.contains(&h.hash())));
+        assert!(chain.iter().all(|h| stored.contains(&h.hash())));
+    }
+}
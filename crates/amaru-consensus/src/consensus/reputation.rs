@@ -0,0 +1,336 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{consensus::EVENT_TARGET, peer::Peer};
+use amaru_kernel::PoolId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// A misbehaviour of a peer, lowering its score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offence {
+    /// The peer sent a header that failed validation.
+    InvalidHeader,
+    /// The peer stopped sending headers while we expected some.
+    Stall,
+    /// The peer rolled back further than the security parameter allows.
+    DeepRollback,
+}
+
+/// How a peer is treated, given its score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerStatus {
+    Good,
+    /// The peer misbehaved, but not enough to be banned yet.
+    Demoted,
+    /// The peer misbehaved too much, and whatever it sends is rejected from now on.
+    Banned,
+}
+
+/// The penalties for each offence and the scores below which peers are demoted or banned.
+///
+/// Peers start with a score of 0, lowered by their offences, and recover a point every
+/// `recovery_interval` until back to 0: bans are lifted once peers behave for long enough.
+/// A single offence is meant to demote a peer at most, and only repeated ones to ban it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReputationConfig {
+    pub invalid_header_penalty: i64,
+    pub stall_penalty: i64,
+    pub deep_rollback_penalty: i64,
+    pub demote_below: i64,
+    pub ban_below: i64,
+    pub recovery_interval: Duration,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            invalid_header_penalty: 10,
+            stall_penalty: 5,
+            deep_rollback_penalty: 20,
+            demote_below: 0,
+            ban_below: -50,
+            recovery_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReputationConfig {
+    fn penalty(&self, offence: Offence) -> i64 {
+        match offence {
+            Offence::InvalidHeader => self.invalid_header_penalty,
+            Offence::Stall => self.stall_penalty,
+            Offence::DeepRollback => self.deep_rollback_penalty,
+        }
+    }
+
+    /// The points recovered by a score since `since`.
+    fn recovered(&self, since: Instant, now: Instant) -> i64 {
+        let elapsed = now.saturating_duration_since(since).as_nanos();
+        let interval = self.recovery_interval.as_nanos().max(1);
+        i64::try_from(elapsed / interval).unwrap_or(i64::MAX)
+    }
+
+    fn status(&self, score: i64) -> PeerStatus {
+        if score < self.ban_below {
+            PeerStatus::Banned
+        } else if score < self.demote_below {
+            PeerStatus::Demoted
+        } else {
+            PeerStatus::Good
+        }
+    }
+}
+
+/// A change of the score of a peer, following an offence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScoreChange {
+    pub peer: Peer,
+    pub offence: Offence,
+    /// The score of the peer after the offence.
+    pub score: i64,
+    pub status: PeerStatus,
+    /// The status of the peer before the offence, to tell demotions and bans apart from
+    /// further offences of peers already demoted or banned.
+    pub previous_status: PeerStatus,
+}
+
+/// The score of a peer as of its last offence.
+#[derive(Debug, Clone, Copy)]
+struct Score {
+    points: i64,
+    at: Instant,
+}
+
+/// The scores of the peers, lowered as they misbehave and recovering over time.
+///
+/// Score changes are logged under [`EVENT_TARGET`] and kept until they are
+/// [taken](Reputation::take_events), so that the node and the simulator can act on them.
+///
+/// Equivocations are counted against the pool issuing the headers rather than against the
+/// peers relaying them, which may well be honest: see [`Reputation::record_equivocation`].
+#[derive(Debug, Default)]
+pub struct Reputation {
+    config: ReputationConfig,
+    scores: HashMap<Peer, Score>,
+    equivocations: HashMap<PoolId, u64>,
+    events: Vec<ScoreChange>,
+}
+
+impl Reputation {
+    pub fn new(config: ReputationConfig) -> Self {
+        Self {
+            config,
+            scores: HashMap::new(),
+            equivocations: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Lower the score of `peer` for the given offence, returning the resulting change.
+    pub fn record(&mut self, peer: &Peer, offence: Offence) -> ScoreChange {
+        self.record_at(peer, offence, Instant::now())
+    }
+
+    /// Like [`Self::record`], for an offence at the given time.
+    pub fn record_at(&mut self, peer: &Peer, offence: Offence, now: Instant) -> ScoreChange {
+        let score = self.score_at(peer, now);
+        let previous_status = self.config.status(score);
+        let score = score.saturating_sub(self.config.penalty(offence));
+        self.scores.insert(
+            peer.clone(),
+            Score {
+                points: score,
+                at: now,
+            },
+        );
+        let change = ScoreChange {
+            peer: peer.clone(),
+            offence,
+            score,
+            status: self.config.status(score),
+            previous_status,
+        };
+        info!(
+            target: EVENT_TARGET,
+            peer = %peer.name,
+            offence = ?offence,
+            score = change.score,
+            status = ?change.status,
+            "peer_score"
+        );
+        self.events.push(change.clone());
+        change
+    }
+
+    /// Count an equivocation of `issuer`, which issued two different headers for `slot`,
+    /// returning how many it issued so far.
+    pub fn record_equivocation(&mut self, issuer: &PoolId, slot: u64) -> u64 {
+        let equivocations = self.equivocations.entry(*issuer).or_default();
+        *equivocations += 1;
+        warn!(
+            target: EVENT_TARGET,
+            issuer = %issuer,
+            slot,
+            equivocations = *equivocations,
+            "issuer_equivocation"
+        );
+        *equivocations
+    }
+
+    /// How many equivocations of `issuer` were recorded.
+    pub fn equivocations(&self, issuer: &PoolId) -> u64 {
+        self.equivocations.get(issuer).copied().unwrap_or_default()
+    }
+
+    pub fn score(&self, peer: &Peer) -> i64 {
+        self.score_at(peer, Instant::now())
+    }
+
+    /// The score of `peer` at the given time, having recovered since its last offence.
+    pub fn score_at(&self, peer: &Peer, now: Instant) -> i64 {
+        self.scores.get(peer).map_or(0, |score| {
+            score
+                .points
+                .saturating_add(self.config.recovered(score.at, now))
+                .min(0)
+        })
+    }
+
+    pub fn status(&self, peer: &Peer) -> PeerStatus {
+        self.config.status(self.score(peer))
+    }
+
+    pub fn is_banned(&self, peer: &Peer) -> bool {
+        self.status(peer) == PeerStatus::Banned
+    }
+
+    pub fn is_banned_at(&self, peer: &Peer, now: Instant) -> bool {
+        self.config.status(self.score_at(peer, now)) == PeerStatus::Banned
+    }
+
+    /// The score changes since the last call, in the order they happened.
+    pub fn take_events(&mut self) -> Vec<ScoreChange> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offences_demote_then_ban_peers() {
+        let alice = Peer::new("alice");
+        let bob = Peer::new("bob");
+        let mut reputation = Reputation::default();
+        let now = Instant::now();
+
+        assert_eq!(reputation.status(&alice), PeerStatus::Good);
+
+        let change = reputation.record_at(&alice, Offence::InvalidHeader, now);
+        assert_eq!(change.score, -10);
+        assert_eq!(change.previous_status, PeerStatus::Good);
+        assert_eq!(change.status, PeerStatus::Demoted);
+
+        for _ in 0..2 {
+            reputation.record_at(&alice, Offence::DeepRollback, now);
+        }
+        let change = reputation.record_at(&alice, Offence::Stall, now);
+        assert_eq!(change.score, -55);
+        assert_eq!(change.previous_status, PeerStatus::Demoted);
+        assert_eq!(change.status, PeerStatus::Banned);
+        assert!(reputation.is_banned_at(&alice, now));
+
+        assert_eq!(reputation.score(&bob), 0);
+        assert_eq!(reputation.status(&bob), PeerStatus::Good);
+    }
+
+    #[test]
+    fn no_single_offence_bans_a_peer() {
+        for offence in [
+            Offence::InvalidHeader,
+            Offence::Stall,
+            Offence::DeepRollback,
+        ] {
+            let mut reputation = Reputation::default();
+            let change = reputation.record(&Peer::new("alice"), offence);
+            assert_ne!(change.status, PeerStatus::Banned, "{:?}", offence);
+        }
+    }
+
+    #[test]
+    fn bans_are_lifted_as_scores_recover() {
+        let alice = Peer::new("alice");
+        let mut reputation = Reputation::new(ReputationConfig {
+            recovery_interval: Duration::from_secs(1),
+            ..ReputationConfig::default()
+        });
+        let now = Instant::now();
+        for _ in 0..6 {
+            reputation.record_at(&alice, Offence::InvalidHeader, now);
+        }
+
+        assert!(reputation.is_banned_at(&alice, now));
+        assert_eq!(
+            reputation.score_at(&alice, now + Duration::from_secs(9)),
+            -51
+        );
+        assert!(!reputation.is_banned_at(&alice, now + Duration::from_secs(10)));
+        assert_eq!(
+            reputation.score_at(&alice, now + Duration::from_secs(600)),
+            0
+        );
+
+        // further offences count from the recovered score
+        let change = reputation.record_at(&alice, Offence::Stall, now + Duration::from_secs(30));
+        assert_eq!(change.score, -35);
+        assert_eq!(change.previous_status, PeerStatus::Demoted);
+    }
+
+    #[test]
+    fn equivocations_are_counted_against_their_issuer() {
+        let issuer = PoolId::new([1; 28]);
+        let mut reputation = Reputation::default();
+
+        assert_eq!(reputation.record_equivocation(&issuer, 42), 1);
+        assert_eq!(reputation.record_equivocation(&issuer, 43), 2);
+        assert_eq!(reputation.equivocations(&issuer), 2);
+        assert_eq!(reputation.equivocations(&PoolId::new([2; 28])), 0);
+        assert!(reputation.take_events().is_empty());
+    }
+
+    #[test]
+    fn score_changes_are_taken_once() {
+        let alice = Peer::new("alice");
+        let mut reputation = Reputation::new(ReputationConfig {
+            stall_penalty: 1,
+            ..ReputationConfig::default()
+        });
+
+        reputation.record(&alice, Offence::Stall);
+        reputation.record(&alice, Offence::Stall);
+
+        let scores = reputation
+            .take_events()
+            .into_iter()
+            .map(|change| change.score)
+            .collect::<Vec<_>>();
+        assert_eq!(scores, vec![-1, -2]);
+        assert!(reputation.take_events().is_empty());
+    }
+}
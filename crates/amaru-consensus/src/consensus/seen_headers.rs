@@ -0,0 +1,205 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use amaru_kernel::Hash;
+use std::collections::{HashMap, VecDeque};
+
+/// The number of header hashes remembered by default, see [`SeenHeaders`].
+pub const DEFAULT_SEEN_HEADERS_CAPACITY: usize = 10_000;
+
+/// The hashes of the last headers a stage processed, so that headers announced by several
+/// peers are only processed once.
+///
+/// Once full, the oldest hashes are forgotten first, and the headers they stand for are
+/// processed anew should a peer announce them again.
+///
+/// Lookups are counted, so that the capacity can be tuned from the [`SeenHeadersMetrics`].
+#[derive(Debug)]
+pub struct SeenHeaders {
+    capacity: usize,
+    /// Each hash remembered, along with the number it was inserted as.
+    hashes: HashMap<Hash<32>, u64>,
+    /// The hashes in order of insertion. Removed hashes are left behind, to be skipped when
+    /// forgetting the oldest ones, along with those inserted again since, which their number
+    /// tells apart.
+    order: VecDeque<(u64, Hash<32>)>,
+    inserted: u64,
+    hits: u64,
+    misses: u64,
+}
+
+/// How well a [`SeenHeaders`] performs, to tune its capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeenHeadersMetrics {
+    /// The number of lookups of headers processed already.
+    pub hits: u64,
+    /// The number of lookups of headers that had to be processed.
+    pub misses: u64,
+    /// The number of hashes currently remembered.
+    pub size: usize,
+    pub capacity: usize,
+}
+
+impl Default for SeenHeaders {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEEN_HEADERS_CAPACITY)
+    }
+}
+
+impl SeenHeaders {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            hashes: HashMap::new(),
+            order: VecDeque::new(),
+            inserted: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn contains(&self, hash: &Hash<32>) -> bool {
+        self.hashes.contains_key(hash)
+    }
+
+    /// Like [`Self::contains`], counting the lookup in the [`Self::metrics`].
+    pub fn lookup(&mut self, hash: &Hash<32>) -> bool {
+        let seen = self.hashes.contains_key(hash);
+        if seen {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        seen
+    }
+
+    pub fn metrics(&self) -> SeenHeadersMetrics {
+        SeenHeadersMetrics {
+            hits: self.hits,
+            misses: self.misses,
+            size: self.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// Remember the given hash, returning whether it was new.
+    pub fn insert(&mut self, hash: Hash<32>) -> bool {
+        if self.hashes.contains_key(&hash) {
+            return false;
+        }
+        self.inserted += 1;
+        self.hashes.insert(hash, self.inserted);
+        self.order.push_back((self.inserted, hash));
+        while self.hashes.len() > self.capacity {
+            let Some((number, oldest)) = self.order.pop_front() else {
+                break;
+            };
+            if self.hashes.get(&oldest) == Some(&number) {
+                self.hashes.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Forget the given hash, e.g. once the header it stands for was removed from the store,
+    /// returning whether it was remembered.
+    ///
+    /// The hash is only dropped from the order of insertion once it comes first, or when the
+    /// removed hashes outnumber those remembered, keeping removals cheap.
+    pub fn remove(&mut self, hash: &Hash<32>) -> bool {
+        if self.hashes.remove(hash).is_none() {
+            return false;
+        }
+        if self.order.len() > 2 * self.capacity {
+            let hashes = &self.hashes;
+            self.order
+                .retain(|(number, remembered)| hashes.get(remembered) == Some(number));
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_the_oldest_hashes_once_full() {
+        let mut seen = SeenHeaders::new(2);
+        let hashes = [1, 2, 3].map(|n| Hash::from([n; 32]));
+
+        assert!(seen.insert(hashes[0]));
+        assert!(seen.insert(hashes[1]));
+        assert!(!seen.insert(hashes[0]));
+        assert!(seen.insert(hashes[2]));
+
+        assert!(!seen.contains(&hashes[0]));
+        assert!(seen.contains(&hashes[1]));
+        assert!(seen.contains(&hashes[2]));
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let mut seen = SeenHeaders::new(2);
+        let hashes = [1, 2, 3].map(|n| Hash::from([n; 32]));
+
+        assert!(!seen.lookup(&hashes[0]));
+        seen.insert(hashes[0]);
+        assert!(seen.lookup(&hashes[0]));
+        seen.insert(hashes[1]);
+        seen.insert(hashes[2]);
+        assert!(!seen.lookup(&hashes[0]));
+
+        assert_eq!(
+            seen.metrics(),
+            SeenHeadersMetrics {
+                hits: 1,
+                misses: 2,
+                size: 2,
+                capacity: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn forgets_removed_hashes_without_evicting_them_again() {
+        let mut seen = SeenHeaders::new(2);
+        let hashes = [1, 2, 3].map(|n| Hash::from([n; 32]));
+
+        seen.insert(hashes[0]);
+        seen.insert(hashes[1]);
+        assert!(seen.remove(&hashes[0]));
+        assert!(!seen.remove(&hashes[0]));
+        // the removed hash is remembered anew, as the newest one
+        assert!(seen.insert(hashes[0]));
+        assert!(seen.insert(hashes[2]));
+
+        assert_eq!(seen.len(), 2);
+        assert!(!seen.contains(&hashes[1]));
+        assert!(seen.contains(&hashes[0]));
+        assert!(seen.contains(&hashes[2]));
+    }
+}
@@ -16,6 +16,8 @@ use super::{chain_selection::RollbackChainSelection, DecodedChainSyncEvent, Vali
 use crate::{
     consensus::{
         chain_selection::{self, ChainSelector, Fork},
+        reputation::{Offence, Reputation},
+        store::ChainStore,
         EVENT_TARGET,
     },
     peer::Peer,
@@ -25,15 +27,56 @@ use amaru_kernel::{Hash, Header, Point};
 use amaru_ouroboros::IsHeader;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tracing::{trace, Span};
+use tracing::{trace, warn, Span};
 
 pub struct SelectChain {
     chain_selector: Arc<Mutex<ChainSelector<Header>>>,
+    reputation: Arc<Mutex<Reputation>>,
+    store: Option<Arc<Mutex<dyn ChainStore<Header>>>>,
 }
 
 impl SelectChain {
     pub fn new(chain_selector: Arc<Mutex<ChainSelector<Header>>>) -> Self {
-        SelectChain { chain_selector }
+        SelectChain {
+            chain_selector,
+            reputation: Arc::new(Mutex::new(Reputation::default())),
+            store: None,
+        }
+    }
+
+    /// Persist the state of the chain selector in the given store whenever the tip changes, so
+    /// that it can be restored after a restart, see [`ChainSelectorBuilder::restore_onto_tip`].
+    ///
+    /// The chains of the peers are thus restored as of the last change of tip: those growing
+    /// without taking over since then are sent again by the peers anyway.
+    ///
+    /// [`ChainSelectorBuilder::restore_onto_tip`]: chain_selection::ChainSelectorBuilder::restore_onto_tip
+    pub fn with_store(mut self, store: Arc<Mutex<dyn ChainStore<Header>>>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    async fn persist(&self) -> Result<(), ConsensusError> {
+        if let Some(store) = &self.store {
+            let state = self.chain_selector.lock().await.state();
+            store
+                .lock()
+                .await
+                .store_chain_selector_state(&state)
+                .map_err(ConsensusError::StoreChainSelectorFailed)?;
+        }
+        Ok(())
+    }
+
+    /// Keep the scores of peers in the given reputation, e.g. to share it with other stages.
+    pub fn with_reputation(mut self, reputation: Arc<Mutex<Reputation>>) -> Self {
+        self.reputation = reputation;
+        self
+    }
+
+    /// The scores of the peers, lowered when they roll back deeper than allowed.
+    pub fn reputation(&self) -> Arc<Mutex<Reputation>> {
+        self.reputation.clone()
     }
 
     fn forward_block<H: IsHeader>(&self, peer: Peer, header: H, span: Span) -> ValidateHeaderEvent {
@@ -126,6 +169,29 @@ impl SelectChain {
                 tip: _,
             }) => Ok(self.switch_to_fork(peer, rollback_point, fork, span)),
             RollbackChainSelection::NoChange => Ok(vec![]),
+            RollbackChainSelection::RollbackTooDeep {
+                depth,
+                max_rollback,
+            } => {
+                warn!(
+                    target: EVENT_TARGET,
+                    peer = %peer.name,
+                    point = ?rollback_point,
+                    depth,
+                    max_rollback,
+                    "rollback_too_deep"
+                );
+                self.reputation
+                    .lock()
+                    .await
+                    .record(&peer, Offence::DeepRollback);
+                Err(ConsensusError::RollbackTooDeep {
+                    peer,
+                    point: rollback_point,
+                    depth,
+                    max_rollback,
+                })
+            }
         }
     }
 
@@ -133,7 +199,7 @@ impl SelectChain {
         &mut self,
         chain_sync: DecodedChainSyncEvent,
     ) -> Result<Vec<ValidateHeaderEvent>, ConsensusError> {
-        match chain_sync {
+        let events = match chain_sync {
             DecodedChainSyncEvent::RollForward { peer, header, .. } => {
                 self.select_chain(peer, header).await
             }
@@ -142,6 +208,11 @@ impl SelectChain {
                 rollback_point,
                 ..
             } => self.select_rollback(peer, rollback_point).await,
+        }?;
+        // the tip is left unchanged when there is nothing to pass on
+        if !events.is_empty() {
+            self.persist().await?;
         }
+        Ok(events)
     }
 }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::consensus::chain_selection::ChainSelectorState;
 use amaru_kernel::{protocol_parameters::GlobalParameters, EraHistory, Nonce, Point, RawBlock};
 use amaru_ouroboros::{praos::nonce, Nonces};
 use amaru_ouroboros_traits::{IsHeader, Praos};
@@ -47,6 +48,13 @@ where
     fn load_header(&self, hash: &Hash<32>) -> Option<H>;
     fn store_header(&mut self, hash: &Hash<32>, header: &H) -> Result<(), StoreError>;
 
+    /// Remove the header with the given hash, along with its block and nonces, if any.
+    fn remove_header(&mut self, hash: &Hash<32>) -> Result<(), StoreError>;
+
+    /// The hashes of the headers in the store at or above the given block height, in no
+    /// particular order.
+    fn header_hashes_from(&self, height: u64) -> Result<Vec<Hash<32>>, StoreError>;
+
     fn load_block(&self, hash: &Hash<32>) -> Result<RawBlock, StoreError>;
     fn store_block(&mut self, hash: &Hash<32>, block: &RawBlock) -> Result<(), StoreError>;
 
@@ -54,6 +62,10 @@ where
     fn put_nonces(&mut self, header: &Hash<32>, nonces: &Nonces) -> Result<(), StoreError>;
 
     fn era_history(&self) -> &EraHistory;
+
+    /// The state of the chain selector persisted last, if any, see [`ChainSelectorState`].
+    fn load_chain_selector_state(&self) -> Option<ChainSelectorState>;
+    fn store_chain_selector_state(&mut self, state: &ChainSelectorState) -> Result<(), StoreError>;
 }
 
 impl<H: IsHeader> ChainStore<H> for Box<dyn ChainStore<H>> {
@@ -65,6 +77,14 @@ impl<H: IsHeader> ChainStore<H> for Box<dyn ChainStore<H>> {
         self.as_mut().store_header(hash, header)
     }
 
+    fn remove_header(&mut self, hash: &Hash<32>) -> Result<(), StoreError> {
+        self.as_mut().remove_header(hash)
+    }
+
+    fn header_hashes_from(&self, height: u64) -> Result<Vec<Hash<32>>, StoreError> {
+        self.as_ref().header_hashes_from(height)
+    }
+
     fn get_nonces(&self, header: &Hash<32>) -> Option<Nonces> {
         self.as_ref().get_nonces(header)
     }
@@ -84,6 +104,14 @@ impl<H: IsHeader> ChainStore<H> for Box<dyn ChainStore<H>> {
     fn store_block(&mut self, hash: &Hash<32>, block: &RawBlock) -> Result<(), StoreError> {
         self.as_mut().store_block(hash, block)
     }
+
+    fn load_chain_selector_state(&self) -> Option<ChainSelectorState> {
+        self.as_ref().load_chain_selector_state()
+    }
+
+    fn store_chain_selector_state(&mut self, state: &ChainSelectorState) -> Result<(), StoreError> {
+        self.as_mut().store_chain_selector_state(state)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -104,6 +132,37 @@ pub enum NoncesError {
     EraHistoryError(#[from] TimeHorizonError),
 }
 
+/// The nonces of the parent of the given header, as stored or, failing that, evolved from those
+/// of its nearest ancestor with stored nonces through each of the stored headers in between.
+///
+/// Nonces are thus computed from the chain itself, and only need to be provided for its anchor.
+fn parent_nonces<H: IsHeader>(
+    store: &mut dyn ChainStore<H>,
+    header: &H,
+    global_parameters: &GlobalParameters,
+) -> Result<Nonces, NoncesError> {
+    // The ancestors without nonces, from the parent backwards.
+    let mut ancestors = vec![];
+    let mut child = header.hash();
+    let mut next = header.parent().unwrap_or((&Point::Origin).into());
+    let mut nonces = loop {
+        if let Some(nonces) = store.get_nonces(&next) {
+            break nonces;
+        }
+        let ancestor = store.load_header(&next).ok_or(NoncesError::UnknownParent {
+            header: child,
+            parent: next,
+        })?;
+        child = next;
+        next = ancestor.parent().unwrap_or((&Point::Origin).into());
+        ancestors.push(ancestor);
+    };
+    for ancestor in ancestors.iter().rev() {
+        nonces = store.evolve_nonce(ancestor, global_parameters)?;
+    }
+    Ok(nonces)
+}
+
 impl<H: IsHeader> Praos<H> for dyn ChainStore<H> {
     type Error = NoncesError;
 
@@ -122,12 +181,7 @@ impl<H: IsHeader> Praos<H> for dyn ChainStore<H> {
 
         let parent_hash = header.parent().unwrap_or((&Point::Origin).into());
 
-        let parent = self
-            .get_nonces(&parent_hash)
-            .ok_or_else(|| NoncesError::UnknownParent {
-                header: header.hash(),
-                parent: parent_hash,
-            })?;
+        let parent = parent_nonces(self, header, global_parameters)?;
 
         // Compute the new evolving nonce by combining it with the current one and the header's VRF
         // output.
@@ -190,7 +244,7 @@ impl<H: IsHeader> Praos<H> for dyn ChainStore<H> {
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
     use crate::test::include_header;
     use amaru_kernel::{from_cbor, hash, network::NetworkName, to_cbor, Header};
@@ -200,11 +254,11 @@ mod test {
     use std::{collections::BTreeMap, sync::LazyLock};
 
     // Epoch 164's last header
-    include_header!(PREPROD_HEADER_69638382, 69638382);
+    include_header!(pub(crate) PREPROD_HEADER_69638382, 69638382);
 
     // Epoch 165's before-last header
-    include_header!(PREPROD_HEADER_70070331, 70070331);
-    static PREPROD_NONCES_70070331: LazyLock<Nonces> = LazyLock::new(|| Nonces {
+    include_header!(pub(crate) PREPROD_HEADER_70070331, 70070331);
+    pub(crate) static PREPROD_NONCES_70070331: LazyLock<Nonces> = LazyLock::new(|| Nonces {
         epoch: Epoch::from(165),
         active: hash!("a7c4477e9fcfd519bf7dcba0d4ffe35a399125534bc8c60fa89ff6b50a060a7a"),
         candidate: hash!("74fe03b10c4f52dd41105a16b5f6a11015ec890a001a5253db78a779fe43f6b6",),
@@ -213,7 +267,7 @@ mod test {
     });
 
     // Epoch 165's last header
-    include_header!(PREPROD_HEADER_70070379, 70070379);
+    include_header!(pub(crate) PREPROD_HEADER_70070379, 70070379);
     static PREPROD_NONCES_70070379: LazyLock<Nonces> = LazyLock::new(|| Nonces {
         epoch: Epoch::from(165),
         active: hash!("a7c4477e9fcfd519bf7dcba0d4ffe35a399125534bc8c60fa89ff6b50a060a7a"),
@@ -223,7 +277,7 @@ mod test {
     });
 
     // Epoch 166's first header
-    include_header!(PREPROD_HEADER_70070426, 70070426);
+    include_header!(pub(crate) PREPROD_HEADER_70070426, 70070426);
     static PREPROD_NONCES_70070426: LazyLock<Nonces> = LazyLock::new(|| Nonces {
         epoch: Epoch::from(166),
         active: hash!("b2853ec951e7ed91b674a47c8276189f414e22b19d61d9da0ac7490801e4bf0d"),
@@ -242,10 +296,13 @@ mod test {
         tail: hash!("d6fe6439aed8bddc10eec22c1575bf0648e4a76125387d9e985e9a3f8342870d"),
     });
 
+    /// A chain store keeping headers and nonces in memory.
     #[derive(Default)]
-    struct FakeStore {
+    pub(crate) struct FakeStore {
         headers: BTreeMap<Hash<32>, Header>,
         nonces: BTreeMap<Hash<32>, Nonces>,
+        /// How many times a header was stored.
+        pub(crate) header_writes: usize,
     }
 
     impl ChainStore<Header> for FakeStore {
@@ -254,10 +311,19 @@ mod test {
         }
 
         fn store_header(&mut self, hash: &Hash<32>, header: &Header) -> Result<(), StoreError> {
+            self.header_writes += 1;
             self.headers.insert(*hash, header.clone());
             Ok(())
         }
 
+        fn remove_header(&mut self, _hash: &Hash<32>) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        fn header_hashes_from(&self, _height: u64) -> Result<Vec<Hash<32>>, StoreError> {
+            unimplemented!()
+        }
+
         fn get_nonces(&self, header: &Hash<32>) -> Option<Nonces> {
             self.nonces.get(header).cloned()
         }
@@ -278,6 +344,17 @@ mod test {
         fn store_block(&mut self, _hash: &Hash<32>, _block: &RawBlock) -> Result<(), StoreError> {
             unimplemented!()
         }
+
+        fn load_chain_selector_state(&self) -> Option<ChainSelectorState> {
+            unimplemented!()
+        }
+
+        fn store_chain_selector_state(
+            &mut self,
+            _state: &ChainSelectorState,
+        ) -> Result<(), StoreError> {
+            unimplemented!()
+        }
     }
 
     fn evolve_nonce(
@@ -348,6 +425,45 @@ mod test {
         )
     }
 
+    #[test]
+    fn evolve_nonce_through_ancestors_without_nonces() {
+        let mut store = Box::new(FakeStore::default()) as Box<dyn ChainStore<Header>>;
+        for header in [&*PREPROD_HEADER_69638382, &*PREPROD_HEADER_70070379] {
+            store
+                .store_header(&header.hash(), header)
+                .expect("database failure");
+        }
+        // Only the nonces of an older ancestor are known, not those of the parent.
+        store
+            .put_nonces(&PREPROD_HEADER_70070331.hash(), &PREPROD_NONCES_70070331)
+            .expect("database failure");
+
+        let nonces = store
+            .evolve_nonce(&*PREPROD_HEADER_70070426, &GlobalParameters::default())
+            .expect("evolve nonce failed");
+
+        assert_eq!(&nonces, &*PREPROD_NONCES_70070426);
+        assert_eq!(
+            store.get_nonces(&PREPROD_HEADER_70070379.hash()).as_ref(),
+            Some(&*PREPROD_NONCES_70070379)
+        );
+    }
+
+    #[test]
+    fn evolve_nonce_fails_without_anchor() {
+        let mut store = Box::new(FakeStore::default()) as Box<dyn ChainStore<Header>>;
+        store
+            .store_header(&PREPROD_HEADER_70070379.hash(), &PREPROD_HEADER_70070379)
+            .expect("database failure");
+
+        let result = store.evolve_nonce(&*PREPROD_HEADER_70070426, &GlobalParameters::default());
+
+        assert!(matches!(
+            result,
+            Err(NoncesError::UnknownParent { header, .. }) if header == PREPROD_HEADER_70070379.hash()
+        ));
+    }
+
     prop_compose! {
         fn any_nonces()(
             active in any::<[u8; 32]>(),
@@ -54,7 +54,7 @@ impl StoreBlock {
 
 #[cfg(test)]
 mod tests {
-    use crate::consensus::store::StoreError;
+    use crate::consensus::{chain_selection::ChainSelectorState, store::StoreError};
 
     use super::*;
     use amaru_kernel::{Hash, Point, RawBlock};
@@ -89,6 +89,14 @@ mod tests {
             unimplemented!()
         }
 
+        fn remove_header(&mut self, _hash: &Hash<32>) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        fn header_hashes_from(&self, _height: u64) -> Result<Vec<Hash<32>>, StoreError> {
+            unimplemented!()
+        }
+
         fn load_block(&self, _hash: &Hash<32>) -> Result<RawBlock, StoreError> {
             unimplemented!()
         }
@@ -108,6 +116,17 @@ mod tests {
         fn era_history(&self) -> &amaru_kernel::EraHistory {
             unimplemented!()
         }
+
+        fn load_chain_selector_state(&self) -> Option<ChainSelectorState> {
+            unimplemented!()
+        }
+
+        fn store_chain_selector_state(
+            &mut self,
+            _state: &ChainSelectorState,
+        ) -> Result<(), StoreError> {
+            unimplemented!()
+        }
     }
 
     #[tokio::test]
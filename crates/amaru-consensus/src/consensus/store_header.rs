@@ -12,7 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{consensus::store::ChainStore, ConsensusError};
+use crate::{
+    consensus::{seen_headers::SeenHeaders, store::ChainStore},
+    ConsensusError,
+};
 use amaru_kernel::{Header, Point};
 use amaru_ouroboros_traits::IsHeader;
 use std::sync::Arc;
@@ -22,23 +25,42 @@ use super::DecodedChainSyncEvent;
 
 pub struct StoreHeader {
     store: Arc<Mutex<dyn ChainStore<Header>>>,
+    /// The headers stored lately, not to store again those announced by several peers.
+    stored: Arc<Mutex<SeenHeaders>>,
 }
 
 impl StoreHeader {
     pub fn new(chain_store: Arc<Mutex<dyn ChainStore<Header>>>) -> Self {
-        StoreHeader { store: chain_store }
+        StoreHeader {
+            store: chain_store,
+            stored: Arc::new(Mutex::new(SeenHeaders::default())),
+        }
+    }
+
+    /// The headers stored lately, e.g. to forget those pruned from the store, see
+    /// [`PruneForks::with_stored`](super::prune::PruneForks::with_stored).
+    pub fn stored(&self) -> Arc<Mutex<SeenHeaders>> {
+        self.stored.clone()
     }
 
-    pub async fn store(&self, point: &Point, header: &Header) -> Result<(), ConsensusError> {
+    /// Store the header, unless it was stored lately.
+    pub async fn store(&mut self, point: &Point, header: &Header) -> Result<(), ConsensusError> {
+        let hash = header.hash();
+        let mut stored = self.stored.lock().await;
+        if stored.contains(&hash) {
+            return Ok(());
+        }
         self.store
             .lock()
             .await
-            .store_header(&header.hash(), header)
-            .map_err(|e| ConsensusError::StoreHeaderFailed(point.clone(), e))
+            .store_header(&hash, header)
+            .map_err(|e| ConsensusError::StoreHeaderFailed(point.clone(), e))?;
+        stored.insert(hash);
+        Ok(())
     }
 
     pub async fn handle_event(
-        &self,
+        &mut self,
         event: DecodedChainSyncEvent,
     ) -> Result<DecodedChainSyncEvent, ConsensusError> {
         match event {
@@ -54,3 +76,87 @@ impl StoreHeader {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus::{
+            chain_selection::ChainSelectorBuilder,
+            select_chain::SelectChain,
+            store::test::{
+                FakeStore, PREPROD_HEADER_70070331, PREPROD_HEADER_70070379,
+                PREPROD_NONCES_70070331,
+            },
+            validate_header::ValidateHeader,
+        },
+        peer::Peer,
+    };
+    use amaru_kernel::{protocol_parameters::GlobalParameters, Hasher};
+    use amaru_ouroboros_traits::has_stake_distribution::mock::MockLedgerState;
+    use tracing::Span;
+
+    #[tokio::test]
+    async fn headers_announced_by_several_peers_are_validated_and_stored_once() {
+        let parent = &*PREPROD_HEADER_70070331;
+        let header = &*PREPROD_HEADER_70070379;
+        let (alice, bob) = (Peer::new("alice"), Peer::new("bob"));
+
+        let mut store = FakeStore::default();
+        store
+            .put_nonces(&parent.hash(), &PREPROD_NONCES_70070331)
+            .unwrap();
+        store.store_header(&parent.hash(), parent).unwrap();
+        let store = Arc::new(Mutex::new(store));
+        let mut ledger = MockLedgerState::new(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            1,
+            1,
+        );
+        ledger.vrf_vkey_hash = Hasher::<256>::hash(&header.header_body.vrf_vkey);
+        let mut validate_header = ValidateHeader::new(Box::new(ledger), store.clone());
+        let mut store_header = StoreHeader::new(store.clone());
+        let chain_selector = ChainSelectorBuilder::new()
+            .set_tip(parent)
+            .add_peer(&alice)
+            .add_peer(&bob)
+            .build()
+            .unwrap();
+        let chain_selector = Arc::new(Mutex::new(chain_selector));
+        let mut select_chain = SelectChain::new(chain_selector.clone());
+
+        let events = [&alice, &bob]
+            .into_iter()
+            .map(|peer| DecodedChainSyncEvent::RollForward {
+                peer: peer.clone(),
+                point: header.point(),
+                header: header.clone(),
+                span: Span::current(),
+            })
+            .collect();
+        let writes_before = store.lock().await.header_writes;
+        for validated in validate_header
+            .handle_chain_sync_batch(events, &GlobalParameters::default())
+            .await
+        {
+            let stored = store_header.handle_event(validated.unwrap()).await.unwrap();
+            select_chain.handle_chain_sync(stored).await.unwrap();
+        }
+
+        let metrics = validate_header.validated_metrics();
+        assert_eq!((metrics.hits, metrics.misses), (1, 1));
+        assert_eq!(store.lock().await.header_writes - writes_before, 1);
+        let tips = chain_selector
+            .lock()
+            .await
+            .state()
+            .peers
+            .into_iter()
+            .map(|chain| (chain.peer, chain.tip))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tips,
+            vec![(alice, Some(header.hash())), (bob, Some(header.hash()))]
+        );
+    }
+}
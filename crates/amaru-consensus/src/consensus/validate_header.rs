@@ -12,13 +12,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{consensus::store::ChainStore, peer::Peer, ConsensusError};
-use amaru_kernel::{protocol_parameters::GlobalParameters, to_cbor, Hash, Header, Nonce, Point};
-use amaru_ouroboros::{praos, Nonces};
+use crate::{
+    consensus::{
+        opcert::{issuer, OpCertCounters},
+        reputation::{Offence, Reputation},
+        seen_headers::{SeenHeaders, SeenHeadersMetrics},
+        store::ChainStore,
+    },
+    peer::Peer,
+    ConsensusError,
+};
+use amaru_kernel::{
+    protocol_parameters::GlobalParameters, to_cbor, Bytes, Hash, Header, Nonce, Point,
+};
+use amaru_ouroboros::{praos, praos::header::AssertHeaderError, IsHeader, Nonces};
 use amaru_ouroboros_traits::{HasStakeDistribution, Praos};
 use pallas_math::math::FixedDecimal;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+use tokio::{sync::Mutex, task::JoinHandle};
 use tracing::{instrument, Level, Span};
 
 use super::DecodedChainSyncEvent;
@@ -38,8 +52,7 @@ pub fn header_is_valid(
     ledger: &dyn HasStakeDistribution,
     global_parameters: &GlobalParameters,
 ) -> Result<(), ConsensusError> {
-    let active_slot_coeff: FixedDecimal = FixedDecimal::from(1_u64)
-        / FixedDecimal::from(global_parameters.active_slot_coeff_inverse as u64);
+    let active_slot_coeff = active_slot_coeff(global_parameters);
 
     praos::header::assert_all(
         header,
@@ -52,12 +65,110 @@ pub fn header_is_valid(
         use rayon::prelude::*;
         assertions.into_par_iter().try_for_each(|assert| assert())
     })
-    .map_err(|e| ConsensusError::InvalidHeader(point.clone(), e))
+    .map_err(|e| invalid_header(point, e))
+}
+
+fn active_slot_coeff(global_parameters: &GlobalParameters) -> FixedDecimal {
+    FixedDecimal::from(1_u64)
+        / FixedDecimal::from(global_parameters.active_slot_coeff_inverse as u64)
+}
+
+/// The error for a header failing the given assertion, telling forged operational certificates
+/// and KES signatures apart from other failures.
+fn invalid_header(point: &Point, e: AssertHeaderError) -> ConsensusError {
+    match e {
+        AssertHeaderError::OperationalCertificate(e) => {
+            ConsensusError::InvalidOperationalCertificate(point.clone(), e)
+        }
+        AssertHeaderError::KesSignature(e) => ConsensusError::InvalidKesSignature(point.clone(), e),
+        e @ (AssertHeaderError::KnownLeaderVrf(..)
+        | AssertHeaderError::VrfProof(..)
+        | AssertHeaderError::LeaderStake(..)
+        | AssertHeaderError::TryFromSliceError(..)
+        | AssertHeaderError::UnknownPool { .. }) => ConsensusError::InvalidHeader(point.clone(), e),
+    }
+}
+
+/// The number of headers validated at once by default, see
+/// [`ValidateHeader::handle_chain_sync_batch`].
+pub const DEFAULT_MAX_CONCURRENCY: usize = 4;
+
+/// The number of slots of issuers remembered by default to detect equivocations.
+pub const DEFAULT_ISSUED_HEADERS_CAPACITY: usize = 10_000;
+
+/// The hash of the valid header each issuer issued in each slot, for the last slots, to detect
+/// equivocations.
+///
+/// Once full, the oldest slots are forgotten first: an issuer equivocating on those goes
+/// unnoticed, but they are far behind the tip by then.
+#[derive(Debug)]
+struct IssuedHeaders {
+    capacity: usize,
+    hashes: HashMap<(Bytes, u64), Hash<32>>,
+    order: VecDeque<(Bytes, u64)>,
+}
+
+impl IssuedHeaders {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            hashes: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Remember that the issuer of the header issued it in its slot, unless it issued another
+    /// one in that slot already, whose hash is returned.
+    fn insert(&mut self, header: &Header) -> Result<(), Hash<32>> {
+        let key = (
+            header.header_body.issuer_vkey.clone(),
+            header.header_body.slot,
+        );
+        let hash = header.hash();
+        match self.hashes.get(&key) {
+            Some(issued) if *issued != hash => return Err(*issued),
+            Some(_) => return Ok(()),
+            None => (),
+        }
+        self.hashes.insert(key.clone(), hash);
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A header on its way through validation, see [`ValidateHeader::handle_chain_sync_batch`].
+enum Pending {
+    /// The outcome is known without validating the header, e.g. for a rollback.
+    Settled(Result<DecodedChainSyncEvent, ConsensusError>),
+    /// The header is being validated on a blocking thread.
+    Validating {
+        peer: Peer,
+        point: Point,
+        header: Header,
+        span: Span,
+        validity: JoinHandle<Result<(), ConsensusError>>,
+    },
+    /// The same header as one being validated ahead of it, not to validate it twice.
+    Duplicate(DecodedChainSyncEvent),
 }
 
 pub struct ValidateHeader {
-    ledger: Box<dyn HasStakeDistribution>,
+    ledger: Arc<dyn HasStakeDistribution>,
     store: Arc<Mutex<dyn ChainStore<Header>>>,
+    reputation: Arc<Mutex<Reputation>>,
+    issued: IssuedHeaders,
+    /// The headers found valid lately, not to validate again those announced by several peers.
+    ///
+    /// This also spares verifying their VRF proof, the most expensive check, again: a header is
+    /// only validated anew once forgotten here, so a separate cache of verified proofs would
+    /// never hit before this one does.
+    validated: SeenHeaders,
+    max_concurrency: usize,
 }
 
 impl ValidateHeader {
@@ -65,7 +176,59 @@ impl ValidateHeader {
         ledger: Box<dyn HasStakeDistribution>,
         store: Arc<Mutex<dyn ChainStore<Header>>>,
     ) -> Self {
-        Self { ledger, store }
+        Self {
+            ledger: Arc::from(ledger),
+            store,
+            reputation: Arc::new(Mutex::new(Reputation::default())),
+            issued: IssuedHeaders::new(DEFAULT_ISSUED_HEADERS_CAPACITY),
+            validated: SeenHeaders::default(),
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+        }
+    }
+
+    /// Validate at most this many headers at once, see [`Self::handle_chain_sync_batch`].
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// Keep the scores of peers in the given reputation, e.g. to share it with other stages.
+    pub fn with_reputation(mut self, reputation: Arc<Mutex<Reputation>>) -> Self {
+        self.reputation = reputation;
+        self
+    }
+
+    /// Remember at most this many headers found valid, see [`Self::validated_metrics`].
+    pub fn with_validated_capacity(mut self, capacity: usize) -> Self {
+        self.validated = SeenHeaders::new(capacity);
+        self
+    }
+
+    /// How often headers were found valid already when announced, to tune the capacity.
+    pub fn validated_metrics(&self) -> SeenHeadersMetrics {
+        self.validated.metrics()
+    }
+
+    /// The scores of the peers, lowered when they send invalid headers, and the equivocations
+    /// of issuers.
+    pub fn reputation(&self) -> Arc<Mutex<Reputation>> {
+        self.reputation.clone()
+    }
+
+    /// Count an equivocation against the issuer of the header if it already issued a different
+    /// one for the same slot.
+    ///
+    /// The header is passed on all the same: the peer relaying it may well be honest, e.g.
+    /// following the fork of a slot battle, and chain selection settles which header wins.
+    async fn check_equivocation(&mut self, header: &Header) {
+        if self.issued.insert(header).is_err() {
+            if let Some(issuer) = issuer(header) {
+                self.reputation
+                    .lock()
+                    .await
+                    .record_equivocation(&issuer, header.header_body.slot);
+            }
+        }
     }
 
     #[instrument(
@@ -84,32 +247,229 @@ impl ValidateHeader {
         header: Header,
         global_parameters: &GlobalParameters,
     ) -> Result<DecodedChainSyncEvent, ConsensusError> {
-        let Nonces {
-            active: ref epoch_nonce,
-            ..
-        } = self
+        let event = DecodedChainSyncEvent::RollForward {
+            peer,
+            point,
+            header,
+            span: Span::current(),
+        };
+        let pending = self.prepare(event, global_parameters).await;
+        self.settle(pending, global_parameters).await
+    }
+
+    /// The epoch nonce against which to validate the header, unless its peer is banned.
+    async fn evolve_nonce(
+        &mut self,
+        peer: &Peer,
+        header: &Header,
+        global_parameters: &GlobalParameters,
+    ) -> Result<Nonce, ConsensusError> {
+        if self.reputation.lock().await.is_banned(peer) {
+            return Err(ConsensusError::BannedPeer(peer.clone()));
+        }
+
+        let Nonces { active, .. } = self
             .store
             .lock()
             .await
-            .evolve_nonce(&header, global_parameters)?;
+            .evolve_nonce(header, global_parameters)?;
 
-        header_is_valid(
-            &point,
-            &header,
-            to_cbor(&header.header_body).as_slice(),
-            epoch_nonce,
-            self.ledger.as_ref(),
-            global_parameters,
-        )?;
+        Ok(active)
+    }
+
+    /// The sequence numbers of the operational certificates of the pools as of the parent of
+    /// the header, from those of its ancestors within the security parameter.
+    async fn opcert_counters(
+        &self,
+        header: &Header,
+        global_parameters: &GlobalParameters,
+    ) -> OpCertCounters {
+        OpCertCounters::before(
+            &*self.store.lock().await,
+            header,
+            global_parameters.consensus_security_param as u64,
+        )
+    }
 
+    /// Pass on a header found valid before, e.g. when sent by another peer, unless its peer is
+    /// banned.
+    async fn pass_validated(
+        &self,
+        peer: Peer,
+        point: Point,
+        header: Header,
+        span: Span,
+    ) -> Result<DecodedChainSyncEvent, ConsensusError> {
+        if self.reputation.lock().await.is_banned(&peer) {
+            return Err(ConsensusError::BannedPeer(peer));
+        }
         Ok(DecodedChainSyncEvent::RollForward {
             peer,
             point,
             header,
-            span: Span::current(),
+            span,
+        })
+    }
+
+    /// Account for the outcome of the validation of a header, lowering the score of its peer
+    /// if it is invalid.
+    async fn conclude(
+        &mut self,
+        peer: Peer,
+        point: Point,
+        header: Header,
+        validity: Result<(), ConsensusError>,
+        span: Span,
+    ) -> Result<DecodedChainSyncEvent, ConsensusError> {
+        // the peer may have been banned while its header was validated
+        if self.reputation.lock().await.is_banned(&peer) {
+            return Err(ConsensusError::BannedPeer(peer));
+        }
+
+        if let Err(e) = validity {
+            self.reputation
+                .lock()
+                .await
+                .record(&peer, Offence::InvalidHeader);
+            return Err(e);
+        }
+
+        self.check_equivocation(&header).await;
+        self.validated.insert(header.hash());
+
+        Ok(DecodedChainSyncEvent::RollForward {
+            peer,
+            point,
+            header,
+            span,
         })
     }
 
+    /// Validate the headers of a batch of events, at most
+    /// [`max_concurrency`](Self::with_max_concurrency) at once, on blocking threads.
+    ///
+    /// Headers are validated as soon as there is room for them, in the order of the events,
+    /// and their outcomes accounted for in that order too: those of each peer are thus handled
+    /// in the order they were sent, and a peer sending headers that are slow to verify only
+    /// holds back the others once `max_concurrency` headers are waiting on it. A header
+    /// announced by several peers is only validated once.
+    ///
+    /// Returns the outcome for each event, in the order of the events.
+    pub async fn handle_chain_sync_batch(
+        &mut self,
+        events: Vec<DecodedChainSyncEvent>,
+        global_parameters: &GlobalParameters,
+    ) -> Vec<Result<DecodedChainSyncEvent, ConsensusError>> {
+        let mut results = Vec::with_capacity(events.len());
+        let mut window: VecDeque<(Option<Hash<32>>, Pending)> = VecDeque::new();
+        for event in events {
+            if window.len() >= self.max_concurrency {
+                if let Some((_, pending)) = window.pop_front() {
+                    results.push(self.settle(pending, global_parameters).await);
+                }
+            }
+            let hash = match &event {
+                DecodedChainSyncEvent::RollForward { header, .. } => Some(header.hash()),
+                DecodedChainSyncEvent::Rollback { .. } => None,
+            };
+            let pending = if hash.is_some() && window.iter().any(|(ahead, _)| *ahead == hash) {
+                Pending::Duplicate(event)
+            } else {
+                self.prepare(event, global_parameters).await
+            };
+            window.push_back((hash, pending));
+        }
+        for (_, pending) in window {
+            results.push(self.settle(pending, global_parameters).await);
+        }
+        results
+    }
+
+    /// Start validating the header of the event on a blocking thread, unless its outcome is
+    /// already known, e.g. because the header was found valid before.
+    async fn prepare(
+        &mut self,
+        event: DecodedChainSyncEvent,
+        global_parameters: &GlobalParameters,
+    ) -> Pending {
+        let (peer, point, header, span) = match event {
+            DecodedChainSyncEvent::RollForward {
+                peer,
+                point,
+                header,
+                span,
+            } => (peer, point, header, span),
+            rollback @ DecodedChainSyncEvent::Rollback { .. } => {
+                return Pending::Settled(Ok(rollback))
+            }
+        };
+        if self.validated.lookup(&header.hash()) {
+            return Pending::Settled(self.pass_validated(peer, point, header, span).await);
+        }
+        let epoch_nonce = match self.evolve_nonce(&peer, &header, global_parameters).await {
+            Ok(epoch_nonce) => epoch_nonce,
+            Err(e) => return Pending::Settled(Err(e)),
+        };
+        let opcert_counters = self.opcert_counters(&header, global_parameters).await;
+
+        let validity = {
+            let ledger = self.ledger.clone();
+            let global_parameters = global_parameters.clone();
+            let point = point.clone();
+            let header = header.clone();
+            tokio::task::spawn_blocking(move || {
+                header_is_valid(
+                    &point,
+                    &header,
+                    to_cbor(&header.header_body).as_slice(),
+                    &epoch_nonce,
+                    &opcert_counters.over(ledger.as_ref()),
+                    &global_parameters,
+                )
+            })
+        };
+
+        Pending::Validating {
+            peer,
+            point,
+            header,
+            span,
+            validity,
+        }
+    }
+
+    /// Wait for the outcome of the validation of a header, and account for it.
+    async fn settle(
+        &mut self,
+        pending: Pending,
+        global_parameters: &GlobalParameters,
+    ) -> Result<DecodedChainSyncEvent, ConsensusError> {
+        match pending {
+            Pending::Settled(result) => result,
+            Pending::Validating {
+                peer,
+                point,
+                header,
+                span,
+                validity,
+            } => {
+                let validity = validity.await.unwrap_or_else(|e| {
+                    Err(ConsensusError::ValidationAborted(
+                        point.clone(),
+                        e.to_string(),
+                    ))
+                });
+                self.conclude(peer, point, header, validity, span).await
+            }
+            Pending::Duplicate(event) => {
+                // the same header ahead of it is settled by now: if found valid, it is passed
+                // on, otherwise it is validated anew and its peer held accountable too
+                let pending = self.prepare(event, global_parameters).await;
+                Box::pin(self.settle(pending, global_parameters)).await
+            }
+        }
+    }
+
     pub async fn handle_chain_sync(
         &mut self,
         chain_sync: DecodedChainSyncEvent,
@@ -129,3 +489,325 @@ impl ValidateHeader {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::store::{
+        test::{
+            FakeStore, PREPROD_HEADER_69638382, PREPROD_HEADER_70070331, PREPROD_HEADER_70070379,
+            PREPROD_HEADER_70070426, PREPROD_NONCES_70070331,
+        },
+        NoncesError,
+    };
+    use amaru_kernel::Hasher;
+    use amaru_ouroboros::praos::header::{
+        AssertKesSignatureError, AssertOperationalCertificateError,
+    };
+    use amaru_ouroboros_traits::has_stake_distribution::mock::MockLedgerState;
+
+    /// A ledger in which the pool of the header holds all the stake.
+    fn ledger_of(header: &Header) -> MockLedgerState {
+        let mut ledger = MockLedgerState::new(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            1,
+            1,
+        );
+        ledger.vrf_vkey_hash = Hasher::<256>::hash(&header.header_body.vrf_vkey);
+        ledger
+    }
+
+    /// A store holding the nonces of the parent of the header, along with the given headers.
+    fn store_with(headers: &[(Hash<32>, &Header)]) -> Arc<Mutex<dyn ChainStore<Header>>> {
+        let mut store = FakeStore::default();
+        store
+            .put_nonces(&PREPROD_HEADER_70070331.hash(), &PREPROD_NONCES_70070331)
+            .unwrap();
+        for (hash, header) in headers {
+            store.store_header(hash, header).unwrap();
+        }
+        Arc::new(Mutex::new(store))
+    }
+
+    async fn roll_forward(
+        validate_header: &mut ValidateHeader,
+        header: &Header,
+    ) -> Result<DecodedChainSyncEvent, ConsensusError> {
+        validate_header
+            .handle_roll_forward(
+                Peer::new("alice"),
+                header.point(),
+                header.clone(),
+                &GlobalParameters::default(),
+            )
+            .await
+    }
+
+    fn sequence_number(header: &Header) -> u64 {
+        header
+            .header_body
+            .operational_cert
+            .operational_cert_sequence_number
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_headers() {
+        let header = &*PREPROD_HEADER_70070379;
+        let mut validate_header = ValidateHeader::new(Box::new(ledger_of(header)), store_with(&[]));
+
+        assert!(roll_forward(&mut validate_header, header).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_operational_certificates_older_than_those_of_the_ledger() {
+        let header = &*PREPROD_HEADER_70070379;
+        let mut ledger = ledger_of(header);
+        ledger
+            .op_certs
+            .insert(issuer(header).unwrap(), sequence_number(header) + 1);
+        let mut validate_header = ValidateHeader::new(Box::new(ledger), store_with(&[]));
+
+        assert!(matches!(
+            roll_forward(&mut validate_header, header).await,
+            Err(ConsensusError::InvalidOperationalCertificate(
+                _,
+                AssertOperationalCertificateError::SequenceNumberTooSmall { .. }
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_operational_certificates_older_than_those_of_the_chain() {
+        let header = &*PREPROD_HEADER_70070379;
+        // the pool issued the parent of the header with a newer certificate
+        let mut parent = header.clone();
+        parent
+            .header_body
+            .operational_cert
+            .operational_cert_sequence_number = sequence_number(header) + 1;
+        parent.header_body.prev_hash = None;
+        let mut validate_header = ValidateHeader::new(
+            Box::new(ledger_of(header)),
+            store_with(&[(PREPROD_HEADER_70070331.hash(), &parent)]),
+        );
+
+        assert!(matches!(
+            roll_forward(&mut validate_header, header).await,
+            Err(ConsensusError::InvalidOperationalCertificate(
+                _,
+                AssertOperationalCertificateError::SequenceNumberTooSmall { .. }
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_headers_signed_with_expired_kes_keys() {
+        let header = &*PREPROD_HEADER_70070379;
+        let mut ledger = ledger_of(header);
+        ledger.max_kes_evolutions = 0;
+        let mut validate_header = ValidateHeader::new(Box::new(ledger), store_with(&[]));
+
+        assert!(matches!(
+            roll_forward(&mut validate_header, header).await,
+            Err(ConsensusError::InvalidKesSignature(
+                _,
+                AssertKesSignatureError::OpCertKesPeriodTooOld { .. }
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn passes_on_equivocating_headers_blaming_their_issuer() {
+        let header = &*PREPROD_HEADER_70070379;
+        let mut equivocation = header.clone();
+        equivocation.header_body.block_body_size += 1;
+        let bob = Peer::new("bob");
+        let mut validate_header = ValidateHeader::new(Box::new(ledger_of(header)), store_with(&[]));
+
+        assert!(roll_forward(&mut validate_header, header).await.is_ok());
+        // only a forged KES signature would make the other header pass validation
+        let result = validate_header
+            .conclude(
+                bob.clone(),
+                equivocation.point(),
+                equivocation,
+                Ok(()),
+                Span::current(),
+            )
+            .await;
+
+        assert!(result.is_ok());
+        let reputation = validate_header.reputation.lock().await;
+        assert_eq!(reputation.score(&bob), 0);
+        assert_eq!(reputation.equivocations(&issuer(header).unwrap()), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_headers_from_banned_peers() {
+        let header = &*PREPROD_HEADER_70070379;
+        let mut validate_header = ValidateHeader::new(Box::new(ledger_of(header)), store_with(&[]));
+        for _ in 0..6 {
+            validate_header
+                .reputation
+                .lock()
+                .await
+                .record(&Peer::new("alice"), Offence::InvalidHeader);
+        }
+
+        assert!(matches!(
+            roll_forward(&mut validate_header, header).await,
+            Err(ConsensusError::BannedPeer(peer)) if peer == Peer::new("alice")
+        ));
+    }
+
+    #[test]
+    fn forgets_the_oldest_slots_of_issuers_once_full() {
+        let header = &*PREPROD_HEADER_70070379;
+        let mut equivocation = header.clone();
+        equivocation.header_body.block_body_size += 1;
+        let mut later = header.clone();
+        later.header_body.slot += 1;
+        let mut issued = IssuedHeaders::new(1);
+
+        assert_eq!(issued.insert(header), Ok(()));
+        assert_eq!(issued.insert(header), Ok(()));
+        assert_eq!(issued.insert(&equivocation), Err(header.hash()));
+        assert_eq!(issued.insert(&later), Ok(()));
+        assert_eq!(issued.insert(&equivocation), Ok(()));
+    }
+
+    fn roll_forward_from(peer: &str, header: &Header) -> DecodedChainSyncEvent {
+        DecodedChainSyncEvent::RollForward {
+            peer: Peer::new(peer),
+            point: header.point(),
+            header: header.clone(),
+            span: Span::current(),
+        }
+    }
+
+    fn rollback_from(peer: &str, rollback_point: Point) -> DecodedChainSyncEvent {
+        DecodedChainSyncEvent::Rollback {
+            peer: Peer::new(peer),
+            rollback_point,
+            span: Span::current(),
+        }
+    }
+
+    /// The peer of each event passed on, or `None` for those rejected.
+    fn peers(results: &[Result<DecodedChainSyncEvent, ConsensusError>]) -> Vec<Option<&str>> {
+        results
+            .iter()
+            .map(|result| match result {
+                Ok(DecodedChainSyncEvent::RollForward { peer, .. })
+                | Ok(DecodedChainSyncEvent::Rollback { peer, .. }) => Some(peer.name.as_str()),
+                Err(_) => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn validates_the_headers_of_each_peer_in_order() {
+        let parent = &*PREPROD_HEADER_70070379;
+        // the nonces of the header, first of its epoch, need the last header of an epoch before
+        let header = &*PREPROD_HEADER_70070426;
+        let tail = &*PREPROD_HEADER_69638382;
+        let mut validate_header = ValidateHeader::new(
+            Box::new(ledger_of(parent)),
+            store_with(&[(tail.hash(), tail)]),
+        );
+
+        let results = validate_header
+            .handle_chain_sync_batch(
+                vec![
+                    roll_forward_from("alice", parent),
+                    roll_forward_from("alice", header),
+                ],
+                &GlobalParameters::default(),
+            )
+            .await;
+
+        assert!(results[0].is_ok());
+        assert!(!matches!(results[1], Err(ConsensusError::NoncesError(_))));
+
+        // the other way around, the header doesn't know the nonces of its parent yet
+        let mut validate_header = ValidateHeader::new(
+            Box::new(ledger_of(parent)),
+            store_with(&[(tail.hash(), tail)]),
+        );
+
+        let results = validate_header
+            .handle_chain_sync_batch(
+                vec![
+                    roll_forward_from("alice", header),
+                    roll_forward_from("alice", parent),
+                ],
+                &GlobalParameters::default(),
+            )
+            .await;
+
+        assert!(matches!(
+            results[0],
+            Err(ConsensusError::NoncesError(
+                NoncesError::UnknownParent { .. }
+            ))
+        ));
+        assert!(results[1].is_ok());
+    }
+
+    #[tokio::test]
+    async fn returns_outcomes_in_the_order_of_the_events_across_peers() {
+        let header = &*PREPROD_HEADER_70070379;
+        let mut expired = ledger_of(header);
+        expired.max_kes_evolutions = 0;
+        let mut validate_header =
+            ValidateHeader::new(Box::new(expired), store_with(&[])).with_max_concurrency(2);
+
+        let results = validate_header
+            .handle_chain_sync_batch(
+                vec![
+                    roll_forward_from("alice", header),
+                    rollback_from("bob", PREPROD_HEADER_70070331.point()),
+                    roll_forward_from("carol", header),
+                    rollback_from("dave", Point::Origin),
+                ],
+                &GlobalParameters::default(),
+            )
+            .await;
+
+        assert_eq!(peers(&results), vec![None, Some("bob"), None, Some("dave")]);
+        // both peers sending the invalid header are held accountable
+        let reputation = validate_header.reputation.lock().await;
+        assert!(reputation.score(&Peer::new("alice")) < 0);
+        assert!(reputation.score(&Peer::new("carol")) < 0);
+        assert_eq!(reputation.score(&Peer::new("bob")), 0);
+    }
+
+    #[tokio::test]
+    async fn validates_headers_announced_by_several_peers_once() {
+        let header = &*PREPROD_HEADER_70070379;
+        let mut validate_header = ValidateHeader::new(Box::new(ledger_of(header)), store_with(&[]));
+
+        let results = validate_header
+            .handle_chain_sync_batch(
+                vec![
+                    roll_forward_from("alice", header),
+                    roll_forward_from("bob", header),
+                ],
+                &GlobalParameters::default(),
+            )
+            .await;
+        assert_eq!(peers(&results), vec![Some("alice"), Some("bob")]);
+
+        // the header was found valid, it is passed on right away from now on
+        let results = validate_header
+            .handle_chain_sync_batch(
+                vec![roll_forward_from("carol", header)],
+                &GlobalParameters::default(),
+            )
+            .await;
+        assert_eq!(peers(&results), vec![Some("carol")]);
+
+        let metrics = validate_header.validated_metrics();
+        assert_eq!((metrics.hits, metrics.misses), (2, 1));
+    }
+}
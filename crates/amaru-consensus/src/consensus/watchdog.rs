@@ -0,0 +1,214 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{ChainSyncEvent, EVENT_TARGET};
+use crate::peer::Peer;
+use pure_stage::{Effects, Instant, StageRef, Void};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// How long a peer may go without a chain-sync event before it is deemed stalled, by default.
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchdogMsg {
+    /// The peer made progress, e.g. sent a header or a rollback.
+    Progress(Peer),
+    /// Check whether the peer made progress lately, scheduled by the watchdog itself.
+    Check(Peer),
+}
+
+impl WatchdogMsg {
+    /// The progress made by the peer that sent the given event.
+    pub fn progress(event: &ChainSyncEvent) -> Self {
+        match event {
+            ChainSyncEvent::RollForward { peer, .. } | ChainSyncEvent::Rollback { peer, .. } => {
+                Self::Progress(peer.clone())
+            }
+        }
+    }
+}
+
+/// A peer sent nothing for longer than the stall timeout, and should be replaced by another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerStalled {
+    pub peer: Peer,
+    /// When the peer last made progress.
+    pub since: Instant,
+}
+
+/// The state of a stage keeping track of the time since each peer last made progress, which
+/// emits a [`PeerStalled`] event downstream once a peer stays silent for longer than the
+/// timeout.
+///
+/// A peer is checked upon a timer started by its first progress, and again each time the timer
+/// fires until it stalls. A stalled peer is reported once, and watched anew as soon as it makes
+/// progress again.
+#[derive(Debug)]
+pub struct Watchdog {
+    timeout: Duration,
+    last_seen: HashMap<Peer, Instant>,
+    stalled: HashSet<Peer>,
+    downstream: StageRef<PeerStalled, Void>,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last_seen: HashMap::new(),
+            stalled: HashSet::new(),
+            downstream: StageRef::noop::<PeerStalled>(),
+        }
+    }
+
+    /// Send the [`PeerStalled`] events to the given stage, e.g. once the stage graph is wired up.
+    pub fn connect(&mut self, downstream: StageRef<PeerStalled, Void>) {
+        self.downstream = downstream;
+    }
+
+    pub fn is_stalled(&self, peer: &Peer) -> bool {
+        self.stalled.contains(peer)
+    }
+
+    pub async fn handle(mut self, msg: WatchdogMsg, eff: Effects<WatchdogMsg, Self>) -> Self {
+        match msg {
+            WatchdogMsg::Progress(peer) => {
+                let now = eff.clock().await;
+                let is_watched = self.last_seen.insert(peer.clone(), now).is_some();
+                if self.stalled.remove(&peer) {
+                    info!(target: EVENT_TARGET, peer = %peer.name, "peer_resumed");
+                } else if is_watched {
+                    // the timer of the peer is still running
+                    return self;
+                }
+                eff.schedule(WatchdogMsg::Check(peer), self.timeout).await;
+            }
+            WatchdogMsg::Check(peer) => {
+                let Some(since) = self.last_seen.get(&peer).copied() else {
+                    return self;
+                };
+                let elapsed = eff.clock().await.saturating_since(since);
+                if elapsed < self.timeout {
+                    let remaining = self.timeout.saturating_sub(elapsed);
+                    eff.schedule(WatchdogMsg::Check(peer), remaining).await;
+                } else if self.stalled.insert(peer.clone()) {
+                    warn!(
+                        target: EVENT_TARGET,
+                        peer = %peer.name,
+                        elapsed = ?elapsed,
+                        "peer_stalled"
+                    );
+                    eff.send(&self.downstream, PeerStalled { peer, since })
+                        .await;
+                }
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pure_stage::{simulation::SimulationBuilder, StageGraph};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn after(start: Instant, secs: u64) -> Instant {
+        start.checked_add(Duration::from_secs(secs)).unwrap()
+    }
+
+    #[test]
+    fn reports_peers_silent_for_longer_than_the_timeout() {
+        let mut network = SimulationBuilder::default();
+        let watchdog = network.stage(
+            "watchdog",
+            async |watchdog: Watchdog, msg, eff| Ok(watchdog.handle(msg, eff).await),
+            Watchdog::new(TIMEOUT),
+        );
+        let (output, mut rx) = network.output("output");
+        let watchdog = network.wire_up(watchdog, |state| state.connect(output.without_state()));
+        let mut running = network.run();
+        let (alice, bob) = (Peer::new("alice"), Peer::new("bob"));
+
+        let start = running.now();
+        running.enqueue_msg(
+            &watchdog,
+            [
+                WatchdogMsg::Progress(alice.clone()),
+                WatchdogMsg::Progress(bob.clone()),
+            ],
+        );
+        // bob keeps sending headers while alice falls silent
+        for secs in [6, 12, 18] {
+            running.run_until(after(start, secs));
+            running.enqueue_msg(&watchdog, [WatchdogMsg::Progress(bob.clone())]);
+        }
+        running.run_until(after(start, 25));
+
+        assert_eq!(
+            rx.drain().collect::<Vec<_>>(),
+            vec![PeerStalled {
+                peer: alice.clone(),
+                since: start
+            }]
+        );
+        let state = running.get_state(&watchdog).unwrap();
+        assert!(state.is_stalled(&alice));
+        assert!(!state.is_stalled(&bob));
+
+        running.run_until(after(start, 40));
+        assert_eq!(
+            rx.drain().map(|stalled| stalled.peer).collect::<Vec<_>>(),
+            vec![bob]
+        );
+    }
+
+    #[test]
+    fn watches_stalled_peers_anew_once_they_make_progress() {
+        let mut network = SimulationBuilder::default();
+        let watchdog = network.stage(
+            "watchdog",
+            async |watchdog: Watchdog, msg, eff| Ok(watchdog.handle(msg, eff).await),
+            Watchdog::new(TIMEOUT),
+        );
+        let (output, mut rx) = network.output("output");
+        let watchdog = network.wire_up(watchdog, |state| state.connect(output.without_state()));
+        let mut running = network.run();
+        let alice = Peer::new("alice");
+
+        let start = running.now();
+        running.enqueue_msg(&watchdog, [WatchdogMsg::Progress(alice.clone())]);
+        running.run_until(after(start, 30));
+        assert_eq!(rx.drain().count(), 1);
+
+        running.enqueue_msg(&watchdog, [WatchdogMsg::Progress(alice.clone())]);
+        running.run_until(after(start, 35));
+        assert!(!running.get_state(&watchdog).unwrap().is_stalled(&alice));
+        assert_eq!(rx.drain().count(), 0);
+
+        running.run_until(after(start, 45));
+        assert_eq!(
+            rx.drain().collect::<Vec<_>>(),
+            vec![PeerStalled {
+                peer: alice,
+                since: after(start, 30)
+            }]
+        );
+    }
+}
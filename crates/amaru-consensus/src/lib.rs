@@ -13,7 +13,9 @@
 // limitations under the License
 
 use amaru_kernel::Point;
-use amaru_ouroboros::praos::header::AssertHeaderError;
+use amaru_ouroboros::praos::header::{
+    AssertHeaderError, AssertKesSignatureError, AssertOperationalCertificateError,
+};
 use thiserror::Error;
 
 pub use amaru_ouroboros_traits::*;
@@ -35,14 +37,33 @@ pub enum ConsensusError {
     FetchBlockFailed(Point),
     #[error("Failed to validate header at {0:?}: {1}")]
     InvalidHeader(Point, AssertHeaderError),
+    #[error("Invalid operational certificate in header at {0:?}: {1}")]
+    InvalidOperationalCertificate(Point, AssertOperationalCertificateError),
+    #[error("Invalid KES period or signature of header at {0:?}: {1}")]
+    InvalidKesSignature(Point, AssertKesSignatureError),
     #[error("Failed to store header at {0:?}: {1}")]
     StoreHeaderFailed(Point, consensus::store::StoreError),
     #[error("Failed to store block body at {0:?}: {1}")]
     StoreBlockFailed(Point, consensus::store::StoreError),
+    #[error("Failed to prune abandoned forks from tip {0:?}: {1}")]
+    PruneForksFailed(Point, consensus::store::StoreError),
+    #[error("Failed to persist the state of the chain selector: {0}")]
+    StoreChainSelectorFailed(consensus::store::StoreError),
     #[error("Failed to decode header at {0:?}")]
     CannotDecodeHeader(Point),
     #[error("Unknown peer {0:?}, bailing out")]
     UnknownPeer(peer::Peer),
+    #[error("Peer {0:?} is banned")]
+    BannedPeer(peer::Peer),
+    #[error("Validation of header at {0:?} did not complete: {1}")]
+    ValidationAborted(Point, String),
+    #[error("Peer {peer:?} rolled back {depth} blocks to {point:?}, deeper than the maximum rollback of {max_rollback}")]
+    RollbackTooDeep {
+        peer: peer::Peer,
+        point: Point,
+        depth: u64,
+        max_rollback: u64,
+    },
     #[error("{0}")]
     NoncesError(#[from] consensus::store::NoncesError),
 }
@@ -50,8 +71,8 @@ pub enum ConsensusError {
 #[cfg(test)]
 pub(crate) mod test {
     macro_rules! include_header {
-        ($name:ident, $slot:expr) => {
-            static $name: std::sync::LazyLock<Header> = std::sync::LazyLock::new(|| {
+        ($vis:vis $name:ident, $slot:expr) => {
+            $vis static $name: std::sync::LazyLock<Header> = std::sync::LazyLock::new(|| {
                 let data =
                     include_bytes!(concat!("../../tests/data/headers/preprod_", $slot, ".cbor"));
                 amaru_kernel::from_cbor(data.as_slice()).expect("invalid header")
@@ -353,6 +353,10 @@ pub struct GlobalParameters {
     /// Number of slots at the end of each epoch which do NOT contribute randomness to the candidate
     /// nonce of the following epoch.
     pub randomness_stabilization_window: u64,
+
+    /// Number of slots following the intersection of two chains over which their densities are
+    /// compared under the Ouroboros Genesis rule, for forks deeper than CONSENSUS_SECURITY_PARAM.
+    pub genesis_window: u64,
 }
 
 impl Default for GlobalParameters {
@@ -383,6 +387,7 @@ impl Default for GlobalParameters {
             randomness_stabilization_window: (4
                 * consensus_security_param
                 * active_slot_coeff_inverse) as u64,
+            genesis_window: (3 * consensus_security_param * active_slot_coeff_inverse) as u64,
         }
     }
 }
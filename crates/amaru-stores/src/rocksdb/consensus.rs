@@ -13,14 +13,20 @@
 // limitations under the License.
 
 use amaru_consensus::{
-    consensus::store::{ChainStore, StoreError},
+    consensus::{
+        chain_selection::ChainSelectorState,
+        store::{ChainStore, StoreError},
+    },
     Nonces,
 };
 use amaru_kernel::{cbor, from_cbor, network::NetworkName, to_cbor, Hash, RawBlock};
 use amaru_ouroboros_traits::is_header::IsHeader;
-use rocksdb::{OptimisticTransactionDB, Options};
+use rocksdb::{Direction, IteratorMode, OptimisticTransactionDB, Options};
 use slot_arithmetic::EraHistory;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+};
 use tracing::{instrument, Level};
 
 pub struct RocksDBStore {
@@ -45,25 +51,82 @@ impl RocksDBStore {
     }
 }
 
+const HEADER_PREFIX: [u8; 5] = [0x68, 0x65, 0x61, 0x64, 0x72];
+
 const NONCES_PREFIX: [u8; 5] = [0x6e, 0x6f, 0x6e, 0x63, 0x65];
 
 const BLOCK_PREFIX: [u8; 5] = [0x62, 0x6c, 0x6f, 0x63, 0x6b];
 
+/// Followed by the block height in big-endian, then the hash of a header, indexing the headers
+/// by height.
+const HEIGHT_PREFIX: [u8; 5] = [0x68, 0x65, 0x69, 0x67, 0x68];
+
+const CHAIN_SELECTOR_KEY: [u8; 8] = [0x73, 0x65, 0x6c, 0x65, 0x63, 0x74, 0x6f, 0x72];
+
 impl<H: IsHeader + for<'d> cbor::Decode<'d, ()>> ChainStore<H> for RocksDBStore {
     fn load_header(&self, hash: &Hash<32>) -> Option<H> {
         self.db
-            .get_pinned(hash)
+            .get_pinned([&HEADER_PREFIX[..], &hash[..]].concat())
             .ok()
-            .and_then(|bytes| from_cbor(bytes?.as_ref()))
+            .flatten()
+            // headers used to be stored under their hash only
+            .or_else(|| self.db.get_pinned(hash).ok().flatten())
+            .and_then(|bytes| from_cbor(bytes.as_ref()))
     }
 
     #[instrument(level = Level::TRACE, skip_all, fields(%hash))]
     fn store_header(&mut self, hash: &Hash<32>, header: &H) -> Result<(), StoreError> {
-        self.db
-            .put(hash, to_cbor(header))
-            .map_err(|e| StoreError::WriteError {
+        let write_error = |e: rocksdb::Error| StoreError::WriteError {
+            error: e.to_string(),
+        };
+        let tx = self.db.transaction();
+        tx.put([&HEADER_PREFIX[..], &hash[..]].concat(), to_cbor(header))
+            .map_err(write_error)?;
+        tx.put(height_key(header.block_height(), hash), b"")
+            .map_err(write_error)?;
+        tx.commit().map_err(write_error)
+    }
+
+    #[instrument(level = Level::TRACE, skip_all, fields(%hash))]
+    fn remove_header(&mut self, hash: &Hash<32>) -> Result<(), StoreError> {
+        let write_error = |e: rocksdb::Error| StoreError::WriteError {
+            error: e.to_string(),
+        };
+        let header: Option<H> = self.load_header(hash);
+        let tx = self.db.transaction();
+        if let Some(header) = header {
+            tx.delete(height_key(header.block_height(), hash))
+                .map_err(write_error)?;
+        }
+        tx.delete([&HEADER_PREFIX[..], &hash[..]].concat())
+            .map_err(write_error)?;
+        tx.delete(hash).map_err(write_error)?;
+        tx.delete([&NONCES_PREFIX[..], &hash[..]].concat())
+            .map_err(write_error)?;
+        tx.delete([&BLOCK_PREFIX[..], &hash[..]].concat())
+            .map_err(write_error)?;
+        tx.commit().map_err(write_error)
+    }
+
+    /// The hashes found in the height index from the given height on, which only reads the
+    /// entries of the headers at or above it. Headers stored by earlier versions, which kept no
+    /// index, are left out.
+    fn header_hashes_from(&self, height: u64) -> Result<Vec<Hash<32>>, StoreError> {
+        let mut hashes = Vec::new();
+        let from = [&HEIGHT_PREFIX[..], &height.to_be_bytes()[..]].concat();
+        let entries = self
+            .db
+            .iterator(IteratorMode::From(&from, Direction::Forward));
+        for entry in entries {
+            let (key, _) = entry.map_err(|e| StoreError::ReadError {
                 error: e.to_string(),
-            })
+            })?;
+            match key.strip_prefix(&HEIGHT_PREFIX[..]) {
+                Some(key) if key.len() == 8 + 32 => hashes.push(Hash::from(&key[8..])),
+                _ => break,
+            }
+        }
+        Ok(hashes)
     }
 
     fn get_nonces(&self, header: &Hash<32>) -> Option<Nonces> {
@@ -104,10 +167,39 @@ impl<H: IsHeader + for<'d> cbor::Decode<'d, ()>> ChainStore<H> for RocksDBStore
                 error: e.to_string(),
             })
     }
+
+    fn load_chain_selector_state(&self) -> Option<ChainSelectorState> {
+        self.db
+            .get_pinned(CHAIN_SELECTOR_KEY)
+            .ok()
+            .flatten()
+            .as_deref()
+            .and_then(from_cbor)
+    }
+
+    fn store_chain_selector_state(&mut self, state: &ChainSelectorState) -> Result<(), StoreError> {
+        self.db
+            .put(CHAIN_SELECTOR_KEY, to_cbor(state))
+            .map_err(|e| StoreError::WriteError {
+                error: e.to_string(),
+            })
+    }
 }
 
+/// The key of a header in the height index of the store.
+fn height_key(height: u64, hash: &Hash<32>) -> Vec<u8> {
+    [&HEIGHT_PREFIX[..], &height.to_be_bytes()[..], &hash[..]].concat()
+}
+
+/// A chain store keeping everything in memory, for tests and simulations which should not
+/// leave anything behind them.
 pub struct InMemConsensusStore {
     nonces: HashMap<Hash<32>, Nonces>,
+    headers: HashMap<Hash<32>, Vec<u8>>,
+    /// The height and hash of each header, ordered by height.
+    heights: BTreeSet<(u64, Hash<32>)>,
+    blocks: HashMap<Hash<32>, RawBlock>,
+    chain_selector_state: Option<ChainSelectorState>,
 }
 
 impl Default for InMemConsensusStore {
@@ -120,17 +212,42 @@ impl InMemConsensusStore {
     pub fn new() -> InMemConsensusStore {
         InMemConsensusStore {
             nonces: HashMap::new(),
+            headers: HashMap::new(),
+            heights: BTreeSet::new(),
+            blocks: HashMap::new(),
+            chain_selector_state: None,
         }
     }
 }
 
-impl<H: IsHeader> ChainStore<H> for InMemConsensusStore {
-    fn load_header(&self, _hash: &Hash<32>) -> Option<H> {
-        unimplemented!()
+impl<H: IsHeader + for<'d> cbor::Decode<'d, ()>> ChainStore<H> for InMemConsensusStore {
+    fn load_header(&self, hash: &Hash<32>) -> Option<H> {
+        self.headers.get(hash).and_then(|bytes| from_cbor(bytes))
+    }
+
+    fn store_header(&mut self, hash: &Hash<32>, header: &H) -> Result<(), StoreError> {
+        self.headers.insert(*hash, to_cbor(header));
+        self.heights.insert((header.block_height(), *hash));
+        Ok(())
+    }
+
+    fn remove_header(&mut self, hash: &Hash<32>) -> Result<(), StoreError> {
+        let header: Option<H> = self.load_header(hash);
+        if let Some(header) = header {
+            self.heights.remove(&(header.block_height(), *hash));
+        }
+        self.headers.remove(hash);
+        self.nonces.remove(hash);
+        self.blocks.remove(hash);
+        Ok(())
     }
 
-    fn store_header(&mut self, _hash: &Hash<32>, _header: &H) -> Result<(), StoreError> {
-        unimplemented!()
+    fn header_hashes_from(&self, height: u64) -> Result<Vec<Hash<32>>, StoreError> {
+        Ok(self
+            .heights
+            .range((height, Hash::new([0; 32]))..)
+            .map(|(_, hash)| *hash)
+            .collect())
     }
 
     fn get_nonces(&self, header: &Hash<32>) -> Option<Nonces> {
@@ -146,12 +263,25 @@ impl<H: IsHeader> ChainStore<H> for InMemConsensusStore {
         NetworkName::Testnet(42).into()
     }
 
-    fn load_block(&self, _hash: &Hash<32>) -> Result<RawBlock, StoreError> {
-        unimplemented!()
+    fn load_block(&self, hash: &Hash<32>) -> Result<RawBlock, StoreError> {
+        self.blocks
+            .get(hash)
+            .cloned()
+            .ok_or(StoreError::NotFound { hash: *hash })
+    }
+
+    fn store_block(&mut self, hash: &Hash<32>, block: &RawBlock) -> Result<(), StoreError> {
+        self.blocks.insert(*hash, block.clone());
+        Ok(())
     }
 
-    fn store_block(&mut self, _hash: &Hash<32>, _block: &RawBlock) -> Result<(), StoreError> {
-        unimplemented!()
+    fn load_chain_selector_state(&self) -> Option<ChainSelectorState> {
+        self.chain_selector_state.clone()
+    }
+
+    fn store_chain_selector_state(&mut self, state: &ChainSelectorState) -> Result<(), StoreError> {
+        self.chain_selector_state = Some(state.clone());
+        Ok(())
     }
 }
 
@@ -208,6 +338,142 @@ mod test {
         assert_eq!(block, block2);
     }
 
+    #[test]
+    fn rocksdb_chain_store_can_get_chain_selector_state_it_puts() {
+        let mut store = initialise_test_store();
+
+        let state = ChainSelectorState {
+            tip: Some(random_bytes(32).as_slice().into()),
+            peers: vec![],
+        };
+
+        assert_eq!(
+            None,
+            <RocksDBStore as ChainStore<FakeHeader>>::load_chain_selector_state(&store)
+        );
+        <RocksDBStore as ChainStore<FakeHeader>>::store_chain_selector_state(&mut store, &state)
+            .unwrap();
+        assert_eq!(
+            Some(state),
+            <RocksDBStore as ChainStore<FakeHeader>>::load_chain_selector_state(&store)
+        );
+    }
+
+    fn fake_header(block_number: u64, parent: Option<Hash<32>>) -> FakeHeader {
+        FakeHeader {
+            block_number,
+            slot: block_number,
+            parent,
+            body_hash: random_bytes(32).as_slice().into(),
+        }
+    }
+
+    fn nonces() -> Nonces {
+        Nonces {
+            active: random_bytes(32).as_slice().into(),
+            evolving: random_bytes(32).as_slice().into(),
+            candidate: random_bytes(32).as_slice().into(),
+            tail: random_bytes(32).as_slice().into(),
+            epoch: 1.into(),
+        }
+    }
+
+    #[test]
+    fn rocksdb_chain_store_lists_the_hashes_of_headers_only() {
+        let mut store = initialise_test_store();
+
+        let header = fake_header(1, None);
+        let child = fake_header(2, Some(header.hash()));
+        store.store_header(&header.hash(), &header).unwrap();
+        store.store_header(&child.hash(), &child).unwrap();
+        <RocksDBStore as ChainStore<FakeHeader>>::store_block(
+            &mut store,
+            &header.hash(),
+            &vec![1; 64],
+        )
+        .unwrap();
+        <RocksDBStore as ChainStore<FakeHeader>>::put_nonces(&mut store, &header.hash(), &nonces())
+            .unwrap();
+        <RocksDBStore as ChainStore<FakeHeader>>::store_chain_selector_state(
+            &mut store,
+            &ChainSelectorState {
+                tip: Some(child.hash()),
+                peers: vec![],
+            },
+        )
+        .unwrap();
+
+        let mut hashes =
+            <RocksDBStore as ChainStore<FakeHeader>>::header_hashes_from(&store, 0).unwrap();
+        let mut expected = vec![header.hash(), child.hash()];
+        hashes.sort();
+        expected.sort();
+        assert_eq!(expected, hashes);
+        assert_eq!(
+            vec![child.hash()],
+            <RocksDBStore as ChainStore<FakeHeader>>::header_hashes_from(&store, 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn rocksdb_chain_store_removes_header_along_with_its_block_and_nonces() {
+        let mut store = initialise_test_store();
+
+        let header = fake_header(1, None);
+        let other = fake_header(1, None);
+        let hash = header.hash();
+        store.store_header(&hash, &header).unwrap();
+        store.store_header(&other.hash(), &other).unwrap();
+        <RocksDBStore as ChainStore<FakeHeader>>::store_block(&mut store, &hash, &vec![1; 64])
+            .unwrap();
+        <RocksDBStore as ChainStore<FakeHeader>>::put_nonces(&mut store, &hash, &nonces()).unwrap();
+
+        <RocksDBStore as ChainStore<FakeHeader>>::remove_header(&mut store, &hash).unwrap();
+
+        let removed: Option<FakeHeader> = store.load_header(&hash);
+        assert_eq!(None, removed);
+        assert_eq!(
+            Err(StoreError::NotFound { hash }),
+            <RocksDBStore as ChainStore<FakeHeader>>::load_block(&store, &hash)
+        );
+        assert_eq!(
+            None,
+            <RocksDBStore as ChainStore<FakeHeader>>::get_nonces(&store, &hash)
+        );
+        assert_eq!(
+            vec![other.hash()],
+            <RocksDBStore as ChainStore<FakeHeader>>::header_hashes_from(&store, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn in_memory_chain_store_can_get_header_and_block_it_puts() {
+        let mut store = InMemConsensusStore::new();
+
+        let header = FakeHeader {
+            block_number: 1,
+            slot: 0,
+            parent: None,
+            body_hash: random_bytes(32).as_slice().into(),
+        };
+        let block = vec![1; 64];
+
+        store.store_header(&header.hash(), &header).unwrap();
+        <InMemConsensusStore as ChainStore<FakeHeader>>::store_block(
+            &mut store,
+            &header.hash(),
+            &block,
+        )
+        .unwrap();
+
+        let header2: FakeHeader = store.load_header(&header.hash()).unwrap();
+        assert_eq!(header, header2);
+        assert_eq!(
+            Ok(block),
+            <InMemConsensusStore as ChainStore<FakeHeader>>::load_block(&store, &header.hash())
+        );
+    }
+
     #[test]
     fn rocksdb_chain_store_returns_not_found_for_nonexistent_block() {
         let store = initialise_test_store();
@@ -58,6 +58,12 @@ pub struct Args {
     /// The maximum number of downstream peers to connect to.
     #[arg(long, value_name = "MAX_DOWNSTREAM_PEERS", default_value_t = 10)]
     max_downstream_peers: usize,
+
+    /// Record the chain sync events received from upstream peers to this file.
+    ///
+    /// The capture can then be replayed through the simulator, using its `--replay` option.
+    #[arg(long, value_name = "FILE")]
+    capture_file: Option<PathBuf>,
 }
 
 pub async fn run(
@@ -117,5 +123,6 @@ fn parse_args(args: Args) -> Result<Config, Box<dyn std::error::Error>> {
         network_magic: args.network.to_network_magic(),
         listen_address: args.listen_address,
         max_downstream_peers: args.max_downstream_peers,
+        capture_file: args.capture_file,
     })
 }
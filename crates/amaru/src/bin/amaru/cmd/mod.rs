@@ -18,6 +18,7 @@ pub(crate) mod daemon;
 pub(crate) mod import_headers;
 pub(crate) mod import_ledger_state;
 pub(crate) mod import_nonces;
+pub(crate) mod prune_forks;
 
 /// Default path to the on-disk ledger storage.
 pub(crate) const DEFAULT_LEDGER_DB_DIR: &str = "./ledger.db";
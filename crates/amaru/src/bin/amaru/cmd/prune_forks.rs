@@ -0,0 +1,67 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use amaru_consensus::consensus::{prune::prune_abandoned_forks, store::ChainStore};
+use amaru_kernel::{network::NetworkName, protocol_parameters::GlobalParameters, Header, Point};
+use amaru_stores::rocksdb::consensus::RocksDBStore;
+use clap::Parser;
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Path of the consensus on-disk storage.
+    #[arg(long, value_name = "DIR", default_value = super::DEFAULT_CHAIN_DB_DIR)]
+    chain_dir: PathBuf,
+
+    /// Tip of the chain to keep, forks branching off it deeper than the security parameter are
+    /// removed.
+    #[arg(long, value_name = "POINT", value_parser = super::parse_point)]
+    tip: Point,
+
+    /// Maximum number of blocks the chain can be rolled back, defaults to the security
+    /// parameter of the network.
+    #[arg(long, value_name = "BLOCKS")]
+    max_rollback: Option<u64>,
+
+    /// Network the chain storage belongs to
+    ///
+    /// Should be one of 'mainnet', 'preprod', 'preview' or 'testnet:<magic>' where
+    /// `magic` is a 32-bits unsigned value denoting a particular testnet.
+    #[arg(
+        long,
+        value_name = "NETWORK",
+        default_value_t = NetworkName::Preprod,
+    )]
+    network: NetworkName,
+}
+
+pub async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let era_history = args.network.into();
+    let mut db =
+        Box::new(RocksDBStore::new(&args.chain_dir, era_history)?) as Box<dyn ChainStore<Header>>;
+
+    let max_rollback = args
+        .max_rollback
+        .unwrap_or(GlobalParameters::default().consensus_security_param as u64);
+
+    info!(point.slot = %args.tip.slot_or_default(), max_rollback, "pruning abandoned forks");
+
+    // walk the whole chain, no pruning happened before as far as we know
+    let pruned = prune_abandoned_forks(&mut db, &args.tip, max_rollback, 0)?;
+
+    info!(pruned = pruned.len(), "pruned abandoned forks");
+
+    Ok(())
+}
@@ -34,7 +34,13 @@ enum Command {
     ImportHeaders(cmd::import_headers::Args),
 
     /// Import VRF nonces intermediate states
+    ///
+    /// Only needed at the point the node starts from: the nonces of the headers that follow are
+    /// computed from the chain.
     ImportNonces(cmd::import_nonces::Args),
+
+    /// Remove the headers of forks abandoned more than k blocks ago from the chain storage.
+    PruneForks(cmd::prune_forks::Args),
 }
 
 #[derive(Debug, Parser)]
@@ -75,6 +81,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Command::ImportLedgerState(args) => cmd::import_ledger_state::run(args).await,
         Command::ImportHeaders(args) => cmd::import_headers::run(args).await,
         Command::ImportNonces(args) => cmd::import_nonces::run(args).await,
+        Command::PruneForks(args) => cmd::prune_forks::run(args).await,
     };
 
     // TODO: we might also want to integrate this into a graceful shutdown system, and into a panic hook
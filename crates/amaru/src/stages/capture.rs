@@ -0,0 +1,166 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording of the chain sync traffic received from upstream peers.
+//!
+//! A capture is a file with one JSON-encoded [`CapturedEvent`] per line, in the order the
+//! events were received, which the simulator can replay.
+
+use amaru_consensus::consensus::ChainSyncEvent;
+use amaru_kernel::Point;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+/// A chain sync event, stripped from everything that only makes sense in the running node.
+///
+/// Hashes and headers are hex-encoded. A rollback to the origin has slot 0 and an empty hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CapturedEvent {
+    RollForward {
+        peer: String,
+        slot: u64,
+        hash: String,
+        header: String,
+    },
+    Rollback {
+        peer: String,
+        slot: u64,
+        hash: String,
+    },
+}
+
+fn split_point(point: &Point) -> (u64, String) {
+    match point {
+        Point::Origin => (0, String::new()),
+        Point::Specific(slot, hash) => (*slot, hex::encode(hash)),
+    }
+}
+
+impl From<&ChainSyncEvent> for CapturedEvent {
+    fn from(event: &ChainSyncEvent) -> Self {
+        match event {
+            ChainSyncEvent::RollForward {
+                peer,
+                point,
+                raw_header,
+                ..
+            } => {
+                let (slot, hash) = split_point(point);
+                CapturedEvent::RollForward {
+                    peer: peer.name.clone(),
+                    slot,
+                    hash,
+                    header: hex::encode(raw_header),
+                }
+            }
+            ChainSyncEvent::Rollback {
+                peer,
+                rollback_point,
+                ..
+            } => {
+                let (slot, hash) = split_point(rollback_point);
+                CapturedEvent::Rollback {
+                    peer: peer.name.clone(),
+                    slot,
+                    hash,
+                }
+            }
+        }
+    }
+}
+
+/// Appends the events received from all upstream peers to a capture file.
+pub struct Recorder {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl Recorder {
+    /// Start a new capture in the given file, replacing any previous one.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    pub fn record(&self, event: &ChainSyncEvent) -> io::Result<()> {
+        let line = serde_json::to_string(&CapturedEvent::from(event))?;
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| io::Error::other("capture writer poisoned"))?;
+        writeln!(writer, "{}", line)?;
+        // events are rare enough, and a capture is most useful when the node crashed
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CapturedEvent, Recorder};
+    use amaru_consensus::{consensus::ChainSyncEvent, peer::Peer};
+    use amaru_kernel::Point;
+    use tracing::Span;
+
+    #[test]
+    fn recorded_events_can_be_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.jsonl");
+        let recorder = Recorder::create(&path).unwrap();
+        let peer = Peer::new("upstream");
+
+        recorder
+            .record(&ChainSyncEvent::RollForward {
+                peer: peer.clone(),
+                point: Point::Specific(42, vec![0xab; 32]),
+                raw_header: vec![1, 2, 3],
+                span: Span::none(),
+            })
+            .unwrap();
+        recorder
+            .record(&ChainSyncEvent::Rollback {
+                peer,
+                rollback_point: Point::Origin,
+                span: Span::none(),
+            })
+            .unwrap();
+
+        let events: Vec<CapturedEvent> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                CapturedEvent::RollForward {
+                    peer: "upstream".to_string(),
+                    slot: 42,
+                    hash: "ab".repeat(32),
+                    header: "010203".to_string(),
+                },
+                CapturedEvent::Rollback {
+                    peer: "upstream".to_string(),
+                    slot: 0,
+                    hash: String::new(),
+                },
+            ]
+        );
+    }
+}
@@ -14,95 +14,94 @@
 
 use std::collections::HashMap;
 
-use amaru_consensus::{consensus::ValidateHeaderEvent, peer::Peer, ConsensusError};
-use amaru_kernel::{block::ValidateBlockEvent, Point};
+use amaru_consensus::{
+    consensus::{
+        fetch_block::{BlockFetcher, FetchBlock, MAX_FETCHES_IN_FLIGHT},
+        reputation::Reputation,
+        ValidateHeaderEvent,
+    },
+    peer::Peer,
+    ConsensusError,
+};
+use amaru_kernel::{block::ValidateBlockEvent, Point, RawBlock};
 use gasket::framework::*;
-use tracing::{instrument, Span};
-use tracing_opentelemetry::OpenTelemetrySpanExt;
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Mutex, time::timeout};
+use tracing::instrument;
 
 use crate::stages::PeerSession;
 
 pub type UpstreamPort = gasket::messaging::InputPort<ValidateHeaderEvent>;
 pub type DownstreamPort = gasket::messaging::OutputPort<ValidateBlockEvent>;
 
+/// The events whose blocks are fetched together, see [`FetchBlock::handle_events`].
+pub type Batch = Vec<ValidateHeaderEvent>;
+
+/// Fetches blocks with the block-fetch clients of the upstream peers.
+pub struct PeerClients {
+    sessions: HashMap<Peer, PeerSession>,
+}
+
+impl BlockFetcher for PeerClients {
+    async fn fetch_block(&self, peer: &Peer, point: &Point) -> Result<RawBlock, ConsensusError> {
+        let peer_session = self
+            .sessions
+            .get(peer)
+            .ok_or_else(|| ConsensusError::UnknownPeer(peer.clone()))?;
+        let mut session = peer_session.peer_client.lock().await;
+        let client = (*session).blockfetch();
+        let new_point: pallas_network::miniprotocols::Point = match point.clone() {
+            Point::Origin => pallas_network::miniprotocols::Point::Origin,
+            Point::Specific(slot, hash) => {
+                pallas_network::miniprotocols::Point::Specific(slot, hash)
+            }
+        };
+        client
+            .fetch_single(new_point)
+            .await
+            .map_err(|_| ConsensusError::FetchBlockFailed(point.clone()))
+    }
+}
+
 #[derive(Stage)]
-#[stage(
-    name = "consensus.fetch",
-    unit = "ValidateHeaderEvent",
-    worker = "Worker"
-)]
+#[stage(name = "consensus.fetch", unit = "Batch", worker = "Worker")]
 pub struct BlockFetchStage {
-    pub peer_sessions: HashMap<Peer, PeerSession>,
+    pub fetch_block: FetchBlock<PeerClients>,
     pub upstream: UpstreamPort,
     pub downstream: DownstreamPort,
 }
 
 impl BlockFetchStage {
     pub fn new(sessions: &[PeerSession]) -> Self {
-        let peer_sessions = sessions
+        let peers = sessions.iter().map(|p| p.peer.clone()).collect::<Vec<_>>();
+        let sessions = sessions
             .iter()
             .map(|p| (p.peer.clone(), p.clone()))
             .collect::<HashMap<_, _>>();
         Self {
-            peer_sessions,
+            fetch_block: FetchBlock::new(PeerClients { sessions }, &peers),
             upstream: Default::default(),
             downstream: Default::default(),
         }
     }
 
+    /// Ask demoted peers last and banned ones not at all, see [`FetchBlock::with_reputation`].
+    pub fn with_reputation(mut self, reputation: Arc<Mutex<Reputation>>) -> Self {
+        self.fetch_block = self.fetch_block.with_reputation(reputation);
+        self
+    }
+
     #[instrument(level = tracing::Level::TRACE, skip_all)]
-    async fn handle_event(&mut self, event: ValidateHeaderEvent) -> Result<(), WorkerError> {
-        match event {
-            ValidateHeaderEvent::Validated { peer, point, span } => {
-                Span::current().set_parent(span.context());
-                let block = self.fetch_block(&peer, &point).await.or_panic()?;
-                self.downstream
-                    .send(ValidateBlockEvent::Validated { point, block, span }.into())
-                    .await
-                    .or_panic()?;
-            }
-            ValidateHeaderEvent::Rollback {
-                rollback_point,
-                span,
-                ..
-            } => {
-                self.downstream
-                    .send(
-                        ValidateBlockEvent::Rollback {
-                            rollback_point,
-                            span,
-                        }
-                        .into(),
-                    )
-                    .await
-                    .or_panic()?;
-            }
+    async fn handle_batch(&mut self, batch: Batch) -> Result<(), WorkerError> {
+        for event in self.fetch_block.handle_events(batch).await {
+            self.downstream
+                .send(ValidateBlockEvent::from(event).into())
+                .await
+                .or_panic()?;
         }
 
         Ok(())
     }
-
-    async fn fetch_block(&self, peer: &Peer, point: &Point) -> Result<Vec<u8>, ConsensusError> {
-        // FIXME: should not crash if the peer is not found
-        // the block should be fetched from any other valid peer
-        // which is known to have it
-        let peer_session = self
-            .peer_sessions
-            .get(peer)
-            .ok_or_else(|| ConsensusError::UnknownPeer(peer.clone()))?;
-        let mut session = peer_session.peer_client.lock().await;
-        let client = (*session).blockfetch();
-        let new_point: pallas_network::miniprotocols::Point = match point.clone() {
-            Point::Origin => pallas_network::miniprotocols::Point::Origin,
-            Point::Specific(slot, hash) => {
-                pallas_network::miniprotocols::Point::Specific(slot, hash)
-            }
-        };
-        client
-            .fetch_single(new_point)
-            .await
-            .map_err(|_| ConsensusError::FetchBlockFailed(point.clone()))
-    }
 }
 
 pub struct Worker {}
@@ -116,17 +115,26 @@ impl gasket::framework::Worker<BlockFetchStage> for Worker {
     async fn schedule(
         &mut self,
         stage: &mut BlockFetchStage,
-    ) -> Result<WorkSchedule<ValidateHeaderEvent>, WorkerError> {
+    ) -> Result<WorkSchedule<Batch>, WorkerError> {
         let unit = stage.upstream.recv().await.or_panic()?;
+        let mut batch = vec![unit.payload];
+
+        // along with the events that arrived meanwhile, fetching their blocks at once
+        while batch.len() < MAX_FETCHES_IN_FLIGHT {
+            match timeout(Duration::ZERO, stage.upstream.recv()).await {
+                Ok(unit) => batch.push(unit.or_panic()?.payload),
+                Err(_) => break,
+            }
+        }
 
-        Ok(WorkSchedule::Unit(unit.payload))
+        Ok(WorkSchedule::Unit(batch))
     }
 
     async fn execute(
         &mut self,
-        unit: &ValidateHeaderEvent,
+        unit: &Batch,
         stage: &mut BlockFetchStage,
     ) -> Result<(), WorkerError> {
-        stage.handle_event(unit.clone()).await
+        stage.handle_batch(unit.clone()).await
     }
 }
@@ -4,7 +4,10 @@ use super::{ForwardChainStage, ForwardEvent, PrettyPoint};
 use crate::stages::PallasPoint;
 use acto::{AcTokio, AcTokioRuntime, ActoCell, ActoInput, ActoRuntime};
 use amaru_consensus::{
-    consensus::store::{ChainStore, StoreError},
+    consensus::{
+        chain_selection::ChainSelectorState,
+        store::{ChainStore, StoreError},
+    },
     IsHeader, Nonces,
 };
 use amaru_kernel::{block::BlockValidationResult, from_cbor, Hash, Header, RawBlock, EMPTY_BLOCK};
@@ -81,6 +84,14 @@ impl ChainStore<Header> for TestStore {
         Ok(())
     }
 
+    fn remove_header(&mut self, _hash: &Hash<32>) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn header_hashes_from(&self, _height: u64) -> Result<Vec<Hash<32>>, StoreError> {
+        unimplemented!()
+    }
+
     fn get_nonces(&self, _header: &Hash<32>) -> Option<Nonces> {
         unimplemented!()
     }
@@ -104,6 +115,17 @@ impl ChainStore<Header> for TestStore {
     ) -> Result<(), StoreError> {
         unimplemented!()
     }
+
+    fn load_chain_selector_state(&self) -> Option<ChainSelectorState> {
+        unimplemented!()
+    }
+
+    fn store_chain_selector_state(
+        &mut self,
+        _state: &ChainSelectorState,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
 }
 
 pub const CHAIN_47: &str = "tests/data/chain41.json";
@@ -19,3 +19,4 @@ pub mod select_chain;
 pub mod store_block;
 pub mod store_header;
 pub mod validate_header;
+pub mod watchdog;
@@ -12,8 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use amaru_consensus::consensus::{
-    select_chain::SelectChain, DecodedChainSyncEvent, ValidateHeaderEvent,
+use amaru_consensus::{
+    consensus::{select_chain::SelectChain, DecodedChainSyncEvent, ValidateHeaderEvent},
+    ConsensusError,
 };
 use gasket::framework::*;
 
@@ -42,11 +43,12 @@ impl SelectChainStage {
     }
 
     async fn handle_event(&mut self, sync_event: DecodedChainSyncEvent) -> Result<(), WorkerError> {
-        let events = self
-            .select_chain
-            .handle_chain_sync(sync_event)
-            .await
-            .or_panic()?;
+        let events = match self.select_chain.handle_chain_sync(sync_event).await {
+            Ok(events) => events,
+            // the rollback is dropped and the peer flagged, the chain is left unchanged
+            Err(ConsensusError::RollbackTooDeep { .. }) => vec![],
+            Err(e) => return Err(e).or_panic(),
+        };
 
         for event in events {
             self.downstream.send(event.into()).await.or_panic()?;
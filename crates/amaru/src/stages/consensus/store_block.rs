@@ -12,8 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use amaru_consensus::consensus::store_block::StoreBlock;
-use amaru_kernel::block::ValidateBlockEvent;
+use amaru_consensus::consensus::{prune::PruneForks, store_block::StoreBlock};
+use amaru_kernel::{block::ValidateBlockEvent, Header};
 use gasket::framework::*;
 
 pub type UpstreamPort = gasket::messaging::InputPort<ValidateBlockEvent>;
@@ -27,6 +27,7 @@ pub type DownstreamPort = gasket::messaging::OutputPort<ValidateBlockEvent>;
 )]
 pub struct StoreBlockStage {
     pub store_block: StoreBlock,
+    pub prune_forks: Option<PruneForks<Header>>,
     pub upstream: UpstreamPort,
     pub downstream: DownstreamPort,
 }
@@ -35,17 +36,33 @@ impl StoreBlockStage {
     pub fn new(store_block: StoreBlock) -> Self {
         Self {
             store_block,
+            prune_forks: None,
             upstream: Default::default(),
             downstream: Default::default(),
         }
     }
 
+    /// Prune the forks abandoned by the node from the chain store as blocks are stored.
+    pub fn with_pruning(mut self, prune_forks: PruneForks<Header>) -> Self {
+        self.prune_forks = Some(prune_forks);
+        self
+    }
+
     async fn handle_event(&mut self, event: ValidateBlockEvent) -> Result<(), WorkerError> {
         let event = self.store_block.handle_event(&event).await.map_err(|e| {
             tracing::error!(?e, "Failed to handle store block event");
             WorkerError::Recv
         })?;
 
+        if let (Some(prune_forks), ValidateBlockEvent::Validated { point, .. }) =
+            (&mut self.prune_forks, &event)
+        {
+            // pruning is best effort, the forks left behind are pruned next time
+            if let Err(e) = prune_forks.handle_new_tip(point).await {
+                tracing::error!(?e, "Failed to prune abandoned forks");
+            }
+        }
+
         self.downstream.send(event.into()).await.or_panic()?;
 
         Ok(())
@@ -12,24 +12,52 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use amaru_consensus::consensus::{validate_header::ValidateHeader, DecodedChainSyncEvent};
+use amaru_consensus::{
+    consensus::{reputation::PeerStatus, validate_header::ValidateHeader, DecodedChainSyncEvent},
+    peer::Peer,
+    ConsensusError,
+};
 use amaru_kernel::protocol_parameters::GlobalParameters;
 use gasket::framework::*;
+use std::{collections::HashSet, time::Duration};
+use tokio::time::timeout;
+use tracing::{info, warn};
 
 pub type UpstreamPort = gasket::messaging::InputPort<DecodedChainSyncEvent>;
 pub type DownstreamPort = gasket::messaging::OutputPort<DecodedChainSyncEvent>;
 
+/// The events validated together, see [`ValidateHeader::handle_chain_sync_batch`].
+pub type Batch = Vec<DecodedChainSyncEvent>;
+
+/// The maximum number of events taken from upstream at once, among those already waiting.
+pub const MAX_BATCH_SIZE: usize = 32;
+
 #[derive(Stage)]
-#[stage(
-    name = "consensus.validate_header",
-    unit = "DecodedChainSyncEvent",
-    worker = "Worker"
-)]
+#[stage(name = "consensus.validate_header", unit = "Batch", worker = "Worker")]
 pub struct ValidateHeaderStage {
     pub consensus: ValidateHeader,
     pub upstream: UpstreamPort,
     pub downstream: DownstreamPort,
     pub global_parameters: GlobalParameters,
+
+    #[metric]
+    validated_hits: gasket::metrics::Gauge,
+
+    #[metric]
+    validated_misses: gasket::metrics::Gauge,
+
+    #[metric]
+    validated_size: gasket::metrics::Gauge,
+
+    /// The peers demoted or banned so far, as reported by the reputation of the stage.
+    demoted: HashSet<Peer>,
+    banned: HashSet<Peer>,
+
+    #[metric]
+    peers_demoted: gasket::metrics::Gauge,
+
+    #[metric]
+    peers_banned: gasket::metrics::Gauge,
 }
 
 impl ValidateHeaderStage {
@@ -39,20 +67,83 @@ impl ValidateHeaderStage {
             upstream: Default::default(),
             downstream: Default::default(),
             global_parameters: global_parameters.clone(),
+            validated_hits: Default::default(),
+            validated_misses: Default::default(),
+            validated_size: Default::default(),
+            demoted: HashSet::new(),
+            banned: HashSet::new(),
+            peers_demoted: Default::default(),
+            peers_banned: Default::default(),
         }
     }
 
-    async fn handle_event(&mut self, unit: DecodedChainSyncEvent) -> Result<(), WorkerError> {
-        let event = self
+    /// How often headers were found valid already, sparing their validation.
+    fn track_validated(&self) {
+        let metrics = self.consensus.validated_metrics();
+        self.validated_hits.set(metrics.hits as i64);
+        self.validated_misses.set(metrics.misses as i64);
+        self.validated_size.set(metrics.size as i64);
+    }
+
+    /// Account for the score changes of peers since the last event, including those following
+    /// deep rollbacks found by chain selection, which shares the reputation.
+    ///
+    /// Banned peers are disconnected by their pull stage, see [`crate::stages::pull::Stage`].
+    async fn track_reputation(&mut self) {
+        let reputation = self.consensus.reputation();
+        let mut reputation = reputation.lock().await;
+        // scores recover over time, lifting bans and demotions
+        self.banned.retain(|peer| reputation.is_banned(peer));
+        self.demoted
+            .retain(|peer| reputation.status(peer) == PeerStatus::Demoted);
+        let changes = reputation.take_events();
+        for change in changes {
+            match change.status {
+                PeerStatus::Banned if change.previous_status != PeerStatus::Banned => {
+                    warn!(peer = %change.peer.name, score = change.score, "peer banned");
+                    self.demoted.remove(&change.peer);
+                    self.banned.insert(change.peer);
+                }
+                PeerStatus::Demoted if change.previous_status == PeerStatus::Good => {
+                    info!(peer = %change.peer.name, score = change.score, "peer demoted");
+                    self.demoted.insert(change.peer);
+                }
+                PeerStatus::Good | PeerStatus::Demoted | PeerStatus::Banned => (),
+            }
+        }
+        self.peers_demoted.set(self.demoted.len() as i64);
+        self.peers_banned.set(self.banned.len() as i64);
+    }
+
+    async fn handle_batch(&mut self, batch: Batch) -> Result<(), WorkerError> {
+        let results = self
             .consensus
-            .handle_chain_sync(unit, &self.global_parameters)
-            .await
-            .map_err(|_| WorkerError::Recv)?;
+            .handle_chain_sync_batch(batch, &self.global_parameters)
+            .await;
+        self.track_validated();
+        self.track_reputation().await;
 
-        self.downstream
-            .send(event.into())
-            .await
-            .map_err(|_| WorkerError::Panic)?;
+        for result in results {
+            let event = match result {
+                Ok(event) => event,
+                // the header is dropped and its peer's score lowered, the pipeline carries on
+                Err(
+                    e @ (ConsensusError::InvalidHeader(..)
+                    | ConsensusError::InvalidOperationalCertificate(..)
+                    | ConsensusError::InvalidKesSignature(..)
+                    | ConsensusError::BannedPeer(..)),
+                ) => {
+                    warn!(error = %e, "dropped header");
+                    continue;
+                }
+                Err(e) => return Err(e).or_panic(),
+            };
+
+            self.downstream
+                .send(event.into())
+                .await
+                .map_err(|_| WorkerError::Panic)?;
+        }
 
         Ok(())
     }
@@ -69,17 +160,26 @@ impl gasket::framework::Worker<ValidateHeaderStage> for Worker {
     async fn schedule(
         &mut self,
         stage: &mut ValidateHeaderStage,
-    ) -> Result<WorkSchedule<DecodedChainSyncEvent>, WorkerError> {
+    ) -> Result<WorkSchedule<Batch>, WorkerError> {
         let unit = stage.upstream.recv().await.or_panic()?;
+        let mut batch = vec![unit.payload];
+
+        // along with the events that arrived meanwhile, without waiting for more
+        while batch.len() < MAX_BATCH_SIZE {
+            match timeout(Duration::ZERO, stage.upstream.recv()).await {
+                Ok(unit) => batch.push(unit.or_panic()?.payload),
+                Err(_) => break,
+            }
+        }
 
-        Ok(WorkSchedule::Unit(unit.payload))
+        Ok(WorkSchedule::Unit(batch))
     }
 
     async fn execute(
         &mut self,
-        unit: &DecodedChainSyncEvent,
+        unit: &Batch,
         stage: &mut ValidateHeaderStage,
     ) -> Result<(), WorkerError> {
-        stage.handle_event(unit.clone()).await
+        stage.handle_batch(unit.clone()).await
     }
 }
@@ -0,0 +1,161 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use amaru_consensus::consensus::{
+    reputation::{Offence, Reputation},
+    watchdog::{PeerStalled, Watchdog, WatchdogMsg, DEFAULT_STALL_TIMEOUT},
+    ChainSyncEvent,
+};
+use gasket::framework::*;
+use pure_stage::{
+    tokio::{Input, TokioBuilder, TokioRunning},
+    StageGraph,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc::UnboundedReceiver, Mutex},
+    time::timeout,
+};
+use tracing::{instrument, warn, Level};
+
+pub type UpstreamPort = gasket::messaging::InputPort<ChainSyncEvent>;
+pub type DownstreamPort = gasket::messaging::OutputPort<ChainSyncEvent>;
+
+/// How long the stage waits for an event from upstream before looking for stalled peers, which
+/// matters most when no peer sends anything.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum WorkUnit {
+    Progress(ChainSyncEvent),
+    Stalled(PeerStalled),
+}
+
+/// Pass the chain-sync events of the peers on, watching the time since each peer last made
+/// progress with a [`Watchdog`] and the [`DEFAULT_STALL_TIMEOUT`].
+///
+/// A stalled peer is charged with an [`Offence::Stall`], which demotes it: it is pulled from
+/// less often and asked for blocks last, see [`crate::stages::pull::Stage`] and
+/// [`super::fetch_block::BlockFetchStage`].
+#[derive(Stage)]
+#[stage(name = "consensus.watchdog", unit = "WorkUnit", worker = "Worker")]
+pub struct WatchdogStage {
+    pub upstream: UpstreamPort,
+    pub downstream: DownstreamPort,
+
+    /// The scores of the peers, charged for their stalls.
+    reputation: Arc<Mutex<Reputation>>,
+
+    #[metric]
+    peers_stalled: gasket::metrics::Counter,
+}
+
+impl Default for WatchdogStage {
+    fn default() -> Self {
+        Self {
+            upstream: Default::default(),
+            downstream: Default::default(),
+            reputation: Arc::new(Mutex::new(Reputation::default())),
+            peers_stalled: Default::default(),
+        }
+    }
+}
+
+impl WatchdogStage {
+    /// Charge the stalls to the given reputation, e.g. that shared with the stages pulling
+    /// from and fetching blocks from the peers.
+    pub fn with_reputation(mut self, reputation: Arc<Mutex<Reputation>>) -> Self {
+        self.reputation = reputation;
+        self
+    }
+}
+
+pub struct Worker {
+    /// The network running the watchdog, aborted along with the worker.
+    running: Option<TokioRunning>,
+    watchdog: Input<WatchdogMsg>,
+    stalled: UnboundedReceiver<PeerStalled>,
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.abort();
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl gasket::framework::Worker<WatchdogStage> for Worker {
+    async fn bootstrap(_stage: &WatchdogStage) -> Result<Self, WorkerError> {
+        let mut network = TokioBuilder::default();
+        let watchdog = network.stage(
+            "watchdog",
+            async |watchdog: Watchdog, msg, eff| Ok(watchdog.handle(msg, eff).await),
+            Watchdog::new(DEFAULT_STALL_TIMEOUT),
+        );
+        let (output, stalled) = network.output("stalled");
+        let watchdog = network.wire_up(watchdog, |state| state.connect(output.without_state()));
+        let running = network.run();
+        let watchdog = running.input(&watchdog);
+
+        Ok(Self {
+            running: Some(running),
+            watchdog,
+            stalled,
+        })
+    }
+
+    async fn schedule(
+        &mut self,
+        stage: &mut WatchdogStage,
+    ) -> Result<WorkSchedule<WorkUnit>, WorkerError> {
+        if let Ok(stalled) = self.stalled.try_recv() {
+            return Ok(WorkSchedule::Unit(WorkUnit::Stalled(stalled)));
+        }
+
+        match timeout(CHECK_INTERVAL, stage.upstream.recv()).await {
+            Ok(unit) => Ok(WorkSchedule::Unit(WorkUnit::Progress(
+                unit.or_panic()?.payload,
+            ))),
+            Err(_) => Ok(WorkSchedule::Idle),
+        }
+    }
+
+    #[instrument(
+        level = Level::TRACE,
+        skip_all,
+        name = "stage.watchdog"
+    )]
+    async fn execute(
+        &mut self,
+        unit: &WorkUnit,
+        stage: &mut WatchdogStage,
+    ) -> Result<(), WorkerError> {
+        match unit {
+            WorkUnit::Progress(event) => {
+                self.watchdog
+                    .send(WatchdogMsg::progress(event))
+                    .await
+                    .or_panic()?;
+                stage.downstream.send(event.clone().into()).await.or_panic()
+            }
+            WorkUnit::Stalled(PeerStalled { peer, .. }) => {
+                warn!(peer = %peer.name, "peer stalled");
+                stage.reputation.lock().await.record(peer, Offence::Stall);
+                stage.peers_stalled.inc(1);
+                Ok(())
+            }
+        }
+    }
+}
@@ -15,6 +15,7 @@
 use amaru_consensus::{
     consensus::{
         chain_selection::{ChainSelector, ChainSelectorBuilder},
+        prune::PruneForks,
         select_chain::SelectChain,
         store::ChainStore,
         store_block::StoreBlock,
@@ -40,7 +41,7 @@ use consensus::{
     fetch_block::BlockFetchStage, forward_chain::ForwardChainStage,
     receive_header::ReceiveHeaderStage, select_chain::SelectChainStage,
     store_block::StoreBlockStage, store_header::StoreHeaderStage,
-    validate_header::ValidateHeaderStage,
+    validate_header::ValidateHeaderStage, watchdog::WatchdogStage,
 };
 use gasket::{
     messaging::{tokio::funnel_ports, OutputPort},
@@ -51,6 +52,7 @@ use pallas_network::{facades::PeerClient, miniprotocols::chainsync::Tip};
 use std::{error::Error, path::PathBuf, sync::Arc};
 use tokio::sync::Mutex;
 
+pub mod capture;
 pub mod consensus;
 pub mod ledger;
 pub mod pull;
@@ -72,6 +74,8 @@ pub struct Config {
     pub network_magic: u32,
     pub listen_address: String,
     pub max_downstream_peers: usize,
+    /// Record the chain sync events received from upstream peers to this file.
+    pub capture_file: Option<PathBuf>,
 }
 
 impl Default for Config {
@@ -84,6 +88,7 @@ impl Default for Config {
             network_magic: 1,
             listen_address: "0.0.0.0:3000".to_string(),
             max_downstream_peers: 10,
+            capture_file: None,
         }
     }
 }
@@ -118,16 +123,15 @@ pub fn bootstrap(
         })
         .collect();
 
-    let mut fetch_block_stage = BlockFetchStage::new(peer_sessions.as_slice());
+    let (our_tip, header, chain_store) = make_chain_store(&config, era_history, tip.clone())?;
 
-    let mut stages = peer_sessions
-        .iter()
-        .map(|session| pull::Stage::new(session.clone(), vec![tip.clone()]))
-        .collect::<Vec<_>>();
-
-    let (our_tip, header, chain_store_ref) = make_chain_store(&config, era_history, tip)?;
-
-    let chain_selector = make_chain_selector(&header, &peer_sessions)?;
+    let chain_selector = make_chain_selector(
+        &header,
+        &peer_sessions,
+        &global_parameters,
+        chain_store.as_ref(),
+    )?;
+    let chain_store_ref: Arc<Mutex<dyn ChainStore<Header>>> = Arc::new(Mutex::new(chain_store));
     let consensus = match ledger_stage {
         LedgerStage::InMemLedgerStage(ref validate_block_stage) => ValidateHeader::new(
             Box::new(validate_block_stage.state.view_stake_distribution()),
@@ -142,13 +146,52 @@ pub fn bootstrap(
 
     let mut receive_header_stage = ReceiveHeaderStage::default();
 
+    let reputation = consensus.reputation();
+
+    let mut watchdog_stage = WatchdogStage::default().with_reputation(reputation.clone());
+
+    let mut fetch_block_stage =
+        BlockFetchStage::new(peer_sessions.as_slice()).with_reputation(reputation.clone());
+
+    let recorder = config
+        .capture_file
+        .as_deref()
+        .map(capture::Recorder::create)
+        .transpose()?
+        .map(Arc::new);
+
+    let mut stages = peer_sessions
+        .iter()
+        .map(|session| {
+            let stage = pull::Stage::new(session.clone(), vec![tip.clone()])
+                .with_reputation(reputation.clone());
+            match &recorder {
+                Some(recorder) => stage.with_recorder(recorder.clone()),
+                None => stage,
+            }
+        })
+        .collect::<Vec<_>>();
+
     let mut validate_header_stage = ValidateHeaderStage::new(consensus, &global_parameters);
 
-    let mut store_header_stage = StoreHeaderStage::new(StoreHeader::new(chain_store_ref.clone()));
+    let store_header = StoreHeader::new(chain_store_ref.clone());
+    let stored_headers = store_header.stored();
+    let mut store_header_stage = StoreHeaderStage::new(store_header);
 
-    let mut select_chain_stage = SelectChainStage::new(SelectChain::new(chain_selector));
+    let mut select_chain_stage = SelectChainStage::new(
+        SelectChain::new(chain_selector)
+            .with_reputation(reputation)
+            .with_store(chain_store_ref.clone()),
+    );
 
-    let mut store_block_stage = StoreBlockStage::new(StoreBlock::new(chain_store_ref.clone()));
+    let mut store_block_stage = StoreBlockStage::new(StoreBlock::new(chain_store_ref.clone()))
+        .with_pruning(
+            PruneForks::new(
+                chain_store_ref.clone(),
+                global_parameters.consensus_security_param as u64,
+            )
+            .with_stored(stored_headers),
+        );
 
     let mut forward_chain_stage = ForwardChainStage::new(
         None,
@@ -159,6 +202,7 @@ pub fn bootstrap(
         our_tip,
     );
 
+    let (to_receive_header, from_watchdog) = gasket::messaging::tokio::mpsc_channel(50);
     let (to_validate_header, from_receive_header) = gasket::messaging::tokio::mpsc_channel(50);
     let (to_store_header, from_validate_header) = gasket::messaging::tokio::mpsc_channel(50);
     let (to_select_chain, from_store_header) = gasket::messaging::tokio::mpsc_channel(50);
@@ -171,7 +215,10 @@ pub fn bootstrap(
         .iter_mut()
         .map(|p| &mut p.downstream)
         .collect::<Vec<_>>();
-    funnel_ports(outputs, &mut receive_header_stage.upstream, 50);
+    funnel_ports(outputs, &mut watchdog_stage.upstream, 50);
+    watchdog_stage.downstream.connect(to_receive_header);
+
+    receive_header_stage.upstream.connect(from_watchdog);
     receive_header_stage.downstream.connect(to_validate_header);
 
     validate_header_stage.upstream.connect(from_receive_header);
@@ -202,6 +249,7 @@ pub fn bootstrap(
         .collect::<Vec<_>>();
 
     let validate_header = gasket::runtime::spawn_stage(validate_header_stage, policy.clone());
+    let watchdog = gasket::runtime::spawn_stage(watchdog_stage, policy.clone());
     let receive_header = gasket::runtime::spawn_stage(receive_header_stage, policy.clone());
     let store_header = gasket::runtime::spawn_stage(store_header_stage, policy.clone());
     let select_chain = gasket::runtime::spawn_stage(select_chain_stage, policy.clone());
@@ -211,6 +259,7 @@ pub fn bootstrap(
     let block_forward = gasket::runtime::spawn_stage(forward_chain_stage, policy.clone());
 
     stages.push(store_header);
+    stages.push(watchdog);
     stages.push(receive_header);
     stages.push(select_chain);
     stages.push(validate_header);
@@ -221,7 +270,7 @@ pub fn bootstrap(
     Ok(stages)
 }
 
-type ChainStoreResult = (Tip, Option<Header>, Arc<Mutex<dyn ChainStore<Header>>>);
+type ChainStoreResult = (Tip, Option<Header>, Box<dyn ChainStore<Header>>);
 
 #[allow(clippy::todo, clippy::panic)]
 fn make_chain_store(
@@ -247,8 +296,7 @@ fn make_chain_store(
         (Tip(pallas_network::miniprotocols::Point::Origin, 0), None)
     };
 
-    let chain_store_ref: Arc<Mutex<dyn ChainStore<Header>>> = Arc::new(Mutex::new(chain_store));
-    Ok((our_tip, header, chain_store_ref))
+    Ok((our_tip, header, chain_store))
 }
 
 enum LedgerStage {
@@ -324,6 +372,8 @@ fn make_ledger(
 fn make_chain_selector(
     header: &Option<Header>,
     peers: &Vec<PeerSession>,
+    global_parameters: &GlobalParameters,
+    chain_store: &dyn ChainStore<Header>,
 ) -> Result<Arc<Mutex<ChainSelector<Header>>>, ConsensusError> {
     let mut builder = ChainSelectorBuilder::new();
 
@@ -331,6 +381,14 @@ fn make_chain_selector(
         Some(h) => builder.set_tip(h),
         None => &builder,
     };
+    builder
+        .set_max_rollback(global_parameters.consensus_security_param as u64)
+        .set_genesis_window(global_parameters.genesis_window);
+
+    // resume chain selection where it left off, unless the ledger is on another chain
+    if let Some(state) = chain_store.load_chain_selector_state() {
+        builder.restore_onto_tip(&state, chain_store);
+    }
 
     for peer in peers {
         builder.add_peer(&peer.peer);
@@ -388,6 +446,6 @@ mod tests {
 
         let stages = bootstrap(config, vec![]).unwrap();
 
-        assert_eq!(8, stages.len());
+        assert_eq!(9, stages.len());
     }
 }
@@ -12,17 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::PeerSession;
+use super::{capture::Recorder, PeerSession};
 use crate::point::{from_network_point, to_network_point};
-use amaru_consensus::{consensus::ChainSyncEvent, RawHeader};
+use amaru_consensus::{
+    consensus::{
+        reputation::{PeerStatus, Reputation},
+        ChainSyncEvent,
+    },
+    RawHeader,
+};
 use amaru_kernel::Point;
 use anyhow::anyhow;
 use gasket::framework::*;
 use pallas_network::miniprotocols::chainsync::{HeaderContent, NextResponse, Tip};
 use pallas_traverse::MultiEraHeader;
-use std::time::Duration;
-use tokio::time::timeout;
-use tracing::{instrument, Level, Span};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::Mutex,
+    time::{sleep, timeout},
+};
+use tracing::{info, instrument, warn, Level, Span};
 
 pub fn to_traverse(header: &HeaderContent) -> Result<MultiEraHeader<'_>, WorkerError> {
     let out = match header.byron_prefix {
@@ -35,6 +44,10 @@ pub fn to_traverse(header: &HeaderContent) -> Result<MultiEraHeader<'_>, WorkerE
 
 pub type DownstreamPort = gasket::messaging::OutputPort<ChainSyncEvent>;
 
+/// How long the stage waits before each pull from a demoted peer, e.g. one which stalled, to
+/// favour the other peers.
+const DEMOTED_PULL_DELAY: Duration = Duration::from_millis(500);
+
 pub enum WorkUnit {
     Pull,
     Await,
@@ -48,6 +61,13 @@ pub struct Stage {
 
     pub downstream: DownstreamPort,
 
+    recorder: Option<Arc<Recorder>>,
+
+    /// The scores of the peers: the peer is pulled from less often while demoted, and not at
+    /// all while banned.
+    reputation: Arc<Mutex<Reputation>>,
+    disconnected: bool,
+
     #[metric]
     chain_tip: gasket::metrics::Gauge,
 }
@@ -58,10 +78,58 @@ impl Stage {
             peer_session,
             intersection,
             downstream: Default::default(),
+            recorder: None,
+            reputation: Arc::new(Mutex::new(Reputation::default())),
+            disconnected: false,
             chain_tip: Default::default(),
         }
     }
 
+    /// Record every event received from the peer, see [`Recorder`].
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Stop pulling from the peer while banned in the given reputation, e.g. that of the stages
+    /// validating headers.
+    pub fn with_reputation(mut self, reputation: Arc<Mutex<Reputation>>) -> Self {
+        self.reputation = reputation;
+        self
+    }
+
+    /// Whether the peer is banned, in which case the stage stops pulling from it until its
+    /// score recovers.
+    async fn is_disconnected(&mut self) -> bool {
+        let banned = self
+            .reputation
+            .lock()
+            .await
+            .is_banned(&self.peer_session.peer);
+        if banned != self.disconnected {
+            if banned {
+                warn!(peer = %self.peer_session.peer.name, "peer_disconnected");
+            } else {
+                info!(peer = %self.peer_session.peer.name, "peer_reconnected");
+            }
+            self.disconnected = banned;
+        }
+        self.disconnected
+    }
+
+    /// Whether the peer is demoted, e.g. for stalling, in which case the stage slows down
+    /// pulling from it.
+    async fn is_demoted(&self) -> bool {
+        self.reputation.lock().await.status(&self.peer_session.peer) == PeerStatus::Demoted
+    }
+
+    async fn send(&mut self, event: ChainSyncEvent) -> Result<(), WorkerError> {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&event).or_panic()?;
+        }
+        self.downstream.send(event.into()).await.or_panic()
+    }
+
     fn track_tip(&self, tip: &Tip) {
         self.chain_tip.set(tip.0.slot_or_default() as i64);
     }
@@ -108,18 +176,13 @@ impl Stage {
 
         let raw_header: RawHeader = header.cbor().to_vec();
 
-        self.downstream
-            .send(
-                ChainSyncEvent::RollForward {
-                    peer: peer.clone(),
-                    point,
-                    raw_header,
-                    span: Span::current(),
-                }
-                .into(),
-            )
-            .await
-            .or_panic()
+        self.send(ChainSyncEvent::RollForward {
+            peer: peer.clone(),
+            point,
+            raw_header,
+            span: Span::current(),
+        })
+        .await
     }
 
     #[instrument(
@@ -135,17 +198,12 @@ impl Stage {
         self.track_tip(&tip);
 
         let peer = &self.peer_session.peer;
-        self.downstream
-            .send(
-                ChainSyncEvent::Rollback {
-                    peer: peer.clone(),
-                    rollback_point,
-                    span: Span::current(),
-                }
-                .into(),
-            )
-            .await
-            .or_panic()
+        self.send(ChainSyncEvent::Rollback {
+            peer: peer.clone(),
+            rollback_point,
+            span: Span::current(),
+        })
+        .await
     }
 }
 
@@ -162,6 +220,16 @@ impl gasket::framework::Worker<Stage> for Worker {
     }
 
     async fn schedule(&mut self, stage: &mut Stage) -> Result<WorkSchedule<WorkUnit>, WorkerError> {
+        // the stage stays up, not to bring down the whole pipeline, but leaves the peer alone
+        if stage.is_disconnected().await {
+            return Ok(WorkSchedule::Idle);
+        }
+
+        // without holding the session, which the block fetch shares
+        if stage.is_demoted().await {
+            sleep(DEMOTED_PULL_DELAY).await;
+        }
+
         let mut peer_client = stage.peer_session.lock().await;
         let client = (*peer_client).chainsync();
 
@@ -0,0 +1,32 @@
+use crate::{Message, Name};
+
+/// Why a message could not be delivered to its target, see [`DeadLetter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadLetterReason {
+    /// The target is a placeholder that was never wired up, see [`StageRef::noop`](crate::StageRef::noop).
+    Noop,
+    /// The target stage has failed and doesn’t receive messages anymore.
+    Terminated,
+    /// The mailbox of the target stage was full and it drops the messages that overflow it,
+    /// see [`StageGraph::drop_overflow`](crate::StageGraph::drop_overflow).
+    Overflow,
+}
+
+/// A message that could not be delivered, as received by the stage configured with
+/// [`StageGraph::dead_letters`](crate::StageGraph::dead_letters).
+#[derive(Debug)]
+pub struct DeadLetter {
+    pub from: Name,
+    pub to: Name,
+    pub reason: DeadLetterReason,
+    pub msg: Box<dyn Message>,
+}
+
+impl PartialEq for DeadLetter {
+    fn eq(&self, other: &Self) -> bool {
+        self.from == other.from
+            && self.to == other.to
+            && self.reason == other.reason
+            && self.msg.eq(&*other.msg)
+    }
+}
@@ -1,5 +1,12 @@
-use crate::{cast_msg, CallId, CallRef, Instant, Message, Name, StageRef};
-use std::{any::Any, fmt::Debug, time::Duration};
+use crate::{
+    cast_msg, cast_msg_ref, BoxFuture, CallId, CallRef, ForkHandle, ForkId, Instant, Message, Name,
+    StageRef, StoreError,
+};
+use std::{
+    any::TypeId,
+    fmt::{self, Debug},
+    time::Duration,
+};
 use tokio::sync::oneshot;
 
 /// An effect emitted by a stage (in which case T is `Box<dyn Message>`) or an effect
@@ -7,6 +14,8 @@ use tokio::sync::oneshot;
 #[derive(Debug)]
 pub(crate) enum StageEffect<T> {
     Receive,
+    ReceiveMatching(Matcher),
+    ReceiveQueued(usize),
     Send(
         Name,
         T,
@@ -15,6 +24,7 @@ pub(crate) enum StageEffect<T> {
     ),
     Clock,
     Wait(Duration),
+    Schedule(T, Duration),
     Call(
         Name,
         Instant,
@@ -23,7 +33,17 @@ pub(crate) enum StageEffect<T> {
         CallId,
     ),
     Respond(Name, CallId, Instant, oneshot::Sender<Box<dyn Message>>, T),
-    Interrupt,
+    External(T),
+    /// The payload and the type of decision the stage expects back.
+    Interrupt(T, TypeId),
+    /// The key and value to write to the store, kept in the marker as well so that the
+    /// simulation can perform the write when resuming it.
+    Persist(String, Vec<u8>),
+    Load(String),
+    /// The sub-task to run, kept in the marker so that the simulation can take it over when
+    /// resuming the effect.
+    Fork(ForkId, SubTask),
+    Join(ForkId),
 }
 
 #[derive(Debug)]
@@ -33,6 +53,54 @@ pub(crate) enum StageResponse {
     WaitResponse(Instant),
     CallResponse(Box<dyn Message>),
     CallTimeout,
+    ExternalResponse(Box<dyn Message>),
+    InterruptResponse(Box<dyn Message>),
+    MatchedMessage(Box<dyn Message>),
+    QueuedMessages(Vec<Box<dyn Message>>),
+    Persisted(Result<(), StoreError>),
+    Loaded(Result<Option<Vec<u8>>, StoreError>),
+    Joined(Box<dyn Message>),
+}
+
+/// A sub-task forked by a stage, see [`Effects::fork`](crate::Effects::fork).
+pub(crate) struct SubTask(pub BoxFuture<'static, Box<dyn Message>>);
+
+impl Debug for SubTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SubTask").finish_non_exhaustive()
+    }
+}
+
+/// The predicate of a selective receive, see [`Effects::receive_matching`](crate::Effects::receive_matching).
+pub(crate) struct Matcher(Box<dyn Fn(&dyn Message) -> bool + Send>);
+
+impl Matcher {
+    pub fn new<Msg: Message>(predicate: impl Fn(&Msg) -> bool + Send + 'static) -> Self {
+        Self(Box::new(move |msg| {
+            predicate(cast_msg_ref::<Msg>(msg).expect("internal messaging type error"))
+        }))
+    }
+
+    pub fn matches(&self, msg: &dyn Message) -> bool {
+        (self.0)(msg)
+    }
+}
+
+impl Debug for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Matcher").finish_non_exhaustive()
+    }
+}
+
+/// A request to the world outside of the stage graph, e.g. to read from disk or to connect to
+/// a peer, made with [`Effects::external`](crate::Effects::external).
+///
+/// Requests are answered by the handler registered for their type with
+/// [`StageGraph::external`](crate::StageGraph::external): real I/O on Tokio, scripted responses
+/// or failures in a simulation.
+pub trait ExternalEffect: Message {
+    /// The response to the request, which may be a `Result` to tell of failures.
+    type Response: Message;
 }
 
 impl StageEffect<Box<dyn Message>> {
@@ -42,6 +110,17 @@ impl StageEffect<Box<dyn Message>> {
     pub fn split(self, at_name: Name) -> (StageEffect<()>, Effect) {
         match self {
             StageEffect::Receive => (StageEffect::Receive, Effect::Receive { at_stage: at_name }),
+            StageEffect::ReceiveMatching(matcher) => (
+                StageEffect::ReceiveMatching(matcher),
+                Effect::ReceiveMatching { at_stage: at_name },
+            ),
+            StageEffect::ReceiveQueued(max) => (
+                StageEffect::ReceiveQueued(max),
+                Effect::ReceiveQueued {
+                    at_stage: at_name,
+                    max,
+                },
+            ),
             StageEffect::Send(name, msg, call_param) => {
                 let call = call_param
                     .as_ref()
@@ -64,6 +143,14 @@ impl StageEffect<Box<dyn Message>> {
                     duration,
                 },
             ),
+            StageEffect::Schedule(msg, after) => (
+                StageEffect::Schedule((), after),
+                Effect::Schedule {
+                    at_stage: at_name,
+                    msg,
+                    after,
+                },
+            ),
             StageEffect::Call(..) => {
                 panic!("call effect is only generated internally")
             }
@@ -76,9 +163,48 @@ impl StageEffect<Box<dyn Message>> {
                     msg,
                 },
             ),
-            StageEffect::Interrupt => (
-                StageEffect::Interrupt,
-                Effect::Interrupt { at_stage: at_name },
+            StageEffect::External(request) => (
+                StageEffect::External(()),
+                Effect::External {
+                    at_stage: at_name,
+                    request,
+                },
+            ),
+            StageEffect::Interrupt(payload, decision) => (
+                StageEffect::Interrupt((), decision),
+                Effect::Interrupt {
+                    at_stage: at_name,
+                    payload,
+                },
+            ),
+            StageEffect::Persist(key, value) => (
+                StageEffect::Persist(key.clone(), value.clone()),
+                Effect::Persist {
+                    at_stage: at_name,
+                    key,
+                    value,
+                },
+            ),
+            StageEffect::Load(key) => (
+                StageEffect::Load(key.clone()),
+                Effect::Load {
+                    at_stage: at_name,
+                    key,
+                },
+            ),
+            StageEffect::Fork(id, task) => (
+                StageEffect::Fork(id, task),
+                Effect::Fork {
+                    at_stage: at_name,
+                    id,
+                },
+            ),
+            StageEffect::Join(id) => (
+                StageEffect::Join(id),
+                Effect::Join {
+                    at_stage: at_name,
+                    id,
+                },
             ),
         }
     }
@@ -89,6 +215,15 @@ pub enum Effect {
     Receive {
         at_stage: Name,
     },
+    /// The stage waits for a message matching its predicate, leaving the others queued.
+    ReceiveMatching {
+        at_stage: Name,
+    },
+    /// The stage takes up to `max` of the messages queued in its mailbox, without waiting.
+    ReceiveQueued {
+        at_stage: Name,
+        max: usize,
+    },
     Send {
         from: Name,
         to: Name,
@@ -102,19 +237,57 @@ pub enum Effect {
         at_stage: Name,
         duration: Duration,
     },
+    /// The stage asks for `msg` to be delivered to itself `after` the given delay.
+    Schedule {
+        at_stage: Name,
+        msg: Box<dyn Message>,
+        after: Duration,
+    },
     Respond {
         at_stage: Name,
         target: Name,
         id: CallId,
         msg: Box<dyn Message>,
     },
+    /// The stage waits for the response to an [`ExternalEffect`].
+    External {
+        at_stage: Name,
+        request: Box<dyn Message>,
+    },
+    /// The stage interrupts the simulation, handing the payload to the test harness.
     Interrupt {
         at_stage: Name,
+        payload: Box<dyn Message>,
+    },
+    /// The stage writes `value` under `key` to the store, see [`Effects::persist`](crate::Effects::persist).
+    Persist {
+        at_stage: Name,
+        key: String,
+        value: Vec<u8>,
+    },
+    /// The stage reads the value under `key` from the store, see [`Effects::load`](crate::Effects::load).
+    Load {
+        at_stage: Name,
+        key: String,
+    },
+    /// The stage starts a sub-task, see [`Effects::fork`](crate::Effects::fork).
+    Fork {
+        at_stage: Name,
+        id: ForkId,
+    },
+    /// The stage waits for the result of a sub-task, see [`Effects::join`](crate::Effects::join).
+    Join {
+        at_stage: Name,
+        id: ForkId,
     },
     Failure {
         at_stage: Name,
         error: anyhow::Error,
     },
+    /// The stage has returned from its last transition, see [`Effects::terminate`](crate::Effects::terminate).
+    Termination {
+        at_stage: Name,
+    },
 }
 
 #[allow(clippy::wildcard_enum_match_arm)]
@@ -122,12 +295,21 @@ impl Effect {
     pub fn at_stage(&self) -> &Name {
         match self {
             Effect::Receive { at_stage, .. } => at_stage,
+            Effect::ReceiveMatching { at_stage } => at_stage,
+            Effect::ReceiveQueued { at_stage, .. } => at_stage,
             Effect::Send { from, .. } => from,
             Effect::Clock { at_stage, .. } => at_stage,
             Effect::Wait { at_stage, .. } => at_stage,
+            Effect::Schedule { at_stage, .. } => at_stage,
             Effect::Respond { at_stage, .. } => at_stage,
-            Effect::Interrupt { at_stage } => at_stage,
+            Effect::External { at_stage, .. } => at_stage,
+            Effect::Interrupt { at_stage, .. } => at_stage,
+            Effect::Persist { at_stage, .. } => at_stage,
+            Effect::Load { at_stage, .. } => at_stage,
+            Effect::Fork { at_stage, .. } => at_stage,
+            Effect::Join { at_stage, .. } => at_stage,
             Effect::Failure { at_stage, .. } => at_stage,
+            Effect::Termination { at_stage } => at_stage,
         }
     }
 
@@ -138,6 +320,27 @@ impl Effect {
         }
     }
 
+    pub fn assert_receive_matching<Msg, St>(&self, at_stage: &StageRef<Msg, St>) {
+        match self {
+            Effect::ReceiveMatching { at_stage: a } if a == &at_stage.name => {}
+            _ => {
+                panic!("unexpected effect {self:?}\n  looking for ReceiveMatching at {at_stage:?}")
+            }
+        }
+    }
+
+    pub fn assert_receive_queued<Msg, St>(&self, at_stage: &StageRef<Msg, St>, max: usize) {
+        match self {
+            Effect::ReceiveQueued {
+                at_stage: a,
+                max: m,
+            } if a == &at_stage.name && *m == max => {}
+            _ => panic!(
+                "unexpected effect {self:?}\n  looking for ReceiveQueued at {at_stage:?} with max {max}"
+            ),
+        }
+    }
+
     #[allow(clippy::unwrap_used)]
     pub fn assert_send<Msg1, Msg2: Message + PartialEq, St1, St2>(
         &self,
@@ -145,15 +348,14 @@ impl Effect {
         target: &StageRef<Msg2, St2>,
         msg: Msg2,
     ) {
+        let msg = target.adapt(Box::new(msg));
         match self {
             Effect::Send {
                 from,
                 to,
                 msg: m,
                 call: None,
-            } if from == &at_stage.name
-                && to == &target.name
-                && (&**m as &dyn Any).downcast_ref::<Msg2>().unwrap() == &msg => {}
+            } if from == &at_stage.name && to == &target.name && m.eq(&*msg) => {}
             _ => panic!("unexpected effect {self:?}\n  looking for Send from {at_stage:?} to {target:?} with msg {msg:?}"),
         }
     }
@@ -175,6 +377,22 @@ impl Effect {
         }
     }
 
+    pub fn assert_schedule<Msg: Message + PartialEq, St>(
+        &self,
+        at_stage: &StageRef<Msg, St>,
+        msg: Msg,
+        after: Duration,
+    ) {
+        match self {
+            Effect::Schedule {
+                at_stage: a,
+                msg: m,
+                after: d,
+            } if a == &at_stage.name && msg.eq(&**m) && d == &after => {}
+            _ => panic!("unexpected effect {self:?}\n  looking for Schedule at {at_stage:?} with msg {msg:?} after {after:?}"),
+        }
+    }
+
     pub fn assert_call<Msg1, Msg2: Message, Out, St1, St2>(
         self,
         at_stage: &StageRef<Msg1, St1>,
@@ -195,6 +413,83 @@ impl Effect {
         }
     }
 
+    pub fn assert_external<Msg, St, E: ExternalEffect>(
+        &self,
+        at_stage: &StageRef<Msg, St>,
+        request: E,
+    ) {
+        match self {
+            Effect::External {
+                at_stage: a,
+                request: r,
+            } if a == &at_stage.name && request.eq(&**r) => {}
+            _ => panic!("unexpected effect {self:?}\n  looking for External at {at_stage:?} with request {request:?}"),
+        }
+    }
+
+    pub fn assert_interrupt<Msg, St, P: Message>(&self, at_stage: &StageRef<Msg, St>, payload: P) {
+        match self {
+            Effect::Interrupt {
+                at_stage: a,
+                payload: p,
+            } if a == &at_stage.name && payload.eq(&**p) => {}
+            _ => panic!("unexpected effect {self:?}\n  looking for Interrupt at {at_stage:?} with payload {payload:?}"),
+        }
+    }
+
+    pub fn assert_persist<Msg, St>(
+        &self,
+        at_stage: &StageRef<Msg, St>,
+        key: impl AsRef<str>,
+        value: impl AsRef<[u8]>,
+    ) {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        match self {
+            Effect::Persist {
+                at_stage: a,
+                key: k,
+                value: v,
+            } if a == &at_stage.name && k == key && v == value => {}
+            _ => panic!("unexpected effect {self:?}\n  looking for Persist at {at_stage:?} with key {key:?} and value {value:?}"),
+        }
+    }
+
+    pub fn assert_load<Msg, St>(&self, at_stage: &StageRef<Msg, St>, key: impl AsRef<str>) {
+        let key = key.as_ref();
+        match self {
+            Effect::Load {
+                at_stage: a,
+                key: k,
+            } if a == &at_stage.name && k == key => {}
+            _ => panic!(
+                "unexpected effect {self:?}\n  looking for Load at {at_stage:?} with key {key:?}"
+            ),
+        }
+    }
+
+    pub fn assert_fork<Msg, St>(&self, at_stage: &StageRef<Msg, St>) {
+        match self {
+            Effect::Fork { at_stage: a, .. } if a == &at_stage.name => {}
+            _ => panic!("unexpected effect {self:?}\n  looking for Fork at {at_stage:?}"),
+        }
+    }
+
+    pub fn assert_join<Msg, St, T>(&self, at_stage: &StageRef<Msg, St>, handle: &ForkHandle<T>) {
+        match self {
+            Effect::Join { at_stage: a, id } if a == &at_stage.name && *id == handle.id => {}
+            _ => panic!(
+                "unexpected effect {self:?}\n  looking for Join at {at_stage:?} with handle {handle:?}"
+            ),
+        }
+    }
+
+    pub fn assert_termination<Msg, St>(&self, at_stage: &StageRef<Msg, St>) {
+        match self {
+            Effect::Termination { at_stage: a } if a == &at_stage.name => {}
+            _ => panic!("unexpected effect {self:?}\n  looking for Termination at {at_stage:?}"),
+        }
+    }
+
     pub fn assert_respond<Msg, St, Msg2: Message>(
         &self,
         at_stage: &StageRef<Msg, St>,
@@ -222,6 +517,19 @@ impl PartialEq for Effect {
                     at_stage: other_at_stage,
                 },
             ) => at_stage == other_at_stage,
+            (
+                Effect::ReceiveMatching { at_stage },
+                Effect::ReceiveMatching {
+                    at_stage: other_at_stage,
+                },
+            ) => at_stage == other_at_stage,
+            (
+                Effect::ReceiveQueued { at_stage, max },
+                Effect::ReceiveQueued {
+                    at_stage: other_at_stage,
+                    max: other_max,
+                },
+            ) => at_stage == other_at_stage && max == other_max,
             (
                 Effect::Send {
                     from,
@@ -251,6 +559,84 @@ impl PartialEq for Effect {
                     duration: other_duration,
                 },
             ) => at_stage == other_at_stage && duration == other_duration,
+            (
+                Effect::Schedule {
+                    at_stage,
+                    msg,
+                    after,
+                },
+                Effect::Schedule {
+                    at_stage: other_at_stage,
+                    msg: other_msg,
+                    after: other_after,
+                },
+            ) => at_stage == other_at_stage && msg.eq(&**other_msg) && after == other_after,
+            (
+                Effect::Respond {
+                    at_stage,
+                    target,
+                    id,
+                    msg,
+                },
+                Effect::Respond {
+                    at_stage: other_at_stage,
+                    target: other_target,
+                    id: other_id,
+                    msg: other_msg,
+                },
+            ) => {
+                at_stage == other_at_stage
+                    && target == other_target
+                    && id == other_id
+                    && msg.eq(&**other_msg)
+            }
+            (
+                Effect::External { at_stage, request },
+                Effect::External {
+                    at_stage: other_at_stage,
+                    request: other_request,
+                },
+            ) => at_stage == other_at_stage && request.eq(&**other_request),
+            (
+                Effect::Interrupt { at_stage, payload },
+                Effect::Interrupt {
+                    at_stage: other_at_stage,
+                    payload: other_payload,
+                },
+            ) => at_stage == other_at_stage && payload.eq(&**other_payload),
+            (
+                Effect::Persist {
+                    at_stage,
+                    key,
+                    value,
+                },
+                Effect::Persist {
+                    at_stage: other_at_stage,
+                    key: other_key,
+                    value: other_value,
+                },
+            ) => at_stage == other_at_stage && key == other_key && value == other_value,
+            (
+                Effect::Load { at_stage, key },
+                Effect::Load {
+                    at_stage: other_at_stage,
+                    key: other_key,
+                },
+            ) => at_stage == other_at_stage && key == other_key,
+            (
+                Effect::Fork { at_stage, id },
+                Effect::Fork {
+                    at_stage: other_at_stage,
+                    id: other_id,
+                },
+            ) => at_stage == other_at_stage && id == other_id,
+            (
+                Effect::Join { at_stage, id },
+                Effect::Join {
+                    at_stage: other_at_stage,
+                    id: other_id,
+                },
+            ) => at_stage == other_at_stage && id == other_id,
             (
                 Effect::Failure { at_stage, error },
                 Effect::Failure {
@@ -258,6 +644,12 @@ impl PartialEq for Effect {
                     error: other_error,
                 },
             ) => at_stage == other_at_stage && error.to_string() == other_error.to_string(),
+            (
+                Effect::Termination { at_stage },
+                Effect::Termination {
+                    at_stage: other_at_stage,
+                },
+            ) => at_stage == other_at_stage,
             _ => false,
         }
     }
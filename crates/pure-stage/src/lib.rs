@@ -1,15 +1,22 @@
 #![allow(clippy::panic, clippy::expect_used)]
 
+mod dead_letter;
 mod effect;
+mod metrics;
 pub mod simulation;
 mod stage;
 mod stagegraph;
+mod store;
 mod time;
 pub mod tokio;
 mod types;
+mod wiring;
 
-pub use effect::Effect;
+pub use dead_letter::{DeadLetter, DeadLetterReason};
+pub use effect::{Effect, ExternalEffect};
+pub use metrics::{Histogram, Metrics, StageMetrics};
 pub use stage::{StageBuildRef, StageRef, Void};
-pub use stagegraph::{CallId, CallRef, Effects, StageGraph};
+pub use stagegraph::{CallId, CallRef, Effects, ForkHandle, ForkId, ShutdownPolicy, StageGraph};
+pub use store::{MemoryStore, Store, StoreError};
 pub use time::Instant;
 pub use types::{cast_msg, cast_msg_ref, cast_state, BoxFuture, Message, Name, State};
@@ -0,0 +1,173 @@
+use crate::Name;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+/// The upper bounds of the buckets of processing times, in seconds.
+const PROCESSING_SECONDS: [f64; 8] = [0.0001, 0.001, 0.01, 0.1, 1.0, 10.0, 60.0, 600.0];
+/// The upper bounds of the buckets of mailbox depths.
+const MAILBOX_DEPTH: [f64; 8] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 1000.0];
+
+/// A histogram the way Prometheus has them: the number of observations up to each bound,
+/// cumulatively, along with their sum and count.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    pub buckets: Vec<(f64, u64)>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        Self {
+            buckets: bounds.iter().map(|bound| (*bound, 0)).collect(),
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        for (bound, count) in &mut self.buckets {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// The metrics the runtime maintains for each stage.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageMetrics {
+    /// The messages processed successfully.
+    pub processed: u64,
+    /// The messages whose processing failed, which stops the stage.
+    pub failures: u64,
+    /// The time from receiving a message until being done with it, in seconds; this is
+    /// simulated time in a simulation.
+    pub processing_time: Histogram,
+    /// The number of messages in the mailbox when receiving one of them.
+    pub mailbox_depth: Histogram,
+}
+
+impl Default for StageMetrics {
+    fn default() -> Self {
+        Self {
+            processed: 0,
+            failures: 0,
+            processing_time: Histogram::new(&PROCESSING_SECONDS),
+            mailbox_depth: Histogram::new(&MAILBOX_DEPTH),
+        }
+    }
+}
+
+impl StageMetrics {
+    pub(crate) fn received(&mut self, mailbox_depth: usize) {
+        self.mailbox_depth.observe(mailbox_depth as f64);
+    }
+
+    pub(crate) fn processed(&mut self, elapsed: Duration, success: bool) {
+        if success {
+            self.processed += 1;
+        } else {
+            self.failures += 1;
+        }
+        self.processing_time.observe(elapsed.as_secs_f64());
+    }
+}
+
+/// The metrics of all stages of a graph, see
+/// [`SimulationRunning::metrics`](crate::simulation::SimulationRunning::metrics) and
+/// [`TokioRunning::metrics`](crate::tokio::TokioRunning::metrics).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metrics {
+    pub stages: BTreeMap<Name, StageMetrics>,
+}
+
+impl Metrics {
+    /// The metrics of the stage of the given name.
+    pub fn stage(&self, name: impl AsRef<str>) -> Option<&StageMetrics> {
+        self.stages.get(&Name::from(name.as_ref()))
+    }
+
+    /// Render in the Prometheus text exposition format, with a `stage` label on each series.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        self.counter(
+            &mut out,
+            "pure_stage_messages_processed_total",
+            "Messages processed successfully.",
+            |metrics| metrics.processed,
+        );
+        self.counter(
+            &mut out,
+            "pure_stage_failures_total",
+            "Messages whose processing failed.",
+            |metrics| metrics.failures,
+        );
+        self.histogram(
+            &mut out,
+            "pure_stage_processing_seconds",
+            "Time from receiving a message until being done with it.",
+            |metrics| &metrics.processing_time,
+        );
+        self.histogram(
+            &mut out,
+            "pure_stage_mailbox_depth",
+            "Messages in the mailbox when receiving one of them.",
+            |metrics| &metrics.mailbox_depth,
+        );
+        out
+    }
+
+    fn counter(
+        &self,
+        out: &mut String,
+        name: &str,
+        help: &str,
+        value: impl Fn(&StageMetrics) -> u64,
+    ) {
+        writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter").ok();
+        for (stage, metrics) in &self.stages {
+            let stage = label(stage);
+            writeln!(out, "{name}{{stage=\"{stage}\"}} {}", value(metrics)).ok();
+        }
+    }
+
+    fn histogram(
+        &self,
+        out: &mut String,
+        name: &str,
+        help: &str,
+        value: impl Fn(&StageMetrics) -> &Histogram,
+    ) {
+        writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram").ok();
+        for (stage, metrics) in &self.stages {
+            let stage = label(stage);
+            let histogram = value(metrics);
+            for (bound, count) in &histogram.buckets {
+                writeln!(
+                    out,
+                    "{name}_bucket{{stage=\"{stage}\",le=\"{bound}\"}} {count}"
+                )
+                .ok();
+            }
+            let count = histogram.count;
+            writeln!(
+                out,
+                "{name}_bucket{{stage=\"{stage}\",le=\"+Inf\"}} {count}"
+            )
+            .ok();
+            writeln!(out, "{name}_sum{{stage=\"{stage}\"}} {}", histogram.sum).ok();
+            writeln!(out, "{name}_count{{stage=\"{stage}\"}} {count}").ok();
+        }
+    }
+}
+
+/// Escape a label value as the text exposition format requires.
+fn label(name: &Name) -> String {
+    name.as_str()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,34 +1,55 @@
 #![allow(clippy::wildcard_enum_match_arm, clippy::unwrap_used, clippy::panic)]
 
 use crate::{
-    cast_msg,
-    effect::{StageEffect, StageResponse},
-    BoxFuture, Effects, Instant, Message, Name, StageBuildRef, StageGraph, StageRef, State,
+    cast_msg, cast_msg_ref,
+    effect::{ExternalEffect, StageEffect, StageResponse},
+    wiring::{observe_targets, Wiring},
+    BoxFuture, DeadLetter, Effects, Instant, MemoryStore, Message, Name, StageBuildRef, StageGraph,
+    StageMetrics, StageRef, State,
 };
 use std::{
-    any::Any,
+    any::{Any, TypeId},
     collections::{HashMap, VecDeque},
     future::{poll_fn, Future},
     marker::PhantomData,
+    pin::pin,
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
-    task::Poll,
+    task::{Context, Poll, Waker},
     time::Duration,
 };
 use tokio::sync::mpsc::unbounded_channel;
+use tracing::Span;
 
+pub use causality::{Causality, MsgId, MsgRecord};
+pub use explore::{explore, Exploration};
+pub use fairness::{Fairness, StageFairness};
 pub use receiver::Receiver;
-pub use running::{Blocked, SimulationRunning};
+pub use report::{GraphReport, PriorityInversion, StageReport, StageStatus, WaitingFor};
+pub use running::{Blocked, SimulationRunning, Snapshot};
+pub use scheduler::Scheduler;
+pub use skew::ClockSkew;
+pub use trace::{Trace, TraceStep};
 
 use either::Either;
 use parking_lot::Mutex;
-use state::{InitStageData, InitStageState, StageData, StageState, Transition};
+use rand::{rngs::StdRng, SeedableRng};
+use state::{
+    Cloner, ExternalHandler, InitStageData, InitStageState, StageData, StageState, Transition,
+};
 
+mod causality;
+mod explore;
+mod fairness;
 mod receiver;
+mod report;
 mod running;
+mod scheduler;
+mod skew;
 mod state;
+mod trace;
 
 pub(crate) type EffectBox =
     Arc<Mutex<Option<Either<StageEffect<Box<dyn Message>>, StageResponse>>>>;
@@ -66,6 +87,66 @@ pub(crate) fn airlock_effect<Out>(
     }))
 }
 
+/// The clock of a stage, i.e. the simulated clock as skewed for it, see [`ClockSkew`].
+fn stage_clock(
+    now: Arc<dyn Fn() -> Instant + Send + Sync>,
+    clock: Arc<AtomicU64>,
+    skew: Arc<Mutex<ClockSkew>>,
+) -> Arc<dyn Fn() -> Instant + Send + Sync> {
+    Arc::new(move || skew.lock().local_time(now(), clock.load(Ordering::Relaxed)))
+}
+
+/// The span in which the stage `name` processes a message, as a child of the span in which
+/// the message was sent, so that the journey of a message through the stages forms one trace.
+pub(crate) fn stage_span(name: &Name, sent_in: &Span) -> Span {
+    tracing::info_span!(parent: sent_in, "stage", stage = %name)
+}
+
+/// Wrap the transition function of a stage, to be called with its state and messages.
+fn transition<Msg: Message, St: State, F, Fut>(mut f: F, effects: Effects<Msg, St>) -> Transition
+where
+    F: FnMut(St, Msg, Effects<Msg, St>) -> Fut + 'static + Send,
+    Fut: Future<Output = anyhow::Result<St>> + 'static + Send,
+{
+    Box::new(move |state: Box<dyn State>, msg: Box<dyn Message>| {
+        let state = (state as Box<dyn Any>).downcast::<St>().unwrap();
+        let msg = cast_msg::<Msg>(msg).unwrap();
+        let state = f(*state, msg, effects.clone());
+        Box::pin(async move { Ok(Box::new(state.await?) as Box<dyn State>) })
+    })
+}
+
+/// The priority of each message to a stage, see [`StageGraph::prioritize`].
+pub(crate) type Priority = Arc<dyn Fn(&dyn Message) -> u8 + Send + Sync>;
+
+pub(crate) fn priority<Msg: Message>(f: impl Fn(&Msg) -> u8 + Send + Sync + 'static) -> Priority {
+    Arc::new(move |msg| f(cast_msg_ref::<Msg>(msg).expect("internal messaging type error")))
+}
+
+/// What to do with the final state of a stage, see [`StageGraph::finalizer`].
+pub(crate) type Finalizer = Box<dyn FnOnce(Box<dyn State>) + Send>;
+
+pub(crate) fn finalizer<St: State>(f: impl FnOnce(St) + Send + 'static) -> Finalizer {
+    Box::new(move |state| {
+        let state = (state as Box<dyn Any>)
+            .downcast::<St>()
+            .expect("internal state type error");
+        f(*state)
+    })
+}
+
+/// The index and priority of the first of the highest priorities, i.e. the message to deliver
+/// next, if any.
+pub(crate) fn highest_priority(priorities: impl IntoIterator<Item = u8>) -> Option<(usize, u8)> {
+    let mut highest: Option<(usize, u8)> = None;
+    for (idx, priority) in priorities.into_iter().enumerate() {
+        if highest.is_none_or(|(_, p)| priority > p) {
+            highest = Some((idx, priority));
+        }
+    }
+    highest
+}
+
 /// A fully controllable and deterministic [`StageGraph`] for testing purposes.
 ///
 /// Execution is controlled entirely via the [`SimulationRunning`] handle returned from
@@ -116,6 +197,12 @@ pub struct SimulationBuilder {
     clock: Arc<AtomicU64>,
     now: Arc<dyn Fn() -> Instant + Send + Sync>,
     mailbox_size: usize,
+    backpressure_stops: bool,
+    rng: Arc<Mutex<StdRng>>,
+    externals: HashMap<TypeId, ExternalHandler>,
+    wiring: Wiring,
+    dead_letters: Option<Name>,
+    store: MemoryStore,
 }
 
 impl SimulationBuilder {
@@ -123,6 +210,72 @@ impl SimulationBuilder {
         self.mailbox_size = size;
         self
     }
+
+    /// Seed the generator of [`Effects::random`], which is seeded with 0 otherwise.
+    pub fn with_seed(self, seed: u64) -> Self {
+        *self.rng.lock() = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Make [`SimulationRunning::run_until_blocked`] stop with [`Blocked::Backpressure`]
+    /// whenever a stage sends to a full mailbox, instead of carrying on with the other stages
+    /// while the sender waits.
+    pub fn with_backpressure_stops(mut self, stops: bool) -> Self {
+        self.backpressure_stops = stops;
+        self
+    }
+
+    /// Allow the given stage to be part of a [`Snapshot`], by cloning its state and the
+    /// messages in its mailbox.
+    ///
+    /// A simulation can only be snapshotted if this was done for all of its stages.
+    pub fn enable_snapshots<Msg: Message + Clone, St: State + Clone>(
+        &mut self,
+        stage: &StageRef<Msg, St>,
+    ) {
+        self.stages
+            .get_mut(&stage.name)
+            .expect("stage ref exists, so stage must exist")
+            .cloner = Some(Cloner::new::<Msg, St>());
+    }
+
+    /// Skew the clock of the given stage against the simulated clock, e.g. to test how stages
+    /// agree on time-dependent decisions when their clocks don’t.
+    ///
+    /// The stage sees the skewed time in the responses to its [`clock`](Effects::clock) and
+    /// [`wait`](Effects::wait) effects and the durations it waits for or schedules messages
+    /// after pass on its own clock, so a drifting clock makes them take more or less simulated
+    /// time.
+    pub fn clock_skew<Msg, St>(&mut self, stage: &StageRef<Msg, St>, skew: ClockSkew) {
+        *self
+            .stages
+            .get_mut(&stage.name)
+            .expect("stage ref exists, so stage must exist")
+            .skew
+            .lock() = skew;
+    }
+
+    /// The store of the simulation, which answers the [`persist`](Effects::persist) and
+    /// [`load`](Effects::load) effects of its stages, to fill it beforehand, inject faults
+    /// or inspect its contents later.
+    pub fn store(&self) -> MemoryStore {
+        self.store.clone()
+    }
+
+    /// Report the stages created so far, telling those not yet wired up.
+    pub fn report(&self) -> GraphReport {
+        let mut stages = self
+            .stages
+            .iter()
+            .map(|(name, data)| StageReport::new_init(name, data))
+            .collect::<Vec<_>>();
+        stages.sort_by(|a, b| a.name.cmp(&b.name));
+        GraphReport {
+            stages,
+            sleeping: 0,
+            next_wakeup: None,
+        }
+    }
 }
 
 impl Default for SimulationBuilder {
@@ -140,6 +293,12 @@ impl Default for SimulationBuilder {
             clock,
             now,
             mailbox_size: 10,
+            backpressure_stops: false,
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(0))),
+            externals: HashMap::new(),
+            wiring: Wiring::default(),
+            dead_letters: None,
+            store: MemoryStore::default(),
         }
     }
 }
@@ -220,7 +379,7 @@ impl super::StageGraph for SimulationBuilder {
     fn stage<Msg: Message, St: State, F, Fut>(
         &mut self,
         name: impl AsRef<str>,
-        mut f: F,
+        f: F,
         state: St,
     ) -> StageBuildRef<Msg, St, Self::RefAux<Msg, St>>
     where
@@ -228,18 +387,18 @@ impl super::StageGraph for SimulationBuilder {
         Fut: Future<Output = anyhow::Result<St>> + 'static + Send,
     {
         let name = Name::from(name.as_ref());
-        let me = StageRef {
-            name: name.clone(),
-            _ph: PhantomData,
-        };
-        let effects = Effects::new(me, self.effect.clone(), self.now.clone());
-        let transition: Transition =
-            Box::new(move |state: Box<dyn State>, msg: Box<dyn Message>| {
-                let state = (state as Box<dyn Any>).downcast::<St>().unwrap();
-                let msg = cast_msg::<Msg>(msg).unwrap();
-                let state = f(*state, msg, effects.clone());
-                Box::pin(async move { Ok(Box::new(state.await?) as Box<dyn State>) })
-            });
+        let me = StageRef::new(name.clone());
+        let skew = Arc::new(Mutex::new(ClockSkew::default()));
+        let now = stage_clock(self.now.clone(), self.clock.clone(), skew.clone());
+        let terminating = Arc::new(AtomicBool::new(false));
+        let effects = Effects::new(
+            me,
+            self.effect.clone(),
+            now.clone(),
+            self.rng.clone(),
+            terminating.clone(),
+        );
+        let transition = transition(f, effects);
 
         if let Some(old) = self.stages.insert(
             name.clone(),
@@ -247,10 +406,18 @@ impl super::StageGraph for SimulationBuilder {
                 state: InitStageState::Uninitialized,
                 mailbox: VecDeque::new(),
                 transition,
+                cloner: None,
+                priority: None,
+                drops_overflow: false,
+                skew,
+                now,
+                terminating,
+                finalizer: None,
             },
         ) {
             panic!("stage {name} already exists with state {:?}", old.state);
         }
+        self.wiring.add_stage(&name);
 
         StageBuildRef {
             name,
@@ -260,6 +427,50 @@ impl super::StageGraph for SimulationBuilder {
         }
     }
 
+    fn prioritize<Msg: Message, St>(
+        &mut self,
+        stage: &StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
+        f: impl Fn(&Msg) -> u8 + Send + Sync + 'static,
+    ) {
+        self.stages.get_mut(&stage.name).unwrap().priority = Some(priority(f));
+    }
+
+    fn drop_overflow<Msg: Message, St>(
+        &mut self,
+        stage: &StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
+    ) {
+        self.stages.get_mut(&stage.name).unwrap().drops_overflow = true;
+    }
+
+    fn dead_letters<St>(&mut self, stage: &StageRef<DeadLetter, St>) {
+        self.dead_letters = Some(stage.name());
+    }
+
+    fn finalizer<Msg: Message, St: State>(
+        &mut self,
+        stage: &StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
+        f: impl FnOnce(St) + Send + 'static,
+    ) {
+        self.stages.get_mut(&stage.name).unwrap().finalizer = Some(finalizer(f));
+    }
+
+    fn external<E, F, Fut>(&mut self, handler: F)
+    where
+        E: ExternalEffect,
+        F: Fn(E) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = E::Response> + Send + 'static,
+    {
+        let handler: ExternalHandler = Box::new(move |request| {
+            let request = cast_msg::<E>(request).expect("internal messaging type error");
+            let response = pin!(handler(request));
+            match response.poll(&mut Context::from_waker(Waker::noop())) {
+                Poll::Ready(response) => Some(Box::new(response) as Box<dyn Message>),
+                Poll::Pending => None,
+            }
+        });
+        self.externals.insert(TypeId::of::<E>(), handler);
+    }
+
     fn wire_up<Msg: Message, St: State>(
         &mut self,
         stage: crate::StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
@@ -272,14 +483,16 @@ impl super::StageGraph for SimulationBuilder {
             _ph,
         } = stage;
 
-        f(&mut state);
+        let targets = observe_targets(|| f(&mut state));
+        self.wiring.wire_up(&name, targets);
         let data = self.stages.get_mut(&name).unwrap();
         data.state = InitStageState::Idle(Box::new(state));
 
-        StageRef {
-            name,
-            _ph: PhantomData,
-        }
+        StageRef::new(name)
+    }
+
+    fn export_dot(&self) -> String {
+        self.wiring.to_dot()
     }
 
     fn run(self) -> Self::Running {
@@ -289,6 +502,12 @@ impl super::StageGraph for SimulationBuilder {
             clock,
             now,
             mailbox_size,
+            backpressure_stops,
+            rng,
+            externals,
+            wiring,
+            dead_letters,
+            store,
         } = self;
         let mut stages = HashMap::new();
         for (
@@ -297,6 +516,13 @@ impl super::StageGraph for SimulationBuilder {
                 mailbox,
                 state,
                 transition,
+                cloner,
+                priority,
+                drops_overflow,
+                skew,
+                now: stage_now,
+                terminating,
+                finalizer,
             },
         ) in s
         {
@@ -311,9 +537,34 @@ impl super::StageGraph for SimulationBuilder {
                 transition,
                 waiting: Some(StageEffect::Receive),
                 senders: VecDeque::new(),
+                span: Span::none(),
+                processing: None,
+                cloner,
+                priority,
+                drops_overflow,
+                skew,
+                now: stage_now,
+                terminating,
+                finalizer,
+                forks: BTreeMap::new(),
+                inversions: Vec::new(),
+                metrics: StageMetrics::default(),
+                received_at: None,
             };
             stages.insert(name, data);
         }
-        SimulationRunning::new(stages, effect, clock, now, mailbox_size)
+        SimulationRunning::new(
+            stages,
+            effect,
+            clock,
+            now,
+            rng,
+            externals,
+            wiring,
+            mailbox_size,
+            backpressure_stops,
+            dead_letters,
+            store,
+        )
     }
 }
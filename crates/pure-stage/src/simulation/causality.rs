@@ -0,0 +1,77 @@
+use crate::{Message, Name};
+use serde::Serialize;
+
+/// The id of a message sent within a simulation, unique within it, see [`Causality`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct MsgId(u64);
+
+/// A message that was put into the mailbox of a stage, see [`Causality`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MsgRecord {
+    pub id: MsgId,
+    /// The message that started the transition of the sending stage, `None` for messages fed
+    /// into the simulation from outside, e.g. with
+    /// [`enqueue_msg`](super::SimulationRunning::enqueue_msg).
+    pub parent: Option<MsgId>,
+    /// The sending stage, `None` for messages fed into the simulation from outside.
+    pub from: Option<Name>,
+    pub to: Name,
+    /// The message, as rendered by its `Debug` implementation.
+    pub msg: String,
+}
+
+/// The messages sent within a simulation and which message caused each of them, see
+/// [`SimulationRunning::causality`](super::SimulationRunning::causality).
+///
+/// A stage may take more messages out of its mailbox during a transition, e.g. with
+/// [`receive_matching`](crate::Effects::receive_matching), but the messages it sends are
+/// attributed to the one that started the transition. Messages a stage
+/// [schedules](crate::Effects::schedule) to itself are caused by that message, too, and so are
+/// the [dead letters](crate::DeadLetter) of the messages it sends.
+#[derive(Debug, Clone, Default)]
+pub struct Causality {
+    messages: Vec<MsgRecord>,
+}
+
+impl Causality {
+    pub(super) fn record(
+        &mut self,
+        parent: Option<MsgId>,
+        from: Option<Name>,
+        to: Name,
+        msg: &dyn Message,
+    ) -> MsgId {
+        let id = MsgId(self.messages.len() as u64);
+        self.messages.push(MsgRecord {
+            id,
+            parent,
+            from,
+            to,
+            msg: format!("{msg:?}"),
+        });
+        id
+    }
+
+    /// All messages, in the order in which they were sent.
+    pub fn messages(&self) -> &[MsgRecord] {
+        &self.messages
+    }
+
+    pub fn get(&self, id: MsgId) -> Option<&MsgRecord> {
+        usize::try_from(id.0)
+            .ok()
+            .and_then(|idx| self.messages.get(idx))
+    }
+
+    /// The message that caused the given one, if any.
+    pub fn parent(&self, id: MsgId) -> Option<&MsgRecord> {
+        self.get(id)?.parent.and_then(|parent| self.get(parent))
+    }
+
+    /// The messages caused by the given one, in the order in which they were sent.
+    pub fn children(&self, id: MsgId) -> impl Iterator<Item = &MsgRecord> + '_ {
+        self.messages
+            .iter()
+            .filter(move |record| record.parent == Some(id))
+    }
+}
@@ -0,0 +1,205 @@
+use super::{Blocked, SimulationRunning};
+use crate::{effect::StageResponse, Effect, Name};
+use anyhow::Context;
+use std::collections::{BTreeSet, VecDeque};
+
+/// The outcome of [`explore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exploration {
+    /// The number of schedules that were run and checked.
+    pub schedules: usize,
+    /// Whether all schedules that are not equivalent to one of these were run, i.e. the
+    /// exploration finished before reaching its bound.
+    pub exhaustive: bool,
+}
+
+/// Run a small stage graph under all of its non-equivalent schedules, up to `max_schedules`
+/// of them, and check the outcome of each, e.g. that chain selection ends up on the same tip
+/// regardless of the order in which its inputs arrive.
+///
+/// Each schedule starts from a fresh simulation returned by `build`, which is then run with
+/// [`run_until_blocked`](SimulationRunning::run_until_blocked), choosing another one of the
+/// runnable stages to take the next step than in the schedules before. Once blocked, the
+/// simulation is passed to `check`, whose error is returned with the schedule that led to
+/// it, i.e. the names of the stages in the order in which they took their steps.
+///
+/// Two schedules are equivalent if they only differ in the order of steps that don’t
+/// interfere with each other, as tracked by dynamic partial-order reduction: only steps that
+/// touch the same mailbox — sending to it or receiving from it — or the same key of the
+/// [store](crate::Store), or that both make [external](crate::ExternalEffect) requests are
+/// reordered.
+///
+/// The simulations must be deterministic, i.e. `build` needs to set up the same stages with
+/// the same inputs each time, and they must eventually block.
+pub fn explore(
+    max_schedules: usize,
+    mut build: impl FnMut() -> SimulationRunning,
+    mut check: impl FnMut(&mut SimulationRunning, Blocked) -> anyhow::Result<()>,
+) -> anyhow::Result<Exploration> {
+    let mut stack = Vec::<Node>::new();
+    let mut schedules = 0;
+    loop {
+        if schedules == max_schedules {
+            return Ok(Exploration {
+                schedules,
+                exhaustive: false,
+            });
+        }
+
+        let mut sim = build();
+        sim.explorer = Some(Explorer::new(
+            stack.iter().map(|node| node.chosen.clone()).collect(),
+        ));
+        let blocked = sim.run_until_blocked();
+        let explorer = sim.explorer.take().expect("explorer was set above");
+        schedules += 1;
+        check(&mut sim, blocked).with_context(|| {
+            let schedule = explorer.steps.iter().map(|step| step.stage.as_str());
+            format!("in schedule {}", schedule.collect::<Vec<_>>().join(", "))
+        })?;
+
+        for step in &explorer.steps[stack.len()..] {
+            stack.push(Node {
+                enabled: step.enabled.clone(),
+                chosen: step.stage.clone(),
+                done: BTreeSet::from([step.stage.clone()]),
+                backtrack: BTreeSet::new(),
+            });
+        }
+        add_backtracks(&explorer.steps, &mut stack);
+
+        loop {
+            let Some(node) = stack.last_mut() else {
+                return Ok(Exploration {
+                    schedules,
+                    exhaustive: true,
+                });
+            };
+            if let Some(next) = node.backtrack.difference(&node.done).next().cloned() {
+                node.done.insert(next.clone());
+                node.chosen = next;
+                break;
+            }
+            stack.pop();
+        }
+    }
+}
+
+/// A step of the schedule currently explored, with the alternatives still to be tried.
+struct Node {
+    enabled: BTreeSet<Name>,
+    chosen: Name,
+    done: BTreeSet<Name>,
+    backtrack: BTreeSet<Name>,
+}
+
+/// For each step, find the last step of another stage that interferes with it and make sure
+/// the schedule is also explored with this step’s stage going first at that point, or any
+/// of the stages that were runnable there if this one wasn’t.
+fn add_backtracks(steps: &[Step], stack: &mut [Node]) {
+    for (idx, step) in steps.iter().enumerate() {
+        let Some(touches) = &step.touches else {
+            continue;
+        };
+        let Some(earlier) = steps[..idx].iter().rposition(|other| {
+            other.stage != step.stage && other.touches.as_ref() == Some(touches)
+        }) else {
+            continue;
+        };
+        let node = &mut stack[earlier];
+        if node.enabled.contains(&step.stage) {
+            node.backtrack.insert(step.stage.clone());
+        } else {
+            node.backtrack.extend(node.enabled.iter().cloned());
+        }
+    }
+}
+
+/// What a step touches that other stages’ steps may touch as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Touches {
+    Mailbox(Name),
+    External,
+    Key(String),
+}
+
+#[derive(Debug)]
+struct Step {
+    stage: Name,
+    enabled: BTreeSet<Name>,
+    touches: Option<Touches>,
+}
+
+/// Chooses the stage to take the next step while exploring schedules, see [`explore`].
+#[derive(Debug)]
+pub(super) struct Explorer {
+    prefix: Vec<Name>,
+    steps: Vec<Step>,
+}
+
+impl Explorer {
+    fn new(prefix: Vec<Name>) -> Self {
+        Self {
+            prefix,
+            steps: Vec::new(),
+        }
+    }
+
+    /// The index of the runnable stage to take the next step: the next one of the prefix,
+    /// then the first one by name.
+    pub(super) fn choose(&mut self, runnable: &VecDeque<(Name, StageResponse)>) -> usize {
+        let enabled = runnable
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect::<BTreeSet<_>>();
+        let stage = match self.prefix.get(self.steps.len()) {
+            Some(stage) => {
+                assert!(
+                    enabled.contains(stage),
+                    "stage `{stage}` is not runnable when replaying a schedule, is the simulation deterministic?"
+                );
+                stage.clone()
+            }
+            None => match enabled.first() {
+                Some(stage) => stage.clone(),
+                None => return 0,
+            },
+        };
+        let idx = runnable
+            .iter()
+            .position(|(name, _)| name == &stage)
+            .expect("stage is runnable");
+        self.steps.push(Step {
+            stage,
+            enabled,
+            touches: None,
+        });
+        idx
+    }
+
+    /// Record what the step just taken touched, given its effect.
+    pub(super) fn took(&mut self, effect: &Effect) {
+        let touches = match effect {
+            Effect::Receive { at_stage }
+            | Effect::ReceiveMatching { at_stage }
+            | Effect::ReceiveQueued { at_stage, .. }
+            | Effect::Schedule { at_stage, .. }
+            | Effect::Termination { at_stage } => Some(Touches::Mailbox(at_stage.clone())),
+            Effect::Send { to, .. } => Some(Touches::Mailbox(to.clone())),
+            Effect::Respond { target, .. } => Some(Touches::Mailbox(target.clone())),
+            Effect::External { .. } => Some(Touches::External),
+            Effect::Persist { key, .. } | Effect::Load { key, .. } => {
+                Some(Touches::Key(key.clone()))
+            }
+            Effect::Clock { .. }
+            | Effect::Wait { .. }
+            | Effect::Fork { .. }
+            | Effect::Join { .. }
+            | Effect::Interrupt { .. }
+            | Effect::Failure { .. } => None,
+        };
+        if let Some(step) = self.steps.last_mut() {
+            step.touches = touches;
+        }
+    }
+}
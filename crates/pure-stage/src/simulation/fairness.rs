@@ -0,0 +1,66 @@
+use crate::{effect::StageResponse, Name};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+/// How often a stage took a step, and how long it waited for its turn while runnable, see
+/// [`Fairness`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StageFairness {
+    /// The number of steps taken by the stage.
+    pub scheduled: u64,
+    /// The number of steps taken by other stages since this one became runnable, zero if it
+    /// is not runnable.
+    pub waiting: u64,
+    /// The largest number of steps taken by other stages while this one was runnable.
+    pub longest_wait: u64,
+}
+
+/// The scheduling of the stages of a simulation, to catch stages that are starved by the
+/// [`Scheduler`](super::Scheduler) or by a test stepping through the simulation by hand, see
+/// [`SimulationRunning::assert_fair_within`](super::SimulationRunning::assert_fair_within).
+#[derive(Debug, Clone, Default)]
+pub struct Fairness {
+    stages: BTreeMap<Name, StageFairness>,
+}
+
+impl Fairness {
+    /// Record that `chosen` took a step while the stages in `runnable` waited for their turn.
+    pub(super) fn took(&mut self, chosen: &Name, runnable: &VecDeque<(Name, StageResponse)>) {
+        for (name, _) in runnable {
+            self.stages.entry(name.clone()).or_default();
+        }
+        for (name, stage) in &mut self.stages {
+            if name == chosen {
+                continue;
+            }
+            if runnable.iter().any(|(n, _)| n == name) {
+                stage.waiting += 1;
+                stage.longest_wait = stage.longest_wait.max(stage.waiting);
+            } else {
+                stage.waiting = 0;
+            }
+        }
+        let stage = self.stages.entry(chosen.clone()).or_default();
+        stage.scheduled += 1;
+        stage.waiting = 0;
+    }
+
+    pub fn stage(&self, name: impl AsRef<str>) -> Option<&StageFairness> {
+        self.stages.get(&Name::from(name.as_ref()))
+    }
+
+    /// All stages that took a step or waited for one, by name.
+    pub fn stages(&self) -> impl Iterator<Item = (&Name, &StageFairness)> + '_ {
+        self.stages.iter()
+    }
+
+    /// The stage that waited longest for its turn, and for how many steps, the first one by
+    /// name in case of a tie.
+    pub fn most_starved(&self) -> Option<(&Name, u64)> {
+        self.stages
+            .iter()
+            .map(|(name, stage)| (name, stage.longest_wait))
+            .rev()
+            .max_by_key(|(_, wait)| *wait)
+    }
+}
@@ -1,9 +1,12 @@
+use std::time::Duration;
 use tokio::sync::mpsc::{error::TryRecvError, UnboundedReceiver};
 
 /// The message receptacle used by [`SimulationBuilder::output`](super::SimulationBuilder::output).
 ///
 /// It should be noted that [`Self::try_next`] returning `None` only means that the message
-/// queue is currently empty — it may be refilled by future simulation steps.
+/// queue is currently empty — it may be refilled by future simulation steps. The same holds
+/// for [`Self::recv_timeout`], while [`Self::recv`] waits for those steps, e.g. taken by another
+/// task.
 #[derive(Default, Debug)]
 pub struct Receiver<T> {
     rx: Option<UnboundedReceiver<T>>,
@@ -26,6 +29,25 @@ impl<T> Receiver<T> {
         }
     }
 
+    /// Wait for the next message, or `None` once the simulation with the output stage has been
+    /// dropped.
+    pub async fn recv(&mut self) -> Option<T> {
+        let msg = self.rx.as_mut()?.recv().await;
+        if msg.is_none() {
+            self.rx = None;
+        }
+        msg
+    }
+
+    /// Like [`Self::recv`], but give up and return `None` if no message arrives within
+    /// `timeout`.
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Option<T> {
+        tokio::time::timeout(timeout, self.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
     /// Produce an iterator over all messages currently enqueued.
     pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
         struct Iter<'a, T>(&'a mut Receiver<T>);
@@ -0,0 +1,174 @@
+use super::{InitStageData, InitStageState, StageData, StageEffect, StageState};
+use crate::{Instant, Name};
+use serde::Serialize;
+use std::time::Duration;
+
+/// A snapshot of all stages of a simulation, see [`SimulationRunning::report`] and
+/// [`SimulationBuilder::report`].
+///
+/// [`SimulationRunning::report`]: super::SimulationRunning::report
+/// [`SimulationBuilder::report`]: super::SimulationBuilder::report
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphReport {
+    /// The stages, ordered by name.
+    pub stages: Vec<StageReport>,
+    /// The number of wakeups pending on the simulated clock.
+    pub sleeping: usize,
+    /// The time until the next of these wakeups, if any.
+    pub next_wakeup: Option<Duration>,
+}
+
+impl GraphReport {
+    /// The report of the stage of the given name.
+    pub fn stage(&self, name: impl AsRef<str>) -> Option<&StageReport> {
+        self.stages
+            .iter()
+            .find(|stage| stage.name.as_str() == name.as_ref())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageReport {
+    pub name: Name,
+    pub state: StageStatus,
+    /// The number of messages in the mailbox.
+    pub mailbox: usize,
+    /// The stages suspended on sending to this one because its mailbox is full, in the order
+    /// in which they will be let in.
+    pub blocked_senders: Vec<Name>,
+    /// The effect the stage is suspended on, `None` if it is runnable, has failed
+    /// or the graph isn't running yet.
+    pub waiting_for: Option<WaitingFor>,
+}
+
+/// A message a stage received while one of higher [priority](crate::StageGraph::prioritize)
+/// was held back by its full mailbox, see
+/// [`priority_inversions`](super::SimulationRunning::priority_inversions).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PriorityInversion {
+    pub stage: Name,
+    /// The priority of the message the stage received.
+    pub received: u8,
+    /// The highest priority among the messages of blocked senders.
+    pub held_back: u8,
+}
+
+/// The state of a stage, without its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    /// The stage was created but not yet wired up.
+    Uninitialized,
+    /// The stage is waiting for its next message.
+    Idle,
+    /// The stage is processing a message.
+    Running,
+    Failed,
+    /// The stage has stopped, see [`Effects::terminate`](crate::Effects::terminate).
+    Terminated,
+}
+
+/// The effect a stage is suspended on, without the messages it carries.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitingFor {
+    Receive,
+    ReceiveMatching,
+    ReceiveQueued {
+        max: usize,
+    },
+    Send {
+        to: Name,
+        call: bool,
+    },
+    Clock,
+    Wait {
+        duration: Duration,
+    },
+    Schedule {
+        after: Duration,
+    },
+    /// Waiting for the response of `target`, timing out in `timeout_in`.
+    Call {
+        target: Name,
+        timeout_in: Duration,
+    },
+    Respond {
+        target: Name,
+    },
+    External,
+    Interrupt,
+    Persist {
+        key: String,
+    },
+    Load {
+        key: String,
+    },
+    Fork,
+    Join,
+}
+
+impl WaitingFor {
+    fn new(effect: &StageEffect<()>, now: Instant) -> Self {
+        match effect {
+            StageEffect::Receive => WaitingFor::Receive,
+            StageEffect::ReceiveMatching(_) => WaitingFor::ReceiveMatching,
+            StageEffect::ReceiveQueued(max) => WaitingFor::ReceiveQueued { max: *max },
+            StageEffect::Send(to, (), call) => WaitingFor::Send {
+                to: to.clone(),
+                call: call.is_some(),
+            },
+            StageEffect::Clock => WaitingFor::Clock,
+            StageEffect::Wait(duration) => WaitingFor::Wait {
+                duration: *duration,
+            },
+            StageEffect::Schedule((), after) => WaitingFor::Schedule { after: *after },
+            StageEffect::Call(target, deadline, (), _, _) => WaitingFor::Call {
+                target: target.clone(),
+                timeout_in: deadline.checked_since(now).unwrap_or_default(),
+            },
+            StageEffect::Respond(target, ..) => WaitingFor::Respond {
+                target: target.clone(),
+            },
+            StageEffect::External(()) => WaitingFor::External,
+            StageEffect::Interrupt((), _) => WaitingFor::Interrupt,
+            StageEffect::Persist(key, _) => WaitingFor::Persist { key: key.clone() },
+            StageEffect::Load(key) => WaitingFor::Load { key: key.clone() },
+            StageEffect::Fork(..) => WaitingFor::Fork,
+            StageEffect::Join(_) => WaitingFor::Join,
+        }
+    }
+}
+
+impl StageReport {
+    pub(super) fn new(data: &StageData, now: Instant) -> Self {
+        Self {
+            name: data.name.clone(),
+            state: match data.state {
+                StageState::Idle(_) => StageStatus::Idle,
+                StageState::Running(_) => StageStatus::Running,
+                StageState::Failed => StageStatus::Failed,
+                StageState::Terminated => StageStatus::Terminated,
+            },
+            mailbox: data.mailbox.len(),
+            blocked_senders: data.senders.iter().map(|(name, ..)| name.clone()).collect(),
+            waiting_for: data
+                .waiting
+                .as_ref()
+                .map(|effect| WaitingFor::new(effect, now)),
+        }
+    }
+
+    pub(super) fn new_init(name: &Name, data: &InitStageData) -> Self {
+        Self {
+            name: name.clone(),
+            state: match data.state {
+                InitStageState::Uninitialized => StageStatus::Uninitialized,
+                InitStageState::Idle(_) => StageStatus::Idle,
+            },
+            mailbox: data.mailbox.len(),
+            blocked_senders: Vec::new(),
+            waiting_for: None,
+        }
+    }
+}
@@ -1,8 +1,27 @@
-use super::{EffectBox, Instant, StageData, StageEffect, StageResponse, StageState};
-use crate::{cast_state, stagegraph::CallRef, CallId, Effect, Message, Name, StageRef, State};
+use super::{
+    causality::{Causality, MsgId},
+    explore::Explorer,
+    fairness::Fairness,
+    highest_priority,
+    scheduler::Scheduler,
+    stage_span,
+    state::{Cloner, ExternalHandler, Mailbox},
+    trace::{Replay, Trace, TraceStep},
+    transition, EffectBox, GraphReport, Instant, PriorityInversion, StageData, StageEffect,
+    StageReport, StageResponse, StageState,
+};
+use crate::{
+    cast_msg_ref, cast_state, stagegraph::CallRef, wiring::Wiring, CallId, DeadLetter,
+    DeadLetterReason, Effect, Effects, MemoryStore, Message, Metrics, Name, ShutdownPolicy,
+    StageRef, State, Store,
+};
 use either::Either::{Left, Right};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
 use std::{
-    collections::{BinaryHeap, HashMap, VecDeque},
+    any::{Any, TypeId},
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, VecDeque},
+    future::Future,
     mem::{replace, take},
     sync::{
         atomic::{AtomicU64, Ordering},
@@ -12,9 +31,10 @@ use std::{
     time::Duration,
 };
 use tokio::sync::oneshot::{Receiver, Sender};
+use tracing::{Instrument, Span};
 
 /// Classification of why [`SimulationRunning::run_until_blocked`] has stopped.
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub enum Blocked {
     /// All stages are suspended on [`Effect::Receive`].
     Idle,
@@ -22,11 +42,26 @@ pub enum Blocked {
     Sleeping,
     /// All stages are suspended on either [`Effect::Receive`] or [`Effect::Send`].
     Deadlock(Vec<Name>),
-    /// The given stage interrupted the simulation.
-    Interrupted(Name),
+    /// Like [`Blocked::Deadlock`], but the given stages are suspended on sending to each other in
+    /// a cycle: each one to the full mailbox of the next one, the last one to the first one,
+    /// starting with the smallest name.
+    DeadlockCycle(Vec<Name>),
+    /// The given stage interrupted the simulation, handing over the payload; resume it with
+    /// [`SimulationRunning::resume_interrupt_with`] to pass a decision back.
+    Interrupted(Name, Box<dyn Message>),
+    /// Stage `from` is suspended on sending to `to`, whose mailbox is full.
+    ///
+    /// This is only reported when enabled with
+    /// [`with_backpressure_stops`](crate::simulation::SimulationBuilder::with_backpressure_stops);
+    /// running the simulation again resumes it, letting the message in once `to` has
+    /// received from its mailbox.
+    Backpressure { from: Name, to: Name },
     /// The given stages are suspended on effects other than [`Effect::Receive`]
     /// while none are suspended on [`Effect::Send`].
     Busy(Vec<Name>),
+    /// The simulation ran out of fuel, see [`SimulationRunning::run_until_blocked_with_fuel`],
+    /// while the given stages kept sending messages around in cycles.
+    Livelock(Vec<Name>),
 }
 
 impl Blocked {
@@ -46,22 +81,45 @@ impl Blocked {
         }
     }
 
-    /// Assert that the blocking reason is `Deadlock` by at least the given stages.
+    /// Assert that the blocking reason is `Deadlock` or `DeadlockCycle` by at least the given
+    /// stages.
     pub fn assert_deadlock(&self, names: impl IntoIterator<Item = impl AsRef<str>>) {
         let names = names
             .into_iter()
             .map(|n| Name::from(n.as_ref()))
             .collect::<Vec<_>>();
         match self {
-            Blocked::Deadlock(deadlock) if deadlock.iter().all(|n| names.contains(n)) => {}
+            Blocked::Deadlock(deadlock) | Blocked::DeadlockCycle(deadlock)
+                if deadlock.iter().all(|n| names.contains(n)) => {}
             _ => panic!("expected deadlock by {:?}, got {:?}", names, self),
         }
     }
 
+    /// Assert that the blocking reason is `DeadlockCycle` by exactly the given stages, each
+    /// one sending to the next one, starting with any of them.
+    pub fn assert_deadlock_cycle(&self, names: impl IntoIterator<Item = impl AsRef<str>>) {
+        let mut names = names
+            .into_iter()
+            .map(|n| Name::from(n.as_ref()))
+            .collect::<Vec<_>>();
+        if let Some(first) = names
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, n)| *n)
+            .map(|(i, _)| i)
+        {
+            names.rotate_left(first);
+        }
+        match self {
+            Blocked::DeadlockCycle(cycle) if cycle == &names => {}
+            _ => panic!("expected deadlock cycle {:?}, got {:?}", names, self),
+        }
+    }
+
     /// Assert that the blocking reason is `Interrupted` by the given stage.
     pub fn assert_interrupted(&self, name: impl AsRef<str>) {
         match self {
-            Blocked::Interrupted(n) if n.as_str() == name.as_ref() => {}
+            Blocked::Interrupted(n, _payload) if n.as_str() == name.as_ref() => {}
             _ => panic!(
                 "expected interrupted by `{}`, got {:?}",
                 name.as_ref(),
@@ -70,6 +128,28 @@ impl Blocked {
         }
     }
 
+    /// The payload of an `Interrupted` blocking reason, if it has the given type.
+    pub fn interrupt_payload<P: Message>(&self) -> Option<&P> {
+        match self {
+            Blocked::Interrupted(_name, payload) => cast_msg_ref::<P>(&**payload).ok(),
+            _ => None,
+        }
+    }
+
+    /// Assert that the blocking reason is `Backpressure` of `from` sending to `to`.
+    pub fn assert_backpressure(&self, from: impl AsRef<str>, to: impl AsRef<str>) {
+        match self {
+            Blocked::Backpressure { from: f, to: t }
+                if f.as_str() == from.as_ref() && t.as_str() == to.as_ref() => {}
+            _ => panic!(
+                "expected backpressure from `{}` to `{}`, got {:?}",
+                from.as_ref(),
+                to.as_ref(),
+                self
+            ),
+        }
+    }
+
     /// Assert that the blocking reason is `Busy` by at least the given stages.
     pub fn assert_busy(&self, names: impl IntoIterator<Item = impl AsRef<str>>) {
         let names = names
@@ -81,6 +161,46 @@ impl Blocked {
             _ => panic!("expected busy by {:?}, got {:?}", names, self),
         }
     }
+
+    /// Assert that the blocking reason is `Livelock` by exactly the given stages.
+    pub fn assert_livelock(&self, names: impl IntoIterator<Item = impl AsRef<str>>) {
+        let mut names = names
+            .into_iter()
+            .map(|n| Name::from(n.as_ref()))
+            .collect::<Vec<_>>();
+        names.sort();
+        match self {
+            Blocked::Livelock(livelock) if livelock == &names => {}
+            _ => panic!("expected livelock by {:?}, got {:?}", names, self),
+        }
+    }
+}
+
+impl PartialEq for Blocked {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Blocked::Idle, Blocked::Idle) => true,
+            (Blocked::Sleeping, Blocked::Sleeping) => true,
+            (Blocked::Deadlock(names), Blocked::Deadlock(other_names)) => names == other_names,
+            (Blocked::DeadlockCycle(names), Blocked::DeadlockCycle(other_names)) => {
+                names == other_names
+            }
+            (
+                Blocked::Interrupted(name, payload),
+                Blocked::Interrupted(other_name, other_payload),
+            ) => name == other_name && payload.eq(&**other_payload),
+            (
+                Blocked::Backpressure { from, to },
+                Blocked::Backpressure {
+                    from: other_from,
+                    to: other_to,
+                },
+            ) => from == other_from && to == other_to,
+            (Blocked::Busy(names), Blocked::Busy(other_names)) => names == other_names,
+            (Blocked::Livelock(names), Blocked::Livelock(other_names)) => names == other_names,
+            _ => false,
+        }
+    }
 }
 
 /// An entry for the sleeping stage heap.
@@ -124,6 +244,17 @@ impl PartialOrd for Sleeping {
     }
 }
 
+/// The states and mailboxes of all stages of a simulation, along with its clock and random
+/// generator, taken by
+/// [`SimulationRunning::snapshot`] and brought back by [`SimulationRunning::restore`].
+#[derive(Debug)]
+pub struct Snapshot {
+    /// The state of each stage, `None` if it failed, and its mailbox.
+    stages: HashMap<Name, (Option<Box<dyn State>>, Mailbox)>,
+    clock: u64,
+    rng: StdRng,
+}
+
 /// A handle to a running [`SimulationBuilder`](crate::simulation::SimulationBuilder).
 ///
 /// It allows fine-grained control over single-stepping the simulation and when each
@@ -140,29 +271,60 @@ pub struct SimulationRunning {
     effect: EffectBox,
     clock: Arc<AtomicU64>,
     now: Arc<dyn Fn() -> Instant + Send + Sync>,
+    rng: Arc<Mutex<StdRng>>,
+    externals: HashMap<TypeId, ExternalHandler>,
+    wiring: Wiring,
     runnable: VecDeque<(Name, StageResponse)>,
     sleeping: BinaryHeap<Sleeping>,
     responded: Vec<(Name, CallId)>,
     mailbox_size: usize,
+    backpressure_stops: bool,
+    dead_letters: Option<Name>,
+    store: MemoryStore,
+    scheduler: Scheduler,
+    pub(super) explorer: Option<Explorer>,
+    trace: Option<Trace>,
+    replay: Option<Replay>,
+    causality: Causality,
+    fairness: Fairness,
 }
 
 impl SimulationRunning {
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
         stages: HashMap<Name, StageData>,
         effect: EffectBox,
         clock: Arc<AtomicU64>,
         now: Arc<dyn Fn() -> Instant + Send + Sync>,
+        rng: Arc<Mutex<StdRng>>,
+        externals: HashMap<TypeId, ExternalHandler>,
+        wiring: Wiring,
         mailbox_size: usize,
+        backpressure_stops: bool,
+        dead_letters: Option<Name>,
+        store: MemoryStore,
     ) -> Self {
         Self {
             stages,
             effect,
             clock,
             now,
+            rng,
+            externals,
+            wiring,
             runnable: VecDeque::new(),
             sleeping: BinaryHeap::new(),
             responded: Vec::new(),
             mailbox_size,
+            backpressure_stops,
+            dead_letters,
+            store,
+            scheduler: Scheduler::Fifo,
+            explorer: None,
+            trace: None,
+            replay: None,
+            causality: Causality::default(),
+            fairness: Fairness::default(),
         }
     }
 
@@ -170,6 +332,16 @@ impl SimulationRunning {
         (self.now)()
     }
 
+    /// The time the clock of the given stage shows, which differs from [`now`](Self::now)
+    /// if it is [skewed](super::SimulationBuilder::clock_skew).
+    pub fn now_at<Msg, St>(&self, stage: &StageRef<Msg, St>) -> Instant {
+        let data = self
+            .stages
+            .get(&stage.name)
+            .expect("stage ref exists, so stage must exist");
+        (data.now)()
+    }
+
     pub fn skip_to_next_wakeup(&mut self) -> bool {
         let Some(Sleeping { time, .. }) = self.sleeping.peek() else {
             return false;
@@ -184,6 +356,29 @@ impl SimulationRunning {
         true
     }
 
+    /// The time at which the next sleeping stage is due to wake up, if any.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        let Sleeping { time, .. } = self.sleeping.peek()?;
+        let now = self.clock.load(Ordering::Relaxed);
+        self.now().checked_add(Duration::from_nanos(time - now))
+    }
+
+    /// Move the clock forward to the given time without performing any wakeups.
+    ///
+    /// This is meant for driving the simulation from an outer clock, performing the wakeups
+    /// due until then with [`Self::skip_to_next_wakeup`] first, so it panics if a wakeup is
+    /// scheduled before or at `target`. Times in the past are ignored.
+    pub fn advance_clock_to(&mut self, target: Instant) {
+        let Some(delta) = target.checked_since(self.now()) else {
+            return;
+        };
+        if let Some(wakeup) = self.next_wakeup() {
+            assert!(wakeup > target, "cannot advance clock past next wakeup");
+        }
+        let nanos = u64::try_from(delta.as_nanos()).expect("clock wrapped around");
+        self.clock.fetch_add(nanos, Ordering::Relaxed);
+    }
+
     fn schedule_wakeup(
         &mut self,
         nanos: u64,
@@ -200,15 +395,81 @@ impl SimulationRunning {
 
     /// Place messages in the given stage’s mailbox, but don’t resume it.
     /// The next message will be consumed when resuming an [`Effect::Receive`]
-    /// for this stage.
+    /// for this stage, within a span that is a child of the current one.
     pub fn enqueue_msg<T: Message, St>(
         &mut self,
         sr: &StageRef<T, St>,
         msg: impl IntoIterator<Item = T>,
     ) {
         let data = self.stages.get_mut(&sr.name).unwrap();
-        data.mailbox
-            .extend(msg.into_iter().map(|m| Box::new(m) as Box<dyn Message>));
+        for msg in msg {
+            let msg = sr.adapt(Box::new(msg));
+            let id = self.causality.record(None, None, sr.name(), &*msg);
+            data.mailbox.push_back((msg, Span::current(), id));
+        }
+    }
+
+    /// The messages sent so far and which message caused each of them.
+    pub fn causality(&self) -> &Causality {
+        &self.causality
+    }
+
+    /// How often each stage took a step so far, and how long runnable stages waited for it.
+    pub fn fairness(&self) -> &Fairness {
+        &self.fairness
+    }
+
+    /// Assert that no stage has been runnable for more than `steps` steps taken by other
+    /// stages without taking one itself.
+    pub fn assert_fair_within(&self, steps: u64) {
+        if let Some((name, wait)) = self.fairness.most_starved() {
+            if wait > steps {
+                panic!(
+                    "stage `{name}` was starved for {wait} steps, more than {steps}\n  fairness: {:?}",
+                    self.fairness
+                );
+            }
+        }
+    }
+
+    /// The messages received by stages while ones of higher priority were held back by their
+    /// full mailboxes, by stage and then in the order they were received.
+    ///
+    /// Tests of [prioritized](crate::StageGraph::prioritize) stages can assert that this is
+    /// empty, i.e. that their mailboxes are large enough for the priorities to take effect.
+    pub fn priority_inversions(&self) -> Vec<PriorityInversion> {
+        let mut stages = self.stages.values().collect::<Vec<_>>();
+        stages.sort_by(|a, b| a.name.cmp(&b.name));
+        stages
+            .into_iter()
+            .flat_map(|data| data.inversions.iter().cloned())
+            .collect()
+    }
+
+    /// Render the stages as a DOT digraph like [`StageGraph::export_dot`], adding the sends
+    /// seen so far.
+    ///
+    /// [`StageGraph::export_dot`]: crate::StageGraph::export_dot
+    pub fn export_dot(&self) -> String {
+        self.wiring.to_dot()
+    }
+
+    /// The store answering the [`persist`](crate::Effects::persist) and
+    /// [`load`](crate::Effects::load) effects, see
+    /// [`SimulationBuilder::store`](super::SimulationBuilder::store).
+    pub fn store(&self) -> MemoryStore {
+        self.store.clone()
+    }
+
+    /// The metrics of all stages so far, with processing times on the simulated clock.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            stages: self
+                .stages
+                .iter()
+                .map(|(name, data)| (name.clone(), data.metrics.clone()))
+                .collect(),
+        }
     }
 
     /// Retrieve the number of messages currently in the given stage’s mailbox.
@@ -229,6 +490,159 @@ impl SimulationRunning {
         }
     }
 
+    /// Like [`Self::get_state`], but for a stage known only by name, e.g. from a
+    /// [`StageRef`] without its state type, so the type of the state is checked at runtime.
+    ///
+    /// Fails if there is no such stage or its state is not an `St`, returns `None` while the
+    /// stage is not suspended on an [`Effect::Receive`].
+    pub fn get_state_as<St: State>(&self, name: impl AsRef<str>) -> anyhow::Result<Option<&St>> {
+        let name = name.as_ref();
+        let Some(data) = self.stages.get(&Name::from(name)) else {
+            anyhow::bail!("no stage named `{name}`");
+        };
+        match &data.state {
+            StageState::Idle(state) => cast_state(&**state).map(Some),
+            StageState::Running(_) | StageState::Failed | StageState::Terminated => Ok(None),
+        }
+    }
+
+    /// Replace the transition function of the given stage, keeping its state and mailbox, e.g.
+    /// to make it misbehave from some message on, or to compare variants of it within one
+    /// simulation.
+    ///
+    /// A transition in progress is finished by the old function, the messages received
+    /// afterwards are processed by the new one.
+    pub fn replace_transition<Msg: Message, St: State, F, Fut>(
+        &mut self,
+        stage: &StageRef<Msg, St>,
+        f: F,
+    ) where
+        F: FnMut(St, Msg, Effects<Msg, St>) -> Fut + 'static + Send,
+        Fut: Future<Output = anyhow::Result<St>> + 'static + Send,
+    {
+        let data = self
+            .stages
+            .get_mut(&stage.name)
+            .expect("stage ref exists, so stage must exist");
+        let effects = Effects::new(
+            StageRef::new(stage.name()),
+            self.effect.clone(),
+            data.now.clone(),
+            self.rng.clone(),
+            data.terminating.clone(),
+        );
+        data.transition = transition(f, effects);
+    }
+
+    /// Report the state of all stages, their mailboxes and the effects they are suspended on,
+    /// e.g. to find out why a simulation is stuck.
+    pub fn report(&self) -> GraphReport {
+        let now = self.now();
+        let mut stages = self
+            .stages
+            .values()
+            .map(|data| StageReport::new(data, now))
+            .collect::<Vec<_>>();
+        stages.sort_by(|a, b| a.name.cmp(&b.name));
+        GraphReport {
+            stages,
+            sleeping: self.sleeping.len(),
+            next_wakeup: self
+                .next_wakeup()
+                .map(|wakeup| wakeup.checked_since(now).unwrap_or_default()),
+        }
+    }
+
+    /// Copy the states and mailboxes of all stages, to restore them later with
+    /// [`Self::restore`], e.g. to explore several interleavings from a common prefix.
+    ///
+    /// This requires [`enable_snapshots`](crate::simulation::SimulationBuilder::enable_snapshots)
+    /// for each stage, and that no stage is running nor any wakeup pending: the suspended
+    /// computation of a stage cannot be copied. A simulation that is [`Blocked::Idle`] can thus
+    /// be snapshotted. Note that the messages already forwarded to a [`Receiver`](super::Receiver)
+    /// are not part of the snapshot.
+    pub fn snapshot(&self) -> anyhow::Result<Snapshot> {
+        if !self.sleeping.is_empty() {
+            anyhow::bail!(
+                "cannot snapshot while {} wakeups are pending",
+                self.sleeping.len()
+            );
+        }
+        let mut stages = HashMap::new();
+        for (name, data) in &self.stages {
+            let Some(cloner) = data.cloner else {
+                anyhow::bail!("stage `{name}` does not support snapshots");
+            };
+            let state = match &data.state {
+                StageState::Idle(state) => Some((cloner.state)(&**state)),
+                StageState::Running(_) => anyhow::bail!("cannot snapshot running stage `{name}`"),
+                StageState::Terminated => {
+                    anyhow::bail!("cannot snapshot terminated stage `{name}`")
+                }
+                StageState::Failed => None,
+            };
+            if !data.forks.is_empty() {
+                anyhow::bail!("cannot snapshot stage `{name}` with sub-tasks that were not joined");
+            }
+            stages.insert(name.clone(), (state, clone_mailbox(&data.mailbox, cloner)));
+        }
+        Ok(Snapshot {
+            stages,
+            clock: self.clock.load(Ordering::Relaxed),
+            rng: self.rng.lock().clone(),
+        })
+    }
+
+    /// Bring all stages back to the given snapshot, which can be restored any number of times,
+    /// dropping whatever they were doing meanwhile.
+    ///
+    /// The snapshot must have been taken of a simulation with the same stages, usually this one.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        for name in self.stages.keys() {
+            if !snapshot.stages.contains_key(name) {
+                anyhow::bail!("stage `{name}` is missing from the snapshot");
+            }
+        }
+        for name in snapshot.stages.keys() {
+            let Some(data) = self.stages.get(name) else {
+                anyhow::bail!("snapshot contains unknown stage `{name}`");
+            };
+            if data.cloner.is_none() {
+                anyhow::bail!("stage `{name}` does not support snapshots");
+            }
+        }
+
+        // it is important that all validations (i.e. `?``) happen before this point
+        for (name, (state, mailbox)) in &snapshot.stages {
+            let data = self.stages.get_mut(name).expect("checked above");
+            let cloner = data.cloner.expect("checked above");
+            data.mailbox = clone_mailbox(mailbox, cloner);
+            data.senders.clear();
+            data.span = Span::none();
+            data.processing = None;
+            data.received_at = None;
+            data.forks.clear();
+            data.terminating.store(false, Ordering::Relaxed);
+            match state {
+                Some(state) => {
+                    data.state = StageState::Idle((cloner.state)(&**state));
+                    data.waiting = Some(StageEffect::Receive);
+                }
+                None => {
+                    data.state = StageState::Failed;
+                    data.waiting = None;
+                }
+            }
+        }
+        self.runnable.clear();
+        self.sleeping.clear();
+        self.responded.clear();
+        *self.effect.lock() = None;
+        self.clock.store(snapshot.clock, Ordering::Relaxed);
+        *self.rng.lock() = snapshot.rng.clone();
+        Ok(())
+    }
+
     /// Assert that a simulation step can be taken, take it and return the resulting effect.
     pub fn effect(&mut self) -> Effect {
         self.try_effect().unwrap()
@@ -239,12 +653,18 @@ impl SimulationRunning {
     /// and needs more inputs, it could be deadlocked, or a stage is still suspended on an
     /// effect other than send — the latter case is called “busy” for want of a better term).
     pub fn try_effect(&mut self) -> Result<Effect, Blocked> {
-        let Some((name, response)) = self.runnable.pop_front() else {
+        let idx = match (&mut self.replay, &mut self.explorer) {
+            (Some(replay), _) => replay.choose(&self.runnable),
+            (None, Some(explorer)) => explorer.choose(&self.runnable),
+            (None, None) => self.scheduler.choose(&self.runnable),
+        };
+        let Some((name, response)) = self.runnable.remove(idx) else {
             let reason = block_reason(self);
             tracing::info!("blocking for reason: {:?}", reason);
             return Err(reason);
         };
         tracing::info!("resuming stage: {}", name);
+        self.fairness.took(&name, &self.runnable);
 
         let data = self
             .stages
@@ -257,11 +677,23 @@ impl SimulationRunning {
             );
         };
 
+        // the first poll after receiving a message starts its processing
+        let now = (self.now)();
+        let received_at = *data.received_at.get_or_insert(now);
+
         *self.effect.lock() = Some(Right(response));
         let result = pin.as_mut().poll(&mut Context::from_waker(Waker::noop()));
 
+        let mut terminated = None;
         let ret = if let Poll::Ready(result) = result {
+            data.received_at = None;
+            let elapsed = now.checked_since(received_at).unwrap_or_default();
+            data.metrics.processed(elapsed, result.is_ok());
             match result {
+                Ok(state) if data.terminating.load(Ordering::Relaxed) => {
+                    terminated = Some((name.clone(), state));
+                    Ok(Effect::Termination { at_stage: name })
+                }
                 Ok(state) => {
                     data.state = StageState::Idle(state);
                     data.waiting = Some(StageEffect::Receive);
@@ -291,6 +723,21 @@ impl SimulationRunning {
             data.waiting = Some(wait_effect);
             Ok(effect)
         };
+        if let Some((name, state)) = terminated {
+            self.terminate_stage(&name, Some(state));
+        }
+        if let (Some(explorer), Ok(effect)) = (&mut self.explorer, &ret) {
+            explorer.took(effect);
+        }
+        if let Ok(effect) = &ret {
+            let clock = self.clock.load(Ordering::Relaxed);
+            if let Some(trace) = &mut self.trace {
+                trace.steps.push(TraceStep::new(effect, clock));
+            }
+            if let Some(replay) = &mut self.replay {
+                replay.took(effect, clock);
+            }
+        }
 
         let names = take(&mut self.responded);
         let runnable = &mut self.runnable;
@@ -316,34 +763,197 @@ impl SimulationRunning {
     /// See [`Self::run_until_sleeping_or_blocked`] for a variant that stops when the simulation is
     /// waiting for a wakeup.
     pub fn run_until_blocked(&mut self) -> Blocked {
-        loop {
+        self.run_until_blocked_internal(None)
+    }
+
+    /// Like [`Self::run_until_blocked`], but give up after performing `fuel` effects, so that a
+    /// test of a graph whose stages never stop sending messages to each other fails instead of
+    /// hanging.
+    ///
+    /// Running out of fuel yields [`Blocked::Livelock`] with the stages that kept sending
+    /// messages around in cycles during the second half of the run, e.g. two stages bouncing a
+    /// message back and forth, or a stage scheduling messages to itself.
+    pub fn run_until_blocked_with_fuel(&mut self, fuel: usize) -> Blocked {
+        self.run_until_blocked_internal(Some(&mut Fuel::new(fuel)))
+    }
+
+    /// Like [`Self::run_until_blocked`], but pick the runnable stage that takes each step by
+    /// the given [`Scheduler`] instead of in the order in which the stages became runnable, to
+    /// stress the graph with unfavorable schedules.
+    ///
+    /// Steps taken afterwards with [`Self::try_effect`] are back in that order.
+    pub fn run_until_blocked_with_scheduler(&mut self, scheduler: Scheduler) -> Blocked {
+        self.scheduler = scheduler;
+        let blocked = self.run_until_blocked_internal(None);
+        self.scheduler = Scheduler::Fifo;
+        blocked
+    }
+
+    /// Stop all stages, handing their final states to their
+    /// [finalizers](crate::StageGraph::finalizer).
+    ///
+    /// With [`ShutdownPolicy::Drain`] the stages first process all queued messages, while with
+    /// [`ShutdownPolicy::FinishCurrent`] the idle stages stop right away and the others stop
+    /// once their current transition returns. In both cases the clock is advanced only as far
+    /// as needed for running transitions to return, pending scheduled messages are dropped.
+    ///
+    /// Returns why the last transitions stopped, e.g. [`Blocked::Busy`] with the stages that
+    /// were then stopped in the middle of a transition, without calling their finalizers.
+    pub fn shutdown(&mut self, policy: ShutdownPolicy) -> Blocked {
+        if policy == ShutdownPolicy::FinishCurrent {
+            let mut idle = Vec::new();
+            for (name, data) in &self.stages {
+                data.terminating.store(true, Ordering::Relaxed);
+                if matches!(data.state, StageState::Idle(_)) {
+                    idle.push(name.clone());
+                }
+            }
+            for name in idle {
+                let StageState::Idle(state) = replace(
+                    &mut self.stages.get_mut(&name).unwrap().state,
+                    StageState::Failed,
+                ) else {
+                    panic!("stage `{name}` must have been Idle");
+                };
+                self.terminate_stage(&name, Some(state));
+            }
+        }
+
+        let blocked = loop {
             match self.run_until_sleeping_or_blocked() {
+                Blocked::Sleeping
+                    if self
+                        .stages
+                        .values()
+                        .any(|data| matches!(data.state, StageState::Running(_))) =>
+                {
+                    self.skip_to_next_wakeup();
+                }
+                blocked => break blocked,
+            }
+        };
+
+        let names = self.stages.keys().cloned().collect::<Vec<_>>();
+        for name in names {
+            let data = self.stages.get_mut(&name).unwrap();
+            let state = match replace(&mut data.state, StageState::Terminated) {
+                StageState::Idle(state) => Some(state),
+                StageState::Running(_) => None,
+                StageState::Failed => {
+                    data.state = StageState::Failed;
+                    continue;
+                }
+                StageState::Terminated => continue,
+            };
+            self.terminate_stage(&name, state);
+        }
+        self.runnable.clear();
+        self.sleeping.clear();
+        self.responded.clear();
+        blocked
+    }
+
+    /// Start recording the steps taken from now on into a [`Trace`], dropping the ones
+    /// recorded so far.
+    pub fn record_trace(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    /// Stop recording and return the steps recorded since [`Self::record_trace`], if any.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    /// Like [`Self::run_until_blocked`], but let the stages take their steps in the order of
+    /// the given trace, e.g. one recorded under a [random](Scheduler::Random) schedule that
+    /// made a test fail, to debug it.
+    ///
+    /// The simulation needs to be set up like the recorded one, with the same inputs. Fails if
+    /// the steps taken deviate from the trace or don’t cover it, in which case the simulation
+    /// is still run until blocked, taking the steps after the deviation in the usual order.
+    pub fn replay(&mut self, trace: &Trace) -> anyhow::Result<Blocked> {
+        self.replay = Some(Replay::new(trace));
+        let blocked = self.run_until_blocked_internal(None);
+        let replay = self.replay.take().expect("replay was set above");
+        if let Some(divergence) = replay.divergence {
+            anyhow::bail!("{divergence}");
+        }
+        if replay.remaining() > 0 {
+            anyhow::bail!(
+                "replay blocked with {} steps of the trace left: {blocked:?}",
+                replay.remaining()
+            );
+        }
+        Ok(blocked)
+    }
+
+    fn run_until_blocked_internal(&mut self, mut fuel: Option<&mut Fuel>) -> Blocked {
+        loop {
+            match self.run_until_sleeping_or_blocked_internal(fuel.as_deref_mut()) {
                 Blocked::Sleeping => assert!(self.skip_to_next_wakeup()),
                 blocked => return blocked,
             }
         }
     }
 
+    /// Like [`Self::run_until_blocked`], advancing the clock to each wakeup while all stages
+    /// are waiting for one, but only up to `deadline`, so that a graph with periodic timers,
+    /// which never stops scheduling wakeups, can be run for a while.
+    ///
+    /// If the next wakeup is later than `deadline`, the clock is moved to `deadline` and
+    /// [`Blocked::Sleeping`] is returned.
+    pub fn run_until(&mut self, deadline: Instant) -> Blocked {
+        loop {
+            match self.run_until_sleeping_or_blocked() {
+                Blocked::Sleeping => match self.next_wakeup() {
+                    Some(wakeup) if wakeup <= deadline => {
+                        self.skip_to_next_wakeup();
+                    }
+                    _ => {
+                        self.advance_clock_to(deadline);
+                        return Blocked::Sleeping;
+                    }
+                },
+                blocked => return blocked,
+            }
+        }
+    }
+
     pub fn run_until_sleeping_or_blocked(&mut self) -> Blocked {
+        self.run_until_sleeping_or_blocked_internal(None)
+    }
+
+    fn run_until_sleeping_or_blocked_internal(&mut self, mut fuel: Option<&mut Fuel>) -> Blocked {
         {
             let runnable = &mut self.runnable;
             let run = &mut |name, response| {
                 runnable.push_back((name, response));
             };
             for data in self.stages.values_mut() {
-                let Some(StageEffect::Receive) = &data.waiting else {
-                    continue;
+                match &data.waiting {
+                    Some(StageEffect::Receive) => Self::resume_receive_internal(data, run).ok(),
+                    Some(StageEffect::ReceiveMatching(_)) => {
+                        Self::resume_receive_matching_internal(data, run).ok()
+                    }
+                    _ => None,
                 };
-                Self::resume_receive_internal(data, run).ok();
             }
         }
 
         loop {
+            if let Some(fuel) = fuel.as_deref_mut() {
+                if fuel.remaining == 0 {
+                    return Blocked::Livelock(fuel.cycles());
+                }
+            }
             let effect = match self.try_effect() {
                 Ok(effect) => effect,
                 Err(blocked) => return blocked,
             };
             tracing::info!(run = ?self.runnable, "effect {:?}", effect);
+            if let Some(fuel) = fuel.as_deref_mut() {
+                fuel.burn(&effect);
+            }
 
             let runnable = &mut self.runnable;
             let run = &mut |name, response| {
@@ -357,15 +967,23 @@ impl SimulationRunning {
                         continue;
                     };
                     // resuming receive has removed one message from the mailbox, so check for blocked senders
-                    let Some((from, msg)) = data_to.senders.pop_front() else {
+                    self.admit_blocked_sender(to);
+                }
+                Effect::ReceiveMatching { at_stage: to } => {
+                    let data_to = self.stages.get_mut(&to).unwrap();
+                    // there may be no matching message yet, in which case the stage keeps waiting
+                    let Ok(()) = Self::resume_receive_matching_internal(data_to, run) else {
                         continue;
                     };
-                    Self::post_message(data_to, self.mailbox_size, msg)
-                        .expect("mailbox is not full");
-                    let data_from = self.stages.get_mut(&from).unwrap();
-                    let call = Self::resume_send_internal(data_from, run, to.clone())
-                        .expect("call is always runnable");
-                    self.handle_call_continuation(from, to, call);
+                    self.admit_blocked_sender(to);
+                }
+                Effect::ReceiveQueued { at_stage, max: _ } => {
+                    let data = self.stages.get_mut(&at_stage).unwrap();
+                    let taken = Self::resume_receive_queued_internal(data, run)
+                        .expect("receive queued effect is always runnable");
+                    for _ in 0..taken {
+                        self.admit_blocked_sender(at_stage.clone());
+                    }
                 }
                 Effect::Send {
                     from,
@@ -373,12 +991,31 @@ impl SimulationRunning {
                     msg,
                     call: _,
                 } => {
+                    let span = self.stages[&from].span.clone();
+                    let parent = self.stages[&from].processing;
+                    if let Some(reason) = self.undeliverable(&to) {
+                        self.send_dead_letter(from, to, msg, span, reason)
+                            .expect("send is always runnable");
+                        continue;
+                    }
+                    self.wiring.sent(&from, &to);
                     let data_to = self.stages.get_mut(&to).unwrap();
-                    if let Err(msg) = Self::post_message(data_to, self.mailbox_size, msg) {
-                        data_to.senders.push_back((from, msg));
+                    if let Err((msg, span)) = Self::post_message(
+                        data_to,
+                        self.mailbox_size,
+                        &mut self.causality,
+                        (parent, Some(from.clone())),
+                        msg,
+                        span,
+                    ) {
+                        data_to.senders.push_back((from.clone(), msg, span));
+                        if self.backpressure_stops {
+                            return Blocked::Backpressure { from, to };
+                        }
                     } else {
                         // `to` may not be suspended on receive, so failure to resume is okay
                         Self::resume_receive_internal(data_to, run).ok();
+                        Self::resume_receive_matching_internal(data_to, run).ok();
                         let data_from = self.stages.get_mut(&from).unwrap();
                         let call = Self::resume_send_internal(data_from, run, to.clone())
                             .expect("call is always runnable");
@@ -387,26 +1024,40 @@ impl SimulationRunning {
                 }
                 Effect::Clock { at_stage } => {
                     let data = self.stages.get_mut(&at_stage).unwrap();
-                    Self::resume_clock_internal(data, run, (self.now)())
+                    let now = (data.now)();
+                    Self::resume_clock_internal(data, run, now)
                         .expect("clock effect is always runnable");
                 }
                 Effect::Wait { at_stage, duration } => {
+                    let duration = self.stages[&at_stage].skew.lock().simulated(duration);
                     let delay = duration_to_nanos(duration);
                     self.schedule_wakeup(delay, move |sim| {
                         let data = sim
                             .stages
                             .get_mut(&at_stage)
                             .expect("stage ref exists, so stage must exist");
+                        let now = (data.now)();
                         Self::resume_wait_internal(
                             data,
                             &mut |name, response| {
                                 sim.runnable.push_back((name, response));
                             },
-                            (sim.now)(),
+                            now,
                         )
                         .expect("wait effect is always runnable");
                     });
                 }
+                Effect::Schedule {
+                    at_stage,
+                    msg,
+                    after,
+                } => {
+                    let data = self.stages.get_mut(&at_stage).unwrap();
+                    let after = Self::resume_schedule_internal(data, run)
+                        .expect("schedule effect is always runnable");
+                    let after = data.skew.lock().simulated(after);
+                    self.deliver_after(at_stage, msg, after);
+                }
                 Effect::Respond {
                     at_stage,
                     target,
@@ -418,7 +1069,43 @@ impl SimulationRunning {
                         .expect("respond effect is always runnable");
                     self.handle_send_response(msg, res);
                 }
-                Effect::Interrupt { at_stage } => return Blocked::Interrupted(at_stage),
+                Effect::External { at_stage, request } => {
+                    // without a ready response, the stage waits for the test to resume it
+                    let Some(response) = self
+                        .externals
+                        .get_mut(&(&*request as &dyn Any).type_id())
+                        .and_then(|handler| handler(request))
+                    else {
+                        continue;
+                    };
+                    let data = self.stages.get_mut(&at_stage).unwrap();
+                    Self::resume_external_internal(data, run, response)
+                        .expect("external effect is always runnable");
+                }
+                Effect::Interrupt { at_stage, payload } => {
+                    return Blocked::Interrupted(at_stage, payload)
+                }
+                Effect::Persist { at_stage, .. } => {
+                    let data = self.stages.get_mut(&at_stage).unwrap();
+                    Self::resume_persist_internal(data, run, &self.store)
+                        .expect("persist effect is always runnable");
+                }
+                Effect::Load { at_stage, .. } => {
+                    let data = self.stages.get_mut(&at_stage).unwrap();
+                    Self::resume_load_internal(data, run, &self.store)
+                        .expect("load effect is always runnable");
+                }
+                Effect::Fork { at_stage, .. } => {
+                    let data = self.stages.get_mut(&at_stage).unwrap();
+                    Self::resume_fork_internal(data, run).expect("fork effect is always runnable");
+                }
+                Effect::Join { at_stage, .. } => {
+                    let data = self.stages.get_mut(&at_stage).unwrap();
+                    if let Err(err) = Self::resume_join_internal(data, run) {
+                        panic!("stage `{at_stage}` cannot join: {err:#}");
+                    }
+                }
+                Effect::Termination { .. } => {}
                 Effect::Failure { at_stage, error } => {
                     panic!("stage `{at_stage}` failed with {error:?}");
                 }
@@ -428,8 +1115,8 @@ impl SimulationRunning {
 
     /// If a stage is Idle, it is waiting for Receive and NOT runnable.
     /// If a stage is Running, it may be waiting for a non-Receive effect and may be runnable.
-    /// If a stage is Failed, it is not waiting for any effect and is not runnable.
-    /// A non-Failed stage is either waiting or runnable.
+    /// If a stage is Failed or Terminated, it is not waiting for any effect and is not runnable.
+    /// A non-Failed, non-Terminated stage is either waiting or runnable.
     #[cfg(test)]
     fn invariants(&self) {
         for (name, data) in &self.stages {
@@ -445,9 +1132,12 @@ impl SimulationRunning {
                         panic!("stage `{name}` is Running but waiting for Receive");
                     }
                 }
-                StageState::Failed => {
+                StageState::Failed | StageState::Terminated => {
                     if waiting.is_some() {
-                        panic!("stage `{name}` is Failed but waiting for {waiting:?}");
+                        panic!(
+                            "stage `{name}` is {:?} but waiting for {waiting:?}",
+                            data.state
+                        );
                     }
                     return;
                 }
@@ -490,62 +1180,345 @@ impl SimulationRunning {
             )
         }
 
-        let msg = data
-            .mailbox
-            .pop_front()
-            .ok_or_else(|| anyhow::anyhow!("mailbox is empty while resuming receive"))?;
+        let (idx, received) =
+            highest_priority(data.mailbox.iter().map(|(msg, ..)| data.priority(&**msg)))
+                .ok_or_else(|| anyhow::anyhow!("mailbox is empty while resuming receive"))?;
 
         // it is important that all validations (i.e. `?``) happen before this point
         data.waiting = None;
 
+        data.metrics.received(data.mailbox.len());
+        let (msg, sent_in, id) = data.mailbox.remove(idx).expect("index is valid");
+        let held_back = data
+            .senders
+            .iter()
+            .map(|(_, msg, _)| data.priority(&**msg))
+            .max();
+        if let Some(held_back) = held_back.filter(|held_back| *held_back > received) {
+            data.inversions.push(PriorityInversion {
+                stage: data.name.clone(),
+                received,
+                held_back,
+            });
+        }
+
         let StageState::Idle(state) = replace(&mut data.state, StageState::Failed) else {
             panic!(
                 "stage {} must have been Idle, was {:?}",
                 data.name, data.state
             );
         };
-        data.state = StageState::Running((data.transition)(state, msg));
+        let span = stage_span(&data.name, &sent_in);
+        let transition = (data.transition)(state, msg).instrument(span.clone());
+        data.state = StageState::Running(Box::pin(transition));
+        data.span = span;
+        data.processing = Some(id);
 
         run(data.name.clone(), StageResponse::Unit);
         Ok(())
     }
 
-    /// Resume an [`Effect::Send`].
-    pub fn resume_send<Msg1, Msg2: Message, St1, St2>(
+    /// Resume an [`Effect::ReceiveMatching`] with the first matching message in the mailbox.
+    pub fn resume_receive_matching<Msg, St>(
         &mut self,
-        from: &StageRef<Msg1, St1>,
-        to: &StageRef<Msg2, St2>,
-        msg: Msg2,
+        at_stage: &StageRef<Msg, St>,
     ) -> anyhow::Result<()> {
         let data = self
             .stages
-            .get_mut(&to.name)
-            .expect("stage ref exists, so stage must exist");
-        if Self::post_message(data, self.mailbox_size, Box::new(msg)).is_err() {
-            anyhow::bail!("mailbox is full while resuming send");
-        }
-
-        let data = self
-            .stages
-            .get_mut(&from.name)
+            .get_mut(&at_stage.name)
             .expect("stage ref exists, so stage must exist");
-        let call = Self::resume_send_internal(
-            data,
-            &mut |name, response| {
-                self.runnable.push_back((name, response));
-            },
-            to.name(),
-        )?;
-
-        self.handle_call_continuation(from.name(), to.name(), call);
+        Self::resume_receive_matching_internal(data, &mut |name, response| {
+            self.runnable.push_back((name, response));
+        })?;
+        self.admit_blocked_sender(at_stage.name());
         Ok(())
     }
 
-    fn handle_call_continuation(
-        &mut self,
-        from: Name,
-        to: Name,
-        call: Option<(Duration, Receiver<Box<dyn Message + 'static>>, CallId)>,
+    fn resume_receive_matching_internal(
+        data: &mut StageData,
+        run: &mut dyn FnMut(Name, StageResponse),
+    ) -> anyhow::Result<()> {
+        let waiting_for = data.waiting.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("stage `{}` was not waiting for any effect", data.name)
+        })?;
+
+        let StageEffect::ReceiveMatching(matcher) = waiting_for else {
+            anyhow::bail!(
+                "stage `{}` was not waiting for a matching message, but {:?}",
+                data.name,
+                waiting_for
+            )
+        };
+
+        let idx = data
+            .mailbox
+            .iter()
+            .position(|(msg, ..)| matcher.matches(&**msg))
+            .ok_or_else(|| anyhow::anyhow!("no matching message in the mailbox"))?;
+
+        // it is important that all validations (i.e. `?``) happen before this point
+        data.waiting = None;
+
+        let (msg, ..) = data.mailbox.remove(idx).expect("index is valid");
+        run(data.name.clone(), StageResponse::MatchedMessage(msg));
+        Ok(())
+    }
+
+    /// Resume an [`Effect::ReceiveQueued`] with the messages queued in the mailbox, up to the
+    /// maximum the stage asked for.
+    pub fn resume_receive_queued<Msg, St>(
+        &mut self,
+        at_stage: &StageRef<Msg, St>,
+    ) -> anyhow::Result<()> {
+        let data = self
+            .stages
+            .get_mut(&at_stage.name)
+            .expect("stage ref exists, so stage must exist");
+        let taken = Self::resume_receive_queued_internal(data, &mut |name, response| {
+            self.runnable.push_back((name, response));
+        })?;
+        for _ in 0..taken {
+            self.admit_blocked_sender(at_stage.name());
+        }
+        Ok(())
+    }
+
+    /// Returns the number of messages taken out of the mailbox.
+    fn resume_receive_queued_internal(
+        data: &mut StageData,
+        run: &mut dyn FnMut(Name, StageResponse),
+    ) -> anyhow::Result<usize> {
+        let waiting_for = data.waiting.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("stage `{}` was not waiting for any effect", data.name)
+        })?;
+
+        let StageEffect::ReceiveQueued(max) = waiting_for else {
+            anyhow::bail!(
+                "stage `{}` was not waiting for queued messages, but {:?}",
+                data.name,
+                waiting_for
+            )
+        };
+        let max = *max;
+
+        // it is important that all validations (i.e. `?``) happen before this point
+        data.waiting = None;
+
+        let mut msgs = Vec::new();
+        while msgs.len() < max {
+            let Some((idx, _)) =
+                highest_priority(data.mailbox.iter().map(|(msg, ..)| data.priority(&**msg)))
+            else {
+                break;
+            };
+            let (msg, ..) = data.mailbox.remove(idx).expect("index is valid");
+            msgs.push(msg);
+        }
+        let taken = msgs.len();
+        run(data.name.clone(), StageResponse::QueuedMessages(msgs));
+        Ok(taken)
+    }
+
+    /// Let in the message of the blocked sender of highest priority, if any, now that a
+    /// message has left the mailbox of `to`.
+    fn admit_blocked_sender(&mut self, to: Name) {
+        let data_to = self.stages.get_mut(&to).unwrap();
+        let Some((idx, _)) = highest_priority(
+            data_to
+                .senders
+                .iter()
+                .map(|(_, msg, _)| data_to.priority(&**msg)),
+        ) else {
+            return;
+        };
+        let (from, msg, span) = data_to.senders.remove(idx).expect("index is valid");
+        let parent = self.stages[&from].processing;
+        let data_to = self.stages.get_mut(&to).unwrap();
+        Self::post_message(
+            data_to,
+            self.mailbox_size,
+            &mut self.causality,
+            (parent, Some(from.clone())),
+            msg,
+            span,
+        )
+        .expect("mailbox is not full");
+        let data_from = self.stages.get_mut(&from).unwrap();
+        let call = Self::resume_send_internal(
+            data_from,
+            &mut |name, response| {
+                self.runnable.push_back((name, response));
+            },
+            to.clone(),
+        )
+        .expect("call is always runnable");
+        self.handle_call_continuation(from, to, call);
+    }
+
+    /// Resume an [`Effect::Send`].
+    pub fn resume_send<Msg1, Msg2: Message, St1, St2>(
+        &mut self,
+        from: &StageRef<Msg1, St1>,
+        to: &StageRef<Msg2, St2>,
+        msg: Msg2,
+    ) -> anyhow::Result<()> {
+        let data_from = self
+            .stages
+            .get(&from.name)
+            .expect("stage ref exists, so stage must exist");
+        let (span, parent) = (data_from.span.clone(), data_from.processing);
+        let msg = to.adapt(Box::new(msg));
+        if let Some(reason) = self.undeliverable(&to.name) {
+            return self.send_dead_letter(from.name(), to.name(), msg, span, reason);
+        }
+        let data = self
+            .stages
+            .get_mut(&to.name)
+            .expect("stage ref exists, so stage must exist");
+        if Self::post_message(
+            data,
+            self.mailbox_size,
+            &mut self.causality,
+            (parent, Some(from.name())),
+            msg,
+            span,
+        )
+        .is_err()
+        {
+            anyhow::bail!("mailbox is full while resuming send");
+        }
+
+        let data = self
+            .stages
+            .get_mut(&from.name)
+            .expect("stage ref exists, so stage must exist");
+        let call = Self::resume_send_internal(
+            data,
+            &mut |name, response| {
+                self.runnable.push_back((name, response));
+            },
+            to.name(),
+        )?;
+
+        self.wiring.sent(&from.name, &to.name);
+        self.handle_call_continuation(from.name(), to.name(), call);
+        Ok(())
+    }
+
+    /// Why a message sent to `to` cannot be delivered, if it cannot.
+    fn undeliverable(&self, to: &Name) -> Option<DeadLetterReason> {
+        let Some(data) = self.stages.get(to) else {
+            return Some(DeadLetterReason::Noop);
+        };
+        if matches!(data.state, StageState::Terminated)
+            || (matches!(data.state, StageState::Failed) && self.dead_letters.is_some())
+        {
+            Some(DeadLetterReason::Terminated)
+        } else if data.drops_overflow && data.mailbox.len() >= self.mailbox_size {
+            Some(DeadLetterReason::Overflow)
+        } else {
+            None
+        }
+    }
+
+    /// Resume the send of an undeliverable message, delivering it to the dead letters instead
+    /// if they are configured.
+    fn send_dead_letter(
+        &mut self,
+        from: Name,
+        to: Name,
+        msg: Box<dyn Message>,
+        span: Span,
+        reason: DeadLetterReason,
+    ) -> anyhow::Result<()> {
+        let data_from = self
+            .stages
+            .get_mut(&from)
+            .expect("stage ref exists, so stage must exist");
+        let parent = data_from.processing;
+        let call = Self::resume_send_internal(
+            data_from,
+            &mut |name, response| {
+                self.runnable.push_back((name, response));
+            },
+            to.clone(),
+        )?;
+        self.wiring.sent(&from, &to);
+        self.handle_call_continuation(from.clone(), to.clone(), call);
+
+        let sent_by = (parent, Some(from.clone()));
+        let letter = DeadLetter {
+            from,
+            to,
+            reason,
+            msg,
+        };
+        let Some(data) = self
+            .dead_letters
+            .as_ref()
+            .and_then(|name| self.stages.get_mut(name))
+            .filter(|data| !matches!(data.state, StageState::Terminated))
+        else {
+            tracing::warn!("undeliverable message dropped: {letter:?}");
+            return Ok(());
+        };
+        let run = &mut |name, response| {
+            self.runnable.push_back((name, response));
+        };
+        match Self::post_message(
+            data,
+            self.mailbox_size,
+            &mut self.causality,
+            sent_by,
+            Box::new(letter),
+            span,
+        ) {
+            Ok(()) => {
+                // the dead letters may not be suspended on receive, so failure to resume is okay
+                Self::resume_receive_internal(data, run).ok();
+                Self::resume_receive_matching_internal(data, run).ok();
+            }
+            Err((letter, _span)) => {
+                tracing::warn!("mailbox of `{}` is full, dropped: {letter:?}", data.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop the given stage, handing its final state to its
+    /// [finalizer](crate::StageGraph::finalizer) if it has one, dropping its mailbox and turning
+    /// the messages of its blocked senders into dead letters.
+    fn terminate_stage(&mut self, name: &Name, state: Option<Box<dyn State>>) {
+        let data = self
+            .stages
+            .get_mut(name)
+            .expect("stage ref exists, so stage must exist");
+        data.state = StageState::Terminated;
+        data.waiting = None;
+        data.received_at = None;
+        data.forks.clear();
+        if !data.mailbox.is_empty() {
+            tracing::info!(
+                "stage `{name}` terminated, dropping {} queued messages",
+                data.mailbox.len()
+            );
+            data.mailbox.clear();
+        }
+        if let (Some(state), Some(finalizer)) = (state, data.finalizer.take()) {
+            finalizer(state);
+        }
+        for (from, msg, span) in take(&mut data.senders) {
+            // the sender may have terminated meanwhile, in which case its message is dropped
+            self.send_dead_letter(from, name.clone(), msg, span, DeadLetterReason::Terminated)
+                .ok();
+        }
+    }
+
+    fn handle_call_continuation(
+        &mut self,
+        from: Name,
+        to: Name,
+        call: Option<(Duration, Receiver<Box<dyn Message + 'static>>, CallId)>,
     ) {
         if let Some((timeout, recv, id)) = call {
             let deadline = (self.now)()
@@ -572,15 +1545,21 @@ impl SimulationRunning {
         }
     }
 
+    /// Put a message sent by the given stage, while processing the given message, into the
+    /// mailbox, recording it in the causality if there is room for it.
     fn post_message(
         data: &mut StageData,
         mailbox_size: usize,
+        causality: &mut Causality,
+        (parent, from): (Option<MsgId>, Option<Name>),
         msg: Box<dyn Message>,
-    ) -> Result<(), Box<dyn Message>> {
+        span: Span,
+    ) -> Result<(), (Box<dyn Message>, Span)> {
         if data.mailbox.len() >= mailbox_size {
-            return Err(msg);
+            return Err((msg, span));
         }
-        data.mailbox.push_back(msg);
+        let id = causality.record(parent, from, data.name.clone(), &*msg);
+        data.mailbox.push_back((msg, span, id));
         Ok(())
     }
 
@@ -701,6 +1680,68 @@ impl SimulationRunning {
         Ok(())
     }
 
+    /// Resume an [`Effect::Schedule`], delivering `msg` to the stage once the scheduled
+    /// delay has passed on the simulated clock.
+    pub fn resume_schedule<Msg: Message, St>(
+        &mut self,
+        at_stage: &StageRef<Msg, St>,
+        msg: Msg,
+    ) -> anyhow::Result<()> {
+        let data = self
+            .stages
+            .get_mut(&at_stage.name)
+            .expect("stage ref exists, so stage must exist");
+        let after = Self::resume_schedule_internal(data, &mut |name, response| {
+            self.runnable.push_back((name, response));
+        })?;
+        self.deliver_after(at_stage.name(), Box::new(msg), after);
+        Ok(())
+    }
+
+    fn resume_schedule_internal(
+        data: &mut StageData,
+        run: &mut dyn FnMut(Name, StageResponse),
+    ) -> anyhow::Result<Duration> {
+        let waiting_for = data.waiting.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("stage `{}` was not waiting for any effect", data.name)
+        })?;
+
+        let StageEffect::Schedule((), after) = waiting_for else {
+            anyhow::bail!(
+                "stage `{}` was not waiting for a schedule effect, but {:?}",
+                data.name,
+                waiting_for
+            )
+        };
+        let after = *after;
+
+        // it is important that all validations (i.e. `?``) happen before this point
+        data.waiting = None;
+
+        run(data.name.clone(), StageResponse::Unit);
+        Ok(after)
+    }
+
+    /// Place `msg` in the mailbox of the given stage once `after` has passed, regardless of
+    /// the mailbox size. Like other messages, it is consumed when resuming a receive.
+    fn deliver_after(&mut self, to: Name, msg: Box<dyn Message>, after: Duration) {
+        let data = self
+            .stages
+            .get(&to)
+            .expect("stage ref exists, so stage must exist");
+        let (span, parent) = (data.span.clone(), data.processing);
+        self.schedule_wakeup(duration_to_nanos(after), move |sim| {
+            let id = sim
+                .causality
+                .record(parent, Some(to.clone()), to.clone(), &*msg);
+            sim.stages
+                .get_mut(&to)
+                .expect("stage ref exists, so stage must exist")
+                .mailbox
+                .push_back((msg, span, id));
+        });
+    }
+
     /// Resume an [`Effect::Call`].
     ///
     /// If `msg` is `None`, the call has timed out.
@@ -825,21 +1866,144 @@ impl SimulationRunning {
         Ok((target, id, deadline, sender))
     }
 
-    /// Resume an [`Effect::Interrupt`].
-    pub fn resume_interrupt<Msg, St>(
+    /// Resume an [`Effect::External`] with the response to its request.
+    ///
+    /// This is only needed for requests without a ready handler, see
+    /// [`StageGraph::external`](crate::StageGraph::external).
+    pub fn resume_external<Msg, St, Resp: Message>(
         &mut self,
         at_stage: &StageRef<Msg, St>,
+        response: Resp,
     ) -> anyhow::Result<()> {
         let data = self
             .stages
             .get_mut(&at_stage.name)
             .expect("stage ref exists, so stage must exist");
-        Self::resume_interrupt_internal(data, &mut |name, response| {
+        Self::resume_external_internal(
+            data,
+            &mut |name, response| {
+                self.runnable.push_back((name, response));
+            },
+            Box::new(response),
+        )
+    }
+
+    fn resume_external_internal(
+        data: &mut StageData,
+        run: &mut dyn FnMut(Name, StageResponse),
+        response: Box<dyn Message>,
+    ) -> anyhow::Result<()> {
+        let waiting_for = data.waiting.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("stage `{}` was not waiting for any effect", data.name)
+        })?;
+
+        if !matches!(waiting_for, StageEffect::External(())) {
+            anyhow::bail!(
+                "stage `{}` was not waiting for an external effect, but {:?}",
+                data.name,
+                waiting_for
+            )
+        }
+
+        // it is important that all validations (i.e. `?``) happen before this point
+        data.waiting = None;
+
+        run(data.name.clone(), StageResponse::ExternalResponse(response));
+        Ok(())
+    }
+
+    /// Resume an [`Effect::Persist`] by performing the write on the [store](Self::store).
+    pub fn resume_persist<Msg, St>(&mut self, at_stage: &StageRef<Msg, St>) -> anyhow::Result<()> {
+        let data = self
+            .stages
+            .get_mut(&at_stage.name)
+            .expect("stage ref exists, so stage must exist");
+        Self::resume_persist_internal(
+            data,
+            &mut |name, response| {
+                self.runnable.push_back((name, response));
+            },
+            &self.store,
+        )
+    }
+
+    fn resume_persist_internal(
+        data: &mut StageData,
+        run: &mut dyn FnMut(Name, StageResponse),
+        store: &MemoryStore,
+    ) -> anyhow::Result<()> {
+        let waiting_for = data.waiting.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("stage `{}` was not waiting for any effect", data.name)
+        })?;
+
+        let StageEffect::Persist(key, value) = waiting_for else {
+            anyhow::bail!(
+                "stage `{}` was not waiting for a persist effect, but {:?}",
+                data.name,
+                waiting_for
+            )
+        };
+        let result = store.put(key, value.clone());
+
+        // it is important that all validations (i.e. `?``) happen before this point
+        data.waiting = None;
+
+        run(data.name.clone(), StageResponse::Persisted(result));
+        Ok(())
+    }
+
+    /// Resume an [`Effect::Load`] by performing the read on the [store](Self::store).
+    pub fn resume_load<Msg, St>(&mut self, at_stage: &StageRef<Msg, St>) -> anyhow::Result<()> {
+        let data = self
+            .stages
+            .get_mut(&at_stage.name)
+            .expect("stage ref exists, so stage must exist");
+        Self::resume_load_internal(
+            data,
+            &mut |name, response| {
+                self.runnable.push_back((name, response));
+            },
+            &self.store,
+        )
+    }
+
+    fn resume_load_internal(
+        data: &mut StageData,
+        run: &mut dyn FnMut(Name, StageResponse),
+        store: &MemoryStore,
+    ) -> anyhow::Result<()> {
+        let waiting_for = data.waiting.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("stage `{}` was not waiting for any effect", data.name)
+        })?;
+
+        let StageEffect::Load(key) = waiting_for else {
+            anyhow::bail!(
+                "stage `{}` was not waiting for a load effect, but {:?}",
+                data.name,
+                waiting_for
+            )
+        };
+        let result = store.get(key);
+
+        // it is important that all validations (i.e. `?``) happen before this point
+        data.waiting = None;
+
+        run(data.name.clone(), StageResponse::Loaded(result));
+        Ok(())
+    }
+
+    /// Resume an [`Effect::Fork`], setting the sub-task aside until it is joined.
+    pub fn resume_fork<Msg, St>(&mut self, at_stage: &StageRef<Msg, St>) -> anyhow::Result<()> {
+        let data = self
+            .stages
+            .get_mut(&at_stage.name)
+            .expect("stage ref exists, so stage must exist");
+        Self::resume_fork_internal(data, &mut |name, response| {
             self.runnable.push_back((name, response));
         })
     }
 
-    fn resume_interrupt_internal(
+    fn resume_fork_internal(
         data: &mut StageData,
         run: &mut dyn FnMut(Name, StageResponse),
     ) -> anyhow::Result<()> {
@@ -847,34 +2011,227 @@ impl SimulationRunning {
             anyhow::anyhow!("stage `{}` was not waiting for any effect", data.name)
         })?;
 
-        if !matches!(waiting_for, StageEffect::Interrupt) {
+        if !matches!(waiting_for, StageEffect::Fork(..)) {
             anyhow::bail!(
-                "stage `{}` was not waiting for an interrupt effect",
+                "stage `{}` was not waiting for a fork effect, but {:?}",
+                data.name,
+                waiting_for
+            )
+        }
+
+        // it is important that all validations (i.e. `?``) happen before this point
+        let Some(StageEffect::Fork(id, task)) = data.waiting.take() else {
+            unreachable!("checked above");
+        };
+        data.forks.insert(id, task);
+
+        run(data.name.clone(), StageResponse::Unit);
+        Ok(())
+    }
+
+    /// Resume an [`Effect::Join`] by running the sub-task to completion.
+    ///
+    /// Sub-tasks run only when joined, so they must not wait for anything but each other; a
+    /// sub-task that cannot complete is an error.
+    pub fn resume_join<Msg, St>(&mut self, at_stage: &StageRef<Msg, St>) -> anyhow::Result<()> {
+        let data = self
+            .stages
+            .get_mut(&at_stage.name)
+            .expect("stage ref exists, so stage must exist");
+        Self::resume_join_internal(data, &mut |name, response| {
+            self.runnable.push_back((name, response));
+        })
+    }
+
+    fn resume_join_internal(
+        data: &mut StageData,
+        run: &mut dyn FnMut(Name, StageResponse),
+    ) -> anyhow::Result<()> {
+        let waiting_for = data.waiting.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("stage `{}` was not waiting for any effect", data.name)
+        })?;
+
+        let &StageEffect::Join(id) = waiting_for else {
+            anyhow::bail!(
+                "stage `{}` was not waiting for a join effect, but {:?}",
+                data.name,
+                waiting_for
+            )
+        };
+        let Some(task) = data.forks.get_mut(&id) else {
+            anyhow::bail!("stage `{}` has no sub-task {:?}", data.name, id);
+        };
+        let Poll::Ready(result) = task
+            .0
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        else {
+            anyhow::bail!(
+                "sub-task {:?} of stage `{}` did not complete",
+                id,
                 data.name
+            );
+        };
+        data.forks.remove(&id);
+
+        // it is important that all validations (i.e. `?``) happen before this point
+        data.waiting = None;
+
+        run(data.name.clone(), StageResponse::Joined(result));
+        Ok(())
+    }
+
+    /// Resume an [`Effect::Interrupt`] that expects no decision, i.e. from
+    /// [`Effects::interrupt`](crate::Effects::interrupt).
+    pub fn resume_interrupt<Msg, St>(
+        &mut self,
+        at_stage: &StageRef<Msg, St>,
+    ) -> anyhow::Result<()> {
+        self.resume_interrupt_with(at_stage, ())
+    }
+
+    /// Resume an [`Effect::Interrupt`] with the decision the stage continues with, see
+    /// [`Effects::interrupt_with`](crate::Effects::interrupt_with).
+    pub fn resume_interrupt_with<Msg, St, D: Message>(
+        &mut self,
+        at_stage: &StageRef<Msg, St>,
+        decision: D,
+    ) -> anyhow::Result<()> {
+        let data = self
+            .stages
+            .get_mut(&at_stage.name)
+            .expect("stage ref exists, so stage must exist");
+        Self::resume_interrupt_internal(
+            data,
+            &mut |name, response| {
+                self.runnable.push_back((name, response));
+            },
+            Box::new(decision),
+        )
+    }
+
+    fn resume_interrupt_internal(
+        data: &mut StageData,
+        run: &mut dyn FnMut(Name, StageResponse),
+        decision: Box<dyn Message>,
+    ) -> anyhow::Result<()> {
+        let waiting_for = data.waiting.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("stage `{}` was not waiting for any effect", data.name)
+        })?;
+
+        let StageEffect::Interrupt((), decision_type) = waiting_for else {
+            anyhow::bail!(
+                "stage `{}` was not waiting for an interrupt effect, but {:?}",
+                data.name,
+                waiting_for
+            )
+        };
+        if *decision_type != (&*decision as &dyn Any).type_id() {
+            anyhow::bail!(
+                "stage `{}` does not expect a decision of type {}",
+                data.name,
+                decision.type_name()
             )
         }
 
         // it is important that all validations (i.e. `?``) happen before this point
         data.waiting = None;
 
-        run(data.name.clone(), StageResponse::Unit);
+        run(
+            data.name.clone(),
+            StageResponse::InterruptResponse(decision),
+        );
         Ok(())
     }
 }
 
+fn clone_mailbox(mailbox: &Mailbox, cloner: Cloner) -> Mailbox {
+    mailbox
+        .iter()
+        .map(|(msg, span, id)| ((cloner.msg)(&**msg), span.clone(), *id))
+        .collect()
+}
+
 fn duration_to_nanos(duration: std::time::Duration) -> u64 {
     u64::try_from(duration.as_nanos())
         .expect("duration too large")
         .max(1)
 }
 
+/// The effects a simulation may still perform, see
+/// [`SimulationRunning::run_until_blocked_with_fuel`], along with who sent messages to whom
+/// during the second half of them.
+struct Fuel {
+    remaining: usize,
+    half: usize,
+    sent: BTreeSet<(Name, Name)>,
+}
+
+impl Fuel {
+    fn new(fuel: usize) -> Self {
+        Self {
+            remaining: fuel,
+            half: fuel / 2,
+            sent: BTreeSet::new(),
+        }
+    }
+
+    fn burn(&mut self, effect: &Effect) {
+        self.remaining -= 1;
+        if self.remaining > self.half {
+            return;
+        }
+        match effect {
+            Effect::Send { from, to, .. } => self.sent.insert((from.clone(), to.clone())),
+            Effect::Schedule { at_stage, .. } => {
+                self.sent.insert((at_stage.clone(), at_stage.clone()))
+            }
+            Effect::Respond {
+                at_stage, target, ..
+            } => self.sent.insert((at_stage.clone(), target.clone())),
+            _ => false,
+        };
+    }
+
+    /// The stages on a cycle of the messages sent, sorted by name.
+    fn cycles(&self) -> Vec<Name> {
+        let stages = self
+            .sent
+            .iter()
+            .map(|(from, _)| from)
+            .collect::<BTreeSet<_>>();
+        stages
+            .into_iter()
+            .filter(|stage| self.reaches(stage, stage))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether messages went from `from` to `to`, possibly via other stages.
+    fn reaches(&self, from: &Name, to: &Name) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut todo = vec![from];
+        while let Some(stage) = todo.pop() {
+            for (_, next) in self.sent.iter().filter(|(f, _)| f == stage) {
+                if next == to {
+                    return true;
+                }
+                if seen.insert(next) {
+                    todo.push(next);
+                }
+            }
+        }
+        false
+    }
+}
+
 fn block_reason(sim: &SimulationRunning) -> Blocked {
     debug_assert!(sim.runnable.is_empty(), "runnable must be empty");
     if sim
         .stages
         .values()
         .filter_map(|d| d.waiting.as_ref())
-        .all(|v| matches!(v, StageEffect::Receive))
+        .all(|v| matches!(v, StageEffect::Receive | StageEffect::ReceiveMatching(_)))
     {
         if sim.sleeping.is_empty() {
             return Blocked::Idle;
@@ -891,7 +2248,7 @@ fn block_reason(sim: &SimulationRunning) -> Blocked {
     {
         match v {
             StageEffect::Send(..) => send.push(k.clone()),
-            StageEffect::Receive => {}
+            StageEffect::Receive | StageEffect::ReceiveMatching(_) => {}
             StageEffect::Wait(..) => sleep.push(k.clone()),
             _ => busy.push(k.clone()),
         }
@@ -902,6 +2259,8 @@ fn block_reason(sim: &SimulationRunning) -> Blocked {
         Blocked::Sleeping
     } else if !busy.is_empty() {
         Blocked::Busy(busy)
+    } else if let Some(cycle) = send_cycle(sim) {
+        Blocked::DeadlockCycle(cycle)
     } else if !send.is_empty() {
         Blocked::Deadlock(send)
     } else {
@@ -909,9 +2268,39 @@ fn block_reason(sim: &SimulationRunning) -> Blocked {
     }
 }
 
+/// A cycle of stages suspended on sending to each other, starting with the smallest name, if
+/// there is one.
+fn send_cycle(sim: &SimulationRunning) -> Option<Vec<Name>> {
+    let waits_for = sim
+        .stages
+        .iter()
+        .filter_map(|(name, data)| match &data.waiting {
+            Some(StageEffect::Send(to, ..)) => Some((name, to)),
+            _ => None,
+        })
+        .collect::<BTreeMap<_, _>>();
+    for start in waits_for.keys() {
+        let mut path = vec![*start];
+        while let Some(&next) = path.last().and_then(|last| waits_for.get(last)) {
+            if let Some(pos) = path.iter().position(|name| *name == next) {
+                let mut cycle = path.split_off(pos);
+                let first = cycle
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, name)| *name)
+                    .map_or(0, |(idx, _)| idx);
+                cycle.rotate_left(first);
+                return Some(cycle.into_iter().cloned().collect());
+            }
+            path.push(next);
+        }
+    }
+    None
+}
+
 #[test]
 fn simulation_invariants() {
-    use crate::{cast_msg_ref, stagegraph::CallRef, StageGraph};
+    use crate::{stagegraph::CallRef, StageGraph};
 
     tracing_subscriber::fmt()
         .with_test_writer()
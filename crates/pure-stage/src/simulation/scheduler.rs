@@ -0,0 +1,37 @@
+use crate::{effect::StageResponse, Name};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::VecDeque;
+
+/// The policy by which a simulation picks the runnable stage that takes the next step, see
+/// [`SimulationRunning::run_until_blocked_with_scheduler`].
+///
+/// [`SimulationRunning::run_until_blocked_with_scheduler`]: super::SimulationRunning::run_until_blocked_with_scheduler
+#[derive(Debug, Clone, Default)]
+pub enum Scheduler {
+    /// Run the stages in the order in which they became runnable.
+    #[default]
+    Fifo,
+    /// Run any of the runnable stages, picked by a random number generator, so that a seed
+    /// stands for one schedule.
+    Random(StdRng),
+    /// Run the stage that became runnable last, holding back the others for as long as
+    /// possible, e.g. the receiver of a message while its sender keeps on sending.
+    LongestDelay,
+}
+
+impl Scheduler {
+    /// A [`Random`](Self::Random) scheduler seeded with `seed`.
+    pub fn random(seed: u64) -> Self {
+        Self::Random(StdRng::seed_from_u64(seed))
+    }
+
+    /// The index of the runnable stage to take the next step.
+    pub(super) fn choose(&mut self, runnable: &VecDeque<(Name, StageResponse)>) -> usize {
+        match self {
+            Scheduler::Fifo => 0,
+            Scheduler::Random(rng) if !runnable.is_empty() => rng.random_range(0..runnable.len()),
+            Scheduler::Random(_) => 0,
+            Scheduler::LongestDelay => runnable.len().saturating_sub(1),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::Instant;
+use std::time::Duration;
+
+/// How the clock of a stage deviates from the simulated clock, see
+/// [`SimulationBuilder::clock_skew`](super::SimulationBuilder::clock_skew).
+///
+/// The clock of the stage is off by a fixed offset, and it drifts by the given parts per
+/// million of the simulated time elapsed since the start of the simulation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClockSkew {
+    offset_nanos: i128,
+    drift_ppm: i64,
+}
+
+impl ClockSkew {
+    /// A clock that is ahead of the simulated clock by `offset`.
+    pub fn ahead(offset: Duration) -> Self {
+        Self {
+            offset_nanos: i128::try_from(offset.as_nanos()).expect("offset too large"),
+            drift_ppm: 0,
+        }
+    }
+
+    /// A clock that is behind the simulated clock by `offset`.
+    pub fn behind(offset: Duration) -> Self {
+        Self {
+            offset_nanos: -i128::try_from(offset.as_nanos()).expect("offset too large"),
+            drift_ppm: 0,
+        }
+    }
+
+    /// Let the clock gain `ppm` microseconds per second of simulated time, or lose them if
+    /// negative.
+    pub fn with_drift_ppm(mut self, ppm: i64) -> Self {
+        assert!(
+            ppm > -1_000_000,
+            "a clock cannot stand still or run backwards"
+        );
+        self.drift_ppm = ppm;
+        self
+    }
+
+    /// The time this clock shows when the simulated clock shows `now`, `elapsed` nanoseconds
+    /// after the start of the simulation.
+    pub(crate) fn local_time(&self, now: Instant, elapsed: u64) -> Instant {
+        let skew = self.offset_nanos + i128::from(elapsed) * i128::from(self.drift_ppm) / 1_000_000;
+        let duration =
+            Duration::from_nanos(u64::try_from(skew.unsigned_abs()).expect("clock skew too large"));
+        if skew >= 0 {
+            now.checked_add(duration)
+        } else {
+            now.checked_sub(duration)
+        }
+        .expect("clock skew out of range")
+    }
+
+    /// The simulated time it takes this clock to advance by `duration`.
+    pub(crate) fn simulated(&self, duration: Duration) -> Duration {
+        let rate = u128::try_from(1_000_000 + self.drift_ppm).expect("checked on construction");
+        let nanos = duration.as_nanos() * 1_000_000 / rate;
+        Duration::from_nanos(u64::try_from(nanos).expect("duration too large"))
+    }
+}
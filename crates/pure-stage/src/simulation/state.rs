@@ -1,6 +1,15 @@
-use super::StageEffect;
-use crate::{BoxFuture, Message, Name, State};
-use std::{collections::VecDeque, fmt};
+use super::{ClockSkew, Finalizer, MsgId, Priority, PriorityInversion, StageEffect};
+use crate::{
+    cast_msg_ref, cast_state, effect::SubTask, BoxFuture, ForkId, Instant, Message, Name,
+    StageMetrics, State,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    sync::{atomic::AtomicBool, Arc},
+};
+use tracing::Span;
 
 pub enum InitStageState {
     Uninitialized,
@@ -23,16 +32,49 @@ pub type Transition = Box<
     ) -> BoxFuture<'static, anyhow::Result<Box<dyn State>>>,
 >;
 
+/// The handler of the requests of some [`ExternalEffect`](crate::ExternalEffect) type, giving
+/// no response if it isn't ready right away.
+pub type ExternalHandler = Box<dyn FnMut(Box<dyn Message>) -> Option<Box<dyn Message>> + Send>;
+
+/// The messages waiting for a stage, along with the span of the stage which sent each of them
+/// and their ids in the [`Causality`](super::Causality).
+pub type Mailbox = VecDeque<(Box<dyn Message>, Span, MsgId)>;
+
+/// How to copy the state and the messages of a stage, for snapshots.
+#[derive(Clone, Copy)]
+pub struct Cloner {
+    pub state: fn(&dyn State) -> Box<dyn State>,
+    pub msg: fn(&dyn Message) -> Box<dyn Message>,
+}
+
+impl Cloner {
+    pub fn new<Msg: Message + Clone, St: State + Clone>() -> Self {
+        Self {
+            state: |state| Box::new(cast_state::<St>(state).unwrap().clone()),
+            msg: |msg| Box::new(cast_msg_ref::<Msg>(msg).unwrap().clone()),
+        }
+    }
+}
+
 pub struct InitStageData {
-    pub mailbox: VecDeque<Box<dyn Message>>,
+    pub mailbox: Mailbox,
     pub state: InitStageState,
     pub transition: Transition,
+    pub cloner: Option<Cloner>,
+    pub priority: Option<Priority>,
+    pub drops_overflow: bool,
+    pub skew: Arc<Mutex<ClockSkew>>,
+    pub now: Arc<dyn Fn() -> Instant + Send + Sync>,
+    pub terminating: Arc<AtomicBool>,
+    pub finalizer: Option<Finalizer>,
 }
 
 pub enum StageState {
     Idle(Box<dyn State>),
     Running(BoxFuture<'static, anyhow::Result<Box<dyn State>>>),
     Failed,
+    /// The stage has stopped, see [`Effects::terminate`](crate::Effects::terminate).
+    Terminated,
 }
 
 impl fmt::Debug for StageState {
@@ -41,15 +83,43 @@ impl fmt::Debug for StageState {
             Self::Idle(arg0) => f.debug_tuple("Idle").field(arg0).finish(),
             Self::Running(_) => f.debug_tuple("Running").finish(),
             Self::Failed => f.debug_tuple("Failed").finish(),
+            Self::Terminated => f.debug_tuple("Terminated").finish(),
         }
     }
 }
 
 pub struct StageData {
     pub name: Name,
-    pub mailbox: VecDeque<Box<dyn Message>>,
+    pub mailbox: Mailbox,
     pub state: StageState,
     pub transition: Transition,
     pub waiting: Option<StageEffect<()>>,
-    pub senders: VecDeque<(Name, Box<dyn Message>)>,
+    pub senders: VecDeque<(Name, Box<dyn Message>, Span)>,
+    /// The span of the message the stage is processing.
+    pub span: Span,
+    /// The id of the message that started the transition in progress.
+    pub processing: Option<MsgId>,
+    pub cloner: Option<Cloner>,
+    pub priority: Option<Priority>,
+    /// Whether messages sent to the full mailbox are dropped rather than held back.
+    pub drops_overflow: bool,
+    pub skew: Arc<Mutex<ClockSkew>>,
+    /// The clock of this stage, skewed according to `skew`.
+    pub now: Arc<dyn Fn() -> Instant + Send + Sync>,
+    /// Whether the stage terminates once its current transition returns.
+    pub terminating: Arc<AtomicBool>,
+    pub finalizer: Option<Finalizer>,
+    /// The sub-tasks the stage has forked and not yet joined.
+    pub forks: BTreeMap<ForkId, SubTask>,
+    pub inversions: Vec<PriorityInversion>,
+    pub metrics: StageMetrics,
+    /// When the stage received the message it is processing.
+    pub received_at: Option<Instant>,
+}
+
+impl StageData {
+    /// The priority of a message to this stage, the same for all messages if it has none.
+    pub fn priority(&self, msg: &dyn Message) -> u8 {
+        self.priority.as_ref().map_or(0, |priority| priority(msg))
+    }
 }
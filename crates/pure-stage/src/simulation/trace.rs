@@ -0,0 +1,101 @@
+use crate::{effect::StageResponse, Effect, Name};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// The steps a simulation took while [recording](super::SimulationRunning::record_trace), to
+/// be compared against a golden trace, inspected after a failure or
+/// [replayed](super::SimulationRunning::replay).
+///
+/// Messages need not be serializable, so the effects are recorded as rendered by their
+/// `Debug` implementation. Replaying a trace compares the stage, the time and the kind of effect
+/// of each step, but not the rendering, which contains the ids of calls that differ between runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// The stage that took the step.
+    pub stage: Name,
+    /// The effect the step ended with.
+    pub effect: String,
+    /// The simulated time of the step, in nanoseconds since the start of the simulation.
+    pub clock: u64,
+}
+
+impl TraceStep {
+    pub(super) fn new(effect: &Effect, clock: u64) -> Self {
+        Self {
+            stage: effect.at_stage().clone(),
+            effect: format!("{effect:?}"),
+            clock,
+        }
+    }
+
+    /// The name of the effect variant, e.g. `Send`.
+    fn kind(&self) -> &str {
+        self.effect.split([' ', '(']).next().unwrap_or_default()
+    }
+
+    fn matches(&self, other: &TraceStep) -> bool {
+        self.stage == other.stage && self.clock == other.clock && self.kind() == other.kind()
+    }
+}
+
+/// The steps of a trace that are yet to be replayed, and the first deviation from them.
+pub(super) struct Replay {
+    steps: VecDeque<TraceStep>,
+    taken: usize,
+    pub(super) divergence: Option<String>,
+}
+
+impl Replay {
+    pub(super) fn new(trace: &Trace) -> Self {
+        Self {
+            steps: trace.steps.iter().cloned().collect(),
+            taken: 0,
+            divergence: None,
+        }
+    }
+
+    /// The number of steps of the trace that were not replayed.
+    pub(super) fn remaining(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// The index of the runnable stage that took the next step of the trace; once the replay
+    /// has diverged, the stages run in the order in which they became runnable.
+    pub(super) fn choose(&mut self, runnable: &VecDeque<(Name, StageResponse)>) -> usize {
+        if self.divergence.is_some() {
+            return 0;
+        }
+        let Some(step) = self.steps.front() else {
+            return 0;
+        };
+        match runnable.iter().position(|(name, _)| name == &step.stage) {
+            Some(idx) => idx,
+            None => {
+                self.diverge(format!("stage `{}` is not runnable", step.stage));
+                0
+            }
+        }
+    }
+
+    /// Compare the step just taken with the next one of the trace.
+    pub(super) fn took(&mut self, effect: &Effect, clock: u64) {
+        if self.divergence.is_some() {
+            return;
+        }
+        let step = TraceStep::new(effect, clock);
+        match self.steps.pop_front() {
+            Some(expected) if expected.matches(&step) => self.taken += 1,
+            Some(expected) => self.diverge(format!("expected {expected:?}, got {step:?}")),
+            None => self.diverge(format!("trace ended, got {step:?}")),
+        }
+    }
+
+    fn diverge(&mut self, reason: String) {
+        self.divergence = Some(format!("replay diverged at step {}: {reason}", self.taken));
+    }
+}
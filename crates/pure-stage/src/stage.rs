@@ -1,5 +1,5 @@
-use crate::Name;
-use std::{fmt, marker::PhantomData};
+use crate::{cast_msg, wiring::observe, Message, Name};
+use std::{fmt, marker::PhantomData, sync::Arc};
 
 /// A handle to a stage during the building phase of a [`StageGraph`](crate::StageGraph).
 pub struct StageBuildRef<Msg, St, RefAux> {
@@ -12,16 +12,18 @@ pub struct StageBuildRef<Msg, St, RefAux> {
 impl<Msg, State, RefAux> StageBuildRef<Msg, State, RefAux> {
     /// Derive the handle that can later be used for sending messages to this stage.
     pub fn sender(&self) -> StageRef<Msg, Void> {
-        StageRef {
-            name: self.name.clone(),
-            _ph: PhantomData,
-        }
+        observe(&self.name);
+        StageRef::new(self.name.clone())
     }
 }
 
+/// Converts the messages sent via a [`StageRef::contramap`] into those of the target stage.
+type Adapter = Arc<dyn Fn(Box<dyn Message>) -> Box<dyn Message> + Send + Sync>;
+
 /// A handle for sending messages to a stage via the [`Effects`](crate::Effects) argument to the stage transition function.
 pub struct StageRef<Msg, State> {
     pub(crate) name: Name,
+    pub(crate) adapter: Option<Adapter>,
     pub(crate) _ph: PhantomData<(Msg, State)>,
 }
 
@@ -30,8 +32,10 @@ unsafe impl<Msg: Send, State: Send> Sync for StageRef<Msg, State> {}
 
 impl<Msg, State> Clone for StageRef<Msg, State> {
     fn clone(&self) -> Self {
+        observe(&self.name);
         Self {
             name: self.name.clone(),
+            adapter: self.adapter.clone(),
             _ph: PhantomData,
         }
     }
@@ -46,16 +50,63 @@ impl<Msg, State> fmt::Debug for StageRef<Msg, State> {
 }
 
 impl<Msg, State> StageRef<Msg, State> {
+    pub(crate) fn new(name: Name) -> Self {
+        Self {
+            name,
+            adapter: None,
+            _ph: PhantomData,
+        }
+    }
+
     pub fn name(&self) -> Name {
         self.name.clone()
     }
 
     pub fn without_state(&self) -> StageRef<Msg, Void> {
+        observe(&self.name);
         StageRef {
             name: self.name.clone(),
+            adapter: self.adapter.clone(),
             _ph: PhantomData,
         }
     }
+
+    /// Derive a handle for sending messages of another type to this stage, which converts
+    /// them with `f` before they are enqueued, so that stages can be wired together without
+    /// an adapter stage in between.
+    ///
+    /// The conversion runs in the sending stage, hence the sent messages are those of the
+    /// target stage, e.g. in the [`Effect::Send`](crate::Effect::Send) of a simulation.
+    pub fn contramap<NewMsg: Message>(
+        &self,
+        f: impl Fn(NewMsg) -> Msg + Send + Sync + 'static,
+    ) -> StageRef<NewMsg, Void>
+    where
+        Msg: Message,
+    {
+        observe(&self.name);
+        let inner = self.adapter.clone();
+        StageRef {
+            name: self.name.clone(),
+            adapter: Some(Arc::new(move |msg| {
+                let msg = cast_msg::<NewMsg>(msg).expect("internal messaging type error");
+                let msg: Box<dyn Message> = Box::new(f(msg));
+                match &inner {
+                    Some(inner) => inner(msg),
+                    None => msg,
+                }
+            })),
+            _ph: PhantomData,
+        }
+    }
+
+    /// Convert a message sent via this handle into one for the target stage.
+    pub(crate) fn adapt(&self, msg: Box<dyn Message>) -> Box<dyn Message> {
+        match &self.adapter {
+            Some(adapter) => adapter(msg),
+            None => msg,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -66,11 +117,9 @@ impl StageRef<(), ()> {
     /// construction, to initialize the stage state with a value that will later be replaced during
     /// the wiring phase of StageGraph construction.
     ///
-    /// If you attempt to use this handle to send messages, it will panic.
+    /// Messages sent to this handle are not delivered, they go to the
+    /// [dead letters](crate::StageGraph::dead_letters) instead, if configured.
     pub fn noop<Msg>() -> StageRef<Msg, Void> {
-        StageRef {
-            name: Name::from("noop"),
-            _ph: PhantomData,
-        }
+        StageRef::new(Name::from("noop"))
     }
 }
@@ -1,15 +1,23 @@
 use crate::{
     cast_msg,
-    effect::{StageEffect, StageResponse},
+    effect::{ExternalEffect, Matcher, StageEffect, StageResponse, SubTask},
     simulation::{airlock_effect, EffectBox},
-    BoxFuture, Instant, Message, Name, StageBuildRef, StageRef, State,
+    BoxFuture, DeadLetter, Instant, Message, Name, StageBuildRef, StageRef, State, StoreError,
+    Void,
+};
+use parking_lot::Mutex;
+use rand::{
+    distr::{Distribution, StandardUniform},
+    rngs::StdRng,
+    Rng,
 };
 use std::{
+    any::TypeId,
     fmt::Debug,
     future::Future,
     marker::PhantomData,
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
     time::Duration,
@@ -20,6 +28,8 @@ pub struct Effects<M, S> {
     me: StageRef<M, S>,
     effect: EffectBox,
     now: Arc<dyn Fn() -> Instant + Send + Sync>,
+    rng: Arc<Mutex<StdRng>>,
+    terminating: Arc<AtomicBool>,
 }
 
 impl<M, S> Clone for Effects<M, S> {
@@ -28,6 +38,8 @@ impl<M, S> Clone for Effects<M, S> {
             me: self.me.clone(),
             effect: self.effect.clone(),
             now: self.now.clone(),
+            rng: self.rng.clone(),
+            terminating: self.terminating.clone(),
         }
     }
 }
@@ -46,14 +58,60 @@ impl<M: Message, S> Effects<M, S> {
         me: StageRef<M, S>,
         effect: EffectBox,
         now: Arc<dyn Fn() -> Instant + Send + Sync>,
+        rng: Arc<Mutex<StdRng>>,
+        terminating: Arc<AtomicBool>,
     ) -> Self {
-        Self { me, effect, now }
+        Self {
+            me,
+            effect,
+            now,
+            rng,
+            terminating,
+        }
     }
 
     pub fn me(&self) -> StageRef<M, S> {
         self.me.clone()
     }
 
+    /// Wait for the first message in this stage’s mailbox that matches `predicate`, e.g. the
+    /// reply to a request, while the messages before it stay queued for later.
+    ///
+    /// The skipped messages still take up room in the mailbox, so a stage waiting for a message
+    /// that cannot enter its full mailbox waits forever.
+    pub fn receive_matching(
+        &self,
+        predicate: impl Fn(&M) -> bool + Send + 'static,
+    ) -> BoxFuture<'static, M> {
+        airlock_effect(
+            &self.effect,
+            StageEffect::ReceiveMatching(Matcher::new(predicate)),
+            |eff| match eff {
+                Some(StageResponse::MatchedMessage(msg)) => {
+                    Some(cast_msg::<M>(msg).expect("internal messaging type error"))
+                }
+                _ => None,
+            },
+        )
+    }
+
+    /// Take up to `max` of the messages queued in this stage’s mailbox, in the order it would
+    /// hand them out, without waiting for more, see [`StageGraph::batch_stage`].
+    pub fn receive_queued(&self, max: usize) -> BoxFuture<'static, Vec<M>> {
+        airlock_effect(
+            &self.effect,
+            StageEffect::ReceiveQueued(max),
+            |eff| match eff {
+                Some(StageResponse::QueuedMessages(msgs)) => Some(
+                    msgs.into_iter()
+                        .map(|msg| cast_msg::<M>(msg).expect("internal messaging type error"))
+                        .collect(),
+                ),
+                _ => None,
+            },
+        )
+    }
+
     pub fn send<Msg: Message, St>(
         &self,
         target: &StageRef<Msg, St>,
@@ -61,13 +119,36 @@ impl<M: Message, S> Effects<M, S> {
     ) -> BoxFuture<'static, ()> {
         airlock_effect(
             &self.effect,
-            StageEffect::Send(target.name(), Box::new(msg), None),
+            StageEffect::Send(target.name(), target.adapt(Box::new(msg)), None),
             |_eff| Some(()),
         )
     }
 
     pub fn interrupt(&self) -> BoxFuture<'static, ()> {
-        airlock_effect(&self.effect, StageEffect::Interrupt, |_eff| Some(()))
+        self.interrupt_with(())
+    }
+
+    /// Interrupt the simulation, handing `payload` to the test harness, and continue with the
+    /// decision it passes to
+    /// [`resume_interrupt_with`](crate::simulation::SimulationRunning::resume_interrupt_with).
+    ///
+    /// Outside of a simulation there is nobody to decide, so the stage continues right away
+    /// with the default decision.
+    pub fn interrupt_with<P: Message, D: Message + Default>(
+        &self,
+        payload: P,
+    ) -> BoxFuture<'static, D> {
+        airlock_effect(
+            &self.effect,
+            StageEffect::Interrupt(Box::new(payload), TypeId::of::<D>()),
+            |eff| match eff {
+                Some(StageResponse::InterruptResponse(decision)) => {
+                    Some(cast_msg::<D>(decision).expect("internal messaging type error"))
+                }
+                Some(StageResponse::Unit) => Some(D::default()),
+                _ => None,
+            },
+        )
     }
 
     pub fn clock(&self) -> BoxFuture<'static, Instant> {
@@ -84,6 +165,52 @@ impl<M: Message, S> Effects<M, S> {
         })
     }
 
+    /// Suspend the stage for the given duration, like [`wait`](Self::wait) but without
+    /// telling when it woke up.
+    pub fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        airlock_effect(&self.effect, StageEffect::Wait(duration), |eff| match eff {
+            Some(StageResponse::WaitResponse(_instant)) => Some(()),
+            _ => None,
+        })
+    }
+
+    /// Draw a random value, from the generator shared by all stages of a simulation, seeded
+    /// with [`with_seed`](crate::simulation::SimulationBuilder::with_seed), or from OS
+    /// randomness on Tokio.
+    ///
+    /// This doesn't suspend the stage: the values a simulation draws only depend on its seed
+    /// and on the order in which its stages run, which is deterministic.
+    pub fn random<T>(&self) -> T
+    where
+        StandardUniform: Distribution<T>,
+    {
+        self.rng.lock().random()
+    }
+
+    /// Deliver `msg` to this stage once the given delay has passed, without suspending the
+    /// stage meanwhile, e.g. to implement timeouts or periodic tasks.
+    ///
+    /// The message joins the mailbox like any other, but stages are not back-pressured by their
+    /// own timers: the simulation places it in a full mailbox regardless, while Tokio holds it
+    /// back on a task of its own until there is room.
+    pub fn schedule(&self, msg: M, after: Duration) -> BoxFuture<'static, ()> {
+        airlock_effect(
+            &self.effect,
+            StageEffect::Schedule(Box::new(msg), after),
+            |_eff| Some(()),
+        )
+    }
+
+    /// Send a request to `target` and wait for its response, or for `timeout` to pass.
+    ///
+    /// The request is built by `msg` around the [`CallRef`] with which the target responds,
+    /// using [`respond`](Self::respond): this is what routes the response back to this stage,
+    /// matched to the request by a unique [`CallId`]. A response arriving after the timeout is
+    /// dropped, and this returns `None`.
+    ///
+    /// In a simulation, the request shows up as a send effect, see
+    /// [`assert_call`](crate::Effect::assert_call), after which the stage waits for the
+    /// response on the simulated clock.
     pub fn call<Req: Message, Resp: Message, St>(
         &self,
         target: &StageRef<Req, St>,
@@ -93,17 +220,17 @@ impl<M: Message, S> Effects<M, S> {
         let (response, recv) = oneshot::channel();
         let now = (self.now)();
         let deadline = now.checked_add(timeout).expect("timeout too long");
-        let target = target.name();
         let me = self.me.name();
         let id = CallId::new();
 
-        let msg = Box::new(msg(CallRef {
+        let msg = target.adapt(Box::new(msg(CallRef {
             target: me,
             id,
             deadline,
             response,
             _ph: PhantomData,
-        }));
+        })));
+        let target = target.name();
 
         airlock_effect(
             &self.effect,
@@ -118,6 +245,102 @@ impl<M: Message, S> Effects<M, S> {
         )
     }
 
+    /// Make a request to the world outside of the stage graph and wait for its response, from
+    /// the handler registered for requests of this type, see [`ExternalEffect`].
+    pub fn external<E: ExternalEffect>(&self, request: E) -> BoxFuture<'static, E::Response> {
+        airlock_effect(
+            &self.effect,
+            StageEffect::External(Box::new(request)),
+            |eff| match eff {
+                Some(StageResponse::ExternalResponse(resp)) => {
+                    Some(cast_msg::<E::Response>(resp).expect("internal messaging type error"))
+                }
+                _ => None,
+            },
+        )
+    }
+
+    /// Start `task` as a sub-task of this stage, to run concurrently with it until it is
+    /// [joined](Self::join) using the returned handle.
+    ///
+    /// Tokio spawns the task right away, while a simulation runs it to completion when the
+    /// stage joins it, so that the order in which sub-tasks complete is up to the test. Hence
+    /// the task must not use the effects of the stage, whose transition is suspended meanwhile.
+    pub fn fork<T: Message>(
+        &self,
+        task: impl Future<Output = T> + Send + 'static,
+    ) -> BoxFuture<'static, ForkHandle<T>> {
+        let id = ForkId::new();
+        let task = SubTask(Box::pin(
+            async move { Box::new(task.await) as Box<dyn Message> },
+        ));
+        airlock_effect(&self.effect, StageEffect::Fork(id, task), move |_eff| {
+            Some(ForkHandle {
+                id,
+                _ph: PhantomData,
+            })
+        })
+    }
+
+    /// Wait for the result of a sub-task started with [`fork`](Self::fork).
+    pub fn join<T: Message>(&self, handle: ForkHandle<T>) -> BoxFuture<'static, T> {
+        airlock_effect(
+            &self.effect,
+            StageEffect::Join(handle.id),
+            |eff| match eff {
+                Some(StageResponse::Joined(result)) => {
+                    Some(cast_msg::<T>(result).expect("internal messaging type error"))
+                }
+                _ => None,
+            },
+        )
+    }
+
+    /// Stop this stage once the current transition has returned its state, which is then
+    /// handed to the [finalizer](StageGraph::finalizer) of the stage, if any.
+    ///
+    /// The messages still queued for the stage are dropped, and those sent to it afterwards
+    /// go to the [dead letters](StageGraph::dead_letters), if configured.
+    pub fn terminate(&self) {
+        self.terminating.store(true, Ordering::Relaxed);
+    }
+
+    /// Write `value` under `key` to the [`Store`](crate::Store) of the stage graph, e.g. to persist the state
+    /// of the stage across restarts, and wait for the write to complete.
+    ///
+    /// A simulation writes to its [`MemoryStore`](crate::MemoryStore), which can be made to fail
+    /// to test how the stage copes with the returned error.
+    pub fn persist(
+        &self,
+        key: impl Into<String>,
+        value: Vec<u8>,
+    ) -> BoxFuture<'static, Result<(), StoreError>> {
+        airlock_effect(
+            &self.effect,
+            StageEffect::Persist(key.into(), value),
+            |eff| match eff {
+                Some(StageResponse::Persisted(result)) => Some(result),
+                _ => None,
+            },
+        )
+    }
+
+    /// Read the value under `key` from the [`Store`](crate::Store) of the stage graph, `None` if there is
+    /// none, see [`persist`](Self::persist).
+    pub fn load(
+        &self,
+        key: impl Into<String>,
+    ) -> BoxFuture<'static, Result<Option<Vec<u8>>, StoreError>> {
+        airlock_effect(
+            &self.effect,
+            StageEffect::Load(key.into()),
+            |eff| match eff {
+                Some(StageResponse::Loaded(result)) => Some(result),
+                _ => None,
+            },
+        )
+    }
+
     pub fn respond<Resp: Message>(&self, cr: CallRef<Resp>, resp: Resp) -> BoxFuture<'static, ()> {
         let CallRef {
             target,
@@ -193,6 +416,46 @@ impl<Resp: Message> CallRef<Resp> {
     }
 }
 
+/// The id of a sub-task, see [`Effects::fork`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ForkId(u64);
+
+impl ForkId {
+    fn new() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        Self(COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// The handle of a sub-task started with [`Effects::fork`], to [join](Effects::join) it.
+pub struct ForkHandle<T> {
+    pub(crate) id: ForkId,
+    _ph: PhantomData<T>,
+}
+
+impl<T> ForkHandle<T> {
+    pub fn id(&self) -> ForkId {
+        self.id
+    }
+}
+
+impl<T> Debug for ForkHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ForkHandle").field("id", &self.id).finish()
+    }
+}
+
+/// How the stages of a graph deal with their queued messages when it is shut down, see
+/// [`SimulationRunning::shutdown`](crate::simulation::SimulationRunning::shutdown) and
+/// [`TokioRunning::shutdown`](crate::tokio::TokioRunning::shutdown).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownPolicy {
+    /// Process the messages already queued before stopping.
+    Drain,
+    /// Finish the transition in progress, if any, and drop the queued messages.
+    FinishCurrent,
+}
+
 /// A factory for processing network stages and their wiring.
 ///
 /// Network construction proceeds in two phases:
@@ -200,8 +463,8 @@ impl<Resp: Message> CallRef<Resp> {
 ///    [dummy stage references](StageRef::noop) for messaging targets
 /// 2. wire up the stages by injecting the real [`StageRef`](StageRef) messaging targets
 ///
-/// Sending to a `noop` target does nothing, it also doesn’t suspend the stage to create
-/// an effect when using [`SimulationBuilder`](crate::simulation::SimulationBuilder).
+/// Sending to a `noop` target delivers the message to the [dead letters](StageGraph::dead_letters)
+/// if there are any, and drops it otherwise.
 /// If you forget to call [`wire_up`](StageGraph::wire_up) on
 ///
 /// Example:
@@ -266,6 +529,65 @@ pub trait StageGraph {
         F: FnMut(St, Msg, Effects<Msg, St>) -> Fut + 'static + Send,
         Fut: Future<Output = anyhow::Result<St>> + 'static + Send;
 
+    /// Deliver the messages to the given stage by priority, e.g. to handle rollbacks before
+    /// the forwards that preceded them: the mailbox hands out the queued message of highest
+    /// `priority` first, and messages of equal priority in the order they arrived.
+    ///
+    /// Priorities don't extend past the mailbox, a full one still holds back a message to it
+    /// whichever its priority. A simulation records such cases, see
+    /// [`priority_inversions`](crate::simulation::SimulationRunning::priority_inversions).
+    fn prioritize<Msg: Message, St>(
+        &mut self,
+        stage: &StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
+        priority: impl Fn(&Msg) -> u8 + Send + Sync + 'static,
+    );
+
+    /// Drop the messages sent to the given stage while its mailbox is full instead of holding
+    /// back their senders, e.g. for a stage that only needs the most recent updates.
+    ///
+    /// The dropped messages go to the [dead letters](StageGraph::dead_letters), if configured.
+    fn drop_overflow<Msg: Message, St>(
+        &mut self,
+        stage: &StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
+    );
+
+    /// Deliver the messages that cannot be delivered to the given stage as [`DeadLetter`]s, so
+    /// that nothing is discarded silently: those sent to a [`StageRef::noop`], to a stage that
+    /// has failed, or to a stage that [drops the overflow](StageGraph::drop_overflow) of its
+    /// full mailbox.
+    ///
+    /// Without dead letters, messages sent to a failed stage are handled as before (queued in a
+    /// simulation, failing the sender on Tokio) while the others are dropped with a warning;
+    /// dead letters that find the mailbox of this stage full are dropped with a warning, too.
+    fn dead_letters<St>(&mut self, stage: &StageRef<DeadLetter, St>);
+
+    /// Run `f` with the final state of the given stage when it stops, after it has
+    /// [terminated](Effects::terminate) itself or when the graph is shut down, e.g. to flush
+    /// what the stage has buffered.
+    ///
+    /// A stage that fails, or that is dropped in the middle of a transition during shutdown,
+    /// has no final state, so `f` is not run then.
+    fn finalizer<Msg: Message, St: State>(
+        &mut self,
+        stage: &StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
+        f: impl FnOnce(St) + Send + 'static,
+    );
+
+    /// Answer the [external effects](Effects::external) of type `E` with `handler`.
+    ///
+    /// On Tokio, the handler performs the actual I/O while the requesting stage waits for it,
+    /// and a stage making a request of a type without handler fails.
+    ///
+    /// A simulation answers with scripted responses or failures, so it polls the future
+    /// returned by the handler only once: a request whose handler isn't ready by then, or which
+    /// has no handler, leaves the stage waiting for the test to answer it with
+    /// [`resume_external`](crate::simulation::SimulationRunning::resume_external).
+    fn external<E, F, Fut>(&mut self, handler: F)
+    where
+        E: ExternalEffect,
+        F: Fn(E) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = E::Response> + Send + 'static;
+
     /// Finalize the given stage.
     ///
     /// A mutable reference to the stage’s state is provided, mainly for the purpose of
@@ -287,4 +609,76 @@ pub trait StageGraph {
     /// a task while [`SimulationBuilder`](crate::simulation::SimulationBuilder) won’t
     /// run anything unless explicitly requested by a test procedure.
     fn run(self) -> Self::Running;
+
+    /// Render the stages and their wiring as a [Graphviz](https://graphviz.org) DOT digraph,
+    /// with an edge to each target handed to a stage in [`wire_up`](StageGraph::wire_up),
+    /// i.e. each [`StageRef`] derived or cloned there.
+    ///
+    /// The running graphs of [`SimulationRunning`](crate::simulation::SimulationRunning) and
+    /// [`TokioRunning`](crate::tokio::TokioRunning) add dashed edges for the sends seen only
+    /// at runtime, e.g. to targets passed along in messages.
+    fn export_dot(&self) -> String;
+
+    /// Create a stage whose transition function processes the messages in batches, e.g. to
+    /// write them to a store at once: each call receives the next message along with those
+    /// queued behind it, up to `max_batch` messages in total.
+    ///
+    /// The stage receives the first message of a batch as usual and takes the others with
+    /// [`receive_queued`](Effects::receive_queued), so it never waits for a batch to fill up.
+    fn batch_stage<Msg: Message, St: State, F, Fut>(
+        &mut self,
+        name: impl AsRef<str>,
+        max_batch: usize,
+        f: F,
+        state: St,
+    ) -> StageBuildRef<Msg, St, Self::RefAux<Msg, St>>
+    where
+        F: FnMut(St, Vec<Msg>, Effects<Msg, St>) -> Fut + 'static + Send,
+        Fut: Future<Output = anyhow::Result<St>> + 'static + Send,
+        Self: Sized,
+    {
+        assert!(max_batch > 0, "batches must hold at least one message");
+        let f = Arc::new(Mutex::new(f));
+        self.stage(
+            name,
+            move |state: St, msg: Msg, eff: Effects<Msg, St>| {
+                let f = f.clone();
+                async move {
+                    let mut batch = vec![msg];
+                    if max_batch > 1 {
+                        batch.extend(eff.receive_queued(max_batch - 1).await);
+                    }
+                    let transition = (*f.lock())(state, batch, eff);
+                    transition.await
+                }
+            },
+            state,
+        )
+    }
+
+    /// Create a stage that sends a copy of each message it receives to each of `targets`, in
+    /// the given order, waiting for each send to complete before the next one.
+    ///
+    /// Each of these is an ordinary send effect: a full mailbox of any target holds back the
+    /// delivery to those after it, and a simulation shows them one by one.
+    fn fan_out<Msg: Message + Clone>(
+        &mut self,
+        name: impl AsRef<str>,
+        targets: Vec<StageRef<Msg, Void>>,
+    ) -> StageRef<Msg, Vec<StageRef<Msg, Void>>>
+    where
+        Self: Sized,
+    {
+        let stage = self.stage(
+            name,
+            async |targets: Vec<StageRef<Msg, Void>>, msg: Msg, eff| {
+                for target in &targets {
+                    eff.send(target, msg.clone()).await;
+                }
+                Ok(targets)
+            },
+            Vec::new(),
+        );
+        self.wire_up(stage, |state| state.clone_from(&targets))
+    }
 }
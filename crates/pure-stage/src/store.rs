@@ -0,0 +1,74 @@
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, sync::Arc};
+
+/// A failure of the [`Store`] behind [`Effects::persist`](crate::Effects::persist) and
+/// [`Effects::load`](crate::Effects::load), which the stage may handle, e.g. by retrying.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("store failure: {0}")]
+pub struct StoreError(pub String);
+
+/// The key-value store in which stages persist their state, e.g. the tip of the chain they
+/// selected, with [`Effects::persist`](crate::Effects::persist) and
+/// [`Effects::load`](crate::Effects::load).
+///
+/// Simulations use a [`MemoryStore`], while a deployment binds the actual storage with
+/// [`TokioBuilder::store`](crate::tokio::TokioBuilder::store).
+pub trait Store: Send + Sync + 'static {
+    fn put(&self, key: &str, value: Vec<u8>) -> Result<(), StoreError>;
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError>;
+}
+
+/// An in-memory [`Store`], e.g. the one of a simulation, see
+/// [`SimulationBuilder::store`](crate::simulation::SimulationBuilder::store).
+///
+/// Clones of it share the same contents, so that a test can inspect them and inject faults
+/// while the stages use the store.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    inner: Arc<Mutex<MemoryStoreInner>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryStoreInner {
+    contents: BTreeMap<String, Vec<u8>>,
+    failing_writes: usize,
+    failing_reads: usize,
+}
+
+impl MemoryStore {
+    /// Make the next `count` writes fail, leaving the contents as they are.
+    pub fn fail_writes(&self, count: usize) {
+        self.inner.lock().failing_writes = count;
+    }
+
+    /// Make the next `count` reads fail.
+    pub fn fail_reads(&self, count: usize) {
+        self.inner.lock().failing_reads = count;
+    }
+
+    /// The current contents of the store, ordered by key.
+    pub fn contents(&self) -> BTreeMap<String, Vec<u8>> {
+        self.inner.lock().contents.clone()
+    }
+}
+
+impl Store for MemoryStore {
+    fn put(&self, key: &str, value: Vec<u8>) -> Result<(), StoreError> {
+        let mut inner = self.inner.lock();
+        if inner.failing_writes > 0 {
+            inner.failing_writes -= 1;
+            return Err(StoreError(format!("injected failure writing `{key}`")));
+        }
+        inner.contents.insert(key.to_owned(), value);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        let mut inner = self.inner.lock();
+        if inner.failing_reads > 0 {
+            inner.failing_reads -= 1;
+            return Err(StoreError(format!("injected failure reading `{key}`")));
+        }
+        Ok(inner.contents.get(key).cloned())
+    }
+}
@@ -1,23 +1,40 @@
 use crate::{
     cast_msg,
-    effect::{StageEffect, StageResponse},
-    simulation::EffectBox,
-    BoxFuture, Effects, Instant, Message, Name, StageBuildRef, StageGraph, StageRef, State,
+    effect::{ExternalEffect, Matcher, StageEffect, StageResponse},
+    simulation::{
+        finalizer, highest_priority, priority, stage_span, EffectBox, Finalizer, Priority,
+    },
+    wiring::{observe_targets, Wiring},
+    BoxFuture, DeadLetter, DeadLetterReason, Effects, ForkId, Instant, Message, Metrics, Name,
+    ShutdownPolicy, StageBuildRef, StageGraph, StageMetrics, StageRef, State, Store, StoreError,
+    Void,
 };
 use either::Either::{Left, Right};
 use parking_lot::Mutex;
+use rand::{rngs::StdRng, SeedableRng};
 use std::{
-    collections::HashMap,
-    future::Future,
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet, VecDeque},
+    future::{poll_fn, Future},
     marker::PhantomData,
-    sync::Arc,
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     task::{Context, Poll, Waker},
 };
 use tokio::{
     spawn,
-    sync::mpsc::{self, Receiver},
+    sync::mpsc::{
+        self,
+        error::{TryRecvError, TrySendError},
+        Receiver, UnboundedReceiver,
+    },
+    sync::watch,
     task::JoinHandle,
 };
+use tracing::{Instrument, Span};
 
 #[derive(Debug, thiserror::Error)]
 #[error("message send failed to stage `{target}`")]
@@ -25,25 +42,130 @@ pub struct SendError {
     target: Name,
 }
 
+/// The sending side of a mailbox, carrying the span in which each message was sent.
+type MailboxSender = mpsc::Sender<(Box<dyn Message>, Span)>;
+
+/// The handler of the requests of some [`ExternalEffect`] type.
+type ExternalHandler =
+    Arc<dyn Fn(Box<dyn Message>) -> BoxFuture<'static, Box<dyn Message>> + Send + Sync>;
+
 struct TokioInner {
-    senders: HashMap<Name, mpsc::Sender<Box<dyn Message>>>,
+    senders: HashMap<Name, MailboxSender>,
     mailbox_size: usize,
+    rng: Arc<Mutex<StdRng>>,
+    externals: HashMap<TypeId, ExternalHandler>,
+    wiring: Mutex<Wiring>,
+    metrics: HashMap<Name, Arc<Mutex<StageMetrics>>>,
+    drops_overflow: HashSet<Name>,
+    dead_letters: Option<Name>,
+    store: Option<Arc<dyn Store>>,
+    shutdown: watch::Sender<Option<ShutdownPolicy>>,
 }
 
-/// A [`StageGraph`] implementation that dispatches each stage as a task on the Tokio global pool.
+/// A [`StageGraph`] implementation that dispatches each stage as a task on the Tokio runtime,
+/// to deploy the stages tested with [`SimulationBuilder`](crate::simulation::SimulationBuilder).
+///
+/// Each stage has a bounded mailbox, so that a stage sending to a full one waits for room in
+/// it, and effects take real time: a stage waiting for one second does so on the Tokio clock.
+/// As in a simulation, a stage processes each message within a span that is a child of the
+/// span in which the message was sent.
+/// A [prioritized](StageGraph::prioritize) stage moves up to a mailbox worth of messages out of
+/// its mailbox to pick the one to process next, so it holds up to twice as many.
+/// Messages are fed to the network through the [`Input`] handles of [`TokioRunning`], and
+/// [`run`](StageGraph::run) spawns the tasks, so it has to be called within a Tokio runtime.
+///
+/// Example:
+/// ```rust
+/// use pure_stage::{StageGraph, tokio::TokioBuilder, StageRef};
 ///
-/// *This is currently only a minimal sketch that will likely not fit the intended design.
-/// It is more likely that the effect handling will be done like in the [`SimulationBuilder`](crate::simulation::SimulationBuilder)
-/// implementation.*
+/// # tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap().block_on(async {
+/// let mut network = TokioBuilder::default();
+/// let stage = network.stage(
+///     "basic",
+///     async |(mut state, out), msg: u32, eff| {
+///         state += msg;
+///         eff.send(&out, state).await;
+///         Ok((state, out))
+///     },
+///     (1u32, StageRef::noop::<u32>()),
+/// );
+/// let (output, mut rx) = network.output("output");
+/// let stage = network.wire_up(stage, |state| state.1 = output.without_state());
+/// let running = network.run();
+///
+/// running.input(&stage).send(1).await.unwrap();
+/// assert_eq!(rx.recv().await, Some(2));
+/// running.abort();
+/// # });
+/// ```
 pub struct TokioBuilder {
     tasks: Vec<Box<dyn FnOnce(Arc<TokioInner>) -> BoxFuture<'static, anyhow::Result<()>>>>,
+    priorities: HashMap<Name, Priority>,
+    finalizers: HashMap<Name, Finalizer>,
     inner: TokioInner,
 }
 
+impl Default for TokioBuilder {
+    fn default() -> Self {
+        Self {
+            tasks: Vec::new(),
+            priorities: HashMap::new(),
+            finalizers: HashMap::new(),
+            inner: TokioInner {
+                senders: HashMap::new(),
+                mailbox_size: 10,
+                rng: Arc::new(Mutex::new(StdRng::from_os_rng())),
+                externals: HashMap::new(),
+                wiring: Mutex::new(Wiring::default()),
+                metrics: HashMap::new(),
+                drops_overflow: HashSet::new(),
+                dead_letters: None,
+                store: None,
+                shutdown: watch::channel(None).0,
+            },
+        }
+    }
+}
+
+impl TokioBuilder {
+    /// Set the number of messages the mailbox of each stage created afterwards holds.
+    pub fn with_mailbox_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "mailboxes must hold at least one message");
+        self.inner.mailbox_size = size;
+        self
+    }
+
+    /// Answer the [`persist`](crate::Effects::persist) and [`load`](crate::Effects::load)
+    /// effects with `store`, e.g. the on-disk store of the node.
+    ///
+    /// Its operations run on the blocking thread pool of Tokio while the stage waits.
+    pub fn store(&mut self, store: impl Store) {
+        self.inner.store = Some(Arc::new(store));
+    }
+
+    /// Construct a stage that sends received messages to an [`UnboundedReceiver`] that is
+    /// also returned, for the outside world to consume the output of the network.
+    pub fn output<T: Message>(
+        &mut self,
+        name: impl AsRef<str>,
+    ) -> (StageRef<T, ()>, UnboundedReceiver<T>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let stage = self.stage(
+            &name,
+            move |_st, msg, _eff| {
+                let tx = tx.clone();
+                async move { tx.send(msg).map_err(|_| anyhow::anyhow!("channel closed")) }
+            },
+            (),
+        );
+        (self.wire_up(stage, |_| {}), rx)
+    }
+}
+
 impl StageGraph for TokioBuilder {
     type Running = TokioRunning;
     type RefAux<Msg, State> = (
-        Receiver<Box<dyn Message>>,
+        Receiver<(Box<dyn Message>, Span)>,
         Box<
             dyn FnMut(State, Msg, Effects<Msg, State>) -> BoxFuture<'static, anyhow::Result<State>>
                 + 'static
@@ -65,8 +187,10 @@ impl StageGraph for TokioBuilder {
         if self.inner.senders.contains_key(&name) {
             panic!("stage named `{name}` already exists");
         }
-        let (tx, rx) = mpsc::channel(self.inner.mailbox_size / 2);
+        let (tx, rx) = mpsc::channel(self.inner.mailbox_size);
         self.inner.senders.insert(name.clone(), tx);
+        self.inner.wiring.get_mut().add_stage(&name);
+        self.inner.metrics.insert(name.clone(), Default::default());
         StageBuildRef {
             name,
             state,
@@ -78,6 +202,47 @@ impl StageGraph for TokioBuilder {
         }
     }
 
+    fn prioritize<Msg: Message, St>(
+        &mut self,
+        stage: &StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
+        f: impl Fn(&Msg) -> u8 + Send + Sync + 'static,
+    ) {
+        self.priorities.insert(stage.name.clone(), priority(f));
+    }
+
+    fn drop_overflow<Msg: Message, St>(
+        &mut self,
+        stage: &StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
+    ) {
+        self.inner.drops_overflow.insert(stage.name.clone());
+    }
+
+    fn dead_letters<St>(&mut self, stage: &StageRef<DeadLetter, St>) {
+        self.inner.dead_letters = Some(stage.name());
+    }
+
+    fn finalizer<Msg: Message, St: State>(
+        &mut self,
+        stage: &StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
+        f: impl FnOnce(St) + Send + 'static,
+    ) {
+        self.finalizers.insert(stage.name.clone(), finalizer(f));
+    }
+
+    fn external<E, F, Fut>(&mut self, handler: F)
+    where
+        E: ExternalEffect,
+        F: Fn(E) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = E::Response> + Send + 'static,
+    {
+        let handler: ExternalHandler = Arc::new(move |request| {
+            let request = cast_msg::<E>(request).expect("internal messaging type error");
+            let response = handler(request);
+            Box::pin(async move { Box::new(response.await) as Box<dyn Message> })
+        });
+        self.inner.externals.insert(TypeId::of::<E>(), handler);
+    }
+
     fn wire_up<Msg: Message, St: State>(
         &mut self,
         stage: StageBuildRef<Msg, St, Self::RefAux<Msg, St>>,
@@ -86,50 +251,201 @@ impl StageGraph for TokioBuilder {
         let StageBuildRef {
             name,
             mut state,
-            network: (mut rx, mut ff),
+            network: (rx, mut ff),
             _ph,
         } = stage;
-        f(&mut state);
+        let targets = observe_targets(|| f(&mut state));
+        self.inner.wiring.get_mut().wire_up(&name, targets);
+        let mut mailbox = Mailbox {
+            rx,
+            priority: self.priorities.remove(&name),
+            buffer: VecDeque::new(),
+        };
+        let finalizer = self.finalizers.remove(&name);
         let stage_name = name.clone();
+        let metrics = self.inner.metrics[&name].clone();
         self.tasks.push(Box::new(move |inner| {
             Box::pin(async move {
-                let me = StageRef {
-                    name: stage_name.clone(),
-                    _ph: PhantomData,
-                };
+                let me = StageRef::new(stage_name.clone());
                 let effect = Arc::new(Mutex::new(None));
                 let now = Arc::new(|| Instant::from_tokio(tokio::time::Instant::now()));
-                let effects = Effects::new(me, effect.clone(), now);
-                while let Some(msg) = rx.recv().await {
-                    state = interpreter(
+                let terminating = Arc::new(AtomicBool::new(false));
+                let effects = Effects::new(
+                    me,
+                    effect.clone(),
+                    now,
+                    inner.rng.clone(),
+                    terminating.clone(),
+                );
+                let mut shutdown = inner.shutdown.subscribe();
+                let mut forks = HashMap::new();
+                loop {
+                    let policy = *shutdown.borrow_and_update();
+                    let next = match policy {
+                        None => {
+                            let mut recv = pin!(mailbox.recv(inner.mailbox_size));
+                            let mut changed = pin!(shutdown.changed());
+                            let next = poll_fn(|cx| match recv.as_mut().poll(cx) {
+                                Poll::Ready(msg) => Poll::Ready(Some(msg)),
+                                Poll::Pending => changed.as_mut().poll(cx).map(|_| None),
+                            })
+                            .await;
+                            // the shutdown has been signalled, so check its policy again
+                            let Some(next) = next else {
+                                continue;
+                            };
+                            next
+                        }
+                        Some(ShutdownPolicy::Drain) => mailbox.try_recv(inner.mailbox_size),
+                        Some(ShutdownPolicy::FinishCurrent) => None,
+                    };
+                    let Some((msg, sent_in)) = next else {
+                        break;
+                    };
+                    metrics.lock().received(mailbox.len() + 1);
+                    let received_at = tokio::time::Instant::now();
+                    let span = stage_span(&stage_name, &sent_in);
+                    let result = interpreter(
                         &inner,
                         &effect,
                         &stage_name,
+                        &span,
+                        &mut mailbox,
+                        &mut forks,
                         ff(
                             state,
                             cast_msg(msg).expect("internal message type error"),
                             effects.clone(),
                         ),
                     )
-                    .await
-                    .inspect_err(|err| {
+                    .instrument(span.clone())
+                    .await;
+                    metrics
+                        .lock()
+                        .processed(received_at.elapsed(), result.is_ok());
+                    state = result.inspect_err(|err| {
                         tracing::error!("stage `{}` error: {:?}", stage_name, err);
                     })?;
+                    if terminating.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+                if let Some(finalizer) = finalizer {
+                    finalizer(Box::new(state));
                 }
                 Ok(())
             })
         }));
-        StageRef {
-            name,
-            _ph: PhantomData,
-        }
+        StageRef::new(name)
+    }
+
+    fn export_dot(&self) -> String {
+        self.inner.wiring.lock().to_dot()
     }
 
     fn run(self) -> Self::Running {
-        let Self { tasks, inner } = self;
+        let Self {
+            tasks,
+            priorities: _,
+            finalizers: _,
+            inner,
+        } = self;
         let inner = Arc::new(inner);
         let handles = tasks.into_iter().map(|t| spawn(t(inner.clone()))).collect();
-        TokioRunning { handles }
+        TokioRunning { handles, inner }
+    }
+}
+
+/// The receiving side of a mailbox, picking the next message by priority if the stage has one.
+///
+/// The buffer holds the messages moved out of the channel to pick one of them, either by
+/// priority or by a selective receive.
+struct Mailbox {
+    rx: Receiver<(Box<dyn Message>, Span)>,
+    priority: Option<Priority>,
+    buffer: VecDeque<(Box<dyn Message>, Span)>,
+}
+
+impl Mailbox {
+    fn len(&self) -> usize {
+        self.rx.len() + self.buffer.len()
+    }
+
+    async fn recv(&mut self, mailbox_size: usize) -> Option<(Box<dyn Message>, Span)> {
+        let Some(priority) = &self.priority else {
+            if let Some(msg) = self.buffer.pop_front() {
+                return Some(msg);
+            }
+            return self.rx.recv().await;
+        };
+        while self.buffer.len() < mailbox_size {
+            match self.rx.try_recv() {
+                Ok(msg) => self.buffer.push_back(msg),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+            }
+        }
+        match highest_priority(self.buffer.iter().map(|(msg, _)| priority(&**msg))) {
+            Some((idx, _)) => self.buffer.remove(idx),
+            None => self.rx.recv().await,
+        }
+    }
+
+    /// Take the next queued message without waiting for one, by priority if the stage has one.
+    fn try_recv(&mut self, mailbox_size: usize) -> Option<(Box<dyn Message>, Span)> {
+        let Some(priority) = &self.priority else {
+            return self.buffer.pop_front().or_else(|| self.rx.try_recv().ok());
+        };
+        while self.buffer.len() < mailbox_size {
+            match self.rx.try_recv() {
+                Ok(msg) => self.buffer.push_back(msg),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+            }
+        }
+        let (idx, _) = highest_priority(self.buffer.iter().map(|(msg, _)| priority(&**msg)))?;
+        self.buffer.remove(idx)
+    }
+
+    /// Take up to `max` of the queued messages, by priority if the stage has one.
+    fn recv_queued(&mut self, max: usize) -> Vec<Box<dyn Message>> {
+        while self.buffer.len() < max {
+            match self.rx.try_recv() {
+                Ok(msg) => self.buffer.push_back(msg),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+            }
+        }
+        let mut msgs = Vec::new();
+        while msgs.len() < max {
+            let next = match &self.priority {
+                Some(priority) => {
+                    highest_priority(self.buffer.iter().map(|(msg, _)| priority(&**msg)))
+                        .and_then(|(idx, _)| self.buffer.remove(idx))
+                }
+                None => self.buffer.pop_front(),
+            };
+            let Some((msg, _sent_in)) = next else {
+                break;
+            };
+            msgs.push(msg);
+        }
+        msgs
+    }
+
+    /// Take the first message matching `matcher`, moving the others to the buffer.
+    async fn recv_matching(&mut self, matcher: &Matcher) -> Option<Box<dyn Message>> {
+        if let Some(idx) = self
+            .buffer
+            .iter()
+            .position(|(msg, _)| matcher.matches(&**msg))
+        {
+            return self.buffer.remove(idx).map(|(msg, _)| msg);
+        }
+        loop {
+            let (msg, sent_in) = self.rx.recv().await?;
+            if matcher.matches(&*msg) {
+                return Some(msg);
+            }
+            self.buffer.push_back((msg, sent_in));
+        }
     }
 }
 
@@ -137,6 +453,9 @@ async fn interpreter<St>(
     inner: &TokioInner,
     effect: &EffectBox,
     name: &Name,
+    span: &Span,
+    mailbox: &mut Mailbox,
+    forks: &mut HashMap<ForkId, JoinHandle<Box<dyn Message>>>,
     mut stage: BoxFuture<'static, anyhow::Result<St>>,
 ) -> anyhow::Result<St> {
     loop {
@@ -151,14 +470,18 @@ async fn interpreter<St>(
             StageEffect::Receive => {
                 panic!("effect Receive cannot be explicitly awaited (stage `{name}`)")
             }
-            StageEffect::Send(name, msg, call) => {
-                let tx = inner
-                    .senders
-                    .get(&name)
-                    .expect("stage ref contained unknown name");
-                tx.send(msg).await.map_err(|_| SendError {
-                    target: name.clone(),
-                })?;
+            StageEffect::ReceiveMatching(matcher) => {
+                let msg = mailbox
+                    .recv_matching(&matcher)
+                    .await
+                    .ok_or_else(|| anyhow::anyhow!("mailbox of stage `{name}` was closed"))?;
+                StageResponse::MatchedMessage(msg)
+            }
+            StageEffect::ReceiveQueued(max) => {
+                StageResponse::QueuedMessages(mailbox.recv_queued(max))
+            }
+            StageEffect::Send(target, msg, call) => {
+                send(inner, name, target, msg, span).await?;
                 if let Some((d, rx, _id)) = call {
                     tokio::time::timeout(d, rx)
                         .await
@@ -175,6 +498,22 @@ async fn interpreter<St>(
                 tokio::time::sleep(duration).await;
                 StageResponse::WaitResponse(now())
             }
+            StageEffect::Schedule(msg, after) => {
+                let tx = inner
+                    .senders
+                    .get(name)
+                    .expect("stage ref contained unknown name")
+                    .clone();
+                let name = name.clone();
+                let span = span.clone();
+                spawn(async move {
+                    tokio::time::sleep(after).await;
+                    if tx.send((msg, span)).await.is_err() {
+                        tracing::warn!("scheduled message to stopped stage `{name}` was dropped");
+                    }
+                });
+                StageResponse::Unit
+            }
             StageEffect::Call(..) => {
                 panic!("StageEffect::Call cannot be explicitly awaited (stage `{name}`")
             }
@@ -189,26 +528,214 @@ async fn interpreter<St>(
                 }
                 StageResponse::Unit
             }
-            StageEffect::Interrupt => {
-                tracing::debug!("stage `{name}` interrupt");
+            StageEffect::External(request) => {
+                let handler = inner
+                    .externals
+                    .get(&(&*request as &dyn Any).type_id())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "no handler for external effect {request:?} (stage `{name}`)"
+                        )
+                    })?;
+                StageResponse::ExternalResponse(handler(request).await)
+            }
+            StageEffect::Interrupt(payload, _decision) => {
+                tracing::debug!("stage `{name}` interrupt: {payload:?}");
+                StageResponse::Unit
+            }
+            StageEffect::Persist(key, value) => {
+                let store = store(inner, name);
+                StageResponse::Persisted(
+                    tokio::task::spawn_blocking(move || store.put(&key, value))
+                        .await
+                        .unwrap_or_else(|err| Err(StoreError(err.to_string()))),
+                )
+            }
+            StageEffect::Load(key) => {
+                let store = store(inner, name);
+                StageResponse::Loaded(
+                    tokio::task::spawn_blocking(move || store.get(&key))
+                        .await
+                        .unwrap_or_else(|err| Err(StoreError(err.to_string()))),
+                )
+            }
+            StageEffect::Fork(id, task) => {
+                forks.insert(id, spawn(task.0.instrument(span.clone())));
                 StageResponse::Unit
             }
+            StageEffect::Join(id) => {
+                let task = forks
+                    .remove(&id)
+                    .ok_or_else(|| anyhow::anyhow!("stage `{name}` has no sub-task {id:?}"))?;
+                let result = task.await.map_err(|err| {
+                    anyhow::anyhow!("sub-task {id:?} of stage `{name}` failed: {err}")
+                })?;
+                StageResponse::Joined(result)
+            }
         };
         *effect.lock() = Some(Right(resp));
     }
 }
 
+/// The store for the persistence effects of the given stage.
+fn store(inner: &TokioInner, name: &Name) -> Arc<dyn Store> {
+    let Some(store) = &inner.store else {
+        panic!("no store for persistence effect (stage `{name}`)");
+    };
+    store.clone()
+}
+
+/// Deliver `msg` to `target`, or to the dead letters if it cannot be delivered.
+async fn send(
+    inner: &TokioInner,
+    from: &Name,
+    target: Name,
+    msg: Box<dyn Message>,
+    span: &Span,
+) -> Result<(), SendError> {
+    let Some(tx) = inner.senders.get(&target) else {
+        dead_letter(inner, from, target, msg, span, DeadLetterReason::Noop);
+        return Ok(());
+    };
+    inner.wiring.lock().sent(from, &target);
+    let closed = if inner.drops_overflow.contains(&target) {
+        match tx.try_send((msg, span.clone())) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full((msg, _span))) => {
+                dead_letter(inner, from, target, msg, span, DeadLetterReason::Overflow);
+                return Ok(());
+            }
+            Err(TrySendError::Closed((msg, _span))) => msg,
+        }
+    } else {
+        match tx.send((msg, span.clone())).await {
+            Ok(()) => return Ok(()),
+            Err(mpsc::error::SendError((msg, _span))) => msg,
+        }
+    };
+    if inner.dead_letters.is_none() {
+        return Err(SendError { target });
+    }
+    dead_letter(
+        inner,
+        from,
+        target,
+        closed,
+        span,
+        DeadLetterReason::Terminated,
+    );
+    Ok(())
+}
+
+/// Hand an undeliverable message to the dead letters, dropping it with a warning if there are
+/// none or their mailbox is full.
+fn dead_letter(
+    inner: &TokioInner,
+    from: &Name,
+    to: Name,
+    msg: Box<dyn Message>,
+    span: &Span,
+    reason: DeadLetterReason,
+) {
+    let letter = DeadLetter {
+        from: from.clone(),
+        to,
+        reason,
+        msg,
+    };
+    let Some(tx) = inner
+        .dead_letters
+        .as_ref()
+        .and_then(|name| inner.senders.get(name))
+    else {
+        tracing::warn!("undeliverable message dropped: {letter:?}");
+        return;
+    };
+    if let Err(err) = tx.try_send((Box::new(letter), span.clone())) {
+        let (letter, _span) = err.into_inner();
+        tracing::warn!("dead letters are full or stopped, dropped: {letter:?}");
+    }
+}
+
 fn now() -> Instant {
     Instant::from_tokio(tokio::time::Instant::now())
 }
 
+/// A handle for sending messages to a stage from outside the network, see
+/// [`TokioRunning::input`].
+pub struct Input<Msg> {
+    target: StageRef<Msg, Void>,
+    tx: MailboxSender,
+}
+
+impl<Msg> Clone for Input<Msg> {
+    fn clone(&self) -> Self {
+        Self {
+            target: self.target.clone(),
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<Msg: Message> Input<Msg> {
+    /// Send a message to the stage, waiting for room in its mailbox. The stage processes it
+    /// within a child of the current span.
+    ///
+    /// Fails if the stage has stopped, e.g. because its transition function failed.
+    pub async fn send(&self, msg: Msg) -> Result<(), SendError> {
+        let msg = (self.target.adapt(Box::new(msg)), Span::current());
+        self.tx.send(msg).await.map_err(|_| SendError {
+            target: self.target.name(),
+        })
+    }
+}
+
 /// Handle to the running stages.
 #[must_use = "this handle needs to be either joined or aborted"]
 pub struct TokioRunning {
     handles: Vec<JoinHandle<anyhow::Result<()>>>,
+    inner: Arc<TokioInner>,
 }
 
 impl TokioRunning {
+    /// Obtain a handle for sending messages to the given stage.
+    pub fn input<Msg: Message, St>(&self, stage: &StageRef<Msg, St>) -> Input<Msg> {
+        let tx = self
+            .inner
+            .senders
+            .get(&stage.name)
+            .expect("stage ref contained unknown name")
+            .clone();
+        Input {
+            target: stage.without_state(),
+            tx,
+        }
+    }
+
+    /// Render the stages as a DOT digraph like [`StageGraph::export_dot`], adding the sends
+    /// seen so far.
+    pub fn export_dot(&self) -> String {
+        self.inner.wiring.lock().to_dot()
+    }
+
+    /// The metrics of all stages so far, see [`Metrics`].
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            stages: self
+                .inner
+                .metrics
+                .iter()
+                .map(|(name, metrics)| (name.clone(), metrics.lock().clone()))
+                .collect(),
+        }
+    }
+
+    /// Render the metrics of all stages in the Prometheus text exposition format, to be
+    /// served to a Prometheus scraper.
+    pub fn export_prometheus(&self) -> String {
+        self.metrics().to_prometheus()
+    }
+
     /// Abort all stage tasks of this network.
     pub fn abort(self) {
         for handle in self.handles {
@@ -216,6 +743,16 @@ impl TokioRunning {
         }
     }
 
+    /// Stop all stages and wait for them, see [`ShutdownPolicy`] for what happens to the
+    /// messages still queued for them.
+    ///
+    /// A stopping stage hands its final state to its [finalizer](StageGraph::finalizer) and
+    /// closes its mailbox, so that messages sent to it afterwards become dead letters.
+    pub async fn shutdown(self, policy: ShutdownPolicy) -> Vec<anyhow::Result<()>> {
+        self.inner.shutdown.send_replace(Some(policy));
+        self.join().await
+    }
+
     pub async fn join(self) -> Vec<anyhow::Result<()>> {
         let mut res = Vec::new();
         for handle in self.handles.into_iter() {
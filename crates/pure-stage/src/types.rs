@@ -102,7 +102,9 @@ pub fn cast_state<T: State>(this: &dyn State) -> anyhow::Result<&T> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
 pub struct Name(String);
 
 impl Name {
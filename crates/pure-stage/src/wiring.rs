@@ -0,0 +1,72 @@
+use crate::Name;
+use std::{cell::RefCell, collections::BTreeSet, fmt::Write};
+
+thread_local! {
+    /// The stage references handed out while wiring up a stage, see [`observe_targets`].
+    static TARGETS: RefCell<Option<BTreeSet<Name>>> = const { RefCell::new(None) };
+}
+
+/// Run `f`, returning the names of the stage references it derived or cloned, i.e. the targets
+/// injected into the state of the stage being wired up.
+pub(crate) fn observe_targets(f: impl FnOnce()) -> BTreeSet<Name> {
+    let outer = TARGETS.replace(Some(BTreeSet::new()));
+    f();
+    TARGETS.replace(outer).unwrap_or_default()
+}
+
+pub(crate) fn observe(name: &Name) {
+    TARGETS.with_borrow_mut(|targets| {
+        if let Some(targets) = targets {
+            targets.insert(name.clone());
+        }
+    });
+}
+
+/// The stages of a graph and the targets they send to, see [`StageGraph::export_dot`].
+///
+/// [`StageGraph::export_dot`]: crate::StageGraph::export_dot
+#[derive(Debug, Default)]
+pub(crate) struct Wiring {
+    stages: BTreeSet<Name>,
+    wired: BTreeSet<(Name, Name)>,
+    sent: BTreeSet<(Name, Name)>,
+}
+
+impl Wiring {
+    pub fn add_stage(&mut self, name: &Name) {
+        self.stages.insert(name.clone());
+    }
+
+    pub fn wire_up(&mut self, from: &Name, targets: BTreeSet<Name>) {
+        self.wired
+            .extend(targets.into_iter().map(|to| (from.clone(), to)));
+    }
+
+    pub fn sent(&mut self, from: &Name, to: &Name) {
+        self.sent.insert((from.clone(), to.clone()));
+    }
+
+    /// Render as a DOT digraph, drawing the edges only seen at runtime dashed.
+    ///
+    /// Everything is sorted by name, so that the output of the same graph can be diffed.
+    /// References to unknown stages, like [`StageRef::noop`](crate::StageRef::noop), are left out.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph stages {\n");
+        for stage in &self.stages {
+            writeln!(dot, "    {:?};", stage.as_str()).ok();
+        }
+        for (from, to) in self.wired.union(&self.sent) {
+            if !self.stages.contains(from) || !self.stages.contains(to) {
+                continue;
+            }
+            let style = if self.wired.contains(&(from.clone(), to.clone())) {
+                ""
+            } else {
+                " [style=dashed]"
+            };
+            writeln!(dot, "    {:?} -> {:?}{style};", from.as_str(), to.as_str()).ok();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
@@ -1,9 +1,17 @@
 use pure_stage::{
-    simulation::{Blocked, SimulationBuilder},
-    CallRef, Name, StageGraph, StageRef,
+    simulation::{
+        explore, Blocked, ClockSkew, PriorityInversion, Scheduler, SimulationBuilder, StageStatus,
+        WaitingFor,
+    },
+    CallRef, DeadLetter, DeadLetterReason, Effect, ExternalEffect, Name, ShutdownPolicy,
+    StageGraph, StageRef, StoreError, Void,
 };
-use std::time::Duration;
-use tracing_subscriber::EnvFilter;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing_subscriber::{layer::SubscriberExt, registry::LookupSpan, EnvFilter, Layer};
 
 #[test]
 fn basic() {
@@ -87,6 +95,216 @@ fn interrupt() {
     assert_eq!(*running.get_state(&stage).unwrap(), 7);
 }
 
+#[test]
+fn interrupt_with() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "oracle",
+        async |mut state: Vec<u32>, msg: u32, eff| {
+            // the harness decides whether to keep each message
+            if eff.interrupt_with::<_, bool>(msg).await {
+                state.push(msg);
+            }
+            Ok(state)
+        },
+        Vec::new(),
+    );
+    let stage = network.wire_up(stage, |_| {});
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, [1, 2, 3]);
+    loop {
+        let blocked = running.run_until_blocked();
+        if blocked == Blocked::Idle {
+            break;
+        }
+        blocked.assert_interrupted("oracle");
+        let msg = *blocked.interrupt_payload::<u32>().unwrap();
+        assert!(blocked.interrupt_payload::<String>().is_none());
+        // the decision must have the type the stage expects
+        assert!(running.resume_interrupt(&stage).is_err());
+        running.resume_interrupt_with(&stage, msg != 2).unwrap();
+    }
+    assert_eq!(running.get_state(&stage).unwrap(), &vec![1, 3]);
+
+    running.enqueue_msg(&stage, [4]);
+    running.resume_receive(&stage).unwrap();
+    running.effect().assert_interrupt(&stage, 4u32);
+    running.resume_interrupt_with(&stage, true).unwrap();
+    running.effect().assert_receive(&stage);
+    assert_eq!(running.get_state(&stage).unwrap(), &vec![1, 3, 4]);
+}
+
+#[test]
+fn livelock() {
+    let mut network = SimulationBuilder::default();
+    let mut player = |name: &str| {
+        network.stage(
+            name,
+            async |(count, other): (u32, StageRef<u32, Void>), ball: u32, eff| {
+                eff.send(&other, ball + 1).await;
+                Ok((count + 1, other))
+            },
+            (0, StageRef::noop()),
+        )
+    };
+    let ping = player("ping");
+    let pong = player("pong");
+    let counter = network.stage(
+        "counter",
+        async |count: u32, _msg: (), _eff| Ok(count + 1),
+        0,
+    );
+    let counter = network.wire_up(counter, |_| {});
+    let ping = network.wire_up(ping, |(_, other)| *other = pong.sender());
+    let pong = network.wire_up(pong, |(_, other)| *other = ping.without_state());
+    let mut running = network.run();
+
+    // a graph that settles down doesn't run out of fuel
+    running.enqueue_msg(&counter, [(), (), ()]);
+    running.run_until_blocked_with_fuel(100).assert_idle();
+    assert_eq!(running.get_state(&counter).unwrap(), &3);
+
+    running.enqueue_msg(&ping, [0]);
+    running.enqueue_msg(&counter, [()]);
+    running
+        .run_until_blocked_with_fuel(100)
+        .assert_livelock(["ping", "pong"]);
+    let (ping_count, _) = running.get_state(&ping).unwrap();
+    let (pong_count, _) = running.get_state(&pong).unwrap();
+    assert!(ping_count + pong_count < 100);
+    assert_eq!(running.get_state(&counter).unwrap(), &4);
+}
+
+#[test]
+fn scheduler() {
+    fn log(scheduler: Scheduler) -> Vec<String> {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut network = SimulationBuilder::default();
+        let source = network.stage(
+            "source",
+            {
+                let log = log.clone();
+                move |target: StageRef<u32, Void>, msgs: Vec<u32>, eff| {
+                    let log = log.clone();
+                    async move {
+                        for msg in msgs {
+                            eff.send(&target, msg).await;
+                            log.lock().unwrap().push(format!("sent {msg}"));
+                        }
+                        Ok(target)
+                    }
+                }
+            },
+            StageRef::noop(),
+        );
+        let sink = network.stage(
+            "sink",
+            {
+                let log = log.clone();
+                move |_state, msg: u32, _eff| {
+                    let log = log.clone();
+                    async move {
+                        log.lock().unwrap().push(format!("got {msg}"));
+                        Ok(())
+                    }
+                }
+            },
+            (),
+        );
+        let sink = network.wire_up(sink, |_| {});
+        let source = network.wire_up(source, |target| *target = sink.without_state());
+        let mut running = network.run();
+        running.enqueue_msg(&source, [vec![1, 2]]);
+        running
+            .run_until_blocked_with_scheduler(scheduler)
+            .assert_idle();
+        let log = log.lock().unwrap().clone();
+        log
+    }
+
+    assert_eq!(
+        log(Scheduler::Fifo),
+        vec!["got 1", "sent 1", "got 2", "sent 2"]
+    );
+    // the sink is held back until the source is done
+    assert_eq!(
+        log(Scheduler::LongestDelay),
+        vec!["sent 1", "sent 2", "got 1", "got 2"]
+    );
+    // a seed always yields the same schedule
+    assert_eq!(log(Scheduler::random(42)), log(Scheduler::random(42)));
+}
+
+#[test]
+fn explore_interleavings() {
+    let build = || {
+        let mut network = SimulationBuilder::default();
+        let mut sender = |name: &str| {
+            network.stage(
+                name,
+                async |target: StageRef<u32, Void>, msg: u32, eff| {
+                    eff.send(&target, msg).await;
+                    Ok(target)
+                },
+                StageRef::noop(),
+            )
+        };
+        let first = sender("first");
+        let second = sender("second");
+        let collector = network.stage(
+            "collector",
+            async |mut msgs: Vec<u32>, msg: u32, _eff| {
+                msgs.push(msg);
+                Ok(msgs)
+            },
+            Vec::new(),
+        );
+        let collector = network.wire_up(collector, |_| {});
+        let first = network.wire_up(first, |target| *target = collector.without_state());
+        let second = network.wire_up(second, |target| *target = collector.without_state());
+        let mut running = network.run();
+        running.enqueue_msg(&first, [1]);
+        running.enqueue_msg(&second, [2]);
+        (running, collector)
+    };
+
+    let outcomes = Arc::new(Mutex::new(BTreeSet::new()));
+    let collector = build().1;
+    let exploration = explore(
+        100,
+        || build().0,
+        |running, blocked| {
+            blocked.assert_idle();
+            let msgs = running.get_state(&collector).unwrap().clone();
+            outcomes.lock().unwrap().insert(msgs);
+            Ok(())
+        },
+    )
+    .unwrap();
+    assert!(exploration.exhaustive);
+    assert_eq!(
+        *outcomes.lock().unwrap(),
+        BTreeSet::from([vec![1, 2], vec![2, 1]])
+    );
+
+    // the bound is respected, and a failed check reports the schedule that led to it
+    let exploration = explore(1, || build().0, |_, _| Ok(())).unwrap();
+    assert_eq!(exploration.schedules, 1);
+    assert!(!exploration.exhaustive);
+    let error = explore(
+        100,
+        || build().0,
+        |running, _| {
+            let msgs = running.get_state(&collector).unwrap();
+            anyhow::ensure!(msgs == &vec![1, 2], "received {msgs:?}");
+            Ok(())
+        },
+    )
+    .unwrap_err();
+    assert!(format!("{error:#}").starts_with("in schedule second, "));
+}
+
 #[test]
 fn backpressure() {
     tracing_subscriber::fmt()
@@ -134,6 +352,210 @@ fn backpressure() {
     assert_eq!(*running.get_state(&pressure).unwrap(), 7);
 }
 
+#[test]
+fn backpressure_stops() {
+    let mut network = SimulationBuilder::default()
+        .with_mailbox_size(1)
+        .with_backpressure_stops(true);
+    let sender = network.stage(
+        "sender",
+        async |target, msg: u32, eff| {
+            eff.send(&target, msg).await;
+            Ok(target)
+        },
+        StageRef::noop::<u32>(),
+    );
+    let slow = network.stage(
+        "slow",
+        async |mut state, msg: u32, eff| {
+            eff.wait(Duration::from_secs(1)).await;
+            state += msg;
+            Ok(state)
+        },
+        0u32,
+    );
+    let sender = network.wire_up(sender, |state| *state = slow.sender());
+    let slow = network.wire_up(slow, |_| {});
+    let mut running = network.run();
+
+    // the first message is being processed and the second waits in the mailbox
+    running.enqueue_msg(&sender, [1, 2, 3]);
+    running
+        .run_until_blocked()
+        .assert_backpressure("sender", "slow");
+    assert_eq!(running.mailbox_len(&slow), 1);
+    assert_eq!(
+        running.report().stage("slow").unwrap().blocked_senders,
+        vec![Name::from("sender")]
+    );
+
+    // the third message gets in once the first one is done
+    running.run_until_blocked().assert_idle();
+    assert_eq!(*running.get_state(&slow).unwrap(), 6);
+}
+
+#[test]
+fn fan_out() {
+    let mut network = SimulationBuilder::default();
+    let (store, mut store_rx) = network.output("store");
+    let (metrics, mut metrics_rx) = network.output("metrics");
+    let fan_out = network.fan_out(
+        "fan_out",
+        vec![store.without_state(), metrics.without_state()],
+    );
+    let mut running = network.run();
+
+    running.enqueue_msg(&fan_out, [1u32]);
+    running.resume_receive(&fan_out).unwrap();
+    running.effect().assert_send(&fan_out, &store, 1u32);
+    running.resume_send(&fan_out, &store, 1u32).unwrap();
+    running.effect().assert_send(&fan_out, &metrics, 1u32);
+    running.resume_send(&fan_out, &metrics, 1u32).unwrap();
+    running.effect().assert_receive(&fan_out);
+
+    running.enqueue_msg(&fan_out, [2, 3]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(store_rx.drain().collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(metrics_rx.drain().collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+/// Records each new span by the stage it is for, or else by its name, along with its parent.
+#[derive(Clone, Default)]
+struct SpanTree(Arc<Mutex<Vec<(String, Option<String>)>>>);
+
+struct Label(String);
+
+impl tracing::field::Visit for Label {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "stage" {
+            self.0 = format!("{value:?}");
+        }
+    }
+}
+
+impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanTree {
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut label = Label(attrs.metadata().name().to_string());
+        attrs.record(&mut label);
+        let span = ctx.span(id).unwrap();
+        let parent = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<Label>().map(|l| l.0.clone()));
+        self.0.lock().unwrap().push((label.0.clone(), parent));
+        span.extensions_mut().insert(label);
+    }
+}
+
+#[test]
+fn spans() {
+    let spans = SpanTree::default();
+    let subscriber = tracing_subscriber::registry().with(spans.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        let mut network = SimulationBuilder::default();
+        let validate = network.stage(
+            "validate",
+            async |out, msg: u32, eff| {
+                eff.send(&out, msg).await;
+                Ok(out)
+            },
+            StageRef::noop::<u32>(),
+        );
+        let store = network.stage(
+            "store",
+            async |out, msg: u32, eff| {
+                eff.send(&out, msg).await;
+                Ok(out)
+            },
+            StageRef::noop::<u32>(),
+        );
+        let (output, mut rx) = network.output("output");
+        let validate = network.wire_up(validate, |out| *out = store.sender());
+        network.wire_up(store, |out| *out = output.without_state());
+        let mut running = network.run();
+
+        tracing::info_span!("header").in_scope(|| running.enqueue_msg(&validate, [1]));
+        running.run_until_blocked().assert_idle();
+        assert_eq!(rx.drain().collect::<Vec<_>>(), vec![1]);
+    });
+
+    let label = |s: &str| s.to_string();
+    assert_eq!(
+        *spans.0.lock().unwrap(),
+        vec![
+            (label("header"), None),
+            (label("validate"), Some(label("header"))),
+            (label("store"), Some(label("validate"))),
+            (label("output"), Some(label("store"))),
+        ]
+    );
+}
+
+#[test]
+fn report() {
+    let mut network = SimulationBuilder::default().with_mailbox_size(1);
+    let sender = network.stage(
+        "sender",
+        async |target, msg: u32, eff| {
+            eff.send(&target, msg).await;
+            Ok(target)
+        },
+        StageRef::noop::<u32>(),
+    );
+    let pressure = network.stage(
+        "pressure",
+        async |state, _msg: u32, eff| {
+            eff.interrupt().await;
+            Ok(state)
+        },
+        (),
+    );
+    let sender = network.wire_up(sender, |state| *state = pressure.sender());
+
+    let report = network.report();
+    assert_eq!(report.stage("sender").unwrap().state, StageStatus::Idle);
+    assert_eq!(
+        report.stage("pressure").unwrap().state,
+        StageStatus::Uninitialized
+    );
+
+    network.wire_up(pressure, |_| {});
+    let mut running = network.run();
+    running.enqueue_msg(&sender, [1, 2, 3]);
+    running.run_until_blocked().assert_interrupted("pressure");
+    running.run_until_blocked().assert_busy(["pressure"]);
+
+    let report = running.report();
+    let names = report
+        .stages
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["pressure", "sender"]);
+
+    let pressure = report.stage("pressure").unwrap();
+    assert_eq!(pressure.state, StageStatus::Running);
+    assert_eq!(pressure.mailbox, 1);
+    assert_eq!(pressure.blocked_senders, vec![Name::from("sender")]);
+    assert_eq!(pressure.waiting_for, Some(WaitingFor::Interrupt));
+
+    let sender = report.stage("sender").unwrap();
+    assert_eq!(sender.state, StageStatus::Running);
+    assert_eq!(sender.mailbox, 0);
+    assert_eq!(
+        sender.waiting_for,
+        Some(WaitingFor::Send {
+            to: Name::from("pressure"),
+            call: false
+        })
+    );
+    assert_eq!(report.sleeping, 0);
+}
+
 #[test]
 fn clock() {
     let mut network = SimulationBuilder::default();
@@ -191,6 +613,149 @@ fn clock_manual() {
     assert_eq!(later.checked_since(now).unwrap(), Duration::from_secs(1));
 }
 
+#[test]
+fn clock_driven_from_outside() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "basic",
+        async |_state, msg: u32, eff| {
+            let later = eff.wait(Duration::from_secs(1)).await;
+            Ok(Some((msg, later)))
+        },
+        None,
+    );
+    let stage = network.wire_up(stage, |_| {});
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, [42]);
+    let start = running.now();
+    running.run_until_sleeping_or_blocked().assert_sleeping();
+    let wakeup = running.next_wakeup().unwrap();
+    assert_eq!(wakeup.checked_since(start).unwrap(), Duration::from_secs(1));
+
+    let halfway = start.checked_add(Duration::from_millis(500)).unwrap();
+    running.advance_clock_to(halfway);
+    assert_eq!(running.now(), halfway);
+    assert_eq!(running.next_wakeup(), Some(wakeup));
+
+    assert!(running.skip_to_next_wakeup());
+    running.run_until_sleeping_or_blocked().assert_idle();
+    assert_eq!(running.get_state(&stage).unwrap(), &Some((42u32, wakeup)));
+    assert_eq!(running.next_wakeup(), None);
+}
+
+#[test]
+fn clock_skew() {
+    let mut network = SimulationBuilder::default();
+    let mut stage = |name: &str| {
+        let stage = network.stage(
+            name,
+            async |_state, _msg: (), eff| {
+                let now = eff.clock().await;
+                let later = eff.wait(Duration::from_secs(11)).await;
+                Ok(Some((now, later)))
+            },
+            None,
+        );
+        network.wire_up(stage, |_| {})
+    };
+    let fast = stage("fast");
+    let slow = stage("slow");
+    network.clock_skew(
+        &fast,
+        ClockSkew::ahead(Duration::from_secs(1)).with_drift_ppm(100_000),
+    );
+    network.clock_skew(&slow, ClockSkew::behind(Duration::from_secs(2)));
+    let mut running = network.run();
+
+    let start = running.now();
+    let at = |secs: u64| start.checked_add(Duration::from_secs(secs)).unwrap();
+    running.enqueue_msg(&fast, [()]);
+    running.enqueue_msg(&slow, [()]);
+    running.run_until_blocked().assert_idle();
+
+    // the fast clock takes ten seconds of simulated time to advance by eleven
+    assert_eq!(running.get_state(&fast).unwrap(), &Some((at(1), at(12))));
+    assert_eq!(
+        running.get_state(&slow).unwrap(),
+        &Some((start.checked_sub(Duration::from_secs(2)).unwrap(), at(9)))
+    );
+    assert_eq!(running.now(), at(11));
+    assert_eq!(
+        running.now_at(&fast),
+        at(13).checked_add(Duration::from_millis(100)).unwrap()
+    );
+}
+
+#[test]
+fn schedule() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "ticker",
+        async |mut ticks: Vec<u32>, tick: u32, eff| {
+            ticks.push(tick);
+            if tick < 3 {
+                eff.schedule(tick + 1, Duration::from_secs(1)).await;
+            }
+            eff.sleep(Duration::from_millis(100)).await;
+            Ok(ticks)
+        },
+        Vec::new(),
+    );
+    let stage = network.wire_up(stage, |_| {});
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, [0]);
+    let start = running.now();
+    running.run_until_blocked().assert_idle();
+
+    assert_eq!(running.get_state(&stage).unwrap(), &vec![0, 1, 2, 3]);
+    assert_eq!(
+        running.now().checked_since(start).unwrap(),
+        Duration::from_millis(3100)
+    );
+}
+
+#[test]
+fn schedule_manual() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "ticker",
+        async |mut ticks: Vec<u32>, tick: u32, eff| {
+            ticks.push(tick);
+            eff.schedule(tick + 1, Duration::from_secs(1)).await;
+            Ok(ticks)
+        },
+        Vec::new(),
+    );
+    let stage = network.wire_up(stage, |_| {});
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, [0]);
+    running.resume_receive(&stage).unwrap();
+    running
+        .effect()
+        .assert_schedule(&stage, 1, Duration::from_secs(1));
+    running.resume_schedule(&stage, 1).unwrap();
+    // the stage goes on without waiting for the message
+    running.effect().assert_receive(&stage);
+    assert_eq!(running.mailbox_len(&stage), 0);
+
+    running.try_effect().unwrap_err().assert_sleeping();
+    let start = running.now();
+    assert!(running.skip_to_next_wakeup());
+    assert_eq!(
+        running.now().checked_since(start).unwrap(),
+        Duration::from_secs(1)
+    );
+    assert_eq!(running.mailbox_len(&stage), 1);
+
+    running.resume_receive(&stage).unwrap();
+    running
+        .effect()
+        .assert_schedule(&stage, 2, Duration::from_secs(1));
+}
+
 #[test]
 fn call() {
     tracing_subscriber::fmt()
@@ -255,3 +820,1127 @@ fn call() {
     sim.effect().assert_receive(&caller);
     assert_eq!(sim.get_state(&caller).unwrap().0, 7);
 }
+
+#[test]
+fn call_timeout() {
+    let mut network = SimulationBuilder::default();
+    let caller = network.stage(
+        "caller",
+        async |(_state, target), _msg: (), eff| {
+            let response = eff.call(&target, Duration::from_secs(1), |cr| cr).await;
+            Ok((response, target))
+        },
+        (Some(0u32), StageRef::noop::<CallRef<u32>>()),
+    );
+    let callee = network.stage(
+        "callee",
+        async |state, cr: CallRef<u32>, eff| {
+            eff.wait(Duration::from_secs(2)).await;
+            eff.respond(cr, 42).await;
+            Ok(state)
+        },
+        (),
+    );
+    let caller = network.wire_up(caller, |state| state.1 = callee.sender());
+    network.wire_up(callee, |_| {});
+    let mut sim = network.run();
+    let start = sim.now();
+
+    sim.enqueue_msg(&caller, [()]);
+    sim.run_until_blocked().assert_idle();
+    assert_eq!(sim.get_state(&caller).unwrap().0, None);
+    // the late response was dropped once the callee was done waiting
+    assert_eq!(sim.now().checked_since(start), Some(Duration::from_secs(2)));
+}
+
+#[test]
+fn snapshot() {
+    let mut network = SimulationBuilder::default();
+    let (_output, _rx) = network.output::<u32>("output");
+    assert!(
+        network.run().snapshot().is_err(),
+        "output stage is not enabled"
+    );
+
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "sum",
+        async |(mut state, out), msg: u32, eff| {
+            state += msg;
+            eff.send(&out, state).await;
+            Ok((state, out))
+        },
+        (0u32, StageRef::noop::<u32>()),
+    );
+    let (output, mut rx) = network.output("output");
+    let stage = network.wire_up(stage, |state| state.1 = output.without_state());
+    network.enable_snapshots(&stage);
+    network.enable_snapshots(&output);
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, [1, 2]);
+    running.run_until_blocked().assert_idle();
+    running.enqueue_msg(&stage, [3]);
+    let snapshot = running.snapshot().unwrap();
+
+    // two different futures from the same prefix
+    running.run_until_blocked().assert_idle();
+    assert_eq!(running.get_state(&stage).unwrap().0, 6);
+    running.restore(&snapshot).unwrap();
+    assert_eq!(running.get_state(&stage).unwrap().0, 3);
+    assert_eq!(running.mailbox_len(&stage), 1);
+    running.enqueue_msg(&stage, [10]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(running.get_state(&stage).unwrap().0, 16);
+    assert_eq!(rx.drain().collect::<Vec<_>>(), vec![1, 3, 6, 6, 16]);
+
+    // a stage in the middle of processing a message cannot be copied
+    running.enqueue_msg(&stage, [1]);
+    running.resume_receive(&stage).unwrap();
+    assert!(running.snapshot().is_err());
+}
+
+#[test]
+fn random() {
+    let draws = |seed: u64| {
+        let mut network = SimulationBuilder::default().with_seed(seed);
+        let stage = network.stage(
+            "dice",
+            async |out, _msg: (), eff| {
+                let roll = eff.random::<u8>() % 6 + 1;
+                eff.send(&out, roll).await;
+                Ok(out)
+            },
+            StageRef::noop::<u8>(),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        network.enable_snapshots(&stage);
+        network.enable_snapshots(&output);
+        let mut running = network.run();
+
+        running.enqueue_msg(&stage, [(); 10]);
+        let snapshot = running.snapshot().unwrap();
+        running.run_until_blocked().assert_idle();
+        let rolls = rx.drain().collect::<Vec<_>>();
+
+        // restoring a snapshot also restores the generator
+        running.restore(&snapshot).unwrap();
+        running.run_until_blocked().assert_idle();
+        assert_eq!(rx.drain().collect::<Vec<_>>(), rolls);
+        rolls
+    };
+
+    assert_eq!(draws(42), draws(42));
+    assert_ne!(draws(42), draws(43));
+}
+
+#[derive(Debug, PartialEq)]
+struct ReadFile(String);
+
+impl ExternalEffect for ReadFile {
+    type Response = Result<String, String>;
+}
+
+#[test]
+fn external() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "reader",
+        async |out, path: String, eff| {
+            let contents = eff.external(ReadFile(path)).await;
+            eff.send(&out, contents).await;
+            Ok(out)
+        },
+        StageRef::noop::<Result<String, String>>(),
+    );
+    let (output, mut rx) = network.output("output");
+    let stage = network.wire_up(stage, |out| *out = output.without_state());
+    network.external(|ReadFile(path)| async move {
+        match path.as_str() {
+            "config" => Ok("42".to_string()),
+            _ => Err(format!("{path}: no such file")),
+        }
+    });
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, ["config".to_string(), "missing".to_string()]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        rx.drain().collect::<Vec<_>>(),
+        vec![
+            Ok("42".to_string()),
+            Err("missing: no such file".to_string())
+        ]
+    );
+}
+
+#[test]
+fn external_without_handler() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "reader",
+        async |out, path: String, eff| {
+            let contents = eff.external(ReadFile(path)).await;
+            eff.send(&out, contents).await;
+            Ok(out)
+        },
+        StageRef::noop::<Result<String, String>>(),
+    );
+    let (output, mut rx) = network.output("output");
+    let stage = network.wire_up(stage, |out| *out = output.without_state());
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, ["config".to_string()]);
+    running.run_until_blocked().assert_busy(["reader"]);
+    running
+        .resume_external(&stage, Result::<String, String>::Ok("42".to_string()))
+        .unwrap();
+    running.run_until_blocked().assert_idle();
+    assert_eq!(rx.drain().collect::<Vec<_>>(), vec![Ok("42".to_string())]);
+}
+
+#[test]
+fn external_manual() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "reader",
+        async |out, path: String, eff| {
+            let contents = eff.external(ReadFile(path)).await;
+            eff.send(&out, contents).await;
+            Ok(out)
+        },
+        StageRef::noop::<Result<String, String>>(),
+    );
+    let (output, _rx) = network.output("output");
+    let stage = network.wire_up(stage, |out| *out = output.without_state());
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, ["config".to_string()]);
+    running.resume_receive(&stage).unwrap();
+    running
+        .effect()
+        .assert_external(&stage, ReadFile("config".to_string()));
+    assert_eq!(
+        running.report().stage("reader").unwrap().waiting_for,
+        Some(WaitingFor::External)
+    );
+    running
+        .resume_external(&stage, Result::<String, String>::Ok("42".to_string()))
+        .unwrap();
+    running.effect().assert_send(
+        &stage,
+        &output,
+        Result::<String, String>::Ok("42".to_string()),
+    );
+}
+
+#[test]
+fn persistence() {
+    let mut network = SimulationBuilder::default();
+    let writer = network.stage(
+        "writer",
+        async |out, tip: u32, eff| {
+            let result = eff.persist("tip", tip.to_be_bytes().to_vec()).await;
+            eff.send(&out, result).await;
+            Ok(out)
+        },
+        StageRef::noop::<Result<(), StoreError>>(),
+    );
+    let reader = network.stage(
+        "reader",
+        async |out, key: String, eff| {
+            let result = eff.load(key).await;
+            eff.send(&out, result).await;
+            Ok(out)
+        },
+        StageRef::noop::<Result<Option<Vec<u8>>, StoreError>>(),
+    );
+    let (written, mut written_rx) = network.output("written");
+    let (read, mut read_rx) = network.output("read");
+    let writer = network.wire_up(writer, |out| *out = written.without_state());
+    let reader = network.wire_up(reader, |out| *out = read.without_state());
+    let store = network.store();
+    let mut running = network.run();
+
+    running.enqueue_msg(&writer, [1]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(written_rx.drain().collect::<Vec<_>>(), vec![Ok(())]);
+
+    // a failed write leaves the contents as they were
+    store.fail_writes(1);
+    running.enqueue_msg(&writer, [2]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        written_rx.drain().collect::<Vec<_>>(),
+        vec![Err(StoreError("injected failure writing `tip`".to_owned()))]
+    );
+    assert_eq!(
+        running.store().contents(),
+        BTreeMap::from([("tip".to_owned(), vec![0, 0, 0, 1])])
+    );
+
+    store.fail_reads(1);
+    running.enqueue_msg(
+        &reader,
+        ["tip".to_owned(), "tip".to_owned(), "other".to_owned()],
+    );
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        read_rx.drain().collect::<Vec<_>>(),
+        vec![
+            Err(StoreError("injected failure reading `tip`".to_owned())),
+            Ok(Some(vec![0, 0, 0, 1])),
+            Ok(None),
+        ]
+    );
+
+    // single-stepping performs the write when resuming the effect
+    running.enqueue_msg(&writer, [3]);
+    running.resume_receive(&writer).unwrap();
+    running
+        .effect()
+        .assert_persist(&writer, "tip", [0, 0, 0, 3]);
+    assert_eq!(
+        running.report().stage("writer").unwrap().waiting_for,
+        Some(WaitingFor::Persist {
+            key: "tip".to_owned()
+        })
+    );
+    assert!(running.resume_load(&writer).is_err());
+    running.resume_persist(&writer).unwrap();
+    running
+        .effect()
+        .assert_send(&writer, &written, Ok::<(), StoreError>(()));
+    assert_eq!(store.contents()["tip"], vec![0, 0, 0, 3]);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Chain {
+    Forward(u32),
+    Rollback(u32),
+}
+
+#[test]
+fn priority() {
+    let deliveries = |mailbox_size: usize| {
+        let mut network = SimulationBuilder::default().with_mailbox_size(mailbox_size);
+        let source = network.stage(
+            "source",
+            async |target, msgs: Vec<Chain>, eff| {
+                for msg in msgs {
+                    eff.send(&target, msg).await;
+                }
+                Ok(target)
+            },
+            StageRef::noop::<Chain>(),
+        );
+        let chain = network.stage(
+            "chain",
+            async |out, msg: Chain, eff| {
+                eff.wait(Duration::from_secs(1)).await;
+                eff.send(&out, msg).await;
+                Ok(out)
+            },
+            StageRef::noop::<Chain>(),
+        );
+        network.prioritize(&chain, |msg| match msg {
+            Chain::Forward(_) => 0,
+            Chain::Rollback(_) => 1,
+        });
+        let (output, mut rx) = network.output("output");
+        let chain = network.wire_up(chain, |out| *out = output.without_state());
+        let source = network.wire_up(source, |target| *target = chain.without_state());
+        let mut running = network.run();
+
+        running.enqueue_msg(
+            &source,
+            [vec![
+                Chain::Forward(1),
+                Chain::Forward(2),
+                Chain::Rollback(0),
+            ]],
+        );
+        running.run_until_blocked().assert_idle();
+        (
+            rx.drain().collect::<Vec<_>>(),
+            running.priority_inversions(),
+        )
+    };
+
+    // the rollback overtakes the forward queued before it
+    assert_eq!(
+        deliveries(2),
+        (
+            vec![Chain::Forward(1), Chain::Rollback(0), Chain::Forward(2)],
+            vec![]
+        )
+    );
+    // unless the mailbox is too small for both
+    assert_eq!(
+        deliveries(1),
+        (
+            vec![Chain::Forward(1), Chain::Forward(2), Chain::Rollback(0)],
+            vec![PriorityInversion {
+                stage: Name::from("chain"),
+                received: 0,
+                held_back: 1,
+            }]
+        )
+    );
+}
+
+#[test]
+fn export_dot() {
+    let mut network = SimulationBuilder::default();
+    let (output, _rx) = network.output::<u32>("output");
+    let double = network.stage(
+        "double",
+        async |out, msg: u32, eff| {
+            eff.send(&out, 2 * msg).await;
+            Ok(out)
+        },
+        StageRef::noop::<u32>(),
+    );
+    // a target captured by the transition function only shows up once it is used
+    let captured = output.without_state();
+    let captured = network.stage(
+        "captured",
+        move |state, msg: u32, eff| {
+            let out = captured.clone();
+            async move {
+                eff.send(&out, msg).await;
+                Ok(state)
+            }
+        },
+        (),
+    );
+    let double = network.wire_up(double, |out| *out = output.without_state());
+    let captured = network.wire_up(captured, |_| {});
+    network.fan_out(
+        "fan_out",
+        vec![double.without_state(), captured.without_state()],
+    );
+
+    assert_eq!(
+        network.export_dot(),
+        r#"digraph stages {
+    "captured";
+    "double";
+    "fan_out";
+    "output";
+    "double" -> "output";
+    "fan_out" -> "captured";
+    "fan_out" -> "double";
+}
+"#
+    );
+
+    let mut running = network.run();
+    running.enqueue_msg(&captured, [1]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        running.export_dot(),
+        r#"digraph stages {
+    "captured";
+    "double";
+    "fan_out";
+    "output";
+    "captured" -> "output" [style=dashed];
+    "double" -> "output";
+    "fan_out" -> "captured";
+    "fan_out" -> "double";
+}
+"#
+    );
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Protocol {
+    Request,
+    Reply(u32),
+    Other(u32),
+}
+
+#[test]
+fn receive_matching() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "client",
+        async |out, msg: Protocol, eff| {
+            let msg = match msg {
+                Protocol::Request => {
+                    eff.receive_matching(|msg| matches!(msg, Protocol::Reply(_)))
+                        .await
+                }
+                msg => msg,
+            };
+            eff.send(&out, msg).await;
+            Ok(out)
+        },
+        StageRef::noop::<Protocol>(),
+    );
+    let (output, mut rx) = network.output("output");
+    let stage = network.wire_up(stage, |out| *out = output.without_state());
+    let mut running = network.run();
+
+    // the reply overtakes the messages queued before it
+    running.enqueue_msg(
+        &stage,
+        [
+            Protocol::Request,
+            Protocol::Other(1),
+            Protocol::Other(2),
+            Protocol::Reply(7),
+        ],
+    );
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        rx.drain().collect::<Vec<_>>(),
+        vec![Protocol::Reply(7), Protocol::Other(1), Protocol::Other(2)]
+    );
+
+    // and the stage waits for one if there is none yet
+    running.enqueue_msg(&stage, [Protocol::Request, Protocol::Other(3)]);
+    running.resume_receive(&stage).unwrap();
+    running.effect().assert_receive_matching(&stage);
+    assert!(running.resume_receive_matching(&stage).is_err());
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        running.report().stage("client").unwrap().waiting_for,
+        Some(WaitingFor::ReceiveMatching)
+    );
+    running.enqueue_msg(&stage, [Protocol::Reply(4)]);
+    running.resume_receive_matching(&stage).unwrap();
+    running
+        .effect()
+        .assert_send(&stage, &output, Protocol::Reply(4));
+    assert_eq!(running.mailbox_len(&stage), 1);
+}
+
+#[test]
+fn contramap() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "sender",
+        async |target, msg: u32, eff| {
+            eff.send(&target, msg).await;
+            Ok(target)
+        },
+        StageRef::noop::<u32>(),
+    );
+    let (output, mut rx) = network.output::<String>("output");
+    let stage = network.wire_up(stage, |target| {
+        *target = output.contramap(|msg: u32| format!("msg {msg}"))
+    });
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, [1]);
+    running.resume_receive(&stage).unwrap();
+    running
+        .effect()
+        .assert_send(&stage, &output, "msg 1".to_owned());
+    running.run_until_blocked().assert_idle();
+
+    // conversions compose, and apply to messages enqueued from the outside as well
+    let input = output
+        .contramap(|msg: u32| format!("msg {msg}"))
+        .contramap(|msg: u8| u32::from(msg) * 10 + 1);
+    running.enqueue_msg(&input, [4]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        rx.drain().collect::<Vec<_>>(),
+        vec!["msg 1".to_owned(), "msg 41".to_owned()]
+    );
+}
+
+#[test]
+fn batch_stage() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.batch_stage(
+        "batch",
+        3,
+        async |out, msgs: Vec<u32>, eff| {
+            eff.send(&out, msgs).await;
+            Ok(out)
+        },
+        StageRef::noop::<Vec<u32>>(),
+    );
+    let (output, mut rx) = network.output("output");
+    let stage = network.wire_up(stage, |out| *out = output.without_state());
+    let mut running = network.run();
+
+    // the queued messages are taken up to the batch size, without waiting for more
+    running.enqueue_msg(&stage, [1, 2, 3, 4, 5]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        rx.drain().collect::<Vec<_>>(),
+        vec![vec![1, 2, 3], vec![4, 5]]
+    );
+
+    running.enqueue_msg(&stage, [6, 7]);
+    running.resume_receive(&stage).unwrap();
+    running.effect().assert_receive_queued(&stage, 2);
+    running.resume_receive_queued(&stage).unwrap();
+    running.effect().assert_send(&stage, &output, vec![6, 7]);
+    assert_eq!(running.mailbox_len(&stage), 0);
+}
+
+#[test]
+fn metrics() {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "worker",
+        async |_state, secs: u64, eff| {
+            eff.wait(Duration::from_secs(secs)).await;
+            anyhow::ensure!(secs > 0, "no work to do");
+            Ok(())
+        },
+        (),
+    );
+    let stage = network.wire_up(stage, |_| {});
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, [1, 2]);
+    running.run_until_blocked().assert_idle();
+    running.enqueue_msg(&stage, [0]);
+    running.resume_receive(&stage).unwrap();
+    running.effect().assert_wait(&stage, Duration::ZERO);
+    running.resume_wait(&stage, running.now()).unwrap();
+    assert!(matches!(running.effect(), Effect::Failure { .. }));
+
+    let metrics = running.metrics();
+    let worker = metrics.stage("worker").unwrap();
+    assert_eq!(worker.processed, 2);
+    assert_eq!(worker.failures, 1);
+    assert_eq!(worker.processing_time.count, 3);
+    assert_eq!(worker.processing_time.sum, 3.0);
+    assert!(worker.processing_time.buckets.contains(&(0.0001, 1)));
+    assert!(worker.processing_time.buckets.contains(&(1.0, 2)));
+    assert!(worker.processing_time.buckets.contains(&(10.0, 3)));
+    assert_eq!(worker.mailbox_depth.sum, 4.0);
+    assert!(worker.mailbox_depth.buckets.contains(&(1.0, 2)));
+
+    let exported = metrics.to_prometheus();
+    assert!(exported.contains("# TYPE pure_stage_messages_processed_total counter\n"));
+    assert!(exported.contains("pure_stage_messages_processed_total{stage=\"worker\"} 2\n"));
+    assert!(exported.contains("pure_stage_failures_total{stage=\"worker\"} 1\n"));
+    assert!(
+        exported.contains("pure_stage_processing_seconds_bucket{stage=\"worker\",le=\"1\"} 2\n")
+    );
+    assert!(
+        exported.contains("pure_stage_processing_seconds_bucket{stage=\"worker\",le=\"+Inf\"} 3\n")
+    );
+    assert!(exported.contains("pure_stage_mailbox_depth_count{stage=\"worker\"} 3\n"));
+}
+
+#[test]
+fn dead_letters() {
+    let mut network = SimulationBuilder::default().with_mailbox_size(1);
+    let sender = network.stage(
+        "sender",
+        async |targets: Vec<StageRef<u32, Void>>, (idx, msgs): (usize, Vec<u32>), eff| {
+            for msg in msgs {
+                eff.send(&targets[idx], msg).await;
+            }
+            Ok(targets)
+        },
+        Vec::new(),
+    );
+    let failing = network.stage(
+        "failing",
+        async |_state, _msg: u32, _eff| Err(anyhow::anyhow!("stopped")),
+        (),
+    );
+    let lossy = network.stage(
+        "lossy",
+        async |_state, _msg: u32, eff| {
+            eff.wait(Duration::from_secs(1)).await;
+            Ok(())
+        },
+        (),
+    );
+    network.drop_overflow(&lossy);
+    let (dead_letters, mut rx) = network.output::<DeadLetter>("dead_letters");
+    network.dead_letters(&dead_letters);
+    let failing = network.wire_up(failing, |_| {});
+    let lossy = network.wire_up(lossy, |_| {});
+    let sender = network.wire_up(sender, |targets| {
+        *targets = vec![
+            StageRef::noop(),
+            failing.without_state(),
+            lossy.without_state(),
+        ]
+    });
+    let mut running = network.run();
+
+    running.enqueue_msg(&failing, [0]);
+    running.resume_receive(&failing).unwrap();
+    assert!(matches!(running.effect(), Effect::Failure { .. }));
+
+    // the lossy stage holds one message while waiting, so the third one overflows
+    running.enqueue_msg(&sender, [(0, vec![1]), (1, vec![2]), (2, vec![3, 4, 5])]);
+    running.run_until_blocked().assert_idle();
+    let letter = |to: &str, reason, msg: u32| DeadLetter {
+        from: Name::from("sender"),
+        to: Name::from(to),
+        reason,
+        msg: Box::new(msg),
+    };
+    assert_eq!(
+        rx.drain().collect::<Vec<_>>(),
+        vec![
+            letter("noop", DeadLetterReason::Noop, 1),
+            letter("failing", DeadLetterReason::Terminated, 2),
+            letter("lossy", DeadLetterReason::Overflow, 5),
+        ]
+    );
+}
+
+#[test]
+fn termination() {
+    let mut network = SimulationBuilder::default();
+    let summer = network.stage(
+        "summer",
+        async |sum, msg: u32, eff| {
+            if msg == 0 {
+                eff.terminate();
+            }
+            Ok(sum + msg)
+        },
+        0u32,
+    );
+    let sender = network.stage(
+        "sender",
+        async |target, msg: u32, eff| {
+            eff.send(&target, msg).await;
+            Ok(target)
+        },
+        StageRef::noop::<u32>(),
+    );
+    let finals = Arc::new(Mutex::new(Vec::new()));
+    let finals2 = finals.clone();
+    network.finalizer(&summer, move |sum| finals2.lock().unwrap().push(sum));
+    let (dead_letters, mut rx) = network.output::<DeadLetter>("dead_letters");
+    network.dead_letters(&dead_letters);
+    let summer = network.wire_up(summer, |_| {});
+    let sender = network.wire_up(sender, |target| *target = summer.without_state());
+    let mut running = network.run();
+
+    running.enqueue_msg(&summer, [1, 2]);
+    running.run_until_blocked().assert_idle();
+    assert!(finals.lock().unwrap().is_empty());
+
+    // the queued message is dropped along with the stage
+    running.enqueue_msg(&summer, [0, 5]);
+    running.resume_receive(&summer).unwrap();
+    running.effect().assert_termination(&summer);
+    assert_eq!(*finals.lock().unwrap(), vec![3]);
+    assert_eq!(
+        running.report().stage("summer").unwrap().state,
+        StageStatus::Terminated
+    );
+    running.run_until_blocked().assert_idle();
+
+    // messages sent to the terminated stage become dead letters
+    running.enqueue_msg(&sender, [7]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        rx.drain().collect::<Vec<_>>(),
+        vec![DeadLetter {
+            from: Name::from("sender"),
+            to: Name::from("summer"),
+            reason: DeadLetterReason::Terminated,
+            msg: Box::new(7u32),
+        }]
+    );
+    assert_eq!(*finals.lock().unwrap(), vec![3]);
+}
+
+#[test]
+fn shutdown() {
+    let run = |policy, finals: Arc<Mutex<Vec<u32>>>| {
+        let mut network = SimulationBuilder::default();
+        let summer = network.stage(
+            "summer",
+            async |sum, msg: u32, eff| {
+                eff.wait(Duration::from_secs(1)).await;
+                Ok(sum + msg)
+            },
+            0u32,
+        );
+        network.finalizer(&summer, move |sum| finals.lock().unwrap().push(sum));
+        let summer = network.wire_up(summer, |_| {});
+        let mut running = network.run();
+
+        running.enqueue_msg(&summer, [1, 2, 4]);
+        running.run_until_sleeping_or_blocked().assert_sleeping();
+        running.shutdown(policy).assert_idle();
+        assert_eq!(
+            running.report().stage("summer").unwrap().state,
+            StageStatus::Terminated
+        );
+    };
+
+    let finals = Arc::new(Mutex::new(Vec::new()));
+    run(ShutdownPolicy::Drain, finals.clone());
+    assert_eq!(*finals.lock().unwrap(), vec![7]);
+
+    // the message being processed is finished, the queued ones are dropped
+    let finals = Arc::new(Mutex::new(Vec::new()));
+    run(ShutdownPolicy::FinishCurrent, finals.clone());
+    assert_eq!(*finals.lock().unwrap(), vec![1]);
+}
+
+#[test]
+fn receiver_recv() {
+    let mut network = SimulationBuilder::default();
+    let (output, mut rx) = network.output::<u32>("output");
+    let mut running = network.run();
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(async {
+            assert_eq!(rx.recv_timeout(Duration::from_millis(10)).await, None);
+
+            running.enqueue_msg(&output, [1, 2]);
+            running.run_until_blocked().assert_idle();
+            assert_eq!(rx.recv().await, Some(1));
+            assert_eq!(rx.recv_timeout(Duration::from_millis(10)).await, Some(2));
+
+            drop(running);
+            assert_eq!(rx.recv().await, None);
+        });
+}
+
+#[test]
+fn trace_replay() {
+    let build = || {
+        let mut network = SimulationBuilder::default();
+        let (output, rx) = network.output::<u32>("output");
+        let mut source = |name: &str| {
+            let source = network.stage(
+                name,
+                async |target: StageRef<u32, Void>, msg: u32, eff| {
+                    eff.send(&target, msg).await;
+                    Ok(target)
+                },
+                StageRef::noop(),
+            );
+            network.wire_up(source, |target| *target = output.without_state())
+        };
+        let a = source("a");
+        let b = source("b");
+        let mut running = network.run();
+        running.enqueue_msg(&a, [1]);
+        running.enqueue_msg(&b, [2]);
+        (running, rx)
+    };
+
+    let (mut running, mut rx) = build();
+    running.record_trace();
+    running
+        .run_until_blocked_with_scheduler(Scheduler::LongestDelay)
+        .assert_idle();
+    let trace = running.take_trace().unwrap();
+    let outputs = rx.drain().collect::<Vec<_>>();
+    assert_eq!(outputs.len(), 2);
+    assert!(trace.steps[0].effect.starts_with("Send"));
+
+    // the replay sends the messages in the same order
+    let (mut running, mut rx) = build();
+    running.replay(&trace).unwrap().assert_idle();
+    assert_eq!(rx.drain().collect::<Vec<_>>(), outputs);
+
+    let mut diverging = trace.clone();
+    diverging.steps[0].stage = Name::from("output");
+    let (mut running, _rx) = build();
+    let error = running.replay(&diverging).unwrap_err().to_string();
+    assert!(
+        error.starts_with("replay diverged at step 0: stage `output` is not runnable"),
+        "{error}"
+    );
+}
+
+#[test]
+fn causality() {
+    let mut network = SimulationBuilder::default();
+    let (output, mut rx) = network.output::<String>("output");
+    let relay = network.stage(
+        "relay",
+        async |out: StageRef<String, Void>, count: u32, eff| {
+            for idx in 0..count {
+                eff.send(&out, format!("fwd {idx}")).await;
+            }
+            Ok(out)
+        },
+        StageRef::noop(),
+    );
+    let relay = network.wire_up(relay, |out| *out = output.without_state());
+    let mut running = network.run();
+
+    running.enqueue_msg(&relay, [2, 1]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(rx.drain().count(), 3);
+
+    let causality = running.causality();
+    let inputs = causality
+        .messages()
+        .iter()
+        .filter(|record| record.from.is_none())
+        .collect::<Vec<_>>();
+    assert_eq!(inputs.len(), 2);
+    assert!(inputs.iter().all(|input| input.parent.is_none()));
+
+    // every forward is caused by exactly one input to the relay
+    let forwards = causality
+        .messages()
+        .iter()
+        .filter(|record| record.msg.starts_with("\"fwd"))
+        .collect::<Vec<_>>();
+    assert_eq!(forwards.len(), 3);
+    for forward in forwards {
+        assert_eq!(forward.from.as_ref().map(Name::as_str), Some("relay"));
+        let parent = causality.parent(forward.id).unwrap();
+        assert_eq!(parent.to.as_str(), "relay");
+        assert!(parent.from.is_none());
+    }
+    assert_eq!(
+        causality
+            .children(inputs[0].id)
+            .map(|record| record.msg.as_str())
+            .collect::<Vec<_>>(),
+        vec!["\"fwd 0\"", "\"fwd 1\""]
+    );
+    assert_eq!(causality.children(inputs[1].id).count(), 1);
+}
+
+#[test]
+fn run_until() {
+    let mut network = SimulationBuilder::default();
+    let ticker = network.stage(
+        "ticker",
+        async |ticks, _tick: (), eff| {
+            eff.schedule((), Duration::from_secs(1)).await;
+            Ok(ticks + 1)
+        },
+        0u32,
+    );
+    let ticker = network.wire_up(ticker, |_| {});
+    let mut running = network.run();
+    let start = running.now();
+
+    // the ticker never stops scheduling ticks, so run_until_blocked would not return
+    running.enqueue_msg(&ticker, [()]);
+    let deadline = start.checked_add(Duration::from_millis(5500)).unwrap();
+    running.run_until(deadline).assert_sleeping();
+    assert_eq!(running.now(), deadline);
+    assert_eq!(running.get_state(&ticker), Some(&6));
+    assert_eq!(
+        running.next_wakeup(),
+        start.checked_add(Duration::from_secs(6))
+    );
+}
+
+#[test]
+fn deadlock_cycle() {
+    let mut network = SimulationBuilder::default().with_mailbox_size(1);
+    let mut echo = |name: &str| {
+        network.stage(
+            name,
+            async |peer: StageRef<u32, Void>, msg: u32, eff| {
+                for _ in 0..3 {
+                    eff.send(&peer, msg).await;
+                }
+                Ok(peer)
+            },
+            StageRef::noop(),
+        )
+    };
+    let ping = echo("ping");
+    let pong = echo("pong");
+    let ping = network.wire_up(ping, |peer| *peer = pong.sender());
+    network.wire_up(pong, |peer| *peer = ping.without_state());
+    let mut running = network.run();
+
+    // each message makes the other stage send three, so both end up waiting for each other
+    running.enqueue_msg(&ping, [1]);
+    let blocked = running.run_until_blocked();
+    blocked.assert_deadlock_cycle(["pong", "ping"]);
+    blocked.assert_deadlock(["ping", "pong"]);
+}
+
+#[test]
+fn state_inspection() {
+    let mut network = SimulationBuilder::default();
+    let counter = network.stage(
+        "counter",
+        async |count, msg: u32, eff| {
+            eff.wait(Duration::from_secs(1)).await;
+            Ok(count + msg)
+        },
+        0u32,
+    );
+    let counter = network.wire_up(counter, |_| {}).without_state();
+    let mut running = network.run();
+
+    running.enqueue_msg(&counter, [1, 2]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(
+        running.get_state_as::<u32>(counter.name()).unwrap(),
+        Some(&3)
+    );
+    assert!(running
+        .get_state_as::<String>("counter")
+        .unwrap_err()
+        .to_string()
+        .starts_with("state type error"));
+    assert_eq!(
+        running
+            .get_state_as::<u32>("other")
+            .unwrap_err()
+            .to_string(),
+        "no stage named `other`"
+    );
+
+    // the state is not accessible in the middle of a transition
+    running.enqueue_msg(&counter, [3]);
+    running.run_until_sleeping_or_blocked().assert_sleeping();
+    assert_eq!(running.get_state_as::<u32>("counter").unwrap(), None);
+}
+
+#[test]
+fn replace_transition() {
+    let mut network = SimulationBuilder::default();
+    let (output, mut rx) = network.output("output");
+    let stage = network.stage(
+        "stage",
+        async |(count, out): (u32, StageRef<u32, Void>), msg: u32, eff| {
+            eff.send(&out, msg).await;
+            Ok((count + 1, out))
+        },
+        (0, StageRef::noop()),
+    );
+    let stage = network.wire_up(stage, |(_, out)| *out = output.without_state());
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, [1, 2]);
+    running.run_until_blocked().assert_idle();
+
+    // start misbehaving after the second message, keeping the count
+    running.replace_transition(&stage, async |(count, out), msg: u32, eff| {
+        eff.send(&out, msg * 10).await;
+        Ok((count + 1, out))
+    });
+    running.enqueue_msg(&stage, [3, 4]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(rx.drain().collect::<Vec<_>>(), vec![1, 2, 30, 40]);
+    assert_eq!(running.get_state(&stage).map(|(count, _)| *count), Some(4));
+}
+
+#[test]
+fn fork_join() {
+    let mut network = SimulationBuilder::default();
+    let (output, mut rx) = network.output("output");
+    let completed = Arc::new(Mutex::new(Vec::new()));
+    let log = completed.clone();
+    let stage = network.stage(
+        "stage",
+        move |out: StageRef<(u32, u32), Void>, msg: u32, eff| {
+            let log = log.clone();
+            async move {
+                let log1 = log.clone();
+                let first = eff
+                    .fork(async move {
+                        log1.lock().unwrap().push(1);
+                        msg + 1
+                    })
+                    .await;
+                let second = eff
+                    .fork(async move {
+                        log.lock().unwrap().push(2);
+                        msg + 2
+                    })
+                    .await;
+                let second = eff.join(second).await;
+                let first = eff.join(first).await;
+                eff.send(&out, (first, second)).await;
+                Ok(out)
+            }
+        },
+        StageRef::noop(),
+    );
+    let stage = network.wire_up(stage, |out| *out = output.without_state());
+    let mut running = network.run();
+
+    running.enqueue_msg(&stage, [10]);
+    running.run_until_blocked().assert_idle();
+    assert_eq!(rx.drain().collect::<Vec<_>>(), vec![(11, 12)]);
+    // the sub-tasks completed in the order in which they were joined
+    assert_eq!(*completed.lock().unwrap(), vec![2, 1]);
+
+    // single-stepping runs a sub-task only when resuming its join
+    completed.lock().unwrap().clear();
+    running.enqueue_msg(&stage, [20]);
+    running.resume_receive(&stage).unwrap();
+    running.effect().assert_fork(&stage);
+    assert_eq!(
+        running.report().stage("stage").unwrap().waiting_for,
+        Some(WaitingFor::Fork)
+    );
+    assert!(running.resume_join(&stage).is_err());
+    running.resume_fork(&stage).unwrap();
+    running.effect().assert_fork(&stage);
+    running.resume_fork(&stage).unwrap();
+    assert!(matches!(running.effect(), Effect::Join { .. }));
+    assert!(completed.lock().unwrap().is_empty());
+    running.resume_join(&stage).unwrap();
+    assert_eq!(*completed.lock().unwrap(), vec![2]);
+    assert!(matches!(running.effect(), Effect::Join { .. }));
+    running.resume_join(&stage).unwrap();
+    running
+        .effect()
+        .assert_send(&stage, &output, (21u32, 22u32));
+}
+
+#[test]
+fn fairness() {
+    let run = |scheduler: Scheduler| {
+        let mut network = SimulationBuilder::default();
+        let source = network.stage(
+            "source",
+            async |target: StageRef<u32, Void>, msgs: Vec<u32>, eff| {
+                for msg in msgs {
+                    eff.send(&target, msg).await;
+                }
+                Ok(target)
+            },
+            StageRef::noop(),
+        );
+        let sink = network.stage("sink", async |sum, msg: u32, _eff| Ok(sum + msg), 0u32);
+        let sink = network.wire_up(sink, |_| {});
+        let source = network.wire_up(source, |target| *target = sink.without_state());
+        let mut running = network.run();
+        running.enqueue_msg(&source, [vec![1, 2, 3]]);
+        running
+            .run_until_blocked_with_scheduler(scheduler)
+            .assert_idle();
+        running
+    };
+
+    let running = run(Scheduler::Fifo);
+    running.assert_fair_within(1);
+    let fairness = running.fairness();
+    assert_eq!(fairness.stage("source").unwrap().scheduled, 4);
+    assert_eq!(fairness.stage("sink").unwrap().scheduled, 3);
+    assert_eq!(fairness.stage("sink").unwrap().waiting, 0);
+
+    // the sink is held back until the source is done
+    let running = run(Scheduler::LongestDelay);
+    let (name, wait) = running.fairness().most_starved().unwrap();
+    assert_eq!(name.as_str(), "sink");
+    assert!(wait > 1, "sink waited only {wait} steps");
+    assert_eq!(running.fairness().stage("sink").unwrap().scheduled, 3);
+}
@@ -0,0 +1,536 @@
+use pure_stage::{
+    tokio::TokioBuilder, CallRef, DeadLetter, DeadLetterReason, ExternalEffect, MemoryStore, Name,
+    ShutdownPolicy, StageGraph, StageRef, Void,
+};
+use std::{future::Future, time::Duration};
+
+fn block_on<F: Future>(f: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(f)
+}
+
+#[test]
+fn basic() {
+    block_on(async {
+        let mut network = TokioBuilder::default().with_mailbox_size(1);
+        let stage = network.stage(
+            "basic",
+            async |(mut state, out), msg: u32, eff| {
+                state += msg;
+                eff.send(&out, state).await;
+                Ok((state, out))
+            },
+            (1u32, StageRef::noop::<u32>()),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |state| state.1 = output.without_state());
+        let running = network.run();
+
+        let input = running.input(&stage);
+        for msg in [1, 2, 3] {
+            input.send(msg).await.unwrap();
+        }
+        let mut outputs = Vec::new();
+        for _ in 0..3 {
+            outputs.push(rx.recv().await.unwrap());
+        }
+        assert_eq!(outputs, vec![2, 4, 7]);
+        running.abort();
+    });
+}
+
+#[test]
+fn schedule() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "ticker",
+            async |out, tick: u32, eff| {
+                let now = eff.clock().await;
+                if tick < 3 {
+                    eff.schedule(tick + 1, Duration::from_millis(10)).await;
+                }
+                eff.send(&out, (tick, now)).await;
+                Ok(out)
+            },
+            StageRef::noop(),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        let running = network.run();
+
+        running.input(&stage).send(0).await.unwrap();
+        let (_, start) = rx.recv().await.unwrap();
+        for expected in 1..=3 {
+            let (tick, at) = rx.recv().await.unwrap();
+            assert_eq!(tick, expected);
+            assert!(
+                at.checked_since(start).unwrap() >= Duration::from_millis(10 * expected as u64)
+            );
+        }
+        running.abort();
+    });
+}
+
+#[test]
+fn call() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let caller = network.stage(
+            "caller",
+            async |(target, out), msg: u32, eff| {
+                let response = eff
+                    .call(&target, Duration::from_secs(2), move |cr| (msg + 1, cr))
+                    .await
+                    .ok_or_else(|| anyhow::anyhow!("call timed out"))?;
+                eff.send(&out, response).await;
+                Ok((target, out))
+            },
+            (
+                StageRef::noop::<(u32, CallRef<u32>)>(),
+                StageRef::noop::<u32>(),
+            ),
+        );
+        let callee = network.stage(
+            "callee",
+            async |state, msg: (u32, CallRef<u32>), eff| {
+                eff.sleep(Duration::from_millis(10)).await;
+                eff.respond(msg.1, msg.0 * 2).await;
+                Ok(state)
+            },
+            (),
+        );
+        let (output, mut rx) = network.output("output");
+        let caller = network.wire_up(caller, |state| {
+            state.0 = callee.sender();
+            state.1 = output.without_state();
+        });
+        network.wire_up(callee, |_| {});
+        let running = network.run();
+
+        let input = running.input(&caller);
+        input.send(1).await.unwrap();
+        assert_eq!(rx.recv().await, Some(4));
+        input.send(2).await.unwrap();
+        assert_eq!(rx.recv().await, Some(6));
+        running.abort();
+    });
+}
+
+#[derive(Debug, PartialEq)]
+struct Double(u32);
+
+impl ExternalEffect for Double {
+    type Response = u32;
+}
+
+#[test]
+fn external() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "doubler",
+            async |out, msg: u32, eff| {
+                let doubled = eff.external(Double(msg)).await;
+                eff.send(&out, doubled).await;
+                Ok(out)
+            },
+            StageRef::noop::<u32>(),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        network.external(|Double(n)| async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            n * 2
+        });
+        let running = network.run();
+
+        running.input(&stage).send(21).await.unwrap();
+        assert_eq!(rx.recv().await, Some(42));
+        running.abort();
+    });
+}
+
+#[test]
+fn external_without_handler() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "doubler",
+            async |(), msg: u32, eff| {
+                eff.external(Double(msg)).await;
+                Ok(())
+            },
+            (),
+        );
+        let stage = network.wire_up(stage, |_| {});
+        let running = network.run();
+
+        running.input(&stage).send(21).await.unwrap();
+        let results = running.shutdown(ShutdownPolicy::Drain).await;
+        assert_eq!(results.len(), 1);
+        let error = results[0].as_ref().unwrap_err().to_string();
+        assert!(error.contains("no handler for external effect"), "{error}");
+    });
+}
+
+#[test]
+fn priority() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "chain",
+            async |out, msg: u32, eff| {
+                eff.send(&out, msg).await;
+                Ok(out)
+            },
+            StageRef::noop::<u32>(),
+        );
+        // odd numbers stand for rollbacks, which go first
+        network.prioritize(&stage, |msg| (msg % 2) as u8);
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        let running = network.run();
+
+        // the stage only gets to run once all of these are in its mailbox
+        let input = running.input(&stage);
+        for msg in [2, 4, 1, 6, 3] {
+            input.send(msg).await.unwrap();
+        }
+        let mut outputs = Vec::new();
+        for _ in 0..5 {
+            outputs.push(rx.recv().await.unwrap());
+        }
+        assert_eq!(outputs, vec![1, 3, 2, 4, 6]);
+        running.abort();
+    });
+}
+
+#[test]
+fn receive_matching() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "client",
+            async |out, msg: u32, eff| {
+                // zero asks for the next multiple of ten
+                let msg = match msg {
+                    0 => eff.receive_matching(|msg| msg % 10 == 0).await,
+                    msg => msg,
+                };
+                eff.send(&out, msg).await;
+                Ok(out)
+            },
+            StageRef::noop::<u32>(),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        let running = network.run();
+
+        let input = running.input(&stage);
+        for msg in [0, 1, 2, 30, 4] {
+            input.send(msg).await.unwrap();
+        }
+        let mut outputs = Vec::new();
+        for _ in 0..4 {
+            outputs.push(rx.recv().await.unwrap());
+        }
+        assert_eq!(outputs, vec![30, 1, 2, 4]);
+        running.abort();
+    });
+}
+
+#[test]
+fn contramap() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "sender",
+            async |target, msg: u32, eff| {
+                eff.send(&target, msg).await;
+                Ok(target)
+            },
+            StageRef::noop::<u32>(),
+        );
+        let (output, mut rx) = network.output::<String>("output");
+        let stage = network.wire_up(stage, |target| {
+            *target = output.contramap(|msg: u32| format!("msg {msg}"))
+        });
+        let running = network.run();
+
+        running.input(&stage).send(1).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), "msg 1");
+        let input = running.input(&output.contramap(|msg: u8| format!("byte {msg}")));
+        input.send(2).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), "byte 2");
+        running.abort();
+    });
+}
+
+#[test]
+fn batch_stage() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.batch_stage(
+            "batch",
+            3,
+            async |out, msgs: Vec<u32>, eff| {
+                eff.send(&out, msgs).await;
+                Ok(out)
+            },
+            StageRef::noop::<Vec<u32>>(),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        let running = network.run();
+
+        let input = running.input(&stage);
+        for msg in 1..=5 {
+            input.send(msg).await.unwrap();
+        }
+        let mut received = Vec::new();
+        while received.len() < 5 {
+            let batch = rx.recv().await.unwrap();
+            assert!(!batch.is_empty() && batch.len() <= 3, "{batch:?}");
+            received.extend(batch);
+        }
+        assert_eq!(received, vec![1, 2, 3, 4, 5]);
+        running.abort();
+    });
+}
+
+#[test]
+fn persistence() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "tip",
+            async |out, tip: u32, eff| {
+                let previous = eff.load("tip").await.unwrap();
+                eff.persist("tip", tip.to_be_bytes().to_vec())
+                    .await
+                    .unwrap();
+                eff.send(&out, previous).await;
+                Ok(out)
+            },
+            StageRef::noop::<Option<Vec<u8>>>(),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        let store = MemoryStore::default();
+        network.store(store.clone());
+        let running = network.run();
+
+        let input = running.input(&stage);
+        input.send(1).await.unwrap();
+        input.send(2).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), None);
+        assert_eq!(rx.recv().await.unwrap(), Some(vec![0, 0, 0, 1]));
+        assert_eq!(store.contents()["tip"], vec![0, 0, 0, 2]);
+        running.abort();
+    });
+}
+
+#[test]
+fn metrics() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "basic",
+            async |out, msg: u32, eff| {
+                eff.send(&out, msg).await;
+                Ok(out)
+            },
+            StageRef::noop::<u32>(),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        let running = network.run();
+
+        let input = running.input(&stage);
+        for msg in [1, 2, 3] {
+            input.send(msg).await.unwrap();
+        }
+        for _ in 0..3 {
+            rx.recv().await.unwrap();
+        }
+        // let the stage finish processing the last message
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let metrics = running.metrics();
+        let basic = metrics.stage("basic").unwrap();
+        assert_eq!(basic.processed, 3);
+        assert_eq!(basic.failures, 0);
+        assert_eq!(basic.processing_time.count, 3);
+        assert_eq!(basic.mailbox_depth.count, 3);
+        assert_eq!(metrics.stage("output").unwrap().processed, 3);
+        assert!(running
+            .export_prometheus()
+            .contains("pure_stage_messages_processed_total{stage=\"basic\"} 3\n"));
+        running.abort();
+    });
+}
+
+#[test]
+fn interrupt_with() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "oracle",
+            async |out, msg: u32, eff| {
+                // without a harness the stage continues with the default decision
+                let keep: bool = eff.interrupt_with(msg).await;
+                eff.send(&out, (msg, keep)).await;
+                Ok(out)
+            },
+            StageRef::noop::<(u32, bool)>(),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        let running = network.run();
+
+        running.input(&stage).send(1).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), (1, false));
+        running.abort();
+    });
+}
+
+#[test]
+fn dead_letters() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let sender = network.stage(
+            "sender",
+            async |targets: Vec<StageRef<u32, Void>>, (idx, msg): (usize, u32), eff| {
+                eff.send(&targets[idx], msg).await;
+                Ok(targets)
+            },
+            Vec::new(),
+        );
+        let failing = network.stage(
+            "failing",
+            async |_state, _msg: u32, _eff| Err(anyhow::anyhow!("stopped")),
+            (),
+        );
+        let (dead_letters, mut rx) = network.output::<DeadLetter>("dead_letters");
+        network.dead_letters(&dead_letters);
+        let failing = network.wire_up(failing, |_| {});
+        let sender = network.wire_up(sender, |targets| {
+            *targets = vec![StageRef::noop(), failing.without_state()]
+        });
+        let running = network.run();
+
+        running.input(&failing).send(0).await.unwrap();
+        // let the failing stage stop
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let input = running.input(&sender);
+        input.send((0, 1)).await.unwrap();
+        input.send((1, 2)).await.unwrap();
+        let mut letters = Vec::new();
+        for _ in 0..2 {
+            let letter = rx.recv().await.unwrap();
+            letters.push((letter.to, letter.reason));
+        }
+        assert_eq!(
+            letters,
+            vec![
+                (Name::from("noop"), DeadLetterReason::Noop),
+                (Name::from("failing"), DeadLetterReason::Terminated),
+            ]
+        );
+        running.abort();
+    });
+}
+
+#[test]
+fn termination() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let summer = network.stage(
+            "summer",
+            async |sum, msg: u32, eff| {
+                if msg == 0 {
+                    eff.terminate();
+                }
+                Ok(sum + msg)
+            },
+            0u32,
+        );
+        let (finals, mut finals_rx) = tokio::sync::mpsc::unbounded_channel();
+        network.finalizer(&summer, move |sum| finals.send(sum).unwrap());
+        let summer = network.wire_up(summer, |_| {});
+        let running = network.run();
+
+        let input = running.input(&summer);
+        for msg in [1, 2, 0] {
+            input.send(msg).await.unwrap();
+        }
+        assert_eq!(finals_rx.recv().await, Some(3));
+        // the mailbox of the terminated stage is closed
+        assert!(input.send(5).await.is_err());
+        running.abort();
+    });
+}
+
+#[test]
+fn shutdown() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let summer = network.stage(
+            "summer",
+            async |sum, msg: u32, eff| {
+                eff.wait(Duration::from_millis(10)).await;
+                Ok(sum + msg)
+            },
+            0u32,
+        );
+        let (finals, mut finals_rx) = tokio::sync::mpsc::unbounded_channel();
+        network.finalizer(&summer, move |sum| finals.send(sum).unwrap());
+        let summer = network.wire_up(summer, |_| {});
+        let running = network.run();
+
+        let input = running.input(&summer);
+        for msg in [1, 2, 4] {
+            input.send(msg).await.unwrap();
+        }
+        for result in running.shutdown(ShutdownPolicy::Drain).await {
+            result.unwrap();
+        }
+        assert_eq!(finals_rx.recv().await, Some(7));
+    });
+}
+
+#[test]
+fn fork_join() {
+    block_on(async {
+        let mut network = TokioBuilder::default();
+        let stage = network.stage(
+            "stage",
+            async |out: StageRef<(u32, u32), Void>, msg: u32, eff| {
+                let slow = eff
+                    .fork(async move {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        msg + 1
+                    })
+                    .await;
+                let fast = eff.fork(async move { msg + 2 }).await;
+                let slow = eff.join(slow).await;
+                let fast = eff.join(fast).await;
+                eff.send(&out, (slow, fast)).await;
+                Ok(out)
+            },
+            StageRef::noop(),
+        );
+        let (output, mut rx) = network.output("output");
+        let stage = network.wire_up(stage, |out| *out = output.without_state());
+        let running = network.run();
+
+        running.input(&stage).send(10).await.unwrap();
+        assert_eq!(rx.recv().await, Some((11, 12)));
+        running.abort();
+    });
+}
@@ -13,11 +13,11 @@
 // limitations under the License.
 
 use amaru_sim::simulator::{self, Args};
-use clap::Parser;
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
+    let args = Args::parse_with_config(std::env::args_os())
+        .unwrap_or_else(|e| panic!("unable to load configuration: {:?}", e));
 
     tracing_subscriber::fmt()
         .with_writer(std::io::stderr)
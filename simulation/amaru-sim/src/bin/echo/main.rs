@@ -21,5 +21,7 @@ async fn main() {
         .json()
         .init();
 
-    run().await;
+    let framed = std::env::args().any(|arg| arg == "--framed");
+
+    run(framed).await;
 }
@@ -0,0 +1,113 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementation of maelstrom's [broadcast workload](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-broadcast),
+//! where nodes talk to each other: clients broadcast values to any node, and read back all
+//! the values a node has seen, which nodes gossip about to their neighbours.
+
+use super::{BroadcastMessage, BroadcastMessage::*, Envelope};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A node of the broadcast workload.
+///
+/// Whenever it learns of new values, a node gossips all the values it has seen to those of
+/// its neighbours which are not known to have them yet, a neighbour being known to have
+/// whatever it gossiped itself. Gossip lost on the way, to a partition say, is thus caught up
+/// with the next time the node learns of something new.
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastNode {
+    /// From the `init` message, all other nodes, until told otherwise by a `topology` one.
+    neighbours: Vec<String>,
+    seen: BTreeSet<u64>,
+    known: BTreeMap<String, BTreeSet<u64>>,
+}
+
+impl BroadcastNode {
+    /// Handle a message sent to this node, returning the replies and gossip it sends.
+    pub fn handle(&mut self, msg: Envelope<BroadcastMessage>) -> Vec<Envelope<BroadcastMessage>> {
+        let reply = |body| Envelope {
+            src: msg.dest.clone(),
+            dest: msg.src.clone(),
+            body,
+        };
+        match &msg.body {
+            Init {
+                msg_id,
+                node_id,
+                node_ids,
+            } => {
+                self.neighbours = node_ids
+                    .iter()
+                    .filter(|id| *id != node_id)
+                    .cloned()
+                    .collect();
+                vec![reply(InitOk {
+                    in_reply_to: *msg_id,
+                })]
+            }
+            Topology { msg_id, topology } => {
+                self.neighbours = topology.get(&msg.dest).cloned().unwrap_or_default();
+                vec![reply(TopologyOk {
+                    in_reply_to: *msg_id,
+                })]
+            }
+            Broadcast { msg_id, message } => {
+                let mut outgoing = vec![reply(BroadcastOk {
+                    in_reply_to: *msg_id,
+                })];
+                if self.seen.insert(*message) {
+                    outgoing.extend(self.gossip(&msg.dest));
+                }
+                outgoing
+            }
+            Read { msg_id } => vec![reply(ReadOk {
+                in_reply_to: *msg_id,
+                messages: self.seen.iter().copied().collect(),
+            })],
+            Gossip { messages } => {
+                self.known
+                    .entry(msg.src.clone())
+                    .or_default()
+                    .extend(messages);
+                let before = self.seen.len();
+                self.seen.extend(messages);
+                if self.seen.len() > before {
+                    self.gossip(&msg.dest)
+                } else {
+                    Vec::new()
+                }
+            }
+            InitOk { .. } | BroadcastOk { .. } | ReadOk { .. } | TopologyOk { .. } => Vec::new(),
+        }
+    }
+
+    /// Gossip what `node_id` has seen to those of its neighbours that may miss some of it.
+    fn gossip(&self, node_id: &str) -> Vec<Envelope<BroadcastMessage>> {
+        self.neighbours
+            .iter()
+            .filter(|neighbour| {
+                self.known
+                    .get(*neighbour)
+                    .is_none_or(|known| !self.seen.is_subset(known))
+            })
+            .map(|neighbour| Envelope {
+                src: node_id.to_string(),
+                dest: neighbour.clone(),
+                body: Gossip {
+                    messages: self.seen.iter().copied().collect(),
+                },
+            })
+            .collect()
+    }
+}
@@ -98,12 +98,16 @@ impl gasket::framework::Worker<ReadInput> for ReadWorker {
 #[stage(name = "write_output", unit = "Action", worker = "OutputWorker")]
 pub struct SendOutput {
     pub upstream: EnvelopeIn,
+    /// Follow the messages sent in response to each input by an empty line, so that the
+    /// reader knows when it has seen all of them.
+    framed: bool,
 }
 
 impl SendOutput {
-    pub fn new() -> Self {
+    pub fn new(framed: bool) -> Self {
         Self {
             upstream: EnvelopeIn::default(),
+            framed,
         }
     }
 
@@ -112,7 +116,12 @@ impl SendOutput {
         writer
             .send(serde_json::to_string(&msg).map_err(|_| WorkerError::Panic)?)
             .await
-            .or_panic()
+            .or_panic()?;
+        if self.framed {
+            // the echo service answers each input with exactly one message
+            writer.send("").await.or_panic()?;
+        }
+        Ok(())
     }
 }
 
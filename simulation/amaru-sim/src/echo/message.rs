@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct Envelope<T> {
@@ -43,11 +44,51 @@ pub enum EchoMessage {
     },
 }
 
+/// The messages of the broadcast workload, along with the gossip nodes exchange, see
+/// [`BroadcastNode`](super::BroadcastNode).
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BroadcastMessage {
+    Init {
+        msg_id: u64,
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+    InitOk {
+        in_reply_to: u64,
+    },
+    Broadcast {
+        msg_id: u64,
+        message: u64,
+    },
+    BroadcastOk {
+        in_reply_to: u64,
+    },
+    Read {
+        msg_id: u64,
+    },
+    ReadOk {
+        in_reply_to: u64,
+        messages: Vec<u64>,
+    },
+    Topology {
+        msg_id: u64,
+        topology: BTreeMap<String, Vec<String>>,
+    },
+    TopologyOk {
+        in_reply_to: u64,
+    },
+    /// Sent by a node to its neighbours: all the values it has seen so far.
+    Gossip {
+        messages: Vec<u64>,
+    },
+}
+
 #[cfg(test)]
 mod test {
     use proptest::{prelude::BoxedStrategy, proptest};
 
-    use super::EchoMessage;
+    use super::{BroadcastMessage, EchoMessage};
 
     fn arbitrary_message() -> BoxedStrategy<EchoMessage> {
         use super::EchoMessage;
@@ -85,4 +126,29 @@ mod test {
             assert_eq!(message, decoded);
         }
     }
+
+    #[test]
+    fn decodes_the_messages_of_the_broadcast_workload() {
+        let topology = r#"{"type":"topology","msg_id":1,"topology":{"n1":["n2"],"n2":["n1"]}}"#;
+        let broadcast = r#"{"type":"broadcast","msg_id":2,"message":1000}"#;
+
+        assert_eq!(
+            serde_json::from_str::<BroadcastMessage>(topology).unwrap(),
+            BroadcastMessage::Topology {
+                msg_id: 1,
+                topology: [
+                    ("n1".to_string(), vec!["n2".to_string()]),
+                    ("n2".to_string(), vec!["n1".to_string()]),
+                ]
+                .into(),
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<BroadcastMessage>(broadcast).unwrap(),
+            BroadcastMessage::Broadcast {
+                msg_id: 2,
+                message: 1000
+            }
+        );
+    }
 }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod broadcast;
 mod io;
 /// Implementation of maelstrom's [echo protocol](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-echo).
 ///
@@ -22,6 +23,7 @@ mod message;
 mod run;
 mod service;
 
+pub use broadcast::*;
 pub use message::*;
 pub use run::*;
 pub use service::*;
@@ -23,8 +23,10 @@ use gasket::runtime::Tether;
 use tokio_util::sync::CancellationToken;
 use tracing::trace;
 
-pub async fn run() {
-    let echo_pipeline = bootstrap();
+/// Run the echo service over stdin and stdout. When `framed`, each batch of responses is
+/// terminated by an empty line, as expected by the simulator's `ProcessNode`.
+pub async fn run(framed: bool) {
+    let echo_pipeline = bootstrap(framed);
 
     let exit = amaru::exit::hook_exit_token();
 
@@ -41,11 +43,11 @@ pub async fn run() {
 /// 2. DoEcho: receives a message, echoes it to the next stage.
 /// 3. SendOutput: receives the echoed message and prints it to stdout as a
 ///    JSON.
-pub fn bootstrap() -> Vec<Tether> {
+pub fn bootstrap(framed: bool) -> Vec<Tether> {
     let mut read_input = ReadInput::new();
     let service = Box::new(EchoService::new());
     let mut echo = DoEcho::new(service);
-    let mut send_output = SendOutput::new();
+    let mut send_output = SendOutput::new(framed);
 
     let (to_echo, from_in) = gasket::messaging::tokio::mpsc_channel(50);
     let (to_out, from_echo) = gasket::messaging::tokio::mpsc_channel(50);
@@ -0,0 +1,279 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generators for peers that do not follow the protocol.
+//!
+//! Each behaviour is generated alongside an honest chain, as the headers the byzantine
+//! peer announces only make sense relative to the chain the other peers agree on. The
+//! byzantine peer always starts by announcing some prefix of the honest chain, so that
+//! its misbehaviour happens on top of headers the node already knows about.
+//!
+//! Headers are fakes, which only chain selection accepts, so the generators are built for
+//! tests only.
+
+use amaru_consensus::peer::Peer;
+use amaru_kernel::Hash;
+use amaru_ouroboros::{fake::FakeHeader, IsHeader};
+use proptest::{collection::vec, prelude::*};
+use std::ops::Range;
+
+/// The ways in which a byzantine peer deviates from the honest chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// Announce two different headers for the same slot.
+    Equivocation,
+    /// Extend a fork anchored deep in the honest chain, which is shorter than it.
+    StaleFork,
+    /// Announce headers whose parent is not a known header.
+    InvalidParent,
+}
+
+/// An honest chain and the headers a byzantine peer announces while the honest peer
+/// announces that chain.
+#[derive(Debug, Clone)]
+pub struct ByzantinePeer {
+    pub misbehaviour: Misbehaviour,
+    pub honest: Vec<FakeHeader>,
+    pub byzantine: Vec<FakeHeader>,
+}
+
+impl ByzantinePeer {
+    /// The headers that are not part of the honest chain, that is the ones the peer made up.
+    pub fn forged(&self) -> impl Iterator<Item = &FakeHeader> {
+        self.byzantine
+            .iter()
+            .filter(|header| !self.honest.contains(header))
+    }
+}
+
+/// Build a chain of headers on top of `anchor` (or genesis), with the given body hashes.
+pub fn chain_from(anchor: Option<&FakeHeader>, bodies: Vec<[u8; 32]>) -> Vec<FakeHeader> {
+    let mut parent = anchor.copied();
+    bodies
+        .into_iter()
+        .map(|body| {
+            let header = FakeHeader {
+                block_number: parent.map_or(0, |h| h.block_height()) + 1,
+                slot: parent.map_or(0, |h| h.slot()) + 1,
+                parent: parent.map(|h| h.hash()),
+                body_hash: body.into(),
+            };
+            parent = Some(header);
+            header
+        })
+        .collect()
+}
+
+/// Generate an honest chain, anchored at genesis, with a length within `length`.
+pub fn honest_chain(length: Range<usize>) -> impl Strategy<Value = Vec<FakeHeader>> {
+    vec(any::<[u8; 32]>(), length).prop_map(|bodies| chain_from(None, bodies))
+}
+
+/// A peer that follows the honest chain up to some header, then announces another header
+/// for the same slot and parent.
+pub fn equivocating_peer(length: Range<usize>) -> impl Strategy<Value = ByzantinePeer> {
+    honest_chain(length)
+        .prop_flat_map(|honest| {
+            let len = honest.len();
+            (Just(honest), 0..len, any::<[u8; 32]>())
+        })
+        .prop_filter("equivocation must differ", |(honest, at, body)| {
+            honest[*at].body_hash != Hash::from(*body)
+        })
+        .prop_map(|(honest, at, body)| {
+            let mut byzantine = honest[..=at].to_vec();
+            byzantine.push(FakeHeader {
+                body_hash: body.into(),
+                ..honest[at]
+            });
+            ByzantinePeer {
+                misbehaviour: Misbehaviour::Equivocation,
+                honest,
+                byzantine,
+            }
+        })
+}
+
+/// A peer that follows the honest chain up to some header, then extends a fork from it
+/// that never catches up with the honest chain.
+pub fn stale_fork_peer(length: Range<usize>) -> impl Strategy<Value = ByzantinePeer> {
+    honest_chain(length)
+        .prop_filter("no room for a shorter fork", |honest| honest.len() >= 3)
+        .prop_flat_map(|honest| {
+            let len = honest.len();
+            (Just(honest), 0..len - 2)
+        })
+        .prop_flat_map(|(honest, anchor)| {
+            // the fork tip must stay strictly below the honest tip
+            let room = honest.len() - anchor - 1;
+            (Just(honest), Just(anchor), vec(any::<[u8; 32]>(), 1..room))
+        })
+        .prop_map(|(honest, anchor, bodies)| {
+            let mut byzantine = honest[..=anchor].to_vec();
+            byzantine.extend(chain_from(Some(&honest[anchor]), bodies));
+            ByzantinePeer {
+                misbehaviour: Misbehaviour::StaleFork,
+                honest,
+                byzantine,
+            }
+        })
+}
+
+/// A peer that follows the honest chain up to some point, then announces headers whose
+/// parent hash does not refer to anything it announced before.
+pub fn invalid_parent_peer(length: Range<usize>) -> impl Strategy<Value = ByzantinePeer> {
+    honest_chain(length)
+        .prop_flat_map(|honest| {
+            let len = honest.len();
+            (
+                Just(honest),
+                0..=len,
+                vec((any::<[u8; 32]>(), any::<[u8; 32]>()), 1..5),
+            )
+        })
+        .prop_map(|(honest, prefix, forged)| {
+            let mut byzantine = honest[..prefix].to_vec();
+            let tip = byzantine.last().copied();
+            byzantine.extend(forged.into_iter().map(|(parent, body)| FakeHeader {
+                block_number: tip.map_or(0, |h| h.block_height()) + 1,
+                slot: tip.map_or(0, |h| h.slot()) + 1,
+                parent: Some(parent.into()),
+                body_hash: body.into(),
+            }));
+            ByzantinePeer {
+                misbehaviour: Misbehaviour::InvalidParent,
+                honest,
+                byzantine,
+            }
+        })
+        .prop_filter("forged parent must be unknown", |peer| {
+            let known: Vec<_> = peer.honest.iter().map(|h| h.hash()).collect();
+            peer.forged().all(|header| {
+                header
+                    .parent
+                    .is_some_and(|p| p != Hash::from([0; 32]) && !known.contains(&p))
+            })
+        })
+}
+
+/// Any of the byzantine behaviours above.
+pub fn any_byzantine_peer(length: Range<usize>) -> impl Strategy<Value = ByzantinePeer> {
+    prop_oneof![
+        equivocating_peer(length.clone()),
+        stale_fork_peer(length.clone()),
+        invalid_parent_peer(length),
+    ]
+}
+
+/// Interleave the announcements of the honest and byzantine peers, preserving the order
+/// in which each of them announces its own headers.
+pub fn interleaved(
+    honest: Peer,
+    byzantine: Peer,
+    peer: ByzantinePeer,
+) -> impl Strategy<Value = Vec<(Peer, FakeHeader)>> {
+    let total = peer.honest.len() + peer.byzantine.len();
+    proptest::sample::subsequence((0..total).collect::<Vec<_>>(), peer.honest.len()).prop_map(
+        move |honest_turns| {
+            let mut from_honest = peer.honest.iter();
+            let mut from_byzantine = peer.byzantine.iter();
+            (0..total)
+                .filter_map(|turn| {
+                    if honest_turns.contains(&turn) {
+                        from_honest.next().map(|h| (honest.clone(), *h))
+                    } else {
+                        from_byzantine.next().map(|h| (byzantine.clone(), *h))
+                    }
+                })
+                .collect()
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amaru_consensus::consensus::chain_selection::{
+        ChainSelectorBuilder, Fork, ForwardChainSelection,
+    };
+
+    /// Feed the interleaved announcements to chain selection, returning the final tip and
+    /// the headers that were forged by the byzantine peer yet got selected.
+    fn select(
+        peer: &ByzantinePeer,
+        announcements: Vec<(Peer, FakeHeader)>,
+    ) -> (Option<FakeHeader>, Vec<FakeHeader>) {
+        let alice = Peer::new("alice");
+        let mallory = Peer::new("mallory");
+        let mut chain_selector = ChainSelectorBuilder::new()
+            .add_peer(&alice)
+            .add_peer(&mallory)
+            .build()
+            .unwrap();
+
+        let forged: Vec<_> = peer.forged().copied().collect();
+        let mut tip = None;
+        let mut selected_forged = vec![];
+        for (from, header) in announcements {
+            let selected = match chain_selector.select_roll_forward(&from, header) {
+                ForwardChainSelection::NewTip(hdr) => vec![hdr],
+                ForwardChainSelection::SwitchToFork(Fork { fork, .. }) => fork,
+                ForwardChainSelection::NoChange => vec![],
+            };
+            if let Some(last) = selected.last() {
+                tip = Some(*last);
+            }
+            selected_forged.extend(selected.into_iter().filter(|h| forged.contains(h)));
+        }
+        (tip, selected_forged)
+    }
+
+    fn scenario(
+        strategy: impl Strategy<Value = ByzantinePeer>,
+    ) -> impl Strategy<Value = (ByzantinePeer, Vec<(Peer, FakeHeader)>)> {
+        strategy.prop_flat_map(|peer| {
+            (
+                Just(peer.clone()),
+                interleaved(Peer::new("alice"), Peer::new("mallory"), peer),
+            )
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn equivocating_headers_are_ignored((peer, announcements) in scenario(equivocating_peer(1..20))) {
+            let (tip, selected_forged) = select(&peer, announcements);
+            prop_assert_eq!(tip, peer.honest.last().copied());
+            prop_assert!(selected_forged.is_empty(), "selected {:?}", selected_forged);
+        }
+
+        #[test]
+        fn headers_with_unknown_parent_are_ignored((peer, announcements) in scenario(invalid_parent_peer(1..20))) {
+            let (tip, selected_forged) = select(&peer, announcements);
+            prop_assert_eq!(tip, peer.honest.last().copied());
+            prop_assert!(selected_forged.is_empty(), "selected {:?}", selected_forged);
+        }
+
+        #[test]
+        fn honest_chain_wins_over_stale_fork((peer, announcements) in scenario(stale_fork_peer(3..20))) {
+            let (tip, _) = select(&peer, announcements);
+            prop_assert_eq!(tip, peer.honest.last().copied());
+        }
+
+        #[test]
+        fn byzantine_peers_announce_some_forged_header(peer in any_byzantine_peer(2..20)) {
+            prop_assert!(peer.forged().next().is_some(), "{:?}", peer.misbehaviour);
+        }
+    }
+}
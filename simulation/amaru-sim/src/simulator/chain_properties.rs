@@ -0,0 +1,587 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The properties Ouroboros guarantees about the chains honest nodes select, checked over
+//! the trace of a simulation.
+//!
+//! The chain of a node is reconstructed from the headers it announces: a `fwd` extends it
+//! and a `bck` rolls it back to the given point. Nodes announce their chain to all their
+//! downstream peers, so a `fwd` of the current tip is ignored rather than extending the
+//! chain twice.
+//!
+//! The chain a node serves to a downstream client is reconstructed in the same way from the
+//! answers to its chain sync requests.
+//!
+//! Beside chains, nodes are checked to answer requests the way the protocol says, see
+//! [`replies_follow_requests`].
+
+use super::{bytes::Bytes, simulate::Trace, sync::ChainSyncMessage};
+use slot_arithmetic::Slot;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+
+/// A block of the chain selected by a node, as announced by that node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnouncedBlock {
+    pub slot: Slot,
+    pub hash: Bytes,
+    pub header: Bytes,
+}
+
+pub type Chains = BTreeMap<String, Vec<AnnouncedBlock>>;
+
+/// Apply an announcement to the chain of the node which sent it, returning whether it is one.
+fn announce(chain: &mut Vec<AnnouncedBlock>, body: &ChainSyncMessage) -> bool {
+    match body {
+        ChainSyncMessage::Fwd {
+            slot, hash, header, ..
+        } => {
+            if chain.last().is_some_and(|tip| tip.hash == *hash) {
+                return false;
+            }
+            chain.push(AnnouncedBlock {
+                slot: *slot,
+                hash: hash.clone(),
+                header: header.clone(),
+            });
+            true
+        }
+        ChainSyncMessage::Bck { hash, .. } => {
+            // rolling back to a point that was never announced forgets the whole chain
+            let keep = chain
+                .iter()
+                .position(|block| block.hash == *hash)
+                .map_or(0, |at| at + 1);
+            chain.truncate(keep);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Replay the announcements of the given nodes, calling `on_update` with the chains of all
+/// of them every time one of them changed.
+fn replay(
+    trace: &Trace<ChainSyncMessage>,
+    nodes: &[&str],
+    mut on_update: impl FnMut(&str, &Chains) -> Result<(), String>,
+) -> Result<Chains, String> {
+    let mut chains: Chains = nodes
+        .iter()
+        .map(|node| (node.to_string(), Vec::new()))
+        .collect();
+    for envelope in &trace.0 {
+        let Some(chain) = chains.get_mut(&envelope.src) else {
+            continue;
+        };
+        if announce(chain, &envelope.body) {
+            on_update(&envelope.src, &chains)?;
+        }
+    }
+    Ok(chains)
+}
+
+/// The chains the given nodes selected by the end of the trace.
+pub fn selected_chains(trace: &Trace<ChainSyncMessage>, nodes: &[&str]) -> Chains {
+    // no check can fail
+    replay(trace, nodes, |_, _| Ok(())).unwrap_or_default()
+}
+
+/// The slots of the blocks of `chain`.
+pub fn describe(chain: &[AnnouncedBlock]) -> String {
+    let slots: Vec<String> = chain.iter().map(|block| block.slot.to_string()).collect();
+    format!("[{}]", slots.join(", "))
+}
+
+/// Common prefix: at any time, the chain of a node without its last `k` blocks is a prefix of
+/// the chain of any other node.
+pub fn common_prefix(
+    trace: &Trace<ChainSyncMessage>,
+    nodes: &[&str],
+    k: usize,
+) -> Result<(), String> {
+    replay(trace, nodes, |updated, chains| {
+        let chain = &chains[updated];
+        for (other, other_chain) in chains {
+            for (a, chain_a, b, chain_b) in [
+                (updated, chain, other.as_str(), other_chain),
+                (other.as_str(), other_chain, updated, chain),
+            ] {
+                let pruned = &chain_a[..chain_a.len().saturating_sub(k)];
+                if !chain_b.starts_with(pruned) {
+                    return Err(format!(
+                        "common prefix violated for k = {}: {} selected {} while {} selected {}",
+                        k,
+                        a,
+                        describe(chain_a),
+                        b,
+                        describe(chain_b)
+                    ));
+                }
+            }
+        }
+        Ok(())
+    })
+    .map(|_| ())
+}
+
+/// The windows of `window` slots within the span of the chain, starting at the given slots,
+/// along with the blocks they contain.
+fn windows(
+    chain: &[AnnouncedBlock],
+    window: u64,
+    starts: impl Iterator<Item = u64>,
+) -> Vec<(u64, &[AnnouncedBlock])> {
+    let (Some(first), Some(last)) = (chain.first(), chain.last()) else {
+        return Vec::new();
+    };
+    let last = u64::from(last.slot);
+    starts
+        .filter(|start| *start >= u64::from(first.slot) && start + window - 1 <= last)
+        .map(|start| {
+            let from = chain.partition_point(|block| u64::from(block.slot) < start);
+            let to = chain.partition_point(|block| u64::from(block.slot) < start + window);
+            (start, &chain[from..to])
+        })
+        .collect()
+}
+
+/// The least number of blocks chains hold in any window of slots, see [`chain_growth`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainGrowth {
+    pub min_blocks: usize,
+    pub window: u64,
+}
+
+/// Parse rates of the form `3/100`, for at least 3 blocks in any window of 100 slots.
+impl FromStr for ChainGrowth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min_blocks, window) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected <blocks>/<slots>, found '{}'", s))?;
+        let min_blocks = min_blocks
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid number of blocks '{}': {}", min_blocks, e))?;
+        let window = window
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid number of slots '{}': {}", window, e))?;
+        Ok(ChainGrowth { min_blocks, window })
+    }
+}
+
+/// Chain growth: the final chain of every node holds at least `min_blocks` blocks in any
+/// window of `window` consecutive slots between its first and last block.
+pub fn chain_growth(
+    trace: &Trace<ChainSyncMessage>,
+    nodes: &[&str],
+    window: u64,
+    min_blocks: usize,
+) -> Result<(), String> {
+    for (node, chain) in selected_chains(trace, nodes) {
+        // the emptiest windows start right after a block
+        let starts = chain
+            .first()
+            .map(|block| u64::from(block.slot))
+            .into_iter()
+            .chain(chain.iter().map(|block| u64::from(block.slot) + 1));
+        for (start, blocks) in windows(&chain, window.max(1), starts) {
+            if blocks.len() < min_blocks {
+                return Err(format!(
+                    "chain growth violated: {} has {} block(s) in slots {}..{}, expected at least {}",
+                    node,
+                    blocks.len(),
+                    start,
+                    start + window,
+                    min_blocks
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Chain quality: in the final chain of every node, any window of `window` consecutive slots
+/// containing blocks has at least a `min_ratio` fraction of blocks accepted by `is_honest`.
+pub fn chain_quality(
+    trace: &Trace<ChainSyncMessage>,
+    nodes: &[&str],
+    window: u64,
+    min_ratio: f64,
+    is_honest: impl Fn(&AnnouncedBlock) -> bool,
+) -> Result<(), String> {
+    for (node, chain) in selected_chains(trace, nodes) {
+        let starts = chain.iter().map(|block| u64::from(block.slot));
+        for (start, blocks) in windows(&chain, window.max(1), starts) {
+            let honest = blocks.iter().filter(|block| is_honest(block)).count();
+            if (honest as f64) < min_ratio * blocks.len() as f64 {
+                return Err(format!(
+                    "chain quality violated: {} has {} honest block(s) out of {} in slots {}..{}, expected a ratio of at least {}",
+                    node,
+                    honest,
+                    blocks.len(),
+                    start,
+                    start + window,
+                    min_ratio
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rollbacks are bounded: no node ever rolls its chain back by more than `k` blocks, the
+/// security parameter, past which blocks are final.
+pub fn bounded_rollbacks(
+    trace: &Trace<ChainSyncMessage>,
+    nodes: &[&str],
+    k: usize,
+) -> Result<(), String> {
+    let mut chains: Chains = nodes
+        .iter()
+        .map(|node| (node.to_string(), Vec::new()))
+        .collect();
+    for envelope in &trace.0 {
+        let Some(chain) = chains.get_mut(&envelope.src) else {
+            continue;
+        };
+        if !matches!(envelope.body, ChainSyncMessage::Bck { .. }) {
+            announce(chain, &envelope.body);
+            continue;
+        }
+        let before = chain.clone();
+        announce(chain, &envelope.body);
+        let depth = before.len() - chain.len();
+        if depth > k {
+            return Err(format!(
+                "{} rolled back {} block(s) of {}, deeper than k = {}",
+                envelope.src,
+                depth,
+                describe(&before),
+                k
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Chain sync serves the selected chain: whenever `node` tells `client` it has nothing more
+/// to send, the chain it served to the client is the one it announced last, and it never
+/// rolls the client back to a point it didn't serve.
+pub fn serves_selected_chain(
+    trace: &Trace<ChainSyncMessage>,
+    node: &str,
+    client: &str,
+) -> Result<(), String> {
+    let mut selected = Vec::new();
+    let mut served: Vec<AnnouncedBlock> = Vec::new();
+    for envelope in trace.0.iter().filter(|envelope| envelope.src == node) {
+        if announce(&mut selected, &envelope.body) || envelope.dest != client {
+            continue;
+        }
+        match &envelope.body {
+            ChainSyncMessage::IntersectFound { hash, .. }
+            | ChainSyncMessage::RollBackward { hash, .. } => {
+                let keep = match served.iter().position(|block| block.hash == *hash) {
+                    Some(at) => at + 1,
+                    None if hash.bytes.is_empty() => 0,
+                    // the client only ever intersects with its empty chain
+                    None if served.is_empty() => continue,
+                    None => {
+                        return Err(format!(
+                            "{} rolled {} back to {} which it never served, after {}",
+                            node,
+                            client,
+                            hex::encode(&hash.bytes),
+                            describe(&served)
+                        ))
+                    }
+                };
+                served.truncate(keep);
+            }
+            ChainSyncMessage::RollForward {
+                slot, hash, header, ..
+            } => served.push(AnnouncedBlock {
+                slot: *slot,
+                hash: hash.clone(),
+                header: header.clone(),
+            }),
+            ChainSyncMessage::AwaitReply { .. } if served != selected => {
+                return Err(format!(
+                    "{} served {} to {} while it selected {}",
+                    node,
+                    describe(&served),
+                    client,
+                    describe(&selected)
+                ))
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Replies are caused by requests: `nodes` give each message they send an identifier greater
+/// than those of the messages they sent before, and only ever answer requests they received,
+/// the `in_reply_to` of their replies being the `msg_id` of a message their destination sent
+/// them earlier.
+///
+/// A message duplicated by the network keeps the identifier of the original, so the same
+/// message may show up twice in a row.
+pub fn replies_follow_requests(
+    trace: &Trace<ChainSyncMessage>,
+    nodes: &[&str],
+) -> Result<(), String> {
+    let mut requests = BTreeSet::new();
+    let mut last_sent: BTreeMap<&str, &ChainSyncMessage> = BTreeMap::new();
+    for envelope in &trace.0 {
+        let is_node = nodes.contains(&envelope.src.as_str());
+        if let (true, Some(in_reply_to)) = (is_node, envelope.body.in_reply_to()) {
+            if !requests.contains(&(envelope.dest.as_str(), envelope.src.as_str(), in_reply_to)) {
+                return Err(format!(
+                    "{} replied to message {} of {} which it never received: {:?}",
+                    envelope.src, in_reply_to, envelope.dest, envelope.body
+                ));
+            }
+        }
+        let Some(msg_id) = envelope.body.msg_id() else {
+            continue;
+        };
+        requests.insert((envelope.src.as_str(), envelope.dest.as_str(), msg_id));
+        if !is_node {
+            continue;
+        }
+        if let Some(previous) = last_sent.insert(envelope.src.as_str(), &envelope.body) {
+            let previous_id = previous.msg_id().unwrap_or_default();
+            if msg_id < previous_id || (msg_id == previous_id && *previous != envelope.body) {
+                return Err(format!(
+                    "{} sent message {} after message {}: {:?}",
+                    envelope.src, msg_id, previous_id, envelope.body
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::echo::Envelope;
+
+    fn fwd(node: &str, slot: u64, hash: u8) -> Envelope<ChainSyncMessage> {
+        Envelope {
+            src: node.to_string(),
+            dest: "c1".to_string(),
+            body: ChainSyncMessage::Fwd {
+                msg_id: 0,
+                slot: Slot::from(slot),
+                hash: vec![hash].into(),
+                header: vec![].into(),
+                block: None,
+            },
+        }
+    }
+
+    fn bck(node: &str, slot: u64, hash: u8) -> Envelope<ChainSyncMessage> {
+        Envelope {
+            src: node.to_string(),
+            dest: "c1".to_string(),
+            body: ChainSyncMessage::Bck {
+                msg_id: 0,
+                slot: Slot::from(slot),
+                hash: vec![hash].into(),
+            },
+        }
+    }
+
+    /// n1 and n2 agree on the first two blocks, then n2 switches to a fork of length two.
+    fn forking_trace() -> Trace<ChainSyncMessage> {
+        Trace(vec![
+            fwd("n1", 1, 1),
+            fwd("n2", 1, 1),
+            fwd("n1", 2, 2),
+            fwd("n2", 2, 2),
+            fwd("n1", 3, 3),
+            fwd("n1", 4, 4),
+            fwd("n2", 3, 13),
+            fwd("n2", 4, 14),
+            bck("n2", 2, 2),
+            fwd("n2", 3, 3),
+            fwd("n2", 4, 4),
+        ])
+    }
+
+    #[test]
+    fn reconstructs_chains_through_rollbacks_and_duplicates() {
+        let mut trace = forking_trace();
+        trace.0.push(fwd("n2", 4, 4));
+
+        let chains = selected_chains(&trace, &["n1", "n2"]);
+
+        assert_eq!(chains["n1"], chains["n2"]);
+        assert_eq!(describe(&chains["n2"]), "[1, 2, 3, 4]");
+    }
+
+    #[test]
+    fn common_prefix_depends_on_depth_of_forks() {
+        let trace = forking_trace();
+
+        assert!(common_prefix(&trace, &["n1", "n2"], 2).is_ok());
+        assert!(common_prefix(&trace, &["n1", "n2"], 1).is_err());
+    }
+
+    #[test]
+    fn bounded_rollbacks_measure_the_depth_of_rollbacks() {
+        let trace = forking_trace();
+
+        assert!(bounded_rollbacks(&trace, &["n1", "n2"], 2).is_ok());
+        assert!(bounded_rollbacks(&trace, &["n1", "n2"], 1)
+            .unwrap_err()
+            .contains("rolled back 2 block(s)"));
+    }
+
+    #[test]
+    fn chain_growth_catches_gaps() {
+        let trace = Trace(vec![fwd("n1", 1, 1), fwd("n1", 2, 2), fwd("n1", 10, 3)]);
+
+        assert!(chain_growth(&trace, &["n1"], 10, 1).is_ok());
+        assert!(chain_growth(&trace, &["n1"], 5, 1).is_err());
+    }
+
+    #[test]
+    fn chain_growth_is_parsed_from_blocks_per_slots() {
+        assert_eq!(
+            "3/100".parse(),
+            Ok(ChainGrowth {
+                min_blocks: 3,
+                window: 100,
+            })
+        );
+        assert!("3".parse::<ChainGrowth>().is_err());
+        assert!("3/many".parse::<ChainGrowth>().is_err());
+    }
+
+    #[test]
+    fn chain_quality_counts_honest_blocks_in_windows() {
+        let trace = Trace(vec![
+            fwd("n1", 1, 1),
+            fwd("n1", 2, 0xff),
+            fwd("n1", 3, 2),
+            fwd("n1", 4, 3),
+        ]);
+        let is_honest = |block: &AnnouncedBlock| block.hash.bytes != vec![0xff];
+
+        assert!(chain_quality(&trace, &["n1"], 2, 0.5, is_honest).is_ok());
+        assert!(chain_quality(&trace, &["n1"], 2, 0.6, is_honest).is_err());
+    }
+
+    fn serve(body: ChainSyncMessage) -> Envelope<ChainSyncMessage> {
+        Envelope {
+            src: "n1".to_string(),
+            dest: "d1".to_string(),
+            body,
+        }
+    }
+
+    fn roll_forward(slot: u64, hash: u8) -> Envelope<ChainSyncMessage> {
+        serve(ChainSyncMessage::RollForward {
+            in_reply_to: 0,
+            slot: Slot::from(slot),
+            hash: vec![hash].into(),
+            header: vec![].into(),
+        })
+    }
+
+    fn roll_backward(slot: u64, hash: u8) -> Envelope<ChainSyncMessage> {
+        serve(ChainSyncMessage::RollBackward {
+            in_reply_to: 0,
+            slot: Slot::from(slot),
+            hash: vec![hash].into(),
+        })
+    }
+
+    fn await_reply() -> Envelope<ChainSyncMessage> {
+        serve(ChainSyncMessage::AwaitReply { in_reply_to: 0 })
+    }
+
+    #[test]
+    fn served_chain_must_catch_up_with_the_selected_one() {
+        let mut trace = Trace(vec![
+            fwd("n1", 1, 1),
+            fwd("n1", 2, 2),
+            roll_forward(1, 1),
+            roll_forward(2, 2),
+            await_reply(),
+            bck("n1", 1, 1),
+            fwd("n1", 2, 12),
+            roll_backward(1, 1),
+            roll_forward(2, 12),
+            await_reply(),
+        ]);
+
+        assert!(serves_selected_chain(&trace, "n1", "d1").is_ok());
+
+        trace.0.remove(8);
+        assert!(serves_selected_chain(&trace, "n1", "d1").is_err());
+
+        trace.0.insert(7, roll_backward(1, 7));
+        assert!(serves_selected_chain(&trace, "n1", "d1")
+            .unwrap_err()
+            .contains("never served"));
+    }
+
+    #[test]
+    fn replies_must_answer_earlier_requests_with_increasing_ids() {
+        let request = |msg_id| Envelope {
+            src: "d1".to_string(),
+            dest: "n1".to_string(),
+            body: ChainSyncMessage::RequestNext { msg_id },
+        };
+        let announce = |msg_id| {
+            serve(ChainSyncMessage::Bck {
+                msg_id,
+                slot: Slot::from(1),
+                hash: vec![1].into(),
+            })
+        };
+        let mut trace = Trace(vec![
+            announce(1),
+            request(7),
+            serve(ChainSyncMessage::AwaitReply { in_reply_to: 7 }),
+            announce(2),
+            announce(2),
+        ]);
+
+        assert_eq!(replies_follow_requests(&trace, &["n1"]), Ok(()));
+
+        trace
+            .0
+            .push(serve(ChainSyncMessage::AwaitReply { in_reply_to: 8 }));
+        assert!(replies_follow_requests(&trace, &["n1"])
+            .unwrap_err()
+            .contains("never received"));
+
+        trace.0.pop();
+        trace.0.push(announce(1));
+        assert!(replies_follow_requests(&trace, &["n1"])
+            .unwrap_err()
+            .contains("sent message 1 after message 2"));
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simulator configuration files, setting up a simulation in a JSON file that can be kept
+//! under version control rather than in a long command line:
+//!
+//! ```json
+//! {
+//!   "number_of_nodes": 3,
+//!   "seed": 42,
+//!   "stake_distribution_file": "stake-distribution.json",
+//!   "consensus_context_file": "consensus-context.json",
+//!   "security_param": 5,
+//!   "latency": {
+//!     "default": { "base_ms": 50, "jitter_ms": 100 },
+//!     "links": [{ "src": "n1", "dest": "n2", "base_ms": 500 }]
+//!   },
+//!   "bandwidths": [{ "nodes": ["n1", "n3"], "bytes_per_second": 1000 }],
+//!   "scenario": {
+//!     "faults": [{ "action": "crash", "at_ms": 20000, "node": "n3" }]
+//!   }
+//! }
+//! ```
+//!
+//! Every field is optional, and named after the command line option it stands for, except
+//! for the `latency` of the links between nodes, the `bandwidths` of some of them and the
+//! inline `scenario`, which only a configuration file sets. The `bandwidth` option then
+//! limits the links not listed in `bandwidths`.
+//!
+//! Instead of listing links one by one, the `latency` can hold a `matrix` of the latencies
+//! between some `participants`, with a row for each sender and a column for each receiver,
+//! which the `links` listed alongside override:
+//!
+//! ```json
+//! {
+//!   "latency": {
+//!     "matrix": {
+//!       "participants": ["n1", "n2"],
+//!       "rows": [
+//!         [{ "base_ms": 0 }, { "base_ms": 50 }],
+//!         [{ "base_ms": 80, "jitter_ms": 20 }, { "base_ms": 0 }]
+//!       ]
+//!     }
+//!   }
+//! }
+//! ``` Options given on the command line override those of the file,
+//! and a `--scenario` file its inline scenario. Relative paths are relative to the
+//! directory of the configuration file.
+
+use super::{
+    nemesis::ChaosWeights,
+    scenario::Scenario,
+    simulate::{Latency, NodeId, World},
+    Args,
+};
+use clap::{parser::ValueSource, ArgMatches};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum ConfigError {
+    IOError(std::io::Error),
+    InvalidConfig(serde_json::Error),
+    InvalidOption(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulatorConfig {
+    pub number_of_nodes: Option<u8>,
+    pub seed: Option<u64>,
+    pub stake_distribution_file: Option<PathBuf>,
+    pub consensus_context_file: Option<PathBuf>,
+    pub chain_dir: Option<PathBuf>,
+    pub in_memory: Option<bool>,
+    pub security_param: Option<u64>,
+    pub bandwidth: Option<u64>,
+    /// The weights of the `chaos` option, in the same format, e.g. `drop=3,crash=1`.
+    pub chaos: Option<String>,
+    pub chaos_interval: Option<u64>,
+    pub latency: Option<LatencyModel>,
+    #[serde(default)]
+    pub bandwidths: Vec<LinkBandwidth>,
+    pub scenario: Option<Scenario>,
+}
+
+/// How long a message takes to travel a link, see [`Latency`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LinkLatency {
+    pub base_ms: u64,
+    #[serde(default)]
+    pub jitter_ms: u64,
+}
+
+impl From<LinkLatency> for Latency {
+    fn from(latency: LinkLatency) -> Self {
+        Latency {
+            base: Duration::from_millis(latency.base_ms),
+            jitter: Duration::from_millis(latency.jitter_ms),
+        }
+    }
+}
+
+/// The latency of the messages `src` sends to `dest`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub src: NodeId,
+    pub dest: NodeId,
+    #[serde(flatten)]
+    pub latency: LinkLatency,
+}
+
+/// The latency of the links of a multi-node run.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LatencyModel {
+    /// The latency of the links not listed in `links`, instead of [`Latency::default`].
+    pub default: Option<LinkLatency>,
+    pub matrix: Option<LatencyMatrix>,
+    #[serde(default)]
+    pub links: Vec<Link>,
+}
+
+/// The latency of every link between `participants`, see [`World::with_latency_matrix`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LatencyMatrix {
+    pub participants: Vec<NodeId>,
+    pub rows: Vec<Vec<LinkLatency>>,
+}
+
+impl LatencyMatrix {
+    fn is_square(&self) -> bool {
+        let n = self.participants.len();
+        self.rows.len() == n && self.rows.iter().all(|row| row.len() == n)
+    }
+}
+
+/// The bandwidth of the link between two nodes, in both directions, see
+/// [`World::with_bandwidth`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkBandwidth {
+    pub nodes: (NodeId, NodeId),
+    pub bytes_per_second: u64,
+}
+
+impl LatencyModel {
+    pub fn apply<Msg>(&self, mut world: World<Msg>) -> World<Msg> {
+        if let Some(latency) = self.default {
+            world = world.with_default_latency(latency.into());
+        }
+        if let Some(matrix) = &self.matrix {
+            let participants: Vec<&str> = matrix.participants.iter().map(String::as_str).collect();
+            let rows: Vec<Vec<Latency>> = matrix
+                .rows
+                .iter()
+                .map(|row| row.iter().map(|&latency| latency.into()).collect())
+                .collect();
+            world = world.with_latency_matrix(&participants, &rows);
+        }
+        for link in &self.links {
+            world = world.with_latency(link.src.clone(), link.dest.clone(), link.latency.into());
+        }
+        world
+    }
+}
+
+impl SimulatorConfig {
+    /// Read a configuration file, making its relative paths relative to its directory.
+    pub fn from_file(config_file: &Path) -> Result<Self, ConfigError> {
+        let config = std::fs::read_to_string(config_file).map_err(ConfigError::IOError)?;
+        let mut config: SimulatorConfig =
+            serde_json::from_str(&config).map_err(ConfigError::InvalidConfig)?;
+        let dir = config_file.parent().unwrap_or(Path::new(""));
+        for path in [
+            &mut config.stake_distribution_file,
+            &mut config.consensus_context_file,
+            &mut config.chain_dir,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = dir.join(&*path);
+        }
+        Ok(config)
+    }
+
+    /// Fill in the options of `args` that were not given on the command line, as told by
+    /// the `matches` they were parsed from.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) -> Result<(), ConfigError> {
+        let chaos = self
+            .chaos
+            .map(|weights| {
+                weights
+                    .parse::<ChaosWeights>()
+                    .map_err(|e| ConfigError::InvalidOption("chaos".to_string(), e))
+            })
+            .transpose()?;
+        if let Some(matrix) = self
+            .latency
+            .as_ref()
+            .and_then(|latency| latency.matrix.as_ref())
+        {
+            if !matrix.is_square() {
+                return Err(ConfigError::InvalidOption(
+                    "latency".to_string(),
+                    format!("expected a {n}x{n} matrix", n = matrix.participants.len()),
+                ));
+            }
+        }
+
+        fill(
+            matches,
+            "number_of_nodes",
+            &mut args.number_of_nodes,
+            self.number_of_nodes,
+        );
+        fill(matches, "seed", &mut args.seed, self.seed.map(Some));
+        fill(
+            matches,
+            "stake_distribution_file",
+            &mut args.stake_distribution_file,
+            self.stake_distribution_file,
+        );
+        fill(
+            matches,
+            "consensus_context_file",
+            &mut args.consensus_context_file,
+            self.consensus_context_file,
+        );
+        fill(matches, "chain_dir", &mut args.chain_dir, self.chain_dir);
+        fill(matches, "in_memory", &mut args.in_memory, self.in_memory);
+        fill(
+            matches,
+            "security_param",
+            &mut args.security_param,
+            self.security_param.map(Some),
+        );
+        fill(
+            matches,
+            "bandwidth",
+            &mut args.bandwidth,
+            self.bandwidth.map(Some),
+        );
+        fill(matches, "chaos", &mut args.chaos, chaos.map(Some));
+        fill(
+            matches,
+            "chaos_interval",
+            &mut args.chaos_interval,
+            self.chaos_interval,
+        );
+        args.latency = self.latency;
+        args.bandwidths = self.bandwidths;
+        args.inline_scenario = self.scenario;
+        Ok(())
+    }
+}
+
+/// Set the option `id` to `value`, if any, unless it was given on the command line.
+fn fill<T>(matches: &ArgMatches, id: &str, option: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            *option = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_options_override_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("simulation.json");
+        std::fs::write(
+            &config_file,
+            r#"{
+                "number_of_nodes": 3,
+                "seed": 42,
+                "stake_distribution_file": "stake.json",
+                "chaos": "drop=1",
+                "latency": { "links": [{ "src": "n1", "dest": "n2", "base_ms": 500 }] },
+                "bandwidths": [{ "nodes": ["n1", "n3"], "bytes_per_second": 1000 }]
+            }"#,
+        )
+        .unwrap();
+
+        let args = Args::parse_with_config([
+            "amaru-sim",
+            "--config",
+            config_file.to_str().unwrap(),
+            "--seed",
+            "7",
+        ])
+        .unwrap();
+
+        assert_eq!(args.number_of_nodes, 3);
+        assert_eq!(args.seed, Some(7));
+        assert_eq!(args.stake_distribution_file, dir.path().join("stake.json"));
+        assert_eq!(
+            args.consensus_context_file,
+            PathBuf::from("./consensus_context.json")
+        );
+        assert!(args.chaos.is_some());
+        assert_eq!(
+            args.latency.unwrap().links,
+            vec![Link {
+                src: "n1".to_string(),
+                dest: "n2".to_string(),
+                latency: LinkLatency {
+                    base_ms: 500,
+                    jitter_ms: 0,
+                },
+            }]
+        );
+        assert_eq!(
+            args.bandwidths,
+            vec![LinkBandwidth {
+                nodes: ("n1".to_string(), "n3".to_string()),
+                bytes_per_second: 1000,
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_latency_matrices_that_are_not_square() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("simulation.json");
+        std::fs::write(
+            &config_file,
+            r#"{
+                "latency": {
+                    "matrix": {
+                        "participants": ["n1", "n2"],
+                        "rows": [[{ "base_ms": 0 }, { "base_ms": 50 }], [{ "base_ms": 80 }]]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Args::parse_with_config([
+                "amaru-sim",
+                "--config",
+                config_file.to_str().unwrap()
+            ]),
+            Err(ConfigError::InvalidOption(option, _)) if option == "latency"
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("simulation.json");
+        std::fs::write(&config_file, r#"{ "number_of_node": 3 }"#).unwrap();
+
+        assert!(matches!(
+            SimulatorConfig::from_file(&config_file),
+            Err(ConfigError::InvalidConfig(_))
+        ));
+    }
+}
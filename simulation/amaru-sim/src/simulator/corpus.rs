@@ -0,0 +1,143 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Regression corpora: the minimal failing cases found by
+//! [`simulate`](super::simulate::simulate), kept in a directory per scenario and replayed
+//! before any new case is generated, so that the bugs found once stay fixed.
+//!
+//! Each case is a JSON file holding the seeds of its run and its schedule, with times given
+//! since the start of the simulation. Cases are only ever added: delete the files of those
+//! no longer relevant, e.g. after a change of the messages nodes exchange.
+
+use super::simulate::NemesisAction;
+use crate::echo::Envelope;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum CorpusError {
+    IOError(std::io::Error),
+    InvalidCase(PathBuf, serde_json::Error),
+}
+
+/// A failing case, as stored in a corpus, see
+/// [`Schedule::to_case`](super::simulate::Schedule::to_case).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Case<Msg> {
+    /// The seed of the runner which found the case.
+    pub seed: u64,
+    pub world_seed: u64,
+    /// The client messages, at their arrival time.
+    pub messages: Vec<(Duration, Envelope<Msg>)>,
+    pub faults: Vec<(Duration, NemesisAction)>,
+}
+
+/// The directory holding the failing cases of a scenario.
+#[derive(Debug, Clone)]
+pub struct Corpus {
+    dir: PathBuf,
+}
+
+impl Corpus {
+    /// The corpus of `scenario`, in its own directory under `root`.
+    pub fn new(root: impl AsRef<Path>, scenario: &str) -> Self {
+        Corpus {
+            dir: root.as_ref().join(scenario),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The cases of the corpus along with their paths, in the order of their file names. A corpus
+    /// whose directory doesn't exist yet has none.
+    pub fn cases<Msg: DeserializeOwned>(&self) -> Result<Vec<(PathBuf, Case<Msg>)>, CorpusError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(CorpusError::IOError(e)),
+        };
+        let mut paths = entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(CorpusError::IOError)?;
+        paths.retain(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        });
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| {
+                let contents = std::fs::read_to_string(&path).map_err(CorpusError::IOError)?;
+                match serde_json::from_str(&contents) {
+                    Ok(case) => Ok((path, case)),
+                    Err(e) => Err(CorpusError::InvalidCase(path, e)),
+                }
+            })
+            .collect()
+    }
+
+    /// Store `case` in the corpus, in a file named after its world seed, and return its path.
+    pub fn add<Msg: Serialize>(&self, case: &Case<Msg>) -> Result<PathBuf, CorpusError> {
+        std::fs::create_dir_all(&self.dir).map_err(CorpusError::IOError)?;
+        let path = self.dir.join(format!("{:016x}.json", case.world_seed));
+        let contents = serde_json::to_string_pretty(case)
+            .map_err(|e| CorpusError::InvalidCase(path.clone(), e))?;
+        std::fs::write(&path, contents).map_err(CorpusError::IOError)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::echo::EchoMessage;
+
+    #[test]
+    fn corpus_returns_the_cases_added_to_it() {
+        let root = tempfile::tempdir().unwrap();
+        let corpus = Corpus::new(root.path(), "echo");
+        let case = Case {
+            seed: 42,
+            world_seed: 7,
+            messages: vec![(
+                Duration::from_millis(12),
+                Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id: 1,
+                        echo: "Please echo 1".to_string(),
+                    },
+                },
+            )],
+            faults: vec![(
+                Duration::from_secs(1),
+                NemesisAction::Crash("n1".to_string()),
+            )],
+        };
+
+        assert!(corpus.cases::<EchoMessage>().unwrap().is_empty());
+        let path = corpus.add(&case).unwrap();
+
+        assert_eq!(path, root.path().join("echo").join("0000000000000007.json"));
+        assert_eq!(corpus.cases().unwrap(), vec![(path, case)]);
+    }
+}
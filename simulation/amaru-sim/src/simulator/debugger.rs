@@ -0,0 +1,332 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An interactive debugger, stepping a [`World`] one delivery at a time, for when even a
+//! minimal counterexample is hard to make sense of.
+
+use super::simulate::{epoch, Entry, Next, World};
+use crate::echo::Envelope;
+use serde::de::DeserializeOwned;
+use std::{
+    fmt::Debug,
+    io::{self, BufRead, Write},
+};
+
+const HELP: &str = "\
+step [N]         deliver the next N messages, one by default
+continue         run until no message is left
+heap             list the messages left to deliver
+state NODE       show the state of a node
+inject MESSAGE   deliver a JSON-encoded message next, e.g. {\"src\":\"c1\",\"dest\":\"n1\",\"body\":{...}}
+trace            list the messages exchanged with clients so far
+save             checkpoint the world, if all its nodes support snapshots
+rewind           go back to the last checkpoint, e.g. to inject other messages
+quit             stop the simulation here";
+
+/// Reads commands from `input` and writes what happens to `output`.
+pub struct Debugger<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+
+    /// Execute commands until the user quits or the input ends. The world is left where it
+    /// was then, possibly with messages still pending.
+    pub fn run<Msg>(&mut self, world: &mut World<Msg>) -> io::Result<()>
+    where
+        Msg: Clone + PartialEq + Debug + DeserializeOwned + 'static,
+    {
+        writeln!(self.output, "Type 'help' for the list of commands.")?;
+        let mut checkpoint = None;
+        loop {
+            write!(self.output, "> ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let line = line.trim();
+            let (command, argument) = line
+                .split_once(' ')
+                .map_or((line, ""), |(command, argument)| (command, argument.trim()));
+            match command {
+                "" => (),
+                "s" | "step" => {
+                    let Ok(count) = (if argument.is_empty() {
+                        Ok(1)
+                    } else {
+                        argument.parse::<usize>()
+                    }) else {
+                        writeln!(self.output, "invalid number of steps '{}'", argument)?;
+                        continue;
+                    };
+                    for _ in 0..count {
+                        if self.step(world)? == Next::Done {
+                            break;
+                        }
+                    }
+                }
+                "c" | "continue" => while self.step(world)? == Next::Continue {},
+                "h" | "heap" => self.heap(world)?,
+                "state" => match world.node_state(argument) {
+                    Some(state) => writeln!(self.output, "{}", state)?,
+                    None => writeln!(self.output, "no state for node '{}'", argument)?,
+                },
+                "i" | "inject" => match serde_json::from_str::<Envelope<Msg>>(argument) {
+                    Ok(envelope) => {
+                        let arrival_time = world
+                            .now()
+                            .or_else(|| world.pending().first().map(|entry| entry.arrival_time))
+                            .unwrap_or_else(epoch);
+                        world.inject(Entry {
+                            arrival_time,
+                            envelope,
+                        });
+                    }
+                    Err(e) => writeln!(self.output, "invalid message: {}", e)?,
+                },
+                "t" | "trace" => {
+                    for envelope in &world.trace().0 {
+                        writeln!(
+                            self.output,
+                            "{} -> {}: {:?}",
+                            envelope.src, envelope.dest, envelope.body
+                        )?;
+                    }
+                }
+                "save" => match world.checkpoint() {
+                    Ok(saved) => {
+                        checkpoint = Some(saved);
+                        writeln!(
+                            self.output,
+                            "saved after {} deliveries",
+                            world.steps().len()
+                        )?;
+                    }
+                    Err(e) => writeln!(self.output, "unable to save: {}", e)?,
+                },
+                "rewind" => match &checkpoint {
+                    Some(saved) => match world.restore(saved) {
+                        Ok(()) => {
+                            writeln!(self.output, "rewound to {} deliveries", world.steps().len())?
+                        }
+                        Err(e) => writeln!(self.output, "unable to rewind: {}", e)?,
+                    },
+                    None => writeln!(self.output, "nothing saved to rewind to")?,
+                },
+                "help" => writeln!(self.output, "{}", HELP)?,
+                "q" | "quit" => return Ok(()),
+                other => writeln!(
+                    self.output,
+                    "unknown command '{}', type 'help' for the list of commands",
+                    other
+                )?,
+            }
+        }
+    }
+
+    fn step<Msg>(&mut self, world: &mut World<Msg>) -> io::Result<Next>
+    where
+        Msg: Clone + PartialEq + Debug + 'static,
+    {
+        let delivered = world.steps().len();
+        let next = world.step_world();
+        let steps = &world.steps()[delivered..];
+        for step in steps {
+            let envelope = &step.envelope;
+            writeln!(
+                self.output,
+                "{} <- {}: {:?}",
+                envelope.dest, envelope.src, envelope.body
+            )?;
+            for sent in &step.outgoing {
+                writeln!(
+                    self.output,
+                    "  {} -> {}: {:?}",
+                    sent.src, sent.dest, sent.body
+                )?;
+            }
+        }
+        match next {
+            Next::Done => writeln!(self.output, "no message left to deliver")?,
+            Next::Continue if steps.is_empty() => writeln!(
+                self.output,
+                "no delivery, a fault, a nemesis or a timer went off"
+            )?,
+            Next::Continue => (),
+        }
+        Ok(next)
+    }
+
+    fn heap<Msg>(&mut self, world: &World<Msg>) -> io::Result<()>
+    where
+        Msg: Clone + PartialEq + Debug + 'static,
+    {
+        let pending = world.pending();
+        let Some(since) = world
+            .now()
+            .or_else(|| pending.first().map(|entry| entry.arrival_time))
+        else {
+            return writeln!(self.output, "no message left to deliver");
+        };
+        for entry in pending {
+            let envelope = &entry.envelope;
+            writeln!(
+                self.output,
+                "+{}ms {} -> {}: {:?}",
+                entry
+                    .arrival_time
+                    .saturating_duration_since(since)
+                    .as_millis(),
+                envelope.src,
+                envelope.dest,
+                envelope.body
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::{
+        echo::{EchoMessage, Envelope},
+        simulator::simulate::{Entry, FnNode, NodeHandle, NodeSnapshot, World},
+    };
+    use std::{
+        cell::RefCell,
+        cmp::Reverse,
+        io::Cursor,
+        rc::Rc,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn steps_inspects_and_injects_messages() {
+        let received = Rc::new(RefCell::new(0u64));
+        let handle_received = received.clone();
+        let node = FnNode::new(
+            move |msg: Envelope<EchoMessage>| {
+                *handle_received.borrow_mut() += 1;
+                let EchoMessage::Echo { msg_id, echo } = msg.body else {
+                    return Ok(Vec::new());
+                };
+                Ok(vec![Envelope {
+                    src: msg.dest,
+                    dest: msg.src,
+                    body: EchoMessage::EchoOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        echo,
+                    },
+                }])
+            },
+            || (),
+        )
+        .with_state(move || serde_json::json!({ "received": *received.borrow() }))
+        .boxed();
+        let start = Instant::now();
+        let echo = |msg_id: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_secs(msg_id),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let mut world = World::new(vec![echo(1), echo(2)], vec![("n1".to_string(), node)]);
+        let commands = [
+            "heap",
+            "step",
+            "state n1",
+            r#"inject {"src":"c1","dest":"n1","body":{"type":"echo","msg_id":3,"echo":"Please echo 3"}}"#,
+            "step",
+            "quit",
+        ];
+        let mut output = Vec::new();
+
+        Debugger::new(Cursor::new(commands.join("\n")), &mut output)
+            .run(&mut world)
+            .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("+1000ms c1 -> n1"), "{}", output);
+        assert!(output.contains(r#"{"received":1}"#), "{}", output);
+        assert!(
+            output.contains("n1 -> c1: EchoOk { msg_id: 3"),
+            "{}",
+            output
+        );
+        // the second echo is left pending
+        assert_eq!(world.pending().len(), 1);
+    }
+
+    #[test]
+    fn rewinds_to_the_last_save() {
+        let received = Rc::new(RefCell::new(0u64));
+        let (handle_received, state_received, snapshot_received) =
+            (received.clone(), received.clone(), received.clone());
+        let node = FnNode::new(
+            move |_: Envelope<EchoMessage>| {
+                *handle_received.borrow_mut() += 1;
+                Ok(Vec::new())
+            },
+            || (),
+        )
+        .with_state(move || serde_json::json!({ "received": *state_received.borrow() }))
+        .with_snapshots(
+            move || Rc::new(*snapshot_received.borrow()) as NodeSnapshot,
+            move |snapshot| {
+                *received.borrow_mut() = *snapshot.downcast_ref::<u64>().unwrap();
+                Ok(())
+            },
+        )
+        .boxed();
+        let echo = Reverse(Entry {
+            arrival_time: Instant::now(),
+            envelope: Envelope {
+                src: "c1".to_string(),
+                dest: "n1".to_string(),
+                body: EchoMessage::Echo {
+                    msg_id: 1,
+                    echo: "Please echo 1".to_string(),
+                },
+            },
+        });
+        let mut world = World::new(vec![echo], vec![("n1".to_string(), node)]);
+        let commands = ["rewind", "save", "step", "state n1", "rewind", "state n1"];
+        let mut output = Vec::new();
+
+        Debugger::new(Cursor::new(commands.join("\n")), &mut output)
+            .run(&mut world)
+            .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("nothing saved to rewind to"), "{}", output);
+        assert!(output.contains(r#"{"received":1}"#), "{}", output);
+        assert!(output.contains("rewound to 0 deliveries"), "{}", output);
+        assert!(output.contains(r#"{"received":0}"#), "{}", output);
+        assert_eq!(world.pending().len(), 1);
+    }
+}
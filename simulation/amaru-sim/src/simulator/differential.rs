@@ -0,0 +1,318 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Differential testing: the same chain sync messages are delivered to two nodes, typically
+//! the in-process pipeline and a reference implementation running as a [`ProcessNode`], and
+//! the chain events they announce in response are compared.
+//!
+//! The reference implementation only has to speak the framed protocol of [`ProcessNode`].
+//! Message ids and header encodings are not compared, only the points nodes move forward or
+//! roll back to.
+//!
+//! The simulator compares a node with the reference implementation given with its
+//! `--reference` option, over the messages of its input. The tests search for divergences
+//! between nodes over generated fork switches instead.
+//!
+//! [`ProcessNode`]: super::simulate::ProcessNode
+
+use super::{bytes::Bytes, simulate::NodeHandle, sync::ChainSyncMessage};
+use crate::echo::Envelope;
+use slot_arithmetic::Slot;
+use std::fmt;
+
+/// A chain event announced by a node, without what implementations are free to differ on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Announcement {
+    Fwd { slot: Slot, hash: Bytes },
+    Bck { slot: Slot, hash: Bytes },
+}
+
+impl Announcement {
+    /// The chain event announced by `msg`, if any.
+    pub fn of(msg: &ChainSyncMessage) -> Option<Self> {
+        match msg {
+            ChainSyncMessage::Fwd { slot, hash, .. } => Some(Announcement::Fwd {
+                slot: *slot,
+                hash: hash.clone(),
+            }),
+            ChainSyncMessage::Bck { slot, hash, .. } => Some(Announcement::Bck {
+                slot: *slot,
+                hash: hash.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// How a node responded to a message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Announced(Vec<Announcement>),
+    /// The node failed to handle the message, for the given reason.
+    Failed(String),
+}
+
+impl Response {
+    fn of(outcome: anyhow::Result<Vec<Envelope<ChainSyncMessage>>>) -> Self {
+        match outcome {
+            Ok(outgoing) => Response::Announced(
+                outgoing
+                    .iter()
+                    .filter_map(|envelope| Announcement::of(&envelope.body))
+                    .collect(),
+            ),
+            Err(e) => Response::Failed(e.to_string()),
+        }
+    }
+
+    /// Whether both nodes did the same, failing for whatever reason or announcing the same
+    /// chain events, in the same order.
+    fn agrees_with(&self, other: &Response) -> bool {
+        match (self, other) {
+            (Response::Failed(_), Response::Failed(_)) => true,
+            (Response::Announced(ours), Response::Announced(theirs)) => ours == theirs,
+            _ => false,
+        }
+    }
+}
+
+/// The first message the two nodes responded differently to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The position of the message among those delivered, starting from 0.
+    pub index: usize,
+    pub input: Envelope<ChainSyncMessage>,
+    pub ours: Response,
+    pub theirs: Response,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "nodes diverge on message #{} {:?}: ours {:?}, theirs {:?}",
+            self.index, self.input.body, self.ours, self.theirs
+        )
+    }
+}
+
+/// Deliver `messages` to both nodes, in order, and compare their responses to each. Stops at
+/// the first divergence, after which the nodes have no reason to agree anymore.
+pub fn compare(
+    ours: &mut dyn NodeHandle<ChainSyncMessage>,
+    theirs: &mut dyn NodeHandle<ChainSyncMessage>,
+    messages: &[Envelope<ChainSyncMessage>],
+) -> Result<(), Divergence> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("unable to create runtime for compared nodes");
+    for (index, input) in messages.iter().enumerate() {
+        let our_response = Response::of(runtime.block_on(ours.handle(input.clone())));
+        let their_response = Response::of(runtime.block_on(theirs.handle(input.clone())));
+        if !our_response.agrees_with(&their_response) {
+            return Err(Divergence {
+                index,
+                input: input.clone(),
+                ours: our_response,
+                theirs: their_response,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{
+        forks::{any_fork_shape, ForkShape},
+        ledger::{ConsensusContext, FakeStakeDistribution},
+        node::Node,
+        simulate::{BoxedNode, ProcessNode, SimRng},
+        Args,
+    };
+    use amaru_kernel::{protocol_parameters::GlobalParameters, Nonce};
+    use clap::Parser;
+    use proptest::{
+        prelude::*,
+        test_runner::{Config, TestError},
+    };
+    use std::{fs::File, path::Path, rc::Rc};
+
+    /// Generate the messages of a peer `p1` announcing a common chain to `n1`, then switching
+    /// to a fork of it, see
+    /// [`ForkScenario::switching_messages`](crate::simulator::forks::ForkScenario).
+    fn fork_switches(
+        stake_distribution: Rc<FakeStakeDistribution>,
+        epoch_nonce: Nonce,
+        global_parameters: GlobalParameters,
+    ) -> impl Strategy<Value = Vec<Envelope<ChainSyncMessage>>> {
+        any_fork_shape(1..2, 1..6, 0..4, 1..6).prop_map(move |shape: ForkShape| {
+            shape
+                .forge(&stake_distribution, &epoch_nonce, &global_parameters)
+                .switching_messages(0)
+                .into_iter()
+                .map(|body| Envelope {
+                    src: "p1".to_string(),
+                    dest: "n1".to_string(),
+                    body,
+                })
+                .collect()
+        })
+    }
+
+    /// Compare fresh nodes spawned by `ours` and `theirs` over the cases of `config`, drawn from
+    /// `schedule` by a runner seeded with `seed`, returning the divergence of the minimal failing
+    /// case, if any.
+    fn search_divergence<S>(
+        config: Config,
+        seed: u64,
+        ours: impl Fn() -> BoxedNode<ChainSyncMessage>,
+        theirs: impl Fn() -> BoxedNode<ChainSyncMessage>,
+        schedule: S,
+    ) -> Result<(), Divergence>
+    where
+        S: Strategy<Value = Vec<Envelope<ChainSyncMessage>>>,
+    {
+        let run = |messages: &[Envelope<ChainSyncMessage>]| {
+            let (mut ours, mut theirs) = (ours(), theirs());
+            let result = compare(ours.as_mut(), theirs.as_mut(), messages);
+            ours.close();
+            theirs.close();
+            result
+        };
+        let mut runner = SimRng::new(seed).runner(config);
+        match runner.run(&schedule, |messages| {
+            run(&messages).map_err(|divergence| TestCaseError::fail(divergence.to_string()))
+        }) {
+            Ok(()) => Ok(()),
+            // runs are deterministic, so the minimal case diverges again
+            Err(TestError::Fail(_, messages)) => run(&messages),
+            Err(TestError::Abort(e)) => panic!("Test aborted: {}", e),
+        }
+    }
+
+    fn args(security_param: &str) -> Args {
+        Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+            "--security-param",
+            security_param,
+        ])
+    }
+
+    fn spawn(args: &Args) -> BoxedNode<ChainSyncMessage> {
+        Node::new(
+            "n1",
+            args,
+            Path::new("unused"),
+            &["p1".to_string()],
+            vec!["c1".to_string()],
+        )
+        .boxed()
+    }
+
+    fn schedule() -> impl Strategy<Value = Vec<Envelope<ChainSyncMessage>>> {
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution = FakeStakeDistribution::from_file(
+            Path::new("tests/data/stake-distribution.json"),
+            &global_parameters,
+        )
+        .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open("tests/data/consensus-context.json").unwrap())
+                .unwrap();
+        fork_switches(
+            Rc::new(stake_distribution),
+            context.nonce,
+            global_parameters,
+        )
+    }
+
+    fn config(cases: u32) -> Config {
+        Config {
+            cases,
+            failure_persistence: None,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn identical_pipelines_never_diverge() {
+        let args = args("3");
+
+        let result =
+            search_divergence(config(16), 42, || spawn(&args), || spawn(&args), schedule());
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn finds_the_rollback_a_pipeline_refuses() {
+        // forks start at most 3 headers below the tip, which the first node can't follow
+        let (ours, theirs) = (args("2"), args("3"));
+
+        let divergence = search_divergence(
+            config(64),
+            42,
+            || spawn(&ours),
+            || spawn(&theirs),
+            schedule(),
+        )
+        .unwrap_err();
+
+        assert!(
+            matches!(divergence.input.body, ChainSyncMessage::Bck { .. }),
+            "{}",
+            divergence
+        );
+        let Response::Announced(announced) = &divergence.theirs else {
+            panic!("{}", divergence)
+        };
+        assert!(
+            matches!(announced[..], [Announcement::Bck { .. }]),
+            "{}",
+            divergence
+        );
+    }
+
+    // Run with the path of a reference implementation in `REFERENCE_NODE`, speaking the
+    // framed protocol of `ProcessNode`.
+    #[test]
+    #[ignore]
+    fn pipeline_agrees_with_the_reference_node() {
+        let reference = std::env::var("REFERENCE_NODE").expect("REFERENCE_NODE is not set");
+        let args = args("2160");
+
+        let result = search_divergence(
+            config(16),
+            rand::random(),
+            || spawn(&args),
+            || {
+                ProcessNode::spawn(Path::new(&reference), &[])
+                    .expect("unable to spawn the reference node")
+                    .boxed()
+            },
+            schedule(),
+        );
+
+        if let Err(divergence) = result {
+            panic!("{}", divergence)
+        }
+    }
+}
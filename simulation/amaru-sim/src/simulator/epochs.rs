@@ -0,0 +1,311 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Chains spanning several epochs, announced at the pace of their slots, so that epoch
+//! boundaries and the nonce rollover coming with them get exercised by simulations.
+//!
+//! Slots and epochs follow the era history of the nodes' chain stores, and the randomness
+//! stabilisation window the [`GlobalParameters`]. Only a few headers are forged in each
+//! epoch, on both sides of the window, and a [`World`](super::simulate::World) jumps over the
+//! idle slots between them at once: simulating several epochs takes milliseconds.
+//!
+//! Nodes have to be anchored on the chains forged here, which only tests do.
+
+use super::{forks::fwd, ledger::FakeStakeDistribution, simulate::Entry, sync::ChainSyncMessage};
+use crate::echo::Envelope;
+use amaru_kernel::{protocol_parameters::GlobalParameters, EraHistory, Hash, Header, Nonce};
+use amaru_ouroboros::{
+    math::FixedDecimal,
+    praos::nonce::{evolve, from_candidate, randomness_stability_window},
+    IsHeader, Nonces,
+};
+use slot_arithmetic::{Epoch, Slot};
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// A chain crossing epoch boundaries, along with the headers it builds upon.
+#[derive(Debug, Clone)]
+pub struct EpochChain {
+    /// Headers before the chain, which nodes must know of up-front, see
+    /// [`Node::anchored`](super::node::Node::anchored): the first nonce rollover needs the
+    /// parent of the last one.
+    pub anchor: Vec<Header>,
+    pub chain: Vec<Header>,
+    /// The nonce of each epoch the chain spans, from the first one.
+    pub epoch_nonces: Vec<Nonce>,
+}
+
+/// Forge a chain spanning the first `epochs` epochs of `era_history`, with `blocks_per_epoch`
+/// headers in each of them, starting from `epoch_nonce`.
+///
+/// The headers are spread across each epoch, so that some of them contribute to the nonce of
+/// the next epoch and the last ones don't. The nonces evolve exactly as in the chain store of
+/// a node, so that the headers of later epochs are valid.
+pub fn forge_epochs(
+    stake_distribution: &FakeStakeDistribution,
+    epoch_nonce: &Nonce,
+    global_parameters: &GlobalParameters,
+    era_history: &EraHistory,
+    epochs: u64,
+    blocks_per_epoch: u64,
+) -> EpochChain {
+    let active_slot_coeff = FixedDecimal::from(1_u64)
+        / FixedDecimal::from(global_parameters.active_slot_coeff_inverse as u64);
+    let anchor = stake_distribution.generate_chain(None, 2, epoch_nonce, global_parameters);
+    let mut headers: BTreeMap<Hash<32>, Header> = anchor
+        .iter()
+        .map(|header| (header.hash(), header.clone()))
+        .collect();
+    let mut parent = anchor[anchor.len() - 1].clone();
+    // as found in the chain store of an anchored node
+    let mut nonces = Nonces {
+        active: *epoch_nonce,
+        evolving: *epoch_nonce,
+        candidate: *epoch_nonce,
+        tail: parent.hash(),
+        epoch: Epoch::from(0),
+    };
+
+    let mut chain = Vec::new();
+    let mut epoch_nonces = Vec::new();
+    for epoch in (0..epochs).map(Epoch::from) {
+        let bounds = era_history
+            .epoch_bounds(epoch)
+            .unwrap_or_else(|e| panic!("epoch {} is beyond the era history: {:?}", epoch, e));
+        let (start, end) = (u64::from(bounds.start), u64::from(bounds.end));
+        let active = if epoch > nonces.epoch {
+            from_candidate(&headers[&nonces.tail], &nonces.candidate)
+                .expect("the tail of an epoch has a parent")
+        } else {
+            nonces.active
+        };
+        epoch_nonces.push(active);
+
+        for i in 0..blocks_per_epoch {
+            let mut slot = (start + i * (end - start) / blocks_per_epoch).max(parent.slot() + 1);
+            let election = loop {
+                assert!(slot < end, "no slot leader left in epoch {}", epoch);
+                if let Some(election) =
+                    stake_distribution.slot_leader(Slot::from(slot), &active, &active_slot_coeff)
+                {
+                    break election;
+                }
+                slot += 1;
+            };
+            let header = stake_distribution.forge_header(&election, Some(&parent));
+            let (_, is_within_stability_window) =
+                randomness_stability_window(&header, era_history, global_parameters)
+                    .unwrap_or_else(|e| panic!("slot {} is beyond the era history: {:?}", slot, e));
+            let evolving = evolve(&header, &nonces.evolving);
+            nonces = Nonces {
+                active,
+                evolving,
+                candidate: if is_within_stability_window {
+                    evolving
+                } else {
+                    nonces.candidate
+                },
+                tail: if epoch > nonces.epoch {
+                    parent.hash()
+                } else {
+                    nonces.tail
+                },
+                epoch,
+            };
+            headers.insert(header.hash(), header.clone());
+            chain.push(header.clone());
+            parent = header;
+        }
+    }
+
+    EpochChain {
+        anchor,
+        chain,
+        epoch_nonces,
+    }
+}
+
+/// The messages of `peer` announcing `chain` to `node`, each arriving at the time of its slot,
+/// since `start`.
+pub fn announcements(
+    chain: &[Header],
+    era_history: &EraHistory,
+    start: Instant,
+    peer: &str,
+    node: &str,
+) -> Vec<Reverse<Entry<ChainSyncMessage>>> {
+    chain
+        .iter()
+        .map(|header| {
+            let elapsed = era_history
+                .slot_to_relative_time(Slot::from(header.slot()))
+                .unwrap_or_else(|e| {
+                    panic!("slot {} is beyond the era history: {:?}", header.slot(), e)
+                });
+            Reverse(Entry {
+                arrival_time: start + Duration::from_millis(elapsed),
+                envelope: Envelope {
+                    src: peer.to_string(),
+                    dest: node.to_string(),
+                    body: fwd(header),
+                },
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{
+        ledger::ConsensusContext,
+        node::Node,
+        simulate::{NodeHandle, World},
+        Args,
+    };
+    use amaru_kernel::network::NetworkName;
+    use clap::Parser;
+    use std::{fs::File, path::Path};
+
+    fn args() -> Args {
+        Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+            // nonces are never read from there
+            "--data-dir",
+            "does-not-exist",
+        ])
+    }
+
+    fn forge(args: &Args, epochs: u64) -> EpochChain {
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        forge_epochs(
+            &stake_distribution,
+            &context.nonce,
+            &global_parameters,
+            NetworkName::Testnet(42).into(),
+            epochs,
+            3,
+        )
+    }
+
+    #[test]
+    fn nodes_follow_a_chain_across_epoch_boundaries() {
+        let args = args();
+        let era_history: &EraHistory = NetworkName::Testnet(42).into();
+        let epochs = forge(&args, 3);
+        let node = Node::anchored(
+            "n1",
+            &args,
+            Path::new("unused"),
+            &["p1".to_string()],
+            vec!["c1".to_string()],
+            &epochs.anchor,
+        );
+        let mut world = World::new(
+            announcements(&epochs.chain, era_history, Instant::now(), "p1", "n1"),
+            vec![("n1".to_string(), node.boxed())],
+        );
+
+        let statistics = world.run_world();
+
+        assert_ne!(epochs.epoch_nonces[0], epochs.epoch_nonces[1]);
+        assert_ne!(epochs.epoch_nonces[1], epochs.epoch_nonces[2]);
+        let state = world.node_state("n1").unwrap();
+        assert_eq!(state["rejected"], 0);
+        assert_eq!(
+            state["tip"],
+            epochs.chain[epochs.chain.len() - 1].point().to_string()
+        );
+        // the last header is past the middle of the third epoch
+        assert!(
+            statistics.duration > Duration::from_secs(2 * 432_000),
+            "{}",
+            statistics
+        );
+    }
+
+    #[test]
+    fn nodes_recompute_the_nonce_of_each_epoch_they_enter() {
+        let args = args();
+        let era_history: &EraHistory = NetworkName::Testnet(42).into();
+        let epochs = forge(&args, 4);
+        // n1 forwards the chain it selects to n2, which validates it on its own
+        let n1 = Node::anchored(
+            "n1",
+            &args,
+            Path::new("unused"),
+            &["p1".to_string()],
+            vec!["n2".to_string()],
+            &epochs.anchor,
+        );
+        let n2 = Node::anchored(
+            "n2",
+            &args,
+            Path::new("unused"),
+            &["n1".to_string()],
+            vec!["c1".to_string()],
+            &epochs.anchor,
+        );
+        let mut world = World::new(
+            announcements(&epochs.chain, era_history, Instant::now(), "p1", "n1"),
+            vec![
+                ("n1".to_string(), n1.boxed()),
+                ("n2".to_string(), n2.boxed()),
+            ],
+        );
+
+        world.run_world();
+
+        let mut checked = 0;
+        for step in world.steps() {
+            let state = step.post_state.as_ref().unwrap();
+            let Some(header) = epochs
+                .chain
+                .iter()
+                .find(|header| header.point().to_string() == state["tip"])
+            else {
+                continue;
+            };
+            let epoch = era_history
+                .slot_to_epoch(Slot::from(header.slot()))
+                .unwrap();
+            assert_eq!(
+                state["epoch_nonce"],
+                epochs.epoch_nonces[u64::from(epoch) as usize].to_string(),
+                "{} at slot {} of epoch {}",
+                step.node,
+                header.slot(),
+                epoch
+            );
+            checked += 1;
+        }
+        assert_eq!(checked, 2 * epochs.chain.len());
+        for node in ["n1", "n2"] {
+            assert_eq!(world.node_state(node).unwrap()["rejected"], 0);
+        }
+    }
+}
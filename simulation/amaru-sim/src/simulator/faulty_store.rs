@@ -0,0 +1,177 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use amaru_consensus::consensus::{
+    chain_selection::ChainSelectorState,
+    store::{ChainStore, StoreError},
+};
+use amaru_kernel::{EraHistory, RawBlock};
+use amaru_ouroboros::{IsHeader, Nonces};
+use pallas_crypto::hash::Hash;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// A storage operation the simulator can make fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreFault {
+    /// The next `store_header` call returns a write error.
+    StoreHeader,
+    /// The next `load_header` call does not find the header.
+    LoadHeader,
+    /// The node crashes right after its next `store_header` call persisted a header, before
+    /// chain selection acts on it. The store itself goes on as usual, it is up to the node to
+    /// crash.
+    CrashAfterStoreHeader,
+}
+
+/// Shared handle to the faults pending on a [`FaultyChainStore`].
+///
+/// Each injected fault makes exactly one call of the corresponding operation fail.
+#[derive(Debug, Clone, Default)]
+pub struct StoreFaults {
+    store_header: Arc<AtomicUsize>,
+    load_header: Arc<AtomicUsize>,
+    crash_after_store_header: Arc<AtomicUsize>,
+}
+
+impl StoreFaults {
+    pub fn inject(&self, fault: StoreFault) {
+        self.counter(fault).fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn counter(&self, fault: StoreFault) -> &AtomicUsize {
+        match fault {
+            StoreFault::StoreHeader => &self.store_header,
+            StoreFault::LoadHeader => &self.load_header,
+            StoreFault::CrashAfterStoreHeader => &self.crash_after_store_header,
+        }
+    }
+
+    /// Consume a pending fault of the given kind, if any.
+    pub fn trip(&self, fault: StoreFault) -> bool {
+        self.counter(fault)
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+    }
+}
+
+/// A [`ChainStore`] that fails header operations on command, and otherwise delegates to
+/// the wrapped store.
+pub struct FaultyChainStore<S> {
+    inner: S,
+    faults: StoreFaults,
+}
+
+impl<S> FaultyChainStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            faults: StoreFaults::default(),
+        }
+    }
+
+    pub fn faults(&self) -> StoreFaults {
+        self.faults.clone()
+    }
+}
+
+impl<H: IsHeader, S: ChainStore<H>> ChainStore<H> for FaultyChainStore<S> {
+    fn load_header(&self, hash: &Hash<32>) -> Option<H> {
+        if self.faults.trip(StoreFault::LoadHeader) {
+            return None;
+        }
+        self.inner.load_header(hash)
+    }
+
+    fn store_header(&mut self, hash: &Hash<32>, header: &H) -> Result<(), StoreError> {
+        if self.faults.trip(StoreFault::StoreHeader) {
+            return Err(StoreError::WriteError {
+                error: format!("injected fault storing header {}", hash),
+            });
+        }
+        self.inner.store_header(hash, header)
+    }
+
+    fn remove_header(&mut self, hash: &Hash<32>) -> Result<(), StoreError> {
+        self.inner.remove_header(hash)
+    }
+
+    fn header_hashes_from(&self, height: u64) -> Result<Vec<Hash<32>>, StoreError> {
+        self.inner.header_hashes_from(height)
+    }
+
+    fn load_block(&self, hash: &Hash<32>) -> Result<RawBlock, StoreError> {
+        self.inner.load_block(hash)
+    }
+
+    fn store_block(&mut self, hash: &Hash<32>, block: &RawBlock) -> Result<(), StoreError> {
+        self.inner.store_block(hash, block)
+    }
+
+    fn get_nonces(&self, header: &Hash<32>) -> Option<Nonces> {
+        self.inner.get_nonces(header)
+    }
+
+    fn put_nonces(&mut self, header: &Hash<32>, nonces: &Nonces) -> Result<(), StoreError> {
+        self.inner.put_nonces(header, nonces)
+    }
+
+    fn era_history(&self) -> &EraHistory {
+        self.inner.era_history()
+    }
+
+    fn load_chain_selector_state(&self) -> Option<ChainSelectorState> {
+        self.inner.load_chain_selector_state()
+    }
+
+    fn store_chain_selector_state(&mut self, state: &ChainSelectorState) -> Result<(), StoreError> {
+        self.inner.store_chain_selector_state(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FaultyChainStore, StoreFault};
+    use amaru_consensus::consensus::store::ChainStore;
+    use amaru_kernel::network::NetworkName;
+    use amaru_ouroboros::{fake::FakeHeader, IsHeader};
+    use amaru_stores::rocksdb::consensus::RocksDBStore;
+
+    #[test]
+    fn injected_faults_fail_a_single_call() {
+        let dir = tempfile::tempdir().unwrap();
+        let rocksdb =
+            RocksDBStore::new(&dir.path().to_path_buf(), NetworkName::Testnet(42).into()).unwrap();
+        let mut store = FaultyChainStore::new(rocksdb);
+        let header = FakeHeader {
+            block_number: 1,
+            slot: 1,
+            parent: None,
+            body_hash: [1; 32].into(),
+        };
+        let hash = header.hash();
+
+        store.faults().inject(StoreFault::StoreHeader);
+        assert!(store.store_header(&hash, &header).is_err());
+        store.store_header(&hash, &header).unwrap();
+
+        store.faults().inject(StoreFault::LoadHeader);
+        assert_eq!(None, ChainStore::<FakeHeader>::load_header(&store, &hash));
+        assert_eq!(Some(header), store.load_header(&hash));
+    }
+}
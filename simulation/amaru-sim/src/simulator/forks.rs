@@ -0,0 +1,366 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generators for scenarios in which several upstream peers serve different forks of a
+//! common chain.
+//!
+//! Each peer follows the common chain up to some depth below its tip, then extends its own
+//! fork from there. The shape of a scenario is generated first, and its headers are then
+//! forged following the leader schedule of a stake distribution, so they pass validation.
+//!
+//! The [`deep_rollback`] scenario has a single peer switching from the common chain to its
+//! fork, rolling back as deep as told, see [`ForkScenario::switching_messages`].
+//!
+//! The scenarios feed the tests of chain selection, here and in the modules simulating nodes
+//! against a model or a reference implementation.
+
+use super::{ledger::FakeStakeDistribution, sync::ChainSyncMessage};
+use amaru_kernel::{protocol_parameters::GlobalParameters, to_cbor, Header, Nonce};
+use amaru_ouroboros::IsHeader;
+use proptest::{collection::vec, prelude::*};
+use slot_arithmetic::Slot;
+use std::ops::Range;
+
+/// The shape of a fork scenario, independently of the headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForkShape {
+    pub common_length: usize,
+    /// For each peer, how many headers below the tip of the common chain its fork starts,
+    /// and how many headers the fork has.
+    pub forks: Vec<(usize, usize)>,
+}
+
+/// The chain served by each peer of a fork scenario.
+#[derive(Debug, Clone)]
+pub struct ForkScenario {
+    pub common: Vec<Header>,
+    pub chains: Vec<Vec<Header>>,
+}
+
+/// Generate the shape of a scenario with a number of peers within `peers`, whose forks start
+/// within `fork_depth` headers of the tip of the common chain.
+pub fn any_fork_shape(
+    peers: Range<usize>,
+    common_length: Range<usize>,
+    fork_depth: Range<usize>,
+    fork_length: Range<usize>,
+) -> impl Strategy<Value = ForkShape> {
+    (common_length, vec((fork_depth, fork_length), peers)).prop_map(|(common_length, forks)| {
+        ForkShape {
+            common_length,
+            forks,
+        }
+    })
+}
+
+/// The shape of a scenario whose single peer first serves the common chain, then rolls back
+/// `depth` headers below its tip and serves a fork one header longer than the common chain.
+pub fn deep_rollback(depth: usize) -> ForkShape {
+    ForkShape {
+        common_length: depth + 1,
+        forks: vec![(depth, depth + 1)],
+    }
+}
+
+/// The order in which the peers of a scenario announce their headers: the index of a peer
+/// appears once for each header of its chain.
+pub fn announcement_order(shape: &ForkShape) -> impl Strategy<Value = Vec<usize>> {
+    let turns: Vec<usize> = shape
+        .chain_lengths()
+        .into_iter()
+        .enumerate()
+        .flat_map(|(peer, length)| std::iter::repeat_n(peer, length))
+        .collect();
+    Just(turns).prop_shuffle()
+}
+
+impl ForkShape {
+    /// How many headers of the common chain the fork of each peer builds on.
+    fn anchors(&self) -> impl Iterator<Item = usize> + '_ {
+        self.forks
+            .iter()
+            .map(|(depth, _)| self.common_length.saturating_sub(*depth))
+    }
+
+    pub fn chain_lengths(&self) -> Vec<usize> {
+        self.anchors()
+            .zip(&self.forks)
+            .map(|(anchor, (_, length))| anchor + length)
+            .collect()
+    }
+
+    /// Forge the headers of the scenario.
+    ///
+    /// A fork starts after the slot of the header following its anchor, either in the common
+    /// chain or in the forks built on the same anchor before it, so that no two peers forge
+    /// the same headers.
+    pub fn forge(
+        &self,
+        stake_distribution: &FakeStakeDistribution,
+        epoch_nonce: &Nonce,
+        global_parameters: &GlobalParameters,
+    ) -> ForkScenario {
+        let common = stake_distribution.generate_chain(
+            None,
+            self.common_length,
+            epoch_nonce,
+            global_parameters,
+        );
+        let mut next_after: Vec<u64> = (0..=self.common_length)
+            .map(|anchor| match (common.get(anchor), anchor.checked_sub(1)) {
+                (Some(next), _) => next.slot(),
+                (None, Some(tip)) => common[tip].slot(),
+                (None, None) => 0,
+            })
+            .collect();
+        let chains = self
+            .anchors()
+            .zip(&self.forks)
+            .map(|(anchor, (_, length))| {
+                let parent = anchor.checked_sub(1).map(|tip| &common[tip]);
+                let fork = stake_distribution.generate_chain_after(
+                    parent,
+                    next_after[anchor],
+                    *length,
+                    epoch_nonce,
+                    global_parameters,
+                );
+                if let Some(first) = fork.first() {
+                    next_after[anchor] = first.slot();
+                }
+                let mut chain = common[..anchor].to_vec();
+                chain.extend(fork);
+                chain
+            })
+            .collect();
+        ForkScenario { common, chains }
+    }
+}
+
+/// The message announcing `header`, without its block.
+pub fn fwd(header: &Header) -> ChainSyncMessage {
+    ChainSyncMessage::Fwd {
+        msg_id: 0,
+        slot: Slot::from(header.slot()),
+        hash: header.hash().to_vec().into(),
+        header: to_cbor(header).into(),
+        block: None,
+    }
+}
+
+impl ForkScenario {
+    /// The messages of a peer serving the common chain first, then rolling back to the last
+    /// header it shares with the chain of the given peer, or to the origin, and serving the
+    /// rest of that chain.
+    pub fn switching_messages(&self, peer: usize) -> Vec<ChainSyncMessage> {
+        let chain = &self.chains[peer];
+        let shared = self
+            .common
+            .iter()
+            .zip(chain)
+            .take_while(|(a, b)| a.hash() == b.hash())
+            .count();
+        let rollback = match shared.checked_sub(1).map(|tip| &chain[tip]) {
+            Some(header) => ChainSyncMessage::Bck {
+                msg_id: 0,
+                slot: Slot::from(header.slot()),
+                hash: header.hash().to_vec().into(),
+            },
+            None => ChainSyncMessage::Bck {
+                msg_id: 0,
+                slot: Slot::from(0),
+                hash: vec![].into(),
+            },
+        };
+        self.common
+            .iter()
+            .map(fwd)
+            .chain(std::iter::once(rollback))
+            .chain(chain[shared..].iter().map(fwd))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        echo::Envelope,
+        simulator::{
+            bytes::Bytes,
+            chain_properties::{bounded_rollbacks, selected_chains},
+            ledger::ConsensusContext,
+            node::Node,
+            simulate::Trace,
+            Args,
+        },
+    };
+    use clap::Parser;
+    use std::{fs::File, path::Path};
+
+    fn hashes(chain: &[Header]) -> Vec<Bytes> {
+        chain
+            .iter()
+            .map(|header| header.hash().to_vec().into())
+            .collect()
+    }
+
+    fn scenario() -> impl Strategy<Value = (ForkShape, Vec<usize>)> {
+        any_fork_shape(2..4, 1..6, 0..4, 0..5).prop_flat_map(|shape| {
+            let order = announcement_order(&shape);
+            (Just(shape), order)
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        #[test]
+        fn node_selects_the_longest_fork((shape, order) in scenario()) {
+            let args = Args::parse_from([
+                "amaru-sim",
+                "--in-memory",
+                "--stake-distribution-file",
+                "tests/data/stake-distribution.json",
+                "--consensus-context-file",
+                "tests/data/consensus-context.json",
+            ]);
+            let global_parameters = GlobalParameters::default();
+            let stake_distribution =
+                FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                    .unwrap();
+            let context: ConsensusContext =
+                serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+            let scenario = shape.forge(&stake_distribution, &context.nonce, &global_parameters);
+
+            let peers: Vec<String> = (1..=scenario.chains.len()).map(|i| format!("p{}", i)).collect();
+            let mut node = Node::new("n1", &args, Path::new("unused"), &peers, vec!["c1".to_string()]);
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+            let mut announced = vec![0; peers.len()];
+            let mut outputs = vec![];
+            for peer in order {
+                let header = &scenario.chains[peer][announced[peer]];
+                announced[peer] += 1;
+                let msg = Envelope {
+                    src: peers[peer].clone(),
+                    dest: "n1".to_string(),
+                    body: fwd(header),
+                };
+                outputs.extend(runtime.block_on(node.handle(msg)).unwrap());
+            }
+
+            let selected: Vec<Bytes> = selected_chains(&Trace(outputs), &["n1"])["n1"]
+                .iter()
+                .map(|block| block.hash.clone())
+                .collect();
+            let longest = scenario.chains.iter().map(Vec::len).max().unwrap_or_default();
+            prop_assert_eq!(selected.len(), longest);
+            prop_assert!(
+                scenario.chains.iter().any(|chain| hashes(chain) == selected),
+                "{:?} selected a chain no peer served",
+                shape
+            );
+        }
+    }
+
+    #[test]
+    fn node_refuses_rollbacks_deeper_than_the_security_parameter() {
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+            "--security-param",
+            "2",
+        ]);
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        for depth in [2, 3] {
+            let scenario =
+                deep_rollback(depth).forge(&stake_distribution, &context.nonce, &global_parameters);
+            let mut node = Node::new(
+                "n1",
+                &args,
+                Path::new("unused"),
+                &["p1".to_string()],
+                vec!["c1".to_string()],
+            );
+            let mut outputs = vec![];
+            for body in scenario.switching_messages(0) {
+                let msg = Envelope {
+                    src: "p1".to_string(),
+                    dest: "n1".to_string(),
+                    body,
+                };
+                outputs.extend(runtime.block_on(node.handle(msg)).unwrap());
+            }
+            let trace = Trace(outputs);
+
+            assert_eq!(bounded_rollbacks(&trace, &["n1"], 2), Ok(()));
+            let selected: Vec<Bytes> = selected_chains(&trace, &["n1"])["n1"]
+                .iter()
+                .map(|block| block.hash.clone())
+                .collect();
+            // the node follows the fork within k, and sticks to the common chain beyond
+            let expected = if depth <= 2 {
+                &scenario.chains[0]
+            } else {
+                &scenario.common
+            };
+            assert_eq!(selected, hashes(expected), "rolling back {} headers", depth);
+        }
+    }
+
+    #[test]
+    fn forks_on_the_same_anchor_differ() {
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution = FakeStakeDistribution::from_file(
+            Path::new("tests/data/stake-distribution.json"),
+            &global_parameters,
+        )
+        .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open("tests/data/consensus-context.json").unwrap())
+                .unwrap();
+        let shape = ForkShape {
+            common_length: 3,
+            forks: vec![(1, 2), (1, 2), (0, 1)],
+        };
+
+        let scenario = shape.forge(&stake_distribution, &context.nonce, &global_parameters);
+
+        assert_eq!(shape.chain_lengths(), vec![4, 4, 4]);
+        let chains: Vec<Vec<Bytes>> = scenario
+            .chains
+            .iter()
+            .map(Vec::as_slice)
+            .map(hashes)
+            .collect();
+        assert_eq!(chains[0][..2], hashes(&scenario.common)[..2]);
+        assert_ne!(chains[0][2..], hashes(&scenario.common)[2..]);
+        assert_ne!(chains[0], chains[1]);
+        assert_eq!(chains[2][..3], hashes(&scenario.common)[..]);
+    }
+}
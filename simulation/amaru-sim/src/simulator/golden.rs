@@ -0,0 +1,119 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Golden files, holding the messages a seeded run sent to its clients, one JSON-encoded
+//! envelope per line, so that unintended changes of behavior show up as a diff.
+
+use crate::echo::Envelope;
+use serde::Serialize;
+use std::path::Path;
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum GoldenError {
+    IOError(std::io::Error),
+    SerializationError(serde_json::Error),
+    /// The first line at which the messages differ from the golden file, starting from 1,
+    /// with what each of them has there, if anything.
+    Mismatch {
+        line: usize,
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+}
+
+fn encode<Msg: Serialize>(outputs: &[Envelope<Msg>]) -> Result<Vec<String>, GoldenError> {
+    outputs
+        .iter()
+        .map(|envelope| serde_json::to_string(envelope).map_err(GoldenError::SerializationError))
+        .collect()
+}
+
+/// The messages in `outputs`, one JSON-encoded envelope per line, as found in golden files.
+/// Useful for snapshots of the messages a node sends.
+pub fn to_jsonl<Msg: Serialize>(outputs: &[Envelope<Msg>]) -> Result<String, GoldenError> {
+    let mut contents = encode(outputs)?.join("\n");
+    contents.push('\n');
+    Ok(contents)
+}
+
+/// Compare `outputs` with the golden file at `path`, or overwrite the file with them when
+/// `update` is set.
+pub fn check<Msg: Serialize>(
+    path: &Path,
+    outputs: &[Envelope<Msg>],
+    update: bool,
+) -> Result<(), GoldenError> {
+    if update {
+        return std::fs::write(path, to_jsonl(outputs)?).map_err(GoldenError::IOError);
+    }
+    let actual = encode(outputs)?;
+    let golden = std::fs::read_to_string(path).map_err(GoldenError::IOError)?;
+    let expected: Vec<&str> = golden.lines().filter(|line| !line.is_empty()).collect();
+    for line in 0..expected.len().max(actual.len()) {
+        let (expected, actual) = (expected.get(line).copied(), actual.get(line));
+        if expected != actual.map(String::as_str) {
+            return Err(GoldenError::Mismatch {
+                line: line + 1,
+                expected: expected.map(str::to_string),
+                actual: actual.cloned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, GoldenError};
+    use crate::echo::{EchoMessage, Envelope};
+
+    fn echo_ok(msg_id: u64) -> Envelope<EchoMessage> {
+        Envelope {
+            src: "n1".to_string(),
+            dest: "c1".to_string(),
+            body: EchoMessage::EchoOk {
+                msg_id,
+                in_reply_to: msg_id,
+                echo: format!("Please echo {}", msg_id),
+            },
+        }
+    }
+
+    #[test]
+    fn reports_the_first_message_differing_from_the_golden_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let golden = dir.path().join("golden.jsonl");
+
+        check(&golden, &[echo_ok(1), echo_ok(2)], true).unwrap();
+        check(&golden, &[echo_ok(1), echo_ok(2)], false).unwrap();
+
+        assert!(matches!(
+            check(&golden, &[echo_ok(1), echo_ok(3)], false),
+            Err(GoldenError::Mismatch {
+                line: 2,
+                expected: Some(_),
+                actual: Some(_)
+            })
+        ));
+        assert!(matches!(
+            check(&golden, &[echo_ok(1)], false),
+            Err(GoldenError::Mismatch {
+                line: 2,
+                actual: None,
+                ..
+            })
+        ));
+    }
+}
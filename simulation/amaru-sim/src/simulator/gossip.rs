@@ -0,0 +1,256 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The broadcast workload of the `echo` module run by the simulator: [`BroadcastNode`]s as
+//! pure-stage graphs, and the property their gossip must uphold, checked by property-based
+//! tests.
+
+use super::simulate::{BoxedNode, NodeHandle, PureStageNode, Trace};
+use crate::echo::{BroadcastMessage, BroadcastMessage::*, BroadcastNode, Envelope};
+use pure_stage::{simulation::SimulationBuilder, StageGraph, StageRef};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Spawn a [`BroadcastNode`] running as a pure-stage graph, for the simulator to drive.
+pub fn spawn_broadcast_node() -> BoxedNode<BroadcastMessage> {
+    let mut network = SimulationBuilder::default();
+    let stage = network.stage(
+        "broadcast",
+        async |(mut node, out), msg: Envelope<BroadcastMessage>, eff| {
+            for outgoing in node.handle(msg) {
+                eff.send(&out, outgoing).await;
+            }
+            Ok((node, out))
+        },
+        (
+            BroadcastNode::default(),
+            StageRef::noop::<Envelope<BroadcastMessage>>(),
+        ),
+    );
+    let (output, rx) = network.output("output");
+    let stage = network.wire_up(stage, |state| state.1 = output.without_state());
+    network.enable_snapshots(&stage);
+    network.enable_snapshots(&output);
+    let running = network.run();
+
+    PureStageNode::new(rx, stage, running).boxed()
+}
+
+/// The property of the broadcast workload, once gossip has settled: the last read answered
+/// by each node returns every value a node acknowledged the broadcast of.
+pub fn broadcast_property(trace: Trace<BroadcastMessage>) -> Result<(), String> {
+    let is_client = |id: &str| id.starts_with('c');
+    let mut acknowledged = BTreeSet::new();
+    let mut last_reads = BTreeMap::new();
+    for (index, msg) in trace.0.iter().enumerate() {
+        match &msg.body {
+            Broadcast { msg_id, message } if is_client(&msg.src) => {
+                let acked = trace.0[index + 1..].iter().any(|response| {
+                    response.dest == msg.src
+                        && response.src == msg.dest
+                        && matches!(response.body, BroadcastOk { in_reply_to } if in_reply_to == *msg_id)
+                });
+                if acked {
+                    acknowledged.insert(*message);
+                }
+            }
+            ReadOk { messages, .. } if is_client(&msg.dest) => {
+                last_reads.insert(msg.src.clone(), messages.clone());
+            }
+            _ => (),
+        }
+    }
+    for (node, messages) in last_reads {
+        let read: BTreeSet<u64> = messages.into_iter().collect();
+        let missing: Vec<&u64> = acknowledged.difference(&read).collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "{} misses acknowledged value(s) {:?} in its last read",
+                node, missing
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::simulate::{Entry, NemesisAction, Next, World};
+    use std::{
+        cmp::Reverse,
+        time::{Duration, Instant},
+    };
+
+    fn message(
+        at: Instant,
+        src: &str,
+        dest: &str,
+        body: BroadcastMessage,
+    ) -> Reverse<Entry<BroadcastMessage>> {
+        Reverse(Entry {
+            arrival_time: at,
+            envelope: Envelope {
+                src: src.to_string(),
+                dest: dest.to_string(),
+                body,
+            },
+        })
+    }
+
+    fn line_topology() -> BTreeMap<String, Vec<String>> {
+        [
+            ("n1", vec!["n2"]),
+            ("n2", vec!["n1", "n3"]),
+            ("n3", vec!["n2"]),
+        ]
+        .into_iter()
+        .map(|(node, neighbours)| {
+            (
+                node.to_string(),
+                neighbours.into_iter().map(str::to_string).collect(),
+            )
+        })
+        .collect()
+    }
+
+    fn world(start: Instant, broadcasts: &[(u64, &str, u64)]) -> World<BroadcastMessage> {
+        let nodes = ["n1", "n2", "n3"];
+        let mut messages = Vec::new();
+        for node in nodes {
+            messages.push(message(
+                start,
+                "c1",
+                node,
+                Topology {
+                    msg_id: 0,
+                    topology: line_topology(),
+                },
+            ));
+            messages.push(message(
+                start + Duration::from_secs(10),
+                "c1",
+                node,
+                Read { msg_id: 100 },
+            ));
+        }
+        for (at, node, value) in broadcasts {
+            messages.push(message(
+                start + Duration::from_secs(*at),
+                "c1",
+                node,
+                Broadcast {
+                    msg_id: *value,
+                    message: *value,
+                },
+            ));
+        }
+        World::new(
+            messages,
+            nodes
+                .iter()
+                .map(|node| (node.to_string(), spawn_broadcast_node()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn values_broadcast_to_any_node_reach_all_of_them() {
+        let start = Instant::now();
+        let mut world = world(start, &[(1, "n1", 1), (2, "n3", 2)]);
+
+        world.run_world();
+
+        assert_eq!(broadcast_property(world.trace().clone()), Ok(()));
+        let reads: Vec<&Vec<u64>> = world
+            .trace()
+            .0
+            .iter()
+            .filter_map(|msg| match &msg.body {
+                ReadOk { messages, .. } => Some(messages),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(reads, vec![&vec![1, 2]; 3]);
+    }
+
+    #[test]
+    fn gossip_lost_to_a_partition_is_caught_up_with_later() {
+        let start = Instant::now();
+        let mut world = world(start, &[(2, "n1", 1), (5, "n1", 2)]);
+        world.schedule(
+            start + Duration::from_secs(1),
+            NemesisAction::Partition("n1".to_string(), "n2".to_string()),
+        );
+        world.schedule(
+            start + Duration::from_secs(3),
+            NemesisAction::Heal("n1".to_string(), "n2".to_string()),
+        );
+
+        world.run_world();
+
+        assert_eq!(broadcast_property(world.trace().clone()), Ok(()));
+    }
+
+    #[test]
+    fn restoring_a_checkpoint_of_pure_stage_nodes_replays_the_same_future() {
+        let start = Instant::now();
+        let mut world = world(start, &[(1, "n1", 1), (2, "n3", 2)]);
+        for _ in 0..4 {
+            assert_eq!(world.step_world(), Next::Continue);
+        }
+        let checkpoint = world.checkpoint().unwrap();
+
+        world.run_world();
+        let future = world.trace().clone();
+        world.restore(&checkpoint).unwrap();
+        world.run_world();
+
+        assert_eq!(world.trace(), &future);
+        assert_eq!(broadcast_property(future), Ok(()));
+    }
+
+    #[test]
+    fn broadcast_property_catches_nodes_missing_values() {
+        let envelope = |src: &str, dest: &str, body| Envelope {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body,
+        };
+        let trace = Trace(vec![
+            envelope(
+                "c1",
+                "n1",
+                Broadcast {
+                    msg_id: 1,
+                    message: 7,
+                },
+            ),
+            envelope("n1", "c1", BroadcastOk { in_reply_to: 1 }),
+            envelope("c1", "n2", Read { msg_id: 2 }),
+            envelope(
+                "n2",
+                "c1",
+                ReadOk {
+                    in_reply_to: 2,
+                    messages: Vec::new(),
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            broadcast_property(trace),
+            Err("n2 misses acknowledged value(s) [7] in its last read".to_string())
+        );
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Invalid headers, obtained by corrupting valid ones in flight, byte by byte, with a
+//! [`MessageCorruptor`] built with [`cbor_corruptor`], to check that nodes reject them and
+//! keep on following the honest chain.
+//!
+//! The tests also corrupt valid headers in the ways a forger would, e.g. with a bad VRF proof
+//! or a wrong issuer, which survive decoding.
+
+use super::{nemesis::MessageCorruptor, simulate::SimRng, sync::ChainSyncMessage};
+use proptest::prelude::*;
+
+/// Flip a bit of the CBOR-encoded header of a forward message, or truncate it, at random.
+/// Other messages are left untouched.
+pub fn corrupt_cbor(msg: ChainSyncMessage, rng: &mut SimRng) -> ChainSyncMessage {
+    match msg {
+        ChainSyncMessage::Fwd {
+            msg_id,
+            slot,
+            hash,
+            header,
+            block,
+        } if !header.bytes.is_empty() => {
+            let mut bytes = header.bytes;
+            let at = rng.gen_range(0..bytes.len());
+            if rng.gen_bool(0.5) {
+                bytes[at] ^= 1 << rng.gen_range(0..8);
+            } else {
+                bytes.truncate(at);
+            }
+            ChainSyncMessage::Fwd {
+                msg_id,
+                slot,
+                hash,
+                header: bytes.into(),
+                block,
+            }
+        }
+        msg => msg,
+    }
+}
+
+/// Corrupt the headers of the given ratio of the forward messages exchanged by nodes, see
+/// [`corrupt_cbor`].
+pub fn cbor_corruptor(ratio: f64) -> MessageCorruptor<ChainSyncMessage> {
+    MessageCorruptor::new(ratio, corrupt_cbor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        echo::Envelope,
+        simulator::{
+            ledger::{ConsensusContext, FakeStakeDistribution},
+            node::Node,
+            simulate::{Entry, NodeHandle, World},
+            Args,
+        },
+    };
+    use amaru_kernel::{protocol_parameters::GlobalParameters, to_cbor, Header};
+    use amaru_ouroboros::IsHeader;
+    use clap::Parser;
+    use slot_arithmetic::Slot;
+    use std::{
+        cmp::Reverse,
+        fs::File,
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    /// The ways in which a valid header gets corrupted.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Corruption {
+        /// Flip a byte of the VRF proof.
+        BadVrfProof,
+        /// Replace the issuer's key with a key of no known pool.
+        WrongIssuer,
+        /// Move the header to a later slot, which its VRF proof and signature don't cover.
+        FutureSlot,
+        /// Announce a prefix of the header's CBOR encoding only.
+        TruncatedCbor,
+    }
+
+    fn any_corruption() -> impl Strategy<Value = Corruption> {
+        prop_oneof![
+            Just(Corruption::BadVrfProof),
+            Just(Corruption::WrongIssuer),
+            Just(Corruption::FutureSlot),
+            Just(Corruption::TruncatedCbor),
+        ]
+    }
+
+    /// Generate the announcement of a corrupted version of `header`, along with the
+    /// corruption it underwent.
+    fn corrupted_fwd(header: Header) -> impl Strategy<Value = (Corruption, ChainSyncMessage)> {
+        (
+            any_corruption(),
+            any::<[u8; 32]>(),
+            any::<prop::sample::Index>(),
+        )
+            .prop_map(move |(corruption, bytes, index)| {
+                (
+                    corruption,
+                    corrupt(&header, corruption, bytes, index.index(usize::MAX)),
+                )
+            })
+    }
+
+    /// Corrupt `header` as told, drawing what's needed from `bytes` and `index`, and announce
+    /// the result.
+    fn corrupt(
+        header: &Header,
+        corruption: Corruption,
+        bytes: [u8; 32],
+        index: usize,
+    ) -> ChainSyncMessage {
+        let mut corrupted = header.clone();
+        match corruption {
+            Corruption::BadVrfProof => {
+                let mut proof = corrupted.header_body.vrf_result.1.to_vec();
+                if !proof.is_empty() {
+                    let at = index % proof.len();
+                    proof[at] ^= bytes[0] | 1;
+                }
+                corrupted.header_body.vrf_result.1 = proof.into();
+            }
+            Corruption::WrongIssuer => {
+                corrupted.header_body.issuer_vkey = bytes.to_vec().into();
+            }
+            Corruption::FutureSlot => {
+                corrupted.header_body.slot += 1 + (index % 100_000) as u64;
+            }
+            Corruption::TruncatedCbor => {
+                let cbor = to_cbor(header);
+                return ChainSyncMessage::Fwd {
+                    msg_id: 0,
+                    slot: Slot::from(header.slot()),
+                    hash: header.hash().to_vec().into(),
+                    header: cbor[..index % cbor.len()].to_vec().into(),
+                    block: None,
+                };
+            }
+        }
+        ChainSyncMessage::Fwd {
+            msg_id: 0,
+            slot: Slot::from(corrupted.slot()),
+            hash: corrupted.hash().to_vec().into(),
+            header: to_cbor(&corrupted).into(),
+            block: None,
+        }
+    }
+
+    fn fwd(body: ChainSyncMessage) -> Envelope<ChainSyncMessage> {
+        Envelope {
+            src: "c1".to_string(),
+            dest: "n1".to_string(),
+            body,
+        }
+    }
+
+    fn valid_fwd(header: &Header) -> ChainSyncMessage {
+        ChainSyncMessage::Fwd {
+            msg_id: 0,
+            slot: Slot::from(header.slot()),
+            hash: header.hash().to_vec().into(),
+            header: to_cbor(header).into(),
+            block: None,
+        }
+    }
+
+    fn chain() -> Vec<Header> {
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution = FakeStakeDistribution::from_file(
+            Path::new("tests/data/stake-distribution.json"),
+            &global_parameters,
+        )
+        .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open("tests/data/consensus-context.json").unwrap())
+                .unwrap();
+        stake_distribution.generate_chain(None, 3, &context.nonce, &global_parameters)
+    }
+
+    fn args() -> Args {
+        Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ])
+    }
+
+    #[test]
+    fn nodes_reject_headers_corrupted_in_flight_and_keep_going() {
+        let chain = chain();
+        let args = args();
+        // n1 forwards what it selects to n2, which reports to the client
+        let n1 = Node::new(
+            "n1",
+            &args,
+            Path::new("unused"),
+            &["c1".to_string()],
+            vec!["n2".to_string()],
+        );
+        let n2 = Node::new(
+            "n2",
+            &args,
+            Path::new("unused"),
+            &["n1".to_string()],
+            vec!["c1".to_string()],
+        );
+        let start = Instant::now();
+        let messages = chain
+            .iter()
+            .enumerate()
+            .map(|(i, header)| {
+                Reverse(Entry {
+                    arrival_time: start + Duration::from_secs(i as u64),
+                    envelope: fwd(valid_fwd(header)),
+                })
+            })
+            .collect();
+        let mut world = World::new(
+            messages,
+            vec![
+                ("n1".to_string(), n1.boxed()),
+                ("n2".to_string(), n2.boxed()),
+            ],
+        )
+        .with_seed(42)
+        .with_nemesis(start, cbor_corruptor(1.0));
+
+        let statistics = world.run_world();
+
+        assert_eq!(statistics.delivered, 2 * chain.len());
+        assert_eq!(world.node_state("n1").unwrap()["rejected"], 0);
+        assert_eq!(
+            world.node_state("n2").unwrap()["rejected"],
+            chain.len() as u64
+        );
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn node_decodes_headers_following_a_corrupted_one(seed in any::<u64>()) {
+            let chain = chain();
+            let mut node = Node::new("n1", &args(), Path::new("unused"), &["c1".to_string()], vec!["c1".to_string()]);
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let corrupted = corrupt_cbor(valid_fwd(&chain[0]), &mut SimRng::new(seed));
+
+            prop_assert!(runtime.block_on(node.handle(fwd(corrupted))).unwrap().is_empty());
+            prop_assert_eq!(node.rejected(), 1);
+            for header in &chain {
+                prop_assert_eq!(runtime.block_on(node.handle(fwd(valid_fwd(header)))).unwrap().len(), 1);
+            }
+        }
+
+        #[test]
+        fn node_rejects_corrupted_headers_and_keeps_going((corruption, corrupted) in corrupted_fwd(chain()[1].clone())) {
+            let chain = chain();
+            let args = args();
+            let mut node = Node::new("n1", &args, Path::new("unused"), &["c1".to_string()], vec!["c1".to_string()]);
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+            prop_assert_eq!(runtime.block_on(node.handle(fwd(valid_fwd(&chain[0])))).unwrap().len(), 1);
+            let announced = runtime.block_on(node.handle(fwd(corrupted))).unwrap();
+            prop_assert!(announced.is_empty(), "{:?} header was selected", corruption);
+            prop_assert_eq!(node.rejected(), 1);
+            for header in &chain[1..] {
+                prop_assert_eq!(runtime.block_on(node.handle(fwd(valid_fwd(header)))).unwrap().len(), 1);
+            }
+        }
+    }
+}
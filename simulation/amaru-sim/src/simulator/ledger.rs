@@ -13,12 +13,25 @@
 // limitations under the License.
 
 use amaru_consensus::consensus::store::{ChainStore, StoreError};
-use amaru_kernel::{protocol_parameters::GlobalParameters, Header, RationalNumber};
-use amaru_ouroboros::{HasStakeDistribution, Nonces, PoolSummary};
-use pallas_crypto::hash::Hash;
+use amaru_kernel::{protocol_parameters::GlobalParameters, to_cbor, Header, Nonce, RationalNumber};
+#[cfg(test)]
+use amaru_ouroboros::issuer_to_pool_id;
+use amaru_ouroboros::{
+    ed25519, kes,
+    math::FixedDecimal,
+    praos::header::AssertLeaderStakeError,
+    vrf::{self, derive_tagged_vrf_output, Derivation},
+    HasStakeDistribution, IsHeader, Nonces, PoolSummary,
+};
+use pallas_crypto::hash::{Hash, Hasher};
+use pallas_primitives::babbage::{HeaderBody, OperationalCert, VrfCert};
+#[cfg(test)]
+use proptest::{collection::vec, prelude::*};
 use serde::{Deserialize, Serialize};
 use serde_json::Error;
 use slot_arithmetic::{Epoch, Slot};
+#[cfg(test)]
+use std::ops::Range;
 use std::{fs::File, io::BufReader, path::Path};
 
 /// Stake data for a single pool.
@@ -47,6 +60,122 @@ pub struct FakeStakePoolInfo {
     vrf_sign_key: String,
 }
 
+impl FakeStakePoolInfo {
+    fn vrf_secret_key(&self) -> vrf::SecretKey {
+        // the signing key is followed by the verification key
+        let bytes = decode_key(&self.vrf_sign_key, "VRF");
+        vrf::SecretKey::try_from(&bytes[..vrf::SecretKey::SIZE])
+            .unwrap_or_else(|e| panic!("invalid VRF key for pool {}: {:?}", self.pool_id, e))
+    }
+
+    fn cold_secret_key(&self) -> ed25519::SecretKey {
+        let bytes: [u8; ed25519::SecretKey::SIZE] = decode_key(&self.cold_sign_key, "cold")
+            .try_into()
+            .unwrap_or_else(|e| panic!("invalid cold key for pool {}: {:?}", self.pool_id, e));
+        bytes.into()
+    }
+
+    fn kes_secret_key_bytes(&self) -> Vec<u8> {
+        decode_key(&self.kes_sign_key, "KES")
+    }
+}
+
+fn decode_key(key: &str, kind: &str) -> Vec<u8> {
+    hex::decode(key).unwrap_or_else(|e| panic!("invalid hex-encoded {} key: {:?}", kind, e))
+}
+
+/// Seeds from which the cold, VRF and KES keys of a pool are derived.
+#[cfg(test)]
+type PoolSeeds = ([u8; 32], [u8; 32], [u8; 32]);
+
+#[cfg(test)]
+impl FakeStakePoolInfo {
+    fn from_seeds(
+        pool_idx: u64,
+        stake: u64,
+        total_stake: u64,
+        ocert_counter: u64,
+        (cold_seed, vrf_seed, mut kes_seed): PoolSeeds,
+    ) -> FakeStakePoolInfo {
+        let cold_vkey = ed25519::SecretKey::from(cold_seed).public_key();
+        let vrf_vkey = vrf::PublicKey::from(&vrf::SecretKey::from(&vrf_seed));
+
+        let mut kes_buffer = vec![0; kes::SecretKey::SIZE];
+        let kes_secret_key = kes::SecretKey::keygen(&mut kes_buffer, &mut kes_seed);
+        // SAFETY: simulated pools only ever sign simulated headers
+        let kes_bytes = unsafe { kes_secret_key.leak_into_bytes() };
+        // the period is not part of the stored key, which is always at period 0
+        let kes_sign_key = hex::encode(&kes_bytes[..kes_bytes.len() - 4]);
+
+        FakeStakePoolInfo {
+            cold_sign_key: hex::encode(cold_seed),
+            individual_stake: IndividualStake {
+                individual_pool_stake: RationalNumber {
+                    numerator: stake,
+                    denominator: total_stake,
+                },
+                individual_pool_stake_vrf: Hasher::<256>::hash(vrf_vkey.as_ref()),
+                individual_total_pool_stake: stake,
+            },
+            kes_sign_key,
+            ocert_counter,
+            pool_id: issuer_to_pool_id(&cold_vkey),
+            pool_idx,
+            vrf_sign_key: hex::encode([&vrf_seed[..], vrf_vkey.as_ref()].concat()),
+        }
+    }
+}
+
+/// Stake of a single pool, skewed towards small values: the order of magnitude of the stake
+/// is uniform, rather than the stake itself, and some pools have no stake at all.
+#[cfg(test)]
+fn any_pool_stake() -> impl Strategy<Value = u64> {
+    prop_oneof![
+        1 => Just(0),
+        4 => (0..48_u32).prop_flat_map(|magnitude| (1_u64 << magnitude)..(2_u64 << magnitude)),
+    ]
+}
+
+/// Generate stake distributions with a number of pools within `pools`, each with its own
+/// randomly generated keys, so that the headers they forge are valid.
+///
+/// At least one pool has some stake, otherwise no one could ever lead a slot.
+#[cfg(test)]
+pub fn any_stake_distribution(
+    pools: Range<usize>,
+    global_parameters: &GlobalParameters,
+) -> impl Strategy<Value = FakeStakeDistribution> {
+    let max_kes_evolutions = global_parameters.max_kes_evolution;
+    let slots_per_kes_period = global_parameters.slots_per_kes_period;
+    vec((any_pool_stake(), 0..16_u64, any::<PoolSeeds>()), pools)
+        .prop_filter("no pool has any stake", |pools| {
+            pools.iter().any(|(stake, ..)| *stake > 0)
+        })
+        .prop_map(move |pools| {
+            let total_active_stake = pools.iter().map(|(stake, ..)| stake).sum();
+            let pools = pools
+                .into_iter()
+                .enumerate()
+                .map(|(pool_idx, (stake, ocert_counter, seeds))| {
+                    FakeStakePoolInfo::from_seeds(
+                        pool_idx as u64,
+                        stake,
+                        total_active_stake,
+                        ocert_counter,
+                        seeds,
+                    )
+                })
+                .collect();
+            FakeStakeDistribution {
+                total_active_stake,
+                pools,
+                max_kes_evolutions,
+                slots_per_kes_period,
+            }
+        })
+}
+
+#[derive(Debug)]
 pub struct FakeStakeDistribution {
     total_active_stake: u64,
     pools: Vec<FakeStakePoolInfo>,
@@ -54,6 +183,13 @@ pub struct FakeStakeDistribution {
     slots_per_kes_period: u64,
 }
 
+/// The proof that a pool is allowed to issue a block in some slot.
+pub struct Election<'a> {
+    pub slot: Slot,
+    pool: &'a FakeStakePoolInfo,
+    proof: vrf::Proof,
+}
+
 impl FakeStakeDistribution {
     pub fn from_file(
         stake_distribution_file: &Path,
@@ -85,6 +221,140 @@ impl FakeStakeDistribution {
     }
 }
 
+#[allow(dead_code)]
+impl FakeStakeDistribution {
+    /// Find the first pool, in the order of the distribution, elected to lead the given slot.
+    ///
+    /// A pool is elected if its VRF output for the slot is below the threshold defined by its
+    /// relative stake, exactly as checked when validating the header it issues.
+    pub fn slot_leader(
+        &self,
+        slot: Slot,
+        epoch_nonce: &Nonce,
+        active_slot_coeff: &FixedDecimal,
+    ) -> Option<Election<'_>> {
+        let input = vrf::Input::new(slot, epoch_nonce);
+        self.pools.iter().find_map(|pool| {
+            let proof = pool.vrf_secret_key().prove(&input);
+            let proof_hash: Hash<{ vrf::Proof::HASH_SIZE }> = (&proof).into();
+            let leader_output = derive_tagged_vrf_output(proof_hash.as_slice(), Derivation::Leader);
+            let relative_stake =
+                FixedDecimal::from(pool.individual_stake.individual_total_pool_stake)
+                    / FixedDecimal::from(self.total_active_stake);
+            AssertLeaderStakeError::new(
+                active_slot_coeff,
+                &relative_stake,
+                &FixedDecimal::from(&leader_output[..]),
+            )
+            .ok()
+            .map(|()| Election { slot, pool, proof })
+        })
+    }
+
+    /// Forge the header of an (empty) block issued by the elected pool on top of `parent`.
+    ///
+    /// The operational certificate starts at the KES period of the slot, so the header is
+    /// signed with the KES key at its initial period.
+    pub fn forge_header(&self, election: &Election<'_>, parent: Option<&Header>) -> Header {
+        let Election { slot, pool, proof } = election;
+
+        let mut kes_bytes = pool.kes_secret_key_bytes();
+        let kes_secret_key = kes::SecretKey::from_bytes(&mut kes_bytes)
+            .unwrap_or_else(|e| panic!("invalid KES key for pool {}: {:?}", pool.pool_id, e));
+        let hot_vkey = kes::PublicKey::from(&kes_secret_key);
+
+        let cold_secret_key = pool.cold_secret_key();
+        let sequence_number = pool.ocert_counter;
+        let kes_period = self.slot_to_kes_period(*slot);
+        let mut opcert_message = Vec::new();
+        opcert_message.extend_from_slice(hot_vkey.as_ref());
+        opcert_message.extend_from_slice(&sequence_number.to_be_bytes());
+        opcert_message.extend_from_slice(&kes_period.to_be_bytes());
+        let opcert_signature = cold_secret_key.sign(&opcert_message);
+
+        let proof_hash: Hash<{ vrf::Proof::HASH_SIZE }> = proof.into();
+        let proof_bytes: [u8; vrf::Proof::SIZE] = proof.into();
+
+        let header_body = HeaderBody {
+            block_number: parent.map_or(0, |p| p.block_height()) + 1,
+            slot: (*slot).into(),
+            prev_hash: parent.map(|p| p.hash()),
+            issuer_vkey: cold_secret_key.public_key().as_ref().to_vec().into(),
+            vrf_vkey: vrf::PublicKey::from(&pool.vrf_secret_key())
+                .as_ref()
+                .to_vec()
+                .into(),
+            vrf_result: VrfCert(
+                proof_hash.as_slice().to_vec().into(),
+                proof_bytes.to_vec().into(),
+            ),
+            block_body_size: 0,
+            block_body_hash: Hasher::<256>::hash(&[]),
+            operational_cert: OperationalCert {
+                operational_cert_hot_vkey: hot_vkey.as_ref().to_vec().into(),
+                operational_cert_sequence_number: sequence_number,
+                operational_cert_kes_period: kes_period,
+                operational_cert_sigma: opcert_signature.as_ref().to_vec().into(),
+            },
+            protocol_version: (9, 0),
+        };
+        let body_signature: [u8; kes::Signature::SIZE] =
+            (&kes_secret_key.sign(&to_cbor(&header_body))).into();
+
+        Header {
+            header_body,
+            body_signature: body_signature.to_vec().into(),
+        }
+    }
+
+    /// Generate a chain of `length` headers on top of `parent`, following the leader
+    /// schedule of the distribution from the slot after the parent's.
+    ///
+    /// The epoch nonce is the same for all headers, so the chain must not cross an epoch
+    /// boundary for the headers to be valid.
+    pub fn generate_chain(
+        &self,
+        parent: Option<&Header>,
+        length: usize,
+        epoch_nonce: &Nonce,
+        global_parameters: &GlobalParameters,
+    ) -> Vec<Header> {
+        self.generate_chain_after(
+            parent,
+            parent.map_or(0, |p| p.slot()),
+            length,
+            epoch_nonce,
+            global_parameters,
+        )
+    }
+
+    /// Like [`Self::generate_chain`], but only looking for leaders after the given slot, so
+    /// that different chains can be forged on top of the same parent.
+    pub fn generate_chain_after(
+        &self,
+        parent: Option<&Header>,
+        after_slot: u64,
+        length: usize,
+        epoch_nonce: &Nonce,
+        global_parameters: &GlobalParameters,
+    ) -> Vec<Header> {
+        let active_slot_coeff = FixedDecimal::from(1_u64)
+            / FixedDecimal::from(global_parameters.active_slot_coeff_inverse as u64);
+        let mut chain: Vec<Header> = Vec::with_capacity(length);
+        let mut slot = after_slot;
+        while chain.len() < length {
+            slot += 1;
+            if let Some(election) =
+                self.slot_leader(Slot::from(slot), epoch_nonce, &active_slot_coeff)
+            {
+                let header = self.forge_header(&election, chain.last().or(parent));
+                chain.push(header);
+            }
+        }
+        chain
+    }
+}
+
 impl HasStakeDistribution for FakeStakeDistribution {
     fn get_pool(
         &self,
@@ -163,7 +433,7 @@ pub(crate) fn populate_chain_store(
 #[allow(non_snake_case)]
 pub struct ConsensusContext {
     active_slot_coeff: f64,
-    nonce: Hash<32>,
+    pub(crate) nonce: Hash<32>,
     praos_max_KES_evo: u8,
     praos_slots_per_KES_period: u64,
 }
@@ -177,10 +447,14 @@ mod test {
 
     use super::populate_chain_store;
 
-    use super::FakeStakeDistribution;
-    use amaru_ouroboros::HasStakeDistribution;
+    use super::{any_stake_distribution, ConsensusContext, FakeStakeDistribution};
+    use amaru_consensus::consensus::validate_header::header_is_valid;
+    use amaru_kernel::to_cbor;
+    use amaru_ouroboros::{HasStakeDistribution, IsHeader};
     use pallas_crypto::hash::Hash;
+    use proptest::prelude::*;
     use rand::{rngs::StdRng, RngCore, SeedableRng};
+    use std::fs::File;
     use std::path::PathBuf;
 
     /// FIXME: already exists in chain_selection test module
@@ -232,6 +506,64 @@ mod test {
         )
     }
 
+    #[test]
+    fn generated_chain_passes_header_validation() {
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution = FakeStakeDistribution::from_file(
+            &PathBuf::from("tests/data/stake-distribution.json"),
+            &global_parameters,
+        )
+        .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open("tests/data/consensus-context.json").unwrap())
+                .unwrap();
+
+        let chain = stake_distribution.generate_chain(None, 3, &context.nonce, &global_parameters);
+
+        assert_eq!(chain.len(), 3);
+        let mut parent = None;
+        for header in &chain {
+            assert_eq!(header.parent(), parent);
+            header_is_valid(
+                &header.point(),
+                header,
+                to_cbor(&header.header_body).as_slice(),
+                &context.nonce,
+                &stake_distribution,
+                &global_parameters,
+            )
+            .unwrap();
+            parent = Some(header.hash());
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn chain_forged_from_any_stake_distribution_is_valid(
+            stake_distribution in any_stake_distribution(1..10, &GlobalParameters::default()),
+            nonce in any::<[u8; 32]>(),
+        ) {
+            let global_parameters = GlobalParameters::default();
+            let nonce = Hash::from(nonce);
+
+            let chain = stake_distribution.generate_chain(None, 2, &nonce, &global_parameters);
+
+            for header in &chain {
+                let validation = header_is_valid(
+                    &header.point(),
+                    header,
+                    to_cbor(&header.header_body).as_slice(),
+                    &nonce,
+                    &stake_distribution,
+                    &global_parameters,
+                );
+                prop_assert!(validation.is_ok(), "{:?}", validation);
+            }
+        }
+    }
+
     #[test]
     fn populate_chain_store_nonces_from_context_file() {
         let consensus_store_file = "tests/data/consensus-context.json";
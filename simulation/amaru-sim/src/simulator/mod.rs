@@ -12,48 +12,93 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::echo::Envelope;
+use crate::echo::Envelope;
 use amaru_consensus::{
     consensus::{
         chain_selection::{ChainSelector, ChainSelectorBuilder},
-        receive_header::handle_chain_sync,
-        select_chain::SelectChain,
         store::ChainStore,
-        store_header::StoreHeader,
-        validate_header::ValidateHeader,
-        ChainSyncEvent, DecodedChainSyncEvent, ValidateHeaderEvent,
     },
     peer::Peer,
 };
 use amaru_kernel::{
-    network::NetworkName,
+    from_cbor,
     protocol_parameters::GlobalParameters,
-    to_cbor, Hash, Header,
+    Hash, Header,
     Point::{self, *},
 };
-use amaru_stores::rocksdb::consensus::RocksDBStore;
-use bytes::Bytes;
-use clap::Parser;
-use gasket::framework::WorkerError;
-use ledger::{populate_chain_store, FakeStakeDistribution};
-use std::{path::PathBuf, sync::Arc};
+use amaru_ouroboros::IsHeader;
+use chain_properties::{
+    bounded_rollbacks, chain_growth, chain_quality, common_prefix, replies_follow_requests,
+    serves_selected_chain, AnnouncedBlock, ChainGrowth,
+};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use config::{ConfigError, LatencyModel, LinkBandwidth, SimulatorConfig};
+use debugger::Debugger;
+use differential::compare;
+use invalid::cbor_corruptor;
+use model::{selects_like_the_model, ChainSelectionModel};
+use nemesis::{Chaos, ChaosWeights, ClockSkewer, Crasher, Partitioner};
+use node::Node;
+use replay::ReplayMessageReader;
+use scenario::Scenario;
+use simulate::{epoch, ChainEvent, Entry, Metrics, NodeHandle, ProcessNode, Step, Trace, World};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    fs::OpenOptions,
+    io::BufReader,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use sync::{
-    mk_message, read_peer_addresses_from_init, ChainSyncMessage, MessageReader, OutputWriter,
-    StdinMessageReader,
+    read_init, ChainSyncMessage, ErrorCode, InitError, MaelstromNode, MessageReader, OutputWriter,
+    ReaderError, StdinMessageReader,
 };
 use tokio::sync::Mutex;
+use topology::Topology;
 use tracing::info;
 
 mod bytes;
+#[cfg(test)]
+mod byzantine;
+mod chain_properties;
+mod config;
+mod corpus;
+mod debugger;
+mod differential;
+#[cfg(test)]
+mod epochs;
+mod faulty_store;
+#[cfg(test)]
+mod forks;
+mod golden;
+#[cfg(test)]
+mod gossip;
+mod invalid;
 mod ledger;
+mod model;
+mod nemesis;
+mod node;
+mod replay;
+mod scenario;
+mod schema;
 mod simulate;
 mod sync;
+mod temporal;
+mod topology;
 
 #[derive(Debug, Parser)]
 #[clap(name = "Amaru Simulator")]
 #[clap(bin_name = "amaru-sim")]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
+    /// Read the options not given on the command line from this JSON file, along with the
+    /// latency of the links between nodes, see the `config` module for its format.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
     /// Path of JSON-formatted stake distribution file.
     #[arg(long, default_value = "./stake_distribution.json")]
     pub stake_distribution_file: PathBuf,
@@ -66,7 +111,14 @@ pub struct Args {
     #[arg(long, default_value = "./chain.db")]
     pub chain_dir: PathBuf,
 
-    /// Path to the directory containing blockchain data such as epoch nonces.
+    /// Keep the chain of every node in memory instead of on disk under `chain_dir`.
+    /// Runs are then faster and leave nothing behind.
+    #[arg(long)]
+    pub in_memory: bool,
+
+    /// Path to the directory containing blockchain data. Nodes don't read epoch nonces from
+    /// it: they start from the nonce of the consensus context, and evolve it from the headers
+    /// they validate across epoch boundaries.
     #[arg(long, default_value = "./data")]
     pub data_dir: PathBuf,
 
@@ -74,203 +126,708 @@ pub struct Args {
     /// Default to genesis hash, eg. all-zero hash.
     #[arg(long, default_value_t = Hash::from([0; 32]))]
     pub start_header: Hash<32>,
+
+    /// Seed for the simulation's random number generator.
+    /// A failing run prints its seed, pass it here to replay the run exactly.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Number of nodes to simulate.
+    /// With more than one node, all nodes run in-process, each with its own chain store
+    /// under `chain_dir`, and forward the chain they select to the next one. The nodes and
+    /// their links are taken from the `init` message instead, if it describes them.
+    #[arg(long, default_value_t = 1)]
+    pub number_of_nodes: u8,
+
+    /// Write the messages exchanged in a multi-node run to this file, as a Mermaid sequence
+    /// diagram.
+    #[arg(long)]
+    pub mermaid: Option<PathBuf>,
+
+    /// Deliver the messages read from the input both to a node and to the reference
+    /// implementation found at this path, instead of simulating nodes, and fail on the first
+    /// message they answer with different `fwd` or `bck` events. See the `differential` module
+    /// for the protocol the reference implementation speaks.
+    #[arg(long)]
+    pub reference: Option<PathBuf>,
+
+    /// Replay the chain sync events of a capture recorded by a node, with its
+    /// `--capture-file` option, instead of reading messages from stdin.
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+
+    /// Security parameter of the chain selection: the maximum number of blocks a node rolls
+    /// back its chain, to follow a rollback or to switch to a fork.
+    /// Defaults to the one of the protocol. Shrink it to exercise fork-switch limits with
+    /// short chains.
+    #[arg(long)]
+    pub security_param: Option<u64>,
+
+    /// Inflict the faults scripted in this JSON file upon the nodes of a multi-node run, see
+    /// the `scenario` module for its format.
+    #[arg(long)]
+    pub scenario: Option<PathBuf>,
+
+    /// Flip a bit of, or truncate, the header of this ratio of the `fwd` messages exchanged
+    /// by the nodes of a multi-node run, between 0 and 1. Nodes are expected to reject these
+    /// headers and keep going.
+    #[arg(long)]
+    pub corrupt_headers: Option<f64>,
+
+    /// Step through a multi-node run interactively instead of running it to the end, reading
+    /// commands from the terminal. Type `help` for the list of commands.
+    #[arg(long)]
+    pub step: bool,
+
+    /// Compare the messages a multi-node run sends to its clients with those in this file,
+    /// one JSON message per line, and fail on the first difference. Runs are only
+    /// reproducible with the same `seed`.
+    #[arg(long)]
+    pub golden: Option<PathBuf>,
+
+    /// Write the messages a multi-node run sends to its clients to the `golden` file instead
+    /// of comparing them.
+    #[arg(long, requires = "golden")]
+    pub update_golden: bool,
+
+    /// Check the properties Ouroboros guarantees over the messages exchanged in a multi-node
+    /// run, and fail on the first violation: the chains nodes select share a common prefix
+    /// and never roll back deeper than the security parameter, they only hold headers sent
+    /// by clients, and nodes serve them to their clients as they announce them.
+    #[arg(long)]
+    pub check_properties: bool,
+
+    /// Also check that the chain of every node of a multi-node run holds at least so many
+    /// blocks in any window of slots, e.g. `3/100` for 3 blocks in any 100 slots.
+    #[arg(long, requires = "check_properties")]
+    pub chain_growth: Option<ChainGrowth>,
+
+    /// Fail a multi-node run if a node ever had more than so many messages which had arrived
+    /// and were waiting for it, to check that nodes keep up with their load.
+    #[arg(long)]
+    pub max_backlog: Option<usize>,
+
+    /// Fail a multi-node run if a message ever took longer than so many milliseconds of
+    /// simulated time to be delivered, waiting for its node included.
+    #[arg(long)]
+    pub max_latency: Option<u64>,
+
+    /// Limit the bandwidth of the links between the nodes of a multi-node run to this many
+    /// bytes per second, so that forward messages take longer to arrive the larger their
+    /// header and block, and may be overtaken by rollbacks sent after them.
+    #[arg(long)]
+    pub bandwidth: Option<u64>,
+
+    /// Check the messages read from stdin and written to stdout against the JSON Schema of
+    /// the protocol, found in `schema/envelope.json`. Malformed input messages are skipped,
+    /// and both kinds are logged along with the path of their first offending field.
+    #[arg(long)]
+    pub validate: bool,
+
+    /// Inflict faults of several kinds upon the nodes of a multi-node run, picked at random
+    /// with the given weights, e.g. `drop=3,duplicate=1,delay=2,partition=1,crash=1`. Kinds
+    /// left out are never picked. Every fault inflicted is logged, along with the seed to
+    /// pass to replay the run.
+    #[arg(long)]
+    pub chaos: Option<ChaosWeights>,
+
+    /// Milliseconds of simulated time between two faults of the `chaos`, `partitions`,
+    /// `crashes` and `clock_skew` options. Partitions and crashes last until the next fault.
+    #[arg(long, default_value_t = 100)]
+    pub chaos_interval: u64,
+
+    /// Every `chaos_interval`, heal the link cut last and cut the link between two other
+    /// nodes of a multi-node run, picked at random.
+    #[arg(long)]
+    pub partitions: bool,
+
+    /// Every `chaos_interval`, crash a node of a multi-node run picked at random, and restart
+    /// it half an interval later.
+    #[arg(long)]
+    pub crashes: bool,
+
+    /// Every `chaos_interval`, set the clock of a node of a multi-node run picked at random
+    /// ahead of the others by up to this many milliseconds.
+    #[arg(long)]
+    pub clock_skew: Option<u64>,
+
+    /// The latency of the links between the nodes of a multi-node run, from the `config`
+    /// file.
+    #[arg(skip)]
+    pub latency: Option<LatencyModel>,
+
+    /// The bandwidth of some of the links between the nodes of a multi-node run, from the
+    /// `config` file, instead of that of the `bandwidth` option.
+    #[arg(skip)]
+    pub bandwidths: Vec<LinkBandwidth>,
+
+    /// The faults scripted in the `config` file, unless a `scenario` file is given.
+    #[arg(skip)]
+    pub inline_scenario: Option<Scenario>,
+}
+
+impl Args {
+    /// Parse the command line, filling in the options it leaves out from the `config` file
+    /// if it names one.
+    pub fn parse_with_config<I, T>(itr: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Args::command().get_matches_from(itr);
+        let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if let Some(config_file) = &args.config {
+            SimulatorConfig::from_file(config_file)?.apply(&mut args, &matches)?;
+        }
+        Ok(args)
+    }
 }
 
+/// The longest a message gets delayed by the `chaos` option.
+const CHAOS_MAX_DELAY: Duration = Duration::from_secs(1);
+
 pub async fn run(args: Args) {
-    let input_reader = StdinMessageReader::new();
-    let consensus = bootstrap(args, input_reader);
+    match args.replay.clone() {
+        Some(capture_file) => {
+            let input_reader =
+                ReplayMessageReader::from_file(&capture_file, "n1").unwrap_or_else(|e| {
+                    panic!(
+                        "unable to replay capture '{}': {:?}",
+                        capture_file.display(),
+                        e
+                    )
+                });
+            run_with(args, input_reader).await
+        }
+        None => {
+            let input_reader = StdinMessageReader::new().with_validation(args.validate);
+            run_with(args, input_reader).await
+        }
+    }
+}
 
-    consensus.await;
+async fn run_with<T: MessageReader>(args: Args, input_reader: T) {
+    if let Some(reference) = args.reference.clone() {
+        run_differential(args, reference, input_reader).await;
+    } else if args.number_of_nodes > 1 {
+        run_nodes(args, input_reader).await;
+    } else if let Err(e) = bootstrap(args, input_reader).await {
+        panic!("simulator aborted: {}", e);
+    }
 }
 
-pub async fn bootstrap<T: MessageReader>(args: Args, mut input_reader: T) {
-    // NOTE: the output writer is behind a mutex because otherwise it's problematic to borrow
-    // it as mutable in the inner loop of run simulator
-    let output_writer = Arc::new(Mutex::new(OutputWriter::new()));
-
-    let global_parameters = GlobalParameters::default();
-    let stake_distribution: FakeStakeDistribution =
-        FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
-            .unwrap();
-    let era_history = NetworkName::Testnet(42).into();
-
-    let mut chain_store = RocksDBStore::new(&args.chain_dir, era_history).unwrap_or_else(|e| {
-        panic!(
-            "unable to open chain store at {}: {:?}",
-            args.chain_dir.display(),
-            e
+/// The ways in which the simulator fails, while setting up a node or processing a message.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum SimulatorError {
+    InitError(InitError),
+    ReaderError(ReaderError),
+    /// The message could not be decoded, or carries an invalid header or block. It is
+    /// dropped and the node goes on with the next one.
+    InvalidMessage(String),
+    /// The header of the message could not be stored, and is dropped.
+    StorageError(String),
+    /// The node crashed, see [`Crashed`](simulate::Crashed).
+    Crashed,
+    /// Chain selection failed, leaving the node in an unknown state.
+    ChainSelectionError(String),
+}
+
+impl SimulatorError {
+    /// Whether the node cannot go on processing messages after this error.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(
+            self,
+            SimulatorError::InvalidMessage(_) | SimulatorError::StorageError(_)
         )
-    });
+    }
+
+    /// The code of the Maelstrom `error` answering the message which caused this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SimulatorError::InvalidMessage(_) => ErrorCode::MalformedRequest,
+            SimulatorError::StorageError(_) => ErrorCode::TemporarilyUnavailable,
+            SimulatorError::Crashed => ErrorCode::Crash,
+            SimulatorError::InitError(_)
+            | SimulatorError::ReaderError(_)
+            | SimulatorError::ChainSelectionError(_) => ErrorCode::Abort,
+        }
+    }
+}
+
+impl std::fmt::Display for SimulatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulatorError::InitError(e) => write!(f, "unable to initialize node: {:?}", e),
+            SimulatorError::ReaderError(e) => write!(f, "unable to read messages: {:?}", e),
+            SimulatorError::InvalidMessage(e) => write!(f, "invalid message: {}", e),
+            SimulatorError::StorageError(e) => write!(f, "failed to store header: {}", e),
+            SimulatorError::Crashed => write!(f, "node crashed"),
+            SimulatorError::ChainSelectionError(e) => write!(f, "error processing event: {}", e),
+        }
+    }
+}
 
-    populate_chain_store(
-        &mut chain_store,
-        &args.start_header,
-        &args.consensus_context_file,
-    )
-    .unwrap();
+pub async fn bootstrap<T: MessageReader>(
+    args: Args,
+    mut input_reader: T,
+) -> Result<(), SimulatorError> {
+    // NOTE: the output writer is behind a mutex because otherwise it's problematic to borrow
+    // it as mutable in the inner loop of run simulator
+    let output_writer = Arc::new(Mutex::new(
+        OutputWriter::new().with_validation(args.validate),
+    ));
 
-    let peer_addresses = read_peer_addresses_from_init(&mut input_reader)
+    let (mut maelstrom, init_ok) = read_init(&mut input_reader)
         .await
-        .unwrap();
+        .map_err(SimulatorError::InitError)?;
+    let topology = maelstrom.topology();
+    let peer_addresses = topology.upstream(&maelstrom.node_id);
 
     info!("using upstream peer addresses: {:?}", peer_addresses);
 
-    {
-        let mut w = output_writer.lock().await;
-        let msg = Envelope {
-            src: "n1".to_string(),
-            dest: "c0".to_string(),
-            body: ChainSyncMessage::InitOk { in_reply_to: 0 },
+    output_writer.lock().await.write(vec![init_ok]).await;
+
+    let mut node = Node::new(
+        &maelstrom.node_id,
+        &args,
+        &args.chain_dir,
+        &peer_addresses,
+        topology.downstream(&maelstrom.node_id),
+    );
+
+    run_simulator(&mut input_reader, output_writer, &mut maelstrom, &mut node).await
+}
+
+/// Run several consensus pipelines in a simulated [`World`], following the topology of the
+/// `init` message. Without one, the nodes form a line: client messages read from the input
+/// are delivered to `n1`, every node forwards the chain it selects to the next node and the
+/// last one reports back to the client.
+async fn run_nodes<T: MessageReader>(args: Args, mut input_reader: T) {
+    let output_writer = Arc::new(Mutex::new(
+        OutputWriter::new().with_validation(args.validate),
+    ));
+
+    let (mut maelstrom, init_ok) = read_init(&mut input_reader).await.unwrap();
+    let topology = maelstrom
+        .topology
+        .clone()
+        .unwrap_or_else(|| Topology::line(args.number_of_nodes, maelstrom.node_ids.clone()));
+
+    info!("using topology: {:?}", topology);
+
+    output_writer.lock().await.write(vec![init_ok]).await;
+
+    // the world is driven to completion up-front, so we need all the client messages
+    let start = epoch();
+    let initial_messages: Vec<_> = read_messages(&mut input_reader, &mut maelstrom, &output_writer)
+        .await
+        .into_iter()
+        .enumerate()
+        .map(|(i, envelope)| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_millis(i as u64),
+                envelope,
+            })
+        })
+        .collect();
+
+    let mermaid = args.mermaid.clone();
+    let golden = args.golden.clone();
+    let update_golden = args.update_golden;
+    let check = args.check_properties;
+    let growth = args.chain_growth;
+    let max_backlog = args.max_backlog;
+    let max_latency = args.max_latency.map(Duration::from_millis);
+    let security_param = args
+        .security_param
+        .unwrap_or(GlobalParameters::default().consensus_security_param as u64);
+    let step = args.step;
+    let corrupt_headers = args.corrupt_headers;
+    let bandwidth = args.bandwidth;
+    let bandwidths = args.bandwidths.clone();
+    let chaos = args.chaos;
+    let chaos_interval = Duration::from_millis(args.chaos_interval);
+    let partitions = args.partitions;
+    let crashes = args.crashes;
+    let clock_skew = args.clock_skew.map(Duration::from_millis);
+    let inflicts_faults = chaos.is_some() || partitions || crashes || clock_skew.is_some();
+    let clients = topology.clone();
+    let scenario = match &args.scenario {
+        Some(scenario_file) => Some(Scenario::from_file(scenario_file).unwrap_or_else(|e| {
+            panic!(
+                "unable to load scenario '{}': {:?}",
+                scenario_file.display(),
+                e
+            )
+        })),
+        None => args.inline_scenario.clone(),
+    };
+    let latency = args.latency.clone();
+
+    // the world blocks on its own runtime to run the nodes, which cannot happen on one of the
+    // main runtime's worker threads
+    let (trace, steps, metrics) = tokio::task::spawn_blocking(move || {
+        let mut journals = BTreeMap::new();
+        let node_handles = topology
+            .node_ids
+            .iter()
+            .map(|id| {
+                let node = Node::new(
+                    id,
+                    &args,
+                    &args.chain_dir.join(id),
+                    &topology.upstream(id),
+                    topology.downstream(id),
+                );
+                journals.insert(id.clone(), node.journal());
+                (id.clone(), node.boxed())
+            })
+            .collect();
+
+        let seed = args.seed.unwrap_or_default();
+        // crashed nodes restart from what they persisted
+        let respawn = move |id: &String| {
+            let node = Node::recover(
+                id,
+                &args,
+                &args.chain_dir.join(id),
+                &topology.upstream(id),
+                topology.downstream(id),
+                journals.get(id).cloned().unwrap_or_default(),
+            )
+            .unwrap_or_else(|e| panic!("crash consistency violated on node '{}': {}", id, e));
+            node.boxed()
+        };
+        let mut world = World::new(initial_messages, node_handles)
+            .with_seed(seed)
+            .with_respawn(respawn)
+            .with_chain_events(|msg| match msg {
+                ChainSyncMessage::Fwd { .. } => Some(ChainEvent::Forward),
+                ChainSyncMessage::Bck { .. } => Some(ChainEvent::Rollback),
+                _ => None,
+            });
+        if let Some(latency) = latency {
+            world = latency.apply(world);
+        }
+        if let Some(scenario) = scenario {
+            world = scenario.apply(world, start);
+        }
+        if let Some(bytes_per_second) = bandwidth {
+            world = world.with_default_bandwidth(bytes_per_second);
+        }
+        if bandwidth.is_some() || !bandwidths.is_empty() {
+            world = world.with_message_size(ChainSyncMessage::payload_size);
+        }
+        for link in bandwidths {
+            world = world.with_bandwidth(link.nodes.0, link.nodes.1, link.bytes_per_second);
+        }
+        if let Some(ratio) = corrupt_headers {
+            world = world.with_nemesis(start, cbor_corruptor(ratio));
+        }
+        if let Some(weights) = chaos {
+            world = world.with_nemesis(start, Chaos::new(weights, chaos_interval, CHAOS_MAX_DELAY));
+        }
+        if partitions {
+            world = world.with_nemesis(start, Partitioner::new(chaos_interval));
+        }
+        if crashes {
+            world = world.with_nemesis(start, Crasher::new(chaos_interval, chaos_interval / 2));
+        }
+        if let Some(max_skew) = clock_skew {
+            world = world.with_nemesis(start, ClockSkewer::new(chaos_interval, max_skew));
+        }
+        let statistics = if step {
+            debug(&mut world);
+            world.statistics()
+        } else {
+            world.run_world()
         };
+        info!("simulation statistics: {}", statistics);
+        info!("simulation metrics: {}", world.metrics());
+        if inflicts_faults {
+            info!(seed, "inflicted {} fault(s)", world.inflicted().len());
+            for (at, fault) in world.inflicted() {
+                info!(
+                    at_ms = at.saturating_duration_since(start).as_millis() as u64,
+                    node = %fault.node(),
+                    "inflicted {:?}",
+                    fault
+                );
+            }
+        }
+        // only the properties need the states of the nodes along the run
+        let steps = if check {
+            world.steps().to_vec()
+        } else {
+            vec![]
+        };
+        (world.trace().clone(), steps, world.metrics().clone())
+    })
+    .await
+    .expect("simulated nodes panicked");
 
-        w.write(vec![msg]).await;
+    if let Some(path) = mermaid {
+        if let Err(e) = std::fs::write(&path, trace.to_mermaid()) {
+            tracing::error!("unable to write diagram to {}: {:?}", path.display(), e);
+        }
     }
-    let chain_selector = make_chain_selector(
-        Origin,
-        &chain_store,
-        &peer_addresses
-            .iter()
-            .map(|a| Peer::new(&a.clone()))
-            .collect::<Vec<_>>(),
-    );
-    let chain_ref = Arc::new(Mutex::new(chain_store));
-    let mut consensus = ValidateHeader::new(Box::new(stake_distribution), chain_ref.clone());
-
-    let mut store_header = StoreHeader::new(chain_ref.clone());
-    let mut select_chain = SelectChain::new(chain_selector);
-
-    run_simulator(
-        &mut input_reader,
-        output_writer,
-        chain_ref,
-        &mut consensus,
-        &mut store_header,
-        &mut select_chain,
-    )
-    .await;
+
+    if check {
+        if let Err(e) = check_properties(&trace, &steps, &clients, security_param as usize, growth)
+        {
+            panic!("property violated: {}", e)
+        }
+    }
+
+    if let Err(e) = check_metrics(&metrics, max_backlog, max_latency) {
+        panic!("metrics threshold exceeded: {}", e)
+    }
+
+    let outputs: Vec<_> = trace
+        .0
+        .into_iter()
+        .filter(|msg| clients.is_client(&msg.dest))
+        .collect();
+    if let Some(path) = golden {
+        if let Err(e) = golden::check(&path, &outputs, update_golden) {
+            panic!("golden trace {} mismatch: {:?}", path.display(), e)
+        }
+    }
+    output_writer
+        .lock()
+        .await
+        .write(maelstrom.stamp(outputs))
+        .await;
+    info!("no more messages to process, exiting");
 }
 
-async fn run_simulator(
-    input_reader: &mut impl MessageReader,
-    output_writer: Arc<Mutex<OutputWriter>>,
-    store: Arc<Mutex<dyn ChainStore<Header>>>,
-    validate_header: &mut ValidateHeader,
-    store_header: &mut StoreHeader,
-    select_chain: &mut SelectChain,
-) {
+/// Deliver the messages read from the input to a node and to the reference implementation
+/// at `reference`, and fail on the first message they answer differently, see [`compare`].
+async fn run_differential<T: MessageReader>(args: Args, reference: PathBuf, mut input_reader: T) {
+    let output_writer = Mutex::new(OutputWriter::new().with_validation(args.validate));
+
+    let (mut maelstrom, init_ok) = read_init(&mut input_reader).await.unwrap();
+    let topology = maelstrom.topology();
+    output_writer.lock().await.write(vec![init_ok]).await;
+
+    let messages = read_messages(&mut input_reader, &mut maelstrom, &output_writer).await;
+    let node_id = maelstrom.node_id.clone();
+
+    // comparing blocks on a runtime of its own, like the world of a multi-node run
+    let outcome = tokio::task::spawn_blocking(move || {
+        let mut ours = Node::new(
+            &node_id,
+            &args,
+            &args.chain_dir,
+            &topology.upstream(&node_id),
+            topology.downstream(&node_id),
+        );
+        let mut theirs = ProcessNode::spawn(&reference, &[]).unwrap_or_else(|e| {
+            panic!(
+                "unable to spawn reference node '{}': {}",
+                reference.display(),
+                e
+            )
+        });
+        let outcome = compare(&mut ours, &mut theirs, &messages);
+        ours.close();
+        theirs.close();
+        outcome
+    })
+    .await
+    .expect("compared nodes panicked");
+
+    match outcome {
+        Ok(()) => info!("no divergence from the reference node, exiting"),
+        Err(divergence) => panic!("{}", divergence),
+    }
+}
+
+/// Read the input until its end, answering the messages [`MaelstromNode`] handles on its own
+/// right away, and return the others.
+async fn read_messages<T: MessageReader>(
+    input_reader: &mut T,
+    maelstrom: &mut MaelstromNode,
+    output_writer: &Mutex<OutputWriter>,
+) -> Vec<Envelope<ChainSyncMessage>> {
+    let mut messages = vec![];
     loop {
-        let span = tracing::info_span!("simulator");
         match input_reader.read().await {
+            Ok(envelope) => match maelstrom.handle(&envelope) {
+                Some(replies) => output_writer.lock().await.write(replies).await,
+                None => messages.push(envelope),
+            },
+            Err(ReaderError::EndOfFile) => break,
+            Err(ReaderError::InvalidMessage(violation)) => {
+                tracing::error!(path = %violation.path, reason = %violation.reason, "skipping malformed message");
+            }
             Err(err) => {
                 tracing::error!("Error reading message: {:?}", err);
                 break;
             }
-            Ok(msg) => {
-                // receive stage
-                let chain_sync_event =
-                    mk_message(msg, span).and_then(|chain_sync: ChainSyncEvent| {
-                        handle_chain_sync(chain_sync).map_err(|_| WorkerError::Recv)
-                    });
-
-                // validate stage
-                let validation_event = match chain_sync_event {
-                    Ok(event) => match event {
-                        DecodedChainSyncEvent::RollForward {
-                            peer,
-                            point,
-                            header,
-                            ..
-                        } => validate_header
-                            .handle_roll_forward(peer, point, header, &GlobalParameters::default())
-                            .await
-                            .expect("unexpected error on roll forward"),
-                        DecodedChainSyncEvent::Rollback { .. } => event,
-                    },
-                    Err(_) => panic!("got error validating chain sync"),
-                };
-
-                // store header stage
-                let store_event = match store_header.handle_event(validation_event).await {
-                    Ok(stored) => stored,
-                    Err(_) => panic!("got error storing event"),
-                };
-
-                // chain selection stage
-                match select_chain.handle_chain_sync(store_event).await {
-                    Ok(events) => {
-                        let mut w = output_writer.lock().await;
-                        write_events(&mut w, &store, &events).await;
-                    }
-                    Err(e) => {
-                        tracing::error!("Error processing event: {:?}", e);
-                        return;
-                    }
-                }
+        }
+    }
+    messages
+}
+
+/// Check the properties of the `chain_properties` module over the trace of a multi-node run,
+/// with `k` the security parameter of its nodes, and that its nodes select the chains the
+/// `model` of chain selection does at the end of their `steps`.
+fn check_properties(
+    trace: &Trace<ChainSyncMessage>,
+    steps: &[Step<ChainSyncMessage>],
+    topology: &Topology,
+    k: usize,
+    growth: Option<ChainGrowth>,
+) -> Result<(), String> {
+    let nodes: Vec<&str> = topology.node_ids.iter().map(String::as_str).collect();
+    let sent_by_clients: BTreeSet<&[u8]> = trace
+        .0
+        .iter()
+        .filter(|envelope| topology.is_client(&envelope.src))
+        .filter_map(|envelope| match &envelope.body {
+            ChainSyncMessage::Fwd { hash, .. } => Some(hash.bytes.as_slice()),
+            _ => None,
+        })
+        .collect();
+
+    common_prefix(trace, &nodes, k)?;
+    bounded_rollbacks(trace, &nodes, k)?;
+    // with a ratio of 1, every block counts whatever the window
+    chain_quality(trace, &nodes, 1, 1.0, |block| {
+        sent_by_clients.contains(block.hash.bytes.as_slice())
+    })?;
+    if let Some(ChainGrowth { min_blocks, window }) = growth {
+        chain_growth(trace, &nodes, window, min_blocks)?;
+    }
+    replies_follow_requests(trace, &nodes)?;
+    for node in &nodes {
+        for client in topology.downstream(node) {
+            if topology.is_client(&client) {
+                serves_selected_chain(trace, node, &client)?;
             }
         }
     }
-    info!("no more messages to process, exiting");
+    selects_like_the_model(steps, ChainSelectionModel::new(k).with_validity(decodes))
+}
+
+/// Check the largest backlog of the nodes of a multi-node run and the longest delivery of its
+/// messages against the given thresholds, if any.
+fn check_metrics(
+    metrics: &Metrics,
+    max_backlog: Option<usize>,
+    max_latency: Option<Duration>,
+) -> Result<(), String> {
+    if let Some(max_backlog) = max_backlog {
+        if metrics.max_backlog() > max_backlog {
+            return Err(format!(
+                "a node had {} message(s) waiting for it, more than {}",
+                metrics.max_backlog(),
+                max_backlog
+            ));
+        }
+    }
+    if let (Some(max_latency), Some(latency)) = (max_latency, metrics.max_latency()) {
+        if latency > max_latency {
+            return Err(format!(
+                "a message took {:?} to be delivered, longer than {:?}",
+                latency, max_latency
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the header of the block decodes and hashes to the hash announced along with it,
+/// which headers corrupted on their way to a node don't.
+fn decodes(block: &AnnouncedBlock) -> bool {
+    from_cbor::<Header>(&block.header.bytes)
+        .is_some_and(|header| header.hash()[..] == block.hash.bytes[..])
 }
 
-async fn write_events(
-    output_writer: &mut OutputWriter,
-    store: &Arc<Mutex<dyn ChainStore<Header>>>,
-    events: &[ValidateHeaderEvent],
-) {
-    let mut msgs = vec![];
-    let s = store.lock().await;
-    for e in events {
-        match e {
-            ValidateHeaderEvent::Validated { point, .. } => {
-                let h: Hash<32> = point.into();
-                let hdr = s.load_header(&h).unwrap();
-                let fwd = ChainSyncMessage::Fwd {
-                    msg_id: 0, // FIXME
-                    slot: point.slot_or_default(),
-                    hash: Bytes {
-                        bytes: (*h).to_vec(),
-                    },
-                    header: Bytes {
-                        bytes: to_cbor(&hdr),
-                    },
-                };
-                let envelope = Envelope {
-                    src: "n1".to_string(),
-                    dest: "c1".to_string(),
-                    body: fwd,
-                };
-                msgs.push(envelope);
+/// Feed the messages read from the input to the node until there are none left.
+///
+/// Messages the node fails to process are answered with a Maelstrom `error`, and the node
+/// goes on with the next ones unless the error is fatal, in which case it is returned.
+async fn run_simulator(
+    input_reader: &mut impl MessageReader,
+    output_writer: Arc<Mutex<OutputWriter>>,
+    maelstrom: &mut MaelstromNode,
+    node: &mut Node,
+) -> Result<(), SimulatorError> {
+    loop {
+        let msg = match input_reader.read().await {
+            Ok(msg) => msg,
+            Err(ReaderError::EndOfFile) => break,
+            Err(ReaderError::JSONError(e)) => {
+                // there is no message to answer
+                tracing::error!("skipping malformed message: {}", e);
+                continue;
             }
-            ValidateHeaderEvent::Rollback { rollback_point, .. } => {
-                let h: Hash<32> = rollback_point.into();
-                let fwd = ChainSyncMessage::Bck {
-                    msg_id: 0, // FIXME
-                    slot: rollback_point.slot_or_default(),
-                    hash: Bytes {
-                        bytes: (*h).to_vec(),
-                    },
-                };
-                let envelope = Envelope {
-                    src: "n1".to_string(),
-                    dest: "c1".to_string(),
-                    body: fwd,
-                };
-                msgs.push(envelope);
+            Err(ReaderError::InvalidMessage(violation)) => {
+                tracing::error!(path = %violation.path, reason = %violation.reason, "skipping malformed message");
+                continue;
+            }
+            Err(e) => return Err(SimulatorError::ReaderError(e)),
+        };
+        if let Some(replies) = maelstrom.handle(&msg) {
+            output_writer.lock().await.write(replies).await;
+            continue;
+        }
+        match node.process(msg.clone()).await {
+            Ok(msgs) => {
+                let mut w = output_writer.lock().await;
+                w.write(maelstrom.stamp(msgs)).await;
+            }
+            Err(e) => {
+                tracing::error!("error processing message: {}", e);
+                let error = maelstrom.error(&msg, e.code(), &e.to_string());
+                output_writer.lock().await.write(error).await;
+                if e.is_fatal() {
+                    return Err(e);
+                }
             }
         }
     }
+    info!("no more messages to process, exiting");
+    Ok(())
+}
 
-    output_writer.write(msgs).await;
+/// Step through the world with a [`Debugger`] reading from, and writing to, the terminal,
+/// as stdin and stdout carry the messages of the simulation.
+fn debug(world: &mut World<ChainSyncMessage>) {
+    let result = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .and_then(|terminal| {
+            let input = BufReader::new(terminal.try_clone()?);
+            Debugger::new(input, terminal).run(world)
+        });
+    if let Err(e) = result {
+        tracing::error!("unable to debug the simulation from the terminal: {:?}", e);
+    }
 }
 
 fn make_chain_selector(
     tip: Point,
     chain_store: &impl ChainStore<Header>,
     peers: &Vec<Peer>,
+    security_param: u64,
+    genesis_window: u64,
 ) -> Arc<Mutex<ChainSelector<Header>>> {
     let mut builder = ChainSelectorBuilder::new();
 
     load_tip_from_store(chain_store, tip, &mut builder);
+    builder
+        .set_max_rollback(security_param)
+        .set_genesis_window(genesis_window);
+
+    // resume chain selection where it left off before a crash, unless the tip is on another chain
+    if let Some(state) = chain_store.load_chain_selector_state() {
+        builder.restore_onto_tip(&state, chain_store);
+    }
 
     for peer in peers {
         builder.add_peer(peer);
@@ -0,0 +1,412 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A reference model of chain selection, fed with the same deliveries as the nodes of a
+//! simulation, to compare the tip each node ends up selecting with the one it should have.
+//!
+//! The model knows nothing of headers but their slot and hash: it keeps the chain each
+//! upstream peer served to a node, from its `fwd` and `bck` messages, and selects the longest
+//! of them. It only switches to a chain strictly longer than the selected one, or when the
+//! selected chain isn't served anymore, and never rolls back more than `k` blocks to do
+//! so. Headers the validity predicate rejects are ignored, as the node would.
+//!
+//! Multi-node runs check that their nodes agree with the model along with the other
+//! properties of their chains.
+
+use super::{
+    chain_properties::{describe, AnnouncedBlock},
+    simulate::Step,
+    sync::ChainSyncMessage,
+};
+use crate::echo::Envelope;
+use amaru_kernel::Point;
+use std::collections::BTreeMap;
+
+/// What the model knows of a node: the chains its peers served it, and the one it selects.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeModel {
+    pub peers: BTreeMap<String, Vec<AnnouncedBlock>>,
+    pub selected: Vec<AnnouncedBlock>,
+}
+
+impl NodeModel {
+    fn select(&mut self, k: usize) {
+        let longest = self.peers.values().map(Vec::len).max().unwrap_or_default();
+        let mut candidates = self.peers.values().filter(|chain| chain.len() == longest);
+        if candidates.clone().any(|chain| *chain == self.selected) {
+            return;
+        }
+        let Some(candidate) = candidates.next() else {
+            return;
+        };
+        let shared = self
+            .selected
+            .iter()
+            .zip(candidate)
+            .take_while(|(a, b)| a.hash == b.hash)
+            .count();
+        if self.selected.len() - shared <= k {
+            self.selected = candidate.clone();
+        }
+    }
+
+    /// The tip of the selected chain.
+    pub fn tip(&self) -> Point {
+        match self.selected.last() {
+            Some(block) => Point::Specific(u64::from(block.slot), block.hash.bytes.clone()),
+            None => Point::Origin,
+        }
+    }
+}
+
+/// The chain selection of all the nodes of a simulation.
+#[derive(Debug, Clone)]
+pub struct ChainSelectionModel {
+    k: usize,
+    is_valid: fn(&AnnouncedBlock) -> bool,
+    nodes: BTreeMap<String, NodeModel>,
+}
+
+impl ChainSelectionModel {
+    /// A model of nodes rolling back at most `k` blocks, and deeming all headers valid.
+    pub fn new(k: usize) -> Self {
+        ChainSelectionModel {
+            k,
+            is_valid: |_| true,
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    /// Ignore the headers for which `is_valid` doesn't hold, e.g. those forged by byzantine
+    /// peers.
+    pub fn with_validity(mut self, is_valid: fn(&AnnouncedBlock) -> bool) -> Self {
+        self.is_valid = is_valid;
+        self
+    }
+
+    /// Update the model with a message delivered to a node. Only announcements matter, from
+    /// whoever sends them.
+    pub fn deliver(&mut self, envelope: &Envelope<ChainSyncMessage>) {
+        let chain = match &envelope.body {
+            ChainSyncMessage::Fwd { .. } | ChainSyncMessage::Bck { .. } => self
+                .nodes
+                .entry(envelope.dest.clone())
+                .or_default()
+                .peers
+                .entry(envelope.src.clone())
+                .or_default(),
+            _ => return,
+        };
+        match &envelope.body {
+            ChainSyncMessage::Fwd {
+                slot, hash, header, ..
+            } => {
+                let block = AnnouncedBlock {
+                    slot: *slot,
+                    hash: hash.clone(),
+                    header: header.clone(),
+                };
+                if chain.last().is_some_and(|tip| tip.hash == *hash) || !(self.is_valid)(&block) {
+                    return;
+                }
+                chain.push(block);
+            }
+            ChainSyncMessage::Bck { hash, .. } => {
+                let keep = chain
+                    .iter()
+                    .position(|block| block.hash == *hash)
+                    .map_or(0, |at| at + 1);
+                chain.truncate(keep);
+            }
+            _ => (),
+        }
+        let k = self.k;
+        if let Some(node) = self.nodes.get_mut(&envelope.dest) {
+            node.select(k);
+        }
+    }
+
+    /// What the model knows of `node`, if it was ever delivered an announcement.
+    pub fn node(&self, node: &str) -> Option<&NodeModel> {
+        self.nodes.get(node)
+    }
+}
+
+/// Every node selected the tip of the model by the end of the run: the model is updated with
+/// each delivery, and compared with the `tip` in the state of the node after its last one.
+///
+/// Nodes which don't expose their state, or were never delivered an announcement, are not
+/// checked.
+pub fn selects_like_the_model(
+    steps: &[Step<ChainSyncMessage>],
+    mut model: ChainSelectionModel,
+) -> Result<(), String> {
+    let mut tips = BTreeMap::new();
+    for step in steps {
+        model.deliver(&step.envelope);
+        if let Some(tip) = step
+            .post_state
+            .as_ref()
+            .and_then(|state| state.get("tip"))
+            .and_then(|tip| tip.as_str())
+        {
+            tips.insert(step.node.as_str(), tip);
+        }
+    }
+    for (node, tip) in tips {
+        let Some(expected) = model.node(node) else {
+            continue;
+        };
+        if tip != expected.tip().to_string() {
+            return Err(format!(
+                "{} selected {} while the model selected {}, the tip of {}",
+                node,
+                tip,
+                expected.tip(),
+                describe(&expected.selected)
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{
+        bytes::Bytes,
+        forks::{announcement_order, any_fork_shape, deep_rollback, fwd, ForkShape},
+        ledger::{ConsensusContext, FakeStakeDistribution},
+        node::Node,
+        simulate::{Entry, NodeHandle, World},
+        Args,
+    };
+    use amaru_kernel::protocol_parameters::GlobalParameters;
+    use clap::Parser;
+    use proptest::prelude::*;
+    use slot_arithmetic::Slot;
+    use std::{
+        cmp::Reverse,
+        fs::File,
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    fn announce(peer: &str, body: ChainSyncMessage) -> Envelope<ChainSyncMessage> {
+        Envelope {
+            src: peer.to_string(),
+            dest: "n1".to_string(),
+            body,
+        }
+    }
+
+    fn fwd_block(slot: u64, hash: u8) -> ChainSyncMessage {
+        ChainSyncMessage::Fwd {
+            msg_id: 0,
+            slot: Slot::from(slot),
+            hash: vec![hash].into(),
+            header: vec![].into(),
+            block: None,
+        }
+    }
+
+    fn bck_block(slot: u64, hash: u8) -> ChainSyncMessage {
+        ChainSyncMessage::Bck {
+            msg_id: 0,
+            slot: Slot::from(slot),
+            hash: vec![hash].into(),
+        }
+    }
+
+    fn selected(model: &ChainSelectionModel) -> Vec<Bytes> {
+        model
+            .node("n1")
+            .unwrap()
+            .selected
+            .iter()
+            .map(|block| block.hash.clone())
+            .collect()
+    }
+
+    #[test]
+    fn model_switches_to_strictly_longer_chains_within_k() {
+        let mut model = ChainSelectionModel::new(1);
+        for message in [
+            announce("p1", fwd_block(1, 1)),
+            announce("p1", fwd_block(2, 2)),
+            // as long as the selected chain: no switch
+            announce("p2", fwd_block(1, 1)),
+            announce("p2", fwd_block(3, 13)),
+        ] {
+            model.deliver(&message);
+        }
+        assert_eq!(selected(&model), vec![vec![1].into(), vec![2].into()]);
+
+        model.deliver(&announce("p2", fwd_block(4, 14)));
+        assert_eq!(
+            selected(&model),
+            vec![vec![1].into(), vec![13].into(), vec![14].into()]
+        );
+
+        // p2 rolls back, leaving p1 with the longest chain, but 2 blocks away from the selected
+        // one
+        model.deliver(&announce("p2", bck_block(0, 0)));
+        assert_eq!(
+            selected(&model),
+            vec![vec![1].into(), vec![13].into(), vec![14].into()]
+        );
+    }
+
+    #[test]
+    fn mismatching_tips_are_reported() {
+        let step = |body, tip: &str| Step {
+            node: "n1".to_string(),
+            pre_state: None,
+            envelope: announce("p1", body),
+            post_state: Some(serde_json::json!({ "tip": tip })),
+            outgoing: Vec::new(),
+        };
+        let steps = vec![
+            step(fwd_block(1, 1), "1.01"),
+            step(fwd_block(2, 2), "2.02"),
+            step(bck_block(1, 1), "2.02"),
+        ];
+
+        assert_eq!(
+            selects_like_the_model(&steps[..2], ChainSelectionModel::new(2)),
+            Ok(())
+        );
+        assert_eq!(
+            selects_like_the_model(&steps, ChainSelectionModel::new(2)),
+            Err("n1 selected 2.02 while the model selected 1.01, the tip of [1]".to_string())
+        );
+        // beyond k, the node is right not to follow the rollback
+        assert_eq!(
+            selects_like_the_model(&steps, ChainSelectionModel::new(0)),
+            Ok(())
+        );
+    }
+
+    fn args(security_param: Option<&str>) -> Args {
+        let mut args = vec![
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ];
+        if let Some(security_param) = security_param {
+            args.extend(["--security-param", security_param]);
+        }
+        Args::parse_from(args)
+    }
+
+    /// Run a node fed with the given announcements, one per millisecond, and compare its
+    /// selection with the model's.
+    fn run(
+        args: &Args,
+        k: usize,
+        peers: &[String],
+        announcements: Vec<Envelope<ChainSyncMessage>>,
+    ) -> Result<(), String> {
+        let node = Node::new(
+            "n1",
+            args,
+            Path::new("unused"),
+            peers,
+            vec!["c1".to_string()],
+        );
+        let start = Instant::now();
+        let messages = announcements
+            .into_iter()
+            .enumerate()
+            .map(|(i, envelope)| {
+                Reverse(Entry {
+                    arrival_time: start + Duration::from_millis(i as u64),
+                    envelope,
+                })
+            })
+            .collect();
+        let mut world = World::new(messages, vec![("n1".to_string(), node.boxed())]);
+        world.run_world();
+        selects_like_the_model(world.steps(), ChainSelectionModel::new(k))
+    }
+
+    fn scenario() -> impl Strategy<Value = (ForkShape, Vec<usize>)> {
+        any_fork_shape(2..4, 1..6, 0..4, 0..5).prop_flat_map(|shape| {
+            let order = announcement_order(&shape);
+            (Just(shape), order)
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        #[test]
+        fn node_selects_like_the_model_among_forks((shape, order) in scenario()) {
+            // nodes measure fork switches from the tip they started with, so only a security
+            // parameter longer than the chains lets them switch to any fork
+            let args = args(None);
+            let global_parameters = GlobalParameters::default();
+            let stake_distribution =
+                FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                    .unwrap();
+            let context: ConsensusContext =
+                serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+            let scenario = shape.forge(&stake_distribution, &context.nonce, &global_parameters);
+            let peers: Vec<String> = (1..=scenario.chains.len()).map(|i| format!("p{}", i)).collect();
+
+            let mut announced = vec![0; peers.len()];
+            let announcements = order
+                .into_iter()
+                .map(|peer| {
+                    announced[peer] += 1;
+                    announce(&peers[peer], fwd(&scenario.chains[peer][announced[peer] - 1]))
+                })
+                .collect();
+
+            let k = global_parameters.consensus_security_param as usize;
+            prop_assert_eq!(run(&args, k, &peers, announcements), Ok(()));
+        }
+    }
+
+    #[test]
+    fn node_selects_like_the_model_through_rollbacks() {
+        let args = args(Some("2"));
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+
+        for depth in [2, 3] {
+            let scenario =
+                deep_rollback(depth).forge(&stake_distribution, &context.nonce, &global_parameters);
+            let announcements = scenario
+                .switching_messages(0)
+                .into_iter()
+                .map(|body| announce("p1", body))
+                .collect();
+
+            assert_eq!(
+                run(&args, 2, &["p1".to_string()], announcements),
+                Ok(()),
+                "rolling back {} headers",
+                depth
+            );
+        }
+    }
+}
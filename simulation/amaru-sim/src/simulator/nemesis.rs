@@ -0,0 +1,575 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Nemeses, in the sense of Jepsen: adversaries striking a [`World`](super::simulate::World)
+//! on their own schedule, as opposed to faults scheduled up-front.
+//!
+//! A world can be given any number of nemeses, each striking independently of the others,
+//! so failure modes specific to amaru are written as new implementations of [`Nemesis`]
+//! and combined with the generic ones found here.
+
+use super::simulate::{NemesisAction, NodeId, SimRng};
+use crate::echo::Envelope;
+use proptest::prelude::*;
+use serde::Serialize;
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// The nodes a [`Nemesis`] can pick its victims from when it strikes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Targets {
+    pub running: Vec<NodeId>,
+    pub crashed: Vec<NodeId>,
+}
+
+impl Targets {
+    /// All the nodes of the world, running or not.
+    pub fn all(&self) -> impl Iterator<Item = &NodeId> {
+        self.running.iter().chain(&self.crashed)
+    }
+}
+
+/// What a [`Nemesis`] does when it strikes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Strike {
+    /// Inflicted upon the world right away, in order.
+    pub actions: Vec<NemesisAction>,
+    /// When to strike next, if ever.
+    pub next: Option<Instant>,
+}
+
+/// What becomes of a message sent by a node to another one, see [`Nemesis::fate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fate {
+    Deliver,
+    Drop,
+    /// Deliver the message twice, each copy with its own latency.
+    Duplicate,
+    /// Deliver the message that much later than its latency says.
+    Delay(Duration),
+}
+
+pub trait Nemesis<Msg> {
+    /// Strike the world at time `now`. All the randomness must come from `rng`, so that runs
+    /// can be replayed from their seed.
+    fn strike(&mut self, now: Instant, targets: &Targets, rng: &mut SimRng) -> Strike;
+
+    /// Tamper with a message sent by a node to another one, before it is routed. Messages are
+    /// left untouched by default.
+    fn tamper(&mut self, envelope: Envelope<Msg>, _rng: &mut SimRng) -> Envelope<Msg> {
+        envelope
+    }
+
+    /// Decide what becomes of a message sent by a node to another one, once tampered with
+    /// and unless a partition dropped it. Messages are delivered by default.
+    fn fate(&mut self, _envelope: &Envelope<Msg>, _rng: &mut SimRng) -> Fate {
+        Fate::Deliver
+    }
+}
+
+fn pick<'a>(nodes: &'a [NodeId], rng: &mut SimRng) -> Option<&'a NodeId> {
+    if nodes.is_empty() {
+        None
+    } else {
+        nodes.get(rng.gen_range(0..nodes.len()))
+    }
+}
+
+/// Every `interval`, heals the partition it made last and cuts the link between two other
+/// nodes, picked at random.
+pub struct Partitioner {
+    interval: Duration,
+    current: Option<(NodeId, NodeId)>,
+}
+
+impl Partitioner {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            current: None,
+        }
+    }
+}
+
+impl<Msg> Nemesis<Msg> for Partitioner {
+    fn strike(&mut self, now: Instant, targets: &Targets, rng: &mut SimRng) -> Strike {
+        let mut actions = Vec::new();
+        if let Some((a, b)) = self.current.take() {
+            actions.push(NemesisAction::Heal(a, b));
+        }
+        let nodes: Vec<NodeId> = targets.all().cloned().collect();
+        if nodes.len() >= 2 {
+            let a = rng.gen_range(0..nodes.len());
+            let b = (a + rng.gen_range(1..nodes.len())) % nodes.len();
+            let link = (nodes[a].clone(), nodes[b].clone());
+            actions.push(NemesisAction::Partition(link.0.clone(), link.1.clone()));
+            self.current = Some(link);
+        }
+        Strike {
+            actions,
+            next: Some(now + self.interval),
+        }
+    }
+}
+
+/// Every `interval`, crashes a running node picked at random, and restarts it `downtime`
+/// later. The world must know how to respawn nodes, see
+/// [`World::with_respawn`](super::simulate::World::with_respawn).
+pub struct Crasher {
+    interval: Duration,
+    downtime: Duration,
+    down: Option<NodeId>,
+}
+
+impl Crasher {
+    pub fn new(interval: Duration, downtime: Duration) -> Self {
+        Self {
+            interval,
+            downtime,
+            down: None,
+        }
+    }
+}
+
+impl<Msg> Nemesis<Msg> for Crasher {
+    fn strike(&mut self, now: Instant, targets: &Targets, rng: &mut SimRng) -> Strike {
+        if let Some(node) = self.down.take() {
+            return Strike {
+                actions: vec![NemesisAction::Restart(node)],
+                next: Some(now + self.interval),
+            };
+        }
+        match pick(&targets.running, rng) {
+            Some(node) => {
+                self.down = Some(node.clone());
+                Strike {
+                    actions: vec![NemesisAction::Crash(node.clone())],
+                    next: Some(now + self.downtime),
+                }
+            }
+            None => Strike {
+                actions: Vec::new(),
+                next: Some(now + self.interval),
+            },
+        }
+    }
+}
+
+/// Every `interval`, sets the clock of a running node picked at random ahead of the world's
+/// by up to `max_skew`, see [`NemesisAction::SkewClock`].
+pub struct ClockSkewer {
+    interval: Duration,
+    max_skew: Duration,
+}
+
+impl ClockSkewer {
+    pub fn new(interval: Duration, max_skew: Duration) -> Self {
+        Self { interval, max_skew }
+    }
+}
+
+impl<Msg> Nemesis<Msg> for ClockSkewer {
+    fn strike(&mut self, now: Instant, targets: &Targets, rng: &mut SimRng) -> Strike {
+        let actions = pick(&targets.running, rng)
+            .map(|node| {
+                let skew = rng.gen_range(0..=self.max_skew.as_millis() as u64);
+                NemesisAction::SkewClock(node.clone(), Duration::from_millis(skew))
+            })
+            .into_iter()
+            .collect();
+        Strike {
+            actions,
+            next: Some(now + self.interval),
+        }
+    }
+}
+
+/// Corrupts a ratio of the messages exchanged by nodes, picked at random, with the given
+/// function. It never strikes on its own.
+pub struct MessageCorruptor<Msg> {
+    ratio: f64,
+    corrupt: Box<dyn Fn(Msg, &mut SimRng) -> Msg>,
+}
+
+impl<Msg> MessageCorruptor<Msg> {
+    pub fn new(ratio: f64, corrupt: impl Fn(Msg, &mut SimRng) -> Msg + 'static) -> Self {
+        Self {
+            ratio: ratio.clamp(0.0, 1.0),
+            corrupt: Box::new(corrupt),
+        }
+    }
+}
+
+impl<Msg> Nemesis<Msg> for MessageCorruptor<Msg> {
+    fn strike(&mut self, _now: Instant, _targets: &Targets, _rng: &mut SimRng) -> Strike {
+        Strike::default()
+    }
+
+    fn tamper(&mut self, envelope: Envelope<Msg>, rng: &mut SimRng) -> Envelope<Msg> {
+        if !rng.gen_bool(self.ratio) {
+            return envelope;
+        }
+        Envelope {
+            body: (self.corrupt)(envelope.body, rng),
+            ..envelope
+        }
+    }
+}
+
+/// How often [`Chaos`] picks each kind of fault, relatively to the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChaosWeights {
+    pub drop: u32,
+    pub duplicate: u32,
+    pub delay: u32,
+    pub partition: u32,
+    pub crash: u32,
+}
+
+impl ChaosWeights {
+    fn total(&self) -> u32 {
+        self.drop + self.duplicate + self.delay + self.partition + self.crash
+    }
+}
+
+/// Parse weights of the form `drop=2,crash=1`, those left out being 0.
+impl FromStr for ChaosWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = ChaosWeights::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (kind, weight) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected <fault>=<weight>, found '{}'", pair))?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid weight '{}' of {}: {}", weight, kind, e))?;
+            match kind.trim() {
+                "drop" => weights.drop = weight,
+                "duplicate" => weights.duplicate = weight,
+                "delay" => weights.delay = weight,
+                "partition" => weights.partition = weight,
+                "crash" => weights.crash = weight,
+                other => {
+                    return Err(format!(
+                        "unknown fault '{}', expected one of drop, duplicate, delay, partition or crash",
+                        other
+                    ))
+                }
+            }
+        }
+        if weights.total() == 0 {
+            return Err("at least one fault must have a weight".to_string());
+        }
+        Ok(weights)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ChaosKind {
+    Drop,
+    Duplicate,
+    Delay,
+    Partition,
+    Crash,
+}
+
+/// Every `interval`, undoes the partition or crash it inflicted last, if any, then picks a
+/// kind of fault at random, following its [`ChaosWeights`]:
+///
+/// - `drop`, `duplicate` and `delay`, by up to `max_delay`, strike the next message sent by a
+///   node to another one;
+/// - `partition` cuts the link between two nodes, and `crash` crashes a running node, until
+///   the next strike. The world must know how to respawn nodes, see
+///   [`World::with_respawn`](super::simulate::World::with_respawn).
+pub struct Chaos {
+    weights: ChaosWeights,
+    interval: Duration,
+    max_delay: Duration,
+    /// The fate of the next message between nodes.
+    next_fate: Option<Fate>,
+    undo: Option<NemesisAction>,
+}
+
+impl Chaos {
+    pub fn new(weights: ChaosWeights, interval: Duration, max_delay: Duration) -> Self {
+        Self {
+            weights,
+            interval,
+            max_delay,
+            next_fate: None,
+            undo: None,
+        }
+    }
+}
+
+impl<Msg> Nemesis<Msg> for Chaos {
+    fn strike(&mut self, now: Instant, targets: &Targets, rng: &mut SimRng) -> Strike {
+        let mut actions: Vec<NemesisAction> = self.undo.take().into_iter().collect();
+        let total = self.weights.total();
+        if total == 0 {
+            return Strike {
+                actions,
+                next: None,
+            };
+        }
+        let ChaosWeights {
+            drop,
+            duplicate,
+            delay,
+            partition,
+            crash,
+        } = self.weights;
+        let mut pick_at = rng.gen_range(0..total);
+        let kind = [
+            (drop, ChaosKind::Drop),
+            (duplicate, ChaosKind::Duplicate),
+            (delay, ChaosKind::Delay),
+            (partition, ChaosKind::Partition),
+            (crash, ChaosKind::Crash),
+        ]
+        .into_iter()
+        .find_map(|(weight, kind)| {
+            if pick_at < weight {
+                Some(kind)
+            } else {
+                pick_at -= weight;
+                None
+            }
+        });
+        match kind {
+            Some(ChaosKind::Drop) => self.next_fate = Some(Fate::Drop),
+            Some(ChaosKind::Duplicate) => self.next_fate = Some(Fate::Duplicate),
+            Some(ChaosKind::Delay) => {
+                let by = rng.gen_range(1..=self.max_delay.as_millis().max(1) as u64);
+                self.next_fate = Some(Fate::Delay(Duration::from_millis(by)));
+            }
+            Some(ChaosKind::Partition) => {
+                let nodes: Vec<NodeId> = targets.all().cloned().collect();
+                if nodes.len() >= 2 {
+                    let a = rng.gen_range(0..nodes.len());
+                    let b = (a + rng.gen_range(1..nodes.len())) % nodes.len();
+                    actions.push(NemesisAction::Partition(nodes[a].clone(), nodes[b].clone()));
+                    self.undo = Some(NemesisAction::Heal(nodes[a].clone(), nodes[b].clone()));
+                }
+            }
+            Some(ChaosKind::Crash) => {
+                if let Some(node) = pick(&targets.running, rng) {
+                    actions.push(NemesisAction::Crash(node.clone()));
+                    self.undo = Some(NemesisAction::Restart(node.clone()));
+                }
+            }
+            None => (),
+        }
+        Strike {
+            actions,
+            next: Some(now + self.interval),
+        }
+    }
+
+    fn fate(&mut self, _envelope: &Envelope<Msg>, _rng: &mut SimRng) -> Fate {
+        self.next_fate.take().unwrap_or(Fate::Deliver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        echo::EchoMessage,
+        simulator::simulate::{Entry, FnNode, Inflicted, NodeHandle, World},
+    };
+    use std::cmp::Reverse;
+
+    fn targets(running: &[&str], crashed: &[&str]) -> Targets {
+        Targets {
+            running: running.iter().map(|node| node.to_string()).collect(),
+            crashed: crashed.iter().map(|node| node.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn partitioner_heals_its_previous_partition() {
+        let mut rng = SimRng::new(42);
+        let mut partitioner = Partitioner::new(Duration::from_secs(1));
+        let now = Instant::now();
+        let targets = targets(&["n1", "n2"], &["n3"]);
+
+        let first = Nemesis::<EchoMessage>::strike(&mut partitioner, now, &targets, &mut rng);
+        let second = Nemesis::<EchoMessage>::strike(&mut partitioner, now, &targets, &mut rng);
+
+        let [NemesisAction::Partition(a, b)] = &first.actions[..] else {
+            panic!("expected a single partition, got {:?}", first.actions)
+        };
+        assert_ne!(a, b);
+        assert_eq!(second.actions[0], NemesisAction::Heal(a.clone(), b.clone()));
+        assert_eq!(second.next, Some(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn crasher_restarts_the_node_it_crashed() {
+        let mut rng = SimRng::new(42);
+        let mut crasher = Crasher::new(Duration::from_secs(10), Duration::from_secs(1));
+        let now = Instant::now();
+
+        let crash =
+            Nemesis::<EchoMessage>::strike(&mut crasher, now, &targets(&["n1"], &[]), &mut rng);
+        let restart =
+            Nemesis::<EchoMessage>::strike(&mut crasher, now, &targets(&[], &["n1"]), &mut rng);
+
+        assert_eq!(
+            crash,
+            Strike {
+                actions: vec![NemesisAction::Crash("n1".to_string())],
+                next: Some(now + Duration::from_secs(1)),
+            }
+        );
+        assert_eq!(
+            restart,
+            Strike {
+                actions: vec![NemesisAction::Restart("n1".to_string())],
+                next: Some(now + Duration::from_secs(10)),
+            }
+        );
+    }
+
+    /// n1 forwards echoes to n2, which answers the client, and the client sends a single
+    /// echo at `start`.
+    fn relayed_echo(start: Instant) -> World<EchoMessage> {
+        let relay = FnNode::new(
+            |msg: Envelope<EchoMessage>| {
+                Ok(vec![Envelope {
+                    src: "n1".to_string(),
+                    dest: "n2".to_string(),
+                    body: msg.body,
+                }])
+            },
+            || (),
+        );
+        let responder = FnNode::new(
+            |msg: Envelope<EchoMessage>| match msg.body {
+                EchoMessage::Echo { msg_id, echo } => Ok(vec![Envelope {
+                    src: "n2".to_string(),
+                    dest: "c1".to_string(),
+                    body: EchoMessage::EchoOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        echo,
+                    },
+                }]),
+                _ => Ok(Vec::new()),
+            },
+            || (),
+        );
+        let echo = Reverse(Entry {
+            arrival_time: start,
+            envelope: Envelope {
+                src: "c1".to_string(),
+                dest: "n1".to_string(),
+                body: EchoMessage::Echo {
+                    msg_id: 1,
+                    echo: "Please echo 1".to_string(),
+                },
+            },
+        });
+        World::new(
+            vec![echo],
+            vec![
+                ("n1".to_string(), relay.boxed()),
+                ("n2".to_string(), responder.boxed()),
+            ],
+        )
+    }
+
+    #[test]
+    fn world_delivers_messages_tampered_with_by_its_nemeses() {
+        let start = Instant::now();
+        let corruptor = MessageCorruptor::new(1.0, |msg, _| match msg {
+            EchoMessage::Echo { msg_id, echo } => EchoMessage::Echo {
+                msg_id,
+                echo: echo.to_uppercase(),
+            },
+            msg => msg,
+        });
+        let mut world = relayed_echo(start).with_nemesis(start, corruptor);
+
+        world.run_world();
+
+        assert!(matches!(
+            &world.trace().0[..],
+            [_, Envelope { body: EchoMessage::EchoOk { echo, .. }, .. }] if echo == "PLEASE ECHO 1"
+        ));
+    }
+
+    #[test]
+    fn chaos_weights_are_parsed_from_pairs() {
+        assert_eq!(
+            "drop=3, crash=1".parse(),
+            Ok(ChaosWeights {
+                drop: 3,
+                crash: 1,
+                ..ChaosWeights::default()
+            })
+        );
+        assert!("drop=3,flood=1".parse::<ChaosWeights>().is_err());
+        assert!("drop=0".parse::<ChaosWeights>().is_err());
+    }
+
+    #[test]
+    fn chaos_undoes_its_last_crash_before_the_next_fault() {
+        let mut rng = SimRng::new(42);
+        let weights = ChaosWeights {
+            crash: 1,
+            ..ChaosWeights::default()
+        };
+        let mut chaos = Chaos::new(weights, Duration::from_secs(1), Duration::from_secs(1));
+        let now = Instant::now();
+
+        let crash =
+            Nemesis::<EchoMessage>::strike(&mut chaos, now, &targets(&["n1"], &[]), &mut rng);
+        let restart =
+            Nemesis::<EchoMessage>::strike(&mut chaos, now, &targets(&[], &["n1"]), &mut rng);
+
+        assert_eq!(crash.actions, vec![NemesisAction::Crash("n1".to_string())]);
+        assert_eq!(
+            restart.actions,
+            vec![NemesisAction::Restart("n1".to_string())]
+        );
+        assert_eq!(restart.next, Some(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn world_logs_the_messages_chaos_duplicates() {
+        let start = Instant::now();
+        let weights = ChaosWeights {
+            duplicate: 1,
+            ..ChaosWeights::default()
+        };
+        let chaos = Chaos::new(weights, Duration::from_secs(10), Duration::from_secs(1));
+        let mut world = relayed_echo(start).with_nemesis(start, chaos);
+
+        world.run_world();
+
+        assert_eq!(world.trace().0.len(), 3);
+        let [(_, Inflicted::Message(envelope, Fate::Duplicate))] = world.inflicted() else {
+            panic!("expected a single duplicate, got {:?}", world.inflicted())
+        };
+        assert_eq!(envelope.dest, "n2");
+    }
+}
@@ -0,0 +1,1381 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    bytes::Bytes,
+    faulty_store::{FaultyChainStore, StoreFault, StoreFaults},
+    ledger::{populate_chain_store, FakeStakeDistribution},
+    make_chain_selector,
+    simulate::{Crashed, NodeHandle, NodeState},
+    sync::{mk_message, ChainSyncMessage},
+    Args, SimulatorError,
+};
+use crate::echo::Envelope;
+use amaru::stages::ledger::ValidateBlockStage;
+use amaru_consensus::{
+    consensus::{
+        receive_header::handle_chain_sync,
+        reputation::{Offence, PeerStatus, Reputation, ScoreChange},
+        select_chain::SelectChain,
+        store::ChainStore,
+        store_header::StoreHeader,
+        validate_header::ValidateHeader,
+        watchdog::{PeerStalled, Watchdog, WatchdogMsg, DEFAULT_STALL_TIMEOUT},
+        DecodedChainSyncEvent, ValidateHeaderEvent,
+    },
+    peer::Peer,
+    ConsensusError,
+};
+use amaru_kernel::{
+    network::NetworkName, protocol_parameters::GlobalParameters, to_cbor, Hash, Hasher, Header,
+    Point,
+};
+use amaru_ledger::{rules::parse_block, store::in_memory::MemoryStore};
+use amaru_ouroboros::IsHeader;
+use amaru_stores::rocksdb::consensus::{InMemConsensusStore, RocksDBStore};
+use anyhow::anyhow;
+use pure_stage::{
+    simulation::{Blocked, Receiver, SimulationBuilder, SimulationRunning},
+    StageGraph, StageRef,
+};
+use slot_arithmetic::Slot;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+/// Shared handle to what a [`Node`] knows of its own past when it restarts after a crash,
+/// besides its chain store: the tip its chain selection acted on last, as a real node would
+/// find it in its ledger, and the header it stored last.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    tip: Rc<RefCell<Point>>,
+    stored: Rc<Cell<Option<Hash<32>>>>,
+    /// The identifier of the last message the node sent, so that those it sends after a
+    /// restart don't reuse identifiers its peers have seen already.
+    msg_id: Rc<Cell<u64>>,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self {
+            tip: Rc::new(RefCell::new(Point::Origin)),
+            stored: Rc::new(Cell::new(None)),
+            msg_id: Rc::new(Cell::new(0)),
+        }
+    }
+}
+
+impl Journal {
+    pub fn tip(&self) -> Point {
+        self.tip.borrow().clone()
+    }
+
+    /// Check that the chain store holds everything the node relied on before crashing.
+    fn check(&self, store: &dyn ChainStore<Header>) -> anyhow::Result<()> {
+        if let Some(hash) = self.stored.get() {
+            match store.load_header(&hash) {
+                Some(header) if header.hash() == hash => (),
+                Some(header) => {
+                    return Err(anyhow!(
+                        "header {} stored before the crash reads back as {}",
+                        hash,
+                        header.hash()
+                    ))
+                }
+                None => {
+                    return Err(anyhow!(
+                        "header {} stored before the crash is missing",
+                        hash
+                    ))
+                }
+            }
+        }
+        let tip = self.tip();
+        if tip != Point::Origin && store.load_header(&Hash::from(&tip)).is_none() {
+            return Err(anyhow!("selected tip {} is missing from the store", tip));
+        }
+        Ok(())
+    }
+}
+
+/// Tells the upstream peers of a node that stay silent for too long, running a [`Watchdog`]
+/// on the node's clock.
+struct StallDetector {
+    running: SimulationRunning,
+    watchdog: StageRef<WatchdogMsg, Watchdog>,
+    stalled: Receiver<PeerStalled>,
+    /// The world's time and the watchdog's the first time the node ticked, mapping one onto
+    /// the other, like for a `PureStageNode`.
+    origin: Option<(Instant, pure_stage::Instant)>,
+}
+
+impl StallDetector {
+    fn new(timeout: Duration) -> Self {
+        let mut network = SimulationBuilder::default();
+        let watchdog = network.stage(
+            "watchdog",
+            async |watchdog: Watchdog, msg, eff| Ok(watchdog.handle(msg, eff).await),
+            Watchdog::new(timeout),
+        );
+        let (output, stalled) = network.output("stalled");
+        let watchdog = network.wire_up(watchdog, |state| state.connect(output.without_state()));
+        Self {
+            running: network.run(),
+            watchdog,
+            stalled,
+            origin: None,
+        }
+    }
+
+    /// Account for a message from the peer.
+    fn progress(&mut self, peer: &Peer) -> anyhow::Result<()> {
+        self.running
+            .enqueue_msg(&self.watchdog, [WatchdogMsg::Progress(peer.clone())]);
+        match self.running.run_until_sleeping_or_blocked() {
+            Blocked::Idle | Blocked::Sleeping => Ok(()),
+            blocked => Err(anyhow!("watchdog is stuck: {:?}", blocked)),
+        }
+    }
+
+    /// Move the watchdog's clock to `now`, returning the peers found stalled meanwhile.
+    fn tick(&mut self, now: Instant) -> anyhow::Result<Vec<PeerStalled>> {
+        let running = &mut self.running;
+        let (world_origin, node_origin) = *self.origin.get_or_insert_with(|| (now, running.now()));
+        let target = node_origin
+            .checked_add(now.saturating_duration_since(world_origin))
+            .ok_or_else(|| anyhow!("node clock overflow"))?;
+        match running.run_until(target) {
+            Blocked::Idle | Blocked::Sleeping => Ok(self.stalled.drain().collect()),
+            blocked => Err(anyhow!("watchdog is stuck: {:?}", blocked)),
+        }
+    }
+}
+
+/// Open the chain store of a node in `chain_dir`, or in memory if `args` say so, with the
+/// nonces of the start header.
+fn open_chain_store(args: &Args, chain_dir: &Path) -> Box<dyn ChainStore<Header>> {
+    let era_history = NetworkName::Testnet(42).into();
+    let mut chain_store: Box<dyn ChainStore<Header>> = if args.in_memory {
+        Box::new(InMemConsensusStore::new())
+    } else {
+        Box::new(
+            RocksDBStore::new(&chain_dir.to_path_buf(), era_history).unwrap_or_else(|e| {
+                panic!(
+                    "unable to open chain store at {}: {:?}",
+                    chain_dir.display(),
+                    e
+                )
+            }),
+        )
+    };
+
+    populate_chain_store(
+        &mut chain_store,
+        &args.start_header,
+        &args.consensus_context_file,
+    )
+    .unwrap();
+
+    chain_store
+}
+
+/// The consensus pipeline of a single simulated node, along with the chain store its
+/// stages share.
+pub struct Node {
+    id: String,
+    downstream: Vec<String>,
+    store: Arc<Mutex<dyn ChainStore<Header>>>,
+    store_faults: StoreFaults,
+    journal: Journal,
+    /// How many messages failed to decode or validate so far.
+    rejected: Rc<Cell<u64>>,
+    /// For each downstream client, the last header of the chain served to it, if any.
+    cursors: BTreeMap<String, Option<Hash<32>>>,
+    /// The upstream peers banned so far, whose messages the node doesn't read anymore, as if
+    /// it had closed the connection.
+    disconnected: BTreeSet<String>,
+    /// Lowers the score of upstream peers that stay silent for too long.
+    stalls: StallDetector,
+    validate_header: ValidateHeader,
+    /// Checks the blocks carried along their header against the ledger rules. The ledger only
+    /// ever sees those blocks, in the order they were received.
+    ledger: ValidateBlockStage<MemoryStore, MemoryStore>,
+    store_header: StoreHeader,
+    select_chain: SelectChain,
+}
+
+impl Node {
+    /// Set up a node with its own chain store in `chain_dir`, or in memory if `args` say so,
+    /// following the given upstream peers and forwarding the chain it selects to the
+    /// `downstream` ones.
+    pub fn new(
+        id: &str,
+        args: &Args,
+        chain_dir: &Path,
+        upstream: &[String],
+        downstream: Vec<String>,
+    ) -> Self {
+        let chain_store = open_chain_store(args, chain_dir);
+        Self::assemble(
+            id,
+            args,
+            chain_store,
+            upstream,
+            downstream,
+            Journal::default(),
+        )
+    }
+
+    /// Set up a node whose chain starts after the given headers instead of the origin, as if
+    /// it had selected them already: they are stored up-front, along with the nonces of the
+    /// consensus context at the last one. Chains crossing epoch boundaries need such an
+    /// anchor, see [`EpochChain`](super::epochs::EpochChain).
+    #[cfg(test)]
+    pub fn anchored(
+        id: &str,
+        args: &Args,
+        chain_dir: &Path,
+        upstream: &[String],
+        downstream: Vec<String>,
+        anchor: &[Header],
+    ) -> Self {
+        let mut chain_store = open_chain_store(args, chain_dir);
+        let journal = Journal::default();
+        for header in anchor {
+            chain_store
+                .store_header(&header.hash(), header)
+                .unwrap_or_else(|e| {
+                    panic!("unable to store anchor header {}: {:?}", header.hash(), e)
+                });
+        }
+        if let Some(tip) = anchor.last() {
+            populate_chain_store(&mut chain_store, &tip.hash(), &args.consensus_context_file)
+                .unwrap();
+            *journal.tip.borrow_mut() = tip.point();
+        }
+        Self::assemble(id, args, chain_store, upstream, downstream, journal)
+    }
+
+    /// Restart a node after a crash, reopening its chain store in `chain_dir` and resuming
+    /// chain selection from the tip found in its `journal`.
+    ///
+    /// Fails if the store and the journal don't reconcile, e.g. because a header the node
+    /// stored before crashing was not persisted. Nothing survives the crash of a node keeping
+    /// its chain in memory, which starts over instead.
+    pub fn recover(
+        id: &str,
+        args: &Args,
+        chain_dir: &Path,
+        upstream: &[String],
+        downstream: Vec<String>,
+        journal: Journal,
+    ) -> anyhow::Result<Self> {
+        let chain_store = open_chain_store(args, chain_dir);
+        if args.in_memory {
+            *journal.tip.borrow_mut() = Point::Origin;
+            journal.stored.set(None);
+        } else {
+            journal.check(chain_store.as_ref())?;
+        }
+        Ok(Self::assemble(
+            id,
+            args,
+            chain_store,
+            upstream,
+            downstream,
+            journal,
+        ))
+    }
+
+    fn assemble(
+        id: &str,
+        args: &Args,
+        chain_store: Box<dyn ChainStore<Header>>,
+        upstream: &[String],
+        downstream: Vec<String>,
+        journal: Journal,
+    ) -> Self {
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution: FakeStakeDistribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+
+        let security_param = args
+            .security_param
+            .unwrap_or(global_parameters.consensus_security_param as u64);
+        // the genesis window follows the security parameter, as it does on the network
+        let genesis_window = global_parameters.genesis_window
+            / global_parameters.consensus_security_param as u64
+            * security_param;
+        let chain_selector = make_chain_selector(
+            journal.tip(),
+            &chain_store,
+            &upstream.iter().map(|a| Peer::new(a)).collect::<Vec<_>>(),
+            security_param,
+            genesis_window,
+        );
+        let chain_store = FaultyChainStore::new(chain_store);
+        let store_faults = chain_store.faults();
+        let store = Arc::new(Mutex::new(chain_store));
+        let (ledger, _) = ValidateBlockStage::new(
+            MemoryStore {},
+            MemoryStore {},
+            NetworkName::Testnet(42).into(),
+            global_parameters,
+        )
+        .unwrap_or_else(|e| panic!("unable to create ledger for node {}: {:?}", id, e));
+        let validate_header = ValidateHeader::new(Box::new(stake_distribution), store.clone());
+        let reputation = validate_header.reputation();
+
+        Self {
+            id: id.to_string(),
+            downstream,
+            validate_header,
+            ledger,
+            store_header: StoreHeader::new(store.clone()),
+            select_chain: SelectChain::new(chain_selector)
+                .with_reputation(reputation)
+                .with_store(store.clone()),
+            store,
+            store_faults,
+            journal,
+            rejected: Rc::new(Cell::new(0)),
+            cursors: BTreeMap::new(),
+            disconnected: BTreeSet::new(),
+            stalls: StallDetector::new(DEFAULT_STALL_TIMEOUT),
+        }
+    }
+
+    /// The handle to make this node's chain store operations fail.
+    pub fn store_faults(&self) -> StoreFaults {
+        self.store_faults.clone()
+    }
+
+    /// The handle to recover this node after a crash, see [`Node::recover`].
+    pub fn journal(&self) -> Journal {
+        self.journal.clone()
+    }
+
+    /// How many messages the node rejected so far, because they could not be decoded or
+    /// carried an invalid header.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.get()
+    }
+
+    /// The scores of the upstream peers of the node, lowered as they send invalid headers or
+    /// roll back deeper than the security parameter, and the equivocations of issuers.
+    /// Messages from banned peers are rejected.
+    pub fn reputation(&self) -> Arc<Mutex<Reputation>> {
+        self.validate_header.reputation()
+    }
+
+    /// A fresh identifier for a message sent by the node, greater than those of all the
+    /// messages it sent before.
+    fn next_msg_id(&self) -> u64 {
+        let msg_id = self.journal.msg_id.get() + 1;
+        self.journal.msg_id.set(msg_id);
+        msg_id
+    }
+
+    fn reject(&self, reason: impl std::fmt::Debug) -> SimulatorError {
+        error!(node = %self.id, "rejected message: {:?}", reason);
+        self.rejected.set(self.rejected.get() + 1);
+        SimulatorError::InvalidMessage(format!("{:?}", reason))
+    }
+
+    /// Push a chain sync message from an upstream peer through the pipeline, returning the
+    /// messages announcing the resulting chain selection to the downstream peers.
+    ///
+    /// Messages that cannot be decoded or whose header or block is invalid are dropped, and
+    /// the node carries on as if it had never received them.
+    pub async fn handle(
+        &mut self,
+        msg: Envelope<ChainSyncMessage>,
+    ) -> anyhow::Result<Vec<Envelope<ChainSyncMessage>>> {
+        match self.process(msg).await {
+            Ok(msgs) => Ok(msgs),
+            Err(e) if !e.is_fatal() => Ok(vec![]),
+            Err(SimulatorError::Crashed) => Err(Crashed.into()),
+            Err(e) => Err(anyhow!("{}", e)),
+        }
+    }
+
+    /// Like [`Node::handle`], but telling why a message was dropped, so that it can be
+    /// answered with an error.
+    ///
+    /// Messages from peers disconnected after being banned are dropped before anything else.
+    pub async fn process(
+        &mut self,
+        msg: Envelope<ChainSyncMessage>,
+    ) -> Result<Vec<Envelope<ChainSyncMessage>>, SimulatorError> {
+        if self.disconnected.contains(&msg.src) {
+            return Err(self.reject(ConsensusError::BannedPeer(Peer::new(&msg.src))));
+        }
+        if let ChainSyncMessage::Fwd { .. } | ChainSyncMessage::Bck { .. } = &msg.body {
+            // even an invalid message shows the peer is alive, it is penalised otherwise
+            self.stalls
+                .progress(&Peer::new(&msg.src))
+                .map_err(|e| SimulatorError::ChainSelectionError(e.to_string()))?;
+        }
+        let result = self.pipeline(msg).await;
+        let changes = self.reputation().lock().await.take_events();
+        self.disconnect_banned(changes);
+        result
+    }
+
+    /// Lower the score of the upstream peers that stayed silent for too long by `now`.
+    fn detect_stalls(&mut self, now: Instant) -> anyhow::Result<()> {
+        let stalled = self.stalls.tick(now)?;
+        let changes = {
+            let reputation = self.reputation();
+            // the reputation is only ever busy while the node handles a message
+            let mut reputation = reputation
+                .try_lock()
+                .map_err(|e| anyhow!("reputation of node {} is busy: {}", self.id, e))?;
+            for PeerStalled { peer, .. } in stalled {
+                reputation.record(&peer, Offence::Stall);
+            }
+            reputation.take_events()
+        };
+        self.disconnect_banned(changes);
+        Ok(())
+    }
+
+    /// Disconnect the peers banned by the given score changes, e.g. for sending invalid
+    /// headers, rolling back too deep or stalling too often.
+    ///
+    /// Scores recover over minutes of wall-clock time, longer than simulations run, so peers
+    /// stay disconnected until the end of the run.
+    fn disconnect_banned(&mut self, changes: Vec<ScoreChange>) {
+        for change in changes {
+            match change.status {
+                PeerStatus::Banned if self.disconnected.insert(change.peer.name.clone()) => {
+                    warn!(node = %self.id, peer = %change.peer.name, "peer_disconnected");
+                }
+                PeerStatus::Good | PeerStatus::Demoted | PeerStatus::Banned => (),
+            }
+        }
+    }
+
+    async fn pipeline(
+        &mut self,
+        msg: Envelope<ChainSyncMessage>,
+    ) -> Result<Vec<Envelope<ChainSyncMessage>>, SimulatorError> {
+        let span = tracing::info_span!("simulator", node = %self.id);
+        let block = match &msg.body {
+            ChainSyncMessage::Fwd { block, .. } => block.clone(),
+            ChainSyncMessage::FindIntersect { .. } | ChainSyncMessage::RequestNext { .. } => {
+                return Ok(self.serve(msg).await)
+            }
+            _ => None,
+        };
+
+        // receive stage
+        let chain_sync_event = match mk_message(msg, span) {
+            Ok(chain_sync) => handle_chain_sync(chain_sync),
+            Err(e) => return Err(self.reject(e)),
+        };
+
+        // validate stage, in batches of one as the node handles a message at a time
+        let chain_sync_event = match chain_sync_event {
+            Ok(event) => event,
+            Err(e) => return Err(self.reject(e)),
+        };
+        let validation_event = match self
+            .validate_header
+            .handle_chain_sync_batch(vec![chain_sync_event], &GlobalParameters::default())
+            .await
+            .pop()
+        {
+            Some(Ok(event)) => event,
+            Some(Err(e)) => return Err(self.reject(e)),
+            // there is one outcome per event
+            None => return Ok(vec![]),
+        };
+
+        // validate block stage
+        match (&validation_event, block) {
+            (DecodedChainSyncEvent::RollForward { point, .. }, Some(block)) => {
+                if let Err(e) = self.validate_block(point, block) {
+                    return Err(self.reject(e));
+                }
+            }
+            (
+                DecodedChainSyncEvent::Rollback {
+                    rollback_point,
+                    span,
+                    ..
+                },
+                _,
+            ) => {
+                // the ledger doesn't know of the point unless it validated its block
+                self.ledger
+                    .rollback_to(rollback_point.clone(), span.clone())
+                    .await;
+            }
+            (DecodedChainSyncEvent::RollForward { .. }, None) => (),
+        }
+
+        // store header stage
+        let store_event = match self.store_header.handle_event(validation_event).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                // the header is dropped, as if it had never been received
+                error!(node = %self.id, "failed to store header: {:?}", e);
+                return Err(SimulatorError::StorageError(format!("{:?}", e)));
+            }
+        };
+        if let DecodedChainSyncEvent::RollForward { header, .. } = &store_event {
+            self.journal.stored.set(Some(header.hash()));
+            if self.store_faults.trip(StoreFault::CrashAfterStoreHeader) {
+                return Err(SimulatorError::Crashed);
+            }
+        }
+
+        // chain selection stage
+        let events = match self.select_chain.handle_chain_sync(store_event).await {
+            Ok(events) => events,
+            Err(e @ ConsensusError::RollbackTooDeep { .. }) => return Err(self.reject(e)),
+            Err(e) => return Err(SimulatorError::ChainSelectionError(format!("{:?}", e))),
+        };
+        if let Some(tip) = events.last().map(|event| match event {
+            ValidateHeaderEvent::Validated { point, .. } => point,
+            ValidateHeaderEvent::Rollback { rollback_point, .. } => rollback_point,
+        }) {
+            *self.journal.tip.borrow_mut() = tip.clone();
+        }
+
+        Ok(self.announce(&events).await)
+    }
+
+    /// The chain selected by the node, from the first header after the start header.
+    async fn selected_chain(&self) -> Vec<Header> {
+        let store = self.store.lock().await;
+        let mut chain = vec![];
+        let mut next = match self.journal.tip() {
+            Point::Origin => None,
+            tip => Some(Hash::from(&tip)),
+        };
+        while let Some(header) = next.and_then(|hash| store.load_header(&hash)) {
+            next = header.parent();
+            chain.push(header);
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// The most recent ancestor of the header with the given hash that is on `chain`, or
+    /// `None` for the origin.
+    async fn intersection(&self, chain: &[Header], hash: Hash<32>) -> Option<Header> {
+        let store = self.store.lock().await;
+        let mut next = Some(hash);
+        while let Some(header) = next.and_then(|hash| store.load_header(&hash)) {
+            if chain
+                .iter()
+                .any(|on_chain| on_chain.hash() == header.hash())
+            {
+                return Some(header);
+            }
+            next = header.parent();
+        }
+        None
+    }
+
+    /// Serve the chain selected by the node to a downstream client, the way chain sync does:
+    /// the client finds an intersection with its own chain, then requests the next update of
+    /// its chain one at a time.
+    async fn serve(&mut self, msg: Envelope<ChainSyncMessage>) -> Vec<Envelope<ChainSyncMessage>> {
+        let chain = self.selected_chain().await;
+        let point = |header: Option<&Header>| -> (Slot, Bytes) {
+            match header {
+                Some(header) => (Slot::from(header.slot()), header.hash().to_vec().into()),
+                None => (Slot::from(0), Bytes { bytes: vec![] }),
+            }
+        };
+        let body = match msg.body {
+            ChainSyncMessage::FindIntersect { msg_id, points } => {
+                let found = points.iter().find_map(|(_, hash)| {
+                    if hash.bytes.is_empty() {
+                        return Some(None);
+                    }
+                    chain
+                        .iter()
+                        .find(|header| header.hash().to_vec() == hash.bytes)
+                        .map(Some)
+                });
+                match found {
+                    Some(header) => {
+                        self.cursors
+                            .insert(msg.src.clone(), header.map(|header| header.hash()));
+                        let (slot, hash) = point(header);
+                        ChainSyncMessage::IntersectFound {
+                            in_reply_to: msg_id,
+                            slot,
+                            hash,
+                        }
+                    }
+                    None => ChainSyncMessage::IntersectNotFound {
+                        in_reply_to: msg_id,
+                    },
+                }
+            }
+            ChainSyncMessage::RequestNext { msg_id } => {
+                let cursor = self.cursors.get(&msg.src).copied().flatten();
+                let position = cursor.map(|hash| chain.iter().position(|h| h.hash() == hash));
+                match (cursor, position) {
+                    // the node switched to a fork since the last update
+                    (Some(hash), Some(None)) => {
+                        let intersection = self.intersection(&chain, hash).await;
+                        self.cursors
+                            .insert(msg.src.clone(), intersection.as_ref().map(|h| h.hash()));
+                        let (slot, hash) = point(intersection.as_ref());
+                        ChainSyncMessage::RollBackward {
+                            in_reply_to: msg_id,
+                            slot,
+                            hash,
+                        }
+                    }
+                    _ => match chain.get(position.flatten().map_or(0, |at| at + 1)) {
+                        Some(header) => {
+                            self.cursors.insert(msg.src.clone(), Some(header.hash()));
+                            ChainSyncMessage::RollForward {
+                                in_reply_to: msg_id,
+                                slot: Slot::from(header.slot()),
+                                hash: header.hash().to_vec().into(),
+                                header: to_cbor(header).into(),
+                            }
+                        }
+                        None => ChainSyncMessage::AwaitReply {
+                            in_reply_to: msg_id,
+                        },
+                    },
+                }
+            }
+            _ => return vec![],
+        };
+        vec![Envelope {
+            src: self.id.clone(),
+            dest: msg.src,
+            body,
+        }]
+    }
+
+    /// Check that `block` is the one announced at `point`, and that it follows the ledger
+    /// rules, e.g. for scripts, fees and certificates.
+    fn validate_block(&mut self, point: &Point, block: Bytes) -> anyhow::Result<()> {
+        let header_hash = parse_block(&block.bytes)
+            .map(|parsed| Hasher::<256>::hash(parsed.header.raw_cbor()))
+            .map_err(|e| anyhow!("cannot decode block: {}", e))?;
+        if header_hash != Hash::from(point) {
+            return Err(anyhow!(
+                "block with header {} announced as {}",
+                header_hash,
+                point
+            ));
+        }
+        match self.ledger.roll_forward(point.clone(), block.bytes)? {
+            None => Ok(()),
+            Some(invalid) => Err(anyhow!("invalid block: {:?}", invalid)),
+        }
+    }
+
+    async fn announce(&self, events: &[ValidateHeaderEvent]) -> Vec<Envelope<ChainSyncMessage>> {
+        let mut msgs = vec![];
+        let s = self.store.lock().await;
+        for e in events {
+            let (point, header) = match e {
+                ValidateHeaderEvent::Validated { point, .. } => {
+                    let h: Hash<32> = point.into();
+                    let Some(hdr) = s.load_header(&h) else {
+                        error!(node = %self.id, "cannot load selected header {}", h);
+                        continue;
+                    };
+                    (point, Some(hdr))
+                }
+                ValidateHeaderEvent::Rollback { rollback_point, .. } => (rollback_point, None),
+            };
+            // each peer gets a message of its own, with its own identifier
+            for dest in &self.downstream {
+                let msg_id = self.next_msg_id();
+                let body = match &header {
+                    Some(header) => forward(point, header, msg_id),
+                    None => backward(point, msg_id),
+                };
+                msgs.push(Envelope {
+                    src: self.id.clone(),
+                    dest: dest.clone(),
+                    body,
+                });
+            }
+        }
+        msgs
+    }
+}
+
+/// The announcement of `header`, selected by a node at `point`.
+fn forward(point: &Point, header: &Header, msg_id: u64) -> ChainSyncMessage {
+    let h: Hash<32> = point.into();
+    ChainSyncMessage::Fwd {
+        msg_id,
+        slot: point.slot_or_default(),
+        hash: Bytes {
+            bytes: (*h).to_vec(),
+        },
+        header: Bytes {
+            bytes: to_cbor(header),
+        },
+        block: None,
+    }
+}
+
+/// The announcement of a node rolling its chain back to `point`.
+fn backward(point: &Point, msg_id: u64) -> ChainSyncMessage {
+    let h: Hash<32> = point.into();
+    ChainSyncMessage::Bck {
+        msg_id,
+        slot: point.slot_or_default(),
+        hash: Bytes {
+            bytes: (*h).to_vec(),
+        },
+    }
+}
+
+/// Nodes of the simulated world run their pipeline to completion for each delivered
+/// message.
+#[async_trait::async_trait(?Send)]
+impl NodeHandle<ChainSyncMessage> for Node {
+    async fn handle(
+        &mut self,
+        msg: Envelope<ChainSyncMessage>,
+    ) -> anyhow::Result<Vec<Envelope<ChainSyncMessage>>> {
+        Node::handle(self, msg).await
+    }
+
+    fn tick(&mut self, now: Instant) -> anyhow::Result<Vec<(Instant, Envelope<ChainSyncMessage>)>> {
+        self.detect_stalls(now)?;
+        Ok(Vec::new())
+    }
+
+    fn state(&self) -> Option<NodeState> {
+        let tip = self.journal.tip();
+        // the nonce of the epoch of the tip, as evolved by the node from the headers it
+        // validated; the store is only ever busy while the node handles a message
+        let epoch_nonce = self
+            .store
+            .try_lock()
+            .ok()
+            .and_then(|store| store.get_nonces(&Hash::from(&tip)))
+            .map(|nonces| nonces.active.to_string());
+        Some(serde_json::json!({
+            "tip": tip.to_string(),
+            "epoch_nonce": epoch_nonce,
+            "stored": self.journal.stored.get().map(|hash| hash.to_string()),
+            "rejected": self.rejected.get(),
+            "disconnected": self.disconnected,
+        }))
+    }
+
+    fn store_faults(&self) -> Option<&StoreFaults> {
+        Some(&self.store_faults)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backward, forward, Node};
+    use crate::{
+        echo::Envelope,
+        simulator::{
+            chain_properties::{replies_follow_requests, serves_selected_chain},
+            faulty_store::StoreFault,
+            forks::{self, ForkShape},
+            golden::to_jsonl,
+            ledger::{ConsensusContext, FakeStakeDistribution},
+            simulate::{Crashed, NodeHandle, Trace},
+            sync::ChainSyncMessage,
+            Args, SimulatorError,
+        },
+    };
+    use amaru_consensus::{
+        consensus::{reputation::Offence, store::ChainStore, watchdog::DEFAULT_STALL_TIMEOUT},
+        peer::Peer,
+    };
+    use amaru_kernel::{
+        from_cbor, protocol_parameters::GlobalParameters, to_cbor, Hash, Header, Point,
+    };
+    use amaru_ouroboros::IsHeader;
+    use clap::Parser;
+    use slot_arithmetic::Slot;
+    use std::{fs::File, path::Path, time::Instant};
+
+    fn fwd(header: &Header) -> Envelope<ChainSyncMessage> {
+        Envelope {
+            src: "c1".to_string(),
+            dest: "n1".to_string(),
+            body: ChainSyncMessage::Fwd {
+                msg_id: 0,
+                slot: Slot::from(header.slot()),
+                hash: header.hash().to_vec().into(),
+                header: to_cbor(header).into(),
+                block: None,
+            },
+        }
+    }
+
+    #[test]
+    fn node_crashing_after_storing_a_header_recovers_from_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let chain_dir = dir.path().join("chain.db");
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ]);
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        let chain = stake_distribution.generate_chain(None, 2, &context.nonce, &global_parameters);
+        let peers = ["c1".to_string()];
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let mut node = Node::new("n1", &args, &chain_dir, &peers, vec!["c1".to_string()]);
+        let journal = node.journal();
+        assert_eq!(
+            runtime.block_on(node.handle(fwd(&chain[0]))).unwrap().len(),
+            1
+        );
+        node.store_faults()
+            .inject(StoreFault::CrashAfterStoreHeader);
+        let crash = runtime.block_on(node.handle(fwd(&chain[1]))).unwrap_err();
+        assert!(crash.is::<Crashed>());
+        drop(node);
+
+        let tip = Point::Specific(chain[0].slot(), chain[0].hash().to_vec());
+        assert_eq!(journal.tip(), tip);
+        // a store that lost what the node relied on doesn't reconcile with the journal
+        assert!(Node::recover(
+            "n1",
+            &args,
+            &dir.path().join("empty.db"),
+            &peers,
+            vec!["c1".to_string()],
+            journal.clone(),
+        )
+        .is_err());
+
+        let mut node = Node::recover(
+            "n1",
+            &args,
+            &chain_dir,
+            &peers,
+            vec!["c1".to_string()],
+            journal,
+        )
+        .unwrap();
+        assert!(node
+            .store
+            .blocking_lock()
+            .load_header(&chain[1].hash())
+            .is_some());
+        // chain selection resumes from the tip, so the header is selected once received again
+        let announced = runtime.block_on(node.handle(fwd(&chain[1]))).unwrap();
+        assert!(matches!(
+            &announced[..],
+            [Envelope { body: ChainSyncMessage::Fwd { hash, .. }, .. }]
+                if hash.bytes == chain[1].hash().to_vec()
+        ));
+    }
+
+    #[test]
+    fn node_resumes_chain_selection_of_its_peers_after_a_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let chain_dir = dir.path().join("chain.db");
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ]);
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        let scenario = ForkShape {
+            common_length: 0,
+            forks: vec![(0, 2), (0, 3)],
+        }
+        .forge(&stake_distribution, &context.nonce, &global_parameters);
+        let peers = ["p1".to_string(), "p2".to_string()];
+        let from = |peer: &str, header: &Header| Envelope {
+            src: peer.to_string(),
+            dest: "n1".to_string(),
+            body: forks::fwd(header),
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let mut node = Node::new("n1", &args, &chain_dir, &peers, vec!["c1".to_string()]);
+        let journal = node.journal();
+        runtime
+            .block_on(node.handle(from("p1", &scenario.chains[0][0])))
+            .unwrap();
+        runtime
+            .block_on(node.handle(from("p2", &scenario.chains[1][0])))
+            .unwrap();
+        // the state of chain selection is persisted along with the new tip
+        runtime
+            .block_on(node.handle(from("p1", &scenario.chains[0][1])))
+            .unwrap();
+        node.store_faults()
+            .inject(StoreFault::CrashAfterStoreHeader);
+        let crash = runtime
+            .block_on(node.handle(from("p2", &scenario.chains[1][1])))
+            .unwrap_err();
+        assert!(crash.is::<Crashed>());
+        drop(node);
+
+        let mut node = Node::recover(
+            "n1",
+            &args,
+            &chain_dir,
+            &peers,
+            vec!["c1".to_string()],
+            journal,
+        )
+        .unwrap();
+        // the chain of p2 as of the last tip is restored, so that it takes over once longer
+        // than that of p1
+        runtime
+            .block_on(node.handle(from("p2", &scenario.chains[1][1])))
+            .unwrap();
+        let announced = runtime
+            .block_on(node.handle(from("p2", &scenario.chains[1][2])))
+            .unwrap();
+        assert!(matches!(
+            announced.last(),
+            Some(Envelope { body: ChainSyncMessage::Fwd { hash, .. }, .. })
+                if hash.bytes == scenario.chains[1][2].hash().to_vec()
+        ));
+    }
+
+    #[test]
+    fn node_rejects_blocks_that_are_not_the_announced_ones() {
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ]);
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        let chain = stake_distribution.generate_chain(None, 1, &context.nonce, &global_parameters);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut node = Node::new(
+            "n1",
+            &args,
+            Path::new("unused"),
+            &["c1".to_string()],
+            vec!["c1".to_string()],
+        );
+
+        let mut with_block = fwd(&chain[0]);
+        if let ChainSyncMessage::Fwd { block, .. } = &mut with_block.body {
+            *block = Some(vec![0x82, 0x07, 0x80].into());
+        }
+
+        assert!(runtime
+            .block_on(node.handle(with_block))
+            .unwrap()
+            .is_empty());
+        assert_eq!(node.rejected(), 1);
+        assert_eq!(
+            runtime.block_on(node.handle(fwd(&chain[0]))).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn node_tells_recoverable_failures_from_fatal_ones() {
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ]);
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        let chain = stake_distribution.generate_chain(None, 2, &context.nonce, &global_parameters);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut node = Node::new(
+            "n1",
+            &args,
+            Path::new("unused"),
+            &["c1".to_string()],
+            vec!["c1".to_string()],
+        );
+
+        let mut truncated = fwd(&chain[0]);
+        if let ChainSyncMessage::Fwd { header, .. } = &mut truncated.body {
+            header.bytes.truncate(10);
+        }
+        let invalid = runtime.block_on(node.process(truncated)).unwrap_err();
+        assert!(matches!(invalid, SimulatorError::InvalidMessage(_)));
+        assert!(!invalid.is_fatal());
+
+        node.store_faults().inject(StoreFault::StoreHeader);
+        let unstored = runtime.block_on(node.process(fwd(&chain[0]))).unwrap_err();
+        assert!(matches!(unstored, SimulatorError::StorageError(_)));
+        assert!(!unstored.is_fatal());
+
+        assert_eq!(
+            runtime
+                .block_on(node.process(fwd(&chain[0])))
+                .unwrap()
+                .len(),
+            1
+        );
+        node.store_faults()
+            .inject(StoreFault::CrashAfterStoreHeader);
+        let crashed = runtime.block_on(node.process(fwd(&chain[1]))).unwrap_err();
+        assert!(matches!(crashed, SimulatorError::Crashed));
+        assert!(crashed.is_fatal());
+    }
+
+    #[test]
+    fn node_disconnects_banned_peers() {
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ]);
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        let chain = stake_distribution.generate_chain(None, 2, &context.nonce, &global_parameters);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut node = Node::new(
+            "n1",
+            &args,
+            Path::new("unused"),
+            &["c1".to_string()],
+            vec!["c1".to_string()],
+        );
+
+        for _ in 0..6 {
+            node.reputation()
+                .blocking_lock()
+                .record(&Peer::new("c1"), Offence::InvalidHeader);
+        }
+        assert!(runtime.block_on(node.process(fwd(&chain[0]))).is_err());
+        assert_eq!(
+            node.state().unwrap()["disconnected"],
+            serde_json::json!(["c1"])
+        );
+
+        // messages of the peer are dropped from now on, not even decoded
+        let mut truncated = fwd(&chain[1]);
+        if let ChainSyncMessage::Fwd { header, .. } = &mut truncated.body {
+            header.bytes.truncate(10);
+        }
+        let dropped = runtime.block_on(node.process(truncated)).unwrap_err();
+        assert!(
+            matches!(dropped, SimulatorError::InvalidMessage(reason) if reason.contains("BannedPeer"))
+        );
+        assert_eq!(node.rejected(), 2);
+    }
+
+    #[test]
+    fn node_lowers_the_score_of_stalled_peers() {
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ]);
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        let chain = stake_distribution.generate_chain(None, 1, &context.nonce, &global_parameters);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut node = Node::new(
+            "n1",
+            &args,
+            Path::new("unused"),
+            &["c1".to_string()],
+            vec!["c1".to_string()],
+        );
+        let c1 = Peer::new("c1");
+        let start = Instant::now();
+
+        node.tick(start).unwrap();
+        runtime.block_on(node.process(fwd(&chain[0]))).unwrap();
+        node.tick(start + DEFAULT_STALL_TIMEOUT / 2).unwrap();
+        assert_eq!(node.reputation().blocking_lock().score(&c1), 0);
+
+        node.tick(start + DEFAULT_STALL_TIMEOUT * 2).unwrap();
+        assert_eq!(node.reputation().blocking_lock().score(&c1), -5);
+        // a stalled peer is only penalised once, until it makes progress again
+        node.tick(start + DEFAULT_STALL_TIMEOUT * 10).unwrap();
+        assert_eq!(node.reputation().blocking_lock().score(&c1), -5);
+    }
+
+    #[test]
+    fn node_serves_its_selected_chain_to_downstream_clients() {
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ]);
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        let chain = stake_distribution.generate_chain(None, 3, &context.nonce, &global_parameters);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut node = Node::new(
+            "n1",
+            &args,
+            Path::new("unused"),
+            &["c1".to_string()],
+            vec!["c1".to_string()],
+        );
+        let mut trace = Trace(vec![]);
+        let mut send = |node: &mut Node, msg: Envelope<ChainSyncMessage>| {
+            trace.0.push(msg.clone());
+            let outputs = runtime.block_on(node.handle(msg)).unwrap();
+            trace.0.extend(outputs.clone());
+            outputs
+        };
+        let from_client = |body| Envelope {
+            src: "d1".to_string(),
+            dest: "n1".to_string(),
+            body,
+        };
+        let request_next = || from_client(ChainSyncMessage::RequestNext { msg_id: 0 });
+
+        send(&mut node, fwd(&chain[0]));
+        send(&mut node, fwd(&chain[1]));
+        let unknown = send(
+            &mut node,
+            from_client(ChainSyncMessage::FindIntersect {
+                msg_id: 1,
+                points: vec![(Slot::from(chain[2].slot()), chain[2].hash().to_vec().into())],
+            }),
+        );
+        assert!(matches!(
+            &unknown[..],
+            [Envelope {
+                body: ChainSyncMessage::IntersectNotFound { in_reply_to: 1 },
+                ..
+            }]
+        ));
+        let origin = send(
+            &mut node,
+            from_client(ChainSyncMessage::FindIntersect {
+                msg_id: 2,
+                points: vec![(Slot::from(0), vec![].into())],
+            }),
+        );
+        assert!(matches!(
+            &origin[..],
+            [Envelope { body: ChainSyncMessage::IntersectFound { hash, .. }, .. }] if hash.bytes.is_empty()
+        ));
+
+        for header in &chain[..2] {
+            let next = send(&mut node, request_next());
+            assert!(matches!(
+                &next[..],
+                [Envelope { body: ChainSyncMessage::RollForward { hash, .. }, dest, .. }]
+                    if hash.bytes == header.hash().to_vec() && dest == "d1"
+            ));
+        }
+        let caught_up = send(&mut node, request_next());
+        assert!(matches!(
+            &caught_up[..],
+            [Envelope {
+                body: ChainSyncMessage::AwaitReply { .. },
+                ..
+            }]
+        ));
+        send(&mut node, fwd(&chain[2]));
+        send(&mut node, request_next());
+        send(&mut node, request_next());
+
+        assert_eq!(serves_selected_chain(&trace, "n1", "d1"), Ok(()));
+        assert_eq!(replies_follow_requests(&trace, &["n1"]), Ok(()));
+    }
+
+    #[test]
+    fn node_gives_increasing_ids_to_its_messages_across_restarts() {
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ]);
+        let global_parameters = GlobalParameters::default();
+        let stake_distribution =
+            FakeStakeDistribution::from_file(&args.stake_distribution_file, &global_parameters)
+                .unwrap();
+        let context: ConsensusContext =
+            serde_json::from_reader(File::open(&args.consensus_context_file).unwrap()).unwrap();
+        let chain = stake_distribution.generate_chain(None, 2, &context.nonce, &global_parameters);
+        let peers = ["c1".to_string()];
+        let downstream = vec!["c1".to_string(), "c2".to_string()];
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let msg_ids = |node: &mut Node, header: &Header| {
+            runtime
+                .block_on(node.handle(fwd(header)))
+                .unwrap()
+                .iter()
+                .map(|msg| (msg.dest.clone(), msg.body.msg_id()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut node = Node::new("n1", &args, Path::new("unused"), &peers, downstream.clone());
+        assert_eq!(
+            msg_ids(&mut node, &chain[0]),
+            vec![("c1".to_string(), Some(1)), ("c2".to_string(), Some(2))]
+        );
+        let journal = node.journal();
+        drop(node);
+
+        // the node starts over from the origin, but not its identifiers
+        let mut node = Node::recover(
+            "n1",
+            &args,
+            Path::new("unused"),
+            &peers,
+            downstream,
+            journal,
+        )
+        .unwrap();
+        assert_eq!(
+            msg_ids(&mut node, &chain[0]),
+            vec![("c1".to_string(), Some(3)), ("c2".to_string(), Some(4))]
+        );
+    }
+
+    /// A header announced by the upstream peer of an actual node, at slot 31.
+    const HEADER: &str = "828a01181ff6582022ff37595005fa65ad731d4fb112de050aa0ca910d9a3110f56f3879d449f88e5820da90997ba81483b3f2b4de751ba3ece6ba6d50f96598eb0940cc7d29452cdcb9825840d350e19abe11a25b28d4d1a846faa8f7792b6dc4a19679780dcdd3d98baf4e9738d82764c59b1c76f80b5ddbff2e145aa26f1652ab83f1f930fc430d1be960305850b444cc452b3fbc01a267ff526ea8a66cb57202303b4b1c22557cad8c1d8afbb47a34e55c5e0bee497f58c812e8b2fed95b40cb1f966ad77445ef573f289910debf5dcb4924cecce47d181f325b4d21040058200a9aa01fbdfe2cff3e49a3c02c2610691966075092f76bd26f5bddf85489ffd3845820499fc5dada1544be26d7cd3b2851fe955b44e560b50901abc71333d5d449eac9182e005840595a9a329b2637b8f2cb501aa793a159acc928a27c01e1ef586508492a68cabeae6ced714421be1f648bb05c7196f38e7aa4a8f616ad46e32c84e67951657a038200005901c0c00b0daf83dfc61a23fa9f6e4db5ad31428a7e98839aba420dbdbdc5ad90b72185cb03b5373b73fcd9c0128b3afcc7d14e5b51f4d5592d55a5d222314b590101472338fcc8b178f0ba10f8683725c5df444b7fc6a5afc3c7fbab82e00e7df2d247c673d066eacc1dc7860c10b134c413d71c5a073a5f3e66a17f0d25dabae2699d7b4a969e129d627cd7839995ddf40a3e6672b6d03936de782f5e0dc31bfafdccc5d5d2e5e9a5b3414bface59a824e3a574250474d633115af821b63232de753fddb638606b93b853144dd75692b02e73b2ef8621eb1bfa307cfda8acaa2c43f8b673c9ed749e472cdf2fced20c063a2507ccb985d2b5a9bc77699f42379fb349e1e3a1ab86d1bd510c6ee89720f860d55c208dd262f49746d8fb2a7817d038262a6b266f98f427fc8b958f11adb8c84cc96444b1f5f9d994a4fd1adae2f2be87d8c1dbc0206ace7871da50cc92476d3fced6a4fc2809c8bb47dff992b06259c21f78cd6e72b49b8fe101065aaf243003af67a11d5aabcebf1059b3c92493f954507573a01bf92047ef68f4e980df914b360307b78b3371138b4c1504e155f704df9fabf1bc87ac47b3d5cd563cee6017380e4df6529face9cf39b36277068e";
+
+    #[test]
+    fn announcements_keep_their_encoding() {
+        let header: Header = from_cbor(&hex::decode(HEADER).unwrap()).unwrap();
+        let point = header.point();
+        let outputs: Vec<Envelope<ChainSyncMessage>> = [
+            forward(&point, &header, 0),
+            backward(&point, 0),
+            backward(&Point::Origin, 0),
+        ]
+        .into_iter()
+        .map(|body| Envelope {
+            src: "n1".to_string(),
+            dest: "c1".to_string(),
+            body,
+        })
+        .collect();
+
+        insta::assert_snapshot!("announcements", to_jsonl(&outputs).unwrap());
+    }
+
+    #[test]
+    fn in_memory_node_leaves_nothing_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let chain_dir = dir.path().join("chain.db");
+        let args = Args::parse_from([
+            "amaru-sim",
+            "--in-memory",
+            "--stake-distribution-file",
+            "tests/data/stake-distribution.json",
+            "--consensus-context-file",
+            "tests/data/consensus-context.json",
+        ]);
+
+        let node = Node::new(
+            "n1",
+            &args,
+            &chain_dir,
+            &["c1".to_string()],
+            vec!["c1".to_string()],
+        );
+
+        assert!(!chain_dir.exists());
+        assert!(node
+            .store
+            .blocking_lock()
+            .get_nonces(&Hash::from([0; 32]))
+            .is_some());
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    bytes::Bytes,
+    sync::{ChainSyncMessage, MessageReader, ReaderError},
+};
+use crate::echo::Envelope;
+use amaru::stages::capture::CapturedEvent;
+use slot_arithmetic::Slot;
+use std::{collections::VecDeque, path::Path};
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum ReplayError {
+    IOError(std::io::Error),
+    /// A line of the capture, counting from 1, is not a valid event.
+    InvalidEvent(usize, String),
+}
+
+/// A [`MessageReader`] replaying a capture recorded by a node syncing from real peers.
+///
+/// The replay starts with an `init` message telling the node to follow all the peers found
+/// in the capture, then delivers the recorded events, in order, as chain sync messages sent
+/// by these peers.
+pub struct ReplayMessageReader {
+    messages: VecDeque<Envelope<ChainSyncMessage>>,
+}
+
+impl ReplayMessageReader {
+    pub fn from_file(capture_file: &Path, node_id: &str) -> Result<Self, ReplayError> {
+        let capture = std::fs::read_to_string(capture_file).map_err(ReplayError::IOError)?;
+        let events = capture
+            .lines()
+            .enumerate()
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .map_err(|e| ReplayError::InvalidEvent(index + 1, e.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(events, node_id)
+    }
+
+    pub fn new(events: Vec<CapturedEvent>, node_id: &str) -> Result<Self, ReplayError> {
+        let mut peers: Vec<String> = Vec::new();
+        let mut messages = VecDeque::with_capacity(events.len() + 1);
+        for (index, event) in events.into_iter().enumerate() {
+            let msg_id = index as u64 + 1;
+            let (peer, body) =
+                to_message(msg_id, event).map_err(|e| ReplayError::InvalidEvent(index + 1, e))?;
+            if !peers.contains(&peer) {
+                peers.push(peer.clone());
+            }
+            messages.push_back(Envelope {
+                src: peer,
+                dest: node_id.to_string(),
+                body,
+            });
+        }
+
+        messages.push_front(Envelope {
+            src: "replay".to_string(),
+            dest: node_id.to_string(),
+            body: ChainSyncMessage::Init {
+                msg_id: 0,
+                node_id: node_id.to_string(),
+                node_ids: peers,
+                client_ids: vec![],
+                upstream: Default::default(),
+            },
+        });
+
+        Ok(Self { messages })
+    }
+}
+
+fn decode_hex(field: &str, value: &str) -> Result<Bytes, String> {
+    hex::decode(value)
+        .map(Bytes::from)
+        .map_err(|e| format!("invalid {}: {}", field, e))
+}
+
+fn to_message(msg_id: u64, event: CapturedEvent) -> Result<(String, ChainSyncMessage), String> {
+    match event {
+        CapturedEvent::RollForward {
+            peer,
+            slot,
+            hash,
+            header,
+        } => Ok((
+            peer,
+            ChainSyncMessage::Fwd {
+                msg_id,
+                slot: Slot::from(slot),
+                hash: decode_hex("hash", &hash)?,
+                header: decode_hex("header", &header)?,
+                block: None,
+            },
+        )),
+        CapturedEvent::Rollback { peer, slot, hash } => Ok((
+            peer,
+            ChainSyncMessage::Bck {
+                msg_id,
+                slot: Slot::from(slot),
+                hash: decode_hex("hash", &hash)?,
+            },
+        )),
+    }
+}
+
+impl MessageReader for ReplayMessageReader {
+    async fn read(&mut self) -> Result<Envelope<ChainSyncMessage>, ReaderError> {
+        self.messages.pop_front().ok_or(ReaderError::EndOfFile)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ReplayError, ReplayMessageReader};
+    use crate::simulator::sync::{read_init, ChainSyncMessage, MessageReader, ReaderError};
+    use amaru::stages::capture::CapturedEvent;
+    use slot_arithmetic::Slot;
+
+    fn forward(peer: &str, slot: u64) -> CapturedEvent {
+        CapturedEvent::RollForward {
+            peer: peer.to_string(),
+            slot,
+            hash: "ab".repeat(32),
+            header: "8201".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_captured_events_from_recorded_peers() {
+        let events = vec![
+            forward("alice", 1),
+            forward("bob", 1),
+            CapturedEvent::Rollback {
+                peer: "alice".to_string(),
+                slot: 0,
+                hash: String::new(),
+            },
+        ];
+        let mut reader = ReplayMessageReader::new(events, "n1").unwrap();
+
+        let (node, _) = read_init(&mut reader).await.unwrap();
+        assert_eq!(node.node_ids, vec!["alice".to_string(), "bob".to_string()]);
+
+        let first = reader.read().await.unwrap();
+        assert_eq!((first.src.as_str(), first.dest.as_str()), ("alice", "n1"));
+        assert!(matches!(
+            first.body,
+            ChainSyncMessage::Fwd { msg_id: 1, slot, .. } if slot == Slot::from(1)
+        ));
+        assert_eq!(reader.read().await.unwrap().src, "bob");
+        assert!(matches!(
+            reader.read().await.unwrap().body,
+            ChainSyncMessage::Bck { msg_id: 3, .. }
+        ));
+        assert_eq!(reader.read().await, Err(ReaderError::EndOfFile));
+    }
+
+    #[test]
+    fn rejects_events_with_invalid_hex() {
+        let events = vec![
+            forward("alice", 1),
+            CapturedEvent::Rollback {
+                peer: "alice".to_string(),
+                slot: 0,
+                hash: "not hex".to_string(),
+            },
+        ];
+
+        assert!(matches!(
+            ReplayMessageReader::new(events, "n1"),
+            Err(ReplayError::InvalidEvent(2, _))
+        ));
+    }
+}
@@ -0,0 +1,313 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scripted faults, read from a file, turning known-tricky situations into deterministic
+//! regression tests.
+//!
+//! A scenario is a JSON object listing faults, at times given in milliseconds since the
+//! start of the simulation, and which messages between nodes get lost:
+//!
+//! ```json
+//! {
+//!   "faults": [
+//!     { "action": "partition", "at_ms": 5000, "for_ms": 10000, "nodes": ["n1", "n2"] },
+//!     { "action": "crash", "at_ms": 20000, "node": "n3" }
+//!   ],
+//!   "message_loss": [{ "type": "fwd", "ratio": 0.1 }]
+//! }
+//! ```
+//!
+//! Besides `partition` and `crash`, faults can be a `heal` of two nodes, a `restart`, a
+//! `fail_store` of some `operation` or a `skew_clock` of a node by `skew_ms` milliseconds.
+
+use super::{
+    faulty_store::StoreFault,
+    simulate::{NemesisAction, World},
+};
+use crate::echo::Envelope;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum ScenarioError {
+    IOError(std::io::Error),
+    InvalidScenario(serde_json::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ScriptedFault {
+    /// Partition the two nodes for `for_ms` milliseconds, or until the end of the simulation.
+    Partition {
+        at_ms: u64,
+        for_ms: Option<u64>,
+        nodes: (String, String),
+    },
+    Heal {
+        at_ms: u64,
+        nodes: (String, String),
+    },
+    Crash {
+        at_ms: u64,
+        node: String,
+    },
+    Restart {
+        at_ms: u64,
+        node: String,
+    },
+    FailStore {
+        at_ms: u64,
+        node: String,
+        operation: StoreFault,
+    },
+    /// Set the clock of the node `skew_ms` milliseconds ahead of the simulation's.
+    SkewClock {
+        at_ms: u64,
+        node: String,
+        skew_ms: u64,
+    },
+}
+
+/// Drop the given ratio of the messages of some type, e.g. `fwd`, exchanged by nodes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptedLoss {
+    #[serde(rename = "type")]
+    pub message_type: String,
+    pub ratio: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub faults: Vec<ScriptedFault>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub message_loss: Vec<ScriptedLoss>,
+}
+
+impl Scenario {
+    pub fn from_file(scenario_file: &Path) -> Result<Self, ScenarioError> {
+        let scenario = std::fs::read_to_string(scenario_file).map_err(ScenarioError::IOError)?;
+        serde_json::from_str(&scenario).map_err(ScenarioError::InvalidScenario)
+    }
+
+    /// The scenario inflicting the given nemesis actions, at the given times since its start.
+    pub fn from_actions(actions: &[(Duration, NemesisAction)]) -> Self {
+        let faults = actions
+            .iter()
+            .map(|(at, action)| {
+                let at_ms = at.as_millis() as u64;
+                match action.clone() {
+                    NemesisAction::Crash(node) => ScriptedFault::Crash { at_ms, node },
+                    NemesisAction::Restart(node) => ScriptedFault::Restart { at_ms, node },
+                    NemesisAction::FailStore(node, operation) => ScriptedFault::FailStore {
+                        at_ms,
+                        node,
+                        operation,
+                    },
+                    NemesisAction::Partition(a, b) => ScriptedFault::Partition {
+                        at_ms,
+                        for_ms: None,
+                        nodes: (a, b),
+                    },
+                    NemesisAction::Heal(a, b) => ScriptedFault::Heal {
+                        at_ms,
+                        nodes: (a, b),
+                    },
+                    NemesisAction::SkewClock(node, skew) => ScriptedFault::SkewClock {
+                        at_ms,
+                        node,
+                        skew_ms: skew.as_millis() as u64,
+                    },
+                }
+            })
+            .collect();
+        Scenario {
+            faults,
+            message_loss: Vec::new(),
+        }
+    }
+
+    /// The nemesis actions of the scenario, at the given times since its start.
+    pub fn actions(&self) -> Vec<(Duration, NemesisAction)> {
+        let mut actions = Vec::new();
+        for fault in &self.faults {
+            match fault {
+                ScriptedFault::Partition {
+                    at_ms,
+                    for_ms,
+                    nodes: (a, b),
+                } => {
+                    actions.push((
+                        Duration::from_millis(*at_ms),
+                        NemesisAction::Partition(a.clone(), b.clone()),
+                    ));
+                    if let Some(for_ms) = for_ms {
+                        actions.push((
+                            Duration::from_millis(at_ms + for_ms),
+                            NemesisAction::Heal(a.clone(), b.clone()),
+                        ));
+                    }
+                }
+                ScriptedFault::Heal {
+                    at_ms,
+                    nodes: (a, b),
+                } => actions.push((
+                    Duration::from_millis(*at_ms),
+                    NemesisAction::Heal(a.clone(), b.clone()),
+                )),
+                ScriptedFault::Crash { at_ms, node } => actions.push((
+                    Duration::from_millis(*at_ms),
+                    NemesisAction::Crash(node.clone()),
+                )),
+                ScriptedFault::Restart { at_ms, node } => actions.push((
+                    Duration::from_millis(*at_ms),
+                    NemesisAction::Restart(node.clone()),
+                )),
+                ScriptedFault::FailStore {
+                    at_ms,
+                    node,
+                    operation,
+                } => actions.push((
+                    Duration::from_millis(*at_ms),
+                    NemesisAction::FailStore(node.clone(), *operation),
+                )),
+                ScriptedFault::SkewClock {
+                    at_ms,
+                    node,
+                    skew_ms,
+                } => actions.push((
+                    Duration::from_millis(*at_ms),
+                    NemesisAction::SkewClock(node.clone(), Duration::from_millis(*skew_ms)),
+                )),
+            }
+        }
+        actions
+    }
+
+    /// Schedule the faults of the scenario in the world, relatively to `start`, and make it
+    /// lose messages accordingly.
+    pub fn apply<Msg>(&self, mut world: World<Msg>, start: Instant) -> World<Msg>
+    where
+        Msg: Clone + PartialEq + Debug + Serialize + 'static,
+    {
+        for (at, action) in self.actions() {
+            world.schedule(start + at, action);
+        }
+        for loss in &self.message_loss {
+            let message_type = loss.message_type.clone();
+            world = world.with_message_loss(loss.ratio, move |envelope| {
+                type_of(envelope).as_deref() == Some(message_type.as_str())
+            });
+        }
+        world
+    }
+}
+
+/// The `type` tag of a message, as found in its JSON encoding.
+fn type_of<Msg: Serialize>(envelope: &Envelope<Msg>) -> Option<String> {
+    let body = serde_json::to_value(&envelope.body).ok()?;
+    body.get("type")?.as_str().map(str::to_string)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Scenario, ScriptedFault, ScriptedLoss};
+    use crate::simulator::{faulty_store::StoreFault, simulate::NemesisAction};
+    use std::time::Duration;
+
+    #[test]
+    fn can_read_scripted_faults() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{
+                "faults": [
+                    { "action": "partition", "at_ms": 5000, "for_ms": 10000, "nodes": ["n1", "n2"] },
+                    { "action": "crash", "at_ms": 20000, "node": "n3" },
+                    { "action": "fail_store", "at_ms": 25000, "node": "n1", "operation": "store_header" }
+                ],
+                "message_loss": [{ "type": "fwd", "ratio": 0.1 }]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            scenario.faults[2],
+            ScriptedFault::FailStore {
+                at_ms: 25000,
+                node: "n1".to_string(),
+                operation: StoreFault::StoreHeader,
+            }
+        );
+        assert_eq!(
+            scenario.message_loss,
+            vec![ScriptedLoss {
+                message_type: "fwd".to_string(),
+                ratio: 0.1,
+            }]
+        );
+        assert_eq!(
+            scenario.actions()[..3],
+            [
+                (
+                    Duration::from_secs(5),
+                    NemesisAction::Partition("n1".to_string(), "n2".to_string())
+                ),
+                (
+                    Duration::from_secs(15),
+                    NemesisAction::Heal("n1".to_string(), "n2".to_string())
+                ),
+                (
+                    Duration::from_secs(20),
+                    NemesisAction::Crash("n3".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn scenarios_round_trip_through_nemesis_actions() {
+        let actions = vec![
+            (
+                Duration::from_millis(10),
+                NemesisAction::Partition("n1".to_string(), "n2".to_string()),
+            ),
+            (
+                Duration::from_millis(20),
+                NemesisAction::SkewClock("n2".to_string(), Duration::from_millis(500)),
+            ),
+            (
+                Duration::from_millis(30),
+                NemesisAction::Heal("n1".to_string(), "n2".to_string()),
+            ),
+            (
+                Duration::from_millis(40),
+                NemesisAction::FailStore("n1".to_string(), StoreFault::LoadHeader),
+            ),
+        ];
+
+        let scenario = Scenario::from_actions(&actions);
+        let encoded = serde_json::to_string(&scenario).unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<Scenario>(&encoded)
+                .unwrap()
+                .actions(),
+            actions
+        );
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The JSON Schema of the messages exchanged with the harness, published as
+//! `schema/envelope.json`, and a validator pointing at the first field of a message breaking
+//! it, rather than at the line and column serde chokes on.
+//!
+//! The validator only knows of the keywords the schema uses: `$ref` to its own `$defs`,
+//! `type`, `const`, `minimum`, `required`, `properties`, `additionalProperties`,
+//! `prefixItems`, `items`, `minItems` and `oneOf`. Hex-encoded bytes are checked through their
+//! `contentEncoding` of `base16`, in place of the equivalent `pattern`.
+
+use serde_json::{Map, Value};
+use std::{fmt, sync::OnceLock};
+
+/// The JSON Schema of an `Envelope<ChainSyncMessage>`.
+pub const SCHEMA: &str = include_str!("../../schema/envelope.json");
+
+/// The first part of a message breaking the schema, and how.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// A JSON pointer to the offending value, empty for the whole message.
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "<message>"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: {}", path, self.reason)
+    }
+}
+
+/// Check a JSON-decoded envelope against the [`SCHEMA`].
+pub fn validate(value: &Value) -> Result<(), Violation> {
+    static ROOT: OnceLock<Value> = OnceLock::new();
+    let root = ROOT.get_or_init(|| serde_json::from_str(SCHEMA).expect("invalid JSON Schema"));
+    Validator { root }.check(root, value, "")
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+}
+
+fn violation(path: &str, reason: impl Into<String>) -> Violation {
+    Violation {
+        path: path.to_string(),
+        reason: reason.into(),
+    }
+}
+
+fn child(path: &str, key: &str) -> String {
+    format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_u64() || n.is_i64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(ty: &str, value: &Value) -> bool {
+    ty == kind(value) || (ty == "number" && value.is_number())
+}
+
+impl Validator<'_> {
+    fn resolve(&self, reference: &str) -> &Value {
+        reference
+            .strip_prefix('#')
+            .and_then(|pointer| self.root.pointer(pointer))
+            .unwrap_or_else(|| panic!("unknown reference in JSON Schema: {}", reference))
+    }
+
+    fn check(&self, schema: &Value, value: &Value, path: &str) -> Result<(), Violation> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok(()),
+            Value::Bool(false) => return Err(violation(path, "unexpected value")),
+            Value::Object(schema) => schema,
+            _ => panic!("invalid JSON Schema at {}", path),
+        };
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            self.check(self.resolve(reference), value, path)?;
+        }
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                return Err(violation(
+                    path,
+                    format!("expected {}, found {}", expected, value),
+                ));
+            }
+        }
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                ty => ty.as_str().into_iter().collect(),
+            };
+            if !types.iter().any(|ty| has_type(ty, value)) {
+                return Err(violation(
+                    path,
+                    format!("expected {}, found {}", types.join(" or "), kind(value)),
+                ));
+            }
+        }
+        if let (Some(minimum), Some(n)) = (
+            schema.get("minimum").and_then(Value::as_f64),
+            value.as_f64(),
+        ) {
+            if n < minimum {
+                return Err(violation(
+                    path,
+                    format!("expected at least {}, found {}", minimum, n),
+                ));
+            }
+        }
+        if let (Some("base16"), Some(s)) = (
+            schema.get("contentEncoding").and_then(Value::as_str),
+            value.as_str(),
+        ) {
+            if hex::decode(s).is_err() {
+                return Err(violation(path, "expected hex-encoded bytes"));
+            }
+        }
+        if let Value::Object(fields) = value {
+            self.check_object(schema, fields, path)?;
+        }
+        if let Value::Array(items) = value {
+            self.check_array(schema, items, path)?;
+        }
+        if let Some(Value::Array(alternatives)) = schema.get("oneOf") {
+            self.check_one_of(alternatives, value, path)?;
+        }
+        Ok(())
+    }
+
+    fn check_object(
+        &self,
+        schema: &Map<String, Value>,
+        fields: &Map<String, Value>,
+        path: &str,
+    ) -> Result<(), Violation> {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for name in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !fields.contains_key(name) {
+                return Err(violation(&child(path, name), "missing field"));
+            }
+        }
+        for (name, field) in fields {
+            match properties.and_then(|properties| properties.get(name)) {
+                Some(property) => self.check(property, field, &child(path, name))?,
+                None => {
+                    if let Some(additional) = schema.get("additionalProperties") {
+                        self.check(additional, field, &child(path, name))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_array(
+        &self,
+        schema: &Map<String, Value>,
+        items: &[Value],
+        path: &str,
+    ) -> Result<(), Violation> {
+        if let Some(min_items) = schema.get("minItems").and_then(Value::as_u64) {
+            if (items.len() as u64) < min_items {
+                return Err(violation(
+                    path,
+                    format!(
+                        "expected at least {} items, found {}",
+                        min_items,
+                        items.len()
+                    ),
+                ));
+            }
+        }
+        let prefix = schema
+            .get("prefixItems")
+            .and_then(Value::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        for (index, item) in items.iter().enumerate() {
+            let item_schema = match prefix.get(index) {
+                Some(item_schema) => item_schema,
+                None => match schema.get("items") {
+                    Some(item_schema) => item_schema,
+                    None => continue,
+                },
+            };
+            self.check(item_schema, item, &child(path, &index.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Messages are told apart by their `type`, so when all the alternatives have a constant
+    /// one, only the alternative of the message's type is checked, and its violation reported.
+    fn check_one_of(
+        &self,
+        alternatives: &[Value],
+        value: &Value,
+        path: &str,
+    ) -> Result<(), Violation> {
+        let tags: Option<Vec<&Value>> = alternatives
+            .iter()
+            .map(|alternative| alternative.pointer("/properties/type/const"))
+            .collect();
+        if let (Some(tags), Value::Object(fields)) = (tags, value) {
+            let tag_path = child(path, "type");
+            let tag = fields
+                .get("type")
+                .ok_or_else(|| violation(&tag_path, "missing field"))?;
+            return match tags.iter().position(|known| *known == tag) {
+                Some(index) => self.check(&alternatives[index], value, path),
+                None => Err(violation(
+                    &tag_path,
+                    format!("unknown message type {}", tag),
+                )),
+            };
+        }
+        let matching = alternatives
+            .iter()
+            .filter(|alternative| self.check(alternative, value, path).is_ok())
+            .count();
+        match matching {
+            1 => Ok(()),
+            0 => Err(violation(path, "matches none of the alternatives")),
+            _ => Err(violation(path, "matches several alternatives")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fwd(body: Value) -> Value {
+        json!({ "src": "c1", "dest": "n1", "body": body })
+    }
+
+    #[test]
+    fn accepts_well_formed_messages_with_unknown_fields() {
+        let message = fwd(json!({
+            "type": "fwd",
+            "msg_id": 1,
+            "slot": 31,
+            "hash": "2487bd4f",
+            "header": "828a01",
+            "block": null,
+            "height": 1,
+        }));
+
+        assert_eq!(validate(&message), Ok(()));
+    }
+
+    #[test]
+    fn points_at_the_first_offending_field() {
+        let cases = [
+            (
+                json!({ "src": "c1", "dest": "n1" }),
+                "/body",
+                "missing field",
+            ),
+            (
+                fwd(json!({ "type": "fwd", "msg_id": 1, "slot": 31, "hash": "2487bd4f" })),
+                "/body/header",
+                "missing field",
+            ),
+            (
+                fwd(json!({ "type": "bck", "msg_id": 1, "slot": -1, "hash": "" })),
+                "/body/slot",
+                "expected at least 0, found -1",
+            ),
+            (
+                fwd(json!({ "type": "bck", "msg_id": 1, "slot": 31, "hash": "xyz" })),
+                "/body/hash",
+                "expected hex-encoded bytes",
+            ),
+            (
+                fwd(json!({ "type": "find_intersect", "msg_id": 1, "points": [[31]] })),
+                "/body/points/0",
+                "expected at least 2 items, found 1",
+            ),
+            (
+                fwd(json!({ "type": "topology", "msg_id": 1, "topology": { "n1": [2] } })),
+                "/body/topology/n1/0",
+                "expected string, found integer",
+            ),
+            (
+                fwd(json!({ "type": "forward", "msg_id": 1 })),
+                "/body/type",
+                "unknown message type \"forward\"",
+            ),
+        ];
+
+        for (message, path, reason) in cases {
+            assert_eq!(
+                validate(&message),
+                Err(Violation {
+                    path: path.to_string(),
+                    reason: reason.to_string(),
+                }),
+                "{}",
+                message
+            );
+        }
+    }
+}
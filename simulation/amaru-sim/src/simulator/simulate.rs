@@ -24,269 +24,3331 @@
 // Go to 3 and continue until heap is empty;
 // Make assertions on the trace to ensure the execution was correct, if not, shrink and present minimal trace that breaks the assertion together with the seed that allows us to reproduce the execution.
 
-use crate::echo::{EchoMessage, Envelope};
-use pure_stage::simulation::{Receiver, SimulationRunning};
-use pure_stage::{StageRef, Void};
+use super::{
+    corpus::{Case, Corpus},
+    faulty_store::{StoreFault, StoreFaults},
+    nemesis::{Fate, Nemesis, Targets},
+    scenario::Scenario,
+    temporal::{Monitor, Temporal},
+};
+use crate::echo::{BroadcastMessage, EchoMessage, Envelope};
+#[cfg(test)]
+use pure_stage::simulation::{Blocked, Receiver, SimulationRunning, Snapshot};
+#[cfg(test)]
+use pure_stage::StageRef;
 
 use anyhow::anyhow;
 use proptest::{
     prelude::*,
-    test_runner::{Config, TestError, TestRunner},
+    strategy::{NewTree, ValueTree},
+    test_runner::{Config, RngAlgorithm, TestError, TestRng, TestRunner},
 };
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
+    any::Any,
+    cell::Cell,
     cmp::Reverse,
-    collections::{BTreeMap, BinaryHeap},
+    collections::{BTreeMap, BTreeSet, BinaryHeap},
     fmt::Debug,
-    io::{BufRead, BufReader, Write},
-    path::Path,
-    process::{Command, Stdio},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    marker::PhantomData,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    rc::Rc,
+    sync::OnceLock,
     time::{Duration, Instant},
 };
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Entry<Msg> {
+    pub arrival_time: Instant,
+    pub envelope: Envelope<Msg>,
+}
+
+impl<Msg: PartialEq> PartialOrd for Entry<Msg> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Msg: PartialEq> Ord for Entry<Msg> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.arrival_time.cmp(&other.arrival_time)
+    }
+}
+
+impl<Msg: PartialEq> Eq for Entry<Msg> {}
+
+/// A message in the heap of a [`World`], along with when it was sent, to measure how long it
+/// takes to be delivered. Ordered by arrival time only, like entries.
+#[derive(Debug, Clone, PartialEq)]
+struct Queued<Msg> {
+    entry: Entry<Msg>,
+    sent_at: Instant,
+}
+
+impl<Msg> From<Entry<Msg>> for Queued<Msg> {
+    /// Messages from clients are sent as they arrive.
+    fn from(entry: Entry<Msg>) -> Self {
+        Queued {
+            sent_at: entry.arrival_time,
+            entry,
+        }
+    }
+}
+
+impl<Msg: PartialEq> PartialOrd for Queued<Msg> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Msg: PartialEq> Ord for Queued<Msg> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.entry.cmp(&other.entry)
+    }
+}
+
+impl<Msg: PartialEq> Eq for Queued<Msg> {}
+
+pub type NodeId = String;
+
+/// A fault the simulator can inflict upon the nodes of a [`World`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum NemesisAction {
+    /// Kill the node, invoking its `close` hook. Messages addressed to a crashed node are
+    /// dropped until it is restarted.
+    Crash(NodeId),
+    /// Respawn a crashed node. Nodes backed by an on-disk chain store (e.g. the simulator
+    /// binary started again with the same `--chain-dir`) are expected to recover their tip
+    /// and chain selection state from it.
+    Restart(NodeId),
+    /// Make the next chain store operation of the given kind fail on the node. Only nodes
+    /// whose handle exposes their [`StoreFaults`] are affected.
+    FailStore(NodeId, StoreFault),
+    /// Drop all the messages exchanged by the two nodes, in both directions, until they are
+    /// healed.
+    Partition(NodeId, NodeId),
+    /// Let the two nodes exchange messages again.
+    Heal(NodeId, NodeId),
+    /// Set the clock of the node ahead of the world's by the given duration, from now on:
+    /// its timers fire that much earlier, and those overdue after a jump fire right away.
+    /// Only nodes with timers are affected, see [`NodeHandle::tick`].
+    SkewClock(NodeId, Duration),
+}
+
+/// The link between two nodes, regardless of the direction of the messages.
+fn link(a: NodeId, b: NodeId) -> (NodeId, NodeId) {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// A [`NemesisAction`] scheduled at some point in time.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fault {
+    at: Instant,
+    action: NemesisAction,
+}
+
+/// A fault inflicted upon a [`World`], see [`World::inflicted`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Inflicted<Msg> {
+    /// Scheduled, struck by a nemesis, or a node crashing on its own.
+    Action(NemesisAction),
+    /// A message between nodes met another fate than its delivery, see [`Nemesis::fate`].
+    Message(Envelope<Msg>, Fate),
+}
+
+impl<Msg> Inflicted<Msg> {
+    /// The node the fault is inflicted upon: the receiver of a message, or the first node
+    /// named by an action.
+    pub fn node(&self) -> &NodeId {
+        match self {
+            Inflicted::Message(envelope, _) => &envelope.dest,
+            Inflicted::Action(
+                NemesisAction::Crash(node)
+                | NemesisAction::Restart(node)
+                | NemesisAction::FailStore(node, _)
+                | NemesisAction::Partition(node, _)
+                | NemesisAction::Heal(node, _)
+                | NemesisAction::SkewClock(node, _),
+            ) => node,
+        }
+    }
+}
+
+/// The inputs of a simulation run: client messages to inject and faults to inflict.
+#[derive(Debug, Clone)]
+pub struct Schedule<Msg> {
+    pub messages: Vec<Reverse<Entry<Msg>>>,
+    pub faults: Vec<Fault>,
+}
+
+impl<Msg: Clone> Schedule<Msg> {
+    /// The schedule as stored in a [`Corpus`], with times given since the [`epoch`].
+    pub fn to_case(&self, seed: u64, world_seed: u64) -> Case<Msg> {
+        Case {
+            seed,
+            world_seed,
+            messages: self
+                .messages
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.0.arrival_time.saturating_duration_since(epoch()),
+                        entry.0.envelope.clone(),
+                    )
+                })
+                .collect(),
+            faults: self
+                .faults
+                .iter()
+                .map(|fault| {
+                    (
+                        fault.at.saturating_duration_since(epoch()),
+                        fault.action.clone(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn from_case(case: &Case<Msg>) -> Self {
+        Schedule {
+            messages: case
+                .messages
+                .iter()
+                .map(|(at, envelope)| {
+                    Reverse(Entry {
+                        arrival_time: epoch() + *at,
+                        envelope: envelope.clone(),
+                    })
+                })
+                .collect(),
+            faults: case
+                .faults
+                .iter()
+                .map(|(at, action)| Fault {
+                    at: epoch() + *at,
+                    action: action.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A [`Strategy`] generating [`Schedule`]s of client messages sent to "n1", from "c1" or,
+/// in tests, from several clients at once, see `ScheduleStrategy::with_clients`.
+///
+/// Unlike a plain collection strategy, shrinking a schedule doesn't only shrink message
+/// payloads: it also tries removing faults and messages, and reordering deliveries so that
+/// messages arrive in the order they were generated, so the minimal counterexample only
+/// retains what's needed to make the property fail.
+#[derive(Debug)]
+pub struct ScheduleStrategy<S, F> {
+    start: Instant,
+    generate_message: S,
+    generate_faults: F,
+    size: Range<usize>,
+    clients: usize,
+}
+
+impl<S, F> ScheduleStrategy<S, F>
+where
+    S: Strategy,
+    F: Strategy<Value = Vec<(Duration, NemesisAction)>>,
+{
+    /// Create a strategy for schedules of `size` messages, arriving within one second, along
+    /// with faults whose time is given relative to the start of the simulation.
+    pub fn new(generate_message: S, generate_faults: F, size: Range<usize>) -> Self {
+        Self {
+            start: epoch(),
+            generate_message,
+            generate_faults,
+            size,
+            clients: 1,
+        }
+    }
+
+    /// Send each message from one of "c1" to "cN", picked at random, so that the requests of
+    /// several clients interleave.
+    #[cfg(test)]
+    pub fn with_clients(mut self, clients: usize) -> Self {
+        self.clients = clients.max(1);
+        self
+    }
+}
+
+impl<S, F> Strategy for ScheduleStrategy<S, F>
+where
+    S: Strategy,
+    F: Strategy<Value = Vec<(Duration, NemesisAction)>>,
+{
+    type Tree = ScheduleValueTree<S::Tree>;
+    type Value = Schedule<S::Value>;
+
+    fn new_tree(&self, runner: &mut TestRunner) -> NewTree<Self> {
+        let len = runner.rng().gen_range(self.size.clone());
+        let mut messages = Vec::with_capacity(len);
+        for _ in 0..len {
+            let arrival_time = self.start + Duration::from_millis(runner.rng().gen_range(0..1000));
+            // a single client draws nothing, so that its schedules don't depend on this
+            let client = if self.clients > 1 {
+                runner.rng().gen_range(1..=self.clients)
+            } else {
+                1
+            };
+            messages.push(ScheduledMessage {
+                arrival_time,
+                src: format!("c{}", client),
+                dest: "n1".to_string(),
+                payload: self.generate_message.new_tree(runner)?,
+            });
+        }
+        let faults = self
+            .generate_faults
+            .new_tree(runner)?
+            .current()
+            .into_iter()
+            .map(|(after, action)| Fault {
+                at: self.start + after,
+                action,
+            })
+            .collect();
+
+        Ok(ScheduleValueTree {
+            messages,
+            faults,
+            step: ShrinkStep::RemoveFault(0),
+            testing: false,
+        })
+    }
+}
+
+struct ScheduledMessage<T> {
     arrival_time: Instant,
-    envelope: Envelope<Msg>,
+    src: NodeId,
+    dest: NodeId,
+    payload: T,
+}
+
+/// The next simplification a [`ScheduleValueTree`] tries, in order.
+#[derive(Debug, Clone, Copy)]
+enum ShrinkStep {
+    RemoveFault(usize),
+    RemoveMessage(usize),
+    /// Swap the arrival times of the messages at this index and the next one.
+    Reorder(usize),
+    Payload(usize),
+    Done,
+}
+
+impl ShrinkStep {
+    /// The step to try once this one turned out to make the property pass.
+    fn skip(self) -> Self {
+        match self {
+            ShrinkStep::RemoveFault(i) => ShrinkStep::RemoveFault(i + 1),
+            ShrinkStep::RemoveMessage(i) => ShrinkStep::RemoveMessage(i + 1),
+            ShrinkStep::Reorder(i) => ShrinkStep::Reorder(i + 1),
+            ShrinkStep::Payload(i) => ShrinkStep::Payload(i + 1),
+            ShrinkStep::Done => ShrinkStep::Done,
+        }
+    }
+}
+
+/// The [`ValueTree`] of [`ScheduleStrategy`].
+///
+/// `messages` and `faults` hold the smallest schedule known to fail; while `testing` is set,
+/// [`current`](ValueTree::current) returns it with `step` applied.
+pub struct ScheduleValueTree<T> {
+    messages: Vec<ScheduledMessage<T>>,
+    faults: Vec<Fault>,
+    step: ShrinkStep,
+    testing: bool,
+}
+
+impl<T: ValueTree> ScheduleValueTree<T> {
+    /// The schedule under test still fails, make it the new minimal one.
+    fn accept(&mut self) {
+        self.testing = false;
+        match self.step {
+            ShrinkStep::RemoveFault(i) => {
+                self.faults.remove(i);
+            }
+            ShrinkStep::RemoveMessage(i) => {
+                self.messages.remove(i);
+            }
+            ShrinkStep::Reorder(i) => {
+                let (before, after) = self.messages.split_at_mut(i + 1);
+                std::mem::swap(&mut before[i].arrival_time, &mut after[0].arrival_time);
+                self.step = ShrinkStep::Reorder(i + 1);
+            }
+            // the payload tree keeps track of its own simplification
+            ShrinkStep::Payload(_) | ShrinkStep::Done => {}
+        }
+    }
+
+    fn next_candidate(&mut self) -> bool {
+        loop {
+            match self.step {
+                ShrinkStep::RemoveFault(i) if i < self.faults.len() => break,
+                ShrinkStep::RemoveFault(_) => self.step = ShrinkStep::RemoveMessage(0),
+                ShrinkStep::RemoveMessage(i) if i < self.messages.len() => break,
+                ShrinkStep::RemoveMessage(_) => self.step = ShrinkStep::Reorder(0),
+                ShrinkStep::Reorder(i) if i + 1 < self.messages.len() => {
+                    if self.messages[i].arrival_time > self.messages[i + 1].arrival_time {
+                        break;
+                    }
+                    self.step = ShrinkStep::Reorder(i + 1);
+                }
+                ShrinkStep::Reorder(_) => self.step = ShrinkStep::Payload(0),
+                ShrinkStep::Payload(i) if i < self.messages.len() => {
+                    if self.messages[i].payload.simplify() {
+                        break;
+                    }
+                    self.step = ShrinkStep::Payload(i + 1);
+                }
+                ShrinkStep::Payload(_) => self.step = ShrinkStep::Done,
+                ShrinkStep::Done => return false,
+            }
+        }
+        self.testing = true;
+        true
+    }
+}
+
+impl<T: ValueTree> ValueTree for ScheduleValueTree<T> {
+    type Value = Schedule<T::Value>;
+
+    fn current(&self) -> Self::Value {
+        let mut messages = self
+            .messages
+            .iter()
+            .map(|msg| Entry {
+                arrival_time: msg.arrival_time,
+                envelope: Envelope {
+                    src: msg.src.clone(),
+                    dest: msg.dest.clone(),
+                    body: msg.payload.current(),
+                },
+            })
+            .collect::<Vec<_>>();
+        let mut faults = self.faults.clone();
+        if self.testing {
+            match self.step {
+                ShrinkStep::RemoveFault(i) => {
+                    faults.remove(i);
+                }
+                ShrinkStep::RemoveMessage(i) => {
+                    messages.remove(i);
+                }
+                ShrinkStep::Reorder(i) => {
+                    let (before, after) = messages.split_at_mut(i + 1);
+                    std::mem::swap(&mut before[i].arrival_time, &mut after[0].arrival_time);
+                }
+                ShrinkStep::Payload(_) | ShrinkStep::Done => {}
+            }
+        }
+        Schedule {
+            messages: messages.into_iter().map(Reverse).collect(),
+            faults,
+        }
+    }
+
+    fn simplify(&mut self) -> bool {
+        if self.testing {
+            self.accept();
+        }
+        self.next_candidate()
+    }
+
+    fn complicate(&mut self) -> bool {
+        if !self.testing {
+            return false;
+        }
+        self.testing = false;
+        match self.step {
+            ShrinkStep::Payload(i) if self.messages[i].payload.complicate() => {
+                self.testing = true;
+                return true;
+            }
+            step => self.step = step.skip(),
+        }
+        self.next_candidate()
+    }
+}
+
+/// A node of a simulated [`World`].
+///
+/// Only [`handle`](NodeHandle::handle) is required: by default, a node has no timers, no
+/// state to show, no snapshots nor store faults, and it handles messages instantaneously.
+#[async_trait::async_trait(?Send)]
+pub trait NodeHandle<Msg> {
+    /// Handle a message, returning the messages sent in response, if any.
+    async fn handle(&mut self, msg: Envelope<Msg>) -> anyhow::Result<Vec<Envelope<Msg>>>;
+
+    /// Advance the node's clock to `now`, returning the messages sent by the timers that
+    /// fired meanwhile, along with the time at which they were sent, see
+    /// [`World::step_world`].
+    ///
+    /// The clock of a node without timers never moves.
+    fn tick(&mut self, _now: Instant) -> anyhow::Result<Vec<(Instant, Envelope<Msg>)>> {
+        Ok(Vec::new())
+    }
+
+    /// Release what the node holds, when it crashes or the world goes away.
+    fn close(&mut self) {}
+
+    /// The node's state, recorded before and after each delivery, see [`World::steps`].
+    fn state(&self) -> Option<NodeState> {
+        None
+    }
+
+    /// A snapshot of the node, to bring it back to later with
+    /// [`restore`](NodeHandle::restore), or `None` if it doesn't support snapshots, see
+    /// [`World::checkpoint`].
+    fn snapshot(&self) -> Option<NodeSnapshot> {
+        None
+    }
+
+    /// Go back to a snapshot taken earlier. A snapshot can be restored any number of times,
+    /// so it must not be consumed.
+    fn restore(&mut self, _snapshot: &NodeSnapshot) -> anyhow::Result<()> {
+        Err(anyhow!("node does not support snapshots"))
+    }
+
+    /// Where the simulator injects faults in the node's chain store, see
+    /// [`NemesisAction::FailStore`].
+    fn store_faults(&self) -> Option<&StoreFaults> {
+        None
+    }
+
+    /// How long the node takes to handle the message, in simulated time. The node sends its
+    /// responses once done, and messages arriving meanwhile wait for it.
+    fn processing_time(&mut self, _msg: &Envelope<Msg>) -> Duration {
+        Duration::ZERO
+    }
+
+    fn boxed(self) -> BoxedNode<Msg>
+    where
+        Self: Sized + 'static,
+    {
+        Box::new(self)
+    }
+}
+
+pub type BoxedNode<Msg> = Box<dyn NodeHandle<Msg>>;
+
+/// A snapshot of the internal state of a node, see [`NodeHandle::state`].
+pub type NodeState = serde_json::Value;
+
+/// Everything a node needs to go back to some earlier point, see
+/// [`NodeHandle::snapshot`]. Only the node knows what's inside.
+pub type NodeSnapshot = Rc<dyn Any>;
+
+/// The error a node returns when it crashed while handling a message, e.g. because of
+/// [`StoreFault::CrashAfterStoreHeader`](super::faulty_store::StoreFault).
+///
+/// The world then crashes the node, losing the message, and restarts it right away if it
+/// knows how to, see [`World::with_respawn`].
+#[derive(Debug)]
+pub struct Crashed;
+
+impl std::fmt::Display for Crashed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "node crashed")
+    }
+}
+
+impl std::error::Error for Crashed {}
+
+#[cfg(test)]
+type Timers<Msg> = Box<dyn FnMut(Instant) -> Result<Vec<(Instant, Envelope<Msg>)>, anyhow::Error>>;
+
+/// A node made of closures, for nodes simple enough to be written inline in tests.
+#[cfg(test)]
+pub struct FnNode<Msg> {
+    handle: Box<dyn FnMut(Envelope<Msg>) -> Result<Vec<Envelope<Msg>>, anyhow::Error>>,
+    advance: Timers<Msg>,
+    close: Box<dyn FnMut()>,
+    store_faults: Option<StoreFaults>,
+    get_state: Option<Box<dyn Fn() -> NodeState>>,
+    snapshot: Option<Box<dyn Fn() -> NodeSnapshot>>,
+    restore: Option<Box<dyn FnMut(&NodeSnapshot) -> Result<(), anyhow::Error>>>,
+    processing_time: Box<dyn FnMut(&Envelope<Msg>) -> Duration>,
+}
+
+#[cfg(test)]
+impl<Msg: 'static> FnNode<Msg> {
+    pub fn new(
+        handle: impl FnMut(Envelope<Msg>) -> Result<Vec<Envelope<Msg>>, anyhow::Error> + 'static,
+        close: impl FnMut() + 'static,
+    ) -> Self {
+        FnNode {
+            handle: Box::new(handle),
+            advance: Box::new(|_| Ok(Vec::new())),
+            close: Box::new(close),
+            store_faults: None,
+            get_state: None,
+            snapshot: None,
+            restore: None,
+            processing_time: Box::new(|_| Duration::ZERO),
+        }
+    }
+
+    /// Let the node react to the passing of time, see [`NodeHandle::tick`].
+    pub fn with_timers(
+        mut self,
+        advance: impl FnMut(Instant) -> Result<Vec<(Instant, Envelope<Msg>)>, anyhow::Error> + 'static,
+    ) -> Self {
+        self.advance = Box::new(advance);
+        self
+    }
+
+    /// Let the simulator inject faults in the node's chain store, see
+    /// [`NodeHandle::store_faults`].
+    pub fn with_store_faults(mut self, store_faults: StoreFaults) -> Self {
+        self.store_faults = Some(store_faults);
+        self
+    }
+
+    /// Let the simulator record the node's state, see [`NodeHandle::state`].
+    pub fn with_state(mut self, get_state: impl Fn() -> NodeState + 'static) -> Self {
+        self.get_state = Some(Box::new(get_state));
+        self
+    }
+
+    /// Let the simulator take snapshots of the node and bring it back to one of them later,
+    /// see [`NodeHandle::snapshot`].
+    pub fn with_snapshots(
+        mut self,
+        snapshot: impl Fn() -> NodeSnapshot + 'static,
+        restore: impl FnMut(&NodeSnapshot) -> Result<(), anyhow::Error> + 'static,
+    ) -> Self {
+        self.snapshot = Some(Box::new(snapshot));
+        self.restore = Some(Box::new(restore));
+        self
+    }
+
+    /// Tell how long the node takes to handle each message, see
+    /// [`NodeHandle::processing_time`].
+    pub fn with_processing_time(
+        mut self,
+        processing_time: impl FnMut(&Envelope<Msg>) -> Duration + 'static,
+    ) -> Self {
+        self.processing_time = Box::new(processing_time);
+        self
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait(?Send)]
+impl<Msg: 'static> NodeHandle<Msg> for FnNode<Msg> {
+    async fn handle(&mut self, msg: Envelope<Msg>) -> anyhow::Result<Vec<Envelope<Msg>>> {
+        (self.handle)(msg)
+    }
+
+    fn tick(&mut self, now: Instant) -> anyhow::Result<Vec<(Instant, Envelope<Msg>)>> {
+        (self.advance)(now)
+    }
+
+    fn close(&mut self) {
+        (self.close)()
+    }
+
+    fn state(&self) -> Option<NodeState> {
+        self.get_state.as_ref().map(|get_state| get_state())
+    }
+
+    fn snapshot(&self) -> Option<NodeSnapshot> {
+        self.snapshot.as_ref().map(|snapshot| snapshot())
+    }
+
+    fn restore(&mut self, snapshot: &NodeSnapshot) -> anyhow::Result<()> {
+        match self.restore.as_mut() {
+            Some(restore) => restore(snapshot),
+            None => Err(anyhow!("node does not support snapshots")),
+        }
+    }
+
+    fn store_faults(&self) -> Option<&StoreFaults> {
+        self.store_faults.as_ref()
+    }
+
+    fn processing_time(&mut self, msg: &Envelope<Msg>) -> Duration {
+        (self.processing_time)(msg)
+    }
+}
+
+/// A node running a pure-stage graph: messages are enqueued to `stage`, whose state is `St`,
+/// and what the graph sends to `rx` is sent in response.
+///
+/// The simulation's clock is only moved by [`tick`](NodeHandle::tick), so stages waiting on
+/// a timer are left sleeping when handling a message. The node supports snapshots when all
+/// stages of the graph do and none is sleeping, see [`SimulationRunning::snapshot`].
+#[cfg(test)]
+pub struct PureStageNode<Msg, St> {
+    running: SimulationRunning,
+    rx: Receiver<Envelope<Msg>>,
+    stage: StageRef<Envelope<Msg>, St>,
+    /// The world's time and the simulation's the first time the node ticked, mapping one onto
+    /// the other.
+    origin: Option<(Instant, pure_stage::Instant)>,
+}
+
+#[cfg(test)]
+impl<Msg, St> PureStageNode<Msg, St> {
+    pub fn new(
+        rx: Receiver<Envelope<Msg>>,
+        stage: StageRef<Envelope<Msg>, St>,
+        running: SimulationRunning,
+    ) -> Self {
+        PureStageNode {
+            running,
+            rx,
+            stage,
+            origin: None,
+        }
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait(?Send)]
+impl<Msg: Message + Send, St> NodeHandle<Msg> for PureStageNode<Msg, St> {
+    async fn handle(&mut self, msg: Envelope<Msg>) -> anyhow::Result<Vec<Envelope<Msg>>> {
+        self.running.enqueue_msg(&self.stage, [msg]);
+        match self.running.run_until_sleeping_or_blocked() {
+            Blocked::Idle | Blocked::Sleeping => Ok(self.rx.drain().collect()),
+            blocked => Err(anyhow!("node is stuck: {:?}", blocked)),
+        }
+    }
+
+    fn tick(&mut self, now: Instant) -> anyhow::Result<Vec<(Instant, Envelope<Msg>)>> {
+        let running = &mut self.running;
+        let (world_origin, node_origin) = *self.origin.get_or_insert_with(|| (now, running.now()));
+        let target = node_origin
+            .checked_add(now.saturating_duration_since(world_origin))
+            .ok_or_else(|| anyhow!("node clock overflow"))?;
+
+        let mut outputs = Vec::new();
+        while let Some(wakeup) = running.next_wakeup().filter(|wakeup| *wakeup <= target) {
+            running.skip_to_next_wakeup();
+            match running.run_until_sleeping_or_blocked() {
+                Blocked::Idle | Blocked::Sleeping => {}
+                blocked => return Err(anyhow!("node is stuck: {:?}", blocked)),
+            }
+            let sent_at = world_origin + wakeup.saturating_since(node_origin);
+            outputs.extend(self.rx.drain().map(|msg| (sent_at, msg)));
+        }
+        running.advance_clock_to(target);
+        Ok(outputs)
+    }
+
+    fn snapshot(&self) -> Option<NodeSnapshot> {
+        let snapshot = self.running.snapshot().ok()?;
+        Some(Rc::new((snapshot, self.origin)))
+    }
+
+    fn restore(&mut self, snapshot: &NodeSnapshot) -> anyhow::Result<()> {
+        let (snapshot, origin) = snapshot
+            .downcast_ref::<(Snapshot, Option<(Instant, pure_stage::Instant)>)>()
+            .ok_or_else(|| anyhow!("not a snapshot of a pure-stage node"))?;
+        self.running.restore(snapshot)?;
+        self.rx.drain().for_each(drop);
+        self.origin = *origin;
+        Ok(())
+    }
+}
+
+/// A node running an external process, which receives each message as a line of JSON on
+/// its stdin.
+///
+/// The process answers each message with any number of lines of JSON on its stdout, each of
+/// them a message, followed by an empty line marking the end of its response.
+pub struct ProcessNode<Msg> {
+    child: Child,
+    stdin: ChildStdin,
+    /// Outlives a single message, as it may have buffered the next responses.
+    reader: BufReader<ChildStdout>,
+    _messages: PhantomData<Msg>,
+}
+
+impl<Msg> ProcessNode<Msg> {
+    pub fn spawn(filepath: &Path, args: &[&str]) -> anyhow::Result<Self> {
+        let mut child = Command::new(filepath)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("Failed to create process: {}", e))?;
+        let stdin = child.stdin.take().ok_or(anyhow!("Failed to take stdin"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or(anyhow!("Failed to take stdout"))?;
+        Ok(ProcessNode {
+            child,
+            stdin,
+            reader: BufReader::new(stdout),
+            _messages: PhantomData,
+        })
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Msg: Message> NodeHandle<Msg> for ProcessNode<Msg> {
+    async fn handle(&mut self, msg: Envelope<Msg>) -> anyhow::Result<Vec<Envelope<Msg>>> {
+        let json =
+            serde_json::to_string(&msg).map_err(|e| anyhow!("Failed to encode JSON: {}", e))?;
+        tracing::debug!("About to write: {}", json);
+        writeln!(self.stdin, "{}", json)
+            .map_err(|e| anyhow!("Failed to write to child's stdin: {}", e))?;
+        self.stdin
+            .flush()
+            .map_err(|e| anyhow!("Failed to flush child's stdin: {}", e))?;
+
+        let mut msgs = Vec::new();
+        loop {
+            let mut line = String::new();
+            let read = self
+                .reader
+                .read_line(&mut line)
+                .map_err(|e| anyhow!("Failed to read from child's stdout: {}", e))?;
+            if read == 0 {
+                return Err(anyhow!("Child's stdout closed in the middle of a response"));
+            }
+
+            tracing::debug!("Just read: {}", &line);
+            let line = line.trim_end();
+            if line.is_empty() {
+                return Ok(msgs);
+            }
+            msgs.push(
+                serde_json::from_str(line).map_err(|e| anyhow!("Failed to decode JSON: {}", e))?,
+            );
+        }
+    }
+
+    fn close(&mut self) {
+        self.child
+            .kill()
+            .map_err(|e| anyhow!("Failed to terminate process: {}", e))
+            .ok();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace<Msg>(pub Vec<Envelope<Msg>>);
+
+/// The delivery of a message to a node, along with the state of the node around it, for
+/// nodes exposing it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step<Msg> {
+    pub node: NodeId,
+    pub pre_state: Option<NodeState>,
+    pub envelope: Envelope<Msg>,
+    pub post_state: Option<NodeState>,
+    pub outgoing: Vec<Envelope<Msg>>,
+}
+
+#[cfg(test)]
+impl<Msg: Clone> Trace<Msg> {
+    /// The messages `client` sent and received, in order, to check properties client by
+    /// client.
+    pub fn of_client(&self, client: &str) -> Trace<Msg> {
+        Trace(
+            self.0
+                .iter()
+                .filter(|msg| msg.src == client || msg.dest == client)
+                .cloned()
+                .collect(),
+        )
+    }
 }
 
-impl<Msg: PartialEq> PartialOrd for Entry<Msg> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+impl<Msg: Debug> Trace<Msg> {
+    /// Render the messages exchanged between clients and nodes as a Mermaid sequence diagram.
+    pub fn to_mermaid(&self) -> String {
+        let mut participants: Vec<&NodeId> = Vec::new();
+        for msg in &self.0 {
+            for id in [&msg.src, &msg.dest] {
+                if !participants.contains(&id) {
+                    participants.push(id);
+                }
+            }
+        }
+
+        let mut diagram = String::from("sequenceDiagram\n");
+        for id in participants {
+            let kind = if id.starts_with("c") {
+                "actor"
+            } else {
+                "participant"
+            };
+            diagram += &format!("    {} {}\n", kind, id);
+        }
+        for msg in &self.0 {
+            diagram += &format!(
+                "    {}->>{}: {}\n",
+                msg.src,
+                msg.dest,
+                escape_mermaid(&format!("{:?}", msg.body))
+            );
+        }
+        diagram
+    }
+}
+
+/// Mermaid ends statements at `;` and uses `#` for entity codes, so both need escaping.
+fn escape_mermaid(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '#' => "#35;".to_string(),
+            ';' => "#59;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// What a message sent by a node does to the chain it announces, as counted in
+/// [`Statistics`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChainEvent {
+    Forward,
+    Rollback,
+}
+
+/// What happened during a run, to tell at a glance whether it exercised interesting
+/// behavior.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Statistics {
+    /// Messages handled by nodes.
+    pub delivered: usize,
+    /// Messages lost to crashed nodes, partitions or message loss.
+    pub dropped: usize,
+    /// Forward events sent by nodes, see [`World::with_chain_events`].
+    pub forwards: usize,
+    /// Rollbacks sent by nodes, see [`World::with_chain_events`].
+    pub rollbacks: usize,
+    /// The largest number of messages in flight at once.
+    pub max_heap_depth: usize,
+    /// Simulated time between the first and the last delivery.
+    pub duration: Duration,
+}
+
+impl Statistics {
+    /// Account for another run.
+    fn combine(&mut self, other: &Statistics) {
+        self.delivered += other.delivered;
+        self.dropped += other.dropped;
+        self.forwards += other.forwards;
+        self.rollbacks += other.rollbacks;
+        self.max_heap_depth = self.max_heap_depth.max(other.max_heap_depth);
+        self.duration += other.duration;
+    }
+}
+
+impl std::fmt::Display for Statistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} message(s) delivered, {} dropped, {} forward(s), {} rollback(s), at most {} message(s) in flight, {:?} of simulated time",
+            self.delivered,
+            self.dropped,
+            self.forwards,
+            self.rollbacks,
+            self.max_heap_depth,
+            self.duration
+        )
+    }
+}
+
+/// How the queues of a [`World`] behaved during a run, to check that they stay bounded and
+/// that nodes keep up with their load, see [`Properties::metrics`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// The number of messages in flight at each delivery, the delivered one included, by
+    /// simulated time since the first delivery.
+    pub heap_depth: Vec<(Duration, usize)>,
+    /// For each node, the largest number of messages which had arrived and were still waiting
+    /// for it when it took the next one.
+    pub max_backlog: BTreeMap<NodeId, usize>,
+    /// For each node, how long the messages it took had been in flight since they were sent,
+    /// waiting for the node included, in order of delivery.
+    pub latencies: BTreeMap<NodeId, Vec<Duration>>,
+}
+
+impl Metrics {
+    /// The longest time any message took to be delivered, if any was.
+    pub fn max_latency(&self) -> Option<Duration> {
+        self.latencies.values().flatten().max().copied()
+    }
+
+    /// The largest backlog of any node.
+    pub fn max_backlog(&self) -> usize {
+        self.max_backlog.values().max().copied().unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let average_heap_depth = match self.heap_depth.len() {
+            0 => 0.0,
+            deliveries => {
+                self.heap_depth
+                    .iter()
+                    .map(|(_, depth)| depth)
+                    .sum::<usize>() as f64
+                    / deliveries as f64
+            }
+        };
+        write!(
+            f,
+            "{:.1} message(s) in flight on average, at most {} waiting for a node, {:?} at most to deliver one",
+            average_heap_depth,
+            self.max_backlog(),
+            self.max_latency().unwrap_or_default()
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Next {
+    Done,
+    Continue,
+}
+
+/// The state of a [`World`] at some point of a simulation, see [`World::checkpoint`].
+#[derive(Clone)]
+pub struct Checkpoint<Msg> {
+    heap: BinaryHeap<Reverse<Queued<Msg>>>,
+    faults: BinaryHeap<Reverse<Fault>>,
+    nodes: BTreeMap<NodeId, NodeSnapshot>,
+    crashed: BTreeSet<NodeId>,
+    partitions: BTreeSet<(NodeId, NodeId)>,
+    skews: BTreeMap<NodeId, Duration>,
+    busy: BTreeMap<NodeId, Instant>,
+    rng: SimRng,
+    trace: Trace<Msg>,
+    steps: Vec<Step<Msg>>,
+    inflicted: Vec<(Instant, Inflicted<Msg>)>,
+    start: Option<Instant>,
+    now: Option<Instant>,
+    monitors: Vec<Monitor<Msg>>,
+    violation: Option<String>,
+    statistics: Statistics,
+    metrics: Metrics,
+}
+
+/// How long messages take to travel a link, one way: `base`, plus a jitter of less than
+/// `jitter` picked at random for each message, to the millisecond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Latency {
+    pub base: Duration,
+    pub jitter: Duration,
+}
+
+impl Default for Latency {
+    /// The latency of the links between nodes not given one, see [`World::with_latency`].
+    fn default() -> Self {
+        Latency {
+            base: Duration::from_millis(50),
+            jitter: Duration::from_millis(100),
+        }
+    }
+}
+
+impl Latency {
+    /// A latency without jitter.
+    #[cfg(test)]
+    pub fn fixed(base: Duration) -> Self {
+        Latency {
+            base,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    fn sample(&self, rng: &mut SimRng) -> Duration {
+        match self.jitter.as_millis() as u64 {
+            0 => self.base,
+            jitter => self.base + Duration::from_millis(rng.gen_range(0..jitter)),
+        }
+    }
+}
+
+/// Messages between nodes which are randomly dropped, see [`World::with_message_loss`].
+struct MessageLoss<Msg> {
+    ratio: f64,
+    affects: Box<dyn Fn(&Envelope<Msg>) -> bool>,
+}
+
+pub struct World<Msg> {
+    heap: BinaryHeap<Reverse<Queued<Msg>>>,
+    faults: BinaryHeap<Reverse<Fault>>,
+    nodes: BTreeMap<NodeId, BoxedNode<Msg>>,
+    crashed: BTreeSet<NodeId>,
+    partitions: BTreeSet<(NodeId, NodeId)>,
+    skews: BTreeMap<NodeId, Duration>,
+    /// Until when each node is busy handling a message, see
+    /// [`NodeHandle::processing_time`].
+    busy: BTreeMap<NodeId, Instant>,
+    losses: Vec<MessageLoss<Msg>>,
+    /// From the sender to the receiver, for the links given one, see [`World::with_latency`].
+    latencies: BTreeMap<(NodeId, NodeId), Latency>,
+    default_latency: Latency,
+    /// In bytes per second, for the links given one, see [`World::with_bandwidth`].
+    bandwidths: BTreeMap<(NodeId, NodeId), u64>,
+    default_bandwidth: Option<u64>,
+    message_size: fn(&Msg) -> usize,
+    /// Along with when each of them strikes next, if ever.
+    nemeses: Vec<(Option<Instant>, Box<dyn Nemesis<Msg>>)>,
+    respawn: Option<Box<dyn FnMut(&NodeId) -> BoxedNode<Msg>>>,
+    /// Drives nodes handling messages to completion, one at a time.
+    runtime: tokio::runtime::Runtime,
+    rng: SimRng,
+    trace: Trace<Msg>,
+    steps: Vec<Step<Msg>>,
+    inflicted: Vec<(Instant, Inflicted<Msg>)>,
+    export: Option<Box<dyn FnMut(&TraceEntry<Msg>) -> anyhow::Result<()>>>,
+    start: Option<Instant>,
+    /// The arrival time of the last delivery.
+    now: Option<Instant>,
+    monitors: Vec<Monitor<Msg>>,
+    violation: Option<String>,
+    chain_event: fn(&Msg) -> Option<ChainEvent>,
+    statistics: Statistics,
+    metrics: Metrics,
+}
+
+/// What happened on a node at some point of a simulation, as exported by
+/// [`World::with_trace_export`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry<Msg> {
+    /// Milliseconds of simulated time since the first delivery.
+    pub at: u64,
+    pub node: NodeId,
+    /// The delivered message, or `None` when the outgoing messages were sent by timers.
+    pub incoming: Option<Envelope<Msg>>,
+    pub outgoing: Vec<Envelope<Msg>>,
+    /// The fault inflicted upon the node, for the entries logging one, with neither incoming
+    /// nor outgoing messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fault: Option<Inflicted<Msg>>,
+}
+
+/// The single source of randomness of a simulation run.
+///
+/// Message generation, latencies and fault decisions all draw from generators derived from
+/// the seed of the run, and simulated time starts at [`epoch`], so that the whole run is a
+/// pure function of the seed.
+#[derive(Debug, Clone)]
+pub struct SimRng(TestRng);
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        let mut bytes = [0; 32];
+        bytes[..8].copy_from_slice(&seed.to_le_bytes());
+        SimRng(TestRng::from_seed(RngAlgorithm::ChaCha, &bytes))
+    }
+
+    /// An independent generator derived from this one, for another component of the run:
+    /// what one of them draws doesn't change what the other one does.
+    pub fn fork(&mut self) -> SimRng {
+        SimRng::new(self.0.next_u64())
+    }
+
+    /// A test runner drawing its cases from a generator derived from this one.
+    pub fn runner(&mut self, config: Config) -> TestRunner {
+        TestRunner::new_with_rng(config, self.fork().0)
+    }
+}
+
+impl std::ops::Deref for SimRng {
+    type Target = TestRng;
+
+    fn deref(&self) -> &TestRng {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for SimRng {
+    fn deref_mut(&mut self) -> &mut TestRng {
+        &mut self.0
+    }
+}
+
+/// The instant simulated time starts from, the same for all the runs of a process, so that
+/// the schedules generated from the same seed are equal.
+pub fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+impl<Msg: Clone + PartialEq + Debug + 'static> World<Msg> {
+    pub fn new(
+        initial_messages: Vec<Reverse<Entry<Msg>>>,
+        node_handles: Vec<(NodeId, BoxedNode<Msg>)>,
+    ) -> Self {
+        World {
+            heap: initial_messages
+                .into_iter()
+                .map(|Reverse(entry)| Reverse(entry.into()))
+                .collect(),
+            faults: BinaryHeap::new(),
+            nodes: node_handles.into_iter().collect(),
+            crashed: BTreeSet::new(),
+            partitions: BTreeSet::new(),
+            skews: BTreeMap::new(),
+            busy: BTreeMap::new(),
+            losses: Vec::new(),
+            latencies: BTreeMap::new(),
+            default_latency: Latency::default(),
+            bandwidths: BTreeMap::new(),
+            default_bandwidth: None,
+            message_size: |_| 0,
+            nemeses: Vec::new(),
+            respawn: None,
+            runtime: tokio::runtime::Builder::new_current_thread()
+                .build()
+                .expect("unable to create runtime for simulated nodes"),
+            rng: SimRng::new(0),
+            trace: Trace(Vec::new()),
+            steps: Vec::new(),
+            inflicted: Vec::new(),
+            export: None,
+            start: None,
+            now: None,
+            monitors: Vec::new(),
+            violation: None,
+            chain_event: |_| None,
+            statistics: Statistics::default(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Check the given temporal properties against the messages delivered to nodes and the
+    /// responses sent to clients while running, stopping at the first violation.
+    pub fn with_temporal(mut self, properties: impl IntoIterator<Item = Temporal<Msg>>) -> Self {
+        self.monitors
+            .extend(properties.into_iter().map(Monitor::new));
+        self
+    }
+
+    /// Every delivery to a node so far, in order.
+    pub fn steps(&self) -> &[Step<Msg>] {
+        &self.steps
+    }
+
+    /// The messages sent by clients and the responses they got so far, in order.
+    pub fn trace(&self) -> &Trace<Msg> {
+        &self.trace
+    }
+
+    /// Tell which messages sent by nodes are forward events or rollbacks, so that the
+    /// [`Statistics`] of the run count them.
+    pub fn with_chain_events(mut self, chain_event: fn(&Msg) -> Option<ChainEvent>) -> Self {
+        self.chain_event = chain_event;
+        self
+    }
+
+    /// The messages left to deliver, by arrival time.
+    pub fn pending(&self) -> Vec<&Entry<Msg>> {
+        let mut pending: Vec<&Entry<Msg>> = self
+            .heap
+            .iter()
+            .map(|Reverse(queued)| &queued.entry)
+            .collect();
+        pending.sort_by_key(|entry| entry.arrival_time);
+        pending
+    }
+
+    /// The state of a running node, if it exposes it, see [`NodeHandle::state`].
+    pub fn node_state(&self, node_id: &str) -> Option<NodeState> {
+        self.nodes.get(node_id).and_then(|node| node.state())
+    }
+
+    /// The arrival time of the last delivered message, if any.
+    pub fn now(&self) -> Option<Instant> {
+        self.now
+    }
+
+    /// Deliver a message at the given time, on top of the ones already pending.
+    pub fn inject(&mut self, entry: Entry<Msg>) {
+        self.heap.push(Reverse(entry.into()));
+    }
+
+    /// What happened so far, see [`World::run_world`].
+    pub fn statistics(&self) -> Statistics {
+        self.statistics
+    }
+
+    /// How the heap and the mailboxes of the nodes behaved so far.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// The first violation of a temporal property, if any.
+    pub fn violation(&self) -> Option<&str> {
+        self.violation.as_deref()
+    }
+
+    fn observe(&mut self, at: Instant, envelope: &Envelope<Msg>) {
+        for monitor in &mut self.monitors {
+            if let Err(violation) = monitor.observe(at, envelope) {
+                self.violation.get_or_insert(violation);
+            }
+        }
+    }
+
+    fn check_monitors(&mut self, now: Option<Instant>) {
+        for monitor in &mut self.monitors {
+            let result = match now {
+                Some(now) => monitor.advance(now),
+                None => monitor.finish(),
+            };
+            if let Err(violation) = result {
+                self.violation.get_or_insert(violation);
+            }
+        }
+    }
+
+    /// Stream every delivery and timer firing to `writer` as it happens, one JSON-encoded
+    /// [`TraceEntry`] per line.
+    pub fn with_trace_export(mut self, mut writer: impl Write + 'static) -> Self
+    where
+        Msg: Serialize,
+    {
+        self.export = Some(Box::new(move |entry: &TraceEntry<Msg>| {
+            serde_json::to_writer(&mut writer, entry)?;
+            writeln!(writer)?;
+            Ok(())
+        }));
+        self
+    }
+
+    fn record(
+        &mut self,
+        at: Instant,
+        node: &NodeId,
+        incoming: Option<&Envelope<Msg>>,
+        outgoing: &[Envelope<Msg>],
+    ) {
+        let start = *self.start.get_or_insert(at);
+        self.statistics.duration = self
+            .statistics
+            .duration
+            .max(at.saturating_duration_since(start));
+        if let Some(export) = self.export.as_mut() {
+            let entry = TraceEntry {
+                at: at.saturating_duration_since(start).as_millis() as u64,
+                node: node.clone(),
+                incoming: incoming.cloned(),
+                outgoing: outgoing.to_vec(),
+                fault: None,
+            };
+            if let Err(err) = export(&entry) {
+                panic!("failed to export trace entry: {}", err)
+            }
+        }
+    }
+
+    /// Keep track of a fault inflicted at `at`, exporting it along with the trace.
+    fn log_fault(&mut self, at: Instant, fault: Inflicted<Msg>) {
+        if let Some(export) = self.export.as_mut() {
+            let entry = TraceEntry {
+                at: at
+                    .saturating_duration_since(self.start.unwrap_or(at))
+                    .as_millis() as u64,
+                node: fault.node().clone(),
+                incoming: None,
+                outgoing: Vec::new(),
+                fault: Some(fault.clone()),
+            };
+            if let Err(err) = export(&entry) {
+                panic!("failed to export trace entry: {}", err)
+            }
+        }
+        self.inflicted.push((at, fault));
+    }
+
+    /// Every fault inflicted so far, in order, along with when: the nemesis actions, whatever
+    /// their origin, and the messages between nodes dropped, duplicated or delayed by nemeses.
+    /// Runs are deterministic, so they are only logged for inspection.
+    pub fn inflicted(&self) -> &[(Instant, Inflicted<Msg>)] {
+        &self.inflicted
+    }
+
+    /// Snapshot the pending messages and faults, the trace so far and the state of every
+    /// running node, so that the simulation can later go on from this point again, see
+    /// [`World::restore`].
+    ///
+    /// Fails if a running node doesn't support snapshots, see [`NodeHandle::snapshot`].
+    pub fn checkpoint(&self) -> anyhow::Result<Checkpoint<Msg>> {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|(node_id, node)| match node.snapshot() {
+                Some(snapshot) => Ok((node_id.clone(), snapshot)),
+                None => Err(anyhow!("node '{}' does not support snapshots", node_id)),
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Checkpoint {
+            heap: self.heap.clone(),
+            faults: self.faults.clone(),
+            nodes,
+            crashed: self.crashed.clone(),
+            partitions: self.partitions.clone(),
+            skews: self.skews.clone(),
+            busy: self.busy.clone(),
+            rng: self.rng.clone(),
+            trace: self.trace.clone(),
+            steps: self.steps.clone(),
+            inflicted: self.inflicted.clone(),
+            start: self.start,
+            now: self.now,
+            monitors: self.monitors.clone(),
+            violation: self.violation.clone(),
+            statistics: self.statistics,
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    /// Go back to the given checkpoint, which can be restored any number of times to explore
+    /// different futures, e.g. by scheduling different faults after each restore.
+    ///
+    /// Nodes which were down at the checkpoint are crashed again, and nodes which have crashed
+    /// since are respawned before being restored. The trace export and the nemeses, if any,
+    /// are not rewound.
+    pub fn restore(&mut self, checkpoint: &Checkpoint<Msg>) -> anyhow::Result<()> {
+        let stale: Vec<NodeId> = self
+            .nodes
+            .keys()
+            .filter(|node_id| !checkpoint.nodes.contains_key(*node_id))
+            .cloned()
+            .collect();
+        for node_id in stale {
+            if let Some(mut node) = self.nodes.remove(&node_id) {
+                node.close();
+            }
+        }
+        for (node_id, snapshot) in &checkpoint.nodes {
+            if !self.nodes.contains_key(node_id) {
+                let respawn = self
+                    .respawn
+                    .as_mut()
+                    .ok_or_else(|| anyhow!("no way to respawn node '{}'", node_id))?;
+                let node = respawn(node_id);
+                self.nodes.insert(node_id.clone(), node);
+            }
+            if let Some(node) = self.nodes.get_mut(node_id) {
+                node.restore(snapshot)
+                    .map_err(|e| anyhow!("unable to restore node '{}': {}", node_id, e))?;
+            }
+        }
+
+        self.heap = checkpoint.heap.clone();
+        self.faults = checkpoint.faults.clone();
+        self.crashed = checkpoint.crashed.clone();
+        self.partitions = checkpoint.partitions.clone();
+        self.skews = checkpoint.skews.clone();
+        self.busy = checkpoint.busy.clone();
+        self.rng = checkpoint.rng.clone();
+        self.trace = checkpoint.trace.clone();
+        self.steps = checkpoint.steps.clone();
+        self.inflicted = checkpoint.inflicted.clone();
+        self.start = checkpoint.start;
+        self.now = checkpoint.now;
+        self.monitors = checkpoint.monitors.clone();
+        self.violation = checkpoint.violation.clone();
+        self.statistics = checkpoint.statistics;
+        self.metrics = checkpoint.metrics.clone();
+        Ok(())
+    }
+
+    /// Drop the given ratio of the messages between nodes accepted by `affects`, picked at
+    /// random. Responses to clients are never lost.
+    pub fn with_message_loss(
+        mut self,
+        ratio: f64,
+        affects: impl Fn(&Envelope<Msg>) -> bool + 'static,
+    ) -> Self {
+        self.losses.push(MessageLoss {
+            ratio: ratio.clamp(0.0, 1.0),
+            affects: Box::new(affects),
+        });
+        self
+    }
+
+    /// Set the latency of the messages `src` sends to `dest`, leaving the other direction of
+    /// the link alone, so that e.g. a node can have a distant peer and a local one.
+    ///
+    /// The destination can also be a client, whose responses are otherwise observed by
+    /// temporal properties as soon as they are sent; the order of the trace is left alone.
+    pub fn with_latency(
+        mut self,
+        src: impl Into<NodeId>,
+        dest: impl Into<NodeId>,
+        latency: Latency,
+    ) -> Self {
+        self.latencies.insert((src.into(), dest.into()), latency);
+        self
+    }
+
+    /// Set the latency of every link between `participants`, nodes or clients: the messages
+    /// `participants[i]` sends to `participants[j]` take `matrix[i][j]`. The diagonal is
+    /// ignored.
+    ///
+    /// Panics if the matrix isn't square, with a row and a column for each participant.
+    pub fn with_latency_matrix(mut self, participants: &[&str], matrix: &[Vec<Latency>]) -> Self {
+        assert!(
+            matrix.len() == participants.len()
+                && matrix.iter().all(|row| row.len() == participants.len()),
+            "expected a {n}x{n} latency matrix",
+            n = participants.len()
+        );
+        for (src, row) in participants.iter().zip(matrix) {
+            for (dest, latency) in participants.iter().zip(row) {
+                if src != dest {
+                    self = self.with_latency(*src, *dest, *latency);
+                }
+            }
+        }
+        self
+    }
+
+    /// Set the latency of the links between nodes not given one with
+    /// [`World::with_latency`], instead of [`Latency::default`].
+    pub fn with_default_latency(mut self, latency: Latency) -> Self {
+        self.default_latency = latency;
+        self
+    }
+
+    /// Limit the bandwidth of the link between two nodes, in both directions, to
+    /// `bytes_per_second`. Each message then takes the time to transmit its bytes on top of
+    /// the latency of the link, so that small messages overtake larger ones sent just before.
+    /// Messages weigh nothing unless told otherwise, see [`World::with_message_size`].
+    pub fn with_bandwidth(
+        mut self,
+        a: impl Into<NodeId>,
+        b: impl Into<NodeId>,
+        bytes_per_second: u64,
+    ) -> Self {
+        self.bandwidths
+            .insert(link(a.into(), b.into()), bytes_per_second);
+        self
+    }
+
+    /// Limit the bandwidth of the links between nodes not given one with
+    /// [`World::with_bandwidth`].
+    pub fn with_default_bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.default_bandwidth = Some(bytes_per_second);
+        self
+    }
+
+    /// Tell how many bytes each message weighs on links of limited bandwidth.
+    pub fn with_message_size(mut self, message_size: fn(&Msg) -> usize) -> Self {
+        self.message_size = message_size;
+        self
+    }
+
+    /// How long transmitting the message takes on its link, on top of its latency.
+    fn transmission_time(&self, envelope: &Envelope<Msg>) -> Duration {
+        let bandwidth = self
+            .bandwidths
+            .get(&link(envelope.src.clone(), envelope.dest.clone()))
+            .or(self.default_bandwidth.as_ref());
+        match bandwidth {
+            Some(bytes_per_second) if *bytes_per_second > 0 => Duration::from_secs_f64(
+                (self.message_size)(&envelope.body) as f64 / *bytes_per_second as f64,
+            ),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Let the given nemesis strike the world, first at time `at`, then whenever it says so.
+    ///
+    /// Nemeses only strike while messages are left to deliver, no earlier than any fault
+    /// scheduled at the same time.
+    pub fn with_nemesis(mut self, at: Instant, nemesis: impl Nemesis<Msg> + 'static) -> Self {
+        self.nemeses.push((Some(at), Box::new(nemesis)));
+        self
+    }
+
+    /// How long the node takes to handle the message, in simulated time.
+    fn processing_time(&mut self, node_id: &NodeId, envelope: &Envelope<Msg>) -> Duration {
+        self.nodes
+            .get_mut(node_id)
+            .map_or(Duration::ZERO, |node| node.processing_time(envelope))
+    }
+
+    /// Seed the random number generator of the world, which assigns arrival times to
+    /// messages, decides which ones get lost, and drives its nemeses.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = SimRng::new(seed);
+        self
+    }
+
+    /// Define how to spawn a fresh node handle when restarting a crashed node.
+    pub fn with_respawn(
+        mut self,
+        respawn: impl FnMut(&NodeId) -> BoxedNode<Msg> + 'static,
+    ) -> Self {
+        self.respawn = Some(Box::new(respawn));
+        self
+    }
+
+    /// Schedule a nemesis action to happen at the given time.
+    ///
+    /// Faults are applied before delivering any message whose arrival time is not earlier
+    /// than the fault's time.
+    pub fn schedule(&mut self, at: Instant, action: NemesisAction) {
+        self.faults.push(Reverse(Fault { at, action }));
+    }
+
+    fn pop_due_fault(&mut self) -> Option<Fault> {
+        let due = match (self.faults.peek(), self.heap.peek()) {
+            (Some(Reverse(fault)), Some(Reverse(queued))) => fault.at <= queued.entry.arrival_time,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if due {
+            self.faults.pop().map(|Reverse(fault)| fault)
+        } else {
+            None
+        }
+    }
+
+    /// Let the first nemesis due before the next delivery strike, returning whether any did.
+    fn unleash_nemesis(&mut self) -> bool {
+        let Some(arrival_time) = self
+            .heap
+            .peek()
+            .map(|Reverse(queued)| queued.entry.arrival_time)
+        else {
+            return false;
+        };
+        let due = self
+            .nemeses
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (next, _))| next.map(|next| (next, index)))
+            .filter(|(next, _)| *next <= arrival_time)
+            .min();
+        let Some((now, index)) = due else {
+            return false;
+        };
+        let targets = Targets {
+            running: self.nodes.keys().cloned().collect(),
+            crashed: self.crashed.iter().cloned().collect(),
+        };
+        let (next, nemesis) = &mut self.nemeses[index];
+        let strike = nemesis.strike(now, &targets, &mut self.rng);
+        // a nemesis always moves on, so that it cannot stall the world
+        *next = strike.next.filter(|next| *next > now);
+        for action in strike.actions {
+            self.inflict(now, action);
+        }
+        true
+    }
+
+    fn inflict(&mut self, at: Instant, action: NemesisAction) {
+        self.log_fault(at, Inflicted::Action(action.clone()));
+        match action {
+            NemesisAction::Crash(node_id) => {
+                if let Some(mut node) = self.nodes.remove(&node_id) {
+                    node.close();
+                    self.busy.remove(&node_id);
+                    self.crashed.insert(node_id);
+                } else if !self.crashed.contains(&node_id) {
+                    panic!("cannot crash unknown node '{}'", node_id)
+                }
+            }
+            NemesisAction::Restart(node_id) => {
+                if self.crashed.remove(&node_id) {
+                    let respawn = self
+                        .respawn
+                        .as_mut()
+                        .unwrap_or_else(|| panic!("no way to respawn node '{}'", node_id));
+                    let node = respawn(&node_id);
+                    self.nodes.insert(node_id, node);
+                } else if !self.nodes.contains_key(&node_id) {
+                    panic!("cannot restart unknown node '{}'", node_id)
+                }
+            }
+            NemesisAction::FailStore(node_id, fault) => match self.nodes.get(&node_id) {
+                Some(node) => {
+                    if let Some(store_faults) = node.store_faults() {
+                        store_faults.inject(fault);
+                    }
+                }
+                None if self.crashed.contains(&node_id) => (),
+                None => panic!("cannot fail store of unknown node '{}'", node_id),
+            },
+            NemesisAction::Partition(a, b) => {
+                self.partitions.insert(link(a, b));
+            }
+            NemesisAction::Heal(a, b) => {
+                self.partitions.remove(&link(a, b));
+            }
+            NemesisAction::SkewClock(node_id, skew) => {
+                self.skews.insert(node_id, skew);
+            }
+        }
+    }
+
+    /// Whether a message sent by a node to another one gets dropped, because of a partition
+    /// or of message loss.
+    fn is_dropped(&mut self, envelope: &Envelope<Msg>) -> bool {
+        if self
+            .partitions
+            .contains(&link(envelope.src.clone(), envelope.dest.clone()))
+        {
+            return true;
+        }
+        for loss in &self.losses {
+            if (loss.affects)(envelope) && self.rng.gen_bool(loss.ratio) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Clients are all the participants that are not nodes of this world.
+    fn is_client(&self, id: &str) -> bool {
+        !self.nodes.contains_key(id) && !self.crashed.contains(id)
+    }
+
+    /// Account for the delivery at `at` to `node_id` of a message sent at `sent_at`, see
+    /// [`World::metrics`].
+    fn measure(&mut self, at: Instant, sent_at: Instant, node_id: &NodeId) {
+        let since = at.saturating_duration_since(self.start.unwrap_or(at));
+        self.metrics.heap_depth.push((since, self.heap.len() + 1));
+        let backlog = self
+            .heap
+            .iter()
+            .filter(|Reverse(queued)| {
+                queued.entry.envelope.dest == *node_id && queued.entry.arrival_time <= at
+            })
+            .count();
+        let max_backlog = self.metrics.max_backlog.entry(node_id.clone()).or_default();
+        *max_backlog = (*max_backlog).max(backlog);
+        self.metrics
+            .latencies
+            .entry(node_id.clone())
+            .or_default()
+            .push(at.saturating_duration_since(sent_at));
+    }
+
+    /// Route a message sent by a node at the given time: responses to clients are recorded in
+    /// the trace, messages to other nodes are enqueued with the latency of their link unless
+    /// they are dropped.
+    fn route(&mut self, sent_at: Instant, mut envelope: Envelope<Msg>) {
+        if !self.is_client(&envelope.dest) {
+            for (_, nemesis) in &mut self.nemeses {
+                envelope = nemesis.tamper(envelope, &mut self.rng);
+            }
+        }
+        match (self.chain_event)(&envelope.body) {
+            Some(ChainEvent::Forward) => self.statistics.forwards += 1,
+            Some(ChainEvent::Rollback) => self.statistics.rollbacks += 1,
+            None => (),
+        }
+        let pair = (envelope.src.clone(), envelope.dest.clone());
+        if self.is_client(&envelope.dest) {
+            let latency = self.latencies.get(&pair).copied();
+            let latency = latency.map_or(Duration::ZERO, |latency| latency.sample(&mut self.rng));
+            self.observe(sent_at + latency, &envelope);
+            self.trace.0.push(envelope);
+        } else if self.is_dropped(&envelope) {
+            self.statistics.dropped += 1;
+        } else {
+            let fate = self
+                .nemeses
+                .iter_mut()
+                .map(|(_, nemesis)| nemesis.fate(&envelope, &mut self.rng))
+                .find(|fate| *fate != Fate::Deliver)
+                .unwrap_or(Fate::Deliver);
+            if fate != Fate::Deliver {
+                self.log_fault(sent_at, Inflicted::Message(envelope.clone(), fate));
+            }
+            match fate {
+                Fate::Deliver => self.enqueue(sent_at, envelope, Duration::ZERO),
+                Fate::Drop => self.statistics.dropped += 1,
+                Fate::Duplicate => {
+                    self.enqueue(sent_at, envelope.clone(), Duration::ZERO);
+                    self.enqueue(sent_at, envelope, Duration::ZERO);
+                }
+                Fate::Delay(delay) => self.enqueue(sent_at, envelope, delay),
+            }
+        }
+    }
+
+    /// Enqueue a message sent by a node to another one, arriving after the latency of their
+    /// link, and `delay` on top of it.
+    fn enqueue(&mut self, sent_at: Instant, envelope: Envelope<Msg>, delay: Duration) {
+        let pair = (envelope.src.clone(), envelope.dest.clone());
+        let latency = self.latencies.get(&pair).copied();
+        let latency = latency
+            .unwrap_or(self.default_latency)
+            .sample(&mut self.rng)
+            + self.transmission_time(&envelope);
+        self.heap.push(Reverse(Queued {
+            entry: Entry {
+                arrival_time: sent_at + latency + delay,
+                envelope,
+            },
+            sent_at,
+        }));
+    }
+
+    /// Advance the clock of all running nodes to `now`, or to when they are done handling
+    /// their last message if later, routing the messages sent by the timers that fired until
+    /// then. Returns whether any message was sent.
+    fn advance_time(&mut self, now: Instant) -> bool {
+        let mut outputs = Vec::new();
+        for (node_id, node) in self.nodes.iter_mut() {
+            let skew = self.skews.get(node_id).copied().unwrap_or_default();
+            let now = self.busy.get(node_id).map_or(now, |busy| now.max(*busy));
+            match node.tick(now + skew) {
+                Ok(sent) => outputs.extend(sent.into_iter().map(|(sent_at, envelope)| {
+                    // back to the world's clock
+                    let sent_at = sent_at.checked_sub(skew).unwrap_or(now);
+                    (node_id.clone(), (sent_at, envelope))
+                })),
+                Err(err) => panic!("{}", err),
+            }
+        }
+        let fired = !outputs.is_empty();
+        for (node_id, (sent_at, envelope)) in outputs {
+            self.record(sent_at, &node_id, None, std::slice::from_ref(&envelope));
+            self.route(sent_at, envelope);
+        }
+        fired
+    }
+
+    /// Simulate a 'World' of interconnected nodes
+    /// see https://github.com/pragma-org/simulation-testing/blob/main/blog/dist/04-simulation-testing-main-loop.md
+    ///
+    /// Before delivering a message, the clocks of all nodes are advanced to its arrival time.
+    /// If this fires any timers, the message is put back, as the messages they sent may have
+    /// to be delivered first. So is a message arriving at a node still busy handling an earlier
+    /// one, until the node is done.
+    pub fn step_world(&mut self) -> Next {
+        if self.violation.is_some() {
+            return Next::Done;
+        }
+
+        if let Some(Fault { at, action }) = self.pop_due_fault() {
+            self.inflict(at, action);
+            return Next::Continue;
+        }
+        if self.unleash_nemesis() {
+            return Next::Continue;
+        }
+
+        self.statistics.max_heap_depth = self.statistics.max_heap_depth.max(self.heap.len());
+        let Some(Reverse(mut queued)) = self.heap.pop() else {
+            // nothing can happen anymore, so pending liveness obligations are violations
+            self.check_monitors(None);
+            return Next::Done;
+        };
+        if let Some(busy) = self.busy.get(&queued.entry.envelope.dest) {
+            if *busy > queued.entry.arrival_time {
+                queued.entry.arrival_time = *busy;
+                self.heap.push(Reverse(queued));
+                return Next::Continue;
+            }
+        }
+        if self.advance_time(queued.entry.arrival_time) {
+            self.heap.push(Reverse(queued));
+            return Next::Continue;
+        }
+        self.check_monitors(Some(queued.entry.arrival_time));
+        self.now = Some(queued.entry.arrival_time);
+
+        let Queued {
+            entry: Entry {
+                arrival_time,
+                envelope,
+            },
+            sent_at,
+        } = queued;
+        if self.nodes.contains_key(&envelope.dest) {
+            self.measure(arrival_time, sent_at, &envelope.dest);
+        }
+        match self.nodes.get_mut(&envelope.dest) {
+            Some(node) => {
+                let pre_state = node.state();
+                let outgoing = match self.runtime.block_on(node.handle(envelope.clone())) {
+                    Ok(outgoing) => outgoing,
+                    Err(err) if err.is::<Crashed>() => {
+                        self.statistics.dropped += 1;
+                        if self.is_client(&envelope.src) {
+                            self.observe(arrival_time, &envelope);
+                            self.trace.0.push(envelope.clone());
+                        }
+                        self.inflict(arrival_time, NemesisAction::Crash(envelope.dest.clone()));
+                        if self.respawn.is_some() {
+                            self.inflict(arrival_time, NemesisAction::Restart(envelope.dest));
+                        }
+                        return Next::Continue;
+                    }
+                    Err(err) => panic!("{}", err),
+                };
+                let post_state = node.state();
+                self.statistics.delivered += 1;
+                let done_at = arrival_time + self.processing_time(&envelope.dest, &envelope);
+                if done_at > arrival_time {
+                    self.busy.insert(envelope.dest.clone(), done_at);
+                }
+                self.steps.push(Step {
+                    node: envelope.dest.clone(),
+                    pre_state,
+                    envelope: envelope.clone(),
+                    post_state,
+                    outgoing: outgoing.clone(),
+                });
+                self.record(arrival_time, &envelope.dest, Some(&envelope), &outgoing);
+                self.observe(arrival_time, &envelope);
+                if self.is_client(&envelope.src) {
+                    self.trace.0.push(envelope);
+                }
+                for msg in outgoing {
+                    self.route(done_at, msg);
+                }
+                Next::Continue
+            }
+            None if self.crashed.contains(&envelope.dest) => {
+                // the message is lost, but clients still have sent it
+                self.statistics.dropped += 1;
+                if self.is_client(&envelope.src) {
+                    self.observe(arrival_time, &envelope);
+                    self.trace.0.push(envelope);
+                }
+                Next::Continue
+            }
+            None => panic!("unknown destination node '{}'", envelope.dest),
+        }
+    }
+
+    /// Run until no message is left to deliver, or a temporal property is violated. What
+    /// clients observed is in the [`World::trace`].
+    pub fn run_world(&mut self) -> Statistics {
+        while self.step_world() == Next::Continue {}
+        self.statistics
+    }
+}
+
+impl<Msg> Drop for World<Msg> {
+    fn drop(&mut self) {
+        self.nodes
+            .values_mut()
+            .for_each(|node_handle| node_handle.close());
+    }
+}
+
+/// The messages [`simulate`] has clients and nodes exchange.
+pub trait Message: Clone + PartialEq + Debug + Serialize + DeserializeOwned + 'static {
+    /// The message starting a node of the `amaru-sim` binary as `node_id`, one of
+    /// `node_ids`, when a failing case is written out to be reproduced.
+    fn init(node_id: NodeId, node_ids: Vec<NodeId>) -> Self;
+}
+
+impl Message for EchoMessage {
+    fn init(node_id: NodeId, node_ids: Vec<NodeId>) -> Self {
+        EchoMessage::Init {
+            msg_id: 0,
+            node_id,
+            node_ids,
+        }
+    }
+}
+
+impl Message for BroadcastMessage {
+    fn init(node_id: NodeId, node_ids: Vec<NodeId>) -> Self {
+        BroadcastMessage::Init {
+            msg_id: 0,
+            node_id,
+            node_ids,
+        }
+    }
+}
+
+/// How [`simulate`] reports the minimal failing case, on top of the panic message listing
+/// its inputs.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Export the trace of the failing case to this file, see [`World::with_trace_export`].
+    pub trace_export: Option<PathBuf>,
+    /// Add a sequence diagram of the failing case's trace to the panic message, see
+    /// [`Trace::to_mermaid`].
+    pub mermaid: bool,
+    /// Write the inputs of the failing case to files and add the `amaru-sim` command running
+    /// it on its own to the panic message.
+    pub reproduction: Option<Reproduction>,
+    /// Replay the cases of this corpus before generating new ones, and add the failing case to
+    /// it.
+    pub corpus: Option<Corpus>,
+}
+
+/// Where to write the inputs of a failing case, and how to run `amaru-sim` on them.
+#[derive(Debug, Clone, Default)]
+pub struct Reproduction {
+    /// The directory receiving the client messages, as `input.jsonl`, and the faults, as
+    /// `scenario.json`.
+    pub dir: PathBuf,
+    /// The other arguments nodes are started with, e.g. `--security-param` or
+    /// `--stake-distribution-file`.
+    pub args: Vec<String>,
+}
+
+impl Reproduction {
+    /// Write the inputs of a run of `number_of_nodes` nodes, with the world seeded with
+    /// `world_seed`, and return the command running it. Client messages are replayed in
+    /// order, right after each other, as `amaru-sim` doesn't know of their arrival times.
+    fn write<Msg: Message>(
+        &self,
+        number_of_nodes: u8,
+        world_seed: u64,
+        schedule: &Schedule<Msg>,
+    ) -> std::io::Result<String> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut messages: Vec<&Entry<Msg>> =
+            schedule.messages.iter().map(|entry| &entry.0).collect();
+        messages.sort();
+        let node_ids: Vec<NodeId> = (1..=number_of_nodes).map(|i| format!("n{}", i)).collect();
+        let init = Envelope {
+            src: "c0".to_string(),
+            dest: "n1".to_string(),
+            body: Msg::init("n1".to_string(), node_ids),
+        };
+        let mut input = serde_json::to_string(&init)?;
+        for entry in messages {
+            input.push('\n');
+            input += &serde_json::to_string(&entry.envelope)?;
+        }
+        input.push('\n');
+        let input_file = self.dir.join("input.jsonl");
+        std::fs::write(&input_file, input)?;
+
+        let actions: Vec<(Duration, NemesisAction)> = schedule
+            .faults
+            .iter()
+            .map(|fault| {
+                (
+                    fault.at.saturating_duration_since(epoch()),
+                    fault.action.clone(),
+                )
+            })
+            .collect();
+        let scenario_file = self.dir.join("scenario.json");
+        std::fs::write(
+            &scenario_file,
+            serde_json::to_string_pretty(&Scenario::from_actions(&actions))?,
+        )?;
+
+        let mut command = format!(
+            "amaru-sim --number-of-nodes {} --seed {} --scenario {}",
+            number_of_nodes,
+            world_seed,
+            scenario_file.display()
+        );
+        for arg in &self.args {
+            command.push(' ');
+            command += arg;
+        }
+        command += &format!(" < {}", input_file.display());
+        Ok(command)
+    }
+}
+
+/// What [`simulate`] checks of each run.
+pub struct Properties<Msg> {
+    /// Checked over the trace once the world has run out of messages.
+    pub trace: fn(Trace<Msg>) -> Result<(), String>,
+    /// Checked while the world runs, see [`World::with_temporal`].
+    pub temporal: Vec<Temporal<Msg>>,
+    /// Checked over the deliveries to nodes, along with the state of the nodes around them,
+    /// once the world has run out of messages, see [`World::steps`].
+    pub steps: fn(&[Step<Msg>]) -> Result<(), String>,
+    /// Checked over the heap depth, mailbox backlogs and delivery latencies of the run, e.g.
+    /// to assert that queues stay bounded under load, see [`World::metrics`].
+    pub metrics: fn(&Metrics) -> Result<(), String>,
+}
+
+impl<Msg> From<fn(Trace<Msg>) -> Result<(), String>> for Properties<Msg> {
+    fn from(trace: fn(Trace<Msg>) -> Result<(), String>) -> Self {
+        Properties {
+            trace,
+            temporal: Vec::new(),
+            steps: |_| Ok(()),
+            metrics: |_| Ok(()),
+        }
+    }
+}
+
+/// A case for which the properties do not hold, found by the runner seeded with `seed`.
+struct Counterexample<Msg> {
+    seed: u64,
+    reason: String,
+    schedule: Schedule<Msg>,
+    world_seed: u64,
+}
+
+fn make_world<Msg: Message>(
+    number_of_nodes: u8,
+    spawn: fn() -> BoxedNode<Msg>,
+    temporal: &[Temporal<Msg>],
+    schedule: Schedule<Msg>,
+    world_seed: u64,
+) -> World<Msg> {
+    let node_handles: Vec<_> = (1..=number_of_nodes)
+        .map(|i| (format!("n{}", i), spawn()))
+        .collect();
+
+    let mut world = World::new(schedule.messages, node_handles)
+        .with_respawn(move |_| spawn())
+        .with_seed(world_seed)
+        .with_temporal(temporal.to_vec());
+    for Fault { at, action } in schedule.faults {
+        world.schedule(at, action);
+    }
+    world
+}
+
+/// Check `properties` against a world which has run out of messages.
+fn check<Msg: Message>(world: &World<Msg>, properties: &Properties<Msg>) -> Result<(), String> {
+    if let Some(violation) = world.violation() {
+        return Err(violation.to_string());
+    }
+    (properties.trace)(world.trace().clone())
+        .and_then(|()| (properties.steps)(world.steps()))
+        .and_then(|()| (properties.metrics)(world.metrics()))
+}
+
+/// Run the cases of `corpus`, returning the first one still failing, if any.
+fn replay_corpus<Msg: Message>(
+    corpus: &Corpus,
+    number_of_nodes: u8,
+    spawn: fn() -> BoxedNode<Msg>,
+    properties: &Properties<Msg>,
+) -> Result<(), Counterexample<Msg>> {
+    let cases = corpus.cases::<Msg>().unwrap_or_else(|e| {
+        panic!(
+            "Failed to read the corpus in {}: {:?}",
+            corpus.dir().display(),
+            e
+        )
+    });
+    let mut statistics = Statistics::default();
+    for (_, case) in &cases {
+        let schedule = Schedule::from_case(case);
+        let mut world = make_world(
+            number_of_nodes,
+            spawn,
+            &properties.temporal,
+            schedule.clone(),
+            case.world_seed,
+        );
+        statistics.combine(&world.run_world());
+        if let Err(reason) = check(&world, properties) {
+            return Err(Counterexample {
+                seed: case.seed,
+                reason,
+                schedule,
+                world_seed: case.world_seed,
+            });
+        }
+    }
+    if !cases.is_empty() {
+        println!(
+            "Replayed {} case(s) of {}: {}",
+            cases.len(),
+            corpus.dir().display(),
+            statistics
+        );
+    }
+    Ok(())
+}
+
+/// Run the cases of `config` with a runner seeded with `seed`, returning the minimal
+/// failing one, if any.
+fn search<Msg, S, F>(
+    config: Config,
+    seed: u64,
+    number_of_nodes: u8,
+    spawn: fn() -> BoxedNode<Msg>,
+    generate_schedule: &ScheduleStrategy<S, F>,
+    properties: Properties<Msg>,
+) -> Result<Statistics, Counterexample<Msg>>
+where
+    Msg: Message,
+    S: Strategy<Value = Msg>,
+    F: Strategy<Value = Vec<(Duration, NemesisAction)>>,
+{
+    let statistics = Cell::new(Statistics::default());
+    let mut runner = SimRng::new(seed).runner(config);
+    // each case gets its own seed for the world, derived from the runner's seeded RNG
+    let generate_world = (generate_schedule, any::<u64>().no_shrink());
+    let result = runner.run(&generate_world, |(schedule, world_seed)| {
+        let mut world = make_world(
+            number_of_nodes,
+            spawn,
+            &properties.temporal,
+            schedule,
+            world_seed,
+        );
+        let mut total = statistics.get();
+        total.combine(&world.run_world());
+        statistics.set(total);
+
+        if let Err(reason) = check(&world, &properties) {
+            prop_assert!(false, "{}", reason);
+        }
+        Ok(())
+    });
+    match result {
+        Ok(_) => Ok(statistics.get()),
+        Err(TestError::Fail(what, (schedule, world_seed))) => Err(Counterexample {
+            seed,
+            reason: what.to_string(),
+            schedule,
+            world_seed,
+        }),
+        Err(TestError::Abort(e)) => panic!("Test aborted: {}", e),
+    }
+}
+
+fn report_counterexample<Msg: Message>(
+    counterexample: Counterexample<Msg>,
+    number_of_nodes: u8,
+    spawn: fn() -> BoxedNode<Msg>,
+    temporal: &[Temporal<Msg>],
+    report: &Report,
+) -> ! {
+    let Counterexample {
+        seed,
+        reason,
+        schedule,
+        world_seed,
+    } = counterexample;
+    // runs are deterministic, so replaying the minimal case yields the same trace
+    let mut world = make_world(
+        number_of_nodes,
+        spawn,
+        temporal,
+        schedule.clone(),
+        world_seed,
+    );
+    if let Some(path) = &report.trace_export {
+        match File::create(path) {
+            Ok(file) => world = world.with_trace_export(BufWriter::new(file)),
+            Err(e) => eprintln!("Failed to export trace to {}: {}", path.display(), e),
+        }
+    }
+    let statistics = world.run_world();
+    let trace = world.trace();
+
+    let mut err = String::new();
+    schedule
+        .messages
+        .iter()
+        .for_each(|entry| err += &format!("  {:?}\n", entry.0.envelope));
+    schedule
+        .faults
+        .iter()
+        .for_each(|fault| err += &format!("  {:?}\n", fault.action));
+    if report.mermaid {
+        err += &format!("\nSequence diagram:\n\n{}", trace.to_mermaid());
+    }
+    if let Some(reproduction) = &report.reproduction {
+        match reproduction.write(number_of_nodes, world_seed, &schedule) {
+            Ok(command) => err += &format!("\nReproduce with:\n\n  {}\n", command),
+            Err(e) => eprintln!(
+                "Failed to write the failing case to {}: {}",
+                reproduction.dir.display(),
+                e
+            ),
+        }
+    }
+    if let Some(corpus) = &report.corpus {
+        match corpus.add(&schedule.to_case(seed, world_seed)) {
+            Ok(path) => err += &format!("\nAdded to the corpus as {}\n", path.display()),
+            Err(e) => eprintln!(
+                "Failed to add the failing case to the corpus in {}: {:?}",
+                corpus.dir().display(),
+                e
+            ),
+        }
+    }
+    panic!(
+        "Found minimal failing case (seed: {}):\n\n{}\nError message:\n\n  {}\n\nStatistics:\n\n  {}",
+        seed, err, reason, statistics
+    )
+}
+
+#[allow(dead_code)]
+pub fn simulate<Msg, S, F>(
+    config: Config,
+    seed: u64,
+    number_of_nodes: u8,
+    spawn: fn() -> BoxedNode<Msg>,
+    generate_schedule: ScheduleStrategy<S, F>,
+    properties: impl Into<Properties<Msg>>,
+    report: Report,
+) where
+    Msg: Message,
+    S: Strategy<Value = Msg>,
+    F: Strategy<Value = Vec<(Duration, NemesisAction)>>,
+{
+    let properties = properties.into();
+    let temporal = properties.temporal.clone();
+    let cases = config.cases;
+    let outcome = match &report.corpus {
+        Some(corpus) => replay_corpus(corpus, number_of_nodes, spawn, &properties),
+        None => Ok(()),
+    };
+    match outcome.and_then(|()| {
+        search(
+            config,
+            seed,
+            number_of_nodes,
+            spawn,
+            &generate_schedule,
+            properties,
+        )
+    }) {
+        Ok(statistics) => println!("Statistics over {} case(s): {}", cases, statistics),
+        Err(counterexample) => {
+            report_counterexample(counterexample, number_of_nodes, spawn, &temporal, &report)
+        }
+    }
+}
+
+/// Like [`simulate`], but spreading the cases over `threads` runners, each on its own thread.
+///
+/// Every runner gets its own seed, derived from `seed`, and its share of the cases. A failing
+/// case is reported with the seed of the runner that found it: passing that seed to
+/// [`simulate`] finds the same case, on a single thread. When several runners fail, the
+/// failure of the first of them is reported, so the outcome doesn't depend on thread
+/// scheduling.
+///
+/// Temporal properties are not thread-safe, hence `properties` builds them for each runner.
+#[allow(dead_code, clippy::too_many_arguments)]
+pub fn simulate_parallel<Msg, S, F>(
+    threads: usize,
+    config: Config,
+    seed: u64,
+    number_of_nodes: u8,
+    spawn: fn() -> BoxedNode<Msg>,
+    generate_schedule: ScheduleStrategy<S, F>,
+    properties: impl Fn() -> Properties<Msg> + Sync,
+    report: Report,
+) where
+    Msg: Message + Send,
+    S: Strategy<Value = Msg> + Sync,
+    F: Strategy<Value = Vec<(Duration, NemesisAction)>> + Sync,
+{
+    let cases = config.cases;
+    let threads = threads.max(1) as u32;
+    let mut rng = SimRng::new(seed);
+    let runners: Vec<(u64, Config)> = (0..threads)
+        .map(|i| {
+            let cases = config.cases / threads + u32::from(i < config.cases % threads);
+            (
+                rng.next_u64(),
+                Config {
+                    cases,
+                    ..config.clone()
+                },
+            )
+        })
+        .collect();
+
+    if let Some(corpus) = &report.corpus {
+        if let Err(counterexample) = replay_corpus(corpus, number_of_nodes, spawn, &properties()) {
+            report_counterexample(
+                counterexample,
+                number_of_nodes,
+                spawn,
+                &properties().temporal,
+                &report,
+            )
+        }
+    }
+
+    let outcome = std::thread::scope(|scope| {
+        let handles: Vec<_> = runners
+            .into_iter()
+            .map(|(seed, config)| {
+                let generate_schedule = &generate_schedule;
+                let properties = &properties;
+                scope.spawn(move || {
+                    search(
+                        config,
+                        seed,
+                        number_of_nodes,
+                        spawn,
+                        generate_schedule,
+                        properties(),
+                    )
+                })
+            })
+            .collect();
+        let mut first = None;
+        let mut statistics = Statistics::default();
+        for handle in handles {
+            match handle.join() {
+                Ok(Ok(runner_statistics)) => statistics.combine(&runner_statistics),
+                Ok(Err(counterexample)) => {
+                    first.get_or_insert(counterexample);
+                }
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+        first.map_or(Ok(statistics), Err)
+    });
+
+    match outcome {
+        Ok(statistics) => println!("Statistics over {} case(s): {}", cases, statistics),
+        Err(counterexample) => report_counterexample(
+            counterexample,
+            number_of_nodes,
+            spawn,
+            &properties().temporal,
+            &report,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::sync::ChainSyncMessage;
+    use pure_stage::{simulation::SimulationBuilder, StageGraph, StageRef};
+    use slot_arithmetic::Slot;
+    use std::cell::RefCell;
+
+    #[test]
+    fn run_stops_when_no_message_to_process_is_left() {
+        let mut world = World::<EchoMessage>::new(Vec::new(), Vec::new());
+
+        assert_eq!(world.run_world(), Statistics::default());
+        assert_eq!(world.trace(), &Trace(Vec::new()));
+    }
+
+    fn spawn_echo_node() -> BoxedNode<EchoMessage> {
+        println!("*** Spawning node!");
+        let mut network = SimulationBuilder::default();
+        let stage = network.stage(
+            "echo",
+            async |(mut state, out), msg: Envelope<EchoMessage>, eff| {
+                if let EchoMessage::Echo { msg_id, echo } = &msg.body {
+                    state += 1;
+                    // Insert a bug every 5 messages.
+                    let echo_response = if state % 5 == 0 {
+                        echo.to_string().to_uppercase()
+                    } else {
+                        echo.to_string()
+                    };
+                    let reply = Envelope {
+                        src: msg.dest,
+                        dest: msg.src,
+                        body: EchoMessage::EchoOk {
+                            msg_id: state,
+                            in_reply_to: *msg_id,
+                            echo: echo_response,
+                        },
+                    };
+                    println!(" ==> {:?}", reply);
+                    eff.send(&out, reply).await;
+                    Ok((state, out))
+                } else {
+                    panic!("Got a message that wasn't an echo: {:?}", msg.body)
+                }
+            },
+            (0u64, StageRef::noop::<Envelope<EchoMessage>>()),
+        );
+        let (output, rx) = network.output("output");
+        let stage = network.wire_up(stage, |state| state.1 = output.without_state());
+        let running = network.run();
+
+        PureStageNode::new(rx, stage, running).boxed()
+    }
+
+    #[test]
+    fn messages_to_crashed_node_are_dropped_until_it_restarts() {
+        let start = Instant::now();
+        let echo = |msg_id: u64, at: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_secs(at),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let mut world = World::new(
+            vec![echo(1, 1), echo(2, 3), echo(3, 5)],
+            vec![("n1".to_string(), spawn_echo_node())],
+        )
+        .with_respawn(|_| spawn_echo_node());
+        world.schedule(
+            start + Duration::from_secs(2),
+            NemesisAction::Crash("n1".to_string()),
+        );
+        world.schedule(
+            start + Duration::from_secs(4),
+            NemesisAction::Restart("n1".to_string()),
+        );
+
+        world.run_world();
+        let answered = world
+            .trace()
+            .0
+            .iter()
+            .filter_map(|msg| match &msg.body {
+                EchoMessage::EchoOk { in_reply_to, .. } => Some(*in_reply_to),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(answered, vec![1, 3]);
+    }
+
+    #[test]
+    fn steps_record_node_state_around_deliveries() {
+        let received = Rc::new(RefCell::new(0u64));
+        let node = {
+            let handle_received = received.clone();
+            let state_received = received.clone();
+            FnNode::new(
+                move |_msg: Envelope<EchoMessage>| {
+                    *handle_received.borrow_mut() += 1;
+                    Ok(Vec::new())
+                },
+                || (),
+            )
+            .with_state(move || serde_json::json!({ "received": *state_received.borrow() }))
+            .boxed()
+        };
+        let start = Instant::now();
+        let echo = |msg_id: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_secs(msg_id),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let mut world = World::new(vec![echo(1), echo(2)], vec![("n1".to_string(), node)]);
+
+        world.run_world();
+
+        let states = world
+            .steps()
+            .iter()
+            .map(|step| (step.pre_state.clone(), step.post_state.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            vec![
+                (
+                    Some(serde_json::json!({ "received": 0 })),
+                    Some(serde_json::json!({ "received": 1 }))
+                ),
+                (
+                    Some(serde_json::json!({ "received": 1 })),
+                    Some(serde_json::json!({ "received": 2 }))
+                ),
+            ]
+        );
+    }
+
+    /// A node answering echoes with the number of messages it received so far.
+    fn spawn_counting_node() -> BoxedNode<EchoMessage> {
+        let received = Rc::new(RefCell::new(0u64));
+        let handle_received = received.clone();
+        let snapshot_received = received.clone();
+        FnNode::new(
+            move |msg: Envelope<EchoMessage>| {
+                *handle_received.borrow_mut() += 1;
+                let EchoMessage::Echo { msg_id, echo } = msg.body else {
+                    return Ok(Vec::new());
+                };
+                Ok(vec![Envelope {
+                    src: msg.dest,
+                    dest: msg.src,
+                    body: EchoMessage::EchoOk {
+                        msg_id: *handle_received.borrow(),
+                        in_reply_to: msg_id,
+                        echo,
+                    },
+                }])
+            },
+            || (),
+        )
+        .with_snapshots(
+            move || Rc::new(*snapshot_received.borrow()) as NodeSnapshot,
+            move |snapshot| {
+                let snapshot = snapshot
+                    .downcast_ref::<u64>()
+                    .ok_or_else(|| anyhow!("not a counter snapshot"))?;
+                *received.borrow_mut() = *snapshot;
+                Ok(())
+            },
+        )
+        .boxed()
+    }
+
+    #[test]
+    fn restoring_a_checkpoint_replays_the_same_future() {
+        let start = Instant::now();
+        let echo = |msg_id: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_secs(msg_id),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let mut world = World::new(
+            vec![echo(1), echo(2), echo(3)],
+            vec![("n1".to_string(), spawn_counting_node())],
+        )
+        .with_respawn(|_| spawn_counting_node());
+        let answers = |trace: &[Envelope<EchoMessage>]| {
+            trace
+                .iter()
+                .filter_map(|msg| match &msg.body {
+                    EchoMessage::EchoOk { msg_id, .. } => Some(*msg_id),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(world.step_world(), Next::Continue);
+        let checkpoint = world.checkpoint().unwrap();
+        world.run_world();
+        let future = world.steps().to_vec();
+        assert_eq!(answers(&world.trace.0), vec![1, 2, 3]);
+
+        // another future, in which the node crashes after the second message
+        world.restore(&checkpoint).unwrap();
+        world.schedule(
+            start + Duration::from_millis(2500),
+            NemesisAction::Crash("n1".to_string()),
+        );
+        world.run_world();
+        assert_eq!(answers(&world.trace.0), vec![1, 2]);
+
+        world.restore(&checkpoint).unwrap();
+        world.run_world();
+        assert_eq!(answers(&world.trace.0), vec![1, 2, 3]);
+        assert_eq!(world.steps(), future.as_slice());
+    }
+
+    #[test]
+    fn partitioned_nodes_do_not_exchange_messages_until_healed() {
+        // n1 forwards echoes to n2, which answers the client
+        let relay = FnNode::new(
+            |msg: Envelope<EchoMessage>| {
+                Ok(vec![Envelope {
+                    src: "n1".to_string(),
+                    dest: "n2".to_string(),
+                    body: msg.body,
+                }])
+            },
+            || (),
+        );
+        let responder = FnNode::new(
+            |msg: Envelope<EchoMessage>| match msg.body {
+                EchoMessage::Echo { msg_id, echo } => Ok(vec![Envelope {
+                    src: "n2".to_string(),
+                    dest: "c1".to_string(),
+                    body: EchoMessage::EchoOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        echo,
+                    },
+                }]),
+                _ => Ok(Vec::new()),
+            },
+            || (),
+        );
+        let start = Instant::now();
+        let echo = |msg_id: u64, at: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_secs(at),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let mut world = World::new(
+            vec![echo(1, 1), echo(2, 3)],
+            vec![
+                ("n1".to_string(), relay.boxed()),
+                ("n2".to_string(), responder.boxed()),
+            ],
+        );
+        world.schedule(
+            start,
+            NemesisAction::Partition("n2".to_string(), "n1".to_string()),
+        );
+        world.schedule(
+            start + Duration::from_secs(2),
+            NemesisAction::Heal("n1".to_string(), "n2".to_string()),
+        );
+
+        let statistics = world.run_world();
+        let answered = world
+            .trace()
+            .0
+            .iter()
+            .filter_map(|msg| match &msg.body {
+                EchoMessage::EchoOk { in_reply_to, .. } => Some(*in_reply_to),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(answered, vec![2]);
+        // both echoes and the second forward reach a node, the first forward is dropped
+        assert_eq!((statistics.delivered, statistics.dropped), (3, 1));
+        assert_eq!(statistics.max_heap_depth, 2);
     }
-}
 
-impl<Msg: PartialEq> Ord for Entry<Msg> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.arrival_time.cmp(&other.arrival_time)
+    fn spawn_node_crashing_on_first_echo() -> BoxedNode<EchoMessage> {
+        FnNode::new(
+            |msg: Envelope<EchoMessage>| match msg.body {
+                EchoMessage::Echo { msg_id: 1, .. } => Err(Crashed.into()),
+                EchoMessage::Echo { msg_id, echo } => Ok(vec![Envelope {
+                    src: msg.dest,
+                    dest: msg.src,
+                    body: EchoMessage::EchoOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        echo,
+                    },
+                }]),
+                _ => Ok(Vec::new()),
+            },
+            || (),
+        )
+        .boxed()
     }
-}
 
-impl<Msg: PartialEq> Eq for Entry<Msg> {}
+    #[test]
+    fn nodes_crashing_on_a_message_are_restarted_right_away() {
+        let start = Instant::now();
+        let echo = |msg_id: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_secs(msg_id),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let respawned = Rc::new(RefCell::new(0));
+        let count_respawns = respawned.clone();
+        let mut world = World::new(
+            vec![echo(1), echo(2)],
+            vec![("n1".to_string(), spawn_node_crashing_on_first_echo())],
+        )
+        .with_respawn(move |_| {
+            *count_respawns.borrow_mut() += 1;
+            spawn_node_crashing_on_first_echo()
+        });
 
-type NodeId = String;
+        let statistics = world.run_world();
+        let answered = world
+            .trace()
+            .0
+            .iter()
+            .filter_map(|msg| match &msg.body {
+                EchoMessage::EchoOk { in_reply_to, .. } => Some(*in_reply_to),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
 
-pub struct NodeHandle {
-    handle:
-        Box<dyn FnMut(Envelope<EchoMessage>) -> Result<Vec<Envelope<EchoMessage>>, anyhow::Error>>,
-    close: Box<dyn FnMut()>,
-}
+        assert_eq!(answered, vec![2]);
+        assert_eq!(*respawned.borrow(), 1);
+        assert_eq!(statistics.dropped, 1);
+    }
 
-#[allow(unused)]
-pub fn pure_stage_node_handle(
-    mut rx: Receiver<Envelope<EchoMessage>>,
-    stage: StageRef<Envelope<EchoMessage>, (u64, StageRef<Envelope<EchoMessage>, Void>)>,
-    mut running: SimulationRunning,
-) -> anyhow::Result<NodeHandle> {
-    let handle = Box::new(move |msg: Envelope<EchoMessage>| {
-        running.enqueue_msg(&stage, [msg]);
-        running.run_until_blocked().assert_idle();
-        Ok(rx.drain().collect::<Vec<_>>())
-    });
+    #[test]
+    fn skewed_clocks_run_ahead_of_the_world() {
+        let advanced = Rc::new(RefCell::new(Vec::new()));
+        let record_advance = advanced.clone();
+        let node =
+            FnNode::new(|_: Envelope<EchoMessage>| Ok(Vec::new()), || ()).with_timers(move |now| {
+                record_advance.borrow_mut().push(now);
+                Ok(Vec::new())
+            });
+        let start = Instant::now();
+        let echo = |msg_id: u64, at: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_secs(at),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let mut world = World::new(
+            vec![echo(1, 0), echo(2, 2)],
+            vec![("n1".to_string(), node.boxed())],
+        );
+        world.schedule(
+            start + Duration::from_secs(1),
+            NemesisAction::SkewClock("n1".to_string(), Duration::from_secs(5)),
+        );
 
-    let close = Box::new(move || ());
+        world.run_world();
 
-    Ok(NodeHandle { handle, close })
-}
+        assert_eq!(
+            *advanced.borrow(),
+            vec![start, start + Duration::from_secs(7)]
+        );
+    }
 
-#[allow(unused)]
-pub fn pipe_node_handle(filepath: &Path, args: &[&str]) -> anyhow::Result<NodeHandle> {
-    let mut child = Command::new(filepath)
-        .args(args)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .map_err(|e| anyhow!("Failed to create process: {}", e))?;
-    let mut stdin = child.stdin.take().ok_or(anyhow!("Failed to take stdin"))?;
-    let mut stdout = child
-        .stdout
-        .take()
-        .ok_or(anyhow!("Failed to take stdout"))?;
+    #[test]
+    fn messages_take_the_latency_of_their_link_in_each_direction() {
+        // n1 sends each echo to n2 and n3, which answer n1
+        let relay = FnNode::new(
+            |msg: Envelope<EchoMessage>| match msg.body {
+                EchoMessage::Echo { msg_id, echo } => Ok(["n2", "n3"]
+                    .into_iter()
+                    .map(|dest| Envelope {
+                        src: "n1".to_string(),
+                        dest: dest.to_string(),
+                        body: EchoMessage::Echo {
+                            msg_id,
+                            echo: echo.clone(),
+                        },
+                    })
+                    .collect()),
+                _ => Ok(Vec::new()),
+            },
+            || (),
+        );
+        let responder = |node_id: &'static str| {
+            FnNode::new(
+                move |msg: Envelope<EchoMessage>| match msg.body {
+                    EchoMessage::Echo { msg_id, echo } => Ok(vec![Envelope {
+                        src: node_id.to_string(),
+                        dest: "n1".to_string(),
+                        body: EchoMessage::EchoOk {
+                            msg_id,
+                            in_reply_to: msg_id,
+                            echo,
+                        },
+                    }]),
+                    _ => Ok(Vec::new()),
+                },
+                || (),
+            )
+        };
+        let echo = Reverse(Entry {
+            arrival_time: Instant::now(),
+            envelope: Envelope {
+                src: "c1".to_string(),
+                dest: "n1".to_string(),
+                body: EchoMessage::Echo {
+                    msg_id: 1,
+                    echo: "Please echo 1".to_string(),
+                },
+            },
+        });
+        let (millis, secs) = (Duration::from_millis, Duration::from_secs);
+        let jittery = Latency {
+            base: secs(2),
+            jitter: secs(1),
+        };
+        let mut world = World::new(
+            vec![echo],
+            vec![
+                ("n1".to_string(), relay.boxed()),
+                ("n2".to_string(), responder("n2").boxed()),
+                ("n3".to_string(), responder("n3").boxed()),
+            ],
+        )
+        .with_seed(42)
+        .with_latency_matrix(
+            &["n1", "n2", "n3"],
+            &[
+                vec![
+                    Latency::default(),
+                    Latency::fixed(millis(10)),
+                    Latency::fixed(secs(5)),
+                ],
+                vec![jittery, Latency::default(), Latency::default()],
+                vec![
+                    Latency::fixed(millis(10)),
+                    Latency::default(),
+                    Latency::default(),
+                ],
+            ],
+        );
 
-    let handle = Box::new(move |msg: Envelope<EchoMessage>| {
-        let json =
-            serde_json::to_string(&msg).map_err(|e| anyhow!("Failed to encode JSON: {}", e))?;
-        println!("About to write: {}", json);
-        writeln!(stdin, "{}", json)
-            .map_err(|e| anyhow!("Failed to write to child's stdin: {}", e))?;
-        stdin
-            .flush()
-            .map_err(|e| anyhow!("Failed to flush child's stdin: {}", e))?;
+        world.run_world();
 
-        let mut reader = BufReader::new(&mut stdout);
-        let mut line = String::new();
-        reader
-            .read_line(&mut line)
-            .map_err(|e| anyhow!("Failed to read from child's stdout: {}", e))?;
-
-        println!("Just read: {}", &line);
-        serde_json::from_str(&line)
-            // TODO: Read more than one message? Either make SUT send one message
-            // per line and end by a termination token, or make write a JSON array
-            // of messages?
-            .map(|msg: Envelope<EchoMessage>| vec![msg])
-            .map_err(|e| anyhow!("Failed to decode JSON: {}", e))
-    });
+        let latencies = &world.metrics().latencies;
+        assert_eq!(latencies["n2"], vec![millis(10)]);
+        assert_eq!(latencies["n3"], vec![secs(5)]);
+        // the echo of the client, then the answers of n2 and n3, in that order
+        let [_, from_n2, from_n3] = latencies["n1"][..] else {
+            panic!("unexpected deliveries to n1: {:?}", latencies["n1"])
+        };
+        assert!(from_n2 >= secs(2) && from_n2 < secs(3), "{:?}", from_n2);
+        assert_eq!(from_n3, millis(10));
+    }
 
-    let close = Box::new(move || {
-        child
-            .kill()
-            .map_err(|e| anyhow!("Failed to terminate process: {}", e))
-            .ok();
-    });
+    #[test]
+    fn small_messages_overtake_large_ones_on_links_of_limited_bandwidth() {
+        // n1 relays each echo to n2 twice, the large copy first
+        let relay = FnNode::new(
+            |msg: Envelope<EchoMessage>| match msg.body {
+                EchoMessage::Echo { msg_id, echo } => Ok(vec![echo.repeat(1000), echo]
+                    .into_iter()
+                    .map(|echo| Envelope {
+                        src: "n1".to_string(),
+                        dest: "n2".to_string(),
+                        body: EchoMessage::Echo { msg_id, echo },
+                    })
+                    .collect()),
+                _ => Ok(Vec::new()),
+            },
+            || (),
+        );
+        let responder = FnNode::new(
+            |msg: Envelope<EchoMessage>| match msg.body {
+                EchoMessage::Echo { msg_id, echo } => Ok(vec![Envelope {
+                    src: "n2".to_string(),
+                    dest: "c1".to_string(),
+                    body: EchoMessage::EchoOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        echo,
+                    },
+                }]),
+                _ => Ok(Vec::new()),
+            },
+            || (),
+        );
+        let echo = Reverse(Entry {
+            arrival_time: Instant::now(),
+            envelope: Envelope {
+                src: "c1".to_string(),
+                dest: "n1".to_string(),
+                body: EchoMessage::Echo {
+                    msg_id: 1,
+                    echo: "Please echo 1".to_string(),
+                },
+            },
+        });
+        let mut world = World::new(
+            vec![echo],
+            vec![
+                ("n1".to_string(), relay.boxed()),
+                ("n2".to_string(), responder.boxed()),
+            ],
+        )
+        // the large copy takes 13s to transmit, far more than any latency
+        .with_bandwidth("n2", "n1", 1000)
+        .with_message_size(|msg| match msg {
+            EchoMessage::Echo { echo, .. } => echo.len(),
+            _ => 0,
+        });
 
-    Ok(NodeHandle { handle, close })
-}
+        world.run_world();
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Trace(pub Vec<Envelope<EchoMessage>>);
+        let echoed: Vec<usize> = world
+            .trace()
+            .0
+            .iter()
+            .filter_map(|msg| match &msg.body {
+                EchoMessage::EchoOk { echo, .. } => Some(echo.len()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(echoed, vec![13, 13_000]);
+    }
 
-#[derive(Debug, PartialEq)]
-pub enum Next {
-    Done,
-    Continue,
-}
+    #[test]
+    fn messages_wait_for_busy_nodes() {
+        let advanced = Rc::new(RefCell::new(Vec::new()));
+        let record_advance = advanced.clone();
+        let node = FnNode::new(|_: Envelope<EchoMessage>| Ok(Vec::new()), || ())
+            .with_timers(move |now| {
+                record_advance.borrow_mut().push(now);
+                Ok(Vec::new())
+            })
+            .with_processing_time(|_| Duration::from_secs(1));
+        let start = Instant::now();
+        let echo = |msg_id: u64, at: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_millis(at),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let mut world = World::new(
+            vec![echo(1, 0), echo(2, 500), echo(3, 3000)],
+            vec![("n1".to_string(), node.boxed())],
+        );
 
-pub struct World {
-    heap: BinaryHeap<Reverse<Entry<EchoMessage>>>,
-    nodes: BTreeMap<NodeId, NodeHandle>,
-    trace: Trace,
-}
+        let statistics = world.run_world();
 
-#[allow(dead_code)]
-impl World {
-    pub fn new(
-        initial_messages: Vec<Reverse<Entry<EchoMessage>>>,
-        node_handles: Vec<(NodeId, NodeHandle)>,
-    ) -> Self {
-        World {
-            heap: BinaryHeap::from(initial_messages),
-            nodes: node_handles.into_iter().collect(),
-            trace: Trace(Vec::new()),
-        }
+        assert_eq!(statistics.delivered, 3);
+        assert_eq!(
+            *advanced.borrow(),
+            vec![
+                start,
+                start + Duration::from_secs(1),
+                start + Duration::from_secs(3)
+            ]
+        );
     }
 
-    /// Simulate a 'World' of interconnected nodes
-    /// see https://github.com/pragma-org/simulation-testing/blob/main/blog/dist/04-simulation-testing-main-loop.md
-    pub fn step_world(&mut self) -> Next {
-        match self.heap.pop() {
-            Some(Reverse(Entry {
-                arrival_time,
-                envelope,
-            })) =>
-            // TODO: deal with time advance across all nodes
-            // eg. run all nodes whose next action is ealier than msg's arrival time
-            // and enqueue their output messages possibly bailing out and recursing
-            {
-                match self.nodes.get_mut(&envelope.dest) {
-                    Some(node) => match (node.handle)(envelope.clone()) {
-                        Ok(outgoing) => {
-                            let (client_responses, outputs): (
-                                Vec<Envelope<EchoMessage>>,
-                                Vec<Envelope<EchoMessage>>,
-                            ) = outgoing
-                                .into_iter()
-                                .partition(|msg| msg.dest.starts_with("c"));
-                            outputs
-                                .iter()
-                                .map(|envelope| Entry {
-                                    arrival_time: arrival_time + Duration::from_millis(100),
-                                    envelope: envelope.clone(),
-                                })
-                                .for_each(|msg| self.heap.push(Reverse(msg)));
-                            if envelope.src.starts_with("c") {
-                                self.trace.0.push(envelope);
-                            }
-                            client_responses
-                                .iter()
-                                .for_each(|msg| self.trace.0.push(msg.clone()));
-                            Next::Continue
-                        }
-                        Err(err) => panic!("{}", err),
+    #[test]
+    fn world_measures_backlogs_and_latencies_of_busy_nodes() {
+        let node = FnNode::new(|_: Envelope<EchoMessage>| Ok(Vec::new()), || ())
+            .with_processing_time(|_| Duration::from_secs(1));
+        let start = Instant::now();
+        let echo = |msg_id: u64, at: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_millis(at),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
                     },
-                    None => panic!("unknown destination node '{}'", envelope.dest),
+                },
+            })
+        };
+        let mut world = World::new(
+            vec![echo(1, 0), echo(2, 100), echo(3, 200)],
+            vec![("n1".to_string(), node.boxed())],
+        );
+
+        world.run_world();
+
+        let metrics = world.metrics();
+        assert_eq!(
+            metrics.heap_depth,
+            vec![
+                (Duration::ZERO, 3),
+                (Duration::from_secs(1), 2),
+                (Duration::from_secs(2), 1)
+            ]
+        );
+        // the last two echoes arrive while n1 handles the first one, and wait for it
+        assert_eq!(metrics.max_backlog(), 1);
+        let latencies = &metrics.latencies["n1"];
+        assert_eq!(latencies[0], Duration::ZERO);
+        // sent at 100ms and 200ms, the other two are delivered at 1s and 2s, in either order
+        assert_eq!(
+            latencies.iter().sum::<Duration>(),
+            Duration::from_millis(900 + 1800)
+        );
+    }
+
+    #[test]
+    fn timers_fire_before_later_messages_are_delivered() {
+        let mut network = SimulationBuilder::default();
+        let stage = network.stage(
+            "delayed-echo",
+            async |(state, out), msg: Envelope<EchoMessage>, eff| {
+                if let EchoMessage::Echo { msg_id, echo } = msg.body {
+                    eff.wait(Duration::from_secs(1)).await;
+                    let reply = Envelope {
+                        src: msg.dest,
+                        dest: msg.src,
+                        body: EchoMessage::EchoOk {
+                            msg_id,
+                            in_reply_to: msg_id,
+                            echo,
+                        },
+                    };
+                    eff.send(&out, reply).await;
                 }
+                Ok((state, out))
+            },
+            (0u64, StageRef::noop::<Envelope<EchoMessage>>()),
+        );
+        let (output, rx) = network.output("output");
+        let stage = network.wire_up(stage, |state| state.1 = output.without_state());
+        let node = PureStageNode::new(rx, stage, network.run()).boxed();
+
+        let start = Instant::now();
+        let echo = |msg_id: u64, at: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_secs(at),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let mut world = World::new(vec![echo(1, 0), echo(2, 3)], vec![("n1".to_string(), node)]);
+
+        world.run_world();
+        let trace = world
+            .trace()
+            .0
+            .iter()
+            .map(|msg| match &msg.body {
+                EchoMessage::Echo { msg_id, .. } => format!("echo {}", msg_id),
+                EchoMessage::EchoOk { in_reply_to, .. } => format!("ok {}", in_reply_to),
+                body => format!("{:?}", body),
+            })
+            .collect::<Vec<_>>();
+
+        // the reply to the second echo is still waiting for its timer when the world runs out
+        // of messages
+        assert_eq!(trace, vec!["echo 1", "ok 1", "echo 2"]);
+    }
+
+    #[test]
+    fn trace_renders_as_sequence_diagram() {
+        let envelope = |src: &str, dest: &str, body| Envelope {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body,
+        };
+        let trace = Trace(vec![
+            envelope(
+                "c1",
+                "n1",
+                EchoMessage::Echo {
+                    msg_id: 1,
+                    echo: "a;b".to_string(),
+                },
+            ),
+            envelope(
+                "n1",
+                "c1",
+                EchoMessage::EchoOk {
+                    msg_id: 1,
+                    in_reply_to: 1,
+                    echo: "a;b".to_string(),
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            trace.to_mermaid(),
+            "sequenceDiagram\n    actor c1\n    participant n1\n    \
+             c1->>n1: Echo { msg_id: 1, echo: \"a#59;b\" }\n    \
+             n1->>c1: EchoOk { msg_id: 1, in_reply_to: 1, echo: \"a#59;b\" }\n"
+        );
+    }
+
+    #[test]
+    fn shrinking_removes_faults_and_messages_and_restores_delivery_order() {
+        let generate_faults = prop::collection::vec(
+            (0..1000u64).prop_map(|ms| {
+                (
+                    Duration::from_millis(ms),
+                    NemesisAction::Crash("n1".to_string()),
+                )
+            }),
+            1..5,
+        );
+        let strategy = ScheduleStrategy::new(0..128u8, generate_faults, 5..20);
+        let mut runner = SimRng::new(42).runner(Config::default());
+
+        let result = runner.run(&strategy, |schedule| {
+            prop_assert!(schedule.messages.len() < 2);
+            Ok(())
+        });
+
+        match result {
+            Err(TestError::Fail(_, schedule)) => {
+                assert!(schedule.faults.is_empty());
+                let messages = schedule
+                    .messages
+                    .into_iter()
+                    .map(|Reverse(entry)| entry)
+                    .collect::<Vec<_>>();
+                assert_eq!(messages.len(), 2);
+                assert!(messages[0].arrival_time <= messages[1].arrival_time);
+                assert!(messages.iter().all(|entry| entry.envelope.body == 0));
             }
-            None => Next::Done,
+            other => panic!("expected a failing case, got {:?}", other),
         }
     }
 
-    pub fn run_world(&mut self) -> &[Envelope<EchoMessage>] {
-        let prev = self.trace.0.len();
-        while self.step_world() == Next::Continue {}
-        &self.trace.0[prev..]
-    }
-}
+    #[test]
+    fn schedules_are_a_function_of_the_seed() {
+        let generate_faults = prop::collection::vec(
+            (0..1000u64).prop_map(|ms| {
+                (
+                    Duration::from_millis(ms),
+                    NemesisAction::Crash("n1".to_string()),
+                )
+            }),
+            0..5,
+        );
+        let generate = |seed| {
+            let strategy = ScheduleStrategy::new(0..128u8, generate_faults.clone(), 5..20);
+            let schedule = strategy
+                .new_tree(&mut SimRng::new(seed).runner(Config::default()))
+                .unwrap()
+                .current();
+            (schedule.messages, schedule.faults)
+        };
 
-impl Drop for World {
-    fn drop(&mut self) {
-        self.nodes
-            .values_mut()
-            .for_each(|node_handle| (node_handle.close)());
+        assert_eq!(generate(42), generate(42));
+        assert_ne!(generate(42), generate(43));
     }
-}
 
-#[allow(dead_code)]
-pub fn simulate(
-    config: Config,
-    number_of_nodes: u8,
-    spawn: fn() -> NodeHandle,
-    generate_message: impl Strategy<Value = EchoMessage>,
-    property: fn(Trace) -> Result<(), String>,
-) {
-    let mut runner = TestRunner::new(config);
-    let generate_messages = prop::collection::vec(
-        generate_message.prop_map(|msg| {
+    #[test]
+    fn failing_cases_are_written_out_for_amaru_sim() {
+        let dir = tempfile::tempdir().unwrap();
+        let echo = |msg_id: u64| {
             Reverse(Entry {
-                arrival_time: Instant::now(),
+                arrival_time: epoch() + Duration::from_millis(10 - msg_id),
                 envelope: Envelope {
                     src: "c1".to_string(),
                     dest: "n1".to_string(),
-                    body: msg,
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
                 },
             })
-        }),
-        0..20,
-    );
-    let result = runner.run(&generate_messages, |initial_messages| {
-        let node_handles: Vec<_> = (1..=number_of_nodes)
-            .map(|i| (format!("n{}", i), spawn()))
-            .collect();
+        };
+        let schedule = Schedule {
+            messages: vec![echo(1), echo(2)],
+            faults: vec![Fault {
+                at: epoch() + Duration::from_millis(5),
+                action: NemesisAction::Crash("n2".to_string()),
+            }],
+        };
+        let reproduction = Reproduction {
+            dir: dir.path().to_path_buf(),
+            args: vec!["--security-param".to_string(), "3".to_string()],
+        };
 
-        let mut world = World::new(initial_messages, node_handles);
-        let trace = world.run_world();
+        let command = reproduction.write(2, 42, &schedule).unwrap();
 
-        match property(Trace(trace.to_vec())) {
-            Ok(()) => (),
-            Err(reason) => prop_assert!(false, "{}", reason),
-        }
-        Ok(())
-    });
-    match result {
-        Ok(_) => (),
-        Err(TestError::Fail(what, entries)) => {
-            let mut err = String::new();
-            entries
-                .into_iter()
-                .for_each(|entry| err += &format!("  {:?}\n", entry.0.envelope));
-            panic!(
-                "Found minimal failing case:\n\n{}\nError message:\n\n  {}",
-                err, what
+        assert_eq!(
+            command,
+            format!(
+                "amaru-sim --number-of-nodes 2 --seed 42 --scenario {} --security-param 3 < {}",
+                dir.path().join("scenario.json").display(),
+                dir.path().join("input.jsonl").display()
             )
-        }
-        Err(TestError::Abort(e)) => panic!("Test aborted: {}", e),
+        );
+        let input = std::fs::read_to_string(dir.path().join("input.jsonl")).unwrap();
+        let input: Vec<Envelope<EchoMessage>> = input
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(
+            matches!(&input[0].body, EchoMessage::Init { node_ids, .. } if node_ids.len() == 2)
+        );
+        // messages come in the order they arrive
+        assert_eq!(input[1..], [echo(2).0.envelope, echo(1).0.envelope]);
+        let scenario = Scenario::from_file(&dir.path().join("scenario.json")).unwrap();
+        assert_eq!(
+            scenario.actions(),
+            vec![(
+                Duration::from_millis(5),
+                NemesisAction::Crash("n2".to_string())
+            )]
+        );
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use pure_stage::{simulation::SimulationBuilder, StageGraph, StageRef};
 
     #[test]
-    fn run_stops_when_no_message_to_process_is_left() {
-        let mut world = World::new(Vec::new(), Vec::new());
+    fn unanswered_echo_violates_leads_to() {
+        let start = Instant::now();
+        let echo = |msg_id: u64, at: u64| {
+            Reverse(Entry {
+                arrival_time: start + Duration::from_secs(at),
+                envelope: Envelope {
+                    src: "c1".to_string(),
+                    dest: "n1".to_string(),
+                    body: EchoMessage::Echo {
+                        msg_id,
+                        echo: format!("Please echo {}", msg_id),
+                    },
+                },
+            })
+        };
+        let answered = Temporal::leads_to(
+            "echo is answered",
+            Duration::from_secs(1),
+            |msg: &Envelope<EchoMessage>| matches!(msg.body, EchoMessage::Echo { .. }),
+            |request, msg| match (&request.body, &msg.body) {
+                (EchoMessage::Echo { msg_id, .. }, EchoMessage::EchoOk { in_reply_to, .. }) => {
+                    msg_id == in_reply_to
+                }
+                _ => false,
+            },
+        );
+        let mut world = World::new(
+            vec![echo(1, 1), echo(2, 3)],
+            vec![("n1".to_string(), spawn_echo_node())],
+        )
+        .with_temporal([answered]);
+        world.schedule(
+            start + Duration::from_secs(2),
+            NemesisAction::Crash("n1".to_string()),
+        );
+
+        world.run_world();
 
-        assert_eq!(world.run_world(), &Vec::new());
+        let violation = world.violation().unwrap();
+        assert!(violation.contains("Please echo 2"), "{}", violation);
     }
 
     #[test]
@@ -296,81 +3358,262 @@ mod tests {
 
         let number_of_nodes = 1;
 
-        let spawn: fn() -> NodeHandle = || {
-            println!("*** Spawning node!");
-            let mut network = SimulationBuilder::default();
-            let stage = network.stage(
-                "echo",
-                async |(mut state, out), msg: Envelope<EchoMessage>, eff| {
-                    if let EchoMessage::Echo { msg_id, echo } = &msg.body {
-                        state += 1;
-                        // Insert a bug every 5 messages.
-                        let echo_response = if state % 5 == 0 {
-                            echo.to_string().to_uppercase()
-                        } else {
-                            echo.to_string()
-                        };
-                        let reply = Envelope {
-                            src: msg.dest,
-                            dest: msg.src,
-                            body: EchoMessage::EchoOk {
-                                msg_id: state,
-                                in_reply_to: *msg_id,
-                                echo: echo_response,
-                            },
-                        };
-                        println!(" ==> {:?}", reply);
-                        eff.send(&out, reply).await;
-                        Ok((state, out))
-                    } else {
-                        panic!("Got a message that wasn't an echo: {:?}", msg.body)
-                    }
+        let spawn: fn() -> BoxedNode<EchoMessage> = spawn_echo_node;
+        let generate_message = (0..128u8).prop_map(|i| EchoMessage::Echo {
+            msg_id: 0,
+            echo: format!("Please echo {}", i),
+        });
+        simulate(
+            config,
+            rand::random(),
+            number_of_nodes,
+            spawn,
+            ScheduleStrategy::new(generate_message, Just(Vec::new()), 0..20),
+            ECHO_PROPERTY,
+            Report::default(),
+        )
+    }
+
+    #[test]
+    fn simulate_drives_chain_sync_messages() {
+        // a node relaying whatever it receives to the client
+        let spawn: fn() -> BoxedNode<ChainSyncMessage> = || {
+            FnNode::new(
+                |msg: Envelope<ChainSyncMessage>| {
+                    Ok(vec![Envelope {
+                        src: msg.dest,
+                        dest: msg.src,
+                        body: msg.body,
+                    }])
                 },
-                (0u64, StageRef::noop::<Envelope<EchoMessage>>()),
-            );
-            let (output, rx) = network.output("output");
-            let stage = network.wire_up(stage, |state| state.1 = output.without_state());
-            let running = network.run();
+                || (),
+            )
+            .boxed()
+        };
+        let generate_message =
+            (0..100u64, any::<u8>()).prop_map(|(slot, hash)| ChainSyncMessage::Fwd {
+                msg_id: 0,
+                slot: Slot::from(slot),
+                hash: vec![hash].into(),
+                header: vec![].into(),
+                block: None,
+            });
+        let relayed: fn(Trace<ChainSyncMessage>) -> Result<(), String> = |trace| {
+            let (sent, received): (Vec<_>, Vec<_>) =
+                trace.0.iter().partition(|msg| msg.src.starts_with('c'));
+            if sent.len() == received.len() {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} message(s) relayed out of {}",
+                    received.len(),
+                    sent.len()
+                ))
+            }
+        };
+
+        simulate(
+            Config::with_cases(16),
+            42,
+            1,
+            spawn,
+            ScheduleStrategy::new(generate_message, Just(Vec::new()), 0..20),
+            relayed,
+            Report::default(),
+        )
+    }
 
-            pure_stage_node_handle(rx, stage, running).unwrap()
+    #[test]
+    fn simulate_correlates_the_requests_of_several_clients() {
+        let spawn: fn() -> BoxedNode<EchoMessage> = || {
+            FnNode::new(
+                |msg: Envelope<EchoMessage>| match msg.body {
+                    EchoMessage::Echo { msg_id, echo } => Ok(vec![Envelope {
+                        src: msg.dest,
+                        dest: msg.src,
+                        body: EchoMessage::EchoOk {
+                            msg_id,
+                            in_reply_to: msg_id,
+                            echo,
+                        },
+                    }]),
+                    _ => Ok(Vec::new()),
+                },
+                || (),
+            )
+            .boxed()
         };
         let generate_message = (0..128u8).prop_map(|i| EchoMessage::Echo {
             msg_id: 0,
             echo: format!("Please echo {}", i),
         });
+        let strategy =
+            ScheduleStrategy::new(generate_message, Just(Vec::new()), 20..21).with_clients(3);
+        let schedule = strategy
+            .new_tree(&mut SimRng::new(42).runner(Config::default()))
+            .unwrap()
+            .current();
+        let mut clients: Vec<&str> = schedule
+            .messages
+            .iter()
+            .map(|entry| entry.0.envelope.src.as_str())
+            .collect();
+        clients.sort();
+        clients.dedup();
+        assert_eq!(clients, vec!["c1", "c2", "c3"]);
+
         simulate(
-            config,
-            number_of_nodes,
+            Config::with_cases(16),
+            42,
+            1,
             spawn,
-            generate_message,
+            strategy,
             ECHO_PROPERTY,
+            Report::default(),
+        )
+    }
+
+    #[test]
+    fn echo_property_catches_responses_sent_to_the_wrong_client() {
+        let message = |src: &str, dest: &str, body| Envelope {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body,
+        };
+        let echo = EchoMessage::Echo {
+            msg_id: 1,
+            echo: "Please echo 1".to_string(),
+        };
+        let echo_ok = EchoMessage::EchoOk {
+            msg_id: 1,
+            in_reply_to: 1,
+            echo: "Please echo 1".to_string(),
+        };
+        let mut trace = Trace(vec![
+            message("c1", "n1", echo.clone()),
+            message("c2", "n1", echo),
+            message("n1", "c1", echo_ok.clone()),
+            message("n1", "c2", echo_ok.clone()),
+        ]);
+
+        assert_eq!(ECHO_PROPERTY(trace.clone()), Ok(()));
+
+        trace.0[3] = message("n1", "c1", echo_ok);
+        assert!(ECHO_PROPERTY(trace).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Found minimal failing case")]
+    fn simulate_pure_stage_echo_in_parallel() {
+        let generate_message = (0..128u8).prop_map(|i| EchoMessage::Echo {
+            msg_id: 0,
+            echo: format!("Please echo {}", i),
+        });
+        simulate_parallel(
+            4,
+            Config::default(),
+            rand::random(),
+            1,
+            spawn_echo_node,
+            ScheduleStrategy::new(generate_message, Just(Vec::new()), 0..20),
+            || ECHO_PROPERTY.into(),
+            Report::default(),
         )
     }
 
+    #[test]
+    fn failing_cases_are_replayed_from_the_corpus() {
+        let root = tempfile::tempdir().unwrap();
+        let report = Report {
+            corpus: Some(Corpus::new(root.path(), "echo")),
+            ..Report::default()
+        };
+        let run = |cases| {
+            let generate_message = (0..128u8).prop_map(|i| EchoMessage::Echo {
+                msg_id: 0,
+                echo: format!("Please echo {}", i),
+            });
+            let report = report.clone();
+            std::panic::catch_unwind(move || {
+                simulate(
+                    Config {
+                        cases,
+                        ..Config::default()
+                    },
+                    42,
+                    1,
+                    spawn_echo_node,
+                    ScheduleStrategy::new(generate_message, Just(Vec::new()), 0..20),
+                    ECHO_PROPERTY,
+                    report,
+                )
+            })
+        };
+
+        assert!(run(256).is_err());
+        let cases = report
+            .corpus
+            .as_ref()
+            .unwrap()
+            .cases::<EchoMessage>()
+            .unwrap();
+        assert_eq!(cases.len(), 1);
+        // no case is generated, yet the one found before still fails
+        let panic = run(0).unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(
+            message.starts_with("Found minimal failing case"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains(&format!("(seed: {})", cases[0].1.seed)),
+            "{}",
+            message
+        );
+    }
+
     // TODO: Take response time into account.
-    const ECHO_PROPERTY: fn(Trace) -> Result<(), String> = |trace: Trace| {
-        for (index, msg) in trace
+    const ECHO_PROPERTY: fn(Trace<EchoMessage>) -> Result<(), String> = |trace| {
+        let mut clients: Vec<&NodeId> = trace
             .0
             .iter()
-            .enumerate()
-            .filter(|(_index, msg)| msg.src.starts_with("c"))
-        {
-            if let EchoMessage::Echo { msg_id, echo } = &msg.body {
-                let response = trace.0.split_at(index + 1).1.iter().find(|resp| {
-                        resp.dest == msg.src
-                            && matches!(&resp.body, EchoMessage::EchoOk { in_reply_to, echo: resp_echo, .. }
-                                if in_reply_to == msg_id && resp_echo == echo)
-                    });
-                if response.is_none() {
-                    let mut err = String::new();
-                    err += &format!(
-                        "No matching response found for echo request:\n    {:?}\n\nTrace:\n",
-                        msg
-                    );
-                    for envelope in trace.0 {
-                        err += &format!("  {envelope:?}\n");
+            .map(|msg| &msg.src)
+            .filter(|src| src.starts_with("c"))
+            .collect();
+        clients.sort();
+        clients.dedup();
+        for client in clients {
+            // each response answers a single request, of the client it is sent to
+            let exchanged = trace.of_client(client).0;
+            let mut answered = vec![false; exchanged.len()];
+            for (index, msg) in exchanged
+                .iter()
+                .enumerate()
+                .filter(|(_, msg)| &msg.src == client)
+            {
+                let EchoMessage::Echo { msg_id, echo } = &msg.body else {
+                    continue;
+                };
+                let response = (index + 1..exchanged.len()).find(|at| {
+                    !answered[*at]
+                        && &exchanged[*at].dest == client
+                        && matches!(&exchanged[*at].body, EchoMessage::EchoOk { in_reply_to, echo: resp_echo, .. }
+                            if in_reply_to == msg_id && resp_echo == echo)
+                });
+                match response {
+                    Some(at) => answered[at] = true,
+                    None => {
+                        let mut err = String::new();
+                        err += &format!(
+                            "No matching response found for echo request:\n    {:?}\n\nTrace:\n",
+                            msg
+                        );
+                        for envelope in trace.0 {
+                            err += &format!("  {envelope:?}\n");
+                        }
+                        return Err(err);
                     }
-                    return Err(err);
                 }
             }
         }
@@ -389,8 +3632,10 @@ mod tests {
         };
 
         let number_of_nodes = 1;
-        let spawn: fn() -> NodeHandle = || {
-            pipe_node_handle(Path::new("../../target/debug/echo"), &[]).expect("node handle failed")
+        let spawn: fn() -> BoxedNode<EchoMessage> = || {
+            ProcessNode::spawn(Path::new("../../target/debug/echo"), &["--framed"])
+                .expect("node handle failed")
+                .boxed()
         };
         let generate_message = (0..128u8).prop_map(|i| EchoMessage::Echo {
             msg_id: 0,
@@ -398,10 +3643,12 @@ mod tests {
         });
         simulate(
             config,
+            rand::random(),
             number_of_nodes,
             spawn,
-            generate_message,
+            ScheduleStrategy::new(generate_message, Just(Vec::new()), 0..20),
             ECHO_PROPERTY,
+            Report::default(),
         )
     }
 }
@@ -12,7 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::bytes::Bytes;
+use super::{
+    bytes::Bytes,
+    schema::{self, Violation},
+    simulate::Message,
+    topology::Topology,
+};
 use crate::echo::Envelope;
 use amaru_consensus::{
     consensus::{ChainSyncEvent, ValidateHeaderEvent},
@@ -23,9 +28,10 @@ use futures_util::sink::SinkExt;
 use gasket::framework::*;
 use serde::{Deserialize, Serialize};
 use slot_arithmetic::Slot;
+use std::collections::BTreeMap;
 use tokio::io::{stdin, stdout, AsyncBufReadExt, BufReader, Lines, Stdin, Stdout};
 use tokio_util::codec::{FramedWrite, LinesCodec};
-use tracing::{error, Span};
+use tracing::{error, info, Span};
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -35,19 +41,13 @@ pub enum InitError {
     NotInitMessage(ChainSyncMessage),
 }
 
-pub async fn read_peer_addresses_from_init(
+/// Read the `init` message the node is started with, see [`MaelstromNode::init`].
+pub async fn read_init(
     reader: &mut impl MessageReader,
-) -> Result<Vec<String>, InitError> {
+) -> Result<(MaelstromNode, Envelope<ChainSyncMessage>), InitError> {
     let input = reader.read().await.map_err(InitError::IOError)?;
 
-    parse_peer_addresses(input)
-}
-
-fn parse_peer_addresses(input: Envelope<ChainSyncMessage>) -> Result<Vec<String>, InitError> {
-    match input.body {
-        ChainSyncMessage::Init { node_ids, .. } => Ok(node_ids),
-        msg => Err(InitError::NotInitMessage(msg)),
-    }
+    MaelstromNode::init(input)
 }
 
 pub trait MessageReader {
@@ -56,10 +56,19 @@ pub trait MessageReader {
     ) -> impl std::future::Future<Output = Result<Envelope<ChainSyncMessage>, ReaderError>> + Send;
 }
 
-fn parse(next_input: &Option<String>) -> Result<Envelope<ChainSyncMessage>, ReaderError> {
+/// Decode a line of input, checking it against the [`schema`] first when `validate` is set.
+fn parse(
+    next_input: &Option<String>,
+    validate: bool,
+) -> Result<Envelope<ChainSyncMessage>, ReaderError> {
     use ReaderError::*;
 
     match next_input {
+        Some(line) if validate => {
+            let value = serde_json::from_str(line).map_err(|err| JSONError(err.to_string()))?;
+            schema::validate(&value).map_err(InvalidMessage)?;
+            serde_json::from_value(value).map_err(|err| JSONError(err.to_string()))
+        }
         Some(line) => match serde_json::from_str::<Envelope<ChainSyncMessage>>(line) {
             Ok(v) => Ok(v),
             Err(err) => Err(JSONError(err.to_string())),
@@ -72,17 +81,28 @@ fn parse(next_input: &Option<String>) -> Result<Envelope<ChainSyncMessage>, Read
 pub enum ReaderError {
     IOError(String),
     JSONError(String),
+    /// The message is valid JSON but breaks the schema of the protocol, see [`schema`].
+    InvalidMessage(Violation),
     EndOfFile,
 }
 
 pub struct StdinMessageReader {
     reader: Lines<BufReader<Stdin>>,
+    validate: bool,
 }
 
 impl StdinMessageReader {
     pub fn new() -> Self {
         let reader = BufReader::new(stdin()).lines();
-        Self { reader }
+        Self {
+            reader,
+            validate: false,
+        }
+    }
+
+    /// Check every message read against the [`schema`] of the protocol when `validate` is set.
+    pub fn with_validation(self, validate: bool) -> Self {
+        Self { validate, ..self }
     }
 }
 
@@ -93,7 +113,7 @@ impl MessageReader for StdinMessageReader {
             ReaderError::IOError(err.to_string())
         })?;
 
-        parse(&next_input)
+        parse(&next_input, self.validate)
     }
 }
 
@@ -110,7 +130,7 @@ impl MessageReader for StringMessageReader {
             return Err(ReaderError::EndOfFile);
         }
 
-        let msg = parse(&Some(self.lines[self.index].clone()));
+        let msg = parse(&Some(self.lines[self.index].clone()), false);
         self.index += 1;
         msg
     }
@@ -118,16 +138,38 @@ impl MessageReader for StringMessageReader {
 
 pub struct OutputWriter {
     writer: FramedWrite<Stdout, LinesCodec>,
+    validate: bool,
 }
 
 impl OutputWriter {
     pub(crate) fn new() -> Self {
         let writer = FramedWrite::new(stdout(), LinesCodec::new());
-        Self { writer }
+        Self {
+            writer,
+            validate: false,
+        }
+    }
+
+    /// Hold back the messages breaking the [`schema`] of the protocol when `validate` is set,
+    /// instead of handing them over to the harness.
+    pub(crate) fn with_validation(self, validate: bool) -> Self {
+        Self { validate, ..self }
     }
 
     pub(crate) async fn write(&mut self, messages: Vec<Envelope<ChainSyncMessage>>) {
         for msg in messages {
+            if self.validate {
+                let value = serde_json::to_value(&msg).unwrap();
+                if let Err(violation) = schema::validate(&value) {
+                    error!(
+                        path = %violation.path,
+                        reason = %violation.reason,
+                        message = %value,
+                        "refusing to write malformed message"
+                    );
+                    continue;
+                }
+            }
             let line = serde_json::to_string(&msg).unwrap();
             self.writer.send(line).await.unwrap();
         }
@@ -141,6 +183,13 @@ pub enum ChainSyncMessage {
         msg_id: u64,
         node_id: String,
         node_ids: Vec<String>,
+        /// The clients of the simulation, when it describes its whole topology.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        client_ids: Vec<String>,
+        /// For each node, the peers whose chain it follows, when the simulation describes
+        /// its whole topology.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        upstream: BTreeMap<String, Vec<String>>,
     },
     InitOk {
         in_reply_to: u64,
@@ -150,12 +199,365 @@ pub enum ChainSyncMessage {
         slot: Slot,
         hash: Bytes,
         header: Bytes,
+        /// The CBOR-encoded block announced by the header, validated against the ledger
+        /// rules after the header when present.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        block: Option<Bytes>,
     },
     Bck {
         msg_id: u64,
         slot: Slot,
         hash: Bytes,
     },
+    /// Sent by a downstream client to find the most recent of the given points on the chain
+    /// selected by a node, from which the node then serves it its chain. The origin is the
+    /// point with an empty hash.
+    FindIntersect {
+        msg_id: u64,
+        points: Vec<(Slot, Bytes)>,
+    },
+    IntersectFound {
+        in_reply_to: u64,
+        slot: Slot,
+        hash: Bytes,
+    },
+    IntersectNotFound {
+        in_reply_to: u64,
+    },
+    /// Sent by a downstream client for the next update of the chain a node serves it.
+    RequestNext {
+        msg_id: u64,
+    },
+    RollForward {
+        in_reply_to: u64,
+        slot: Slot,
+        hash: Bytes,
+        header: Bytes,
+    },
+    /// Roll the chain of the client back to the given point, which may be the origin.
+    RollBackward {
+        in_reply_to: u64,
+        slot: Slot,
+        hash: Bytes,
+    },
+    /// The client has the whole chain selected by the node.
+    AwaitReply {
+        in_reply_to: u64,
+    },
+    Topology {
+        msg_id: u64,
+        topology: BTreeMap<String, Vec<String>>,
+    },
+    TopologyOk {
+        msg_id: u64,
+        in_reply_to: u64,
+    },
+    Error {
+        in_reply_to: u64,
+        code: ErrorCode,
+        text: String,
+    },
+}
+
+impl ChainSyncMessage {
+    /// The identifier of this message, for messages that expect a reply.
+    pub fn msg_id(&self) -> Option<u64> {
+        use ChainSyncMessage::*;
+
+        match self {
+            Init { msg_id, .. }
+            | Fwd { msg_id, .. }
+            | Bck { msg_id, .. }
+            | FindIntersect { msg_id, .. }
+            | RequestNext { msg_id }
+            | Topology { msg_id, .. } => Some(*msg_id),
+            InitOk { .. }
+            | IntersectFound { .. }
+            | IntersectNotFound { .. }
+            | RollForward { .. }
+            | RollBackward { .. }
+            | AwaitReply { .. }
+            | TopologyOk { .. }
+            | Error { .. } => None,
+        }
+    }
+
+    /// The identifier of the request this message answers, for replies.
+    pub fn in_reply_to(&self) -> Option<u64> {
+        use ChainSyncMessage::*;
+
+        match self {
+            InitOk { in_reply_to }
+            | IntersectFound { in_reply_to, .. }
+            | IntersectNotFound { in_reply_to }
+            | RollForward { in_reply_to, .. }
+            | RollBackward { in_reply_to, .. }
+            | AwaitReply { in_reply_to }
+            | TopologyOk { in_reply_to, .. }
+            | Error { in_reply_to, .. } => Some(*in_reply_to),
+            Init { .. }
+            | Fwd { .. }
+            | Bck { .. }
+            | FindIntersect { .. }
+            | RequestNext { .. }
+            | Topology { .. } => None,
+        }
+    }
+
+    /// The number of bytes of chain data carried by this message: the hashes, headers and
+    /// blocks it announces, weighing it down on links of limited bandwidth.
+    pub fn payload_size(&self) -> usize {
+        use ChainSyncMessage::*;
+
+        match self {
+            Fwd {
+                hash,
+                header,
+                block,
+                ..
+            } => {
+                hash.bytes.len()
+                    + header.bytes.len()
+                    + block.as_ref().map_or(0, |block| block.bytes.len())
+            }
+            RollForward { hash, header, .. } => hash.bytes.len() + header.bytes.len(),
+            Bck { hash, .. } | RollBackward { hash, .. } => hash.bytes.len(),
+            _ => 0,
+        }
+    }
+}
+
+/// The error codes defined by the [Maelstrom protocol](https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors).
+///
+/// Codes outside of the standard ones are application-specific and kept as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "u64", into = "u64")]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    Custom(u64),
+}
+
+impl Message for ChainSyncMessage {
+    fn init(node_id: String, node_ids: Vec<String>) -> Self {
+        ChainSyncMessage::Init {
+            msg_id: 0,
+            node_id,
+            node_ids,
+            client_ids: Vec::new(),
+            upstream: BTreeMap::new(),
+        }
+    }
+}
+
+impl From<u64> for ErrorCode {
+    fn from(code: u64) -> Self {
+        use ErrorCode::*;
+
+        match code {
+            0 => Timeout,
+            1 => NodeNotFound,
+            10 => NotSupported,
+            11 => TemporarilyUnavailable,
+            12 => MalformedRequest,
+            13 => Crash,
+            14 => Abort,
+            20 => KeyDoesNotExist,
+            21 => KeyAlreadyExists,
+            22 => PreconditionFailed,
+            30 => TxnConflict,
+            code => Custom(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u64 {
+    fn from(code: ErrorCode) -> Self {
+        use ErrorCode::*;
+
+        match code {
+            Timeout => 0,
+            NodeNotFound => 1,
+            NotSupported => 10,
+            TemporarilyUnavailable => 11,
+            MalformedRequest => 12,
+            Crash => 13,
+            Abort => 14,
+            KeyDoesNotExist => 20,
+            KeyAlreadyExists => 21,
+            PreconditionFailed => 22,
+            TxnConflict => 30,
+            Custom(code) => code,
+        }
+    }
+}
+
+/// The node side of the Maelstrom protocol: who this node is, who its neighbours are, and
+/// the identifiers of the messages it sends.
+///
+/// Only chain sync messages are left for the consensus pipeline to handle, everything else
+/// is answered here.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaelstromNode {
+    pub node_id: String,
+    pub node_ids: Vec<String>,
+    /// The topology of the simulation, if the `init` message carried one.
+    pub topology: Option<Topology>,
+    /// The neighbours given by the last `topology` message, if any was received.
+    pub neighbours: Option<Vec<String>>,
+    next_msg_id: u64,
+}
+
+impl MaelstromNode {
+    /// Set up the node from the `init` message, returning the `init_ok` to send back.
+    pub fn init(
+        input: Envelope<ChainSyncMessage>,
+    ) -> Result<(Self, Envelope<ChainSyncMessage>), InitError> {
+        match input.body {
+            ChainSyncMessage::Init {
+                msg_id,
+                node_id,
+                node_ids,
+                client_ids,
+                upstream,
+            } => {
+                let topology = (!upstream.is_empty()).then(|| Topology {
+                    node_ids: node_ids.clone(),
+                    client_ids,
+                    upstream,
+                });
+                let init_ok = Envelope {
+                    src: node_id.clone(),
+                    dest: input.src,
+                    body: ChainSyncMessage::InitOk {
+                        in_reply_to: msg_id,
+                    },
+                };
+                let node = MaelstromNode {
+                    node_id,
+                    node_ids,
+                    topology,
+                    neighbours: None,
+                    next_msg_id: 1,
+                };
+                Ok((node, init_ok))
+            }
+            msg => Err(InitError::NotInitMessage(msg)),
+        }
+    }
+
+    /// The topology given by the `init` message or, when there was none, that of this node
+    /// alone, following the peers it was given.
+    pub fn topology(&self) -> Topology {
+        self.topology
+            .clone()
+            .unwrap_or_else(|| Topology::single(&self.node_id, self.node_ids.clone()))
+    }
+
+    fn next_msg_id(&mut self) -> u64 {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        msg_id
+    }
+
+    fn reply_to(
+        &self,
+        request: &Envelope<ChainSyncMessage>,
+        body: ChainSyncMessage,
+    ) -> Envelope<ChainSyncMessage> {
+        Envelope {
+            src: self.node_id.clone(),
+            dest: request.src.clone(),
+            body,
+        }
+    }
+
+    /// Answer messages that are part of the protocol rather than of chain sync.
+    ///
+    /// Returns `None` when the message is to be handled by the consensus pipeline, or the
+    /// (possibly empty) replies otherwise.
+    pub fn handle(
+        &mut self,
+        request: &Envelope<ChainSyncMessage>,
+    ) -> Option<Vec<Envelope<ChainSyncMessage>>> {
+        use ChainSyncMessage::*;
+
+        match &request.body {
+            Fwd { .. } | Bck { .. } | FindIntersect { .. } | RequestNext { .. } => None,
+            Topology { msg_id, topology } => {
+                self.neighbours = topology.get(&self.node_id).cloned();
+                info!("using neighbours: {:?}", self.neighbours);
+                let body = TopologyOk {
+                    msg_id: self.next_msg_id(),
+                    in_reply_to: *msg_id,
+                };
+                Some(vec![self.reply_to(request, body)])
+            }
+            Init { .. } => Some(self.error(
+                request,
+                ErrorCode::MalformedRequest,
+                "node is already initialised",
+            )),
+            // replies are never answered
+            InitOk { .. }
+            | IntersectFound { .. }
+            | IntersectNotFound { .. }
+            | RollForward { .. }
+            | RollBackward { .. }
+            | AwaitReply { .. }
+            | TopologyOk { .. }
+            | Error { .. } => Some(vec![]),
+        }
+    }
+
+    /// An error reply to the given request, unless it is not expecting any reply.
+    pub fn error(
+        &self,
+        request: &Envelope<ChainSyncMessage>,
+        code: ErrorCode,
+        text: &str,
+    ) -> Vec<Envelope<ChainSyncMessage>> {
+        request
+            .body
+            .msg_id()
+            .map(|in_reply_to| {
+                self.reply_to(
+                    request,
+                    ChainSyncMessage::Error {
+                        in_reply_to,
+                        code,
+                        text: text.to_string(),
+                    },
+                )
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// Give fresh identifiers to the messages sent by the consensus pipeline.
+    pub fn stamp(
+        &mut self,
+        mut messages: Vec<Envelope<ChainSyncMessage>>,
+    ) -> Vec<Envelope<ChainSyncMessage>> {
+        for msg in &mut messages {
+            match &mut msg.body {
+                ChainSyncMessage::Fwd { msg_id, .. } | ChainSyncMessage::Bck { msg_id, .. } => {
+                    *msg_id = self.next_msg_id()
+                }
+                _ => (),
+            }
+        }
+        messages
+    }
 }
 
 impl From<&ValidateHeaderEvent> for ChainSyncMessage {
@@ -172,6 +574,7 @@ impl From<&ValidateHeaderEvent> for ChainSyncMessage {
                         bytes: raw_hash.to_vec(),
                     },
                     header: Bytes { bytes: vec![] }, // FIXME: vec is the full body not the header
+                    block: None,
                 }
             }
             ValidateHeaderEvent::Rollback { .. } => todo!(),
@@ -193,6 +596,7 @@ pub fn mk_message(
             slot,
             hash,
             header,
+            ..
         } => Ok(ChainSyncEvent::RollForward {
             peer,
             point: Point::Specific(slot.into(), hash.into()),
@@ -214,13 +618,16 @@ pub fn mk_message(
 
 #[cfg(test)]
 mod test {
-    use std::str::FromStr;
+    use std::{collections::BTreeMap, str::FromStr};
 
     use crate::{
         echo::Envelope,
         simulator::{
             bytes::Bytes,
-            sync::{parse, read_peer_addresses_from_init, StringMessageReader},
+            schema::{self, Violation},
+            sync::{
+                parse, read_init, ErrorCode, MaelstromNode, MessageReader, StringMessageReader,
+            },
         },
     };
     use amaru_consensus::{consensus::ValidateHeaderEvent, peer::Peer};
@@ -257,6 +664,7 @@ mod test {
             slot: Slot::from(1234),
             hash: header_hash.to_vec().into(),
             header: header_bytes.into(),
+            block: None,
         }
     }
 
@@ -295,56 +703,221 @@ mod test {
         let init_string = r#"{"body":{"node_id":"c0","node_ids":["n1","n2"],"type":"init","msg_id":0},"dest":"c0","src":"c0"}"#;
         let mut input: StringMessageReader = read_lines_from_vector(vec![init_string.to_string()]);
 
-        let envelope = read_peer_addresses_from_init(&mut input).await;
+        let (node, init_ok) = read_init(&mut input).await.unwrap();
 
-        assert_eq!(envelope.unwrap(), vec!["n1".to_string(), "n2".to_string()]);
+        assert_eq!(node.node_ids, vec!["n1".to_string(), "n2".to_string()]);
+        assert_eq!(init_ok.body, InitOk { in_reply_to: 0 });
+        assert_eq!(
+            node.topology().upstream("c0"),
+            vec!["n1".to_string(), "n2".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn can_read_topology_from_init_message() {
+        let init_string = r#"{"body":{"node_id":"n1","node_ids":["n1","n2"],"client_ids":["c1"],"upstream":{"n1":["c1"],"n2":["n1"]},"type":"init","msg_id":0},"dest":"n1","src":"c1"}"#;
+        let mut input: StringMessageReader = read_lines_from_vector(vec![init_string.to_string()]);
+
+        let (node, _) = read_init(&mut input).await.unwrap();
+        let topology = node.topology.unwrap();
+
+        assert_eq!(topology.client_ids, vec!["c1".to_string()]);
+        assert_eq!(topology.downstream("n1"), vec!["n2".to_string()]);
+        assert_eq!(topology.downstream("n2"), vec!["c1".to_string()]);
     }
 
     #[tokio::test]
     async fn returns_error_when_reading_addresses_given_message_is_not_init() {
         let mut input: StringMessageReader = read_lines_from_vector(vec![TEST_FWD_MSG.to_string()]);
 
-        let envelope = read_peer_addresses_from_init(&mut input).await;
+        let envelope = read_init(&mut input).await;
 
         assert!(envelope.is_err());
     }
 
+    #[tokio::test]
+    async fn answers_topology_and_stamps_outgoing_messages() {
+        let init_string = r#"{"body":{"node_id":"n1","node_ids":["n1","n2"],"type":"init","msg_id":1},"dest":"n1","src":"c0"}"#;
+        let topology_string = r#"{"body":{"type":"topology","msg_id":2,"topology":{"n1":["n2"],"n2":["n1"]}},"dest":"n1","src":"c0"}"#;
+        let mut input =
+            read_lines_from_vector(vec![init_string.to_string(), topology_string.to_string()]);
+        let (mut node, init_ok) = read_init(&mut input).await.unwrap();
+        assert_eq!(init_ok.src, "n1");
+        assert_eq!(init_ok.dest, "c0");
+
+        let topology = input.read().await.unwrap();
+        let replies = node.handle(&topology).unwrap();
+
+        assert_eq!(
+            replies,
+            vec![Envelope {
+                src: "n1".to_string(),
+                dest: "c0".to_string(),
+                body: TopologyOk {
+                    msg_id: 1,
+                    in_reply_to: 2
+                },
+            }]
+        );
+        assert_eq!(node.neighbours, Some(vec!["n2".to_string()]));
+
+        let fwd = Envelope {
+            src: "n2".to_string(),
+            dest: "n1".to_string(),
+            body: some_forward(),
+        };
+        assert_eq!(node.handle(&fwd), None);
+        let stamped = node.stamp(vec![fwd.clone(), fwd]);
+        assert_eq!(
+            stamped.iter().map(|m| m.body.msg_id()).collect::<Vec<_>>(),
+            vec![Some(2), Some(3)]
+        );
+    }
+
+    #[test]
+    fn replies_with_error_to_unexpected_requests() {
+        let (mut node, _) = MaelstromNode::init(Envelope {
+            src: "c0".to_string(),
+            dest: "n1".to_string(),
+            body: Init {
+                msg_id: 1,
+                node_id: "n1".to_string(),
+                node_ids: vec!["n1".to_string()],
+                client_ids: vec![],
+                upstream: BTreeMap::new(),
+            },
+        })
+        .unwrap();
+        let init = Envelope {
+            src: "c0".to_string(),
+            dest: "n1".to_string(),
+            body: Init {
+                msg_id: 7,
+                node_id: "n1".to_string(),
+                node_ids: vec![],
+                client_ids: vec![],
+                upstream: BTreeMap::new(),
+            },
+        };
+
+        let replies = node.handle(&init).unwrap();
+
+        assert!(matches!(
+            replies.as_slice(),
+            [Envelope {
+                body: Error {
+                    in_reply_to: 7,
+                    code: ErrorCode::MalformedRequest,
+                    ..
+                },
+                ..
+            }]
+        ));
+        let error = serde_json::to_value(&replies[0].body).unwrap();
+        assert_eq!(error["code"], 12);
+    }
+
     #[test]
     fn returns_error_when_parsing_message_fails() {
         assert_eq!(
-            parse(&Some("foo".to_string())),
+            parse(&Some("foo".to_string()), false),
             Err(ReaderError::JSONError(
                 "expected ident at line 1 column 2".to_string()
             ))
         );
     }
 
+    #[test]
+    fn returns_the_offending_field_of_messages_breaking_the_schema() {
+        let line =
+            r#"{"src":"c1","dest":"n1","body":{"type":"bck","msg_id":1,"slot":"31","hash":""}}"#;
+
+        assert_eq!(
+            parse(&Some(line.to_string()), true),
+            Err(ReaderError::InvalidMessage(Violation {
+                path: "/body/slot".to_string(),
+                reason: "expected integer, found string".to_string(),
+            }))
+        );
+        assert!(parse(&Some(TEST_FWD_MSG.to_string()), true).is_ok());
+    }
+
     fn arbitrary_message() -> BoxedStrategy<ChainSyncMessage> {
-        use proptest::{collection::vec, prelude::*};
+        use proptest::{
+            collection::{btree_map, vec},
+            prelude::*,
+        };
 
         prop_oneof![
-            (any::<u64>(), any::<String>(), vec(any::<String>(), 0..10)).prop_map(
-                |(msg_id, node_id, node_ids)| ChainSyncMessage::Init {
-                    msg_id,
-                    node_id,
-                    node_ids
-                }
-            ),
+            (
+                any::<u64>(),
+                any::<String>(),
+                vec(any::<String>(), 0..10),
+                vec("c[0-9]", 0..3),
+                btree_map("n[0-9]", vec("[cn][0-9]", 0..5), 0..5)
+            )
+                .prop_map(|(msg_id, node_id, node_ids, client_ids, upstream)| {
+                    ChainSyncMessage::Init {
+                        msg_id,
+                        node_id,
+                        node_ids,
+                        client_ids,
+                        upstream,
+                    }
+                }),
             (any::<u64>()).prop_map(|msg_id| ChainSyncMessage::InitOk {
                 in_reply_to: msg_id
             }),
-            (any::<u64>(), any::<u64>(), any::<[u8; 32]>()).prop_map(|(msg_id, slot, hash)| {
-                ChainSyncMessage::Fwd {
-                    msg_id,
-                    slot: Slot::from(slot),
-                    hash: hash.to_vec().into(),
-                    header: Bytes { bytes: vec![] },
-                }
-            }),
+            (
+                any::<u64>(),
+                any::<u64>(),
+                any::<[u8; 32]>(),
+                proptest::option::of(vec(any::<u8>(), 0..16))
+            )
+                .prop_map(|(msg_id, slot, hash, block)| {
+                    ChainSyncMessage::Fwd {
+                        msg_id,
+                        slot: Slot::from(slot),
+                        hash: hash.to_vec().into(),
+                        header: Bytes { bytes: vec![] },
+                        block: block.map(Bytes::from),
+                    }
+                }),
             (any::<u64>(), any::<u64>(), any::<[u8; 32]>()).prop_map(|(msg_id, slot, hash)| Bck {
                 msg_id,
                 slot: Slot::from(slot),
                 hash: hash.to_vec().into()
+            }),
+            (any::<u64>(), vec((any::<u64>(), any::<[u8; 32]>()), 0..3)).prop_map(
+                |(msg_id, points)| FindIntersect {
+                    msg_id,
+                    points: points
+                        .into_iter()
+                        .map(|(slot, hash)| (Slot::from(slot), hash.to_vec().into()))
+                        .collect()
+                }
+            ),
+            (any::<u64>()).prop_map(|msg_id| RequestNext { msg_id }),
+            (any::<u64>(), any::<u64>(), any::<[u8; 32]>()).prop_map(
+                |(in_reply_to, slot, hash)| RollBackward {
+                    in_reply_to,
+                    slot: Slot::from(slot),
+                    hash: hash.to_vec().into()
+                }
+            ),
+            (any::<u64>()).prop_map(|in_reply_to| AwaitReply { in_reply_to }),
+            (any::<u64>(), btree_map("n[0-9]", vec("n[0-9]", 0..5), 0..5))
+                .prop_map(|(msg_id, topology)| Topology { msg_id, topology }),
+            (any::<u64>(), any::<u64>()).prop_map(|(msg_id, in_reply_to)| TopologyOk {
+                msg_id,
+                in_reply_to
+            }),
+            (any::<u64>(), any::<u64>(), any::<String>()).prop_map(|(in_reply_to, code, text)| {
+                Error {
+                    in_reply_to,
+                    code: code.into(),
+                    text,
+                }
             })
         ]
         .boxed()
@@ -358,6 +931,13 @@ mod test {
             assert_eq!(message, decoded);
         }
 
+        #[test]
+        fn chain_sync_messages_conform_to_the_schema(message in arbitrary_message()) {
+            let envelope = Envelope { src: "n1".to_string(), dest: "c1".to_string(), body: message };
+            let encoded = serde_json::to_value(&envelope).unwrap();
+            prop_assert_eq!(schema::validate(&encoded), Ok(()));
+        }
+
     }
 
     fn arbitrary_block_validated_event() -> BoxedStrategy<ValidateHeaderEvent> {
@@ -0,0 +1,259 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Temporal assertions over the messages of a simulation, evaluated while the simulation
+//! runs rather than over its final trace.
+//!
+//! Liveness is only ever checked with a bounded horizon: a property that something
+//! eventually happens is violated as soon as its deadline has passed in simulated time, or
+//! when the simulation ends before it happened.
+
+use crate::echo::Envelope;
+use std::{
+    fmt::Debug,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+pub type Predicate<Msg> = Rc<dyn Fn(&Envelope<Msg>) -> bool>;
+
+pub type Response<Msg> = Rc<dyn Fn(&Envelope<Msg>, &Envelope<Msg>) -> bool>;
+
+/// A temporal property over the sequence of messages observed in a simulation.
+#[derive(Clone)]
+pub enum Temporal<Msg> {
+    /// Every observed message satisfies the predicate.
+    Always { name: String, holds: Predicate<Msg> },
+    /// Some message satisfying the predicate is observed within the given time of the
+    /// first observation.
+    Eventually {
+        name: String,
+        holds: Predicate<Msg>,
+        within: Duration,
+    },
+    /// Every message satisfying `trigger` is followed, within the given time, by a message
+    /// that `response` accepts as an answer to it.
+    LeadsTo {
+        name: String,
+        trigger: Predicate<Msg>,
+        response: Response<Msg>,
+        within: Duration,
+    },
+}
+
+impl<Msg> Temporal<Msg> {
+    pub fn always(name: &str, holds: impl Fn(&Envelope<Msg>) -> bool + 'static) -> Self {
+        Temporal::Always {
+            name: name.to_string(),
+            holds: Rc::new(holds),
+        }
+    }
+
+    pub fn eventually(
+        name: &str,
+        within: Duration,
+        holds: impl Fn(&Envelope<Msg>) -> bool + 'static,
+    ) -> Self {
+        Temporal::Eventually {
+            name: name.to_string(),
+            holds: Rc::new(holds),
+            within,
+        }
+    }
+
+    pub fn leads_to(
+        name: &str,
+        within: Duration,
+        trigger: impl Fn(&Envelope<Msg>) -> bool + 'static,
+        response: impl Fn(&Envelope<Msg>, &Envelope<Msg>) -> bool + 'static,
+    ) -> Self {
+        Temporal::LeadsTo {
+            name: name.to_string(),
+            trigger: Rc::new(trigger),
+            response: Rc::new(response),
+            within,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Temporal::Always { name, .. }
+            | Temporal::Eventually { name, .. }
+            | Temporal::LeadsTo { name, .. } => name,
+        }
+    }
+}
+
+/// The incremental evaluation of a [`Temporal`] property during one simulation run.
+#[derive(Clone)]
+pub struct Monitor<Msg> {
+    property: Temporal<Msg>,
+    start: Option<Instant>,
+    satisfied: bool,
+    /// Messages still waiting for a response, with the deadline for it.
+    pending: Vec<(Instant, Envelope<Msg>)>,
+}
+
+impl<Msg: Clone + Debug> Monitor<Msg> {
+    pub fn new(property: Temporal<Msg>) -> Self {
+        Self {
+            property,
+            start: None,
+            satisfied: false,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Take a message observed at the given time into account.
+    pub fn observe(&mut self, at: Instant, msg: &Envelope<Msg>) -> Result<(), String> {
+        self.advance(at)?;
+        match &self.property {
+            Temporal::Always { name, holds } => {
+                if !holds(msg) {
+                    return Err(format!("'{}' does not hold for {:?}", name, msg));
+                }
+            }
+            Temporal::Eventually { holds, .. } => {
+                self.satisfied |= holds(msg);
+            }
+            Temporal::LeadsTo {
+                trigger,
+                response,
+                within,
+                ..
+            } => {
+                self.pending.retain(|(_, request)| !response(request, msg));
+                if trigger(msg) {
+                    self.pending.push((at + *within, msg.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that no deadline has passed at the given time.
+    pub fn advance(&mut self, now: Instant) -> Result<(), String> {
+        let start = *self.start.get_or_insert(now);
+        match &self.property {
+            Temporal::Always { .. } => Ok(()),
+            Temporal::Eventually { name, within, .. } => {
+                if !self.satisfied && now > start + *within {
+                    Err(format!("'{}' did not happen within {:?}", name, within))
+                } else {
+                    Ok(())
+                }
+            }
+            Temporal::LeadsTo { name, within, .. } => {
+                match self.pending.iter().find(|(deadline, _)| now > *deadline) {
+                    Some((_, request)) => Err(format!(
+                        "'{}': no response within {:?} to {:?}",
+                        name, within, request
+                    )),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Check the property once the simulation is over, i.e. nothing else can happen.
+    pub fn finish(&self) -> Result<(), String> {
+        let name = self.property.name();
+        if let Temporal::Eventually { .. } = self.property {
+            if !self.satisfied {
+                return Err(format!("'{}' never happened", name));
+            }
+        }
+        match self.pending.first() {
+            Some((_, request)) => Err(format!("'{}': no response to {:?}", name, request)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(src: &str, dest: &str, body: u64) -> Envelope<u64> {
+        Envelope {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body,
+        }
+    }
+
+    fn request_response() -> Temporal<u64> {
+        Temporal::leads_to(
+            "responds",
+            Duration::from_secs(1),
+            |msg| msg.src == "c1",
+            |request, msg| msg.dest == request.src && msg.body == request.body,
+        )
+    }
+
+    #[test]
+    fn leads_to_is_satisfied_by_timely_response() {
+        let start = Instant::now();
+        let mut monitor = Monitor::new(request_response());
+
+        monitor.observe(start, &envelope("c1", "n1", 1)).unwrap();
+        monitor
+            .observe(start + Duration::from_millis(500), &envelope("n1", "c1", 1))
+            .unwrap();
+
+        monitor.advance(start + Duration::from_secs(5)).unwrap();
+        monitor.finish().unwrap();
+    }
+
+    #[test]
+    fn leads_to_is_violated_once_deadline_passed() {
+        let start = Instant::now();
+        let mut monitor = Monitor::new(request_response());
+
+        monitor.observe(start, &envelope("c1", "n1", 1)).unwrap();
+        monitor
+            .observe(start + Duration::from_millis(500), &envelope("n1", "c1", 2))
+            .unwrap();
+
+        assert!(monitor.advance(start + Duration::from_secs(2)).is_err());
+    }
+
+    #[test]
+    fn eventually_is_violated_when_simulation_ends_without_it() {
+        let start = Instant::now();
+        let mut monitor = Monitor::new(Temporal::eventually(
+            "answers",
+            Duration::from_secs(10),
+            |msg: &Envelope<u64>| msg.dest == "c1",
+        ));
+
+        monitor.observe(start, &envelope("c1", "n1", 1)).unwrap();
+
+        assert!(monitor.finish().is_err());
+    }
+
+    #[test]
+    fn always_is_violated_by_first_offending_message() {
+        let mut monitor = Monitor::new(Temporal::always("small", |msg: &Envelope<u64>| {
+            msg.body < 10
+        }));
+
+        monitor
+            .observe(Instant::now(), &envelope("c1", "n1", 1))
+            .unwrap();
+        assert!(monitor
+            .observe(Instant::now(), &envelope("c1", "n1", 11))
+            .is_err());
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright 2025 PRAGMA
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+/// The nodes and clients taking part in a simulation, and who follows whose chain.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Topology {
+    pub node_ids: Vec<String>,
+    pub client_ids: Vec<String>,
+    /// For each node, the peers (nodes or clients) whose chain it follows.
+    pub upstream: BTreeMap<String, Vec<String>>,
+}
+
+impl Topology {
+    /// A single node following the given peers, which are also the clients it reports to.
+    ///
+    /// This is what a plain `init` message describes, as it only lists the peers of the node.
+    pub fn single(node_id: &str, peers: Vec<String>) -> Self {
+        Topology {
+            node_ids: vec![node_id.to_string()],
+            client_ids: peers.clone(),
+            upstream: BTreeMap::from([(node_id.to_string(), peers)]),
+        }
+    }
+
+    /// A line of nodes `n1` to `nN`: `n1` follows the clients, and every other node follows
+    /// its predecessor.
+    pub fn line(number_of_nodes: u8, client_ids: Vec<String>) -> Self {
+        let node_ids: Vec<String> = (1..=number_of_nodes).map(|i| format!("n{}", i)).collect();
+        let upstream = node_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let peers = match i.checked_sub(1) {
+                    Some(previous) => vec![node_ids[previous].clone()],
+                    None => client_ids.clone(),
+                };
+                (id.clone(), peers)
+            })
+            .collect();
+        Topology {
+            node_ids,
+            client_ids,
+            upstream,
+        }
+    }
+
+    pub fn is_client(&self, id: &str) -> bool {
+        self.client_ids.iter().any(|client| client == id)
+    }
+
+    /// The peers the given node follows.
+    pub fn upstream(&self, node_id: &str) -> Vec<String> {
+        self.upstream.get(node_id).cloned().unwrap_or_default()
+    }
+
+    /// The peers the given node forwards the chain it selects to: the nodes following it or,
+    /// when there are none, the clients.
+    pub fn downstream(&self, node_id: &str) -> Vec<String> {
+        let followers: Vec<String> = self
+            .upstream
+            .iter()
+            .filter(|(_, peers)| peers.iter().any(|peer| peer == node_id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        if followers.is_empty() {
+            self.client_ids.clone()
+        } else {
+            followers
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Topology;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn line_forwards_to_next_node_and_last_one_to_clients() {
+        let topology = Topology::line(3, ids(&["c1"]));
+
+        assert_eq!(topology.node_ids, ids(&["n1", "n2", "n3"]));
+        assert_eq!(topology.upstream("n1"), ids(&["c1"]));
+        assert_eq!(topology.upstream("n3"), ids(&["n2"]));
+        assert_eq!(topology.downstream("n1"), ids(&["n2"]));
+        assert_eq!(topology.downstream("n3"), ids(&["c1"]));
+        assert!(topology.is_client("c1"));
+        assert!(!topology.is_client("n1"));
+    }
+
+    #[test]
+    fn single_node_reports_to_its_peers() {
+        let topology = Topology::single("n1", ids(&["c1"]));
+
+        assert_eq!(topology.upstream("n1"), ids(&["c1"]));
+        assert_eq!(topology.downstream("n1"), ids(&["c1"]));
+    }
+}